@@ -0,0 +1,154 @@
+// file: assets/dashboard/app.js
+// version: 0.2.0
+// guid: e33f64b7-d525-4d3b-9cf6-8572ebedb98b
+
+// The serve dashboard: polls the job list and redraws it. Paths go in as text, never
+// as HTML. URLs are relative so it also works behind a reverse proxy's subpath.
+"use strict";
+
+const POLL_MS = 1000;
+const FINISHED_SHOWN = 20;
+const FAILURES_SHOWN = 10;
+// How long a refused cancel or retry stays on screen
+const NOTICE_MS = 5000;
+
+let notice = { text: "", until: 0 };
+
+// The server refuses changes without it
+const TOKEN = document.querySelector('meta[name="transcoderr-token"]').content;
+
+function clock(seconds) {
+  if (seconds == null) {
+    return "";
+  }
+  const s = Math.round(seconds);
+  const pad = (n) => String(n).padStart(2, "0");
+  return `${pad(Math.floor(s / 3600))}:${pad(Math.floor(s / 60) % 60)}:${pad(s % 60)}`;
+}
+
+function cell(row, text, className) {
+  const td = row.insertCell();
+  td.textContent = text ?? "";
+  if (className) {
+    td.className = className;
+  }
+  return td;
+}
+
+function button(label, onClick) {
+  const b = document.createElement("button");
+  b.textContent = label;
+  b.addEventListener("click", onClick);
+  return b;
+}
+
+async function act(method, path) {
+  const response = await fetch(path, {
+    method,
+    headers: { "Content-Type": "application/json", "X-Transcoderr-Token": TOKEN },
+  });
+  if (!response.ok) {
+    const body = await response.json().catch(() => ({}));
+    notice = { text: body.error || `${method} ${path}: ${response.status}`, until: Date.now() + NOTICE_MS };
+  }
+  refresh();
+}
+
+const cancel = (job) => act("DELETE", `jobs/${job.id}`);
+const retry = (job) => act("POST", `jobs/${job.id}/retry`);
+
+function status(text) {
+  document.getElementById("status").textContent = text;
+}
+
+function fill(id, rows) {
+  document.getElementById(id).replaceChildren(...rows);
+  document.getElementById(`${id}-empty`).hidden = rows.length > 0;
+}
+
+function queueRow(job) {
+  const row = document.createElement("tr");
+  cell(row, job.id);
+  cell(row, job.input, "path");
+  cell(row, job.preset);
+  const progress = cell(row, "");
+  if (job.state === "running") {
+    const bar = document.createElement("progress");
+    bar.max = 100;
+    if (job.percent != null) {
+      bar.value = job.percent;
+    }
+    const position = job.percent != null ? `${job.percent}%` : clock(job.position_seconds);
+    progress.append(bar, ` ${position}`);
+  } else {
+    progress.textContent = "queued";
+  }
+  const speed = [job.fps != null ? `${Math.round(job.fps)} fps` : "", job.speed != null ? `${job.speed}x` : ""];
+  cell(row, speed.filter(Boolean).join(" "));
+  cell(row, clock(job.eta_seconds));
+  row.insertCell().append(button("Cancel", () => cancel(job)));
+  return row;
+}
+
+function failure(job) {
+  const div = document.createElement("div");
+  div.className = "failure";
+  const title = document.createElement("p");
+  title.textContent = `#${job.id} ${job.input}: ${job.error ?? "failed"}`;
+  const details = document.createElement("details");
+  const summary = document.createElement("summary");
+  summary.textContent = "ffmpeg output";
+  const log = document.createElement("pre");
+  log.textContent = (job.log || []).join("\n");
+  details.append(summary, log);
+  div.append(title, details, button("Retry", () => retry(job)));
+  return div;
+}
+
+function finishedRow(job) {
+  const row = document.createElement("tr");
+  cell(row, job.id);
+  cell(row, job.input, "path");
+  cell(row, job.output, "path");
+  cell(row, job.state, `state-${job.state}`);
+  cell(row, clock(job.seconds));
+  const actions = row.insertCell();
+  if (job.state !== "done") {
+    actions.append(button("Retry", () => retry(job)));
+  }
+  return row;
+}
+
+function render(jobs) {
+  const counts = {};
+  for (const job of jobs) {
+    counts[job.state] = (counts[job.state] || 0) + 1;
+  }
+  document.getElementById("counts").textContent = ["queued", "running", "done", "failed", "cancelled"]
+    .map((state) => `${counts[state] || 0} ${state}`)
+    .join(" · ");
+
+  const active = jobs.filter((job) => job.state === "queued" || job.state === "running");
+  active.sort((a, b) => (a.state === b.state ? a.id - b.id : a.state === "running" ? -1 : 1));
+  fill("queue", active.map(queueRow));
+
+  const finished = jobs.filter((job) => !active.includes(job)).reverse();
+  // Redrawing the failures while one's output is open would close it
+  if (!document.querySelector(".failure details[open]")) {
+    fill("failures", finished.filter((job) => job.state === "failed").slice(0, FAILURES_SHOWN).map(failure));
+  }
+  fill("finished", finished.slice(0, FINISHED_SHOWN).map(finishedRow));
+}
+
+async function refresh() {
+  try {
+    const response = await fetch("jobs");
+    render(await response.json());
+    status(Date.now() < notice.until ? notice.text : "");
+  } catch (e) {
+    status(`cannot reach the server: ${e}`);
+  }
+}
+
+refresh();
+setInterval(refresh, POLL_MS);
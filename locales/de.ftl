@@ -0,0 +1,54 @@
+# file: locales/de.ftl
+# version: 0.4.0
+# guid: afe9665b-2b44-4123-b9bc-822ee4568ec0
+
+# Meldungen auf Deutsch
+
+error = Fehler
+error-read-only = Nur-Lese-Modus: '{ $command }' wird nicht ausgeführt, weil Dateien kodiert oder geändert würden (--dry-run zeigt eine Vorschau)
+error-unknown-lang = keine Übersetzung für die Sprache '{ $lang }' (verfügbar: { $available })
+error-what-if = --what-if: '{ $command }' lässt sich nicht simulieren
+error-unknown-preset = unbekannte Voreinstellung '{ $name }'
+error-tool-failed = { $tool } konnte nicht gestartet werden; Argumente: { $args }
+error-exit-status = { $tool } endete mit Status: { $code }
+
+batch-completed = Stapel-Transkodierung abgeschlossen!
+batch-counts = { $succeeded } erfolgreich, { $failed } fehlgeschlagen
+batch-sizes = { $before } -> { $after } ({ $change }) in { $time }
+batch-rejected = { $rejected } der Fehlschläge haben --min-vmaf verfehlt; ihre Originale wurden behalten
+checkpoint-summary = Zwischenstand: { $processed } von { $total } Dateien ({ $succeeded } erfolgreich, { $failed } fehlgeschlagen), { $before } -> { $after } ({ $change }) in { $time }
+show-summary = Zusammenfassung pro Serie:
+issues-denied = Zugriff verweigert für { $count ->
+        [one] einen Pfad
+       *[other] { $count } Pfade
+    }:
+issues-other = Andere Dateisystemfehler für { $count ->
+        [one] einen Pfad
+       *[other] { $count } Pfade
+    }:
+
+audiobook-completed = Hörbuch-Konvertierung abgeschlossen!
+audiobook-counts = { $succeeded } erfolgreich, { $failed } fehlgeschlagen ({ $size } in { $time })
+
+migrate-verified = { $verified } geprüft, { $skipped } übersprungen
+migrate-completed = Migration abgeschlossen (Originale: { $removal }): { $swapped } ersetzt, { $failed } fehlgeschlagen
+removal-quarantine = in die Quarantäne { $dir } verschieben
+removal-purge = endgültig löschen
+removal-trash = in den Papierkorb verschieben
+migrate-interrupted = { $count ->
+        [one] Eine Ersetzung in { $dir } wurde unterbrochen
+       *[other] { $count } Ersetzungen in { $dir } wurden unterbrochen
+    }; --swap schließt sie ab oder macht sie rückgängig, `doctor --roll-back { $dir }` macht sie rückgängig
+doctor-rolled-back = { $count ->
+        [0] Keine unterbrochenen Ersetzungen in { $dir }
+        [one] Eine unterbrochene Ersetzung in { $dir } rückgängig gemacht
+       *[other] { $count } unterbrochene Ersetzungen in { $dir } rückgängig gemacht
+    }
+
+column-show = Serie
+column-files = Dateien
+column-succeeded = Erfolgreich
+column-failed = Fehlgeschlagen
+column-input = Eingabe
+column-output = Ausgabe
+column-seasons = Staffeln
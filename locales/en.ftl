@@ -0,0 +1,54 @@
+# file: locales/en.ftl
+# version: 0.4.0
+# guid: bc90e21f-0af6-4e2b-a66a-3deaae4554c8
+
+# Messages for people, in English. Every other catalog has the same ids and placeables.
+
+error = Error
+error-read-only = read-only mode: refusing to run '{ $command }' because it would encode or modify files (use --dry-run to preview)
+error-unknown-lang = no translation for language '{ $lang }' (available: { $available })
+error-what-if = --what-if: '{ $command }' cannot be simulated
+error-unknown-preset = unknown preset '{ $name }'
+error-tool-failed = failed to run { $tool }; args: { $args }
+error-exit-status = { $tool } exited with status: { $code }
+
+batch-completed = Batch transcode completed!
+batch-counts = { $succeeded } succeeded, { $failed } failed
+batch-sizes = { $before } -> { $after } ({ $change }) in { $time }
+batch-rejected = { $rejected } of the failures missed --min-vmaf; their originals were kept
+checkpoint-summary = Checkpoint: { $processed } of { $total } files ({ $succeeded } succeeded, { $failed } failed), { $before } -> { $after } ({ $change }) in { $time }
+show-summary = Per-show summary:
+issues-denied = Permission denied for { $count ->
+        [one] one path
+       *[other] { $count } paths
+    }:
+issues-other = Other filesystem errors for { $count ->
+        [one] one path
+       *[other] { $count } paths
+    }:
+
+audiobook-completed = Audiobook conversion completed!
+audiobook-counts = { $succeeded } succeeded, { $failed } failed ({ $size } in { $time })
+
+migrate-verified = { $verified } verified, { $skipped } skipped
+migrate-completed = Migration completed (originals: { $removal }): { $swapped } swapped, { $failed } failed
+removal-quarantine = move to quarantine { $dir }
+removal-purge = delete permanently
+removal-trash = move to trash
+migrate-interrupted = { $count ->
+        [one] A swap in { $dir } was interrupted
+       *[other] { $count } swaps in { $dir } were interrupted
+    }; --swap finishes or rolls them back, `doctor --roll-back { $dir }` rolls them back
+doctor-rolled-back = { $count ->
+        [0] No interrupted swaps in { $dir }
+        [one] Rolled back one interrupted swap in { $dir }
+       *[other] Rolled back { $count } interrupted swaps in { $dir }
+    }
+
+column-show = Show
+column-files = Files
+column-succeeded = Succeeded
+column-failed = Failed
+column-input = Input
+column-output = Output
+column-seasons = Seasons
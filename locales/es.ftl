@@ -0,0 +1,54 @@
+# file: locales/es.ftl
+# version: 0.4.0
+# guid: 41d25e8d-fff1-4f75-8383-22a91f9c4203
+
+# Mensajes en español
+
+error = Error
+error-read-only = modo de solo lectura: no se ejecuta '{ $command }' porque codificaría o modificaría archivos (use --dry-run para una vista previa)
+error-unknown-lang = no hay traducción para el idioma '{ $lang }' (disponibles: { $available })
+error-what-if = --what-if: '{ $command }' no se puede simular
+error-unknown-preset = preajuste desconocido '{ $name }'
+error-tool-failed = no se pudo ejecutar { $tool }; argumentos: { $args }
+error-exit-status = { $tool } terminó con estado: { $code }
+
+batch-completed = ¡Transcodificación por lotes completada!
+batch-counts = { $succeeded } correctos, { $failed } fallidos
+batch-sizes = { $before } -> { $after } ({ $change }) en { $time }
+batch-rejected = { $rejected } de los fallos no alcanzaron --min-vmaf; se conservaron sus originales
+checkpoint-summary = Punto de control: { $processed } de { $total } archivos ({ $succeeded } correctos, { $failed } fallidos), { $before } -> { $after } ({ $change }) en { $time }
+show-summary = Resumen por serie:
+issues-denied = Permiso denegado en { $count ->
+        [one] una ruta
+       *[other] { $count } rutas
+    }:
+issues-other = Otros errores del sistema de archivos en { $count ->
+        [one] una ruta
+       *[other] { $count } rutas
+    }:
+
+audiobook-completed = ¡Conversión de audiolibros completada!
+audiobook-counts = { $succeeded } correctos, { $failed } fallidos ({ $size } en { $time })
+
+migrate-verified = { $verified } verificados, { $skipped } omitidos
+migrate-completed = Migración completada (originales: { $removal }): { $swapped } intercambiados, { $failed } fallidos
+removal-quarantine = mover a la cuarentena { $dir }
+removal-purge = eliminar permanentemente
+removal-trash = mover a la papelera
+migrate-interrupted = { $count ->
+        [one] Se interrumpió un intercambio en { $dir }
+       *[other] Se interrumpieron { $count } intercambios en { $dir }
+    }; --swap los termina o los deshace, `doctor --roll-back { $dir }` los deshace
+doctor-rolled-back = { $count ->
+        [0] No hay intercambios interrumpidos en { $dir }
+        [one] Se deshizo un intercambio interrumpido en { $dir }
+       *[other] Se deshicieron { $count } intercambios interrumpidos en { $dir }
+    }
+
+column-show = Serie
+column-files = Archivos
+column-succeeded = Correctos
+column-failed = Fallidos
+column-input = Entrada
+column-output = Salida
+column-seasons = Temporadas
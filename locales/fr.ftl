@@ -0,0 +1,54 @@
+# file: locales/fr.ftl
+# version: 0.4.0
+# guid: 09f8052e-e449-4495-bfa0-11c39257fbfe
+
+# Messages en français
+
+error = Erreur
+error-read-only = mode lecture seule : '{ $command }' n'est pas lancé car il encoderait ou modifierait des fichiers (--dry-run pour un aperçu)
+error-unknown-lang = pas de traduction pour la langue '{ $lang }' (disponibles : { $available })
+error-what-if = --what-if : '{ $command }' ne peut pas être simulé
+error-unknown-preset = préréglage inconnu '{ $name }'
+error-tool-failed = impossible de lancer { $tool } ; arguments : { $args }
+error-exit-status = { $tool } s'est terminé avec le statut : { $code }
+
+batch-completed = Transcodage par lots terminé !
+batch-counts = { $succeeded } réussis, { $failed } échoués
+batch-sizes = { $before } -> { $after } ({ $change }) en { $time }
+batch-rejected = { $rejected } des échecs n'ont pas atteint --min-vmaf ; leurs originaux ont été conservés
+checkpoint-summary = Point d'étape : { $processed } fichiers sur { $total } ({ $succeeded } réussis, { $failed } échoués), { $before } -> { $after } ({ $change }) en { $time }
+show-summary = Récapitulatif par série :
+issues-denied = Permission refusée pour { $count ->
+        [one] { $count } chemin
+       *[other] { $count } chemins
+    } :
+issues-other = Autres erreurs du système de fichiers pour { $count ->
+        [one] { $count } chemin
+       *[other] { $count } chemins
+    } :
+
+audiobook-completed = Conversion des livres audio terminée !
+audiobook-counts = { $succeeded } réussis, { $failed } échoués ({ $size } en { $time })
+
+migrate-verified = { $verified } vérifiés, { $skipped } ignorés
+migrate-completed = Migration terminée (originaux : { $removal }) : { $swapped } échangés, { $failed } échoués
+removal-quarantine = déplacer en quarantaine { $dir }
+removal-purge = supprimer définitivement
+removal-trash = mettre à la corbeille
+migrate-interrupted = { $count ->
+        [one] { $count } remplacement dans { $dir } a été interrompu
+       *[other] { $count } remplacements dans { $dir } ont été interrompus
+    } ; --swap les termine ou les annule, `doctor --roll-back { $dir }` les annule
+doctor-rolled-back = { $count ->
+        [0] Aucun remplacement interrompu dans { $dir }
+        [one] { $count } remplacement interrompu annulé dans { $dir }
+       *[other] { $count } remplacements interrompus annulés dans { $dir }
+    }
+
+column-show = Série
+column-files = Fichiers
+column-succeeded = Réussis
+column-failed = Échoués
+column-input = Entrée
+column-output = Sortie
+column-seasons = Saisons
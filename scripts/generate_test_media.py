@@ -1,6 +1,6 @@
 #!/usr/bin/env python3
 # file: scripts/generate_test_media.py
-# version: 1.1.0
+# version: 1.2.0
 # guid: 7b0c2a9e-3f5d-4a6b-9c8d-1e2f3a4b5c6d
 
 """
@@ -16,6 +16,10 @@ Creates:
 
 Requirements:
 - ffmpeg and ffprobe available on PATH
+
+These use libx264/libx265. For small fixtures that need only ffmpeg's built-in
+encoders and cover edge cases (HDR flags, VFR, several audio tracks, subtitle
+formats), use `transcoderr generate-fixtures --out testdata/fixtures` instead.
 """
 
 import shutil
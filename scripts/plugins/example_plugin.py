@@ -0,0 +1,95 @@
+#!/usr/bin/env python3
+# file: scripts/plugins/example_plugin.py
+# version: 1.0.0
+# guid: 4f2b8d61-9a3c-4e75-b0d8-6c1e7a5f3b92
+
+"""
+Example transcoderr plugin: one of each kind, in plain Python with no dependencies.
+
+- analyze: rejects outputs that came out larger than their input (an encode that
+  made things worse) or smaller than --min-kb
+- name: with --flat, writes every batch output straight into the output root,
+  dropping the input's folders
+- notify: appends each lifecycle event to --event-file, one JSON object per line
+
+Enable it in ~/.config/transcoderr/config.toml:
+
+    [[plugins]]
+    name = "example"
+    command = ["python3", "/path/to/example_plugin.py", "--min-kb", "100"]
+
+transcoderr talks JSON-RPC 2.0 on stdin/stdout, one message per line; see
+src/plugins.rs for the methods. Write diagnostics to stderr, or send a `log`
+notification, never free text on stdout.
+"""
+
+import argparse
+import json
+import os
+import sys
+from pathlib import PurePosixPath
+
+
+def reply(request_id, result=None, error=None):
+    message = {"jsonrpc": "2.0", "id": request_id}
+    if error is not None:
+        message["error"] = {"code": -32601, "message": error}
+    else:
+        message["result"] = result
+    print(json.dumps(message), flush=True)
+
+
+def log(text):
+    print(json.dumps({"jsonrpc": "2.0", "method": "log", "params": {"message": text}}), flush=True)
+
+
+def analyze(params, min_kb):
+    input_bytes = os.path.getsize(params["input"])
+    output_bytes = os.path.getsize(params["output"])
+    if output_bytes > input_bytes:
+        return {"ok": False, "message": f"output grew from {input_bytes} to {output_bytes} bytes"}
+    if output_bytes < min_kb * 1024:
+        return {"ok": False, "message": f"output is only {output_bytes} bytes"}
+    saved = 100 - output_bytes * 100 // max(input_bytes, 1)
+    return {"ok": True, "message": f"{saved}% smaller"}
+
+
+def name(params, flat):
+    if not flat:
+        return None
+    stem = PurePosixPath(params["relative"]).stem
+    return {"relative": f"{stem}.{params['ext']}"}
+
+
+def main():
+    parser = argparse.ArgumentParser(description=__doc__.splitlines()[1])
+    parser.add_argument("--min-kb", type=int, default=0, help="reject smaller outputs")
+    parser.add_argument("--flat", action="store_true", help="name outputs without folders")
+    parser.add_argument("--event-file", help="append events here")
+    args = parser.parse_args()
+
+    for line in sys.stdin:
+        if not line.strip():
+            continue
+        message = json.loads(line)
+        method, params = message.get("method"), message.get("params") or {}
+        request_id = message.get("id")
+        if method == "notify":
+            if args.event_file:
+                with open(args.event_file, "a", encoding="utf-8") as events:
+                    events.write(json.dumps(params) + "\n")
+            continue
+        if method == "initialize":
+            log(f"example plugin ready (protocol {params.get('protocol')})")
+            provides = ["analyze", "name"] + (["notify"] if args.event_file else [])
+            reply(request_id, {"provides": provides})
+        elif method == "analyze":
+            reply(request_id, analyze(params, args.min_kb))
+        elif method == "name":
+            reply(request_id, name(params, args.flat))
+        elif request_id is not None:
+            reply(request_id, error=f"unknown method {method}")
+
+
+if __name__ == "__main__":
+    main()
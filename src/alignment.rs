@@ -0,0 +1,141 @@
+// file: src/alignment.rs
+// version: 0.1.0
+// guid: 5042ed37-cf24-4d2c-a2d7-cc171786d01e
+
+//! Frame sizes the encoder can take (`--odd-size`). A 4:2:0 encode needs an even
+//! width and height, so a 1919x1079 capture makes libx264 stop with "width not
+//! divisible by 2" in the middle of a batch.
+//!
+//! Instead the frame is padded (the default) or cropped to the next size the output's
+//! pixel format allows: by one pixel for 4:2:0, at most three for 4:1:0. The change is
+//! printed and recorded with the file's encode history. The correction goes after
+//! the encode's other video filters, as an expression of the size they leave.
+
+use clap::ValueEnum;
+
+use crate::filters::{FilterGraph, add_filter};
+use crate::probe::MediaInfo;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OddSize {
+    #[default]
+    Pad,
+    Crop,
+    Off,
+}
+
+// Add the correction for the input's probed size to the end of the `-vf` chain in
+// `extra`, and return it as the adjustment to record
+pub fn apply(
+    mode: OddSize,
+    vcodec: &str,
+    info: Option<&MediaInfo>,
+    extra: &mut Vec<String>,
+) -> Option<String> {
+    if mode == OddSize::Off || vcodec == "copy" {
+        return None;
+    }
+    let video = info?.video_stream()?;
+    let (width, height) = (video.width?, video.height?);
+    let (x, y) = subsampling(extra);
+    let mut graph = FilterGraph::new();
+    let (verb, w, h) = match mode {
+        OddSize::Crop => {
+            graph.filter(
+                "crop",
+                &[&format!("floor(iw/{x})*{x}"), &format!("floor(ih/{y})*{y}")],
+            );
+            ("cropped", width / x * x, height / y * y)
+        }
+        _ => {
+            graph.filter(
+                "pad",
+                &[&format!("ceil(iw/{x})*{x}"), &format!("ceil(ih/{y})*{y}")],
+            );
+            ("padded", width.div_ceil(x) * x, height.div_ceil(y) * y)
+        }
+    };
+    if (w, h) == (width, height) {
+        return None;
+    }
+    add_filter(extra, "-vf", &graph);
+    Some(format!("{} {}x{} to {}x{}", verb, width, height, w, h))
+}
+
+// How many pixels each chroma sample covers across and down in the output's pixel
+// format (the last `-pix_fmt`, else the encoders' usual 4:2:0)
+fn subsampling(extra: &[String]) -> (u32, u32) {
+    let format = extra
+        .iter()
+        .rposition(|arg| arg == "-pix_fmt")
+        .and_then(|i| extra.get(i + 1))
+        .map_or("yuv420p", String::as_str);
+    let family = |prefixes: &[&str]| prefixes.iter().any(|p| format.starts_with(p));
+    if family(&[
+        "yuv444", "yuvj444", "gbr", "rgb", "bgr", "argb", "abgr", "gray",
+    ]) {
+        (1, 1)
+    } else if family(&["yuv422", "yuvj422", "yuva422"]) {
+        (2, 1)
+    } else if family(&["yuv411", "yuvj411"]) {
+        (4, 1)
+    } else if family(&["yuv410"]) {
+        (4, 4)
+    } else {
+        (2, 2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probe::Stream;
+
+    fn sized(width: u32, height: u32) -> MediaInfo {
+        MediaInfo {
+            streams: vec![Stream {
+                codec_type: Some("video".to_string()),
+                width: Some(width),
+                height: Some(height),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn odd_sizes_are_padded_or_cropped_to_the_pixel_format() {
+        let mut extra = vec!["-vf".to_string(), "yadif".to_string()];
+        let note = apply(
+            OddSize::Pad,
+            "libx264",
+            Some(&sized(1919, 1079)),
+            &mut extra,
+        );
+        assert_eq!(note.as_deref(), Some("padded 1919x1079 to 1920x1080"));
+        assert_eq!(extra, ["-vf", "yadif,pad=ceil(iw/2)*2:ceil(ih/2)*2"]);
+
+        let mut extra = vec!["-pix_fmt".to_string(), "yuv422p10le".to_string()];
+        let note = apply(OddSize::Crop, "libx265", Some(&sized(721, 481)), &mut extra);
+        assert_eq!(note.as_deref(), Some("cropped 721x481 to 720x481"));
+        assert_eq!(extra[3], "crop=floor(iw/2)*2:floor(ih/1)*1");
+        let mut extra = vec!["-pix_fmt".to_string(), "yuv444p".to_string()];
+        assert_eq!(
+            apply(OddSize::Pad, "libx264", Some(&sized(721, 481)), &mut extra),
+            None
+        );
+
+        // Sizes that fit, unknown sizes, copies and `off` are left alone
+        let mut extra = Vec::new();
+        for (mode, vcodec, info) in [
+            (OddSize::Pad, "libx264", Some(sized(1920, 1080))),
+            (OddSize::Pad, "libx264", Some(MediaInfo::default())),
+            (OddSize::Pad, "libx264", None),
+            (OddSize::Pad, "copy", Some(sized(1919, 1079))),
+            (OddSize::Off, "libx264", Some(sized(1919, 1079))),
+        ] {
+            assert_eq!(apply(mode, vcodec, info.as_ref(), &mut extra), None);
+        }
+        assert!(extra.is_empty());
+    }
+}
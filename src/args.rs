@@ -0,0 +1,278 @@
+// file: src/args.rs
+// version: 0.3.0
+// guid: c88b0d70-47a0-4bfd-b513-e5157399bb96
+
+//! ffmpeg command lines, assembled in the order ffmpeg reads them.
+//!
+//! ffmpeg applies options to the next input or output file, so an option in the wrong
+//! place silently does something else or nothing at all. `FfmpegArgs` keeps each kind
+//! of option in its own section and only flattens them in `build`: global options,
+//! then each input's options before its `-i`, then maps, filters, codecs and other
+//! output options, and the output path last. Negative maps (`-map -0:a:2`) only remove
+//! streams mapped before them, so they go after all the others. Secondary outputs (a
+//! preview stream) follow the main one, each with its own options.
+
+use std::ffi::OsString;
+
+// Output options that take a filtergraph
+const FILTER_FLAGS: [&str; 5] = ["-vf", "-af", "-lavfi", "-filter_complex", "-filter"];
+// Output options that pick an encoder
+const CODEC_FLAGS: [&str; 5] = ["-c", "-codec", "-vcodec", "-acodec", "-scodec"];
+
+#[derive(Debug, Clone)]
+pub struct FfmpegArgs {
+    global: Vec<OsString>,
+    // Options for each input, then its path
+    inputs: Vec<(Vec<OsString>, OsString)>,
+    maps: Vec<String>,
+    filters: Vec<OsString>,
+    codecs: Vec<OsString>,
+    options: Vec<OsString>,
+    output: OsString,
+    // Options for each secondary output, then its path
+    outputs: Vec<(Vec<OsString>, OsString)>,
+}
+
+impl FfmpegArgs {
+    // Quiet banner and overwrite, as every command here runs unattended
+    pub fn new(output: impl Into<OsString>) -> Self {
+        Self {
+            global: vec!["-hide_banner".into(), "-y".into()],
+            inputs: Vec::new(),
+            maps: Vec::new(),
+            filters: Vec::new(),
+            codecs: Vec::new(),
+            options: Vec::new(),
+            output: output.into(),
+            outputs: Vec::new(),
+        }
+    }
+
+    pub fn global<I, S>(&mut self, args: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<OsString>,
+    {
+        self.global.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn input(&mut self, path: impl Into<OsString>) -> &mut Self {
+        self.input_with(Vec::<OsString>::new(), path)
+    }
+
+    // An input with options that only apply to it (`-f lavfi`, `-ss` before `-i`)
+    pub fn input_with<I, S>(&mut self, opts: I, path: impl Into<OsString>) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<OsString>,
+    {
+        let opts = opts.into_iter().map(Into::into).collect();
+        self.inputs.push((opts, path.into()));
+        self
+    }
+
+    // Identical specs from different sources (a preset and the subtitle policy) would
+    // duplicate streams, so only the first is kept
+    pub fn map(&mut self, spec: &str) -> &mut Self {
+        if !self.maps.iter().any(|m| m == spec) {
+            self.maps.push(spec.to_string());
+        }
+        self
+    }
+
+    // `flag` is `-vf`, `-af`, `-filter:a:0`, `-lavfi` and the like
+    pub fn filter(&mut self, flag: &str, graph: impl Into<OsString>) -> &mut Self {
+        self.filters.extend([flag.into(), graph.into()]);
+        self
+    }
+
+    // `-c:<spec> codec`. Later entries override earlier ones for the streams they match,
+    // so defaults go first and overrides after.
+    pub fn codec(&mut self, spec: &str, codec: &str) -> &mut Self {
+        self.codecs
+            .extend([format!("-c:{}", spec).into(), codec.into()]);
+        self
+    }
+
+    pub fn option(&mut self, flag: &str, value: impl Into<OsString>) -> &mut Self {
+        self.options.extend([flag.into(), value.into()]);
+        self
+    }
+
+    // A flat extra-args list (presets, `--extra`, the policies): inputs, maps, filters and
+    // codecs go to their sections, everything else stays in order with the output options
+    pub fn extra<S: AsRef<str>>(&mut self, extra: &[S]) -> &mut Self {
+        let mut rest = extra.iter().map(AsRef::as_ref);
+        while let Some(arg) = rest.next() {
+            let section = match flag_name(arg) {
+                "-i" => Section::Input,
+                "-map" => Section::Map,
+                name if FILTER_FLAGS.contains(&name) => Section::Filter,
+                name if CODEC_FLAGS.contains(&name) => Section::Codec,
+                _ => {
+                    self.options.push(arg.into());
+                    continue;
+                }
+            };
+            let Some(value) = rest.next() else {
+                // A dangling flag is passed through for ffmpeg to reject
+                self.options.push(arg.into());
+                break;
+            };
+            match section {
+                Section::Input => {
+                    self.input(value);
+                }
+                Section::Map => {
+                    self.map(value);
+                }
+                Section::Filter => {
+                    self.filter(arg, value);
+                }
+                Section::Codec => self.codecs.extend([arg.into(), value.into()]),
+            }
+        }
+        self
+    }
+
+    // Another output after the main one; `opts` (maps, codecs and all) apply only to it
+    pub fn also_output<I, S>(&mut self, opts: I, path: impl Into<OsString>) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<OsString>,
+    {
+        let opts = opts.into_iter().map(Into::into).collect();
+        self.outputs.push((opts, path.into()));
+        self
+    }
+
+    pub fn build(&self) -> Vec<OsString> {
+        let mut args = self.global.clone();
+        for (opts, path) in &self.inputs {
+            args.extend(opts.iter().cloned());
+            args.extend(["-i".into(), path.clone()]);
+        }
+        let (negative, positive): (Vec<_>, Vec<_>) =
+            self.maps.iter().partition(|spec| spec.starts_with('-'));
+        for spec in positive.into_iter().chain(negative) {
+            args.extend(["-map".into(), spec.into()]);
+        }
+        args.extend(self.filters.iter().cloned());
+        args.extend(self.codecs.iter().cloned());
+        args.extend(self.options.iter().cloned());
+        args.push(self.output.clone());
+        for (opts, path) in &self.outputs {
+            args.extend(opts.iter().cloned());
+            args.push(path.clone());
+        }
+        args
+    }
+}
+
+enum Section {
+    Input,
+    Map,
+    Filter,
+    Codec,
+}
+
+// `-c:v:0` -> `-c`, `-filter:a` -> `-filter`
+fn flag_name(arg: &str) -> &str {
+    arg.split(':').next().unwrap_or(arg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &FfmpegArgs) -> Vec<String> {
+        args.build()
+            .iter()
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn sections_are_ordered_and_maps_deduplicated() {
+        let mut args = FfmpegArgs::new("out.mkv");
+        args.input("in.mkv")
+            .option("-map_metadata", "0")
+            .codec("v", "libx265")
+            .codec("a", "aac")
+            .extra(&[
+                "-crf",
+                "20",
+                "-map",
+                "-0:s:1",
+                "-map",
+                "0:v",
+                "-c:a",
+                "copy",
+                "-vf",
+                "scale=1280:-2",
+                "-map",
+                "0:v",
+                "-i",
+                "cover.jpg",
+                "-filter_threads",
+                "1",
+            ])
+            .global(["-v", "error"])
+            .input_with(["-ss", "5"], "subs.srt")
+            .also_output(["-f", "mpegts"], "udp://127.0.0.1:1234");
+        assert_eq!(
+            strings(&args).join(" "),
+            "-hide_banner -y -v error -i in.mkv -i cover.jpg -ss 5 -i subs.srt -map 0:v -map -0:s:1 -vf scale=1280:-2 \
+             -c:v libx265 -c:a aac -c:a copy -map_metadata 0 -crf 20 -filter_threads 1 out.mkv \
+             -f mpegts udp://127.0.0.1:1234"
+        );
+    }
+
+    #[test]
+    fn arbitrary_extras_never_land_after_the_output() {
+        let tokens = [
+            "-map",
+            "-i",
+            "-vf",
+            "-c:v",
+            "0:a",
+            "x",
+            "-filter:a:0",
+            "-metadata",
+            "-y",
+            "",
+        ];
+        // Every four-token sequence over an alphabet of awkward tokens
+        let n = tokens.len();
+        for seed in 0..n.pow(4) {
+            let mut extra = Vec::new();
+            let mut rest = seed;
+            for _ in 0..4 {
+                extra.push(tokens[rest % n]);
+                rest /= n;
+            }
+            let mut args = FfmpegArgs::new("out.mkv");
+            args.input("in.mkv").extra(&extra);
+            let built = strings(&args);
+            assert_eq!(
+                built.last().map(String::as_str),
+                Some("out.mkv"),
+                "{:?}",
+                extra
+            );
+            assert_eq!(
+                &built[..4],
+                ["-hide_banner", "-y", "-i", "in.mkv"],
+                "{:?}",
+                extra
+            );
+            // Only duplicate maps are dropped, and their spec is already there
+            assert!(
+                extra.iter().all(|t| built.iter().any(|b| b == t)),
+                "{:?}",
+                extra
+            );
+        }
+    }
+}
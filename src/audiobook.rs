@@ -0,0 +1,600 @@
+// file: src/audiobook.rs
+// version: 0.8.0
+// guid: 2aa71240-8d4b-431c-9653-f2b942cf2ca8
+
+//! `audiobook`: one chapterized .m4b per book folder, from a set of files or a cue sheet
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::time::Instant;
+
+use anyhow::{Context, Result, bail};
+use clap::{Args, ValueEnum};
+
+use crate::Runtime;
+use crate::args::FfmpegArgs;
+use crate::batch::{self, Scan, print_issue_summary, scan_inputs};
+use crate::cue::CueSheet;
+use crate::encode::display_args;
+use crate::events::{self, Event, path_str};
+use crate::i18n;
+use crate::probe;
+use crate::report;
+use crate::stats::Stats;
+use crate::style::Marker;
+use crate::tools::{self, Tool};
+use crate::units::porcelain_line;
+
+// Covers in the book folder win over art embedded in the first file
+const COVER_NAMES: [&str; 3] = ["cover", "folder", "front"];
+const COVER_EXTS: [&str; 3] = ["jpg", "jpeg", "png"];
+
+#[derive(Args, Debug)]
+pub struct AudiobookArgs {
+    /// Directory of books; every folder holding audio files becomes one .m4b
+    pub input_dir: PathBuf,
+    /// Output directory (mirrors the folder structure)
+    pub output_dir: PathBuf,
+    /// Audio codec: aac plays everywhere, opus is smaller
+    #[arg(long, value_enum, default_value_t = AudioCodec::Aac)]
+    pub codec: AudioCodec,
+    /// Audio bitrate (default: 64k for AAC, 32k for Opus)
+    #[arg(long)]
+    pub bitrate: Option<String>,
+    /// Keep stereo instead of downmixing speech to mono
+    #[arg(long)]
+    pub stereo: bool,
+    /// Audio file extensions to gather (comma-separated)
+    #[arg(long, default_value = "mp3,m4a,m4b,aac,flac,ogg,opus,wav,wma")]
+    pub input_exts: String,
+    /// Dry run: print commands without executing
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum AudioCodec {
+    Aac,
+    Opus,
+}
+
+impl AudioCodec {
+    fn encoder(self) -> &'static str {
+        match self {
+            AudioCodec::Aac => "aac",
+            AudioCodec::Opus => "libopus",
+        }
+    }
+
+    fn default_bitrate(self) -> &'static str {
+        match self {
+            AudioCodec::Aac => "64k",
+            AudioCodec::Opus => "32k",
+        }
+    }
+
+    // The ipod muxer writes the iTunes atoms audiobook players look for, but only
+    // accepts AAC; Opus goes into a plain MP4 container with the same extension
+    fn muxer(self) -> &'static str {
+        match self {
+            AudioCodec::Aac => "ipod",
+            AudioCodec::Opus => "mp4",
+        }
+    }
+}
+
+// One book folder and the .m4b it becomes
+struct Book {
+    dir: PathBuf,
+    files: Vec<PathBuf>,
+    cue: Option<CueSheet>,
+    output: PathBuf,
+}
+
+struct Chapter {
+    title: String,
+    start_ms: u64,
+    end_ms: u64,
+}
+
+// Book-level tags written into the output
+#[derive(Default)]
+struct BookTags {
+    title: String,
+    artist: Option<String>,
+    genre: Option<String>,
+    date: Option<String>,
+}
+
+// Scratch files for one book: the concat list and the chapter metadata. Removed on drop.
+struct WorkFiles {
+    list: PathBuf,
+    metadata: PathBuf,
+}
+
+impl WorkFiles {
+    fn new() -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let stem = format!(
+            "transcoderr-book-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, AtomicOrdering::Relaxed)
+        );
+        let dir = std::env::temp_dir();
+        Self {
+            list: dir.join(format!("{}.txt", stem)),
+            metadata: dir.join(format!("{}.ffmeta", stem)),
+        }
+    }
+}
+
+impl Drop for WorkFiles {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.list);
+        let _ = fs::remove_file(&self.metadata);
+    }
+}
+
+pub fn run(args: &AudiobookArgs, runtime: &Runtime) -> Result<()> {
+    let events = &runtime.events;
+    let scan = scan_inputs(&args.input_dir, &args.input_exts)?;
+    batch::emit_scan(events, &args.input_dir, &scan);
+    let books = group_books(&args.input_dir, &args.output_dir, &scan);
+    let bitrate = args
+        .bitrate
+        .as_deref()
+        .unwrap_or(args.codec.default_bitrate());
+    println!(
+        "Found {} books in {} audio files (codec={}, bitrate={}, {})",
+        books.len(),
+        scan.files.len(),
+        args.codec.encoder(),
+        bitrate,
+        if args.stereo { "stereo" } else { "mono" }
+    );
+    events.emit(Event::Plan {
+        jobs: books.len(),
+        collisions: 0,
+    });
+
+    let mut succeeded = 0usize;
+    let mut failed = 0usize;
+    let mut bytes_out = 0u64;
+    let run_started = Instant::now();
+    let mut last_milestone = 0usize;
+    for (idx, book) in books.iter().enumerate() {
+        println!(
+            "\n[{}/{}] {} -> {}",
+            idx + 1,
+            books.len(),
+            book.dir.display(),
+            book.output.display()
+        );
+        let work = WorkFiles::new();
+
+        if args.dry_run {
+            let chapters = match &book.cue {
+                Some(sheet) => format!("{} chapters from the cue sheet", sheet.tracks.len()),
+                None => format!("{} chapters", book.files.len()),
+            };
+            println!(
+                "  {} Would join {} files ({})",
+                Marker::DryRun,
+                book.files.len(),
+                chapters
+            );
+            // Embedded art is only found by probing, so the preview shows cover files only
+            let art = find_cover(&book.dir);
+            let cmd = ffmpeg_args(args, bitrate, &work, art.as_deref(), &book.output);
+            println!("  ffmpeg {}", display_args(&cmd));
+            continue;
+        }
+
+        runtime.gate.wait(&book.dir, &book.output, events);
+        events.emit(Event::Start {
+            input: path_str(&book.dir),
+            output: path_str(&book.output),
+            index: idx + 1,
+            total: books.len(),
+            device: None,
+        });
+        let started = Instant::now();
+        match build(args, bitrate, book, &work, runtime.stats) {
+            Ok(()) => {
+                succeeded += 1;
+                let output_bytes = fs::metadata(&book.output).map(|m| m.len()).unwrap_or(0);
+                bytes_out += output_bytes;
+                events.emit(Event::Done {
+                    input: path_str(&book.dir),
+                    output: path_str(&book.output),
+                    seconds: started.elapsed().as_secs_f64(),
+                    output_bytes,
+                });
+            }
+            Err(e) => {
+                eprintln!("  {} {:#}", Marker::Error, e);
+                eprintln!("  Skipping and continuing with next book...");
+                failed += 1;
+                events.emit(Event::Fail {
+                    input: path_str(&book.dir),
+                    output: path_str(&book.output),
+                    error: format!("{:#}", e),
+                });
+            }
+        }
+
+        let done = succeeded + failed;
+        if let Some(percent) = events::milestone(done, books.len(), last_milestone) {
+            last_milestone = percent;
+            events.emit(Event::Progress {
+                done,
+                total: books.len(),
+                percent,
+            });
+        }
+    }
+
+    println!("\n{}", i18n::t("audiobook-completed", &[]));
+    if !args.dry_run {
+        events.emit(Event::BatchDone { succeeded, failed });
+        let (units, seconds) = (runtime.units, run_started.elapsed().as_secs_f64());
+        if units.porcelain() {
+            let fields = [
+                ("succeeded", succeeded.to_string()),
+                ("failed", failed.to_string()),
+                ("output_bytes", units.bytes(bytes_out)),
+                ("seconds", units.duration(seconds)),
+            ];
+            println!("{}", porcelain_line("audiobook", &fields));
+        } else {
+            let fields = [
+                ("succeeded", succeeded.to_string()),
+                ("failed", failed.to_string()),
+                ("size", units.bytes(bytes_out)),
+                ("time", units.duration(seconds)),
+            ];
+            println!("  {}", i18n::t("audiobook-counts", &fields));
+        }
+    }
+    print_issue_summary(&scan.issues);
+    Ok(())
+}
+
+// Every folder that directly holds audio files is a book; its files play in natural
+// order ("Part 2" before "Part 10").
+fn group_books(input_dir: &Path, output_dir: &Path, scan: &Scan) -> Vec<Book> {
+    let mut folders: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+    for file in &scan.files {
+        let dir = file.parent().unwrap_or(input_dir).to_path_buf();
+        folders.entry(dir).or_default().push(file.clone());
+    }
+
+    let mut books = Vec::new();
+    for (dir, mut files) in folders {
+        files.sort_by(|a, b| natural_cmp(&a.to_string_lossy(), &b.to_string_lossy()));
+        // Files directly in the input directory are named after it
+        let relative = match dir.strip_prefix(input_dir) {
+            Ok(rel) if !rel.as_os_str().is_empty() => rel.to_path_buf(),
+            _ => PathBuf::from(
+                fs::canonicalize(input_dir)
+                    .ok()
+                    .and_then(|p| p.file_name().map(|n| n.to_os_string()))
+                    .unwrap_or_else(|| OsString::from("audiobook")),
+            ),
+        };
+        let mut output = output_dir.join(relative);
+        output.as_mut_os_string().push(".m4b");
+        // A cue sheet only describes chapters when the book is a single audio file
+        let cue = match &files[..] {
+            [single] => scan.cues.get(single).cloned(),
+            _ => None,
+        };
+        books.push(Book {
+            dir,
+            files,
+            cue,
+            output,
+        });
+    }
+    books
+}
+
+fn build(
+    args: &AudiobookArgs,
+    bitrate: &str,
+    book: &Book,
+    work: &WorkFiles,
+    stats: Stats,
+) -> Result<()> {
+    let mut infos = Vec::with_capacity(book.files.len());
+    for file in &book.files {
+        infos.push(probe::probe(file)?);
+    }
+    let first = &infos[0];
+    let total_ms = |info: &probe::MediaInfo, file: &Path| -> Result<u64> {
+        let seconds = info
+            .format
+            .duration_seconds()
+            .with_context(|| format!("ffprobe reported no duration for {}", file.display()))?;
+        Ok((seconds * 1000.0).round() as u64)
+    };
+
+    let mut tags = BookTags {
+        title: first
+            .format
+            .tag("album")
+            .map(str::to_string)
+            .unwrap_or_else(|| file_stem(&book.dir)),
+        artist: first
+            .format
+            .tag("album_artist")
+            .or(first.format.tag("artist"))
+            .map(str::to_string),
+        genre: first.format.tag("genre").map(str::to_string),
+        date: first.format.tag("date").map(str::to_string),
+    };
+
+    let chapters = match &book.cue {
+        Some(sheet) => {
+            if let Some(title) = sheet.title.clone() {
+                tags.title = title;
+            }
+            tags.artist = tags.artist.or(sheet.performer.clone());
+            tags.genre = tags.genre.or(sheet.genre.clone());
+            tags.date = tags.date.or(sheet.date.clone());
+            let end = total_ms(first, &book.files[0])?;
+            cue_chapters(sheet, end)
+        }
+        None => {
+            let mut chapters = Vec::with_capacity(book.files.len());
+            let mut start_ms = 0;
+            for (file, info) in book.files.iter().zip(&infos) {
+                let end_ms = start_ms + total_ms(info, file)?;
+                chapters.push(Chapter {
+                    title: info
+                        .format
+                        .tag("title")
+                        .map(str::to_string)
+                        .unwrap_or_else(|| file_stem(file)),
+                    start_ms,
+                    end_ms,
+                });
+                start_ms = end_ms;
+            }
+            chapters
+        }
+    };
+
+    fs::write(&work.list, concat_list(&book.files)?)
+        .with_context(|| format!("failed to write {}", work.list.display()))?;
+    fs::write(&work.metadata, ffmetadata(&tags, &chapters))
+        .with_context(|| format!("failed to write {}", work.metadata.display()))?;
+    if let Some(parent) = book.output.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create {}", parent.display()))?;
+    }
+
+    // Artwork: a cover image in the folder, else a picture attached to the first file
+    let embedded = first
+        .streams
+        .iter()
+        .any(|s| s.is_type("video") && s.has_disposition("attached_pic"));
+    let art = find_cover(&book.dir).or_else(|| embedded.then(|| book.files[0].clone()));
+
+    let cmd = ffmpeg_args(args, bitrate, work, art.as_deref(), &book.output);
+    let status = tools::stream(Tool::Ffmpeg, &cmd, &mut |stderr| {
+        report::tee_log(stderr, stats, None);
+    })?;
+    if !status.success() {
+        bail!("ffmpeg exited with status: {:?}", status.code());
+    }
+    Ok(())
+}
+
+fn ffmpeg_args(
+    args: &AudiobookArgs,
+    bitrate: &str,
+    work: &WorkFiles,
+    art: Option<&Path>,
+    output: &Path,
+) -> Vec<OsString> {
+    let mut cmd = FfmpegArgs::new(output);
+    cmd.input_with(["-f", "concat", "-safe", "0"], &work.list)
+        .input(&work.metadata)
+        .map("0:a:0")
+        .option("-map_metadata", "1")
+        .option("-map_chapters", "1");
+    if let Some(art) = art {
+        cmd.input(art)
+            .map("2:v:0")
+            .codec("v", "copy")
+            .option("-disposition:v:0", "attached_pic");
+    }
+    cmd.codec("a", args.codec.encoder()).option("-b:a", bitrate);
+    if !args.stereo {
+        cmd.option("-ac", "1");
+    }
+    cmd.option("-f", args.codec.muxer());
+    cmd.build()
+}
+
+// Chapters run from each track's start to the next one's; the last ends with the file
+fn cue_chapters(sheet: &CueSheet, end_ms: u64) -> Vec<Chapter> {
+    sheet
+        .tracks
+        .iter()
+        .enumerate()
+        .map(|(i, track)| Chapter {
+            title: track
+                .title
+                .clone()
+                .unwrap_or_else(|| format!("Chapter {}", track.number)),
+            start_ms: track.start_ms,
+            end_ms: sheet
+                .tracks
+                .get(i + 1)
+                .map_or(end_ms, |next| next.start_ms)
+                .max(track.start_ms),
+        })
+        .collect()
+}
+
+// The concat demuxer resolves relative entries against the list file, so paths are absolute
+fn concat_list(files: &[PathBuf]) -> Result<String> {
+    let mut list = String::from("ffconcat version 1.0\n");
+    for file in files {
+        let path = std::path::absolute(file)
+            .with_context(|| format!("failed to resolve {}", file.display()))?;
+        let path = path
+            .to_str()
+            .with_context(|| format!("non-UTF-8 path not supported: {}", file.display()))?;
+        list.push_str(&format!("file '{}'\n", path.replace('\'', r"'\''")));
+    }
+    Ok(list)
+}
+
+fn ffmetadata(tags: &BookTags, chapters: &[Chapter]) -> String {
+    let mut text = String::from(";FFMETADATA1\n");
+    text.push_str(&format!("title={}\n", escape_meta(&tags.title)));
+    text.push_str(&format!("album={}\n", escape_meta(&tags.title)));
+    for (key, value) in [
+        ("artist", &tags.artist),
+        ("album_artist", &tags.artist),
+        ("genre", &tags.genre),
+        ("date", &tags.date),
+    ] {
+        if let Some(value) = value {
+            text.push_str(&format!("{}={}\n", key, escape_meta(value)));
+        }
+    }
+    // iTunes media kind 2 marks the file as an audiobook (remembers position, shows chapters)
+    text.push_str("media_type=2\n");
+    for chapter in chapters {
+        text.push_str(&format!(
+            "\n[CHAPTER]\nTIMEBASE=1/1000\nSTART={}\nEND={}\ntitle={}\n",
+            chapter.start_ms,
+            chapter.end_ms,
+            escape_meta(&chapter.title)
+        ));
+    }
+    text
+}
+
+fn escape_meta(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '=' | ';' | '#' | '\\' | '\n') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+fn find_cover(dir: &Path) -> Option<PathBuf> {
+    find_by_ext(dir, &COVER_EXTS).into_iter().find(|path| {
+        let stem = file_stem(path).to_lowercase();
+        COVER_NAMES.contains(&stem.as_str())
+    })
+}
+
+// Files in `dir` (not recursive) with one of the extensions, sorted
+fn find_by_ext(dir: &Path, exts: &[&str]) -> Vec<PathBuf> {
+    let mut found: Vec<PathBuf> = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|e| e.ok())
+                .map(|e| e.path())
+                .filter(|p| {
+                    p.is_file()
+                        && p.extension().is_some_and(|ext| {
+                            exts.iter()
+                                .any(|e| ext.to_string_lossy().eq_ignore_ascii_case(e))
+                        })
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    found.sort();
+    found
+}
+
+fn file_stem(path: &Path) -> String {
+    path.file_stem()
+        .or(path.file_name())
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+// Compare runs of digits by value so "2.mp3" sorts before "10.mp3"
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a.chars().peekable(), b.chars().peekable());
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let take = |it: &mut std::iter::Peekable<std::str::Chars>| {
+                    let mut digits = String::new();
+                    while let Some(c) = it.next_if(|c| c.is_ascii_digit()) {
+                        digits.push(c);
+                    }
+                    digits
+                };
+                let (da, db) = (take(&mut a), take(&mut b));
+                let (ta, tb) = (da.trim_start_matches('0'), db.trim_start_matches('0'));
+                let order = ta.len().cmp(&tb.len()).then_with(|| ta.cmp(tb));
+                if order != Ordering::Equal {
+                    return order;
+                }
+            }
+            (Some(x), Some(y)) => {
+                let order = x.to_lowercase().cmp(y.to_lowercase());
+                if order != Ordering::Equal {
+                    return order;
+                }
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn natural_order_compares_numbers_by_value() {
+        let mut names = vec!["Part 10.mp3", "part 2.mp3", "Part 1.mp3", "Intro.mp3"];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(
+            names,
+            ["Intro.mp3", "Part 1.mp3", "part 2.mp3", "Part 10.mp3"]
+        );
+    }
+
+    #[test]
+    fn metadata_has_escaped_tags_and_chapters() {
+        let tags = BookTags {
+            title: "Tales; Vol=1".to_string(),
+            artist: Some("A. Author".to_string()),
+            ..Default::default()
+        };
+        let chapters = [Chapter {
+            title: "#1".to_string(),
+            start_ms: 0,
+            end_ms: 61_000,
+        }];
+        let text = ffmetadata(&tags, &chapters);
+        assert!(text.starts_with(";FFMETADATA1\ntitle=Tales\\; Vol\\=1\n"));
+        assert!(text.contains("album_artist=A. Author\n"));
+        assert!(!text.contains("genre="));
+        assert!(text.contains("[CHAPTER]\nTIMEBASE=1/1000\nSTART=0\nEND=61000\ntitle=\\#1\n"));
+    }
+}
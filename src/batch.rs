@@ -0,0 +1,2880 @@
+// file: src/batch.rs
+// version: 0.55.0
+// guid: 2256f693-b4fa-493a-a6e3-98013fa02c49
+
+//! Batch transcoding of a directory tree: scanning, output planning and execution.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Instant;
+
+use anyhow::{Context, Result, anyhow, bail};
+use clap::{Args, Subcommand};
+
+use crate::Runtime;
+use crate::alignment::{self, OddSize};
+use crate::checkpoint::{self, Checkpoints, Totals};
+use crate::cue::{self, CueSheet};
+use crate::deterministic;
+use crate::diagnostics::Failure;
+use crate::encode::{transcode, transcode_quietly};
+use crate::events::{self, Event, Events, path_str};
+use crate::features::{self, Feature};
+use crate::gpu::{GpuPool, Lease};
+use crate::history::{self, Record, SourceChange};
+use crate::i18n;
+use crate::keyint::Keyint;
+use crate::ledger::{Claim, ClaimResult, Ledger};
+use crate::legacy;
+use crate::ownership::OutputOwnership;
+use crate::paths::{
+    self, is_suffixed_output, output_key, paths_equivalent, relative_to, suffixed_output,
+};
+use crate::plugins::Plugins;
+use crate::presets;
+use crate::probe;
+use crate::quality::{BelowMinimum, QualityGate};
+use crate::recommend;
+use crate::removal::{self, Removal};
+use crate::report::{self, FfmpegFailed, Report};
+use crate::results::{self, FileResult};
+use crate::resume::{self, Previous, State};
+use crate::rules::{Condition, Otherwise};
+use crate::shows::{self, ShowKey, ShowsFile};
+use crate::silence::{SilenceTrim, TrimEnds};
+use crate::smart;
+use crate::speed::Speed;
+use crate::spotcheck::SpotCheck;
+use crate::stabilize::Stabilizer;
+use crate::staging::{StageCache, Stager};
+use crate::streamcopy;
+use crate::streams;
+use crate::style::{Marker, Table};
+use crate::subtiming::{Fps, SubShift, SubTiming};
+use crate::subtitles::SubtitlePolicy;
+use crate::thumbnail::{Cover, Thumbnail};
+use crate::tools::RunEnv;
+use crate::units::{Units, porcelain_line};
+use crate::vbv;
+use crate::workers::Workers;
+
+// Batches target h265 unless a preset or --vcodec says otherwise
+const DEFAULT_VCODEC: &str = "libx265";
+pub const DEFAULT_INPUT_EXTS: &str = "mp4,mkv,avi,mov,m4v,ts";
+// ffmpeg lines shown under a failed job of --jobs, whose output was not passed through
+const FAILED_LOG_LINES: usize = 5;
+
+#[derive(Args, Debug)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+pub struct BatchCommand {
+    #[command(subcommand)]
+    pub action: Option<BatchAction>,
+    // Only the leaf args live in `BatchArgs`: clap cannot detect an optional flattened
+    // group that itself flattens another one, so ownership sits alongside it
+    #[command(flatten)]
+    pub args: Option<BatchArgs>,
+    #[command(flatten)]
+    pub ownership: OutputOwnership,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum BatchAction {
+    /// List inputs whose mapped output does not exist yet (optionally transcode just those)
+    Missing {
+        #[command(flatten)]
+        args: Box<BatchArgs>,
+        #[command(flatten)]
+        ownership: OutputOwnership,
+        /// Transcode the missing inputs instead of only listing them
+        #[arg(long)]
+        run: bool,
+    },
+    /// List outputs whose source no longer exists (optionally remove them)
+    Prune {
+        /// Input directory the outputs were transcoded from
+        input_dir: PathBuf,
+        /// Output directory to prune
+        output_dir: PathBuf,
+        /// Output file extension (e.g., mkv, mp4)
+        #[arg(long, default_value = "mkv")]
+        ext: String,
+        /// File extensions to process (comma-separated; default: the config's
+        /// `input-exts`, else mp4,mkv,avi,mov,m4v,ts)
+        #[arg(long)]
+        input_exts: Option<String>,
+        /// Remove the orphaned outputs instead of only listing them
+        #[arg(long)]
+        delete: bool,
+        #[command(flatten)]
+        removal: Removal,
+    },
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct BatchArgs {
+    /// Input directory to scan recursively
+    #[arg(
+        value_name = "INPUT_DIR",
+        required_unless_present = "roots",
+        requires = "output"
+    )]
+    input: Option<PathBuf>,
+    /// Output directory (mirrors input structure)
+    #[arg(value_name = "OUTPUT_DIR", required_unless_present = "roots")]
+    output: Option<PathBuf>,
+    /// Another library section for the same run, with its own output directory and
+    /// optionally its own preset, e.g. `in=/media/tv,out=/staging/tv,preset=tv-h265-fast`
+    /// (repeatable; runs after the positional directories, if given)
+    #[arg(long = "root", value_name = "in=DIR,out=DIR[,preset=NAME]")]
+    pub roots: Vec<Root>,
+    // The directories of the section being run; `for_each_root` sets them
+    #[arg(skip)]
+    pub input_dir: PathBuf,
+    #[arg(skip)]
+    pub output_dir: PathBuf,
+    /// Preset name (e.g., original-h265), or `auto` to pick one per file from its content
+    #[arg(long)]
+    pub preset: Option<String>,
+    /// Video codec (e.g., libx265; default: the preset's, else the config's, else libx265)
+    #[arg(long)]
+    pub vcodec: Option<String>,
+    /// Audio codec (e.g., aac, ac3; default: the preset's, else the config's, else aac)
+    #[arg(long)]
+    pub acodec: Option<String>,
+    /// Output file extension (e.g., mkv, mp4; default: the preset's container, else the
+    /// config's `ext`, else mkv)
+    #[arg(long)]
+    pub ext: Option<String>,
+    /// File extensions to process (comma-separated; default: the config's `input-exts`,
+    /// else mp4,mkv,avi,mov,m4v,ts)
+    #[arg(long)]
+    pub input_exts: Option<String>,
+    /// Extra ffmpeg args (passed as-is after standard args; default: the config's `extra`)
+    #[arg(long, num_args = 0.., value_delimiter = ' ')]
+    pub extra: Vec<String>,
+    /// Default-subtitle policy, e.g. "forced:eng, else none" (overrides config)
+    #[arg(long, value_name = "POLICY")]
+    pub subtitle_default: Option<SubtitlePolicy>,
+    /// Keep only audio and subtitle tracks in these languages, e.g. eng,jpn
+    /// (untagged tracks are kept, and all audio when none matches)
+    #[arg(long, value_name = "LANGS", value_delimiter = ',')]
+    pub languages: Vec<String>,
+    /// Per-show overrides (TOML tables keyed by show name: preset, codecs, crop, ...)
+    #[arg(long, value_name = "PATH")]
+    pub shows: Option<PathBuf>,
+    /// Stabilize shaky footage with a vid.stab analysis pass (needs ffmpeg with libvidstab)
+    #[arg(long)]
+    pub stabilize: bool,
+    /// Keyframe interval: `auto` (2 s for mp4/ts, 10 s otherwise) or seconds
+    #[arg(long, value_name = "auto|SECONDS")]
+    pub keyint: Option<Keyint>,
+    /// Add an AAC stereo downmix (dialogue boosted) as the default track, ahead of the
+    /// untouched surround tracks
+    #[arg(long)]
+    pub stereo_compat: bool,
+    /// Reproducible output: pinned thread counts, no timestamps or version tags
+    #[arg(long)]
+    pub deterministic: bool,
+    /// Embed a cover image: `auto` (a poster beside the input, else a frame 10% in),
+    /// `at=HH:MM:SS` (the frame there) or `from=IMAGE` (a JPEG or PNG)
+    #[arg(long, value_name = "auto|at=TIME|from=IMAGE")]
+    pub embed_thumbnail: Option<Thumbnail>,
+    /// Trim leading, trailing or both runs of silence from recordings (needs audio)
+    #[arg(long, value_name = "start|end|both")]
+    pub trim_silence: Option<TrimEnds>,
+    /// Level below which --trim-silence counts audio as silent, in dB
+    #[arg(
+        long,
+        value_name = "DB",
+        default_value_t = -50.0,
+        allow_negative_numbers = true,
+        requires = "trim_silence"
+    )]
+    pub silence_threshold: f64,
+    /// Shortest run of silence --trim-silence trims, in seconds
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 0.5,
+        requires = "trim_silence"
+    )]
+    pub silence_min: f64,
+    /// Change the playback speed, e.g. 1.25x (audio keeps its pitch)
+    #[arg(long, value_name = "FACTOR")]
+    pub speed: Option<Speed>,
+    /// Move text subtitle cues by this time, e.g. 2.5s or -800ms (after any
+    /// --sub-fps-from change)
+    #[arg(long, value_name = "TIME", allow_hyphen_values = true)]
+    pub sub_shift: Option<SubShift>,
+    /// Frame rate the text subtitles were timed for (e.g. 25 for a PAL release);
+    /// their cues are stretched to --sub-fps-to
+    #[arg(long, value_name = "FPS", requires = "sub_fps_to")]
+    pub sub_fps_from: Option<Fps>,
+    /// Frame rate of the video the text subtitles go with (e.g. 23.976)
+    #[arg(long, value_name = "FPS", requires = "sub_fps_from")]
+    pub sub_fps_to: Option<Fps>,
+    /// Rescue ancient AVI/DivX/Real/WMV sources: tolerate broken indexes, timestamps
+    /// and bitstreams and unpack packed B-frames
+    #[arg(long)]
+    pub legacy_source: bool,
+    /// Fix frame sizes the encoder rejects (odd ones, for 4:2:0) by padding or
+    /// cropping to the next size that fits
+    #[arg(long, value_name = "pad|crop|off", default_value = "pad")]
+    pub odd_size: OddSize,
+    /// Keep existing outputs made with the current settings; re-transcode only those
+    /// made with older or different ones (settings hash in the output or history)
+    #[arg(long)]
+    pub refresh_if_settings_changed: bool,
+    /// Skip inputs whose output already exists and probes as media with a duration, so
+    /// running a batch again only does what is left
+    #[arg(long, conflicts_with = "refresh_if_settings_changed")]
+    pub skip_existing: bool,
+    /// Copy the video when it is already in the video encoder's format (HEVC under
+    /// libx265) and the audio when every track is in the audio encoder's, instead of
+    /// re-encoding them
+    #[arg(long)]
+    pub smart: bool,
+    /// Transcode only the inputs this condition holds for, checked against their probe,
+    /// e.g. "vcodec!=hevc && height>1080 || bitrate>15M" (fields: vcodec, acodec, width,
+    /// height, bitrate, vbitrate, fps, duration, channels, audio_tracks, subtitle_tracks)
+    #[arg(long, value_name = "CONDITION")]
+    pub only_if: Option<Condition>,
+    /// What happens to the inputs --only-if does not hold for: skip them, or remux them
+    /// with every stream copied
+    #[arg(
+        long,
+        value_name = "skip|copy",
+        default_value = "skip",
+        requires = "only_if"
+    )]
+    pub otherwise: Otherwise,
+    /// What --refresh-if-settings-changed does with outputs whose source was replaced
+    /// since (re-downloaded, upgraded remux): transcode them again, or keep them and
+    /// report the change
+    #[arg(
+        long,
+        value_name = "requeue|notify",
+        default_value = "requeue",
+        requires = "refresh_if_settings_changed"
+    )]
+    pub on_source_change: SourceChange,
+    /// Skip inputs the encode history shows were already transcoded with the same
+    /// settings, even if their outputs were moved elsewhere since (without it they are
+    /// transcoded again, with a warning)
+    #[arg(long)]
+    pub trust_history: bool,
+    /// Shared state directory for hosts running batches over the same library; files
+    /// claimed or finished by another host are skipped
+    #[arg(long, value_name = "DIR")]
+    pub ledger: Option<PathBuf>,
+    /// Copy each input to local scratch while the previous file encodes, encode there
+    /// and copy the verified output back (for inputs on slow network storage)
+    #[arg(long)]
+    pub stage_inputs: bool,
+    /// Local scratch directory for --stage-inputs (default: the system temp directory)
+    #[arg(long, value_name = "DIR", requires = "stage_inputs")]
+    pub stage_dir: Option<PathBuf>,
+    /// Keep staged inputs in this directory between runs, by content, so a file encoded
+    /// again (a retry, another CRF) is not transferred again
+    #[arg(
+        long,
+        value_name = "DIR",
+        requires = "stage_inputs",
+        conflicts_with = "stage_dir"
+    )]
+    pub stage_cache: Option<PathBuf>,
+    /// Size limit of --stage-cache; the least recently used inputs go first
+    #[arg(
+        long,
+        value_name = "GIB",
+        default_value_t = 100.0,
+        requires = "stage_cache"
+    )]
+    pub stage_cache_size: f64,
+    /// Spread hardware encodes over these GPUs, pinning each job to one: CUDA indexes
+    /// for NVENC (e.g. 0,1), render nodes for VAAPI/QSV (e.g. /dev/dri/renderD128)
+    #[arg(long, value_name = "LIST", value_delimiter = ',')]
+    pub gpu_devices: Vec<String>,
+    /// After each encode, extract N matched frames from input and output into
+    /// OUTPUT_DIR/spot-check for a quick visual check
+    #[arg(long, value_name = "N")]
+    pub spot_check: Option<usize>,
+    /// Score each spot-check pair with SSIM and flag pairs below this value (e.g. 0.95)
+    #[arg(long, value_name = "MIN", requires = "spot_check")]
+    pub spot_check_ssim: Option<f64>,
+    /// Write a standalone HTML report of the run (per-file sizes, savings, failures
+    /// with ffmpeg's last output) to this path
+    #[arg(long, value_name = "PATH")]
+    pub report_html: Option<PathBuf>,
+    /// Score each output with VMAF (0-100, needs ffmpeg with libvmaf) and reject those
+    /// below this minimum, e.g. 93: the output is deleted and the original kept
+    #[arg(long, value_name = "SCORE")]
+    pub min_vmaf: Option<f64>,
+    /// Re-encode an output that misses --min-vmaf up to N times, lowering the CRF by 2
+    /// each time, before rejecting it
+    #[arg(long, value_name = "N", default_value_t = 0, requires = "min_vmaf")]
+    pub vmaf_retries: u32,
+    /// Every N finished files, print a summary so far, rewrite the --report-html page
+    /// and snapshot the run to OUTPUT_DIR/.transcoderr-checkpoint.json
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    pub checkpoint_every: Option<u64>,
+    /// Run up to N encodes at once (default: the config's `jobs`, else 1); their ffmpeg
+    /// output is kept for failures and reports instead of being shown
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u64).range(1..),
+        conflicts_with = "stage_inputs"
+    )]
+    pub jobs: Option<u64>,
+    /// Skip files the interrupted run recorded as done in
+    /// OUTPUT_DIR/.transcoderr-state.json; its failed and unfinished files run again
+    #[arg(long)]
+    pub resume: bool,
+    /// Dry run: print commands without executing
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+impl BatchArgs {
+    pub fn input_exts(&self) -> &str {
+        self.input_exts.as_deref().unwrap_or(DEFAULT_INPUT_EXTS)
+    }
+}
+
+// One `--root` mapping
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Root {
+    input: PathBuf,
+    output: PathBuf,
+    preset: Option<String>,
+}
+
+impl FromStr for Root {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        // A comma only starts a new key when a key follows, so paths may contain commas
+        let mut fields: Vec<(&str, String)> = Vec::new();
+        for part in value.split(',') {
+            match part.split_once('=') {
+                Some((key, path)) if ["in", "out", "preset"].contains(&key) => {
+                    fields.push((key, path.to_string()))
+                }
+                _ => match fields.last_mut() {
+                    Some((_, path)) => {
+                        path.push(',');
+                        path.push_str(part);
+                    }
+                    None => bail!(
+                        "invalid root '{}': expected in=DIR,out=DIR[,preset=NAME]",
+                        value
+                    ),
+                },
+            }
+        }
+        let field = |key: &str| {
+            fields
+                .iter()
+                .rfind(|(k, _)| *k == key)
+                .map(|(_, v)| v.clone())
+        };
+        let (Some(input), Some(output)) = (field("in"), field("out")) else {
+            bail!(
+                "invalid root '{}': both in=DIR and out=DIR are needed",
+                value
+            );
+        };
+        Ok(Self {
+            input: input.into(),
+            output: output.into(),
+            preset: field("preset"),
+        })
+    }
+}
+
+// Run `f` once per library section: the positional directories, then each `--root`
+// with its preset. Later sections still run when one fails; the run then fails.
+fn for_each_root(args: &BatchArgs, mut f: impl FnMut(&BatchArgs) -> Result<()>) -> Result<()> {
+    let mut sections = Vec::new();
+    if let (Some(input), Some(output)) = (&args.input, &args.output) {
+        sections.push(Root {
+            input: input.clone(),
+            output: output.clone(),
+            preset: None,
+        });
+    }
+    sections.extend(args.roots.iter().cloned());
+    let several = sections.len() > 1;
+    let mut failed = Vec::new();
+    for root in &sections {
+        let mut section = args.clone();
+        section.input_dir = root.input.clone();
+        section.output_dir = root.output.clone();
+        if root.preset.is_some() {
+            section.preset = root.preset.clone();
+        }
+        if !several {
+            return f(&section);
+        }
+        println!(
+            "\n=== {} -> {} ===",
+            root.input.display(),
+            root.output.display()
+        );
+        if let Err(e) = f(&section) {
+            eprintln!("{} {}: {:#}", Marker::Error, root.input.display(), e);
+            failed.push(root.input.display().to_string());
+        }
+    }
+    if !failed.is_empty() {
+        bail!(
+            "{} of {} roots failed: {}",
+            failed.len(),
+            sections.len(),
+            failed.join(", ")
+        );
+    }
+    Ok(())
+}
+
+pub fn batch_transcode(
+    args: &BatchArgs,
+    ownership: &OutputOwnership,
+    subtitle_default: Option<&SubtitlePolicy>,
+    runtime: &Runtime,
+) -> Result<()> {
+    for_each_root(args, |args| {
+        transcode_root(args, ownership, subtitle_default, runtime)
+    })
+}
+
+fn transcode_root(
+    args: &BatchArgs,
+    ownership: &OutputOwnership,
+    subtitle_default: Option<&SubtitlePolicy>,
+    runtime: &Runtime,
+) -> Result<()> {
+    let shows_file = args.shows.as_deref().map(ShowsFile::load).transpose()?;
+    let scan = scan_inputs(&args.input_dir, args.input_exts())?;
+    emit_scan(&runtime.events, &args.input_dir, &scan);
+    if scan.files.is_empty() {
+        println!(
+            "No media files found matching extensions: {}",
+            args.input_exts()
+        );
+        print_issue_summary(&scan.issues);
+        return Ok(());
+    }
+
+    let plan = plan_outputs(
+        &args.input_dir,
+        &args.output_dir,
+        &output_ext(args, runtime)?,
+        &scan.files,
+        &scan.cues,
+        &runtime.plugins,
+    )?;
+    execute_plan(
+        args,
+        ownership,
+        subtitle_default,
+        shows_file.as_ref(),
+        plan,
+        scan.issues,
+        runtime,
+    )
+}
+
+// The extension of a root's outputs: `--ext`, else the container of its preset
+fn output_ext(args: &BatchArgs, runtime: &Runtime) -> Result<String> {
+    runtime
+        .presets
+        .output_ext(args.preset.as_deref(), args.ext.as_deref())
+        .context("choose another --ext or preset")
+}
+
+// `batch missing`: report inputs whose mapped output is absent, and with `--run`
+// transcode only those. Uses the same planner as a normal batch so the expected
+// names always match what a batch run would write.
+pub fn report_missing(
+    args: &BatchArgs,
+    ownership: &OutputOwnership,
+    subtitle_default: Option<&SubtitlePolicy>,
+    run: bool,
+    runtime: &Runtime,
+) -> Result<()> {
+    for_each_root(args, |args| {
+        missing_in_root(args, ownership, subtitle_default, run, runtime)
+    })
+}
+
+fn missing_in_root(
+    args: &BatchArgs,
+    ownership: &OutputOwnership,
+    subtitle_default: Option<&SubtitlePolicy>,
+    run: bool,
+    runtime: &Runtime,
+) -> Result<()> {
+    let shows_file = args.shows.as_deref().map(ShowsFile::load).transpose()?;
+    let scan = scan_inputs(&args.input_dir, args.input_exts())?;
+    emit_scan(&runtime.events, &args.input_dir, &scan);
+    if scan.files.is_empty() {
+        println!(
+            "No media files found matching extensions: {}",
+            args.input_exts()
+        );
+        print_issue_summary(&scan.issues);
+        return Ok(());
+    }
+
+    let mut plan = plan_outputs(
+        &args.input_dir,
+        &args.output_dir,
+        &output_ext(args, runtime)?,
+        &scan.files,
+        &scan.cues,
+        &runtime.plugins,
+    )?;
+    let total = plan.jobs.len();
+    // A zero-length output is what an interrupted or failed ffmpeg run leaves behind
+    plan.jobs
+        .retain(|job| fs::metadata(&job.output).map_or(true, |m| m.len() == 0));
+    plan.found = plan.jobs.len();
+
+    println!(
+        "{} of {} inputs have no output in {}",
+        plan.jobs.len(),
+        total,
+        args.output_dir.display()
+    );
+
+    if !run || plan.jobs.is_empty() {
+        if !plan.jobs.is_empty() {
+            let mut table = Table::new(&[
+                &i18n::t("column-input", &[]),
+                &i18n::t("column-output", &[]),
+            ]);
+            for job in &plan.jobs {
+                table.row(vec![
+                    job.input.display().to_string(),
+                    job.output.display().to_string(),
+                ]);
+            }
+            print!("{}", table);
+        }
+        if plan.collisions > 0 {
+            println!(
+                "  {} skipped due to output name collisions",
+                plan.collisions
+            );
+        }
+        print_issue_summary(&scan.issues);
+        return Ok(());
+    }
+
+    println!();
+    execute_plan(
+        args,
+        ownership,
+        subtitle_default,
+        shows_file.as_ref(),
+        plan,
+        scan.issues,
+        runtime,
+    )
+}
+
+// `batch prune`: find outputs that no current input maps to (the source was deleted or
+// renamed) and list them, or remove them with `--delete`.
+pub fn prune(
+    input_dir: &Path,
+    output_dir: &Path,
+    ext: &str,
+    input_exts: &str,
+    delete: bool,
+    removal: &Removal,
+    runtime: &Runtime,
+) -> Result<()> {
+    let what_if = runtime.what_if.as_ref();
+    let scan = scan_inputs(input_dir, input_exts)?;
+    // Naming plugins take part so renamed outputs are not taken for orphans
+    let plan = plan_outputs(
+        input_dir,
+        output_dir,
+        ext,
+        &scan.files,
+        &scan.cues,
+        &runtime.plugins,
+    )?;
+    if !output_dir.exists() {
+        println!(
+            "Output directory does not exist: {}; nothing to prune",
+            output_dir.display()
+        );
+        return Ok(());
+    }
+
+    let expected: HashSet<OsString> = plan
+        .jobs
+        .iter()
+        .map(|job| paths::comparison_key(&job.output))
+        .collect();
+    let mut output_issues: Vec<PathIssue> = Vec::new();
+    let outputs = collect_media_files(output_dir, &[ext], &mut output_issues)?;
+    let orphans: Vec<&PathBuf> = outputs
+        .iter()
+        // In-place runs share the tree with the sources; only suffixed files are outputs
+        .filter(|out| !plan.same_dir || is_suffixed_output(out))
+        .filter(|out| !expected.contains(&paths::comparison_key(out)))
+        .collect();
+
+    println!(
+        "{} of {} outputs in {} have no source in {}",
+        orphans.len(),
+        outputs.len(),
+        output_dir.display(),
+        input_dir.display()
+    );
+    for orphan in &orphans {
+        println!("  {}", orphan.display());
+    }
+
+    if !delete || orphans.is_empty() {
+        if !orphans.is_empty() {
+            println!(
+                "\nRe-run with --delete to {} these files",
+                removal.describe()
+            );
+        }
+        print_issue_summary(&scan.issues);
+        print_issue_summary(&output_issues);
+        return Ok(());
+    }
+
+    // An unreadable or missing source subtree makes every output under it look orphaned
+    if !scan.issues.is_empty() {
+        print_issue_summary(&scan.issues);
+        bail!(
+            "not removing anything: {} input path(s) could not be read, so their outputs would look orphaned",
+            scan.issues.len()
+        );
+    }
+    if scan.files.is_empty() {
+        bail!(
+            "not removing anything: no media files found in {} (is it mounted?)",
+            input_dir.display()
+        );
+    }
+
+    if let Some(what_if) = what_if {
+        for orphan in orphans {
+            what_if.record(removal.action(orphan, output_dir));
+        }
+        print_issue_summary(&output_issues);
+        return Ok(());
+    }
+    let mut removed = 0usize;
+    let mut failed = 0usize;
+    for orphan in orphans {
+        match removal.remove(orphan, output_dir) {
+            Ok(()) => {
+                removal::remove_empty_parents(orphan, output_dir);
+                removed += 1;
+            }
+            Err(e) => {
+                eprintln!("  {} {:#}", Marker::Error, e);
+                failed += 1;
+            }
+        }
+    }
+    println!(
+        "\nPrune completed ({}): {} removed, {} failed",
+        removal.describe(),
+        removed,
+        failed
+    );
+    print_issue_summary(&output_issues);
+    Ok(())
+}
+
+pub fn emit_scan(events: &Events, root: &Path, scan: &Scan) {
+    events.emit(Event::Scan {
+        root: path_str(root),
+        files: scan.files.len(),
+        unreadable: scan.issues.len(),
+    });
+}
+
+// Media files found under the input directory, plus any paths that could not be read.
+// A single audio file with a cue sheet is one logical input split into its tracks.
+pub struct Scan {
+    pub files: Vec<PathBuf>,
+    pub cues: HashMap<PathBuf, CueSheet>,
+    pub issues: Vec<PathIssue>,
+}
+
+pub fn scan_inputs(input_dir: &Path, input_exts: &str) -> Result<Scan> {
+    if !input_dir.exists() {
+        bail!("Input directory does not exist: {}", input_dir.display());
+    }
+
+    // Parse comma-separated extensions
+    let exts: Vec<&str> = input_exts.split(',').map(|s| s.trim()).collect();
+
+    // Collect all media files recursively; unreadable subtrees are recorded, not fatal
+    let mut issues: Vec<PathIssue> = Vec::new();
+    let found = collect_media_files(input_dir, &[&exts[..], &["cue"]].concat(), &mut issues)?;
+    let (sheets, files): (Vec<PathBuf>, Vec<PathBuf>) = found
+        .into_iter()
+        .partition(|f| f.extension().is_some_and(|e| e.eq_ignore_ascii_case("cue")));
+    let cues = match_cue_sheets(&sheets, &files, &mut issues);
+    Ok(Scan {
+        files,
+        cues,
+        issues,
+    })
+}
+
+// Pair each cue sheet with the audio file it describes. Sheets covering several files
+// (one per track) need no splitting and are ignored; a FILE entry that no longer exists
+// (e.g. `CDImage.wav` after the rip was compressed to FLAC) falls back to a scanned file
+// with the same stem next to the sheet.
+fn match_cue_sheets(
+    sheets: &[PathBuf],
+    files: &[PathBuf],
+    issues: &mut Vec<PathIssue>,
+) -> HashMap<PathBuf, CueSheet> {
+    let mut cues = HashMap::new();
+    for sheet_path in sheets {
+        let sheet = match cue::read(sheet_path) {
+            Ok(sheet) => sheet,
+            Err(e) => {
+                let error = io::Error::new(io::ErrorKind::InvalidData, e.root_cause().to_string());
+                issues.push(PathIssue::new(sheet_path, "parse cue sheet", error));
+                continue;
+            }
+        };
+        let [name] = sheet.files()[..] else {
+            continue;
+        };
+        let dir = sheet_path.parent().unwrap_or(Path::new(""));
+        let named = dir.join(name);
+        let audio = files.iter().find(|f| **f == named).or_else(|| {
+            let stem = Path::new(name).file_stem()?;
+            files
+                .iter()
+                .find(|f| f.parent() == Some(dir) && f.file_stem() == Some(stem))
+        });
+        if let Some(audio) = audio {
+            cues.insert(audio.clone(), sheet);
+        }
+    }
+    cues
+}
+
+// The output mapping for one batch run
+struct Plan<'a> {
+    same_dir: bool,
+    // Number of inputs reported in the run header
+    found: usize,
+    jobs: Vec<PlannedFile<'a>>,
+    collisions: usize,
+    prior_outputs: usize,
+}
+
+// One input file (or one cue sheet track of it) scheduled by a batch run
+struct PlannedFile<'a> {
+    input: &'a PathBuf,
+    output: PathBuf,
+    show: Option<ShowKey>,
+    track: Option<CueTrackRef<'a>>,
+}
+
+// Track `index` of the cue sheet describing the input
+#[derive(Clone, Copy)]
+struct CueTrackRef<'a> {
+    sheet: &'a CueSheet,
+    index: usize,
+}
+
+impl CueTrackRef<'_> {
+    // `03 - Title`, safe as a file name on every platform
+    fn file_name(&self) -> String {
+        let track = &self.sheet.tracks[self.index];
+        let title = track
+            .title
+            .as_deref()
+            .map(|t| t.replace(['/', '\\', ':', '*', '?', '"', '<', '>', '|'], "_"))
+            .unwrap_or_else(|| "Track".to_string());
+        format!("{:02} - {}", track.number, title.trim())
+    }
+
+    // Cut the track out of the album file and tag it: cue times are exact, so the
+    // seek is an accurate output option rather than a keyframe-snapped input one
+    fn ffmpeg_args(&self) -> Vec<String> {
+        let sheet = self.sheet;
+        let track = &sheet.tracks[self.index];
+        let mut args = vec![
+            "-map".to_string(),
+            "0:a".to_string(),
+            "-ss".to_string(),
+            format_seconds(track.start_ms),
+        ];
+        if let Some(next) = sheet.tracks.get(self.index + 1) {
+            args.extend(["-to".to_string(), format_seconds(next.start_ms)]);
+        }
+        // The album file's embedded cue sheet does not describe a single track
+        let mut tags = vec![
+            ("cuesheet", String::new()),
+            ("track", format!("{}/{}", track.number, sheet.tracks.len())),
+        ];
+        let fields = [
+            ("title", track.title.as_ref()),
+            (
+                "artist",
+                track.performer.as_ref().or(sheet.performer.as_ref()),
+            ),
+            ("album_artist", sheet.performer.as_ref()),
+            ("album", sheet.title.as_ref()),
+            ("date", sheet.date.as_ref()),
+            ("genre", sheet.genre.as_ref()),
+        ];
+        for (key, value) in fields {
+            if let Some(value) = value {
+                tags.push((key, value.clone()));
+            }
+        }
+        for (key, value) in tags {
+            args.extend(["-metadata".to_string(), format!("{}={}", key, value)]);
+        }
+        args
+    }
+}
+
+fn format_seconds(ms: u64) -> String {
+    format!("{}.{:03}", ms / 1000, ms % 1000)
+}
+
+fn plan_outputs<'a>(
+    input_path: &Path,
+    output_path: &Path,
+    ext: &str,
+    files: &'a [PathBuf],
+    cues: &'a HashMap<PathBuf, CueSheet>,
+    plugins: &Plugins,
+) -> Result<Plan<'a>> {
+    // Check if input and output directories are the same
+    let same_dir = paths_equivalent(input_path, output_path);
+
+    let mut mapped: Vec<(&PathBuf, PathBuf, PathBuf, Option<CueTrackRef>)> =
+        Vec::with_capacity(files.len());
+    for input_file in files {
+        let rel_path = relative_to(input_file, input_path).with_context(|| {
+            format!(
+                "{} is not inside {}",
+                input_file.display(),
+                input_path.display()
+            )
+        })?;
+
+        // A cue-split album becomes one output per track, next to where the album's
+        // output would have gone
+        if let Some(sheet) = cues.get(input_file) {
+            let rel_dir = rel_path.parent().unwrap_or(Path::new(""));
+            for index in 0..sheet.tracks.len() {
+                let track = CueTrackRef { sheet, index };
+                let output_file = if same_dir {
+                    input_file.with_file_name(format!("{}_transcoded.{}", track.file_name(), ext))
+                } else {
+                    output_path
+                        .join(rel_dir)
+                        .join(format!("{}.{}", track.file_name(), ext))
+                };
+                mapped.push((input_file, rel_path.clone(), output_file, Some(track)));
+            }
+            continue;
+        }
+
+        let output_file = if same_dir {
+            // When writing to same directory, use safe suffix
+            suffixed_output(input_file, ext)
+        } else if let Some(name) = plugins.output_name(input_file, &rel_path, ext)? {
+            output_path.join(name)
+        } else {
+            // Mirror structure in different output dir
+            let mut out = output_path.join(&rel_path);
+            out.set_extension(ext);
+            out
+        };
+        mapped.push((input_file, rel_path, output_file, None));
+    }
+
+    // Outputs of an earlier run (`x_transcoded.mkv` next to `x.mp4`, or an output dir
+    // nested in the input dir) are not new inputs
+    let output_keys: HashSet<OsString> = mapped
+        .iter()
+        .map(|(_, _, out, _)| paths::comparison_key(out))
+        .collect();
+
+    // Plan every output path up front so two inputs that map to the same output
+    // (e.g. `clip.mp4` and `clip.mkv`, or `Clip.mkv`/`clip.MKV` on Windows) are caught
+    // before the second one silently overwrites the first.
+    let found = mapped.len();
+    let mut jobs: Vec<PlannedFile> = Vec::with_capacity(found);
+    let mut planned_outputs: HashMap<OsString, &PathBuf> = HashMap::new();
+    let mut collisions = 0usize;
+    let mut prior_outputs = 0usize;
+    for (input_file, rel_path, output_file, track) in mapped {
+        if output_keys.contains(&paths::comparison_key(input_file)) {
+            prior_outputs += 1;
+            continue;
+        }
+
+        match planned_outputs.get(&paths::comparison_key(&output_file)) {
+            Some(first) => {
+                eprintln!(
+                    "{} {} and {} both map to {}; skipping the second",
+                    Marker::Warning,
+                    first.display(),
+                    input_file.display(),
+                    output_file.display()
+                );
+                collisions += 1;
+            }
+            None => {
+                planned_outputs.insert(paths::comparison_key(&output_file), input_file);
+                jobs.push(PlannedFile {
+                    input: input_file,
+                    output: output_file,
+                    show: track.is_none().then(|| shows::detect(&rel_path)).flatten(),
+                    track,
+                });
+            }
+        }
+    }
+
+    // Group episodes by show and season; the sort is stable so files keep scan order
+    // within a group, and files without a detected show come first.
+    jobs.sort_by(|a, b| a.show.cmp(&b.show));
+
+    Ok(Plan {
+        same_dir,
+        found: found - prior_outputs,
+        jobs,
+        collisions,
+        prior_outputs,
+    })
+}
+
+fn execute_plan(
+    args: &BatchArgs,
+    ownership: &OutputOwnership,
+    subtitle_default: Option<&SubtitlePolicy>,
+    shows_file: Option<&ShowsFile>,
+    plan: Plan,
+    mut issues: Vec<PathIssue>,
+    runtime: &Runtime,
+) -> Result<()> {
+    let events = &runtime.events;
+    let dry_run = args.dry_run;
+    if args.min_vmaf.is_some() {
+        features::require(Feature::Vmaf, "--min-vmaf")?;
+    }
+    let ext = output_ext(args, runtime)?;
+    let defaults = Defaults::new(args, shows_file, subtitle_default, runtime);
+    let settings = defaults.describe(&ext);
+
+    if plan.same_dir {
+        println!(
+            "Found {} files to transcode IN-PLACE ({}) - output will use '_transcoded' suffix",
+            plan.found, settings
+        );
+    } else {
+        println!("Found {} files to transcode ({})", plan.found, settings);
+    }
+
+    events.emit(Event::Plan {
+        jobs: plan.jobs.len(),
+        collisions: plan.collisions,
+    });
+
+    let batch_started = Instant::now();
+    let mut current_group: Option<&ShowKey> = None;
+    let mut counts = Counts::default();
+    let ledger = match &args.ledger {
+        Some(dir) if !dry_run => Some(Ledger::open(dir)?),
+        _ => None,
+    };
+    let mut stager = open_stager(args, dry_run)?;
+    let spot_check = args
+        .spot_check
+        .filter(|frames| *frames > 0)
+        .map(|frames| SpotCheck::new(&args.output_dir, frames, args.spot_check_ssim));
+    let checkpoints = match args.checkpoint_every {
+        Some(every) if dry_run => {
+            println!(
+                "{} Would checkpoint every {} files to {}",
+                Marker::DryRun,
+                every,
+                args.output_dir.join(checkpoint::FILE_NAME).display()
+            );
+            None
+        }
+        Some(every) => Some(Checkpoints::new(
+            every as usize,
+            &args.output_dir,
+            &settings,
+        )),
+        None => None,
+    };
+    let report = match &args.report_html {
+        Some(path) if dry_run => {
+            println!(
+                "{} Would write a report to {}",
+                Marker::DryRun,
+                path.display()
+            );
+            None
+        }
+        Some(path) => Some(Report::new(
+            path,
+            &args.input_dir,
+            &args.output_dir,
+            &settings,
+            runtime.units,
+        )),
+        None => None,
+    };
+    let live = runtime.preview.is_some() || runtime.snapshot_every.is_some();
+    let mut workers = open_workers(args.jobs, dry_run, live)?;
+    let resumed = resumed_outputs(args, &settings, &plan.jobs)?;
+    counts.resumed = resumed.len();
+    let state = match dry_run {
+        true => None,
+        false => Some(State::start(
+            &args.output_dir,
+            &settings,
+            plan.jobs
+                .iter()
+                .map(|job| output_key(&job.output, &args.output_dir)),
+            |key| resumed.contains(key),
+        )?),
+    };
+    let mut tally = Tally {
+        report,
+        checkpoints,
+        state,
+        ..Tally::default()
+    };
+    let finish = Finish {
+        args,
+        runtime,
+        ownership,
+        ledger: ledger.as_ref(),
+        spot_check: spot_check.as_ref(),
+        total: plan.jobs.len(),
+        started: batch_started,
+        parallel: workers.is_some(),
+    };
+    // Probed ahead in parallel, so each job below finds its probe in the cache
+    let inputs: Vec<&Path> = plan.jobs.iter().map(|job| job.input.as_path()).collect();
+    probe::probe_all(&inputs, &runtime.cache, runtime.probe_workers);
+
+    for (idx, job) in plan.jobs.iter().enumerate() {
+        let (input_file, output_file) = (job.input, &job.output);
+        // Wait for a free slot first, so the next file's lines follow the finished one's
+        tally.wait_for_slot(&finish, workers.as_mut());
+        if let Some(key) = &job.show {
+            if current_group != Some(key) {
+                match key.season {
+                    Some(season) => println!("\n== {} / Season {} ==", key.show, season),
+                    None => println!("\n== {} ==", key.show),
+                }
+                current_group = Some(key);
+            }
+        }
+
+        println!(
+            "\n[{}/{}] {} -> {}",
+            idx + 1,
+            plan.jobs.len(),
+            input_file.display(),
+            output_file.display()
+        );
+        if resumed.contains(&output_key(output_file, &args.output_dir)) {
+            println!("  Skipping: finished before the interruption (--resume)");
+            tally.skip(&finish, job, defaults.preset, "finished before");
+            continue;
+        }
+        // An empty or truncated output (no duration) is what a failed run leaves behind
+        if args.skip_existing && output_file.exists() {
+            let valid = probe::probe_cached(output_file, &runtime.cache)
+                .ok()
+                .and_then(|info| info.format.duration_seconds())
+                .is_some_and(|seconds| seconds > 0.0);
+            if valid {
+                println!("  Skipping: output exists (--skip-existing)");
+                counts.existing += 1;
+                tally.skip(&finish, job, defaults.preset, "output exists");
+                continue;
+            }
+            println!("  Output exists but has no duration; transcoding again");
+        }
+
+        // Inputs --only-if does not hold for (or that cannot be probed) are skipped or remuxed
+        let remux = match &args.only_if {
+            Some(condition) => {
+                let holds = match probe::probe_cached(input_file, &runtime.cache) {
+                    Ok(info) => condition.holds(&info),
+                    Err(e) => {
+                        eprintln!(
+                            "  {} --only-if cannot probe the input ({:#})",
+                            Marker::Warning,
+                            e
+                        );
+                        false
+                    }
+                };
+                if !holds {
+                    counts.unmatched += 1;
+                }
+                match (holds, args.otherwise) {
+                    (true, _) => false,
+                    (false, Otherwise::Copy) => {
+                        println!("  Remuxing: does not match --only-if");
+                        true
+                    }
+                    (false, Otherwise::Skip) => {
+                        println!("  Skipping: does not match --only-if");
+                        tally.skip(&finish, job, defaults.preset, "no --only-if match");
+                        continue;
+                    }
+                }
+            }
+            None => false,
+        };
+
+        let mut file = defaults.resolve(job, remux, &mut counts);
+        let settings = defaults.settings_hash(&file);
+        if let Some(reason) = history_skip(args, runtime, job, &settings, &mut counts) {
+            tally.skip(&finish, job, file.preset, reason);
+            continue;
+        }
+        defaults.plan_streams(job, &settings, &mut file);
+
+        if let Some(key) = &job.show {
+            let stats = tally.show_stats.entry(key.show.clone()).or_default();
+            stats.files += 1;
+            if let Some(season) = key.season {
+                *stats.seasons.entry(season).or_default() += 1;
+            }
+            stats.input_bytes += fs::metadata(input_file).map(|m| m.len()).unwrap_or(0);
+        }
+
+        if dry_run {
+            print_dry_run(&finish, &defaults, job, &file);
+            continue;
+        }
+
+        // Keyed by the output's place under the output root, which every host agrees
+        // on however the share is mounted
+        let claim = match claim_output(finish.ledger, &output_key(output_file, &args.output_dir))? {
+            Claimed::Mine(claim) => claim,
+            Claimed::Elsewhere { message, reason } => {
+                println!("  Skipping: {}", message);
+                counts.elsewhere += 1;
+                tally.skip(&finish, job, file.preset, reason);
+                continue;
+            }
+        };
+        if let Some(stager) = &mut stager {
+            stager.keep_only(input_file);
+        }
+
+        runtime.gate.wait(input_file, output_file, events);
+        events.emit(Event::Start {
+            input: path_str(input_file),
+            output: path_str(output_file),
+            index: idx + 1,
+            total: plan.jobs.len(),
+            device: file.gpu.as_ref().map(|gpu| gpu.device().to_string()),
+        });
+        let FileEncode {
+            preset: file_preset,
+            vcodec: file_vcodec,
+            acodec: file_acodec,
+            extra: file_extra,
+            input_opts,
+            run_env,
+            error: job_error,
+            gpu,
+            stabilizer,
+            cover,
+            adjusted,
+            stream_copy,
+            ..
+        } = file;
+        let running = Running {
+            idx,
+            job,
+            file_preset,
+            settings,
+            adjusted,
+            claim,
+            started: Instant::now(),
+            stream_copy,
+            cover,
+            stabilizer,
+            _gpu: gpu,
+        };
+
+        // Check filesystem access up front so permission problems get a precise report,
+        // then perform the transcode
+        let preflight = match job_error {
+            Some(message) => Err(anyhow!(message)),
+            None => preflight_paths(input_file, output_file).map_err(|issue| {
+                let error = anyhow!(issue.to_string());
+                issues.push(issue);
+                error
+            }),
+        };
+        let gate = quality_gate(args, &file_vcodec);
+        // Analysis passes for the encode, and the silence trim it adds to `file_extra`
+        let prepare = |input: &Path| -> Result<Vec<String>> {
+            if let Some(cover) = &running.cover {
+                cover.prepare()?;
+            }
+            if let Some(stabilizer) = &running.stabilizer {
+                stabilizer.detect(input, runtime.stats)?;
+            }
+            let trim = match &defaults.silence {
+                Some(silence) => {
+                    silence.args(input, &runtime.cache, args.speed.map_or(1.0, Speed::factor))?
+                }
+                None => Vec::new(),
+            };
+            Ok([file_extra.as_slice(), &trim].concat())
+        };
+
+        // Side by side, the preparation runs here and only the encode on its own thread
+        if let Some(workers) = &mut workers {
+            match preflight.and_then(|()| prepare(input_file)) {
+                Ok(file_extra) => {
+                    let (input, output) = (input_file.to_path_buf(), output_file.clone());
+                    let codecs = (file_vcodec, file_acodec);
+                    let remux_fallback = runtime.remux_fallback;
+                    workers.spawn(running, move || {
+                        let run = |extra: &[String]| {
+                            transcode_quietly(
+                                &input,
+                                &input_opts,
+                                &output,
+                                (&codecs.0, &codecs.1),
+                                extra,
+                                &run_env,
+                                remux_fallback,
+                            )
+                        };
+                        match &gate {
+                            Some(gate) => gate.encode(&input, &output, &file_extra, run),
+                            None => run(&file_extra),
+                        }
+                    });
+                }
+                Err(e) => tally.finish(&finish, running, Err(e)),
+            }
+            continue;
+        }
+
+        let result = preflight.and_then(|()| {
+            let encode = |input: &Path, output: &Path| {
+                let file_extra = prepare(input)?;
+                let run = |extra: &[String]| {
+                    transcode(
+                        input,
+                        &input_opts,
+                        output,
+                        (&file_vcodec, &file_acodec),
+                        extra,
+                        &run_env,
+                        runtime,
+                    )
+                };
+                match &gate {
+                    Some(gate) => gate.encode(input, output, &file_extra, run),
+                    None => run(&file_extra),
+                }
+            };
+            match &mut stager {
+                Some(stager) => {
+                    // Fetch the next input while this one encodes
+                    if let Some(next) = plan.jobs.get(idx + 1) {
+                        stager.prefetch(next.input);
+                    }
+                    stager.run(input_file, output_file, encode)
+                }
+                None => encode(input_file, output_file),
+            }
+        });
+        tally.finish(&finish, running, result);
+    }
+    tally.drain(&finish, workers.as_mut());
+    let extras = Extras {
+        stager: stager.as_ref(),
+        gpus: defaults.gpus.as_ref(),
+        issues: &issues,
+    };
+    print_summary(&finish, &plan, tally, &counts, extras)
+}
+
+// The batch-wide settings every file starts from
+struct Defaults<'a> {
+    args: &'a BatchArgs,
+    runtime: &'a Runtime,
+    preset: Option<&'a str>,
+    // `--preset auto`, resolved per file
+    auto: bool,
+    // The preset applied once: video codec, audio codec and ffmpeg args
+    applied: (String, String, Vec<String>),
+    shows_file: Option<&'a ShowsFile>,
+    subtitle_default: Option<&'a SubtitlePolicy>,
+    silence: Option<SilenceTrim>,
+    subtitle_timing: Option<SubTiming>,
+    gpus: Option<GpuPool>,
+}
+
+// One file's encode, as its preset, show, probe, sidecar and GPU make it
+struct FileEncode<'a> {
+    preset: Option<&'a str>,
+    vcodec: String,
+    acodec: String,
+    extra: Vec<String>,
+    input_opts: Vec<String>,
+    run_env: RunEnv,
+    subtitle_default: Option<&'a SubtitlePolicy>,
+    // Why the job fails without encoding, e.g. a sidecar that cannot be read
+    error: Option<String>,
+    // Held for the whole job, so the next one goes to another device
+    gpu: Option<Lease<'a>>,
+    // Dropping these at the end of the job removes their files
+    stabilizer: Option<Stabilizer>,
+    cover: Option<Cover>,
+    // The frame size --odd-size changed the video to
+    adjusted: Option<String>,
+    stream_copy: bool,
+}
+
+impl<'a> Defaults<'a> {
+    fn new(
+        args: &'a BatchArgs,
+        shows_file: Option<&'a ShowsFile>,
+        subtitle_default: Option<&'a SubtitlePolicy>,
+        runtime: &'a Runtime,
+    ) -> Self {
+        let preset = args.preset.as_deref();
+        // Apply the preset once to get effective settings; `auto` is resolved per file
+        let auto = preset == Some(presets::AUTO);
+        let applied = runtime.presets.apply(
+            preset.filter(|_| !auto),
+            args.vcodec.as_deref(),
+            args.acodec.as_deref(),
+            DEFAULT_VCODEC,
+            &args.extra,
+        );
+        Self {
+            args,
+            runtime,
+            preset,
+            auto,
+            applied,
+            shows_file,
+            subtitle_default,
+            silence: silence_trim(args),
+            subtitle_timing: SubTiming::new(args.sub_shift, args.sub_fps_from, args.sub_fps_to),
+            gpus: GpuPool::new(&args.gpu_devices),
+        }
+    }
+
+    // The settings line of the run header, checkpoints and the report
+    fn describe(&self, ext: &str) -> String {
+        match self.auto {
+            true => format!("preset=auto, ext={}", ext),
+            false => format!(
+                "vcodec={}, acodec={}, ext={}",
+                self.applied.0, self.applied.1, ext
+            ),
+        }
+    }
+
+    // The codecs, args and environment of `job`'s encode, before its streams are planned
+    fn resolve(&self, job: &PlannedFile, remux: bool, counts: &mut Counts) -> FileEncode<'_> {
+        let (args, runtime) = (self.args, self.runtime);
+        let (vcodec, acodec) = (args.vcodec.as_deref(), args.acodec.as_deref());
+        let input_file = job.input;
+        // Per-show overrides replace the batch-wide settings for this file
+        let overrides = job
+            .show
+            .as_ref()
+            .and_then(|key| self.shows_file?.lookup(&key.show));
+        // `--preset auto` picks from the file's content; a file that cannot be probed
+        // gets the batch defaults
+        let file_preset = if self.auto {
+            match recommend::recommend(input_file, &runtime.cache) {
+                Ok(rec) => {
+                    println!("  auto preset: {} ({})", rec.preset, rec.reasons.join("; "));
+                    *counts.auto_choices.entry(rec.preset).or_default() += 1;
+                    Some(rec.preset)
+                }
+                Err(e) => {
+                    eprintln!(
+                        "  {} cannot pick a preset ({:#}); using defaults",
+                        Marker::Warning,
+                        e
+                    );
+                    *counts.auto_choices.entry("defaults").or_default() += 1;
+                    None
+                }
+            }
+        } else {
+            self.preset
+        };
+        let (mut file_vcodec, mut file_acodec, mut show_extra) = match overrides {
+            Some(o) => o.apply(
+                &runtime.presets,
+                file_preset,
+                vcodec,
+                acodec,
+                DEFAULT_VCODEC,
+                &args.extra,
+            ),
+            None if self.auto => {
+                runtime
+                    .presets
+                    .apply(file_preset, vcodec, acodec, DEFAULT_VCODEC, &args.extra)
+            }
+            None => self.applied.clone(),
+        };
+        if remux {
+            smart::copy_video(&mut file_vcodec, &mut show_extra);
+            smart::copy_audio(&mut file_acodec, &mut show_extra);
+        }
+        // Streams already in the target format are copied; cue tracks are cut, so encoded
+        if args.smart && !remux && job.track.is_none() {
+            match probe::probe_cached(input_file, &runtime.cache) {
+                Ok(info) => {
+                    let copied =
+                        smart::apply(&info, &mut file_vcodec, &mut file_acodec, &mut show_extra);
+                    if !copied.is_empty() {
+                        println!(
+                            "  Copying {}: already in the target codec (--smart)",
+                            copied.join(" and ")
+                        );
+                        counts.smart_copies += 1;
+                    }
+                }
+                Err(e) => eprintln!(
+                    "  {} --smart cannot probe the input ({:#}); encoding every stream",
+                    Marker::Warning,
+                    e
+                ),
+            }
+        }
+        // The GPU's encoder and decoding args under --hwaccel
+        let mut hw_input = Vec::new();
+        if let Some(backend) = runtime.hwaccel {
+            if let Some(encoder) = backend.apply(&file_vcodec, &mut show_extra, &mut hw_input) {
+                file_vcodec = encoder;
+            }
+        }
+        // ffmpeg's variables and working directory: the preset's, then the show's
+        let mut run_env = match overrides {
+            Some(o) => o.run_env(&runtime.presets, file_preset),
+            None => runtime.presets.run_env(file_preset),
+        };
+        // Per-file args are the most specific of all, so they go last; a sidecar that
+        // cannot be read fails the job rather than encoding without its flags
+        let mut job_error = match runtime.file_args.for_input(input_file) {
+            Ok(file_args) => {
+                if !file_args.is_empty() {
+                    println!("  Per-file args: {}", file_args.join(" "));
+                }
+                show_extra.extend(file_args);
+                None
+            }
+            Err(e) => Some(format!("{:#}", e)),
+        };
+        if job_error.is_none() {
+            if let Err(e) = vbv::adapt(&file_vcodec, &mut show_extra) {
+                job_error = Some(format!("{:#}", e));
+            }
+        }
+        // A preset picked per file (auto, per-show) may not fit the root's container
+        if job_error.is_none() {
+            let job_preset = overrides.and_then(|o| o.preset.as_deref()).or(file_preset);
+            let ext = job.output.extension().map(|e| e.to_string_lossy());
+            if let Err(e) = runtime.presets.output_ext(job_preset, ext.as_deref()) {
+                job_error = Some(format!("{:#}", e));
+            }
+        }
+        // Part of the extra args, so the settings hash covers the retiming
+        if let Some(speed) = args.speed {
+            if let Some(warning) = speed.apply(&file_vcodec, &mut show_extra) {
+                eprintln!("  {} {}", Marker::Warning, warning);
+            }
+        }
+        let mut input_opts = match args.legacy_source {
+            true => {
+                let info = probe::probe_cached(input_file, &runtime.cache).ok();
+                let codec = info
+                    .as_ref()
+                    .and_then(|i| i.video_stream()?.codec_name.as_deref());
+                legacy::apply(&file_vcodec, codec, &mut show_extra);
+                legacy::input_args()
+            }
+            false => Vec::new(),
+        };
+        input_opts.splice(0..0, hw_input);
+        let gpu = self.gpus.as_ref().and_then(|pool| pool.lease(&file_vcodec));
+        if let Some(gpu) = &gpu {
+            gpu.apply(&mut run_env, &mut input_opts);
+            println!("  On {}", gpu.describe());
+        }
+        FileEncode {
+            preset: file_preset,
+            vcodec: file_vcodec,
+            acodec: file_acodec,
+            extra: show_extra,
+            input_opts,
+            run_env,
+            subtitle_default: overrides
+                .and_then(|o| o.subtitle_default.as_ref())
+                .or(self.subtitle_default),
+            error: job_error,
+            gpu,
+            stabilizer: None,
+            cover: None,
+            adjusted: None,
+            stream_copy: false,
+        }
+    }
+
+    // What the history records `file`'s encode as
+    fn settings_hash(&self, file: &FileEncode) -> String {
+        let args = self.args;
+        let mut options = history::encode_options(
+            file.preset,
+            &args.languages,
+            args.keyint,
+            args.stabilize,
+            args.stereo_compat,
+            args.deterministic,
+            self.silence.as_ref(),
+        );
+        options.extend(self.subtitle_timing.as_ref().map(SubTiming::option));
+        history::settings_hash(&file.vcodec, &file.acodec, &file.extra, &options)
+    }
+
+    // Put the per-file stream args (from probing) before `file`'s shared ones, then the
+    // filters that must come after every other one
+    fn plan_streams(&self, job: &PlannedFile, settings: &str, file: &mut FileEncode) {
+        let (args, runtime) = (self.args, self.runtime);
+        let (input_file, output_file) = (job.input, &job.output);
+        let policies = streams::Policies {
+            languages: &args.languages,
+            subtitle_default: file.subtitle_default,
+            stereo_compat: args.stereo_compat,
+            subtitle_timing: self.subtitle_timing,
+        };
+        // A cue track maps its one audio stream itself
+        let mut file_extra = match &job.track {
+            Some(track) => track.ffmpeg_args(),
+            None => streams::plan_args(input_file, &runtime.cache, &policies),
+        };
+        file_extra.append(&mut file.extra);
+        file_extra.extend(history::metadata_args(settings));
+        file.stream_copy = streamcopy::is_stream_copy(&file.vcodec, &file.acodec, &file_extra);
+        if file.stream_copy {
+            println!("  Stream copy: only the container changes");
+        }
+        let copy_video = file.vcodec == "copy";
+        if args.stabilize && copy_video {
+            eprintln!(
+                "  {} --stabilize needs the video encoded; not applied to copied video",
+                Marker::Warning
+            );
+        }
+        if let Some(keyint) = args.keyint.filter(|_| !copy_video) {
+            file_extra.extend(keyint.args(input_file, output_file, &runtime.cache));
+        }
+        file.stabilizer =
+            (args.stabilize && !copy_video).then(|| Stabilizer::new(&runtime.cache, input_file));
+        if let Some(stabilizer) = &file.stabilizer {
+            stabilizer.apply(&mut file_extra);
+        }
+        // After every other video filter, which may change the size; cue tracks are audio
+        file.adjusted = match &job.track {
+            Some(_) => None,
+            None => alignment::apply(
+                args.odd_size,
+                &file.vcodec,
+                probe::probe_cached(input_file, &runtime.cache)
+                    .ok()
+                    .as_ref(),
+                &mut file_extra,
+            ),
+        };
+        if let Some(adjusted) = &file.adjusted {
+            println!("  Frame size: {}", adjusted);
+        }
+        if args.deterministic && !file.stream_copy {
+            if let Some(warning) = deterministic::apply(&file.vcodec, &mut file_extra) {
+                eprintln!("  {} {}", Marker::Warning, warning);
+            }
+        }
+        // Cue tracks are audio and get no cover
+        file.cover = match &args.embed_thumbnail {
+            Some(thumbnail) if job.track.is_none() && file.error.is_none() => {
+                match Cover::new(thumbnail, input_file, output_file, &runtime.cache)
+                    .and_then(|cover| cover.apply(&mut file_extra).map(|()| cover))
+                {
+                    Ok(cover) => Some(cover),
+                    Err(e) => {
+                        file.error = Some(format!("{:#}", e));
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
+        file.extra = file_extra;
+    }
+}
+
+// Why `job` is skipped for what the history holds: its output was made with the same
+// settings (--refresh-if-settings-changed), from a source that since changed (with
+// --on-source-change notify), or its input was encoded before (--trust-history)
+fn history_skip(
+    args: &BatchArgs,
+    runtime: &Runtime,
+    job: &PlannedFile,
+    settings: &str,
+    counts: &mut Counts,
+) -> Option<&'static str> {
+    let (input_file, output_file) = (job.input, &job.output);
+    if args.refresh_if_settings_changed && output_file.exists() {
+        let recorded = history::recorded_settings(output_file, &runtime.history);
+        let source_changed = recorded.as_deref() == Some(settings)
+            && history::source_changed(input_file, output_file, &runtime.history);
+        if source_changed {
+            counts.sources_changed += 1;
+            if !args.dry_run {
+                runtime.events.emit(Event::SourceChanged {
+                    input: path_str(input_file),
+                    output: path_str(output_file),
+                });
+            }
+        }
+        match recorded {
+            Some(_) if source_changed && args.on_source_change == SourceChange::Requeue => {
+                println!("  Source changed since its output was made; transcoding again")
+            }
+            Some(_) if source_changed => {
+                println!(
+                    "  {} source changed since its output was made",
+                    Marker::Warning
+                );
+                return Some("source changed");
+            }
+            Some(recorded) if recorded == settings => {
+                println!("  Up to date (settings {})", settings);
+                counts.up_to_date += 1;
+                return Some("up to date");
+            }
+            Some(recorded) => println!("  Settings changed ({} -> {})", recorded, settings),
+            None => println!("  No recorded settings; transcoding again"),
+        }
+    }
+    // Cue tracks share their input and settings, so only whole files can repeat
+    let previous = job
+        .track
+        .is_none()
+        .then(|| history::previous_encode(input_file, settings, &runtime.history))
+        .flatten()?;
+    println!(
+        "  {} {}",
+        Marker::Warning,
+        history::duplicate_warning(&previous)
+    );
+    if !args.trust_history {
+        return None;
+    }
+    println!("  Skipping (--trust-history)");
+    counts.trusted += 1;
+    Some("already transcoded")
+}
+
+// Say what a dry run would do for `job`, and record it as planned in the --json results
+fn print_dry_run(batch: &Finish, defaults: &Defaults, job: &PlannedFile, file: &FileEncode) {
+    let Finish { args, runtime, .. } = *batch;
+    let input_file = job.input;
+    if let Some(results) = &runtime.results {
+        let mut result = FileResult::new(
+            input_file,
+            &job.output,
+            file.preset,
+            match file.error {
+                Some(_) => results::Status::Failed,
+                None => results::Status::Planned,
+            },
+        );
+        result.error = file.error.clone();
+        results.add(result);
+    }
+    if let Some(message) = &file.error {
+        println!("  {} Would fail: {}", Marker::DryRun, message);
+        return;
+    }
+    println!(
+        "  {} Would transcode with vcodec={} acodec={} extra={:?}",
+        Marker::DryRun,
+        file.vcodec,
+        file.acodec,
+        file.extra
+    );
+    if !file.input_opts.is_empty() {
+        println!(
+            "  {} Would read the input with {:?}",
+            Marker::DryRun,
+            file.input_opts
+        );
+    }
+    if !file.run_env.is_empty() {
+        println!(
+            "  {} Would run ffmpeg with {}",
+            Marker::DryRun,
+            file.run_env.describe()
+        );
+    }
+    if let Some(stabilizer) = &file.stabilizer {
+        println!(
+            "  {} Would analyse shake first: ffmpeg {}",
+            Marker::DryRun,
+            stabilizer.describe_detect(input_file)
+        );
+    }
+    if let Some(cover) = &file.cover {
+        println!(
+            "  {} Would embed cover {}",
+            Marker::DryRun,
+            cover.describe()
+        );
+    }
+    if let Some(preview) = &runtime.preview {
+        println!("  {} Would stream a preview to {}", Marker::DryRun, preview);
+    }
+    if let Some(silence) = &defaults.silence {
+        println!(
+            "  {} Would detect silence first: ffmpeg {}",
+            Marker::DryRun,
+            silence.describe_detect(input_file)
+        );
+    }
+    if batch.ownership.is_set() {
+        println!(
+            "  {} Would set {} on output",
+            Marker::DryRun,
+            batch.ownership.describe()
+        );
+    }
+    if let Some(gate) = quality_gate(args, &file.vcodec) {
+        println!("  {} Would require {}", Marker::DryRun, gate.describe());
+    }
+    if let Some(spot_check) = batch.spot_check {
+        println!(
+            "  {} Would extract spot-check frames into {}",
+            Marker::DryRun,
+            spot_check.dir().display()
+        );
+    }
+}
+
+// Where staged inputs go: the stage cache, else --stage-dir, else the temp directory
+fn scratch_dir(args: &BatchArgs) -> PathBuf {
+    args.stage_cache
+        .clone()
+        .or_else(|| args.stage_dir.clone())
+        .unwrap_or_else(std::env::temp_dir)
+}
+
+// The --stage-inputs stager; a dry run only says where it would stage
+fn open_stager(args: &BatchArgs, dry_run: bool) -> Result<Option<Stager>> {
+    if !args.stage_inputs {
+        return Ok(None);
+    }
+    let scratch = scratch_dir(args);
+    if dry_run {
+        println!(
+            "{} Would stage inputs through {}",
+            Marker::DryRun,
+            scratch.display()
+        );
+        return Ok(None);
+    }
+    let cache = match &args.stage_cache {
+        Some(dir) => Some(StageCache::open(
+            dir,
+            (args.stage_cache_size * 1024.0 * 1024.0 * 1024.0) as u64,
+        )?),
+        None => None,
+    };
+    let stager = Stager::new(&scratch, cache)?;
+    println!("Staging inputs through {}", stager.dir().display());
+    Ok(Some(stager))
+}
+
+// The pool for --jobs, none for one encode at a time or a dry run. `live` output (a
+// preview, snapshots) follows one encode, so it needs them one at a time.
+fn open_workers<T>(jobs: Option<u64>, dry_run: bool, live: bool) -> Result<Option<Workers<T>>> {
+    match jobs.unwrap_or(1) {
+        1 => Ok(None),
+        jobs if dry_run => {
+            println!("{} Would run {} encodes at once", Marker::DryRun, jobs);
+            Ok(None)
+        }
+        _ if live => bail!("--jobs cannot be combined with --preview or --snapshot-every"),
+        jobs => {
+            println!("Running up to {} encodes at once", jobs);
+            Ok(Some(Workers::new(jobs as usize)))
+        }
+    }
+}
+
+// Under --resume, the outputs of the files the interrupted run finished that are still
+// there
+fn resumed_outputs(
+    args: &BatchArgs,
+    settings: &str,
+    jobs: &[PlannedFile],
+) -> Result<HashSet<String>> {
+    if !args.resume {
+        return Ok(HashSet::new());
+    }
+    let Some(previous) = Previous::load(&args.output_dir)? else {
+        println!(
+            "No {} in {}; starting from the beginning",
+            resume::FILE_NAME,
+            args.output_dir.display()
+        );
+        return Ok(HashSet::new());
+    };
+    if previous.settings() != settings {
+        println!(
+            "  {} the interrupted run had other settings ({}); its finished files are kept",
+            Marker::Warning,
+            previous.settings()
+        );
+    }
+    let resumed: HashSet<String> = jobs
+        .iter()
+        .filter(|job| job.output.exists())
+        .map(|job| output_key(&job.output, &args.output_dir))
+        .filter(|key| previous.is_done(key))
+        .collect();
+    if !resumed.is_empty() {
+        println!("Resuming: {} files were finished before", resumed.len());
+    }
+    Ok(resumed)
+}
+
+// This host's claim on an output under --ledger, or why another host has it
+enum Claimed {
+    // None without a ledger
+    Mine(Option<Claim>),
+    Elsewhere {
+        message: String,
+        // For the report
+        reason: &'static str,
+    },
+}
+
+fn claim_output(ledger: Option<&Ledger>, key: &str) -> Result<Claimed> {
+    let Some(ledger) = ledger else {
+        return Ok(Claimed::Mine(None));
+    };
+    Ok(match ledger.claim(key)? {
+        ClaimResult::Claimed(claim) => Claimed::Mine(Some(claim)),
+        ClaimResult::Done { host } => Claimed::Elsewhere {
+            message: format!("already transcoded by {}", host),
+            reason: "done by another host",
+        },
+        ClaimResult::Busy { owner } => Claimed::Elsewhere {
+            message: format!("being transcoded by {}", owner),
+            reason: "claimed by another host",
+        },
+    })
+}
+
+// Files neither encoded nor failed, or encoded differently, counted for the summary
+#[derive(Default)]
+struct Counts {
+    up_to_date: usize,
+    existing: usize,
+    smart_copies: usize,
+    unmatched: usize,
+    trusted: usize,
+    sources_changed: usize,
+    elsewhere: usize,
+    resumed: usize,
+    // The presets `--preset auto` picked, by how many files got each
+    auto_choices: BTreeMap<&'static str, usize>,
+}
+
+impl Counts {
+    // The summary's lines for the counts that are not zero
+    fn lines(&self, args: &BatchArgs) -> Vec<String> {
+        let mut lines = Vec::new();
+        if self.up_to_date > 0 {
+            lines.push(format!("{} up to date (same settings)", self.up_to_date));
+        }
+        if self.existing > 0 {
+            lines.push(format!(
+                "{} skipped as their outputs exist (--skip-existing)",
+                self.existing
+            ));
+        }
+        if self.smart_copies > 0 {
+            lines.push(format!(
+                "{} copied streams already in the target codec (--smart)",
+                self.smart_copies
+            ));
+        }
+        if self.unmatched > 0 {
+            let done = match args.otherwise {
+                Otherwise::Skip => "skipped",
+                Otherwise::Copy => "remuxed",
+            };
+            lines.push(format!(
+                "{} {} as they do not match --only-if",
+                self.unmatched, done
+            ));
+        }
+        if self.trusted > 0 {
+            lines.push(format!(
+                "{} skipped as already transcoded with the same settings (--trust-history)",
+                self.trusted
+            ));
+        }
+        if self.sources_changed > 0 {
+            lines.push(match args.on_source_change {
+                SourceChange::Requeue => {
+                    format!("{} redone as their sources changed", self.sources_changed)
+                }
+                SourceChange::Notify => format!(
+                    "{} kept although their sources changed (--on-source-change notify)",
+                    self.sources_changed
+                ),
+            });
+        }
+        if self.elsewhere > 0 {
+            lines.push(format!(
+                "{} skipped (claimed or done by other hosts)",
+                self.elsewhere
+            ));
+        }
+        if self.resumed > 0 {
+            lines.push(format!(
+                "{} skipped as finished before the interruption (--resume)",
+                self.resumed
+            ));
+        }
+        lines
+    }
+}
+
+// What the summary reports besides the tally and counts
+struct Extras<'a> {
+    stager: Option<&'a Stager>,
+    gpus: Option<&'a GpuPool>,
+    issues: &'a [PathIssue],
+}
+
+// The end of a batch: the totals, the counts, the final checkpoint and the report
+fn print_summary(
+    batch: &Finish,
+    plan: &Plan,
+    tally: Tally,
+    counts: &Counts,
+    extras: Extras,
+) -> Result<()> {
+    let Finish { args, runtime, .. } = *batch;
+    let dry_run = args.dry_run;
+    let Tally {
+        succeeded,
+        failed,
+        rejected,
+        bytes_in,
+        bytes_out,
+        spot_checked,
+        spot_flagged,
+        show_stats,
+        report,
+        checkpoints,
+        state,
+        ..
+    } = tally;
+
+    if !dry_run {
+        runtime.events.emit(Event::BatchDone { succeeded, failed });
+    }
+
+    println!("\n{}", i18n::t("batch-completed", &[]));
+    let units = runtime.units;
+    let seconds = batch.started.elapsed().as_secs_f64();
+    if units.porcelain() {
+        println!(
+            "{}",
+            porcelain_line(
+                "batch",
+                &[
+                    ("dry_run", dry_run.to_string()),
+                    ("succeeded", succeeded.to_string()),
+                    ("failed", failed.to_string()),
+                    ("rejected", rejected.to_string()),
+                    ("up_to_date", counts.up_to_date.to_string()),
+                    ("elsewhere", counts.elsewhere.to_string()),
+                    ("collisions", plan.collisions.to_string()),
+                    ("prior_outputs", plan.prior_outputs.to_string()),
+                    ("input_bytes", units.bytes(bytes_in)),
+                    ("output_bytes", units.bytes(bytes_out)),
+                    ("seconds", units.duration(seconds)),
+                ],
+            )
+        );
+    } else if !dry_run {
+        let counts = [
+            ("succeeded", succeeded.to_string()),
+            ("failed", failed.to_string()),
+        ];
+        println!("  {}", i18n::t("batch-counts", &counts));
+        if succeeded > 0 {
+            let sizes = [
+                ("before", units.bytes(bytes_in)),
+                ("after", units.bytes(bytes_out)),
+                ("change", units.change(bytes_in, bytes_out)),
+                ("time", units.duration(seconds)),
+            ];
+            println!("  {}", i18n::t("batch-sizes", &sizes));
+        }
+        if rejected > 0 {
+            let rejected = [("rejected", rejected.to_string())];
+            println!("  {}", i18n::t("batch-rejected", &rejected));
+        }
+    }
+    if plan.collisions > 0 {
+        println!(
+            "  {} skipped due to output name collisions",
+            plan.collisions
+        );
+    }
+    if plan.prior_outputs > 0 {
+        println!(
+            "  {} skipped as outputs of other inputs",
+            plan.prior_outputs
+        );
+    }
+    if let Some(spot_check) = batch.spot_check.filter(|_| spot_checked > 0) {
+        let mut line = format!(
+            "  Spot-checked {} outputs in {}",
+            spot_checked,
+            spot_check.dir().display()
+        );
+        if args.spot_check_ssim.is_some() {
+            line.push_str(&format!(
+                " ({} frames below the SSIM threshold)",
+                spot_flagged
+            ));
+        }
+        println!("{}", line);
+    }
+    for line in counts.lines(args) {
+        println!("  {}", line);
+    }
+    if let Some((inputs, bytes)) = extras.stager.map(Stager::reused).filter(|r| r.0 > 0) {
+        println!(
+            "  {} inputs read from the stage cache ({} not transferred)",
+            inputs,
+            units.bytes(bytes)
+        );
+    }
+    if let Some(used) = extras.gpus.and_then(GpuPool::summary) {
+        println!("  Jobs per GPU: {}", used);
+    }
+    if !counts.auto_choices.is_empty() {
+        let choices: Vec<String> = counts
+            .auto_choices
+            .iter()
+            .map(|(preset, count)| format!("{} {}", preset, count))
+            .collect();
+        println!("  Auto presets: {}", choices.join(", "));
+    }
+    print_show_summary(&show_stats, dry_run, units);
+    if let Some(checkpoints) = &checkpoints {
+        let totals = Totals {
+            input_bytes: bytes_in,
+            output_bytes: bytes_out,
+            seconds: batch.started.elapsed().as_secs_f64(),
+        };
+        match checkpoints.write(plan.jobs.len(), &totals, true) {
+            Ok(()) => println!("  Final checkpoint in {}", checkpoints.path().display()),
+            Err(e) => eprintln!("  {} {:#}", Marker::Warning, e),
+        }
+    }
+    if let Some(state) = state.as_ref().filter(|_| failed > 0) {
+        println!(
+            "  State in {}; --resume runs only the failed files again",
+            state.path().display()
+        );
+    }
+    print_issue_summary(extras.issues);
+    if let Some(report) = &report {
+        report.write()?;
+        println!("\nReport written to {}", report.path().display());
+    }
+    Ok(())
+}
+
+// What finishing a job needs from the batch besides its tally
+#[derive(Clone, Copy)]
+struct Finish<'a> {
+    args: &'a BatchArgs,
+    runtime: &'a Runtime,
+    ownership: &'a OutputOwnership,
+    ledger: Option<&'a Ledger>,
+    spot_check: Option<&'a SpotCheck>,
+    total: usize,
+    started: Instant,
+    // Encodes run side by side (--jobs), so lines of other files come in between
+    parallel: bool,
+}
+
+// A job whose encode has started. Its cover, stabilizer and GPU lease are held until it
+// is finished, as dropping them removes their files or frees the device.
+struct Running<'a> {
+    idx: usize,
+    job: &'a PlannedFile<'a>,
+    file_preset: Option<&'a str>,
+    settings: String,
+    adjusted: Option<String>,
+    claim: Option<Claim>,
+    started: Instant,
+    stream_copy: bool,
+    cover: Option<Cover>,
+    stabilizer: Option<Stabilizer>,
+    _gpu: Option<Lease<'a>>,
+}
+
+// Counts and outputs of the batch so far
+#[derive(Default)]
+struct Tally {
+    succeeded: usize,
+    failed: usize,
+    rejected: usize,
+    // Sizes of the successful encodes, for the summary
+    bytes_in: u64,
+    bytes_out: u64,
+    spot_checked: usize,
+    spot_flagged: usize,
+    last_milestone: usize,
+    show_stats: BTreeMap<String, ShowStats>,
+    report: Option<Report>,
+    checkpoints: Option<Checkpoints>,
+    state: Option<State>,
+}
+
+impl Tally {
+    // Record `job` as skipped for `reason`
+    fn skip(
+        &mut self,
+        batch: &Finish,
+        job: &PlannedFile,
+        preset: Option<&str>,
+        reason: &'static str,
+    ) {
+        let status = report::Status::Skipped(reason);
+        let entry = report_entry(batch.args, job, preset, status, 0, 0.0);
+        self.add(batch.runtime, batch.args, entry);
+    }
+
+    // Finish the jobs that end until `workers` has a free slot
+    fn wait_for_slot(&mut self, batch: &Finish, workers: Option<&mut Workers<Running>>) {
+        let Some(workers) = workers else {
+            return;
+        };
+        while workers.is_full() {
+            let Some((running, result)) = workers.next_finished() else {
+                break;
+            };
+            self.finish(batch, running, result);
+        }
+    }
+
+    // Finish every job still running
+    fn drain(&mut self, batch: &Finish, workers: Option<&mut Workers<Running>>) {
+        let Some(workers) = workers else {
+            return;
+        };
+        while let Some((running, result)) = workers.next_finished() {
+            self.finish(batch, running, result);
+        }
+    }
+
+    // Add a file's outcome to the report and the --json results
+    fn add(&mut self, runtime: &Runtime, args: &BatchArgs, entry: report::Entry) {
+        if let Some(results) = &runtime.results {
+            results.add(FileResult::of(&entry, &args.output_dir));
+        }
+        if let Some(report) = &mut self.report {
+            report.add(entry);
+        }
+    }
+
+    // Record how `running` ended: history, ledger, report, events and the counts
+    fn finish(&mut self, batch: &Finish, running: Running, result: Result<()>) {
+        let Finish { args, runtime, .. } = *batch;
+        let events = &runtime.events;
+        let Running {
+            idx,
+            job,
+            file_preset,
+            started,
+            ..
+        } = running;
+        let (input_file, output_file) = (job.input, &job.output);
+        let mut log = Vec::new();
+        let mut command = None;
+        let mut below_minimum = false;
+        // Plugin analyzers judge the finished output; a rejection fails the job
+        let result = result
+            .and_then(|()| runtime.plugins.analyze(input_file, output_file))
+            .map_err(|e| {
+                if let Some(failed) = e.downcast_ref::<FfmpegFailed>() {
+                    log = failed.log.clone();
+                    command = Some(failed.command.clone());
+                }
+                below_minimum = e.downcast_ref::<BelowMinimum>().is_some();
+                e.to_string()
+            });
+
+        let output_bytes = match &result {
+            Ok(()) => fs::metadata(output_file).map(|m| m.len()).unwrap_or(0),
+            Err(_) => 0,
+        };
+        let record = Record::new(input_file, output_file, &running.settings, file_preset)
+            .adjusted(running.adjusted)
+            .finished(
+                result.is_ok(),
+                started.elapsed().as_secs_f64(),
+                output_bytes,
+            );
+        runtime.history.append(&match below_minimum {
+            true => record.rejected(),
+            false => record,
+        });
+        if let (Some(ledger), Some(claim)) = (batch.ledger, running.claim) {
+            if let Err(e) = ledger.finish(claim, result.is_ok()) {
+                eprintln!("  {} {:#}", Marker::Warning, e);
+            }
+        }
+        if let Some(state) = &mut self.state {
+            let key = output_key(output_file, &args.output_dir);
+            if let Err(e) = state.finished(&key, result.is_ok()) {
+                eprintln!("  {} {:#}", Marker::Warning, e);
+            }
+        }
+        runtime
+            .diagnostics
+            .job(result.as_ref().err().map(|message| Failure {
+                input: input_file,
+                error: message,
+                command: command.as_deref(),
+                log: &log,
+            }));
+        if batch.parallel {
+            let outcome = match &result {
+                Ok(()) => "done",
+                Err(_) => "failed",
+            };
+            println!(
+                "\n[{}/{}] {} {}",
+                idx + 1,
+                batch.total,
+                input_file.display(),
+                outcome
+            );
+            // ffmpeg's output was not shown as it ran
+            if result.is_err() {
+                for line in &log[log.len().saturating_sub(FAILED_LOG_LINES)..] {
+                    eprintln!("    {}", line);
+                }
+            }
+        }
+        let status = match &result {
+            Ok(()) => report::Status::Done,
+            Err(message) => report::Status::Failed {
+                error: message.clone(),
+                log,
+            },
+        };
+        self.add(
+            runtime,
+            args,
+            report_entry(
+                args,
+                job,
+                file_preset,
+                status,
+                output_bytes,
+                started.elapsed().as_secs_f64(),
+            ),
+        );
+
+        let stats = job
+            .show
+            .as_ref()
+            .and_then(|key| self.show_stats.get_mut(&key.show));
+        let succeeded_now = result.is_ok();
+        match result {
+            Err(message) => {
+                eprintln!("  {} {}", Marker::Error, message);
+                eprintln!("  Skipping and continuing with next file...");
+                self.failed += 1;
+                self.rejected += usize::from(below_minimum);
+                if let Some(stats) = stats {
+                    stats.failed += 1;
+                }
+                events.emit(Event::Fail {
+                    input: path_str(input_file),
+                    output: path_str(output_file),
+                    error: message,
+                });
+            }
+            Ok(()) => {
+                batch.ownership.apply(output_file);
+                self.succeeded += 1;
+                self.bytes_in += fs::metadata(input_file).map(|m| m.len()).unwrap_or(0);
+                self.bytes_out += output_bytes;
+                if let Some(stats) = stats {
+                    stats.succeeded += 1;
+                    stats.output_bytes += output_bytes;
+                }
+                events.emit(Event::Done {
+                    input: path_str(input_file),
+                    output: path_str(output_file),
+                    seconds: started.elapsed().as_secs_f64(),
+                    output_bytes,
+                });
+                // A stream copy has the input's own frames
+                if let Some(spot_check) = batch.spot_check.filter(|_| !running.stream_copy) {
+                    let name = relative_to(output_file, &args.output_dir)
+                        .unwrap_or_else(|| output_file.clone());
+                    match spot_check.check(&runtime.cache, input_file, output_file, &name) {
+                        Ok(flagged) => {
+                            self.spot_checked += 1;
+                            self.spot_flagged += flagged;
+                        }
+                        Err(e) => eprintln!("  {} spot check failed: {:#}", Marker::Warning, e),
+                    }
+                }
+            }
+        }
+
+        if let Some(checkpoints) = &mut self.checkpoints {
+            if checkpoints.record(input_file, succeeded_now) {
+                let totals = Totals {
+                    input_bytes: self.bytes_in,
+                    output_bytes: self.bytes_out,
+                    seconds: batch.started.elapsed().as_secs_f64(),
+                };
+                write_checkpoint(
+                    checkpoints,
+                    self.report.as_ref(),
+                    (self.succeeded, self.failed, batch.total),
+                    &totals,
+                    runtime.units,
+                );
+            }
+        }
+
+        let done = self.succeeded + self.failed;
+        if let Some(percent) = events::milestone(done, batch.total, self.last_milestone) {
+            self.last_milestone = percent;
+            events.emit(Event::Progress {
+                done,
+                total: batch.total,
+                percent,
+            });
+        }
+    }
+}
+
+// A --checkpoint-every chunk is done: print the summary so far, then save the report and
+// the state snapshot. Failures only warn, as the run itself is fine.
+fn write_checkpoint(
+    checkpoints: &Checkpoints,
+    report: Option<&Report>,
+    (succeeded, failed, total): (usize, usize, usize),
+    totals: &Totals,
+    units: Units,
+) {
+    let processed = succeeded + failed;
+    if units.porcelain() {
+        let fields = [
+            ("processed", processed.to_string()),
+            ("total", total.to_string()),
+            ("succeeded", succeeded.to_string()),
+            ("failed", failed.to_string()),
+            ("input_bytes", units.bytes(totals.input_bytes)),
+            ("output_bytes", units.bytes(totals.output_bytes)),
+            ("seconds", units.duration(totals.seconds)),
+        ];
+        println!("{}", porcelain_line("checkpoint", &fields));
+    } else {
+        let fields = [
+            ("processed", processed.to_string()),
+            ("total", total.to_string()),
+            ("succeeded", succeeded.to_string()),
+            ("failed", failed.to_string()),
+            ("before", units.bytes(totals.input_bytes)),
+            ("after", units.bytes(totals.output_bytes)),
+            (
+                "change",
+                units.change(totals.input_bytes, totals.output_bytes),
+            ),
+            ("time", units.duration(totals.seconds)),
+        ];
+        println!("\n{}", i18n::t("checkpoint-summary", &fields));
+    }
+    if let Some(report) = report {
+        if let Err(e) = report.write_partial(processed, total) {
+            eprintln!("  {} {:#}", Marker::Warning, e);
+        }
+    }
+    if let Err(e) = checkpoints.write(total, totals, false) {
+        eprintln!("  {} {:#}", Marker::Warning, e);
+    }
+}
+
+// The --min-vmaf gate for a job; stream copies are not re-encoded, so there is nothing
+// to score
+fn quality_gate(args: &BatchArgs, vcodec: &str) -> Option<QualityGate> {
+    match vcodec {
+        "copy" => None,
+        _ => args
+            .min_vmaf
+            .map(|min| QualityGate::new(min, args.vmaf_retries)),
+    }
+}
+
+fn silence_trim(args: &BatchArgs) -> Option<SilenceTrim> {
+    args.trim_silence
+        .map(|ends| SilenceTrim::new(ends, args.silence_threshold, args.silence_min))
+}
+
+fn report_entry(
+    args: &BatchArgs,
+    job: &PlannedFile,
+    preset: Option<&str>,
+    status: report::Status,
+    output_bytes: u64,
+    seconds: f64,
+) -> report::Entry {
+    report::Entry {
+        name: relative_to(&job.output, &args.output_dir)
+            .unwrap_or_else(|| job.output.clone())
+            .display()
+            .to_string(),
+        input: job.input.to_path_buf(),
+        preset: preset.map(str::to_string),
+        status,
+        input_bytes: fs::metadata(job.input).map(|m| m.len()).unwrap_or(0),
+        output_bytes,
+        seconds,
+    }
+}
+
+#[derive(Default)]
+struct ShowStats {
+    files: usize,
+    seasons: BTreeMap<u32, usize>,
+    succeeded: usize,
+    failed: usize,
+    input_bytes: u64,
+    output_bytes: u64,
+}
+
+fn print_show_summary(show_stats: &BTreeMap<String, ShowStats>, dry_run: bool, units: Units) {
+    if show_stats.is_empty() {
+        return;
+    }
+    if units.porcelain() {
+        for (show, stats) in show_stats {
+            let fields = [
+                ("name", show.clone()),
+                ("files", stats.files.to_string()),
+                ("seasons", stats.seasons.len().to_string()),
+                ("succeeded", stats.succeeded.to_string()),
+                ("failed", stats.failed.to_string()),
+                ("input_bytes", units.bytes(stats.input_bytes)),
+                ("output_bytes", units.bytes(stats.output_bytes)),
+            ];
+            println!("{}", porcelain_line("show", &fields));
+        }
+        return;
+    }
+    println!("\n{}", i18n::t("show-summary", &[]));
+    let columns: &[&str] = match dry_run {
+        true => &[
+            "column-show",
+            "column-files",
+            "column-input",
+            "column-seasons",
+        ],
+        false => &[
+            "column-show",
+            "column-files",
+            "column-succeeded",
+            "column-failed",
+            "column-input",
+            "column-output",
+            "column-seasons",
+        ],
+    };
+    let headers: Vec<String> = columns.iter().map(|id| i18n::t(id, &[])).collect();
+    let headers: Vec<&str> = headers.iter().map(String::as_str).collect();
+    let numbers: Vec<usize> = (1..columns.len() - 1).collect();
+    let mut table = Table::new(&headers).right(&numbers);
+    for (show, stats) in show_stats {
+        let seasons: Vec<String> = stats
+            .seasons
+            .iter()
+            .map(|(season, count)| format!("S{:02}: {}", season, count))
+            .collect();
+        let mut row = vec![show.clone(), stats.files.to_string()];
+        if !dry_run {
+            row.push(stats.succeeded.to_string());
+            row.push(stats.failed.to_string());
+        }
+        row.push(units.bytes(stats.input_bytes));
+        if !dry_run {
+            row.push(units.bytes(stats.output_bytes));
+        }
+        row.push(seasons.join(", "));
+        table.row(row);
+    }
+    print!("{}", table);
+}
+
+// A filesystem problem tied to one path. Batch runs record these and keep going
+// instead of aborting, then summarize them at the end.
+pub struct PathIssue {
+    path: PathBuf,
+    action: &'static str,
+    error: io::Error,
+}
+
+impl PathIssue {
+    fn new(path: &Path, action: &'static str, error: io::Error) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            action,
+            error,
+        }
+    }
+
+    fn is_permission_denied(&self) -> bool {
+        self.error.kind() == io::ErrorKind::PermissionDenied
+    }
+}
+
+impl std::fmt::Display for PathIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "failed to {} {}: {}",
+            self.action,
+            self.path.display(),
+            self.error
+        )
+    }
+}
+
+pub fn print_issue_summary(issues: &[PathIssue]) {
+    let (denied, other): (Vec<&PathIssue>, Vec<&PathIssue>) =
+        issues.iter().partition(|i| i.is_permission_denied());
+
+    if !denied.is_empty() {
+        let count = [("count", denied.len().to_string())];
+        println!("\n{}", i18n::t("issues-denied", &count));
+        for issue in denied {
+            println!("  {} ({})", issue.path.display(), issue.action);
+        }
+    }
+    if !other.is_empty() {
+        let count = [("count", other.len().to_string())];
+        println!("\n{}", i18n::t("issues-other", &count));
+        for issue in other {
+            println!("  {}", issue);
+        }
+    }
+}
+
+// Verify the input is readable and the output location is writable before spawning ffmpeg.
+// ffmpeg reports these failures too, but only as an exit code buried in its own log.
+fn preflight_paths(input: &Path, output: &Path) -> std::result::Result<(), PathIssue> {
+    fs::File::open(input).map_err(|e| PathIssue::new(input, "read input", e))?;
+
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| PathIssue::new(parent, "create output directory", e))?;
+    }
+
+    // Probe writability without clobbering an existing output; remove the probe if we made it
+    let existed = output.exists();
+    fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(output)
+        .map_err(|e| PathIssue::new(output, "write output", e))?;
+    if !existed {
+        let _ = fs::remove_file(output);
+    }
+    Ok(())
+}
+
+// Recursively collect files whose extension matches. Only a failure to read the root
+// directory is an error; unreadable entries and subdirectories are recorded in `issues`.
+fn collect_media_files(
+    dir: &Path,
+    extensions: &[&str],
+    issues: &mut Vec<PathIssue>,
+) -> Result<Vec<PathBuf>> {
+    let entries = fs::read_dir(dir)
+        .with_context(|| format!("failed to read input directory: {}", dir.display()))?;
+
+    let mut files = Vec::new();
+    scan_entries(dir, entries, extensions, &mut files, issues);
+    Ok(files)
+}
+
+fn scan_entries(
+    dir: &Path,
+    entries: fs::ReadDir,
+    extensions: &[&str],
+    files: &mut Vec<PathBuf>,
+    issues: &mut Vec<PathIssue>,
+) {
+    for entry in entries {
+        let path = match entry {
+            Ok(entry) => entry.path(),
+            Err(e) => {
+                issues.push(PathIssue::new(dir, "read directory entry in", e));
+                continue;
+            }
+        };
+
+        if path.is_dir() {
+            // Recurse into subdirectories
+            match fs::read_dir(&path) {
+                Ok(sub_entries) => scan_entries(&path, sub_entries, extensions, files, issues),
+                Err(e) => issues.push(PathIssue::new(&path, "read directory", e)),
+            }
+        } else if path.is_file() {
+            if let Some(file_ext) = path.extension() {
+                let file_ext_str = file_ext.to_string_lossy().to_lowercase();
+                if extensions.iter().any(|e| e.to_lowercase() == file_ext_str) {
+                    files.push(path);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Line {
+        #[command(flatten)]
+        args: BatchArgs,
+    }
+
+    fn batch_args(dirs: (&Path, &Path), flags: &[&str]) -> BatchArgs {
+        let line = [OsString::from("batch"), dirs.0.into(), dirs.1.into()];
+        let line = line.into_iter().chain(flags.iter().map(Into::into));
+        let mut args = Line::try_parse_from(line).unwrap().args;
+        args.input_dir = dirs.0.to_path_buf();
+        args.output_dir = dirs.1.to_path_buf();
+        args
+    }
+
+    #[test]
+    fn inputs_are_staged_through_the_cache_else_the_stage_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let (stage, cache) = (dir.path().join("stage"), dir.path().join("cache"));
+        let dirs = (Path::new("/in"), Path::new("/out"));
+        let stage_flags = ["--stage-inputs", "--stage-dir", stage.to_str().unwrap()];
+
+        assert!(
+            open_stager(&batch_args(dirs, &[]), false)
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            open_stager(&batch_args(dirs, &stage_flags), true)
+                .unwrap()
+                .is_none()
+        );
+        assert_eq!(scratch_dir(&batch_args(dirs, &[])), std::env::temp_dir());
+
+        let args = batch_args(dirs, &stage_flags);
+        assert_eq!(scratch_dir(&args), stage);
+        let stager = open_stager(&args, false).unwrap().unwrap();
+        assert!(stager.dir().starts_with(&stage) && stager.dir().is_dir());
+
+        let cached = ["--stage-inputs", "--stage-cache", cache.to_str().unwrap()];
+        let args = batch_args(dirs, &cached);
+        assert_eq!(scratch_dir(&args), cache);
+        assert!(
+            open_stager(&args, false)
+                .unwrap()
+                .unwrap()
+                .dir()
+                .starts_with(&cache)
+        );
+    }
+
+    #[test]
+    fn encodes_run_side_by_side_only_for_real_runs_without_live_output() {
+        let workers = |jobs, dry_run, live| open_workers::<()>(jobs, dry_run, live);
+        assert!(workers(None, false, true).unwrap().is_none());
+        assert!(workers(Some(1), false, true).unwrap().is_none());
+        assert!(workers(Some(4), true, true).unwrap().is_none());
+        assert!(workers(Some(4), false, true).is_err());
+
+        let mut pool = workers(Some(2), false, false).unwrap().unwrap();
+        pool.spawn((), || Ok(()));
+        assert!(!pool.is_full());
+    }
+
+    #[test]
+    fn outputs_other_hosts_claimed_or_finished_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(matches!(
+            claim_output(None, "show/e1.mkv").unwrap(),
+            Claimed::Mine(None)
+        ));
+
+        let ledger = Ledger::open(dir.path()).unwrap();
+        let Claimed::Mine(Some(claim)) = claim_output(Some(&ledger), "show/e1.mkv").unwrap() else {
+            panic!("the first host should claim the output");
+        };
+        assert!(matches!(
+            claim_output(Some(&ledger), "show/e1.mkv").unwrap(),
+            Claimed::Elsewhere {
+                reason: "claimed by another host",
+                ..
+            }
+        ));
+        ledger.finish(claim, true).unwrap();
+        match claim_output(Some(&ledger), "show/e1.mkv").unwrap() {
+            Claimed::Elsewhere { message, reason } => {
+                assert!(message.starts_with("already transcoded by "));
+                assert_eq!(reason, "done by another host");
+            }
+            Claimed::Mine(_) => panic!("a finished output should not be claimed again"),
+        }
+    }
+
+    #[test]
+    fn side_by_side_jobs_are_finished_as_slots_free_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let (input, output) = (dir.path().join("a.mkv"), dir.path().join("out/a.mkv"));
+        fs::write(&input, b"input").unwrap();
+        fs::create_dir_all(output.parent().unwrap()).unwrap();
+        fs::write(&output, b"out").unwrap();
+        let args = batch_args((dir.path(), &dir.path().join("out")), &["--jobs", "1"]);
+        let runtime = Runtime::embedded().unwrap();
+        let ownership = OutputOwnership::default();
+        let job = PlannedFile {
+            input: &input,
+            output,
+            show: None,
+            track: None,
+        };
+        let finish = Finish {
+            args: &args,
+            runtime: &runtime,
+            ownership: &ownership,
+            ledger: None,
+            spot_check: None,
+            total: 2,
+            started: Instant::now(),
+            parallel: true,
+        };
+        let running = |idx| Running {
+            idx,
+            job: &job,
+            file_preset: None,
+            settings: String::new(),
+            adjusted: None,
+            claim: None,
+            started: Instant::now(),
+            stream_copy: false,
+            cover: None,
+            stabilizer: None,
+            _gpu: None,
+        };
+
+        let mut tally = Tally::default();
+        let mut workers = Workers::new(1);
+        tally.wait_for_slot(&finish, None);
+        workers.spawn(running(0), || Ok(()));
+        tally.wait_for_slot(&finish, Some(&mut workers));
+        assert_eq!((tally.succeeded, tally.failed), (1, 0));
+        assert!(!workers.is_full());
+
+        workers.spawn(running(1), || bail!("ffmpeg failed"));
+        tally.drain(&finish, Some(&mut workers));
+        assert_eq!((tally.succeeded, tally.failed), (1, 1));
+        assert_eq!(tally.bytes_out, 3);
+    }
+
+    #[test]
+    fn the_summary_counts_only_what_happened() {
+        let mut args = batch_args((Path::new("/in"), Path::new("/out")), &[]);
+        args.otherwise = Otherwise::Copy;
+        assert!(Counts::default().lines(&args).is_empty());
+        let counts = Counts {
+            up_to_date: 2,
+            unmatched: 1,
+            resumed: 3,
+            ..Counts::default()
+        };
+        assert_eq!(
+            counts.lines(&args),
+            [
+                "2 up to date (same settings)",
+                "1 remuxed as they do not match --only-if",
+                "3 skipped as finished before the interruption (--resume)",
+            ]
+        );
+    }
+}
@@ -0,0 +1,106 @@
+// file: src/cache.rs
+// version: 0.1.0
+// guid: 63fc20e9-d096-4390-8a3f-7138c42b9736
+
+//! Cache for expensive analysis results (probes, stabilization passes), keyed by a
+//! fingerprint of the input so a changed file never reuses a stale result.
+//!
+//! Entries live in `~/.cache/transcoderr/analysis/<kind>/<key>.<ext>`; `--no-cache`
+//! turns the cache off for a run.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use crate::presets::sha256_hex;
+
+#[derive(Debug, Default)]
+pub struct AnalysisCache {
+    // None when caching is off
+    dir: Option<PathBuf>,
+}
+
+impl AnalysisCache {
+    pub fn open(dir: Option<PathBuf>) -> Self {
+        Self {
+            dir: dir.map(|dir| dir.join("analysis")),
+        }
+    }
+
+    // Where the result of analysis `kind` with `params` over `input` is stored. None when
+    // caching is off or the input cannot be fingerprinted.
+    pub fn entry(&self, kind: &str, params: &str, input: &Path, ext: &str) -> Option<PathBuf> {
+        let dir = self.dir.as_ref()?.join(kind);
+        let key = sha256_hex(format!("{}\0{}\0{}", kind, params, fingerprint(input)?).as_bytes());
+        fs::create_dir_all(&dir).ok()?;
+        Some(dir.join(format!("{}.{}", key, ext)))
+    }
+
+    pub fn get<T: DeserializeOwned>(&self, kind: &str, params: &str, input: &Path) -> Option<T> {
+        let text = fs::read(self.entry(kind, params, input, "json")?).ok()?;
+        serde_json::from_slice(&text).ok()
+    }
+
+    // Best effort: a cache that cannot be written only costs a repeated analysis
+    pub fn put<T: Serialize>(&self, kind: &str, params: &str, input: &Path, value: &T) {
+        let Some(path) = self.entry(kind, params, input, "json") else {
+            return;
+        };
+        let Ok(json) = serde_json::to_vec(value) else {
+            return;
+        };
+        // Write then rename so a concurrent reader never sees half an entry
+        let partial = path.with_extension(format!("{}.partial", std::process::id()));
+        if fs::write(&partial, json).is_err() || fs::rename(&partial, &path).is_err() {
+            let _ = fs::remove_file(&partial);
+        }
+    }
+}
+
+// Identity of an input as far as analysis results go: where it is, how big it is and
+// when it last changed
+fn fingerprint(input: &Path) -> Option<String> {
+    let path = fs::canonicalize(input).ok()?;
+    let meta = fs::metadata(&path).ok()?;
+    let modified = meta
+        .modified()
+        .ok()?
+        .duration_since(UNIX_EPOCH)
+        .ok()?
+        .as_nanos();
+    Some(format!(
+        "{}\0{}\0{}",
+        path.to_string_lossy(),
+        meta.len(),
+        modified
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_follow_the_input() {
+        let temp = tempfile::tempdir().unwrap();
+        let cache = AnalysisCache::open(Some(temp.path().join("cache")));
+        let input = temp.path().join("in.mkv");
+        fs::write(&input, b"one").unwrap();
+
+        assert_eq!(cache.get::<u32>("crop", "", &input), None);
+        cache.put("crop", "", &input, &42u32);
+        assert_eq!(cache.get::<u32>("crop", "", &input), Some(42));
+        assert_eq!(cache.get::<u32>("crop", "limit=24", &input), None);
+
+        // A rewritten input is a different file as far as the cache is concerned
+        fs::write(&input, b"longer").unwrap();
+        assert_eq!(cache.get::<u32>("crop", "", &input), None);
+
+        let off = AnalysisCache::default();
+        off.put("crop", "", &input, &7u32);
+        assert_eq!(off.get::<u32>("crop", "", &input), None);
+    }
+}
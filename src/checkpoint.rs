@@ -0,0 +1,180 @@
+// file: src/checkpoint.rs
+// version: 0.3.0
+// guid: 33c78547-98aa-4fac-ac92-19481b7510a3
+
+//! Batch checkpoints (`batch --checkpoint-every N`): every N finished files a multi-day
+//! run prints a partial summary, rewrites its `--report-html` page and snapshots its
+//! state to `OUTPUT_DIR/.transcoderr-checkpoint.json`.
+//!
+//! The snapshot lists the inputs finished so far with totals, and is replaced with a
+//! synced write-then-rename, so a power failure leaves the previous snapshot intact and
+//! costs at most the files of one chunk.
+
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::report::utc_timestamp;
+use crate::schema;
+
+pub const FILE_NAME: &str = ".transcoderr-checkpoint.json";
+
+pub struct Checkpoints {
+    every: usize,
+    path: PathBuf,
+    settings: String,
+    succeeded: Vec<String>,
+    failed: Vec<String>,
+}
+
+// Sizes and time of the run so far
+pub struct Totals {
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+    pub seconds: f64,
+}
+
+#[derive(Serialize)]
+struct Snapshot<'a> {
+    schema_version: u32,
+    updated_utc: String,
+    settings: &'a str,
+    complete: bool,
+    total: usize,
+    processed: usize,
+    input_bytes: u64,
+    output_bytes: u64,
+    seconds: f64,
+    succeeded: &'a [String],
+    failed: &'a [String],
+}
+
+impl Checkpoints {
+    pub fn new(every: usize, output_dir: &Path, settings: &str) -> Self {
+        Self {
+            every,
+            path: output_dir.join(FILE_NAME),
+            settings: settings.to_string(),
+            succeeded: Vec::new(),
+            failed: Vec::new(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn processed(&self) -> usize {
+        self.succeeded.len() + self.failed.len()
+    }
+
+    // Note a finished file; true when it closes a chunk
+    pub fn record(&mut self, input: &Path, succeeded: bool) -> bool {
+        let input = input.to_string_lossy().into_owned();
+        match succeeded {
+            true => self.succeeded.push(input),
+            false => self.failed.push(input),
+        }
+        self.processed().is_multiple_of(self.every)
+    }
+
+    pub fn write(&self, total: usize, totals: &Totals, complete: bool) -> Result<()> {
+        let snapshot = Snapshot {
+            schema_version: schema::CHECKPOINT,
+            updated_utc: utc_timestamp(now_ms()),
+            settings: &self.settings,
+            complete,
+            total,
+            processed: self.processed(),
+            input_bytes: totals.input_bytes,
+            output_bytes: totals.output_bytes,
+            seconds: totals.seconds,
+            succeeded: &self.succeeded,
+            failed: &self.failed,
+        };
+        let json = serde_json::to_vec_pretty(&snapshot)?;
+        replace_file(&self.path, &json)
+            .with_context(|| format!("failed to write checkpoint {}", self.path.display()))
+    }
+}
+
+// Replace `path` with `bytes` through a synced write-then-rename, so a crash leaves
+// either the old contents or the new
+pub fn replace_file(path: &Path, bytes: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create {}", parent.display()))?;
+    }
+    let partial = path.with_extension(format!("{}.partial", std::process::id()));
+    let written = File::create(&partial)
+        .and_then(|mut file| {
+            file.write_all(bytes)?;
+            file.sync_all()
+        })
+        .and_then(|()| fs::rename(&partial, path));
+    if let Err(e) = written {
+        let _ = fs::remove_file(&partial);
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+fn now_ms() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_close_every_n_files_and_snapshots_replace_each_other() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut checkpoints = Checkpoints::new(2, dir.path(), "vcodec=libx265");
+        let totals = Totals {
+            input_bytes: 300,
+            output_bytes: 100,
+            seconds: 12.5,
+        };
+        assert!(!checkpoints.record(Path::new("/in/a.mkv"), true));
+        assert!(checkpoints.record(Path::new("/in/b.mkv"), false));
+        checkpoints.write(5, &totals, false).unwrap();
+        assert!(!checkpoints.record(Path::new("/in/c.mkv"), true));
+        checkpoints.write(5, &totals, true).unwrap();
+
+        let snapshot: serde_json::Value =
+            serde_json::from_slice(&fs::read(dir.path().join(FILE_NAME)).unwrap()).unwrap();
+        assert_eq!(snapshot["processed"], 3);
+        assert_eq!(snapshot["complete"], true);
+        assert_eq!(
+            snapshot["succeeded"],
+            serde_json::json!(["/in/a.mkv", "/in/c.mkv"])
+        );
+        assert_eq!(snapshot["failed"], serde_json::json!(["/in/b.mkv"]));
+        schema::assert_fields(
+            &snapshot,
+            1,
+            &[
+                "updated_utc",
+                "settings",
+                "complete",
+                "total",
+                "processed",
+                "input_bytes",
+                "output_bytes",
+                "seconds",
+                "succeeded",
+                "failed",
+            ],
+        );
+        // Only the snapshot is left behind
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
@@ -0,0 +1,1296 @@
+// file: src/cli.rs
+// version: 0.1.0
+// guid: 0b0fa5ca-1a62-4ad2-8539-d8383e268a3e
+
+//! The `transcoderr` command line: arguments, the run's services, and each subcommand.
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result, bail};
+use clap::{Args, Parser, Subcommand};
+
+use crate::alignment::OddSize;
+use crate::audiobook::AudiobookArgs;
+use crate::batch::{BatchAction, BatchArgs, BatchCommand};
+use crate::cache::AnalysisCache;
+use crate::config::{Config, ConfigAction};
+use crate::diagnostics::Diagnostics;
+use crate::doctor::DoctorArgs;
+use crate::encode::{display_args, ffmpeg_args, ffmpeg_command, transcode};
+use crate::events::{Event, Events, path_str};
+use crate::explain::Explain;
+use crate::hdr::Hdr;
+use crate::history::{History, HistoryAction, Record};
+use crate::hwaccel::{Backend, HwAccel};
+use crate::init::InitArgs;
+use crate::keyint::Keyint;
+#[cfg(feature = "mediainfo")]
+use crate::mediainfo;
+use crate::migrate::MigrateArgs;
+use crate::ownership::OutputOwnership;
+use crate::paths::resolve_output_path;
+use crate::plugins::Plugins;
+use crate::presets::{Defaults, Presets, PresetsAction};
+use crate::preview::Preview;
+use crate::results::{FileResult, Results};
+use crate::schedule::Gate;
+use crate::selftest::SelftestArgs;
+use crate::serve::ServeArgs;
+use crate::silence::{SilenceTrim, TrimEnds};
+use crate::speed::Speed;
+use crate::stabilize::Stabilizer;
+use crate::stats::Stats;
+use crate::style::{ColorChoice, Marker, Role};
+use crate::subtiming::{Fps, SubShift, SubTiming};
+use crate::subtitles::SubtitlePolicy;
+use crate::thumbnail::{Cover, Thumbnail};
+use crate::tools::{Binaries, Tool};
+use crate::tune::TuneArgs;
+use crate::units::Units;
+use crate::watch::WatchArgs;
+use crate::whatif::WhatIf;
+use crate::{
+    Runtime, alignment, audiobook, batch, config, deterministic, doctor, explain, export, features,
+    history, hwaccel, i18n, init, legacy, migrate, presets, probe, recommend, results, schema,
+    selftest, serve, streams, style, synth, tools, tune, upgrades, vbv, watch,
+};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Transcode media while preserving metadata (ffmpeg wrapper)", long_about = None)]
+struct Cli {
+    /// Refuse anything that would encode or modify files (analysis and dry runs only)
+    #[arg(long, global = true, visible_alias = "offline")]
+    read_only: bool,
+    /// Config file (default: ~/.config/transcoderr/config.toml if present)
+    #[arg(long, global = true, value_name = "PATH")]
+    config: Option<PathBuf>,
+    /// Append lifecycle events as NDJSON to this file (overrides config `event-log`)
+    #[arg(long, global = true, value_name = "PATH")]
+    event_log: Option<PathBuf>,
+    /// Do not reuse or store analysis results (~/.cache/transcoderr)
+    #[arg(long, global = true)]
+    no_cache: bool,
+    /// Files to probe at once when scanning a directory (batch, recommend, upgrades);
+    /// default: one per CPU core
+    #[arg(long, global = true, value_name = "N")]
+    probe_workers: Option<usize>,
+    /// ffmpeg's progress: `full` (its own stats line), `line` (a progress bar on a
+    /// terminal), `plain` (a status line every 10%) or `none` (only its last stats
+    /// line, for logs and CI)
+    #[arg(long, global = true, value_enum, default_value_t = Stats::Full)]
+    stats: Stats,
+    /// Plain output for screen readers, dumb terminals and log collectors: periodic
+    /// status lines instead of progress bars and redrawn lines
+    #[arg(long, global = true)]
+    no_fancy: bool,
+    /// During each encode, every MINUTES log a snapshot of its progress (position,
+    /// bitrate, size so far, projected size, time left) as a line and an event
+    #[arg(long, global = true, value_name = "MINUTES")]
+    snapshot_every: Option<f64>,
+    /// Color status markers and table headings: `auto` (on terminals, unless NO_COLOR
+    /// is set), `always` or `never`
+    #[arg(long, global = true, value_enum, default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
+    /// On a crash or repeated batch failures, write a diagnostic bundle (zip) for bug
+    /// reports here: sanitized config, ffmpeg capabilities, failing command and log
+    #[arg(long, global = true, value_name = "DIR")]
+    diagnostics_dir: Option<PathBuf>,
+    /// Summaries for scripts: raw bytes and seconds, one tab-separated `key=value` line
+    /// per summary with fields in a fixed order
+    #[arg(long, global = true)]
+    porcelain: bool,
+    /// While encoding, also stream a low-bitrate preview to this address
+    /// (udp://HOST:PORT, rtp, tcp, srt, rtmp, or http(s) to push to a server)
+    #[arg(long, global = true, value_name = "URL")]
+    preview_stream: Option<Preview>,
+    /// When an encode fails on demuxing or timestamp errors, remux the input to MKV
+    /// with regenerated timestamps and encode from that once more
+    #[arg(long, global = true)]
+    auto_remux_fallback: bool,
+    /// Encode on the GPU: swap software encoders for the backend's (libx265 ->
+    /// hevc_nvenc), decode with -hwaccel and respell quality args; `auto` picks the
+    /// first backend that encodes a test frame, else stays in software
+    #[arg(long, global = true, value_enum, value_name = "BACKEND")]
+    hwaccel: Option<HwAccel>,
+    /// Simulate instead of changing anything: encodes become dry runs, and removals,
+    /// renames and preset installs are printed and written to an action log (LOG, else
+    /// a new file in ~/.local/share/transcoderr/what-if)
+    #[arg(
+        long,
+        global = true,
+        value_name = "LOG",
+        num_args = 0..=1,
+        require_equals = true
+    )]
+    what_if: Option<Option<PathBuf>>,
+    /// Language for summaries and errors: en, de, es or fr (default: config `lang`,
+    /// else the locale)
+    #[arg(long, global = true, value_name = "LANG")]
+    lang: Option<String>,
+    /// Machine-readable output: `info` prints ffprobe's JSON; `transcode` and `batch`
+    /// end with a JSON document of each file's outcome, time and sizes, on stdout or
+    /// written to PATH
+    #[arg(
+        long,
+        global = true,
+        value_name = "PATH",
+        num_args = 0..=1,
+        require_equals = true
+    )]
+    json: Option<Option<PathBuf>>,
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Show media info via ffprobe (optionally as JSON), with HDR metadata spelled out
+    Info {
+        /// Input media file
+        input: PathBuf,
+    },
+    /// Transcode a file while preserving metadata
+    Transcode(Box<TranscodeArgs>),
+    /// Show every decision transcode would make for a file, step by step: probe,
+    /// preset, stream plan, filters, the ffmpeg command and the expected output
+    Explain(Box<TranscodeArgs>),
+    /// Batch transcode a directory recursively (default: h265+aac)
+    Batch(Box<BatchCommand>),
+    /// Join each folder of audio files into one chapterized .m4b audiobook
+    Audiobook(AudiobookArgs),
+    /// Transcode new files in a directory once they stop growing, e.g. finished downloads
+    Watch(WatchArgs),
+    /// Run a small transcoding service: submit, follow and cancel jobs over HTTP
+    Serve(ServeArgs),
+    /// Suggest a preset for a file or every file in a directory, with the reasons
+    Recommend {
+        /// Media file or directory
+        path: PathBuf,
+        /// File extensions to consider in a directory (comma-separated; default: the
+        /// config's `input-exts`, else mp4,mkv,avi,mov,m4v,ts)
+        #[arg(long)]
+        input_exts: Option<String>,
+    },
+    /// List titles with several sources (e.g. a new 4K remux beside an old 1080p copy)
+    /// and the outputs that should be made again from the best one
+    Upgrades {
+        /// Library directory
+        dir: PathBuf,
+        /// File extensions to consider (comma-separated; default: the config's
+        /// `input-exts`, else mp4,mkv,avi,mov,m4v,ts)
+        #[arg(long)]
+        input_exts: Option<String>,
+    },
+    /// List presets, show what one expands to, or share them: export one as a TOML file
+    /// or import one from a file or URL
+    Presets {
+        #[command(subcommand)]
+        action: Option<PresetsAction>,
+    },
+    /// Inspect the configuration file
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Start each plugin from the config file and list what it provides
+    Plugins,
+    /// List optional features: compiled into this build or not, and whether what they
+    /// need is installed
+    Features,
+    /// Find `<name>_transcoded.*` files left by earlier in-place runs, verify them against
+    /// their originals and (with --swap) put them in the originals' place
+    MigrateSuffixed(MigrateArgs),
+    /// Work with the encode history (every encode batch and transcode ran)
+    History {
+        #[command(subcommand)]
+        action: HistoryAction,
+    },
+    /// Check the local ffmpeg setup: generate synthetic media, transcode and verify it
+    Selftest(SelftestArgs),
+    /// Check ffmpeg and ffprobe, the encoders and filters transcoderr uses, and which
+    /// presets work on this machine, without encoding; fails if requirements are missing
+    Doctor(DoctorArgs),
+    /// Write small synthetic media files covering codecs, containers and edge cases
+    /// (HDR flags, VFR, several audio tracks, subtitles) for tests and bug reports
+    GenerateFixtures {
+        /// Directory to write the fixtures into (created if missing)
+        #[arg(long, value_name = "DIR")]
+        out: PathBuf,
+    },
+    /// First-run setup: detect ffmpeg and hardware encoders, ask about your library and
+    /// write a commented config file with a recommended preset
+    Init(InitArgs),
+    /// Encode a short sample at several CRF/speed combinations, compare sizes (and
+    /// VMAF), and save the one you pick as a preset
+    TuneWizard(TuneArgs),
+}
+
+// `transcode`'s args, boxed in `Commands` like batch's to keep the enum small
+#[derive(Args, Debug)]
+struct TranscodeArgs {
+    /// Input media file
+    input: PathBuf,
+    /// Optional output media file; if omitted, will write next to input as `<name>_transcoded.mkv`
+    output: Option<PathBuf>,
+    /// Preset name (e.g., original-h265), or `auto` to pick one from the content
+    #[arg(long)]
+    preset: Option<String>,
+    /// Video codec (e.g., libx264, libx265, copy; default: the preset's, else the config's,
+    /// else libx264)
+    #[arg(long)]
+    vcodec: Option<String>,
+    /// Audio codec (e.g., aac, ac3, copy; default: the preset's, else the config's, else aac)
+    #[arg(long)]
+    acodec: Option<String>,
+    /// Extra ffmpeg args (passed as-is after standard args; default: the config's `extra`)
+    #[arg(long, num_args = 0.., value_delimiter = ' ')]
+    extra: Vec<String>,
+    /// One extra ffmpeg arg, kept whole even with spaces in it (what `serve` passes)
+    #[arg(long, value_name = "ARG", allow_hyphen_values = true, hide = true)]
+    extra_arg: Vec<String>,
+    /// Default-subtitle policy, e.g. "forced:eng, else none" (overrides config)
+    #[arg(long, value_name = "POLICY")]
+    subtitle_default: Option<SubtitlePolicy>,
+    /// Keep only audio and subtitle tracks in these languages, e.g. eng,jpn
+    /// (untagged tracks are kept, and all audio when none matches)
+    #[arg(long, value_name = "LANGS", value_delimiter = ',')]
+    languages: Vec<String>,
+    /// Stabilize shaky footage with a vid.stab analysis pass (needs ffmpeg with libvidstab)
+    #[arg(long)]
+    stabilize: bool,
+    /// Keyframe interval: `auto` (2 s for mp4/ts, 10 s otherwise) or seconds
+    #[arg(long, value_name = "auto|SECONDS")]
+    keyint: Option<Keyint>,
+    /// Add an AAC stereo downmix (dialogue boosted) as the default track, ahead of
+    /// the untouched surround tracks
+    #[arg(long)]
+    stereo_compat: bool,
+    /// Reproducible output: pinned thread counts, no timestamps or version tags
+    #[arg(long)]
+    deterministic: bool,
+    /// Embed a cover image: `auto` (a poster beside the input, else a frame 10% in),
+    /// `at=HH:MM:SS` (the frame there) or `from=IMAGE` (a JPEG or PNG)
+    #[arg(long, value_name = "auto|at=TIME|from=IMAGE")]
+    embed_thumbnail: Option<Thumbnail>,
+    /// Trim leading, trailing or both runs of silence from recordings (needs audio)
+    #[arg(long, value_name = "start|end|both")]
+    trim_silence: Option<TrimEnds>,
+    /// Level below which --trim-silence counts audio as silent, in dB
+    #[arg(
+        long,
+        value_name = "DB",
+        default_value_t = -50.0,
+        allow_negative_numbers = true,
+        requires = "trim_silence"
+    )]
+    silence_threshold: f64,
+    /// Shortest run of silence --trim-silence trims, in seconds
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 0.5,
+        requires = "trim_silence"
+    )]
+    silence_min: f64,
+    /// Change the playback speed, e.g. 1.25x (audio keeps its pitch)
+    #[arg(long, value_name = "FACTOR")]
+    speed: Option<Speed>,
+    /// Move text subtitle cues by this time, e.g. 2.5s or -800ms (after any
+    /// --sub-fps-from change)
+    #[arg(long, value_name = "TIME", allow_hyphen_values = true)]
+    sub_shift: Option<SubShift>,
+    /// Frame rate the text subtitles were timed for (e.g. 25 for a PAL release);
+    /// their cues are stretched to --sub-fps-to
+    #[arg(long, value_name = "FPS", requires = "sub_fps_to")]
+    sub_fps_from: Option<Fps>,
+    /// Frame rate of the video the text subtitles go with (e.g. 23.976)
+    #[arg(long, value_name = "FPS", requires = "sub_fps_from")]
+    sub_fps_to: Option<Fps>,
+    /// Rescue ancient AVI/DivX/Real/WMV sources: tolerate broken indexes, timestamps
+    /// and bitstreams and unpack packed B-frames
+    #[arg(long)]
+    legacy_source: bool,
+    /// Fix frame sizes the encoder rejects (odd ones, for 4:2:0) by padding or
+    /// cropping to the next size that fits
+    #[arg(long, value_name = "pad|crop|off", default_value = "pad")]
+    odd_size: OddSize,
+    /// Do nothing if the encode history shows this input was already transcoded with
+    /// the same settings (without it, it is transcoded again with a warning)
+    #[arg(long)]
+    trust_history: bool,
+    /// Dry run: print command without executing
+    #[arg(long)]
+    dry_run: bool,
+    #[command(flatten)]
+    ownership: OutputOwnership,
+}
+
+impl Commands {
+    fn name(&self) -> &'static str {
+        match self {
+            Commands::Info { .. } => "info",
+            Commands::Transcode(_) => "transcode",
+            Commands::Explain(_) => "explain",
+            Commands::Batch(_) => "batch",
+            Commands::Audiobook(_) => "audiobook",
+            Commands::Watch(_) => "watch",
+            Commands::Serve(_) => "serve",
+            Commands::Recommend { .. } => "recommend",
+            Commands::Upgrades { .. } => "upgrades",
+            Commands::Presets { .. } => "presets",
+            Commands::Config { .. } => "config",
+            Commands::Plugins => "plugins",
+            Commands::Features => "features",
+            Commands::MigrateSuffixed(_) => "migrate-suffixed",
+            Commands::History { .. } => "history",
+            Commands::Selftest(_) => "selftest",
+            Commands::Doctor(_) => "doctor",
+            Commands::GenerateFixtures { .. } => "generate-fixtures",
+            Commands::Init(_) => "init",
+            Commands::TuneWizard(_) => "tune-wizard",
+        }
+    }
+
+    // Whether the command's encodes take --hwaccel
+    fn encodes(&self) -> bool {
+        matches!(
+            self,
+            Commands::Transcode(_)
+                | Commands::Explain(_)
+                | Commands::Batch(_)
+                | Commands::Watch(_)
+                | Commands::Serve(_)
+        )
+    }
+
+    // Whether the command ends with a --json results document
+    fn reports_results(&self) -> bool {
+        match self {
+            Commands::Transcode(_) => true,
+            Commands::Batch(cmd) => cmd.action.is_none(),
+            _ => false,
+        }
+    }
+
+    // Whether running this command would spawn an encode or write to disk.
+    // Kept as an exhaustive match so new subcommands must decide explicitly.
+    fn modifies_files(&self) -> bool {
+        match self {
+            Commands::Info { .. } => false,
+            Commands::Transcode(args) => !args.dry_run,
+            Commands::Explain(_) => false,
+            Commands::Batch(cmd) => match &cmd.action {
+                None => cmd.args.as_ref().is_some_and(|a| !a.dry_run),
+                Some(BatchAction::Missing { args, run, .. }) => *run && !args.dry_run,
+                Some(BatchAction::Prune { delete, .. }) => *delete,
+            },
+            Commands::Audiobook(args) => !args.dry_run,
+            Commands::Watch(args) => !args.dry_run,
+            // Runs whatever jobs are submitted
+            Commands::Serve(_) => true,
+            Commands::Recommend { .. } => false,
+            Commands::Upgrades { .. } => false,
+            Commands::Presets { action } => matches!(action, Some(PresetsAction::Import { .. })),
+            Commands::Config { .. } => false,
+            Commands::Plugins => false,
+            Commands::Features => false,
+            Commands::MigrateSuffixed(args) => args.swap && !args.dry_run,
+            // Only reads the history; the export is a new file, not a change to media
+            Commands::History { .. } => false,
+            // Encodes, if only into a temporary directory
+            Commands::Selftest(_) => true,
+            // Moves files back only to roll back swaps
+            Commands::Doctor(args) => args.roll_back.is_some(),
+            Commands::GenerateFixtures { .. } => true,
+            // Writes the config file (and maybe a preset)
+            Commands::Init(_) => true,
+            // Encodes samples, if only into a temporary directory, and saves a preset
+            Commands::TuneWizard(_) => true,
+        }
+    }
+
+    // `--what-if`: encodes become dry runs, and the destructive commands record their
+    // actions through `Runtime::what_if`. Commands that cannot be simulated refuse.
+    fn simulate(&mut self) -> Result<()> {
+        match self {
+            Commands::Transcode(args) => args.dry_run = true,
+            Commands::Batch(cmd) => {
+                if let Some(args) = &mut cmd.args {
+                    args.dry_run = true;
+                }
+                if let Some(BatchAction::Missing { args, .. }) = &mut cmd.action {
+                    args.dry_run = true;
+                }
+            }
+            Commands::Audiobook(args) => args.dry_run = true,
+            Commands::Watch(args) => args.dry_run = true,
+            Commands::Info { .. }
+            | Commands::Explain(_)
+            | Commands::Recommend { .. }
+            | Commands::Upgrades { .. }
+            | Commands::Presets { .. }
+            | Commands::Config { .. }
+            | Commands::Plugins
+            | Commands::Features
+            | Commands::MigrateSuffixed(_)
+            | Commands::History { .. }
+            | Commands::Doctor(DoctorArgs { roll_back: None })
+            | Commands::Init(_) => {}
+            Commands::Selftest(_)
+            | Commands::Doctor(DoctorArgs { roll_back: Some(_) })
+            | Commands::GenerateFixtures { .. }
+            | Commands::TuneWizard(_)
+            | Commands::Serve(_) => {
+                bail!(i18n::t(
+                    "error-what-if",
+                    &[("command", self.name().to_string())]
+                ))
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!(
+                "{}: {:?}",
+                style::paint(Role::Error, &i18n::t("error", &[])),
+                e
+            );
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run() -> Result<()> {
+    let mut cli = Cli::parse();
+    if cli.what_if.is_some() {
+        cli.command.simulate()?;
+    }
+    // `init` is how a broken config gets replaced, so it starts from the defaults
+    let config = match config::load(cli.config.as_deref()) {
+        Err(_) if matches!(cli.command, Commands::Init(_)) => Config::default(),
+        loaded => loaded?,
+    };
+    i18n::init(cli.lang.as_deref().or(config.lang.as_deref()))?;
+    style::init(cli.color, cli.no_fancy, config.theme.clone());
+    let config_path = cli
+        .config
+        .clone()
+        .or_else(config::default_path)
+        .filter(|path| path.is_file());
+    let diagnostics = Diagnostics::new(cli.diagnostics_dir.clone(), config_path.clone());
+    diagnostics.install_panic_hook();
+    let modifies_files = cli.what_if.is_none() && cli.command.modifies_files();
+    if cli.read_only && modifies_files {
+        bail!(i18n::t(
+            "error-read-only",
+            &[("command", cli.command.name().to_string())]
+        ));
+    }
+    tools::use_binaries(Binaries {
+        ffmpeg: config.ffmpeg.clone(),
+        ffprobe: config.ffprobe.clone(),
+    });
+    let plugins = Arc::new(Plugins::new(config.plugins.clone())?);
+    let event_log = cli.event_log.clone();
+    // Only runs that actually encode or remove files produce events
+    let events = if modifies_files {
+        Events::open(
+            event_log.clone().or(config.event_log.clone()).as_deref(),
+            config.mqtt.as_ref(),
+            Arc::clone(&plugins),
+        )?
+    } else {
+        Events::default()
+    };
+    let runtime = Runtime {
+        events,
+        gate: Gate::new(config.should_run.clone(), config.temperature.clone())?,
+        presets: Presets::load(
+            config::presets_file().as_deref(),
+            config::presets_dir().as_deref(),
+        )?
+        .with_defaults(Defaults {
+            vcodec: config.vcodec.clone(),
+            acodec: config.acodec.clone(),
+            container: config.ext.clone(),
+        }),
+        cache: AnalysisCache::open(config::cache_dir().filter(|_| !cli.no_cache)),
+        probe_workers: cli
+            .probe_workers
+            .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get())),
+        history: History::open(config::data_dir().filter(|_| {
+            modifies_files
+                || matches!(
+                    cli.command,
+                    Commands::History { .. } | Commands::Upgrades { .. }
+                )
+        })),
+        stats: match cli.no_fancy {
+            true => cli.stats.plain(),
+            false => cli.stats,
+        },
+        snapshot_every: cli
+            .snapshot_every
+            .filter(|minutes| *minutes > 0.0)
+            .map(|minutes| Duration::from_secs_f64(minutes * 60.0)),
+        preview: cli.preview_stream,
+        remux_fallback: cli.auto_remux_fallback,
+        hwaccel: match cli.hwaccel {
+            Some(choice) if cli.command.encodes() => hardware(choice)?,
+            _ => None,
+        },
+        what_if: cli.what_if.map(WhatIf::new),
+        units: Units::from_env(cli.porcelain),
+        file_args: config.file_args.clone(),
+        diagnostics,
+        plugins,
+        results: match &cli.json {
+            Some(path) if cli.command.reports_results() => Some(Results::new(path.clone())),
+            _ => None,
+        },
+        metrics: match &cli.command {
+            Commands::Watch(args) if args.metrics.is_some() => Some(Arc::default()),
+            _ => None,
+        },
+    };
+    let command = cli.command.name();
+    let result = match cli.command {
+        Commands::Info { input } => info(&input, cli.json.as_ref()),
+        Commands::Transcode(args) => run_transcode(*args, &config, &runtime, None),
+        Commands::Explain(args) => {
+            let explain = Explain::new(&args.input);
+            run_transcode(*args, &config, &runtime, Some(&explain))
+        }
+        Commands::Batch(cmd) => {
+            let cmd = *cmd;
+            let subtitle_default = |args: &BatchArgs| {
+                args.subtitle_default
+                    .clone()
+                    .or_else(|| config.subtitle_default.clone())
+            };
+            // The config's preset applies when neither --preset nor a --root names one;
+            // its input extensions, extra args and jobs when the flags are not given
+            let with_preset = |mut args: BatchArgs| {
+                args.preset = args.preset.or_else(|| config.preset.clone());
+                args.input_exts = args.input_exts.or_else(|| config.input_exts.clone());
+                if args.extra.is_empty() {
+                    args.extra = config.extra.clone().unwrap_or_default();
+                }
+                // Staged and previewed runs encode one file at a time
+                let serial = args.stage_inputs
+                    || runtime.preview.is_some()
+                    || runtime.snapshot_every.is_some();
+                if !serial {
+                    args.jobs = args.jobs.or(config.jobs);
+                }
+                args
+            };
+            match cmd.action {
+                Some(BatchAction::Missing {
+                    args,
+                    ownership,
+                    run,
+                }) => {
+                    let args = with_preset(*args);
+                    batch::report_missing(
+                        &args,
+                        &ownership,
+                        subtitle_default(&args).as_ref(),
+                        run,
+                        &runtime,
+                    )
+                }
+                Some(BatchAction::Prune {
+                    input_dir,
+                    output_dir,
+                    ext,
+                    input_exts,
+                    delete,
+                    removal,
+                }) => batch::prune(
+                    &input_dir,
+                    &output_dir,
+                    &ext,
+                    config.input_exts(input_exts.as_deref()),
+                    delete,
+                    &removal,
+                    &runtime,
+                ),
+                None => {
+                    // clap enforces the directories (positional or --root) when no subcommand is given
+                    let args = with_preset(cmd.args.context("missing batch arguments")?);
+                    batch::batch_transcode(
+                        &args,
+                        &cmd.ownership,
+                        subtitle_default(&args).as_ref(),
+                        &runtime,
+                    )
+                }
+            }
+        }
+        Commands::Audiobook(args) => audiobook::run(&args, &runtime),
+        Commands::Watch(mut args) => {
+            args.preset = args.preset.or_else(|| config.preset.clone());
+            watch::run(
+                &args,
+                config.input_exts(args.input_exts.as_deref()),
+                &runtime,
+            )
+        }
+        Commands::Serve(mut args) => {
+            args.preset = args.preset.or_else(|| config.preset.clone());
+            // Each job is a `transcode` of its own; `auto` is settled once, here
+            let mut global: Vec<OsString> = Vec::new();
+            if let Some(backend) = runtime.hwaccel {
+                global.extend(["--hwaccel".into(), backend.name().into()]);
+            }
+            for (flag, path) in [
+                ("--event-log", &cli.event_log),
+                ("--diagnostics-dir", &cli.diagnostics_dir),
+            ] {
+                if let Some(path) = path {
+                    global.extend([flag.into(), path.into()]);
+                }
+            }
+            if let Some(lang) = &cli.lang {
+                global.extend(["--lang".into(), lang.into()]);
+            }
+            if let Some(minutes) = cli.snapshot_every {
+                global.extend(["--snapshot-every".into(), minutes.to_string().into()]);
+            }
+            for (flag, set) in [
+                ("--no-cache", cli.no_cache),
+                ("--read-only", cli.read_only),
+                ("--auto-remux-fallback", cli.auto_remux_fallback),
+            ] {
+                if set {
+                    global.push(flag.into());
+                }
+            }
+            serve::run(
+                &args,
+                cli.config.as_deref(),
+                &global,
+                config.templates.clone(),
+                &runtime.presets,
+            )
+        }
+        Commands::Recommend { path, input_exts } => recommend::run(
+            &path,
+            config.input_exts(input_exts.as_deref()),
+            &runtime.cache,
+            runtime.probe_workers,
+        ),
+        Commands::Upgrades { dir, input_exts } => upgrades::run(
+            &dir,
+            config.input_exts(input_exts.as_deref()),
+            &runtime.cache,
+            runtime.probe_workers,
+            &runtime.history,
+        ),
+        Commands::Presets { action } => match action {
+            None => presets::list(&runtime.presets),
+            Some(PresetsAction::Show { name }) => presets::show(
+                &runtime.presets,
+                &name,
+                &preset_command(&runtime.presets, &name)?,
+            ),
+            Some(PresetsAction::Export { name }) => presets::export(&runtime.presets, &name),
+            Some(PresetsAction::Import {
+                source,
+                sha256,
+                yes,
+            }) => presets::import(
+                &runtime.presets,
+                config::presets_dir().as_deref(),
+                &source,
+                sha256.as_deref(),
+                yes,
+                runtime.what_if.as_ref(),
+            ),
+        },
+        Commands::Config {
+            action: ConfigAction::Show,
+        } => config::show(
+            &config,
+            config_path.as_deref(),
+            &[
+                (
+                    "event-log",
+                    event_log.map(|path| path.display().to_string()),
+                ),
+                ("lang", cli.lang),
+            ],
+        ),
+        Commands::Plugins => runtime.plugins.list(),
+        Commands::Features => features::list(),
+        Commands::MigrateSuffixed(args) => migrate::run(&args, &runtime),
+        Commands::History { action } => match action {
+            HistoryAction::Export { format, out } => export::run(&runtime.history, format, &out),
+        },
+        Commands::Selftest(args) => selftest::run(&args),
+        Commands::Doctor(args) => match &args.roll_back {
+            Some(library) => doctor::roll_back(library),
+            None => doctor::run(&runtime.presets, &config),
+        },
+        Commands::GenerateFixtures { out } => synth::run(&out),
+        Commands::Init(args) => init::run(
+            &args,
+            cli.config.clone().or_else(config::default_path).as_deref(),
+            &runtime.presets,
+            config::presets_dir().as_deref(),
+            runtime.what_if.as_ref(),
+        ),
+        Commands::TuneWizard(args) => tune::run(
+            &args,
+            &runtime.cache,
+            &runtime.presets,
+            config::presets_dir().as_deref(),
+            &runtime.units,
+        ),
+    };
+    let result = match &runtime.what_if {
+        Some(what_if) => result.and_then(|()| what_if.finish(command)),
+        None => result,
+    };
+    match &runtime.results {
+        Some(results) => results.finish(command, result),
+        None => result,
+    }
+}
+
+// `transcode`, and `explain` when `explain` is given: the same decisions, narrated
+// step by step, without encoding
+fn run_transcode(
+    args: TranscodeArgs,
+    config: &Config,
+    runtime: &Runtime,
+    explain: Option<&Explain>,
+) -> Result<()> {
+    let events = &runtime.events;
+    let TranscodeArgs {
+        input,
+        output,
+        preset,
+        vcodec,
+        acodec,
+        mut extra,
+        extra_arg,
+        subtitle_default,
+        languages,
+        stabilize,
+        keyint,
+        stereo_compat,
+        deterministic,
+        embed_thumbnail,
+        trim_silence,
+        silence_threshold,
+        silence_min,
+        speed,
+        sub_shift,
+        sub_fps_from,
+        sub_fps_to,
+        legacy_source,
+        odd_size,
+        trust_history,
+        dry_run,
+        ownership,
+    } = args;
+    extra.extend(extra_arg);
+    let extra = match extra.is_empty() {
+        true => config.extra.clone().unwrap_or_default(),
+        false => extra,
+    };
+    let mut chosen = Vec::new();
+    let preset = match preset.or_else(|| config.preset.clone()) {
+        Some(name) if name == presets::AUTO => {
+            let rec = recommend::recommend(&input, &runtime.cache)?;
+            let why = format!("{} ({})", rec.preset, rec.reasons.join("; "));
+            match explain {
+                Some(_) => chosen.push(format!("Preset: {}, picked by --preset auto", why)),
+                None => println!("auto preset: {}", why),
+            }
+            Some(rec.preset.to_string())
+        }
+        other => other,
+    };
+    // Determine safe output path, in the preset's container unless one is given
+    let given_ext = output
+        .as_deref()
+        .and_then(Path::extension)
+        .map(|ext| ext.to_string_lossy());
+    let ext = runtime
+        .presets
+        .output_ext(preset.as_deref(), given_ext.as_deref())?;
+    let resolved_output = resolve_output_path(&input, output.as_deref(), Some(&ext))?;
+    let info = explain.and_then(|_| probe::probe_cached(&input, &runtime.cache).ok());
+    if let Some(explain) = explain {
+        explain.step("Probe", explain::probe_summary(info.as_ref()));
+        let how = match output {
+            Some(_) => "as given",
+            None => "next to the input",
+        };
+        explain.step(
+            "Output",
+            vec![format!("{} ({})", resolved_output.display(), how)],
+        );
+    }
+    let (mut vcodec2, acodec2, mut preset_extra) = runtime.presets.apply(
+        preset.as_deref(),
+        vcodec.as_deref(),
+        acodec.as_deref(),
+        "libx264",
+        &extra,
+    );
+    // Decoding args for --hwaccel, ahead of any others for the input
+    let mut hw_input = Vec::new();
+    let hardware = runtime.hwaccel.and_then(|backend| {
+        let encoder = backend.apply(&vcodec2, &mut preset_extra, &mut hw_input)?;
+        Some((backend, std::mem::replace(&mut vcodec2, encoder)))
+    });
+    let env = runtime.presets.run_env(preset.as_deref());
+    if let Some(explain) = explain {
+        if chosen.is_empty() {
+            chosen.push(match &preset {
+                Some(name) => format!("Preset: {}", name),
+                None => "Preset: none".to_string(),
+            });
+        }
+        let found = preset
+            .as_deref()
+            .and_then(|name| runtime.presets.find(name));
+        let from = |flag: &Option<String>, of_preset: Option<&String>| match (flag, of_preset) {
+            (Some(_), _) => "option",
+            (None, Some(_)) => "preset",
+            (None, None) => "default",
+        };
+        chosen.push(format!(
+            "Video codec: {} ({})",
+            vcodec2,
+            from(&vcodec, found.and_then(|p| p.vcodec.as_ref()))
+        ));
+        chosen.push(format!(
+            "Audio codec: {} ({})",
+            acodec2,
+            from(&acodec, found.and_then(|p| p.acodec.as_ref()))
+        ));
+        if let Some((backend, software)) = &hardware {
+            chosen.push(format!(
+                "Hardware: {} in place of {} (--hwaccel {})",
+                vcodec2,
+                software,
+                backend.name()
+            ));
+        }
+        if !preset_extra.is_empty() {
+            chosen.push(format!("Args: {}", preset_extra.join(" ")));
+        }
+        if !env.is_empty() {
+            chosen.push(format!("ffmpeg environment: {}", env.describe()));
+        }
+        explain.step("Preset and codecs", std::mem::take(&mut chosen));
+    }
+    let before_vbv = preset_extra.clone();
+    vbv::adapt(&vcodec2, &mut preset_extra)?;
+    // What each feature adds, for `explain`
+    let mut applied = Vec::new();
+    if preset_extra != before_vbv {
+        applied.push(format!("Rate cap adapted for {}", vcodec2));
+    }
+    // Per-file stream args go first so user extras can still override them
+    let subtitle_default = subtitle_default.or_else(|| config.subtitle_default.clone());
+    let subtitle_timing = SubTiming::new(sub_shift, sub_fps_from, sub_fps_to);
+    let policies = streams::Policies {
+        languages: &languages,
+        subtitle_default: subtitle_default.as_ref(),
+        stereo_compat,
+        subtitle_timing,
+    };
+    let mut extra2 = streams::plan_args(&input, &runtime.cache, &policies);
+    let plan = info.as_ref().map(|info| streams::plan(info, &policies));
+    if let Some(explain) = explain {
+        let mut lines = Vec::new();
+        if !languages.is_empty() {
+            lines.push(format!("Languages: {}", languages.join(",")));
+        }
+        if let Some(policy) = &subtitle_default {
+            lines.push(format!("Default subtitle: {}", policy));
+        }
+        if stereo_compat {
+            lines.push("Stereo compatibility downmix".to_string());
+        }
+        if let Some(timing) = &subtitle_timing {
+            lines.push(format!("Subtitle timing: {}", timing.option()));
+        }
+        explain.step("Stream policies", lines);
+        explain.step(
+            "Stream plan",
+            plan.as_ref()
+                .map(|plan| plan.describe(&vcodec2, &acodec2))
+                .unwrap_or_default(),
+        );
+    }
+    // Part of the extra args, so the settings hash covers the retiming
+    if let Some(speed) = speed {
+        if let Some(warning) = speed.apply(&vcodec2, &mut preset_extra) {
+            eprintln!("{} {}", Marker::Warning, warning);
+        }
+        applied.push(format!("Speed: {}", speed));
+    }
+    let mut input_opts = match legacy_source {
+        true => {
+            let info = probe::probe_cached(&input, &runtime.cache).ok();
+            let codec = info
+                .as_ref()
+                .and_then(|i| i.video_stream()?.codec_name.as_deref());
+            legacy::apply(&vcodec2, codec, &mut preset_extra);
+            applied.push(format!(
+                "Legacy source ({})",
+                codec.unwrap_or("unknown codec")
+            ));
+            legacy::input_args()
+        }
+        false => Vec::new(),
+    };
+    input_opts.splice(0..0, hw_input);
+    let silence = trim_silence.map(|ends| SilenceTrim::new(ends, silence_threshold, silence_min));
+    let mut options = history::encode_options(
+        preset.as_deref(),
+        &languages,
+        keyint,
+        stabilize,
+        stereo_compat,
+        deterministic,
+        silence.as_ref(),
+    );
+    options.extend(subtitle_timing.as_ref().map(SubTiming::option));
+    let settings = history::settings_hash(&vcodec2, &acodec2, &preset_extra, &options);
+    let previous = explain
+        .is_none()
+        .then(|| history::previous_encode(&input, &settings, &runtime.history))
+        .flatten();
+    if let Some(previous) = previous {
+        eprintln!(
+            "{} {} {}",
+            Marker::Warning,
+            input.display(),
+            history::duplicate_warning(&previous)
+        );
+        if trust_history {
+            println!("Skipping '{}' (--trust-history)", input.display());
+            if let Some(results) = &runtime.results {
+                let mut file = FileResult::new(
+                    &input,
+                    &resolved_output,
+                    preset.as_deref(),
+                    results::Status::Skipped,
+                );
+                file.reason = Some("already transcoded".to_string());
+                results.add(file);
+            }
+            return Ok(());
+        }
+    }
+    extra2.extend(preset_extra);
+    extra2.extend(history::metadata_args(&settings));
+    if let Some(keyint) = keyint.filter(|_| vcodec2 != "copy") {
+        let args = keyint.args(&input, &resolved_output, &runtime.cache);
+        applied.push(format!("Keyframe interval {}: {}", keyint, args.join(" ")));
+        extra2.extend(args);
+    }
+    let stabilizer = stabilize.then(|| Stabilizer::new(&runtime.cache, &input));
+    if let Some(stabilizer) = &stabilizer {
+        stabilizer.apply(&mut extra2);
+        applied.push("Stabilization, after an analysis pass".to_string());
+    }
+    // After every other video filter, which may change the size
+    let adjusted = alignment::apply(
+        odd_size,
+        &vcodec2,
+        probe::probe_cached(&input, &runtime.cache).ok().as_ref(),
+        &mut extra2,
+    );
+    if let Some(adjusted) = &adjusted {
+        match explain {
+            Some(_) => applied.push(format!("Frame size: {}", adjusted)),
+            None => println!("Frame size: {}", adjusted),
+        }
+    }
+    if deterministic {
+        if let Some(warning) = deterministic::apply(&vcodec2, &mut extra2) {
+            eprintln!("{} {}", Marker::Warning, warning);
+        }
+        applied.push("Deterministic output".to_string());
+    }
+    // Dropping the cover at the end removes a grabbed or copied image
+    let cover = match &embed_thumbnail {
+        Some(thumbnail) => {
+            let cover = Cover::new(thumbnail, &input, &resolved_output, &runtime.cache)?;
+            cover.apply(&mut extra2)?;
+            applied.push(format!("Cover: {}", cover.describe()));
+            Some(cover)
+        }
+        None => None,
+    };
+    if let Some(explain) = explain {
+        if let Some(silence) = &silence {
+            applied.push(format!("{}, after a detection pass", silence.option()));
+        }
+        applied.extend(explain::filters(&extra2));
+        explain.step("Filters and options", applied);
+        let mut commands = Vec::new();
+        if let Some(stabilizer) = &stabilizer {
+            commands.push(format!("ffmpeg {}", stabilizer.describe_detect(&input)));
+        }
+        if let Some(silence) = &silence {
+            commands.push(format!("ffmpeg {}", silence.describe_detect(&input)));
+        }
+        let args = ffmpeg_args(
+            &input,
+            &input_opts,
+            &resolved_output,
+            &vcodec2,
+            &acodec2,
+            &extra2,
+        );
+        commands.push(format!("ffmpeg {}", display_args(&args)));
+        explain.step("ffmpeg commands", commands);
+        explain.step(
+            "Predicted output",
+            explain::predicted(
+                &resolved_output,
+                info.as_ref(),
+                &plan.unwrap_or_default(),
+                &vcodec2,
+                adjusted.as_deref(),
+                speed.map_or(1.0, Speed::factor),
+                silence.is_some(),
+            ),
+        );
+        return Ok(());
+    }
+    if dry_run {
+        println!(
+            "{} Would transcode '{}' -> '{}' with vcodec={} acodec={} extra={:?}",
+            Marker::DryRun,
+            input.display(),
+            resolved_output.display(),
+            vcodec2,
+            acodec2,
+            extra2
+        );
+        if let Some(stabilizer) = &stabilizer {
+            println!("  ffmpeg {}", stabilizer.describe_detect(&input));
+        }
+        if let Some(cover) = &cover {
+            println!("  Cover: {}", cover.describe());
+        }
+        if let Some(silence) = &silence {
+            println!("  ffmpeg {}", silence.describe_detect(&input));
+        }
+        if !env.is_empty() {
+            println!("  Environment: {}", env.describe());
+        }
+        let args = ffmpeg_args(
+            &input,
+            &input_opts,
+            &resolved_output,
+            &vcodec2,
+            &acodec2,
+            &extra2,
+        );
+        println!("  ffmpeg {}", display_args(&args));
+        if let Some(preview) = &runtime.preview {
+            println!("  Preview stream: {}", preview);
+        }
+        if ownership.is_set() {
+            println!(
+                "  {} Would set {} on output",
+                Marker::DryRun,
+                ownership.describe()
+            );
+        }
+        if let Some(results) = &runtime.results {
+            results.add(FileResult::new(
+                &input,
+                &resolved_output,
+                preset.as_deref(),
+                results::Status::Planned,
+            ));
+        }
+        Ok(())
+    } else {
+        events.emit(Event::Start {
+            input: path_str(&input),
+            output: path_str(&resolved_output),
+            index: 1,
+            total: 1,
+            device: None,
+        });
+        let started = Instant::now();
+        let record =
+            Record::new(&input, &resolved_output, &settings, preset.as_deref()).adjusted(adjusted);
+        let result = match &stabilizer {
+            Some(stabilizer) => stabilizer.detect(&input, runtime.stats),
+            None => Ok(()),
+        }
+        .and_then(|()| cover.as_ref().map_or(Ok(()), Cover::prepare))
+        .and_then(|()| match &silence {
+            Some(silence) => silence.args(&input, &runtime.cache, speed.map_or(1.0, Speed::factor)),
+            None => Ok(Vec::new()),
+        })
+        .and_then(|trim| {
+            let extra = [extra2.as_slice(), &trim].concat();
+            transcode(
+                &input,
+                &input_opts,
+                &resolved_output,
+                (&vcodec2, &acodec2),
+                &extra,
+                &env,
+                runtime,
+            )
+        })
+        // Plugin analyzers judge the finished output; a rejection fails the transcode
+        .and_then(|()| runtime.plugins.analyze(&input, &resolved_output));
+        if let Err(e) = result {
+            runtime
+                .history
+                .append(&record.finished(false, started.elapsed().as_secs_f64(), 0));
+            if let Some(results) = &runtime.results {
+                let mut file = FileResult::new(
+                    &input,
+                    &resolved_output,
+                    preset.as_deref(),
+                    results::Status::Failed,
+                );
+                file.error = Some(format!("{:#}", e));
+                file.seconds = started.elapsed().as_secs_f64();
+                results.add(file);
+            }
+            events.emit(Event::Fail {
+                input: path_str(&input),
+                output: path_str(&resolved_output),
+                error: e.to_string(),
+            });
+            return Err(e);
+        }
+        ownership.apply(&resolved_output);
+        let output_bytes = std::fs::metadata(&resolved_output)
+            .map(|m| m.len())
+            .unwrap_or(0);
+        runtime.history.append(&record.finished(
+            true,
+            started.elapsed().as_secs_f64(),
+            output_bytes,
+        ));
+        events.emit(Event::Done {
+            input: path_str(&input),
+            output: path_str(&resolved_output),
+            seconds: started.elapsed().as_secs_f64(),
+            output_bytes,
+        });
+        if let Some(results) = &runtime.results {
+            let mut file = FileResult::new(
+                &input,
+                &resolved_output,
+                preset.as_deref(),
+                results::Status::Done,
+            );
+            file.seconds = started.elapsed().as_secs_f64();
+            file.output_bytes = output_bytes;
+            results.add(file);
+        }
+        Ok(())
+    }
+}
+
+fn info(input: &Path, json: Option<&Option<PathBuf>>) -> Result<()> {
+    if let Some(path) = json {
+        // ffprobe's own JSON, plus `schema_version` and an `hdr` object for HDR video
+        let output = tools::output(Tool::Ffprobe, &probe::probe_args(input))?;
+        if !output.status.success() {
+            bail!(
+                "{}: {}",
+                tools::exit_error(Tool::Ffprobe, output.status.code()),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        let mut value: serde_json::Value = serde_json::from_slice(&output.stdout)
+            .with_context(|| format!("failed to parse ffprobe output for {}", input.display()))?;
+        let media: probe::MediaInfo = serde_json::from_value(value.clone())
+            .with_context(|| format!("failed to parse ffprobe output for {}", input.display()))?;
+        #[cfg(feature = "mediainfo")]
+        let media = {
+            let mut media = media;
+            mediainfo::supplement(input, &mut media);
+            mediainfo::add_to_json(&media, &mut value);
+            media
+        };
+        if let Some(hdr) = Hdr::of(input, &media) {
+            value["hdr"] = serde_json::to_value(hdr)?;
+        }
+        schema::stamp(&mut value, schema::INFO);
+        let json = serde_json::to_string_pretty(&value)?;
+        match path {
+            Some(path) => std::fs::write(path, json)
+                .with_context(|| format!("failed to write {}", path.display()))?,
+            None => println!("{}", json),
+        }
+        return Ok(());
+    }
+
+    let args = ["-hide_banner".into(), "-i".into(), input.into()];
+    let status = tools::status(Tool::Ffprobe, &args)?;
+    if !status.success() {
+        bail!(tools::exit_error(Tool::Ffprobe, status.code()));
+    }
+    // ffprobe prints the mastering display and light levels, if at all, as raw ratios
+    let media = probe::probe(input).ok();
+    if let Some(hdr) = media.as_ref().and_then(|media| Hdr::of(input, media)) {
+        for line in hdr.lines() {
+            println!("{}", line);
+        }
+    }
+    #[cfg(feature = "mediainfo")]
+    for line in media.iter().flat_map(mediainfo::lines) {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+// The `--hwaccel` backend for this run, told to the user when `auto` picked it
+fn hardware(choice: HwAccel) -> Result<Option<Backend>> {
+    let backend = hwaccel::resolve(choice)?;
+    match (choice, backend) {
+        (HwAccel::Auto, Some(backend)) => println!("--hwaccel auto: using {}", backend.name()),
+        (HwAccel::Auto, None) => eprintln!(
+            "{} --hwaccel auto: no hardware encoder works here; encoding in software",
+            Marker::Warning
+        ),
+        _ => {}
+    }
+    Ok(backend)
+}
+
+// The ffmpeg command a batch encode with preset `name` runs, for `presets show`
+fn preset_command(presets: &Presets, name: &str) -> Result<String> {
+    presets
+        .find(name)
+        .with_context(|| presets::unknown_preset(name))?;
+    let ext = presets.output_ext(Some(name), None)?;
+    let (vcodec, acodec, extra) = presets.apply(Some(name), None, None, "libx265", &[]);
+    let output = PathBuf::from(format!("OUTPUT.{}", ext));
+    let args = ffmpeg_command(Path::new("INPUT"), &[], &output, &vcodec, &acodec, &extra);
+    Ok(format!("ffmpeg {}", display_args(&args.build())))
+}
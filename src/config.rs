@@ -0,0 +1,232 @@
+// file: src/config.rs
+// version: 0.16.1
+// guid: 3f456dae-6997-4f31-946a-56cb0e8e4828
+
+//! User configuration file (`~/.config/transcoderr/config.toml`, or `--config PATH`)
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use clap::Subcommand;
+use serde::Deserialize;
+
+use crate::mqtt::MqttConfig;
+use crate::plugins::PluginConfig;
+use crate::schedule::{ShouldRunConfig, TemperatureConfig};
+use crate::serve::Template;
+use crate::sidecar::FileArgs;
+use crate::style::{Table, Theme};
+use crate::subtitles::SubtitlePolicy;
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigAction {
+    /// Print the effective settings: the config file's values over the built-in defaults
+    Show,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// Preset for transcode and batch when `--preset` is not given (`transcoderr init`
+    /// writes its recommendation here)
+    pub preset: Option<String>,
+    /// Video codec when neither `--vcodec` nor a preset sets one
+    pub vcodec: Option<String>,
+    /// Audio codec when neither `--acodec` nor a preset sets one
+    pub acodec: Option<String>,
+    /// Output container when neither `--ext` nor a preset sets one
+    pub ext: Option<String>,
+    /// Input extensions for batch, recommend and upgrades (comma-separated)
+    pub input_exts: Option<String>,
+    /// Extra ffmpeg args for transcode and batch when `--extra` is not given
+    pub extra: Option<Vec<String>>,
+    /// ffmpeg binary to run instead of the one on PATH
+    pub ffmpeg: Option<PathBuf>,
+    /// ffprobe binary to run instead of the one on PATH
+    pub ffprobe: Option<PathBuf>,
+    /// Encodes a batch runs at once when `--jobs` is not given
+    pub jobs: Option<u64>,
+    /// Default-subtitle policy applied per file, e.g. `"forced:eng, else none"`
+    pub subtitle_default: Option<SubtitlePolicy>,
+    /// Append lifecycle events (NDJSON) to this file
+    pub event_log: Option<PathBuf>,
+    /// Publish lifecycle events to an MQTT broker (`[mqtt]` table)
+    pub mqtt: Option<MqttConfig>,
+    /// Hook asked before each batch job whether to start it now (`[should-run]` table)
+    pub should_run: Option<ShouldRunConfig>,
+    /// Pause between batch jobs while the machine is too hot (`[temperature]` table)
+    pub temperature: Option<TemperatureConfig>,
+    /// Extra ffmpeg args for particular files in batch runs (`[file-args]` table)
+    pub file_args: FileArgs,
+    /// Language for summaries and errors (`de`, `es`, `fr`; default from the locale)
+    pub lang: Option<String>,
+    /// Colors of status markers and table headings (`[theme]` table)
+    pub theme: Theme,
+    /// External analyzers, output namers and notifiers (`[[plugins]]` entries)
+    pub plugins: Vec<PluginConfig>,
+    /// Named job templates for `serve` (`[templates.NAME]` tables)
+    pub templates: BTreeMap<String, Template>,
+}
+
+impl Config {
+    // `--input-exts` when given, else the config's, else the built-in list
+    pub fn input_exts<'a>(&'a self, flag: Option<&'a str>) -> &'a str {
+        flag.or(self.input_exts.as_deref())
+            .unwrap_or(crate::batch::DEFAULT_INPUT_EXTS)
+    }
+}
+
+// `$XDG_CONFIG_HOME/transcoderr`, falling back to `~/.config` (or `%APPDATA%`)
+pub fn config_dir() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from))
+        .map(|dir| dir.join("transcoderr"))
+}
+
+pub fn default_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("config.toml"))
+}
+
+// Per-user cache root (analysis results); safe to delete at any time
+pub fn cache_dir() -> Option<PathBuf> {
+    env::var_os("XDG_CACHE_HOME")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .or_else(|| env::var_os("LOCALAPPDATA").map(PathBuf::from))
+        .map(|dir| dir.join("transcoderr"))
+}
+
+// Per-user data that should survive cache cleanups (encode history)
+pub fn data_dir() -> Option<PathBuf> {
+    env::var_os("XDG_DATA_HOME")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("share"))
+        })
+        .or_else(|| env::var_os("LOCALAPPDATA").map(PathBuf::from))
+        .map(|dir| dir.join("transcoderr"))
+}
+
+// The user's own presets, several to a file
+pub fn presets_file() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("presets.toml"))
+}
+
+// Where `presets import` installs preset files
+pub fn presets_dir() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("presets"))
+}
+
+// An explicit path must exist; the default location is optional.
+pub fn load(explicit: Option<&Path>) -> Result<Config> {
+    match explicit {
+        Some(path) => read(path),
+        None => match default_path() {
+            Some(path) if path.is_file() => read(&path),
+            _ => Ok(Config::default()),
+        },
+    }
+}
+
+fn read(path: &Path) -> Result<Config> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("failed to read config file {}", path.display()))?;
+    let config: Config =
+        toml::from_str(&text).with_context(|| format!("invalid config file {}", path.display()))?;
+    if config.jobs == Some(0) {
+        bail!(
+            "invalid config file {}: jobs must be at least 1",
+            path.display()
+        );
+    }
+    if let Some(mqtt) = &config.mqtt {
+        mqtt.validate()
+            .with_context(|| format!("invalid config file {}", path.display()))?;
+    }
+    Ok(config)
+}
+
+// `config show`: each setting with its value and where that came from. `path` is the
+// file that was loaded, if any; `flags` are global flags given on this run.
+pub fn show(config: &Config, path: Option<&Path>, flags: &[(&str, Option<String>)]) -> Result<()> {
+    match path {
+        Some(path) => println!("Config file: {}", path.display()),
+        None => println!("Config file: none (built-in defaults)"),
+    }
+    let mut table = Table::new(&["Setting", "Value", "From"]);
+    let mut row = |setting: &str, value: Option<String>, default: &str| {
+        let flag = flags
+            .iter()
+            .find(|(name, _)| *name == setting)
+            .and_then(|(_, value)| value.clone());
+        let (value, from) = match (flag, value) {
+            (Some(flag), _) => (flag, "flag"),
+            (None, Some(value)) => (value, "config"),
+            (None, None) => (default.to_string(), "built-in"),
+        };
+        table.row(vec![setting.to_string(), value, from.to_string()]);
+    };
+    let path_str = |path: &Option<PathBuf>| path.as_ref().map(|p| p.display().to_string());
+    row("preset", config.preset.clone(), "none");
+    row(
+        "vcodec",
+        config.vcodec.clone(),
+        "the preset's, else libx265 (batch) / libx264 (transcode)",
+    );
+    row("acodec", config.acodec.clone(), "the preset's, else aac");
+    row(
+        "ext",
+        config.ext.clone(),
+        "the preset's container, else mkv",
+    );
+    row(
+        "input-exts",
+        config.input_exts.clone(),
+        crate::batch::DEFAULT_INPUT_EXTS,
+    );
+    row(
+        "extra",
+        config.extra.as_ref().map(|extra| extra.join(" ")),
+        "none",
+    );
+    row("ffmpeg", path_str(&config.ffmpeg), "ffmpeg on PATH");
+    row("ffprobe", path_str(&config.ffprobe), "ffprobe on PATH");
+    row("jobs", config.jobs.map(|jobs| jobs.to_string()), "1");
+    row(
+        "subtitle-default",
+        config.subtitle_default.as_ref().map(ToString::to_string),
+        "none",
+    );
+    row("event-log", path_str(&config.event_log), "none");
+    row("lang", config.lang.clone(), "from the locale");
+    let set = |configured: bool| configured.then(|| "set".to_string());
+    row("[mqtt]", set(config.mqtt.is_some()), "not set");
+    row("[should-run]", set(config.should_run.is_some()), "not set");
+    row(
+        "[temperature]",
+        set(config.temperature.is_some()),
+        "not set",
+    );
+    let plugins = config.plugins.len();
+    row(
+        "[[plugins]]",
+        (plugins > 0).then(|| plugins.to_string()),
+        "none",
+    );
+    let templates = config.templates.len();
+    row(
+        "[templates]",
+        (templates > 0).then(|| templates.to_string()),
+        "none",
+    );
+    print!("{}", table);
+    Ok(())
+}
@@ -0,0 +1,205 @@
+// file: src/cue.rs
+// version: 0.2.0
+// guid: 171058bc-0496-46e8-9bb2-dd8c68656952
+
+//! Cue sheet parsing: album tags, track titles, performers and start times (`INDEX 01`)
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CueSheet {
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    // From `REM DATE` / `REM GENRE`, as written by most rippers
+    pub date: Option<String>,
+    pub genre: Option<String>,
+    pub tracks: Vec<CueTrack>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CueTrack {
+    pub number: u32,
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    // The FILE the track's audio lives in, as written in the sheet
+    pub file: String,
+    pub start_ms: u64,
+}
+
+impl CueSheet {
+    // Names of the audio files the tracks refer to, in order of first use
+    pub fn files(&self) -> Vec<&str> {
+        let mut files: Vec<&str> = Vec::new();
+        for track in &self.tracks {
+            if !files.contains(&track.file.as_str()) {
+                files.push(&track.file);
+            }
+        }
+        files
+    }
+}
+
+pub fn read(path: &Path) -> Result<CueSheet> {
+    let bytes =
+        fs::read(path).with_context(|| format!("failed to read cue sheet {}", path.display()))?;
+    // Sheets ripped on Windows are often Latin-1 rather than UTF-8
+    let text = match String::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(e) => e.into_bytes().iter().map(|&b| b as char).collect(),
+    };
+    parse(&text).with_context(|| format!("invalid cue sheet {}", path.display()))
+}
+
+pub fn parse(text: &str) -> Result<CueSheet> {
+    let mut sheet = CueSheet::default();
+    let mut file: Option<String> = None;
+    // The track being read; it is kept once its INDEX 01 has been seen
+    let mut track: Option<(CueTrack, bool)> = None;
+
+    for (lineno, line) in text.trim_start_matches('\u{feff}').lines().enumerate() {
+        let line = line.trim();
+        let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim();
+        match command.to_ascii_uppercase().as_str() {
+            "FILE" => file = Some(quoted(rest)),
+            "REM" => {
+                let (key, value) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                // Unquoted REM values may contain spaces (`REM GENRE Classic Rock`)
+                let value = value.trim();
+                let value = Some(if value.starts_with('"') {
+                    quoted(value)
+                } else {
+                    value.to_string()
+                });
+                match key.to_ascii_uppercase().as_str() {
+                    "DATE" => sheet.date = value,
+                    "GENRE" => sheet.genre = value,
+                    _ => {}
+                }
+            }
+            "TRACK" => {
+                finish_track(&mut sheet, track.take());
+                let number = rest
+                    .split_whitespace()
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .with_context(|| format!("line {}: bad TRACK number", lineno + 1))?;
+                let Some(file) = file.clone() else {
+                    bail!("line {}: TRACK before any FILE", lineno + 1);
+                };
+                track = Some((
+                    CueTrack {
+                        number,
+                        title: None,
+                        performer: None,
+                        file,
+                        start_ms: 0,
+                    },
+                    false,
+                ));
+            }
+            "TITLE" | "PERFORMER" => {
+                let value = Some(quoted(rest));
+                let is_title = command.eq_ignore_ascii_case("TITLE");
+                match (&mut track, is_title) {
+                    (Some((t, _)), true) => t.title = value,
+                    (Some((t, _)), false) => t.performer = value,
+                    (None, true) => sheet.title = value,
+                    (None, false) => sheet.performer = value,
+                }
+            }
+            "INDEX" => {
+                let mut parts = rest.split_whitespace();
+                if parts.next() != Some("01") {
+                    // INDEX 00 is the pregap; the track starts at 01
+                    continue;
+                }
+                let start = parts
+                    .next()
+                    .and_then(parse_time)
+                    .with_context(|| format!("line {}: bad INDEX time", lineno + 1))?;
+                if let Some((t, seen)) = &mut track {
+                    t.start_ms = start;
+                    *seen = true;
+                }
+            }
+            _ => {}
+        }
+    }
+    finish_track(&mut sheet, track);
+    if sheet.tracks.is_empty() {
+        bail!("no tracks");
+    }
+    Ok(sheet)
+}
+
+fn finish_track(sheet: &mut CueSheet, track: Option<(CueTrack, bool)>) {
+    if let Some((track, true)) = track {
+        sheet.tracks.push(track);
+    }
+}
+
+// `"Some Title"` or a bare word; FILE lines also carry a trailing type (`WAVE`, `MP3`)
+fn quoted(rest: &str) -> String {
+    match rest.strip_prefix('"') {
+        Some(inner) => inner.split('"').next().unwrap_or_default().to_string(),
+        None => rest
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .to_string(),
+    }
+}
+
+// mm:ss:ff with 75 frames per second
+fn parse_time(time: &str) -> Option<u64> {
+    let mut parts = time.split(':').map(|p| p.parse::<u64>().ok());
+    let (minutes, seconds, frames) = (parts.next()??, parts.next()??, parts.next()??);
+    if parts.next().is_some() || seconds >= 60 || frames >= 75 {
+        return None;
+    }
+    Some((minutes * 60 + seconds) * 1000 + frames * 1000 / 75)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_tracks_and_start_times() {
+        let sheet = parse(
+            "\u{feff}REM GENRE Audiobook\r\n\
+             PERFORMER \"Jane Author\"\r\n\
+             TITLE \"The Book\"\r\n\
+             FILE \"The Book.flac\" WAVE\r\n\
+             \x20 TRACK 01 AUDIO\r\n\
+             \x20   TITLE \"Opening\"\r\n\
+             \x20   INDEX 01 00:00:00\r\n\
+             \x20 TRACK 02 AUDIO\r\n\
+             \x20   TITLE \"Chapter One\"\r\n\
+             \x20   PERFORMER \"Narrator\"\r\n\
+             \x20   INDEX 00 04:59:00\r\n\
+             \x20   INDEX 01 05:01:30\r\n",
+        )
+        .unwrap();
+        assert_eq!(sheet.title.as_deref(), Some("The Book"));
+        assert_eq!(sheet.performer.as_deref(), Some("Jane Author"));
+        assert_eq!(sheet.genre.as_deref(), Some("Audiobook"));
+        assert_eq!(sheet.tracks.len(), 2);
+        assert_eq!(sheet.tracks[1].number, 2);
+        assert_eq!(sheet.tracks[1].title.as_deref(), Some("Chapter One"));
+        assert_eq!(sheet.tracks[1].performer.as_deref(), Some("Narrator"));
+        assert_eq!(sheet.tracks[1].file, "The Book.flac");
+        assert_eq!(sheet.tracks[1].start_ms, 301_400);
+    }
+
+    #[test]
+    fn rejects_malformed_sheets() {
+        assert!(parse("TRACK 01 AUDIO\nINDEX 01 00:00:00\n").is_err());
+        assert!(parse("FILE a.wav WAVE\nTRACK 01 AUDIO\nINDEX 01 00:61:00\n").is_err());
+        assert!(parse("REM nothing here\n").is_err());
+    }
+}
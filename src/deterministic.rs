@@ -0,0 +1,53 @@
+// file: src/deterministic.rs
+// version: 0.1.0
+// guid: adb2ed69-0f4f-4ba2-8940-104724fe9212
+
+//! Reproducible encodes (`--deterministic`): identical inputs and settings produce
+//! byte-identical outputs on any machine, for dedup-backed storage and verification.
+//!
+//! Encoders split work by the number of cores they find, so thread counts are pinned;
+//! muxers stamp the current time and the ffmpeg version into the file, so both are
+//! suppressed with `bitexact`. Hardware encoders cannot be made reproducible.
+
+// Fixed rather than per-machine; enough to keep software encoders reasonably busy
+const THREADS: &str = "4";
+const X265_THREADING: &str = "pools=4:frame-threads=2";
+const HARDWARE_ENCODERS: [&str; 5] = ["nvenc", "qsv", "vaapi", "videotoolbox", "amf"];
+
+// Append the args after every other extra so nothing later undoes them. Returns a
+// warning when `vcodec` cannot honour them.
+pub fn apply(vcodec: &str, extra: &mut Vec<String>) -> Option<String> {
+    if vcodec == "libx265" {
+        // ffmpeg keeps only the last -x265-params, so extend a preset's instead
+        match extra.iter().position(|arg| arg == "-x265-params") {
+            Some(i) if i + 1 < extra.len() => {
+                extra[i + 1] = format!("{}:{}", extra[i + 1], X265_THREADING);
+            }
+            _ => extra.extend(["-x265-params".to_string(), X265_THREADING.to_string()]),
+        }
+    }
+    extra.extend(
+        [
+            "-threads",
+            THREADS,
+            "-filter_threads",
+            "1",
+            "-fflags",
+            "+bitexact",
+            "-flags:v",
+            "+bitexact",
+            "-flags:a",
+            "+bitexact",
+        ]
+        .map(String::from),
+    );
+    HARDWARE_ENCODERS
+        .iter()
+        .any(|hw| vcodec.contains(hw))
+        .then(|| {
+            format!(
+                "{} is a hardware encoder; its output may still differ between runs and GPUs",
+                vcodec
+            )
+        })
+}
@@ -0,0 +1,312 @@
+// file: src/doctor.rs
+// version: 0.2.1
+// guid: 1f6b93d4-2e8a-47c5-b0d1-8a5c37e9f264
+
+//! `doctor`: check that this machine can run transcoderr, without encoding anything.
+//!
+//! It looks for ffmpeg and ffprobe (as configured, else on PATH), lists the encoders and
+//! filters transcoderr uses and whether this ffmpeg has them, and checks every preset's
+//! codecs and `-vf`/`-af` filters against that. Missing tools, a missing default
+//! encoder or an unusable configured preset fail the command; anything else is only
+//! reported. `selftest` goes further and actually encodes.
+//!
+//! `--roll-back DIR` instead undoes the swaps a crash left unfinished in a library
+//! (see `migrate-suffixed --swap`), putting each original back under its own name.
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Result, bail};
+use clap::Args;
+
+use crate::config::Config;
+use crate::i18n;
+use crate::journal::Journal;
+use crate::presets::Presets;
+use crate::selftest::tool_version;
+use crate::style::{Marker, Table};
+use crate::tools::{self, Tool};
+
+#[derive(Args, Debug)]
+pub struct DoctorArgs {
+    /// Roll back the swaps a crash left unfinished in the library at DIR, instead of
+    /// checking this machine
+    #[arg(long, value_name = "DIR")]
+    pub roll_back: Option<PathBuf>,
+}
+
+// Encoders worth knowing about beyond the ones presets name
+const ENCODERS: [(&str, &str); 16] = [
+    ("libx265", "batch default"),
+    ("libx264", "transcode default"),
+    ("aac", "default audio"),
+    ("libsvtav1", ""),
+    ("libopus", ""),
+    ("libvpx-vp9", ""),
+    ("hevc_nvenc", "--hwaccel nvenc"),
+    ("h264_nvenc", "--hwaccel nvenc"),
+    ("av1_nvenc", "--hwaccel nvenc"),
+    ("hevc_qsv", "--hwaccel qsv"),
+    ("h264_qsv", "--hwaccel qsv"),
+    ("av1_qsv", "--hwaccel qsv"),
+    ("hevc_vaapi", "--hwaccel vaapi"),
+    ("h264_vaapi", "--hwaccel vaapi"),
+    ("hevc_videotoolbox", "--hwaccel videotoolbox"),
+    ("h264_videotoolbox", "--hwaccel videotoolbox"),
+];
+
+// Filters behind options
+const FILTERS: [(&str, &str); 5] = [
+    ("libvmaf", "batch --min-vmaf, tune-wizard --vmaf"),
+    ("vidstabdetect", "--stabilize"),
+    ("vidstabtransform", "--stabilize"),
+    ("silencedetect", "--trim-silence"),
+    ("atempo", "--speed"),
+];
+
+// Names in an `ffmpeg -encoders` or `-filters` listing: the second column of each line
+// after the legend
+fn listed(tool_args: &[&str]) -> BTreeSet<String> {
+    let args: Vec<_> = tool_args.iter().map(|arg| arg.into()).collect();
+    let Ok(output) = tools::output(Tool::Ffmpeg, &args) else {
+        return BTreeSet::new();
+    };
+    let text = String::from_utf8_lossy(&output.stdout);
+    let body = text.split_once("------").map_or(&*text, |(_, body)| body);
+    body.lines()
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .map(str::to_string)
+        .collect()
+}
+
+// The filters a graph uses: `yadif=mode=1,scale=1280:-2` -> yadif, scale. Commas
+// inside quotes, after a backslash or within an expression's parentheses do not split.
+fn graph_filters(graph: &str) -> Vec<String> {
+    let (mut filters, mut current) = (Vec::new(), String::new());
+    let (mut depth, mut quoted, mut escaped) = (0usize, false, false);
+    for c in graph.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '\'' => quoted = !quoted,
+            '(' if !quoted => depth += 1,
+            ')' if !quoted => depth = depth.saturating_sub(1),
+            ',' | ';' if !quoted && depth == 0 => {
+                filters.push(std::mem::take(&mut current));
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    filters.push(current);
+    filters
+        .iter()
+        .map(|filter| {
+            // Drop `[in]` pad labels before the name
+            let mut filter = filter.trim_start();
+            while let Some(rest) = filter.strip_prefix('[') {
+                filter = rest.split_once(']').map_or("", |(_, rest)| rest);
+            }
+            filter
+                .split('=')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_string()
+        })
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+// What a preset needs from ffmpeg: its encoders, then its filters
+fn needs(vcodec: &str, acodec: &str, extra: &[String]) -> (Vec<String>, Vec<String>) {
+    let encoders = [vcodec, acodec]
+        .into_iter()
+        .filter(|codec| *codec != "copy")
+        .map(str::to_string)
+        .collect();
+    let filters = extra
+        .windows(2)
+        .filter(|pair| {
+            ["-vf", "-af", "-filter_complex"].contains(&pair[0].as_str())
+                || pair[0].starts_with("-filter:")
+        })
+        .flat_map(|pair| graph_filters(&pair[1]))
+        .collect();
+    (encoders, filters)
+}
+
+// `doctor --roll-back DIR`
+pub fn roll_back(library: &Path) -> Result<()> {
+    let settled = Journal::open(library).roll_back()?;
+    for line in &settled {
+        println!("{}", line);
+    }
+    println!(
+        "{}",
+        i18n::t(
+            "doctor-rolled-back",
+            &[
+                ("count", settled.len().to_string()),
+                ("dir", library.display().to_string()),
+            ]
+        )
+    );
+    Ok(())
+}
+
+pub fn run(presets: &Presets, config: &Config) -> Result<()> {
+    let mut problems = Vec::new();
+    println!("Tools");
+    let mut table = Table::new(&["", "Tool", "Path", "Version"]);
+    for tool in [Tool::Ffmpeg, Tool::Ffprobe] {
+        let path = tool.path().display().to_string();
+        match tool_version(tool) {
+            Ok(version) => table.row(vec![
+                Marker::Ok.to_string(),
+                tool.program().into(),
+                path,
+                version,
+            ]),
+            Err(e) => {
+                problems.push(format!("{} cannot be run ({:#})", tool.program(), e));
+                table.row(vec![
+                    Marker::Fail.to_string(),
+                    tool.program().into(),
+                    path,
+                    "-".into(),
+                ]);
+            }
+        }
+    }
+    print!("{}", table);
+
+    let encoders = listed(&["-hide_banner", "-encoders"]);
+    let filters = listed(&["-hide_banner", "-filters"]);
+    // Without listings (no ffmpeg) everything counts as found, the tools being the problem
+    let found =
+        |listing: &BTreeSet<String>, name: &str| listing.is_empty() || listing.contains(name);
+    let yes_no =
+        |listing: &BTreeSet<String>, name: &str| match (listing.is_empty(), listing.contains(name))
+        {
+            (true, _) => "-".to_string(),
+            (false, true) => Marker::Ok.to_string(),
+            (false, false) => "missing".to_string(),
+        };
+
+    // The codecs runs fall back to, which the config may change
+    let mut required = vec![
+        config.vcodec.clone().unwrap_or_else(|| "libx265".into()),
+        config.acodec.clone().unwrap_or_else(|| "aac".into()),
+    ];
+    if config.vcodec.is_none() {
+        required.push("libx264".into());
+    }
+    println!("\nEncoders");
+    let mut table = Table::new(&["Encoder", "Found", "Used by"]);
+    let mut shown: Vec<(String, String)> = ENCODERS
+        .iter()
+        .map(|(name, used_by)| (name.to_string(), used_by.to_string()))
+        .collect();
+    let unlisted: Vec<String> = required
+        .iter()
+        .filter(|name| !shown.iter().any(|(n, _)| n == *name))
+        .cloned()
+        .collect();
+    for name in unlisted {
+        shown.insert(0, (name, "config default".into()));
+    }
+    for (name, used_by) in &shown {
+        let is_found = found(&encoders, name);
+        if !is_found && required.contains(name) {
+            problems.push(format!("ffmpeg has no {} encoder", name));
+        }
+        table.row(vec![name.clone(), yes_no(&encoders, name), used_by.clone()]);
+    }
+    print!("{}", table);
+
+    println!("\nFilters");
+    let mut table = Table::new(&["Filter", "Found", "Used by"]);
+    for (name, used_by) in FILTERS {
+        table.row(vec![
+            name.to_string(),
+            yes_no(&filters, name),
+            used_by.to_string(),
+        ]);
+    }
+    print!("{}", table);
+
+    let configured = config
+        .preset
+        .as_deref()
+        .and_then(|name| presets.find(name))
+        .map(|preset| preset.name.as_str());
+    println!("\nPresets");
+    let mut table = Table::new(&["", "Preset", "Missing"]);
+    for preset in presets.names() {
+        let (vcodec, acodec, extra) = presets.apply(Some(&preset), None, None, "libx265", &[]);
+        let (needed_encoders, needed_filters) = needs(&vcodec, &acodec, &extra);
+        let missing: Vec<String> = needed_encoders
+            .iter()
+            .filter(|name| !found(&encoders, name))
+            .map(|name| format!("{} encoder", name))
+            .chain(
+                needed_filters
+                    .iter()
+                    .filter(|name| !found(&filters, name))
+                    .map(|name| format!("{} filter", name)),
+            )
+            .collect();
+        let marker = match (encoders.is_empty(), missing.is_empty()) {
+            (true, _) => "-".to_string(),
+            (false, true) => Marker::Ok.to_string(),
+            (false, false) => Marker::Fail.to_string(),
+        };
+        if !missing.is_empty() && configured == Some(preset.as_str()) {
+            problems.push(format!(
+                "the configured preset {} needs {}",
+                preset,
+                missing.join(", ")
+            ));
+        }
+        let missing = match missing.is_empty() {
+            true => "-".to_string(),
+            false => missing.join(", "),
+        };
+        table.row(vec![marker, preset, missing]);
+    }
+    print!("{}", table);
+
+    if problems.is_empty() {
+        println!("\nNo problems found");
+        return Ok(());
+    }
+    println!();
+    for problem in &problems {
+        println!("{} {}", Marker::Error, problem);
+    }
+    bail!("doctor found {} problem(s)", problems.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_need_their_codecs_and_graph_filters() {
+        let extra: Vec<String> = [
+            "-crf",
+            "20",
+            "-vf",
+            "[0:v]yadif=mode=1,scale='min(1280,iw)':-2[v];[v]unsharp",
+            "-filter:a:0",
+            "atempo=1.25",
+        ]
+        .map(String::from)
+        .to_vec();
+        let (encoders, filters) = needs("libx265", "copy", &extra);
+        assert_eq!(encoders, ["libx265"]);
+        assert_eq!(filters, ["yadif", "scale", "unsharp", "atempo"]);
+    }
+}
@@ -0,0 +1,220 @@
+// file: src/encode.rs
+// version: 0.1.1
+// guid: a95393bc-1d6b-49e0-aa59-0a10aa0aa54e
+
+//! Running ffmpeg for an encode: the command line (metadata kept, subtitles copied, the
+//! caller's codecs and args), progress and previews while it runs, the remux fallback
+//! for inputs ffmpeg cannot demux, and `FfmpegFailed` with its log when it fails.
+
+use std::ffi::OsString;
+use std::io::{self, Read};
+use std::path::Path;
+use std::process::ExitStatus;
+use std::sync::{Mutex, PoisonError};
+
+use anyhow::Result;
+
+use crate::Runtime;
+use crate::args::FfmpegArgs;
+use crate::remux;
+use crate::report::{self, FfmpegFailed};
+use crate::snapshots::Snapshots;
+use crate::stats::{self, Stats, StatsFilter};
+use crate::tools::{self, RunEnv, Tool};
+// `input_opts` apply to the input (before its `-i`), `extra` to the output
+pub fn transcode(
+    input: &Path,
+    input_opts: &[String],
+    output: &Path,
+    (vcodec, acodec): (&str, &str),
+    extra: &[String],
+    env: &RunEnv,
+    runtime: &Runtime,
+) -> Result<()> {
+    let fallback = runtime.remux_fallback.then_some(runtime.stats);
+    with_remux_fallback(input, input_opts, fallback, |input| {
+        encode(
+            input,
+            input_opts,
+            output,
+            (vcodec, acodec),
+            extra,
+            env,
+            runtime,
+        )
+    })
+}
+
+// `transcode` on a worker thread of `batch --jobs`: ffmpeg's stderr is kept for the log
+// but not shown, and there is no preview or snapshots
+pub fn transcode_quietly(
+    input: &Path,
+    input_opts: &[String],
+    output: &Path,
+    (vcodec, acodec): (&str, &str),
+    extra: &[String],
+    env: &RunEnv,
+    remux_fallback: bool,
+) -> Result<()> {
+    let fallback = remux_fallback.then_some(Stats::None);
+    with_remux_fallback(input, input_opts, fallback, |input| {
+        let args = encode_command(input, input_opts, output, (vcodec, acodec), extra, env)?.build();
+        let mut log = Vec::new();
+        let status = tools::stream_in(Tool::Ffmpeg, &args, env, &mut |stderr| {
+            log = report::capture_log(stderr);
+        })?;
+        ffmpeg_result(status, &args, log)
+    })
+}
+
+// Run `encode` on `input`; when ffmpeg cannot demux it and `fallback` gives the stats
+// mode to remux with, remux it to MKV first and encode that instead
+fn with_remux_fallback(
+    input: &Path,
+    input_opts: &[String],
+    fallback: Option<Stats>,
+    encode: impl Fn(&Path) -> Result<()>,
+) -> Result<()> {
+    let Err(e) = encode(input) else {
+        return Ok(());
+    };
+    let demuxing = e
+        .downcast_ref::<FfmpegFailed>()
+        .is_some_and(|failed| remux::is_demux_failure(&failed.log));
+    let Some(stats) = fallback.filter(|_| demuxing) else {
+        return Err(e);
+    };
+    eprintln!(
+        "  Demuxing {} failed; remuxing it to MKV to fix its timestamps and encoding again",
+        input.display()
+    );
+    let intermediate = remux::remux(input, input_opts, stats)?;
+    encode(intermediate.path())
+}
+
+// One ffmpeg run of `transcode`, with the job's variables and working directory
+fn encode(
+    input: &Path,
+    input_opts: &[String],
+    output: &Path,
+    (vcodec, acodec): (&str, &str),
+    extra: &[String],
+    env: &RunEnv,
+    runtime: &Runtime,
+) -> Result<()> {
+    let mut command = encode_command(input, input_opts, output, (vcodec, acodec), extra, env)?;
+    if let Some(preview) = &runtime.preview {
+        let (opts, target) = preview.output(extra);
+        command.also_output(opts, target);
+    }
+    // A progress bar or status lines are drawn from ffmpeg's report on stdout
+    let stats = runtime.stats.for_stderr();
+    let following = stats.follows_progress();
+    if following {
+        command.global(["-progress", "pipe:1"]);
+    }
+    let args = command.build();
+
+    // stderr is passed through as it arrives; its tail goes into batch reports
+    let mut log = Vec::new();
+    let mut snapshots = runtime
+        .snapshot_every
+        .map(|every| Snapshots::new(every, &runtime.events, runtime.units, input, output));
+    let filter = Mutex::new(
+        StatsFilter::new(stats, io::stderr())
+            .following_progress(following)
+            .reporting_to(runtime.metrics.clone()),
+    );
+    let mut progress = |stdout: &mut dyn Read| stats::follow_progress(stdout, &filter);
+    let status = tools::stream_with(
+        Tool::Ffmpeg,
+        &args,
+        env,
+        &mut |stderr| log = report::tee_log_through(stderr, &filter, snapshots.as_mut()),
+        following.then_some(&mut progress),
+    )?;
+    filter
+        .into_inner()
+        .unwrap_or_else(PoisonError::into_inner)
+        .finish();
+    ffmpeg_result(status, &args, log)
+}
+
+fn encode_command(
+    input: &Path,
+    input_opts: &[String],
+    output: &Path,
+    (vcodec, acodec): (&str, &str),
+    extra: &[String],
+    env: &RunEnv,
+) -> Result<FfmpegArgs> {
+    // ffmpeg would resolve relative input and output paths from the job's directory
+    let (input_arg, output_arg) = match &env.cwd {
+        Some(_) => (std::path::absolute(input)?, std::path::absolute(output)?),
+        None => (input.to_path_buf(), output.to_path_buf()),
+    };
+    Ok(ffmpeg_command(
+        &input_arg,
+        input_opts,
+        &output_arg,
+        vcodec,
+        acodec,
+        extra,
+    ))
+}
+
+fn ffmpeg_result(status: ExitStatus, args: &[OsString], log: Vec<String>) -> Result<()> {
+    if !status.success() {
+        return Err(FfmpegFailed {
+            code: status.code(),
+            command: display_args(args),
+            log,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+// Paths are passed through as OsString so non-UTF8 filenames reach ffmpeg byte-for-byte.
+pub fn ffmpeg_args(
+    input: &Path,
+    input_opts: &[String],
+    output: &Path,
+    vcodec: &str,
+    acodec: &str,
+    extra: &[String],
+) -> Vec<OsString> {
+    ffmpeg_command(input, input_opts, output, vcodec, acodec, extra).build()
+}
+
+pub fn ffmpeg_command(
+    input: &Path,
+    input_opts: &[String],
+    output: &Path,
+    vcodec: &str,
+    acodec: &str,
+    extra: &[String],
+) -> FfmpegArgs {
+    // Build a conservative default arg list that tries to preserve metadata
+    // -map_metadata 0 copies global metadata
+    // -movflags use_metadata_tags preserves tags in MP4 containers
+    // -c:s copy keeps subtitle streams
+    let mut args = FfmpegArgs::new(output);
+    args.input_with(input_opts, input)
+        .option("-map_metadata", "0")
+        .option("-movflags", "use_metadata_tags")
+        .codec("v", vcodec)
+        .codec("a", acodec)
+        .codec("s", "copy")
+        // Then any extra args the user provided; codec overrides follow the defaults
+        .extra(extra);
+    args
+}
+
+// Render an argument list for display only; never feed the result back to a Command.
+pub fn display_args(args: &[OsString]) -> String {
+    args.iter()
+        .map(|a| a.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
@@ -0,0 +1,252 @@
+// file: src/events.rs
+// version: 0.9.1
+// guid: 8b1e5d42-7c39-4f0a-a6d2-3e94b0c7f516
+
+//! Lifecycle events for external automation: an NDJSON log (`--event-log`), MQTT and
+//! notifier plugins
+
+use std::cell::{Cell, RefCell};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::mqtt::{MqttClient, MqttConfig};
+use crate::plugins::Plugins;
+use crate::schema;
+use crate::style::Marker;
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    Scan {
+        root: String,
+        files: usize,
+        unreadable: usize,
+    },
+    Plan {
+        jobs: usize,
+        collisions: usize,
+    },
+    Start {
+        input: String,
+        output: String,
+        index: usize,
+        total: usize,
+        // The GPU the job is pinned to (`--gpu-devices`)
+        #[serde(skip_serializing_if = "Option::is_none")]
+        device: Option<String>,
+    },
+    Progress {
+        done: usize,
+        total: usize,
+        percent: usize,
+    },
+    Done {
+        input: String,
+        output: String,
+        seconds: f64,
+        output_bytes: u64,
+    },
+    Fail {
+        input: String,
+        output: String,
+        error: String,
+    },
+    // Where a long encode got to (`--snapshot-every`)
+    Snapshot {
+        input: String,
+        output: String,
+        elapsed_seconds: f64,
+        out_time_seconds: f64,
+        percent: Option<f64>,
+        bitrate_kbps: Option<f64>,
+        size_bytes: Option<u64>,
+        projected_bytes: Option<u64>,
+        eta_seconds: Option<f64>,
+    },
+    // An input was replaced after its output was made
+    SourceChanged {
+        input: String,
+        output: String,
+    },
+    BatchDone {
+        succeeded: usize,
+        failed: usize,
+    },
+    Paused {
+        reason: String,
+    },
+    Resumed,
+}
+
+impl Event {
+    // Matches the serialized `event` tag; used as the MQTT topic suffix
+    fn name(&self) -> &'static str {
+        match self {
+            Event::Scan { .. } => "scan",
+            Event::Plan { .. } => "plan",
+            Event::Start { .. } => "start",
+            Event::Progress { .. } => "progress",
+            Event::Done { .. } => "done",
+            Event::Fail { .. } => "fail",
+            Event::Snapshot { .. } => "snapshot",
+            Event::SourceChanged { .. } => "source_changed",
+            Event::BatchDone { .. } => "batch_done",
+            Event::Paused { .. } => "paused",
+            Event::Resumed => "resumed",
+        }
+    }
+}
+
+// One line of the log: a timestamp and the writing process around the event itself
+#[derive(Serialize)]
+struct Record<'a> {
+    schema_version: u32,
+    ts_ms: u128,
+    pid: u32,
+    #[serde(flatten)]
+    event: &'a Event,
+}
+
+#[derive(Default)]
+pub struct Events {
+    log: Option<File>,
+    mqtt: RefCell<Option<(MqttConfig, MqttClient)>>,
+    // Shared with the runtime, which uses the same plugin processes for the rest
+    plugins: Arc<Plugins>,
+    warned: Cell<bool>,
+}
+
+impl Events {
+    // Open the log for appending and connect to the MQTT broker; `None` disables each.
+    // An unreachable broker only warns so home-automation outages never block encodes.
+    pub fn open(
+        path: Option<&Path>,
+        mqtt: Option<&MqttConfig>,
+        plugins: Arc<Plugins>,
+    ) -> Result<Self> {
+        let log = path
+            .map(|path| {
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .with_context(|| format!("failed to open event log {}", path.display()))
+            })
+            .transpose()?;
+        let mqtt = mqtt.and_then(|config| match MqttClient::connect(config) {
+            Ok(client) => Some((config.clone(), client)),
+            Err(e) => {
+                eprintln!("{} MQTT events disabled: {:#}", Marker::Warning, e);
+                None
+            }
+        });
+        Ok(Self {
+            log,
+            mqtt: RefCell::new(mqtt),
+            plugins,
+            warned: Cell::new(false),
+        })
+    }
+
+    // Append and publish one event. A failing sink must not fail the encode, so errors
+    // are only reported once on stderr.
+    pub fn emit(&self, event: Event) {
+        let mut mqtt = self.mqtt.borrow_mut();
+        if self.log.is_none() && mqtt.is_none() && self.plugins.is_empty() {
+            return;
+        }
+        let record = Record {
+            schema_version: schema::EVENTS,
+            ts_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis())
+                .unwrap_or(0),
+            pid: std::process::id(),
+            event: &event,
+        };
+        let mut line = match serde_json::to_vec(&record) {
+            Ok(line) => line,
+            Err(e) => return self.warn("could not encode event", &e),
+        };
+
+        if !self.plugins.is_empty() {
+            match serde_json::to_value(&record) {
+                Ok(value) => self.plugins.notify(&value),
+                Err(e) => self.warn("could not encode event", &e),
+            }
+        }
+
+        if let Some((config, client)) = mqtt.as_mut() {
+            if let Err(e) = client.publish(&config.topic(event.name()), &line) {
+                self.warn("could not publish MQTT event", &e);
+            }
+        }
+        if let Some(mut log) = self.log.as_ref() {
+            // One write per line keeps concurrent appenders from interleaving
+            line.push(b'\n');
+            if let Err(e) = log.write_all(&line) {
+                self.warn("could not write event log", &e);
+            }
+        }
+    }
+
+    fn warn(&self, what: &str, error: &dyn std::fmt::Display) {
+        if !self.warned.replace(true) {
+            eprintln!("{} {}: {}", Marker::Warning, what, error);
+        }
+    }
+}
+
+// Paths are logged lossily; the log is JSON and has to be valid UTF-8
+pub fn path_str(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+// The 25% step reached after `done` of `total` files, if it is past `last`
+pub fn milestone(done: usize, total: usize, last: usize) -> Option<usize> {
+    if total == 0 {
+        return None;
+    }
+    let step = done * 100 / total / 25 * 25;
+    (step > last).then_some(step)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_keep_their_schema() {
+        let event = Event::Done {
+            input: "/tv/a.mp4".to_string(),
+            output: "/out/a.mkv".to_string(),
+            seconds: 812.4,
+            output_bytes: 734_003_200,
+        };
+        let record = Record {
+            schema_version: schema::EVENTS,
+            ts_ms: 1_760_486_400_000,
+            pid: 4242,
+            event: &event,
+        };
+        schema::assert_fields(
+            &serde_json::to_value(&record).unwrap(),
+            1,
+            &[
+                "ts_ms",
+                "pid",
+                "event",
+                "input",
+                "output",
+                "seconds",
+                "output_bytes",
+            ],
+        );
+    }
+}
@@ -0,0 +1,198 @@
+// file: src/explain.rs
+// version: 0.1.0
+// guid: e824d57e-649c-497e-88db-c7d3bce2c469
+
+//! `explain`: the decisions `transcode` would make for one file, step by step.
+//!
+//! The transcode pipeline runs as a dry run with an `Explain` handed in, and reports
+//! each stage as it decides it: the probe, the output path, the preset and codecs, the
+//! stream policies, the stream plan, the filters and options each feature adds, the
+//! final ffmpeg command line and what the output should look like.
+
+use std::cell::Cell;
+use std::path::Path;
+
+use crate::probe::{MediaInfo, Stream};
+use crate::stats::clock;
+use crate::streams::StreamPlan;
+
+// ffmpeg flags whose values are filter graphs
+const FILTER_FLAGS: [&str; 5] = ["-vf", "-af", "-filter", "-filter_complex", "-lavfi"];
+
+pub struct Explain {
+    steps: Cell<usize>,
+}
+
+impl Explain {
+    pub fn new(input: &Path) -> Self {
+        println!("Explaining the transcode of {}", input.display());
+        Self {
+            steps: Cell::new(0),
+        }
+    }
+
+    // Print the next numbered step with its lines indented under it
+    pub fn step(&self, title: &str, lines: Vec<String>) {
+        let n = self.steps.get() + 1;
+        self.steps.set(n);
+        println!("\n{}. {}", n, title);
+        if lines.is_empty() {
+            println!("   (none)");
+        }
+        for line in lines {
+            println!("   {}", line);
+        }
+    }
+}
+
+pub fn probe_summary(info: Option<&MediaInfo>) -> Vec<String> {
+    let Some(info) = info else {
+        return vec!["ffprobe could not read the file; stream policies are skipped".to_string()];
+    };
+    let mut lines = vec![format!(
+        "Duration {}, {}",
+        info.format
+            .duration_seconds()
+            .map_or_else(|| "unknown".to_string(), clock),
+        info.format
+            .bit_rate()
+            .map_or_else(|| "bitrate unknown".to_string(), kbps),
+    )];
+    for kind in ["video", "audio", "subtitle"] {
+        for (n, stream) in info.streams.iter().filter(|s| s.is_type(kind)).enumerate() {
+            lines.push(format!("{} {}: {}", title_case(kind), n, describe(stream)));
+        }
+    }
+    lines
+}
+
+// `hevc 1920x1080 23.976 fps, eng, default`
+pub fn describe(stream: &Stream) -> String {
+    let mut parts = vec![
+        stream
+            .codec_name
+            .clone()
+            .unwrap_or_else(|| "unknown".into()),
+    ];
+    if let (Some(w), Some(h)) = (stream.width, stream.height) {
+        parts[0] = format!("{} {}x{}", parts[0], w, h);
+    }
+    if let Some(fps) = stream.frame_rate() {
+        parts[0] = format!("{} {:.3} fps", parts[0], fps);
+    }
+    if let Some(channels) = stream.channels {
+        parts.push(match &stream.channel_layout {
+            Some(layout) => format!("{} channels ({})", channels, layout),
+            None => format!("{} channels", channels),
+        });
+    }
+    parts.extend(stream.language().map(str::to_string));
+    parts.extend(stream.tag("title").map(|t| format!("\"{}\"", t)));
+    for flag in ["default", "forced", "attached_pic"] {
+        if stream.has_disposition(flag) {
+            parts.push(flag.to_string());
+        }
+    }
+    parts.join(", ")
+}
+
+// What the output should look like, from the probe and the plan. `adjusted` is the
+// frame size correction, if any; `trimmed` says silence trimming may shorten it further.
+pub fn predicted(
+    output: &Path,
+    info: Option<&MediaInfo>,
+    plan: &StreamPlan,
+    vcodec: &str,
+    adjusted: Option<&str>,
+    speed: f64,
+    trimmed: bool,
+) -> Vec<String> {
+    let mut lines = vec![format!("File: {}", output.display())];
+    let video = info.and_then(MediaInfo::video_stream);
+    if let Some(video) = video {
+        let codec = match vcodec {
+            "copy" => format!(
+                "copy of {}",
+                video.codec_name.as_deref().unwrap_or("unknown")
+            ),
+            codec => codec.to_string(),
+        };
+        let size = match (adjusted, video.width, video.height) {
+            (Some(adjusted), _, _) => format!(", {}", adjusted),
+            (None, Some(w), Some(h)) => format!(", {}x{} unless the filters scale it", w, h),
+            _ => String::new(),
+        };
+        lines.push(format!("Video: {}{}", codec, size));
+    }
+    lines.push(format!(
+        "Audio: {} tracks, subtitles: {} tracks",
+        plan.audio().len(),
+        plan.subtitles().len()
+    ));
+    if let Some(duration) = info.and_then(|i| i.format.duration_seconds()) {
+        let note = if trimmed {
+            " before silence trimming"
+        } else {
+            ""
+        };
+        lines.push(format!("Duration: {}{}", clock(duration / speed), note));
+    }
+    lines
+}
+
+// The filter graphs in the final args, one `-vf ...` per line
+pub fn filters(extra: &[String]) -> Vec<String> {
+    extra
+        .windows(2)
+        .filter(|pair| {
+            FILTER_FLAGS
+                .iter()
+                .any(|flag| pair[0] == *flag || pair[0].starts_with(&format!("{}:", flag)))
+        })
+        .map(|pair| format!("{} {}", pair[0], pair[1]))
+        .collect()
+}
+
+fn kbps(bits: u64) -> String {
+    format!("{} kb/s", bits / 1000)
+}
+
+fn title_case(kind: &str) -> String {
+    let mut chars = kind.chars();
+    chars
+        .next()
+        .map(|c| c.to_ascii_uppercase().to_string() + chars.as_str())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn streams_and_filters_are_spelled_out() {
+        let info: MediaInfo = serde_json::from_value(serde_json::json!({
+            "streams": [
+                {"codec_type": "video", "codec_name": "hevc", "width": 1920, "height": 1080,
+                 "avg_frame_rate": "24000/1001"},
+                {"codec_type": "audio", "codec_name": "ac3", "channels": 6,
+                 "channel_layout": "5.1(side)", "tags": {"language": "eng"},
+                 "disposition": {"default": 1}}
+            ],
+            "format": {"duration": "1320.5", "bit_rate": "4000000"}
+        }))
+        .unwrap();
+        assert_eq!(
+            probe_summary(Some(&info)),
+            [
+                "Duration 00:22:00, 4000 kb/s",
+                "Video 0: hevc 1920x1080 23.976 fps",
+                "Audio 0: ac3, 6 channels (5.1(side)), eng, default",
+            ]
+        );
+        let extra: Vec<String> = ["-crf", "22", "-vf", "yadif", "-filter:a:0", "atempo=1.25"]
+            .map(String::from)
+            .into();
+        assert_eq!(filters(&extra), ["-vf yadif", "-filter:a:0 atempo=1.25"]);
+    }
+}
@@ -0,0 +1,434 @@
+// file: src/export.rs
+// version: 0.1.3
+// guid: 8bb35722-8a02-4487-968b-56163241fb9b
+
+//! `history export`: the encode history as CSV or Parquet, for spreadsheets and
+//! notebooks.
+//!
+//! Both formats have the same columns. The Parquet file is written directly: one row
+//! group, one uncompressed PLAIN-encoded data page per column, and the footer in the
+//! Thrift compact protocol. A history is small enough that nothing fancier pays off.
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+
+use crate::history::{History, Record};
+use crate::report::utc_timestamp;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    Csv,
+    Parquet,
+}
+
+const COLUMNS: [&str; 8] = [
+    "time_utc",
+    "input",
+    "output",
+    "status",
+    "preset",
+    "settings",
+    "seconds",
+    "output_bytes",
+];
+
+pub fn run(history: &History, format: ExportFormat, out: &Path) -> Result<()> {
+    let records = history.records();
+    let bytes = match format {
+        ExportFormat::Csv => csv(&records).into_bytes(),
+        ExportFormat::Parquet => parquet(&records),
+    };
+    if let Some(parent) = out.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create {}", parent.display()))?;
+    }
+    fs::write(out, bytes).with_context(|| format!("failed to write {}", out.display()))?;
+    println!("Exported {} records to {}", records.len(), out.display());
+    Ok(())
+}
+
+fn csv(records: &[Record]) -> String {
+    let mut text = COLUMNS.join(",");
+    text.push('\n');
+    for r in records {
+        let fields = [
+            utc_timestamp(r.ts_ms),
+            r.input.clone(),
+            r.output.clone(),
+            r.status.clone(),
+            r.preset.clone().unwrap_or_default(),
+            r.settings.clone(),
+            r.seconds.to_string(),
+            r.output_bytes.to_string(),
+        ];
+        let fields: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+        text.push_str(&fields.join(","));
+        text.push('\n');
+    }
+    text
+}
+
+// RFC 4180: quote fields with separators, quotes or line breaks; double inner quotes
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+// Parquet physical types, repetitions, converted types and encodings used here
+const INT64: i32 = 2;
+const DOUBLE: i32 = 5;
+const BYTE_ARRAY: i32 = 6;
+const REQUIRED: i32 = 0;
+const OPTIONAL: i32 = 1;
+const UTF8: i32 = 0;
+const TIMESTAMP_MILLIS: i32 = 9;
+const PLAIN: i32 = 0;
+const RLE: i32 = 3;
+
+enum Values {
+    Int64(Vec<i64>),
+    Double(Vec<f64>),
+    Text(Vec<Option<String>>),
+}
+
+struct Column {
+    name: &'static str,
+    converted: Option<i32>,
+    optional: bool,
+    values: Values,
+}
+
+impl Column {
+    fn physical(&self) -> i32 {
+        match self.values {
+            Values::Int64(_) => INT64,
+            Values::Double(_) => DOUBLE,
+            Values::Text(_) => BYTE_ARRAY,
+        }
+    }
+
+    // Definition levels (optional columns only), then the non-null values
+    fn page_data(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        match &self.values {
+            Values::Int64(values) => {
+                for v in values {
+                    data.extend(v.to_le_bytes());
+                }
+            }
+            Values::Double(values) => {
+                for v in values {
+                    data.extend(v.to_le_bytes());
+                }
+            }
+            Values::Text(values) => {
+                if self.optional {
+                    let levels = rle_levels(values.iter().map(Option::is_some));
+                    data.extend((levels.len() as u32).to_le_bytes());
+                    data.extend(levels);
+                }
+                for v in values.iter().flatten() {
+                    data.extend((v.len() as u32).to_le_bytes());
+                    data.extend(v.as_bytes());
+                }
+            }
+        }
+        data
+    }
+}
+
+fn columns(records: &[Record]) -> Vec<Column> {
+    let text = |f: fn(&Record) -> Option<String>| Values::Text(records.iter().map(f).collect());
+    let column = |name, converted, values| Column {
+        name,
+        converted,
+        optional: name == "preset",
+        values,
+    };
+    let values = [
+        Values::Int64(records.iter().map(|r| r.ts_ms as i64).collect()),
+        text(|r| Some(r.input.clone())),
+        text(|r| Some(r.output.clone())),
+        text(|r| Some(r.status.clone())),
+        text(|r| r.preset.clone()),
+        text(|r| Some(r.settings.clone())),
+        Values::Double(records.iter().map(|r| r.seconds).collect()),
+        Values::Int64(records.iter().map(|r| r.output_bytes as i64).collect()),
+    ];
+    COLUMNS
+        .into_iter()
+        .zip(values)
+        .map(|(name, values)| {
+            let converted = match (name, &values) {
+                ("time_utc", _) => Some(TIMESTAMP_MILLIS),
+                (_, Values::Text(_)) => Some(UTF8),
+                _ => None,
+            };
+            column(name, converted, values)
+        })
+        .collect()
+}
+
+fn parquet(records: &[Record]) -> Vec<u8> {
+    let rows = records.len() as i64;
+    let columns = columns(records);
+    let mut file = b"PAR1".to_vec();
+    // (offset, size) of each column chunk
+    let mut chunks = Vec::new();
+    for column in &columns {
+        let data = column.page_data();
+        let mut header = Compact::new();
+        header.i32(1, 0); // DATA_PAGE
+        header.i32(2, data.len() as i32);
+        header.i32(3, data.len() as i32);
+        header.begin(5);
+        header.i32(1, rows as i32);
+        header.i32(2, PLAIN);
+        header.i32(3, RLE);
+        header.i32(4, RLE);
+        header.end();
+        header.end();
+        chunks.push((file.len() as i64, (header.buf.len() + data.len()) as i64));
+        file.extend(header.buf);
+        file.extend(data);
+    }
+
+    let mut footer = Compact::new();
+    footer.i32(1, 1);
+    footer.list(2, STRUCT, columns.len() + 1);
+    footer.element();
+    footer.binary(4, b"schema");
+    footer.i32(5, columns.len() as i32);
+    footer.end();
+    for column in &columns {
+        footer.element();
+        footer.i32(1, column.physical());
+        footer.i32(3, if column.optional { OPTIONAL } else { REQUIRED });
+        footer.binary(4, column.name.as_bytes());
+        if let Some(converted) = column.converted {
+            footer.i32(6, converted);
+        }
+        footer.end();
+    }
+    footer.i64(3, rows);
+    // A row group needs rows; an empty history is a schema alone
+    footer.list(4, STRUCT, usize::from(rows > 0));
+    if rows > 0 {
+        footer.element();
+        footer.list(1, STRUCT, columns.len());
+        for (column, (offset, size)) in columns.iter().zip(&chunks) {
+            footer.element();
+            footer.i64(2, *offset);
+            footer.begin(3);
+            footer.i32(1, column.physical());
+            footer.list(2, I32, 2);
+            footer.zigzag(PLAIN.into());
+            footer.zigzag(RLE.into());
+            footer.list(3, BINARY, 1);
+            footer.bytes(column.name.as_bytes());
+            footer.i32(4, 0); // UNCOMPRESSED
+            footer.i64(5, rows);
+            footer.i64(6, *size);
+            footer.i64(7, *size);
+            footer.i64(9, *offset);
+            footer.end();
+            footer.end();
+        }
+        footer.i64(2, chunks.iter().map(|(_, size)| size).sum());
+        footer.i64(3, rows);
+        footer.end();
+    }
+    footer.binary(
+        6,
+        concat!("transcoderr ", env!("CARGO_PKG_VERSION")).as_bytes(),
+    );
+    footer.end();
+
+    file.extend(&footer.buf);
+    file.extend((footer.buf.len() as u32).to_le_bytes());
+    file.extend(b"PAR1");
+    file
+}
+
+// Bit width 1 levels as RLE runs: a run length header, then the level in one byte
+fn rle_levels(present: impl Iterator<Item = bool>) -> Vec<u8> {
+    let mut runs: Vec<(bool, u64)> = Vec::new();
+    for level in present {
+        match runs.last_mut() {
+            Some((value, count)) if *value == level => *count += 1,
+            _ => runs.push((level, 1)),
+        }
+    }
+    let mut out = Compact::new();
+    for (value, count) in runs {
+        out.varint(count << 1);
+        out.buf.push(u8::from(value));
+    }
+    out.buf
+}
+
+// Thrift compact protocol types
+const I32: u8 = 5;
+const I64: u8 = 6;
+const BINARY: u8 = 8;
+const LIST: u8 = 9;
+const STRUCT: u8 = 12;
+
+// A Thrift compact protocol writer, enough for Parquet's page headers and footer.
+// Field ids are written as deltas from the previous field of the same struct.
+struct Compact {
+    buf: Vec<u8>,
+    // Last field id of each open struct
+    last: Vec<i16>,
+}
+
+impl Compact {
+    fn new() -> Self {
+        Self {
+            buf: Vec::new(),
+            last: vec![0],
+        }
+    }
+
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.buf.push((value as u8 & 0x7f) | 0x80);
+            value >>= 7;
+        }
+        self.buf.push(value as u8);
+    }
+
+    fn zigzag(&mut self, value: i64) {
+        self.varint(((value << 1) ^ (value >> 63)) as u64);
+    }
+
+    fn bytes(&mut self, value: &[u8]) {
+        self.varint(value.len() as u64);
+        self.buf.extend(value);
+    }
+
+    fn field(&mut self, id: i16, kind: u8) {
+        let last = self.last.last_mut().expect("open struct");
+        let delta = id - *last;
+        *last = id;
+        if (1..=15).contains(&delta) {
+            self.buf.push((delta as u8) << 4 | kind);
+        } else {
+            self.buf.push(kind);
+            self.zigzag(id.into());
+        }
+    }
+
+    fn i32(&mut self, id: i16, value: i32) {
+        self.field(id, I32);
+        self.zigzag(value.into());
+    }
+
+    fn i64(&mut self, id: i16, value: i64) {
+        self.field(id, I64);
+        self.zigzag(value);
+    }
+
+    fn binary(&mut self, id: i16, value: &[u8]) {
+        self.field(id, BINARY);
+        self.bytes(value);
+    }
+
+    fn list(&mut self, id: i16, kind: u8, len: usize) {
+        self.field(id, LIST);
+        if len < 15 {
+            self.buf.push((len as u8) << 4 | kind);
+        } else {
+            self.buf.push(0xf0 | kind);
+            self.varint(len as u64);
+        }
+    }
+
+    // A struct-valued field
+    fn begin(&mut self, id: i16) {
+        self.field(id, STRUCT);
+        self.last.push(0);
+    }
+
+    // A struct element of a list
+    fn element(&mut self) {
+        self.last.push(0);
+    }
+
+    // Close the innermost struct
+    fn end(&mut self) {
+        self.buf.push(0);
+        self.last.pop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(input: &str, preset: Option<&str>) -> Record {
+        Record {
+            schema_version: 1,
+            ts_ms: 1_792_056_600_000,
+            input: input.to_string(),
+            output: "/out/a.mkv".to_string(),
+            status: "done".to_string(),
+            settings: "0123456789abcdef".to_string(),
+            preset: preset.map(str::to_string),
+            seconds: 12.5,
+            output_bytes: 1024,
+            adjustments: Vec::new(),
+            source: None,
+        }
+    }
+
+    #[test]
+    fn csv_quotes_awkward_paths() {
+        let text = csv(&[record("/in/a, \"b\".mkv", None)]);
+        assert_eq!(
+            text,
+            "time_utc,input,output,status,preset,settings,seconds,output_bytes\n\
+             2026-10-15 09:30:00,\"/in/a, \"\"b\"\".mkv\",/out/a.mkv,done,,0123456789abcdef,12.5,1024\n"
+        );
+    }
+
+    #[test]
+    fn parquet_has_magic_footer_and_plain_pages() {
+        let records = [
+            record("/in/a.mkv", Some("anime")),
+            record("/in/b.mkv", None),
+        ];
+        let file = parquet(&records);
+        assert_eq!(&file[..4], b"PAR1");
+        assert_eq!(&file[file.len() - 4..], b"PAR1");
+        let len = u32::from_le_bytes(file[file.len() - 8..file.len() - 4].try_into().unwrap());
+        let footer = &file[file.len() - 8 - len as usize..file.len() - 8];
+        // version = 1, then the schema list of nine structs
+        assert_eq!(&footer[..4], [0x15, 0x02, 0x19, 0x9c]);
+        // The first page: DATA_PAGE, sizes, then the first timestamp as PLAIN int64
+        assert_eq!(&file[4..6], [0x15, 0x00]);
+        let first = 1_792_056_600_000i64.to_le_bytes();
+        assert!(file.windows(8).any(|w| w == first));
+        // One level run of 1 (present), one of 0 (null), then only the present value
+        assert_eq!(
+            rle_levels([true, false].into_iter()),
+            [0x02, 0x01, 0x02, 0x00]
+        );
+        assert_eq!(file.windows(5).filter(|w| w == b"anime").count(), 1);
+    }
+
+    #[test]
+    fn compact_protocol_uses_long_form_for_field_gaps() {
+        let mut c = Compact::new();
+        c.i64(1, -1);
+        c.i32(20, 300);
+        c.end();
+        assert_eq!(c.buf, [0x16, 0x01, 0x05, 0x28, 0xd8, 0x04, 0x00]);
+    }
+}
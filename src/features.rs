@@ -0,0 +1,128 @@
+// file: src/features.rs
+// version: 0.1.0
+// guid: 8c41e6b2-5f3a-4d97-a0b8-1e7c29d4f635
+
+//! Optional subsystems: whether this build was compiled with each one (cargo features)
+//! and whether what it needs at run time is installed (`transcoderr features`).
+//!
+//! The default build has `vmaf` and `mqtt`; `--no-default-features` leaves both out for
+//! a minimal CLI, and `--features full` adds `mediainfo` as well. Using a subsystem
+//! the build lacks fails up front, naming the feature to rebuild with, instead of
+//! partway through a batch.
+
+use anyhow::{Result, bail};
+
+use crate::style::Table;
+use crate::tools::{self, Tool};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    Vmaf,
+    Mqtt,
+    Mediainfo,
+}
+
+impl Feature {
+    const ALL: [Feature; 3] = [Feature::Vmaf, Feature::Mqtt, Feature::Mediainfo];
+
+    // The cargo feature
+    fn name(self) -> &'static str {
+        match self {
+            Feature::Vmaf => "vmaf",
+            Feature::Mqtt => "mqtt",
+            Feature::Mediainfo => "mediainfo",
+        }
+    }
+
+    fn purpose(self) -> &'static str {
+        match self {
+            Feature::Vmaf => "VMAF scoring",
+            Feature::Mqtt => "MQTT publishing",
+            Feature::Mediainfo => "mediainfo probing",
+        }
+    }
+
+    // Where it shows up
+    fn used_by(self) -> &'static str {
+        match self {
+            Feature::Vmaf => "batch --min-vmaf, tune-wizard --vmaf",
+            Feature::Mqtt => "[mqtt] in the config file",
+            Feature::Mediainfo => "info (Atmos, Dolby Vision, track delays)",
+        }
+    }
+
+    pub fn compiled(self) -> bool {
+        match self {
+            Feature::Vmaf => cfg!(feature = "vmaf"),
+            Feature::Mqtt => cfg!(feature = "mqtt"),
+            Feature::Mediainfo => cfg!(feature = "mediainfo"),
+        }
+    }
+
+    // What the feature needs installed, and whether it is there. An ffmpeg that cannot
+    // list its filters gets the benefit of the doubt.
+    fn requirement(self) -> Option<(&'static str, bool)> {
+        match self {
+            Feature::Vmaf => {
+                let listed =
+                    tools::output(Tool::Ffmpeg, &["-hide_banner".into(), "-filters".into()])
+                        .ok()
+                        .filter(|output| output.status.success());
+                let found = listed.is_none_or(|output| {
+                    String::from_utf8_lossy(&output.stdout).contains("libvmaf")
+                });
+                Some(("ffmpeg built with libvmaf", found))
+            }
+            Feature::Mqtt => None,
+            Feature::Mediainfo => {
+                let found = std::process::Command::new("mediainfo")
+                    .arg("--Version")
+                    .output()
+                    .is_ok_and(|output| output.status.success());
+                Some(("mediainfo on PATH", found))
+            }
+        }
+    }
+}
+
+// Fail unless `feature` is compiled in and what it needs is installed; `what` is the
+// option or setting that asked for it
+pub fn require(feature: Feature, what: &str) -> Result<()> {
+    if !feature.compiled() {
+        bail!(
+            "{} needs {}, which this build was compiled without (rebuild with `--features {}`)",
+            what,
+            feature.purpose(),
+            feature.name()
+        );
+    }
+    if let Some((needs, false)) = feature.requirement() {
+        bail!("{} needs {}, which was not found", what, needs);
+    }
+    Ok(())
+}
+
+pub fn list() -> Result<()> {
+    let mut table = Table::new(&["Feature", "Compiled in", "Needs", "Found", "Used by"]);
+    for feature in Feature::ALL {
+        let requirement = feature.requirement();
+        table.row(vec![
+            feature.name().to_string(),
+            yes_no(feature.compiled()).to_string(),
+            requirement.map_or("-", |(needs, _)| needs).to_string(),
+            requirement
+                .map_or("-", |(_, found)| yes_no(found))
+                .to_string(),
+            feature.used_by().to_string(),
+        ]);
+    }
+    print!("{}", table);
+    Ok(())
+}
+
+fn yes_no(value: bool) -> &'static str {
+    match value {
+        true => "yes",
+        false => "no",
+    }
+}
@@ -0,0 +1,267 @@
+// file: src/filters.rs
+// version: 0.1.0
+// guid: 70660535-42ef-4e1a-b920-66ca41717baf
+
+//! Filter chains for `-vf`, `-af` and `-filter:<stream>`, built from parts instead of
+//! pasted strings.
+//!
+//! A value inside a filtergraph is unescaped twice: once by the graph parser (where
+//! `,` `;` `[` `]` separate filters and labels) and once by the filter's option parser
+//! (where `:` separates options). `FilterGraph` escapes every value for both, so paths
+//! with drive letters, commas or quotes reach the filter intact.
+
+use std::fmt;
+use std::path::Path;
+
+#[derive(Debug, Clone, Default)]
+pub struct FilterGraph {
+    chain: Vec<Step>,
+    // Extra sources (`movie=`) for overlays, labelled `[ov<N>]`
+    sources: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+enum Step {
+    Filter(String),
+    // Overlay source `N` on the chain so far
+    Overlay { source: usize, x: String, y: String },
+}
+
+impl FilterGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // A filter with positional arguments: `name=a:b:c`
+    pub fn filter(&mut self, name: &str, args: &[&str]) -> &mut Self {
+        let args: Vec<String> = args.iter().map(|a| escape(a)).collect();
+        self.push(name, &args.join(":"))
+    }
+
+    // A filter with named options: `name=key=value:key=value`
+    pub fn filter_opts(&mut self, name: &str, opts: &[(&str, &str)]) -> &mut Self {
+        let opts: Vec<String> = opts
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, escape(value)))
+            .collect();
+        self.push(name, &opts.join(":"))
+    }
+
+    // A chain written by hand (presets, `crop` in shows.toml), kept exactly as given
+    pub fn raw(&mut self, chain: &str) -> &mut Self {
+        if !chain.is_empty() {
+            self.chain.push(Step::Filter(chain.to_string()));
+        }
+        self
+    }
+
+    // Append every step of `other`, renumbering its overlay sources
+    pub fn append(&mut self, other: &FilterGraph) -> &mut Self {
+        let offset = self.sources.len();
+        for (i, source) in other.sources.iter().enumerate() {
+            let label = format!("[ov{}]", i);
+            let body = source.strip_suffix(&label).unwrap_or(source);
+            self.sources.push(format!("{}[ov{}]", body, offset + i));
+        }
+        self.chain.extend(other.chain.iter().map(|step| match step {
+            Step::Overlay { source, x, y } => Step::Overlay {
+                source: source + offset,
+                x: x.clone(),
+                y: y.clone(),
+            },
+            filter => filter.clone(),
+        }));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chain.is_empty()
+    }
+
+    fn push(&mut self, name: &str, args: &str) -> &mut Self {
+        self.chain.push(Step::Filter(if args.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}={}", name, args)
+        }));
+        self
+    }
+}
+
+// The stock filters features build on; not every one has a caller yet
+#[allow(dead_code)]
+impl FilterGraph {
+    // `-2` for either side keeps the aspect ratio at an even size
+    pub fn scale(&mut self, width: i32, height: i32) -> &mut Self {
+        self.filter("scale", &[&width.to_string(), &height.to_string()])
+    }
+
+    pub fn crop(&mut self, width: u32, height: u32, x: u32, y: u32) -> &mut Self {
+        let args = [width, height, x, y].map(|n| n.to_string());
+        self.filter("crop", &args.each_ref().map(String::as_str))
+    }
+
+    // HDR to SDR with a tonemap operator (`hable`, `mobius`, `reinhard`): through linear
+    // light in float, then back to 8-bit BT.709. Needs ffmpeg built with zscale.
+    pub fn tonemap(&mut self, operator: &str) -> &mut Self {
+        self.raw("zscale=t=linear:npl=100,format=gbrpf32le")
+            .filter_opts("tonemap", &[("tonemap", operator), ("desat", "0")])
+            .raw("zscale=p=bt709:t=bt709:m=bt709:r=tv,format=yuv420p")
+    }
+
+    // Burn in a subtitle file
+    pub fn subtitles(&mut self, path: &Path) -> &mut Self {
+        self.filter_opts("subtitles", &[("filename", &filter_path(path))])
+    }
+
+    // Draw an image over the video; `x`/`y` are overlay expressions (`W-w-10`)
+    pub fn overlay(&mut self, image: &Path, x: &str, y: &str) -> &mut Self {
+        let source = self.sources.len();
+        self.sources.push(format!(
+            "movie=filename={}[ov{}]",
+            escape(&filter_path(image)),
+            source
+        ));
+        self.chain.push(Step::Overlay {
+            source,
+            x: escape(x),
+            y: escape(y),
+        });
+        self
+    }
+
+    // EBU R128 loudness normalization (single pass)
+    pub fn loudnorm(&mut self, integrated: f64, true_peak: f64, range: f64) -> &mut Self {
+        self.filter_opts(
+            "loudnorm",
+            &[
+                ("I", &integrated.to_string()),
+                ("TP", &true_peak.to_string()),
+                ("LRA", &range.to_string()),
+            ],
+        )
+    }
+}
+
+// Sources first, then the chain. An overlay closes the chain so far into a label and
+// starts a new one from that label and its source.
+impl fmt::Display for FilterGraph {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for source in &self.sources {
+            write!(f, "{};", source)?;
+        }
+        let mut head = String::new();
+        let mut segment: Vec<String> = Vec::new();
+        let mut labels = 0;
+        for step in &self.chain {
+            match step {
+                Step::Filter(filter) => segment.push(filter.clone()),
+                Step::Overlay { source, x, y } => {
+                    if !segment.is_empty() {
+                        write!(f, "{}{}[v{}];", head, segment.join(","), labels)?;
+                        head = format!("[v{}]", labels);
+                        labels += 1;
+                        segment.clear();
+                    } else if head.is_empty() {
+                        head = "[in]".to_string();
+                    }
+                    head.push_str(&format!("[ov{}]", source));
+                    segment.push(format!("overlay={}:{}", x, y));
+                }
+            }
+        }
+        write!(f, "{}{}", head, segment.join(","))
+    }
+}
+
+// Append `graph` to the `flag` chain in `extra` (`-vf`, `-af`, `-filter:a:0`). ffmpeg
+// keeps only the last of each, so an existing one is extended instead of repeated.
+pub fn add_filter(extra: &mut Vec<String>, flag: &str, graph: &FilterGraph) {
+    if graph.is_empty() {
+        return;
+    }
+    match extra.iter().rposition(|arg| arg == flag) {
+        Some(pos) if pos + 1 < extra.len() => {
+            let chained = FilterGraph::new()
+                .raw(&extra[pos + 1])
+                .append(graph)
+                .to_string();
+            extra[pos + 1] = chained;
+        }
+        _ => extra.extend([flag.to_string(), graph.to_string()]),
+    }
+}
+
+// Escape a value for the option parser, then the result for the graph parser
+fn escape(value: &str) -> String {
+    let option = escape_chars(value, &['\\', '\'', ':']);
+    escape_chars(&option, &['\\', '\'', '[', ']', ',', ';'])
+}
+
+fn escape_chars(value: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if special.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// Filters read '/' on every platform; Windows separators would need escaping twice
+pub fn filter_path(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_are_escaped_for_both_parsers() {
+        let mut graph = FilterGraph::new();
+        graph
+            .raw("crop=1920:800:0:140")
+            .scale(1280, -2)
+            .subtitles(Path::new(r"C:\Subs\it's, [1].srt"))
+            .loudnorm(-16.0, -1.5, 11.0);
+        assert_eq!(
+            graph.to_string(),
+            r"crop=1920:800:0:140,scale=1280:-2,subtitles=filename=C\\:/Subs/it\\\'s\, \[1\].srt,loudnorm=I=-16:TP=-1.5:LRA=11"
+        );
+
+        let mut extra = vec!["-vf".to_string(), "hqdn3d".to_string()];
+        add_filter(&mut extra, "-vf", &graph);
+        assert_eq!(extra.len(), 2);
+        assert!(extra[1].starts_with("hqdn3d,crop="));
+    }
+
+    #[test]
+    fn overlays_split_the_chain_with_labels() {
+        let mut graph = FilterGraph::new();
+        graph
+            .scale(1280, -2)
+            .overlay(Path::new("logo.png"), "W-w-10", "10")
+            .filter("fps", &["30"]);
+        assert_eq!(
+            graph.to_string(),
+            "movie=filename=logo.png[ov0];scale=1280:-2[v0];[v0][ov0]overlay=W-w-10:10,fps=30"
+        );
+
+        let mut only = FilterGraph::new();
+        only.overlay(Path::new("logo.png"), "0", "0");
+        assert_eq!(
+            only.to_string(),
+            "movie=filename=logo.png[ov0];[in][ov0]overlay=0:0"
+        );
+
+        // Chaining onto a preset's -vf keeps the overlay wired to the chain before it
+        let mut extra = vec!["-vf".to_string(), "hqdn3d".to_string()];
+        add_filter(&mut extra, "-vf", &only);
+        assert_eq!(
+            extra[1],
+            "movie=filename=logo.png[ov0];hqdn3d[v0];[v0][ov0]overlay=0:0"
+        );
+    }
+}
@@ -0,0 +1,177 @@
+// file: src/gpu.rs
+// version: 0.1.0
+// guid: 3e8a5c17-64d9-4b2f-9a01-c7f2d85e6b43
+
+//! `--gpu-devices`: spread hardware encodes over several GPUs, each ffmpeg run pinned to
+//! the device its job was given.
+//!
+//! A job takes the device with the fewest jobs on it right now, ties going to the one
+//! given out least so far, so jobs alternate between GPUs whether they run one after
+//! another or side by side. How a run is pinned depends on the encoder: NVENC (and
+//! CUDA decoding) sees only its device through `CUDA_VISIBLE_DEVICES`; VAAPI and QSV
+//! get it as `-vaapi_device`/`-qsv_device` ahead of the input. Software encoders are
+//! not pinned and take no device.
+
+use std::cell::Cell;
+
+use crate::tools::RunEnv;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pinning {
+    // The device index, through the environment
+    Cuda,
+    // The device (a render node such as /dev/dri/renderD129) as a global option
+    Option(&'static str),
+}
+
+fn pinning(vcodec: &str) -> Option<Pinning> {
+    if vcodec.ends_with("_nvenc") {
+        Some(Pinning::Cuda)
+    } else if vcodec.ends_with("_vaapi") {
+        Some(Pinning::Option("-vaapi_device"))
+    } else if vcodec.ends_with("_qsv") {
+        Some(Pinning::Option("-qsv_device"))
+    } else {
+        None
+    }
+}
+
+struct Device {
+    id: String,
+    active: Cell<usize>,
+    assigned: Cell<usize>,
+}
+
+pub struct GpuPool {
+    devices: Vec<Device>,
+}
+
+impl GpuPool {
+    // `None` when no devices are given
+    pub fn new(ids: &[String]) -> Option<Self> {
+        let devices: Vec<Device> = ids
+            .iter()
+            .map(|id| id.trim())
+            .filter(|id| !id.is_empty())
+            .map(|id| Device {
+                id: id.to_string(),
+                active: Cell::new(0),
+                assigned: Cell::new(0),
+            })
+            .collect();
+        (!devices.is_empty()).then_some(Self { devices })
+    }
+
+    // A device for a job encoding with `vcodec`, held until the lease is dropped;
+    // `None` for encoders that cannot be pinned
+    pub fn lease(&self, vcodec: &str) -> Option<Lease<'_>> {
+        let pinning = pinning(vcodec)?;
+        let device = self
+            .devices
+            .iter()
+            .min_by_key(|d| (d.active.get(), d.assigned.get()))?;
+        device.active.set(device.active.get() + 1);
+        device.assigned.set(device.assigned.get() + 1);
+        Some(Lease { device, pinning })
+    }
+
+    // `0: 3 jobs, 1: 2 jobs`, for the batch summary
+    pub fn summary(&self) -> Option<String> {
+        let used: Vec<String> = self
+            .devices
+            .iter()
+            .filter(|d| d.assigned.get() > 0)
+            .map(|d| format!("{}: {}", d.id, d.assigned.get()))
+            .collect();
+        (!used.is_empty()).then(|| used.join(", "))
+    }
+}
+
+pub struct Lease<'a> {
+    device: &'a Device,
+    pinning: Pinning,
+}
+
+impl Lease<'_> {
+    pub fn device(&self) -> &str {
+        &self.device.id
+    }
+
+    // Pin a run: the device wins over one a preset or show set
+    pub fn apply(&self, env: &mut RunEnv, input_opts: &mut Vec<String>) {
+        match self.pinning {
+            Pinning::Cuda => {
+                env.vars
+                    .insert("CUDA_VISIBLE_DEVICES".to_string(), self.device.id.clone());
+            }
+            Pinning::Option(option) => {
+                input_opts.splice(0..0, [option.to_string(), self.device.id.clone()]);
+            }
+        }
+    }
+
+    // `GPU 1 (CUDA_VISIBLE_DEVICES)`
+    pub fn describe(&self) -> String {
+        let how = match self.pinning {
+            Pinning::Cuda => "CUDA_VISIBLE_DEVICES",
+            Pinning::Option(option) => option,
+        };
+        format!("GPU {} ({})", self.device.id, how)
+    }
+}
+
+impl Drop for Lease<'_> {
+    fn drop(&mut self) {
+        self.device.active.set(self.device.active.get() - 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(ids: &[&str]) -> GpuPool {
+        GpuPool::new(&ids.iter().map(|id| id.to_string()).collect::<Vec<_>>()).unwrap()
+    }
+
+    #[test]
+    fn jobs_alternate_between_devices() {
+        let pool = pool(&["0", "1"]);
+        // One after another
+        let order: Vec<String> = (0..4)
+            .map(|_| pool.lease("hevc_nvenc").unwrap().device().to_string())
+            .collect();
+        assert_eq!(order, ["0", "1", "0", "1"]);
+        // Side by side: the busy device is passed over
+        let first = pool.lease("hevc_nvenc").unwrap();
+        let second = pool.lease("hevc_nvenc").unwrap();
+        assert_ne!(first.device(), second.device());
+        assert_eq!(pool.summary().as_deref(), Some("0: 3, 1: 3"));
+    }
+
+    #[test]
+    fn pins_by_encoder_family() {
+        let pool = pool(&["/dev/dri/renderD129"]);
+        assert!(pool.lease("libx265").is_none());
+
+        let (mut env, mut opts) = (RunEnv::default(), vec!["-fflags".to_string()]);
+        pool.lease("hevc_vaapi").unwrap().apply(&mut env, &mut opts);
+        assert!(env.is_empty());
+        assert_eq!(opts, ["-vaapi_device", "/dev/dri/renderD129", "-fflags"]);
+
+        let pool = self::pool(&["1"]);
+        let mut env = RunEnv::default();
+        env.vars
+            .insert("CUDA_VISIBLE_DEVICES".to_string(), "0".to_string());
+        let lease = pool.lease("h264_nvenc").unwrap();
+        lease.apply(&mut env, &mut Vec::new());
+        assert_eq!(env.describe(), "CUDA_VISIBLE_DEVICES=1");
+        assert_eq!(lease.describe(), "GPU 1 (CUDA_VISIBLE_DEVICES)");
+    }
+
+    #[test]
+    fn no_devices_means_no_pool() {
+        assert!(GpuPool::new(&[]).is_none());
+        assert!(GpuPool::new(&[" ".to_string()]).is_none());
+    }
+}
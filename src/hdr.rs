@@ -0,0 +1,428 @@
+// file: src/hdr.rs
+// version: 0.2.1
+// guid: 2ed80033-b1f3-4a9e-88ee-aa1a5a6fad29
+
+//! HDR metadata of the main video stream for `info`: the transfer and primaries, the
+//! mastering display (SMPTE ST 2086), content light levels (MaxCLL/MaxFALL), Dolby
+//! Vision configuration and HDR10+ dynamic metadata, spelled out in human terms.
+//!
+//! Matroska and MP4 carry most of this per stream; HEVC in MPEG-TS only carries it in
+//! the bitstream, so for PQ and HLG video the first frame's side data is read too.
+
+use std::ffi::OsString;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::probe::{MediaInfo, SideData, Stream, find_side_data};
+use crate::tools::{self, Tool};
+
+const MASTERING: &str = "Mastering display metadata";
+const LIGHT_LEVEL: &str = "Content light level metadata";
+const DOLBY_VISION: &str = "DOVI configuration record";
+const HDR10_PLUS: &str = "HDR Dynamic Metadata SMPTE2094-40 (HDR10+)";
+
+// Red, green and blue CIE 1931 xy coordinates of common gamuts
+const GAMUTS: [(&str, [(f64, f64); 3]); 3] = [
+    ("BT.709", [(0.640, 0.330), (0.300, 0.600), (0.150, 0.060)]),
+    (
+        "Display P3",
+        [(0.680, 0.320), (0.265, 0.690), (0.150, 0.060)],
+    ),
+    ("BT.2020", [(0.708, 0.292), (0.170, 0.797), (0.131, 0.046)]),
+];
+const WHITE_POINTS: [(&str, (f64, f64)); 2] = [("D65", (0.3127, 0.3290)), ("DCI", (0.314, 0.351))];
+// Coordinates are stored in steps of 0.00002; mastering tools round further
+const TOLERANCE: f64 = 0.002;
+// Dolby Vision levels 1 to 13: the largest size and frame rate each allows
+const DV_LEVELS: [&str; 13] = [
+    "720p24", "720p30", "1080p24", "1080p30", "1080p60", "2160p24", "2160p30", "2160p48",
+    "2160p60", "2160p120", "4320p60", "4320p120", "4320p120",
+];
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Hdr {
+    // e.g. ["Dolby Vision", "HDR10"]
+    pub formats: Vec<&'static str>,
+    pub transfer: Option<String>,
+    pub primaries: Option<String>,
+    pub matrix: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mastering_display: Option<MasteringDisplay>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_light_level: Option<LightLevel>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dolby_vision: Option<DolbyVision>,
+    // mediainfo's profile string, when the container has no configuration record
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dolby_vision_profile: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MasteringDisplay {
+    // xy coordinates
+    pub red: (f64, f64),
+    pub green: (f64, f64),
+    pub blue: (f64, f64),
+    pub white_point: (f64, f64),
+    // Nits (cd/m²)
+    pub min_luminance: f64,
+    pub max_luminance: f64,
+    // The named gamut and white point the coordinates match, if any
+    pub gamut: Option<&'static str>,
+    pub white: Option<&'static str>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LightLevel {
+    // Nits
+    pub max_cll: u32,
+    pub max_fall: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DolbyVision {
+    pub profile: u32,
+    pub level: u32,
+    // Base layer signal compatibility: 0 none, 1 HDR10, 2 SDR, 4 HLG, 6 UHD Blu-ray
+    pub compatibility: u32,
+    pub rpu: bool,
+    pub enhancement_layer: bool,
+    pub base_layer: bool,
+    pub description: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Frames {
+    #[serde(default)]
+    frames: Vec<Frame>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Frame {
+    #[serde(default)]
+    side_data_list: Vec<SideData>,
+}
+
+impl Hdr {
+    // The HDR metadata of `input`'s main video stream; None for SDR video or none
+    pub fn of(input: &Path, info: &MediaInfo) -> Option<Self> {
+        let stream = info.video_stream()?;
+        let frame = match is_hdr_transfer(stream) || stream.side_data(DOLBY_VISION).is_some() {
+            true => first_frame_side_data(input),
+            false => Vec::new(),
+        };
+        Self::from_side_data(stream, &frame)
+    }
+
+    fn from_side_data(stream: &Stream, frame: &[SideData]) -> Option<Self> {
+        // Stream side data first; the frame's fills in what the container left out
+        let find = |kind: &str| {
+            stream
+                .side_data(kind)
+                .or_else(|| find_side_data(frame, kind))
+        };
+        let mastering_display = find(MASTERING).and_then(mastering_display);
+        let content_light_level = find(LIGHT_LEVEL).and_then(|s| {
+            Some(LightLevel {
+                max_cll: s.number("max_content")? as u32,
+                max_fall: s.number("max_average")? as u32,
+            })
+        });
+        let dolby_vision = find(DOLBY_VISION).and_then(dolby_vision);
+        let dolby_vision_profile = match dolby_vision {
+            Some(_) => None,
+            None => stream.dolby_vision.clone(),
+        };
+        let transfer = stream.color_transfer.as_deref();
+        let mut formats = Vec::new();
+        if dolby_vision.is_some() || dolby_vision_profile.is_some() {
+            formats.push("Dolby Vision");
+        }
+        if find(HDR10_PLUS).is_some() {
+            formats.push("HDR10+");
+        }
+        match transfer {
+            Some("smpte2084") if mastering_display.is_some() || content_light_level.is_some() => {
+                formats.push("HDR10")
+            }
+            Some("smpte2084") => formats.push("PQ"),
+            Some("arib-std-b67") => formats.push("HLG"),
+            _ => {}
+        }
+        if formats.is_empty() && mastering_display.is_none() && content_light_level.is_none() {
+            return None;
+        }
+        Some(Self {
+            formats,
+            transfer: stream.color_transfer.clone(),
+            primaries: stream.color_primaries.clone(),
+            matrix: stream.color_space.clone(),
+            mastering_display,
+            content_light_level,
+            dolby_vision,
+            dolby_vision_profile,
+        })
+    }
+
+    // Indented lines for `info`'s human output
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec![format!(
+            "HDR: {}",
+            match self.formats.is_empty() {
+                true => "static metadata only".to_string(),
+                false => self.formats.join(", "),
+            }
+        )];
+        if let Some(transfer) = &self.transfer {
+            lines.push(format!("  Transfer: {}", transfer_name(transfer)));
+        }
+        if let Some(primaries) = &self.primaries {
+            lines.push(format!("  Primaries: {}", primaries_name(primaries)));
+        }
+        if let Some(m) = &self.mastering_display {
+            let xy = |(x, y): (f64, f64)| format!("{:.4},{:.4}", x, y);
+            let gamut = m.gamut.map_or_else(
+                || format!("R {} G {} B {}", xy(m.red), xy(m.green), xy(m.blue)),
+                |gamut| format!("{} primaries", gamut),
+            );
+            let white = m.white.map_or_else(
+                || format!("white point {}", xy(m.white_point)),
+                |white| format!("{} white point", white),
+            );
+            lines.push(format!(
+                "  Mastering display: {}, {}, {}-{} nits",
+                gamut,
+                white,
+                round(m.min_luminance),
+                round(m.max_luminance)
+            ));
+        }
+        if let Some(l) = &self.content_light_level {
+            lines.push(format!(
+                "  Content light level: MaxCLL {} nits, MaxFALL {} nits",
+                l.max_cll, l.max_fall
+            ));
+        }
+        if let Some(dv) = &self.dolby_vision {
+            let layers = [
+                (dv.base_layer, "base layer"),
+                (dv.enhancement_layer, "enhancement layer"),
+                (dv.rpu, "RPU"),
+            ]
+            .iter()
+            .filter(|(present, _)| *present)
+            .map(|(_, name)| *name)
+            .collect::<Vec<_>>()
+            .join(" + ");
+            let level = match DV_LEVELS.get((dv.level as usize).wrapping_sub(1)) {
+                Some(limit) => format!("level {} (up to {})", dv.level, limit),
+                None => format!("level {}", dv.level),
+            };
+            lines.push(format!(
+                "  Dolby Vision: profile {} ({}), {}; {}",
+                dv_profile(dv.profile, dv.compatibility),
+                dv.description,
+                level,
+                layers
+            ));
+        }
+        if let Some(profile) = &self.dolby_vision_profile {
+            lines.push(format!("  Dolby Vision: {} (per mediainfo)", profile));
+        }
+        lines
+    }
+}
+
+fn is_hdr_transfer(stream: &Stream) -> bool {
+    matches!(
+        stream.color_transfer.as_deref(),
+        Some("smpte2084" | "arib-std-b67")
+    )
+}
+
+fn mastering_display(side_data: &SideData) -> Option<MasteringDisplay> {
+    let xy = |name: &str| -> Option<(f64, f64)> {
+        Some((
+            side_data.number(&format!("{}_x", name))?,
+            side_data.number(&format!("{}_y", name))?,
+        ))
+    };
+    let (red, green, blue) = (xy("red")?, xy("green")?, xy("blue")?);
+    let white_point = xy("white_point")?;
+    let near = |a: (f64, f64), b: (f64, f64)| {
+        (a.0 - b.0).abs() <= TOLERANCE && (a.1 - b.1).abs() <= TOLERANCE
+    };
+    Some(MasteringDisplay {
+        red,
+        green,
+        blue,
+        white_point,
+        min_luminance: side_data.number("min_luminance")?,
+        max_luminance: side_data.number("max_luminance")?,
+        gamut: GAMUTS
+            .iter()
+            .find(|(_, [r, g, b])| near(red, *r) && near(green, *g) && near(blue, *b))
+            .map(|(name, _)| *name),
+        white: WHITE_POINTS
+            .iter()
+            .find(|(_, point)| near(white_point, *point))
+            .map(|(name, _)| *name),
+    })
+}
+
+fn dolby_vision(side_data: &SideData) -> Option<DolbyVision> {
+    let int = |key: &str| side_data.number(key).map(|n| n as u32);
+    let profile = int("dv_profile")?;
+    let compatibility = int("dv_bl_signal_compatibility_id").unwrap_or(0);
+    Some(DolbyVision {
+        profile,
+        level: int("dv_level").unwrap_or(0),
+        compatibility,
+        rpu: int("rpu_present_flag") == Some(1),
+        enhancement_layer: int("el_present_flag") == Some(1),
+        base_layer: int("bl_present_flag") == Some(1),
+        description: dv_description(profile, compatibility),
+    })
+}
+
+// "8.1" for profiles whose base layer has a fallback, else just the profile
+fn dv_profile(profile: u32, compatibility: u32) -> String {
+    match compatibility {
+        0 => profile.to_string(),
+        id => format!("{}.{}", profile, id),
+    }
+}
+
+fn dv_description(profile: u32, compatibility: u32) -> String {
+    let fallback = match compatibility {
+        1 => "HDR10-compatible",
+        2 => "SDR-compatible",
+        4 => "HLG-compatible",
+        6 => "UHD Blu-ray HDR10-compatible",
+        _ => "no fallback",
+    };
+    match profile {
+        4 => "dual layer, SDR-compatible base".to_string(),
+        5 => "single layer, no fallback (IPTPQc2)".to_string(),
+        7 => format!("dual layer, {} base", fallback),
+        8 => format!("single layer, {}", fallback),
+        9 => format!("AVC single layer, {}", fallback),
+        10 => format!("AV1 single layer, {}", fallback),
+        _ => "unrecognised profile".to_string(),
+    }
+}
+
+fn transfer_name(transfer: &str) -> String {
+    match transfer {
+        "smpte2084" => "PQ (SMPTE ST 2084)".to_string(),
+        "arib-std-b67" => "HLG (ARIB STD-B67)".to_string(),
+        "bt709" => "BT.709 (SDR)".to_string(),
+        other => other.to_string(),
+    }
+}
+
+fn primaries_name(primaries: &str) -> String {
+    match primaries {
+        "bt2020" => "BT.2020".to_string(),
+        "bt709" => "BT.709".to_string(),
+        "smpte432" => "Display P3".to_string(),
+        "smpte431" => "DCI-P3".to_string(),
+        other => other.to_string(),
+    }
+}
+
+// Luminances to at most four decimals, without trailing zeros
+fn round(nits: f64) -> f64 {
+    (nits * 10_000.0).round() / 10_000.0
+}
+
+// Best effort: a file whose first frame cannot be read just reports less
+fn first_frame_side_data(input: &Path) -> Vec<SideData> {
+    let mut args: Vec<OsString> = [
+        "-v",
+        "error",
+        "-select_streams",
+        "V:0",
+        "-read_intervals",
+        "%+#1",
+        "-show_entries",
+        "frame=side_data_list",
+        "-print_format",
+        "json",
+    ]
+    .map(OsString::from)
+    .into();
+    args.push(input.into());
+    tools::output(Tool::Ffprobe, &args)
+        .ok()
+        .filter(|o| o.status.success())
+        .and_then(|o| serde_json::from_slice::<Frames>(&o.stdout).ok())
+        .and_then(|f| f.frames.into_iter().next())
+        .map(|frame| frame.side_data_list)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stream(json: &str) -> Stream {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn dolby_vision_with_hdr10_metadata_is_spelled_out() {
+        let stream = stream(
+            r#"{"codec_type": "video", "color_transfer": "smpte2084",
+                "color_primaries": "bt2020", "color_space": "bt2020nc",
+                "side_data_list": [
+                    {"side_data_type": "DOVI configuration record", "dv_version_major": 1,
+                     "dv_profile": 8, "dv_level": 6, "rpu_present_flag": 1,
+                     "el_present_flag": 0, "bl_present_flag": 1,
+                     "dv_bl_signal_compatibility_id": 1},
+                    {"side_data_type": "Mastering display metadata",
+                     "red_x": "34000/50000", "red_y": "16000/50000",
+                     "green_x": "13250/50000", "green_y": "34500/50000",
+                     "blue_x": "7500/50000", "blue_y": "3000/50000",
+                     "white_point_x": "15635/50000", "white_point_y": "16450/50000",
+                     "min_luminance": "50/10000", "max_luminance": "10000000/10000"}]}"#,
+        );
+        // HEVC in MPEG-TS: the light levels are only in the frames
+        let frame: Vec<SideData> = serde_json::from_str(
+            r#"[{"side_data_type": "Content light level metadata",
+                 "max_content": 1000, "max_average": 400}]"#,
+        )
+        .unwrap();
+        let hdr = Hdr::from_side_data(&stream, &frame).unwrap();
+        assert_eq!(hdr.formats, ["Dolby Vision", "HDR10"]);
+        assert_eq!(
+            hdr.lines(),
+            [
+                "HDR: Dolby Vision, HDR10",
+                "  Transfer: PQ (SMPTE ST 2084)",
+                "  Primaries: BT.2020",
+                "  Mastering display: Display P3 primaries, D65 white point, 0.005-1000 nits",
+                "  Content light level: MaxCLL 1000 nits, MaxFALL 400 nits",
+                "  Dolby Vision: profile 8.1 (single layer, HDR10-compatible), \
+                 level 6 (up to 2160p24); base layer + RPU",
+            ]
+        );
+        let json = serde_json::to_value(&hdr).unwrap();
+        assert_eq!(json["content_light_level"]["max_cll"], 1000);
+        assert_eq!(json["mastering_display"]["red"][0], 0.68);
+        assert_eq!(json["dolby_vision"]["profile"], 8);
+    }
+
+    #[test]
+    fn sdr_video_has_none_and_bare_hlg_is_named() {
+        let sdr = stream(r#"{"codec_type": "video", "color_transfer": "bt709"}"#);
+        assert_eq!(Hdr::from_side_data(&sdr, &[]), None);
+        let hlg = stream(r#"{"codec_type": "video", "color_transfer": "arib-std-b67"}"#);
+        let hdr = Hdr::from_side_data(&hlg, &[]).unwrap();
+        assert_eq!(
+            hdr.lines()[..2],
+            ["HDR: HLG", "  Transfer: HLG (ARIB STD-B67)"]
+        );
+    }
+}
@@ -0,0 +1,355 @@
+// file: src/history.rs
+// version: 0.9.0
+// guid: de1a9472-987d-4905-8e6c-66f316c42c7f
+
+//! Encode history (`~/.local/share/transcoderr/history.ndjson`) and the settings hash
+//! that lets a batch tell which outputs were made with different settings.
+//!
+//! Every real encode appends one record. The settings hash is also written into the
+//! output itself (`transcoderr_settings` tag), so `--refresh-if-settings-changed` works
+//! for outputs made on other machines too. Records also fingerprint the input, so a
+//! source that was replaced since (re-downloaded, upgraded remux) is noticed as well.
+//! The same fingerprint catches the opposite too: an input about to be encoded again
+//! with settings it was already encoded with, after its output was moved elsewhere.
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use clap::{Subcommand, ValueEnum};
+use serde::{Deserialize, Serialize};
+
+use crate::export::ExportFormat;
+use crate::keyint::Keyint;
+use crate::presets::sha256_hex;
+use crate::probe;
+use crate::report::utc_timestamp;
+use crate::schema;
+use crate::silence::SilenceTrim;
+use crate::style::Marker;
+
+// Output metadata key holding the settings hash
+pub const SETTINGS_TAG: &str = "transcoderr_settings";
+
+#[derive(Subcommand, Debug)]
+pub enum HistoryAction {
+    /// Write every recorded encode to a CSV or Parquet file
+    Export {
+        /// Output format
+        #[arg(long, value_enum, default_value = "csv")]
+        format: ExportFormat,
+        /// File to write
+        #[arg(long, value_name = "FILE")]
+        out: PathBuf,
+    },
+}
+
+// What a refresh does with an up-to-date output whose source has changed since
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SourceChange {
+    Requeue,
+    Notify,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Record {
+    #[serde(default = "schema::unversioned")]
+    pub schema_version: u32,
+    pub ts_ms: u128,
+    pub input: String,
+    pub output: String,
+    // "done", "failed", or "rejected" (encoded, then discarded by `--min-vmaf`)
+    pub status: String,
+    pub settings: String,
+    #[serde(default)]
+    pub preset: Option<String>,
+    pub seconds: f64,
+    pub output_bytes: u64,
+    // Changes made to fit the input to the encoder, e.g. "padded 1919x1079 to 1920x1080"
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub adjustments: Vec<String>,
+    // The input's size and modification time when it was transcoded; see `fingerprint`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+impl Record {
+    pub fn new(input: &Path, output: &Path, settings: &str, preset: Option<&str>) -> Self {
+        Self {
+            schema_version: schema::HISTORY,
+            ts_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis())
+                .unwrap_or(0),
+            input: absolute(input),
+            output: absolute(output),
+            status: "failed".to_string(),
+            settings: settings.to_string(),
+            preset: preset.map(str::to_string),
+            seconds: 0.0,
+            output_bytes: 0,
+            adjustments: Vec::new(),
+            source: fingerprint(input),
+        }
+    }
+
+    pub fn adjusted(mut self, adjustment: Option<String>) -> Self {
+        self.adjustments.extend(adjustment);
+        self
+    }
+
+    pub fn finished(mut self, succeeded: bool, seconds: f64, output_bytes: u64) -> Self {
+        self.status = if succeeded { "done" } else { "failed" }.to_string();
+        self.seconds = seconds;
+        self.output_bytes = output_bytes;
+        self
+    }
+
+    pub fn rejected(mut self) -> Self {
+        self.status = "rejected".to_string();
+        self
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct History {
+    // None when there is nowhere to keep it
+    path: Option<PathBuf>,
+}
+
+impl History {
+    pub fn open(dir: Option<PathBuf>) -> Self {
+        Self {
+            path: dir.map(|dir| dir.join("history.ndjson")),
+        }
+    }
+
+    // Best effort: a history that cannot be written must not fail the encode it records
+    pub fn append(&self, record: &Record) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let Ok(mut line) = serde_json::to_string(record) else {
+            return;
+        };
+        line.push('\n');
+        let written = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|()| OpenOptions::new().create(true).append(true).open(path))
+            .and_then(|mut file| file.write_all(line.as_bytes()));
+        if let Err(e) = written {
+            eprintln!(
+                "{} cannot write encode history {}: {}",
+                Marker::Warning,
+                path.display(),
+                e
+            );
+        }
+    }
+
+    // All records, oldest first; unreadable lines are skipped
+    pub fn records(&self) -> Vec<Record> {
+        let Some(text) = self.path.as_ref().and_then(|p| fs::read_to_string(p).ok()) else {
+            return Vec::new();
+        };
+        text.lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect()
+    }
+}
+
+// Hash of everything that decides what an encode produces: codecs, the shared args
+// (preset, user and per-show extras) and option flags. Args derived from probing the
+// file are left out; they follow from the file itself.
+pub fn settings_hash(vcodec: &str, acodec: &str, extra: &[String], options: &[String]) -> String {
+    let mut text = format!("{}\0{}", vcodec, acodec);
+    for arg in extra {
+        text.push('\0');
+        text.push_str(arg);
+    }
+    text.push_str("\0\0");
+    text.push_str(&options.join("\0"));
+    sha256_hex(text.as_bytes())[..16].to_string()
+}
+
+// The options that change the encoded result, as `settings_hash` input
+pub fn encode_options(
+    preset: Option<&str>,
+    languages: &[String],
+    keyint: Option<Keyint>,
+    stabilize: bool,
+    stereo_compat: bool,
+    deterministic: bool,
+    silence: Option<&SilenceTrim>,
+) -> Vec<String> {
+    [
+        preset.map(|p| format!("preset={}", p)),
+        (!languages.is_empty()).then(|| format!("languages={}", languages.join(","))),
+        keyint.map(|k| format!("keyint={}", k)),
+        stabilize.then(|| "stabilize".to_string()),
+        stereo_compat.then(|| "stereo-compat".to_string()),
+        deterministic.then(|| "deterministic".to_string()),
+        silence.map(SilenceTrim::option),
+    ]
+    .into_iter()
+    .flatten()
+    .collect()
+}
+
+pub fn metadata_args(settings: &str) -> [String; 2] {
+    [
+        "-metadata".to_string(),
+        format!("{}={}", SETTINGS_TAG, settings),
+    ]
+}
+
+// The settings an existing output was made with: its own tag, or failing that the last
+// successful history record for it
+pub fn recorded_settings(output: &Path, history: &History) -> Option<String> {
+    if let Some(tag) = probe::probe(output)
+        .ok()
+        .and_then(|info| info.format.tag(SETTINGS_TAG).map(str::to_string))
+    {
+        return Some(tag);
+    }
+    let output = absolute(output);
+    history
+        .records()
+        .into_iter()
+        .rev()
+        .find(|r| r.output == output && r.status == "done")
+        .map(|r| r.settings)
+}
+
+// Whether `input` differs from the one the last successful encode to `output` read.
+// Records from before fingerprints were kept never count as changed.
+pub fn source_changed(input: &Path, output: &Path, history: &History) -> bool {
+    let output = absolute(output);
+    history
+        .records()
+        .into_iter()
+        .rev()
+        .find(|r| r.output == output && r.status == "done")
+        .and_then(|r| r.source)
+        .is_some_and(|recorded| Some(recorded) != fingerprint(input))
+}
+
+// The last successful encode of this input (by fingerprint, so wherever it was read
+// from) with these settings
+pub fn previous_encode(input: &Path, settings: &str, history: &History) -> Option<Record> {
+    let source = fingerprint(input)?;
+    history
+        .records()
+        .into_iter()
+        .rev()
+        .find(|r| r.status == "done" && r.settings == settings && r.source == Some(source.clone()))
+}
+
+// `already transcoded with these settings on 2026-10-15 09:30 UTC to /out/a.mkv`
+pub fn duplicate_warning(previous: &Record) -> String {
+    let moved = match Path::new(&previous.output).exists() {
+        true => "",
+        false => " (since moved or removed)",
+    };
+    format!(
+        "already transcoded with these settings on {} UTC to {}{}",
+        &utc_timestamp(previous.ts_ms)[..16],
+        previous.output,
+        moved
+    )
+}
+
+// An input's size and modification time, e.g. "1048576@1760486400123456789"
+fn fingerprint(input: &Path) -> Option<String> {
+    let meta = fs::metadata(input).ok()?;
+    let modified = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some(format!("{}@{}", meta.len(), modified.as_nanos()))
+}
+
+fn absolute(path: &Path) -> String {
+    std::path::absolute(path)
+        .unwrap_or_else(|_| path.to_path_buf())
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_tracks_settings_and_history_finds_outputs() {
+        let extra = vec!["-crf".to_string(), "18".to_string()];
+        let base = settings_hash("libx265", "aac", &extra, &[]);
+        assert_eq!(base.len(), 16);
+        assert_eq!(base, settings_hash("libx265", "aac", &extra, &[]));
+        assert_ne!(base, settings_hash("libx265", "aac", &[], &[]));
+        assert_ne!(
+            base,
+            settings_hash("libx265", "aac", &extra, &["deterministic".to_string()])
+        );
+
+        let temp = tempfile::tempdir().unwrap();
+        let history = History::open(Some(temp.path().to_path_buf()));
+        let output = temp.path().join("out.mkv");
+        history
+            .append(&Record::new(Path::new("in.mkv"), &output, "old", None).finished(true, 1.0, 1));
+        history
+            .append(&Record::new(Path::new("in.mkv"), &output, "new", None).finished(true, 1.0, 1));
+        history.append(&Record::new(Path::new("in.mkv"), &output, "bad", None));
+        assert_eq!(history.records().len(), 3);
+        assert_eq!(recorded_settings(&output, &history).as_deref(), Some("new"));
+    }
+
+    #[test]
+    fn previous_encodes_match_the_input_fingerprint_and_settings() {
+        let temp = tempfile::tempdir().unwrap();
+        let history = History::open(Some(temp.path().to_path_buf()));
+        let input = temp.path().join("in.mkv");
+        fs::write(&input, b"source").unwrap();
+        let moved = temp.path().join("moved.mkv");
+        history.append(&Record::new(&input, &moved, "abc", None).finished(true, 1.0, 1));
+        history.append(&Record::new(&input, &moved, "def", None));
+
+        let previous = previous_encode(&input, "abc", &history).unwrap();
+        assert!(
+            duplicate_warning(&previous).ends_with("moved.mkv (since moved or removed)"),
+            "{}",
+            duplicate_warning(&previous)
+        );
+        // Failed encodes and other settings do not count
+        assert!(previous_encode(&input, "def", &history).is_none());
+        // Nor does a replaced source
+        fs::write(&input, b"a new source").unwrap();
+        assert!(previous_encode(&input, "abc", &history).is_none());
+    }
+
+    #[test]
+    fn records_keep_their_schema() {
+        let record = Record::new(Path::new("in.mkv"), Path::new("out.mkv"), "abc", None)
+            .finished(true, 1.0, 1);
+        schema::assert_fields(
+            &serde_json::to_value(&record).unwrap(),
+            1,
+            &[
+                "ts_ms",
+                "input",
+                "output",
+                "status",
+                "settings",
+                "preset",
+                "seconds",
+                "output_bytes",
+            ],
+        );
+
+        // Lines written before versioning still read, as version 1
+        let old = r#"{"ts_ms":1,"input":"/in/a.mkv","output":"/out/a.mkv","status":"done",
+            "settings":"abc","seconds":1.0,"output_bytes":1}"#;
+        let record: Record = serde_json::from_str(old).unwrap();
+        assert_eq!(record.schema_version, 1);
+        assert_eq!(record.preset, None);
+    }
+}
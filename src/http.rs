@@ -0,0 +1,168 @@
+// file: src/http.rs
+// version: 0.2.0
+// guid: fe4e1eab-fff3-4a1f-b314-6245b622033c
+
+//! Just enough HTTP/1.1 for `serve`: one request per connection, a `Content-Length`
+//! body, and a response that closes the connection. No chunked bodies, keep-alive or
+//! TLS; put a reverse proxy in front for those.
+
+use std::io::{BufRead, Write};
+
+use anyhow::{Context, Result, bail};
+use serde::Serialize;
+
+// Larger bodies are refused rather than read
+const MAX_BODY: usize = 1 << 20;
+const MAX_HEADERS: usize = 100;
+
+#[derive(Debug)]
+pub struct Request {
+    pub method: String,
+    // Without the query string
+    pub path: String,
+    // Names lowercased
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    // The first header called `name`, which must be lowercase
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+pub fn read_request(from: &mut impl BufRead) -> Result<Request> {
+    let mut line = String::new();
+    from.read_line(&mut line)
+        .context("failed to read the request")?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target), Some(_version)) = (parts.next(), parts.next(), parts.next())
+    else {
+        bail!("malformed request line {:?}", line.trim_end());
+    };
+    let (method, path) = (
+        method.to_string(),
+        target.split('?').next().unwrap_or(target).to_string(),
+    );
+
+    let (mut length, mut headers) = (0usize, Vec::new());
+    for _ in 0..=MAX_HEADERS {
+        line.clear();
+        from.read_line(&mut line)
+            .context("failed to read the headers")?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            let mut body = vec![0; length];
+            from.read_exact(&mut body)
+                .context("failed to read the body")?;
+            return Ok(Request {
+                method,
+                path,
+                headers,
+                body,
+            });
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            let (name, value) = (name.trim().to_ascii_lowercase(), value.trim());
+            if name == "content-length" {
+                length = value.parse().context("bad Content-Length")?;
+                if length > MAX_BODY {
+                    bail!(
+                        "request body of {} bytes is over the {} limit",
+                        length,
+                        MAX_BODY
+                    );
+                }
+            }
+            headers.push((name, value.to_string()));
+        }
+    }
+    bail!("more than {} headers", MAX_HEADERS)
+}
+
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn json(status: u16, value: &impl Serialize) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            body: serde_json::to_vec(value).unwrap_or_default(),
+        }
+    }
+
+    // `{"error": message}`
+    pub fn error(status: u16, message: impl Into<String>) -> Self {
+        Self::json(status, &serde_json::json!({ "error": message.into() }))
+    }
+
+    pub fn write_to(&self, to: &mut impl Write) -> std::io::Result<()> {
+        write!(
+            to,
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+             Cache-Control: no-store\r\nConnection: close\r\n\r\n",
+            self.status,
+            reason(self.status),
+            self.content_type,
+            self.body.len()
+        )?;
+        to.write_all(&self.body)?;
+        to.flush()
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        415 => "Unsupported Media Type",
+        _ => "Internal Server Error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_a_request_with_its_body() {
+        let raw = b"POST /jobs?wait=1 HTTP/1.1\r\nHost: nas\r\ncontent-length: 4\r\n\r\n{}\r\nrest";
+        let request = read_request(&mut &raw[..]).unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/jobs");
+        assert_eq!(request.body, b"{}\r\n");
+        assert_eq!(request.header("host"), Some("nas"));
+        assert_eq!(request.header("content-length"), Some("4"));
+        assert_eq!(request.header("origin"), None);
+
+        assert!(read_request(&mut &b"nonsense\r\n\r\n"[..]).is_err());
+        let huge = b"POST / HTTP/1.1\r\nContent-Length: 999999999\r\n\r\n";
+        assert!(read_request(&mut &huge[..]).is_err());
+    }
+
+    #[test]
+    fn responses_close_the_connection() {
+        let mut out = Vec::new();
+        Response::error(404, "no job 7").write_to(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(
+            out.contains("Content-Length: 20\r\nCache-Control: no-store\r\nConnection: close\r\n")
+        );
+        assert!(out.ends_with("\r\n\r\n{\"error\":\"no job 7\"}"));
+    }
+}
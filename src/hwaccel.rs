@@ -0,0 +1,289 @@
+// file: src/hwaccel.rs
+// version: 0.1.0
+// guid: 7c1d4e92-5a3b-4f60-8e27-b94a0d6c35f1
+
+//! `--hwaccel`: encode on the GPU. The software encoder a preset or `--vcodec` names is
+//! swapped for the backend's encoder of the same format (`libx265` -> `hevc_nvenc`),
+//! decoding moves to the GPU with `-hwaccel`, and the quality args are respelled for
+//! it: `-crf` becomes NVENC's `-cq`, QSV's `-global_quality`, VAAPI's `-qp` or a
+//! VideoToolbox `-q:v` (higher is better there, so it is only a rough match), x264-style
+//! `-preset` names become NVENC's p1-p7, and options only the software encoders know
+//! (`-tune`, `-x265-params`, numeric SVT-AV1 presets) are dropped.
+//!
+//! A backend is usable when ffmpeg lists its encoders (`ffmpeg -encoders`) and, for
+//! `auto`, when it encodes a test frame: builds list NVENC whether or not there is an
+//! NVIDIA card. Copied video, encoders that are already hardware ones, and formats the
+//! backend has no encoder for are left alone.
+
+use std::ffi::OsString;
+
+use anyhow::{Result, bail};
+use clap::ValueEnum;
+
+use crate::tools::{self, Tool};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum HwAccel {
+    Auto,
+    Nvenc,
+    Qsv,
+    Vaapi,
+    Videotoolbox,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Nvenc,
+    Qsv,
+    Vaapi,
+    Videotoolbox,
+}
+
+// Tried in this order by `auto`; VideoToolbox is the only one on macOS
+const AUTO_ORDER: [Backend; 4] = [
+    Backend::Videotoolbox,
+    Backend::Nvenc,
+    Backend::Qsv,
+    Backend::Vaapi,
+];
+
+const SOFTWARE_ONLY: [&str; 6] = [
+    "-tune",
+    "-x264-params",
+    "-x265-params",
+    "-svtav1-params",
+    "-aom-params",
+    "-rav1e-params",
+];
+
+impl Backend {
+    pub fn name(self) -> &'static str {
+        match self {
+            Backend::Nvenc => "nvenc",
+            Backend::Qsv => "qsv",
+            Backend::Vaapi => "vaapi",
+            Backend::Videotoolbox => "videotoolbox",
+        }
+    }
+
+    // The backend's encoder for what `vcodec` encodes; None for copy, hardware
+    // encoders and formats it cannot encode
+    pub fn encoder(self, vcodec: &str) -> Option<String> {
+        let format = match vcodec {
+            "libx265" | "libkvazaar" | "hevc" => "hevc",
+            "libx264" | "libopenh264" | "h264" => "h264",
+            "libsvtav1" | "libaom-av1" | "librav1e" | "av1" => "av1",
+            _ => return None,
+        };
+        if self == Backend::Videotoolbox && format == "av1" {
+            return None;
+        }
+        Some(format!("{}_{}", format, self.name()))
+    }
+
+    // Decoding on the same device, ahead of the input
+    fn input_args(self) -> Vec<String> {
+        let args: &[&str] = match self {
+            Backend::Nvenc => &["-hwaccel", "cuda"],
+            Backend::Qsv => &["-hwaccel", "qsv"],
+            // The encoder takes the decoder's frames without leaving the GPU
+            Backend::Vaapi => &["-hwaccel", "vaapi", "-hwaccel_output_format", "vaapi"],
+            Backend::Videotoolbox => &["-hwaccel", "videotoolbox"],
+        };
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    // Swap `vcodec` for the backend's encoder and respell `extra` for it, adding the
+    // decoding args to `input_opts`; returns the new encoder, or None when untouched
+    pub fn apply(
+        self,
+        vcodec: &str,
+        extra: &mut Vec<String>,
+        input_opts: &mut Vec<String>,
+    ) -> Option<String> {
+        let encoder = self.encoder(vcodec)?;
+        let mut adapted = Vec::with_capacity(extra.len());
+        let mut args = extra.drain(..);
+        while let Some(arg) = args.next() {
+            if SOFTWARE_ONLY.contains(&arg.as_str()) {
+                args.next();
+                continue;
+            }
+            match arg.as_str() {
+                "-crf" => {
+                    let Some(crf) = args.next() else { break };
+                    adapted.extend(self.quality(&crf));
+                }
+                "-preset" => {
+                    let Some(preset) = args.next() else { break };
+                    if let Some(preset) = self.preset(&preset) {
+                        adapted.extend(["-preset".to_string(), preset]);
+                    }
+                }
+                _ => adapted.push(arg),
+            }
+        }
+        drop(args);
+        *extra = adapted;
+        input_opts.splice(0..0, self.input_args());
+        Some(encoder)
+    }
+
+    fn quality(self, crf: &str) -> Vec<String> {
+        match self {
+            Backend::Nvenc => vec!["-rc", "vbr", "-cq", crf, "-b:v", "0"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+            Backend::Qsv => vec!["-global_quality".to_string(), crf.to_string()],
+            Backend::Vaapi => vec!["-qp".to_string(), crf.to_string()],
+            Backend::Videotoolbox => match crf.parse::<f64>() {
+                Ok(crf) => {
+                    let q = (100.0 - 2.0 * crf).round().clamp(1.0, 100.0);
+                    vec!["-q:v".to_string(), q.to_string()]
+                }
+                Err(_) => Vec::new(),
+            },
+        }
+    }
+
+    // The backend's spelling of an x264-style preset; None drops it
+    fn preset(self, preset: &str) -> Option<String> {
+        const NAMES: [&str; 10] = [
+            "ultrafast",
+            "superfast",
+            "veryfast",
+            "faster",
+            "fast",
+            "medium",
+            "slow",
+            "slower",
+            "veryslow",
+            "placebo",
+        ];
+        let speed = NAMES.iter().position(|name| *name == preset)?;
+        match self {
+            Backend::Nvenc => Some(format!("p{}", [1, 1, 2, 3, 3, 4, 5, 6, 7, 7][speed])),
+            // QSV knows veryfast to veryslow
+            Backend::Qsv => Some(NAMES[speed.clamp(2, 8)].to_string()),
+            Backend::Vaapi | Backend::Videotoolbox => None,
+        }
+    }
+}
+
+// The backend `choice` stands for on this machine. An explicit one fails when ffmpeg
+// has none of its encoders; `auto` is None when no backend encodes a test frame.
+pub fn resolve(choice: HwAccel) -> Result<Option<Backend>> {
+    let listing = tools::output(Tool::Ffmpeg, &["-hide_banner".into(), "-encoders".into()])
+        .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())?;
+    let listed = |backend: Backend| {
+        let suffix = format!("_{}", backend.name());
+        listing
+            .lines()
+            .filter_map(|line| line.split_whitespace().nth(1))
+            .any(|name| name.ends_with(&suffix))
+    };
+    let backend = match choice {
+        HwAccel::Auto => {
+            return Ok(AUTO_ORDER
+                .into_iter()
+                .find(|backend| listed(*backend) && encodes(*backend)));
+        }
+        HwAccel::Nvenc => Backend::Nvenc,
+        HwAccel::Qsv => Backend::Qsv,
+        HwAccel::Vaapi => Backend::Vaapi,
+        HwAccel::Videotoolbox => Backend::Videotoolbox,
+    };
+    if !listed(backend) {
+        bail!(
+            "this ffmpeg has no {} encoders (see `ffmpeg -encoders`)",
+            backend.name()
+        );
+    }
+    Ok(Some(backend))
+}
+
+// Whether the backend's h264 encoder takes one synthetic frame
+fn encodes(backend: Backend) -> bool {
+    let mut args: Vec<&str> = vec!["-hide_banner", "-loglevel", "error"];
+    if backend == Backend::Vaapi {
+        args.extend(["-vaapi_device", "/dev/dri/renderD128"]);
+    }
+    args.extend(["-f", "lavfi", "-i", "color=black:s=256x256:d=0.1"]);
+    if backend == Backend::Vaapi {
+        args.extend(["-vf", "format=nv12,hwupload"]);
+    }
+    let encoder = format!("h264_{}", backend.name());
+    args.extend(["-frames:v", "1", "-c:v", &encoder, "-f", "null", "-"]);
+    let args: Vec<OsString> = args.into_iter().map(OsString::from).collect();
+    tools::output(Tool::Ffmpeg, &args).is_ok_and(|output| output.status.success())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn software_encoders_map_to_the_backend_of_the_same_format() {
+        assert_eq!(
+            Backend::Nvenc.encoder("libx265").as_deref(),
+            Some("hevc_nvenc")
+        );
+        assert_eq!(Backend::Qsv.encoder("libx264").as_deref(), Some("h264_qsv"));
+        assert_eq!(
+            Backend::Vaapi.encoder("libsvtav1").as_deref(),
+            Some("av1_vaapi")
+        );
+        assert_eq!(Backend::Videotoolbox.encoder("libsvtav1"), None);
+        assert_eq!(Backend::Nvenc.encoder("copy"), None);
+        assert_eq!(Backend::Nvenc.encoder("hevc_qsv"), None);
+        assert_eq!(Backend::Nvenc.encoder("libvpx-vp9"), None);
+    }
+
+    #[test]
+    fn quality_args_are_respelled_and_software_options_dropped() {
+        let preset = strings(&[
+            "-crf",
+            "18",
+            "-preset",
+            "slow",
+            "-tune",
+            "animation",
+            "-x265-params",
+            "aq-mode=3",
+            "-b:a",
+            "256k",
+        ]);
+        let (mut extra, mut input_opts) = (preset.clone(), strings(&["-fflags", "+genpts"]));
+        let encoder = Backend::Nvenc.apply("libx265", &mut extra, &mut input_opts);
+        assert_eq!(encoder.as_deref(), Some("hevc_nvenc"));
+        assert_eq!(
+            extra,
+            strings(&[
+                "-rc", "vbr", "-cq", "18", "-b:v", "0", "-preset", "p5", "-b:a", "256k"
+            ])
+        );
+        assert_eq!(
+            input_opts,
+            strings(&["-hwaccel", "cuda", "-fflags", "+genpts"])
+        );
+
+        let mut extra = preset.clone();
+        Backend::Videotoolbox.apply("libx265", &mut extra, &mut Vec::new());
+        assert_eq!(extra, strings(&["-q:v", "64", "-b:a", "256k"]));
+
+        // Untouched when there is nothing to swap
+        let mut extra = preset.clone();
+        let mut input_opts = Vec::new();
+        assert_eq!(
+            Backend::Qsv.apply("copy", &mut extra, &mut input_opts),
+            None
+        );
+        assert_eq!(extra, preset);
+        assert!(input_opts.is_empty());
+    }
+}
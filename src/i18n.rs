@@ -0,0 +1,294 @@
+// file: src/i18n.rs
+// version: 0.2.0
+// guid: b5bdec86-ed55-42d2-b157-7a7018103964
+
+//! Translated messages for people: run summaries and the errors `main` reports.
+//!
+//! Catalogs are Fluent files (`locales/<lang>.ftl`) built into the binary. Only the
+//! part of Fluent the catalogs use is read: `id = text` messages with `{ $name }`
+//! placeables, indented continuation lines, `#` comments, and selects on a count with
+//! a variant per line, exact numbers first, then the language's plural category:
+//!
+//! ```text
+//! issues-denied = Permission denied for { $count ->
+//!         [one] one path
+//!        *[other] { $count } paths
+//!     }:
+//! ```
+//!
+//! A message missing from a catalog falls back to English. `--porcelain` lines, JSON and the event log are for
+//! programs and are never translated.
+//!
+//! The language is `--lang`, else `lang` in the config, else the locale (`LC_ALL`,
+//! `LC_MESSAGES`, `LANG`); a locale with no catalog gets English.
+
+use std::collections::HashMap;
+use std::env;
+use std::sync::OnceLock;
+
+use anyhow::{Result, bail};
+
+const CATALOGS: [(&str, &str); 4] = [
+    ("en", include_str!("../locales/en.ftl")),
+    ("de", include_str!("../locales/de.ftl")),
+    ("es", include_str!("../locales/es.ftl")),
+    ("fr", include_str!("../locales/fr.ftl")),
+];
+
+static ACTIVE: OnceLock<Catalog> = OnceLock::new();
+static ENGLISH: OnceLock<Catalog> = OnceLock::new();
+
+struct Catalog {
+    lang: &'static str,
+    messages: HashMap<&'static str, String>,
+}
+
+impl Catalog {
+    fn load((lang, text): (&'static str, &'static str)) -> Self {
+        Self {
+            lang,
+            messages: parse(text),
+        }
+    }
+
+    fn get(&self, id: &str) -> Option<(&'static str, &str)> {
+        self.messages.get(id).map(|text| (self.lang, text.as_str()))
+    }
+}
+
+// Pick the catalog for this run. An explicit `lang` must have one; the locale need not.
+pub fn init(lang: Option<&str>) -> Result<()> {
+    let catalog = match lang {
+        Some(lang) => match find(lang) {
+            Some(catalog) => catalog,
+            None => bail!(t(
+                "error-unknown-lang",
+                &[
+                    ("lang", lang.to_string()),
+                    ("available", CATALOGS.map(|(lang, _)| lang).join(", ")),
+                ]
+            )),
+        },
+        None => find(&locale()).unwrap_or(CATALOGS[0]),
+    };
+    let _ = ACTIVE.set(Catalog::load(catalog));
+    Ok(())
+}
+
+// Message `id` in the active language with its `{ $name }` placeables filled in from
+// `args`, as in `t("batch-counts", &[("succeeded", 3.to_string()), ...])`
+pub fn t(id: &str, args: &[(&str, String)]) -> String {
+    let english = ENGLISH.get_or_init(|| Catalog::load(CATALOGS[0]));
+    let (lang, text) = ACTIVE
+        .get()
+        .and_then(|catalog| catalog.get(id))
+        .or_else(|| english.get(id))
+        .unwrap_or(("en", id));
+    format(text, args, lang)
+}
+
+// `de_DE.UTF-8` and `de-AT` find `de`
+fn find(lang: &str) -> Option<(&'static str, &'static str)> {
+    let language = lang
+        .split(['_', '-', '.', '@'])
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    CATALOGS.into_iter().find(|(lang, _)| *lang == language)
+}
+
+fn locale() -> String {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .find_map(|var| env::var(var).ok().filter(|value| !value.is_empty()))
+        .unwrap_or_default()
+}
+
+fn parse(text: &'static str) -> HashMap<&'static str, String> {
+    let mut messages: HashMap<&str, String> = HashMap::new();
+    let mut current = None;
+    for line in text.lines() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            current = None;
+            continue;
+        }
+        if line.starts_with([' ', '\t']) {
+            // A continuation of the message above
+            if let Some(value) = current.and_then(|id| messages.get_mut(id)) {
+                if !value.is_empty() {
+                    value.push('\n');
+                }
+                value.push_str(line.trim());
+            }
+            continue;
+        }
+        current = line.split_once('=').map(|(id, value)| {
+            let id = id.trim();
+            messages.insert(id, value.trim().to_string());
+            id
+        });
+    }
+    messages
+}
+
+// Replace each `{ $name }` with its argument and each select with its variant;
+// unknown names are left as they are
+fn format(text: &str, args: &[(&str, String)], lang: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('{') {
+        let Some(end) = closing(&rest[start..]).map(|end| start + end) else {
+            break;
+        };
+        out.push_str(&rest[..start]);
+        let placeable = &rest[start..=end];
+        let inner = &placeable[1..placeable.len() - 1];
+        match inner.split_once("->") {
+            Some((selector, variants)) => {
+                let variant = select(selector, variants, args, lang);
+                out.push_str(&format(variant, args, lang));
+            }
+            None => match args.iter().find(|(key, _)| *key == variable(inner)) {
+                Some((_, value)) => out.push_str(value),
+                None => out.push_str(placeable),
+            },
+        }
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+fn variable(expression: &str) -> &str {
+    expression.trim().trim_start_matches('$')
+}
+
+// Where the `{` that `text` starts with is closed, counting nested placeables
+fn closing(text: &str) -> Option<usize> {
+    let mut depth = 0usize;
+    for (i, c) in text.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+// The variant of `{ $name -> [key] text ... *[key] text }` for the argument: the one
+// keyed by its exact value, else by its plural category, else the `*` default
+fn select<'a>(selector: &str, variants: &'a str, args: &[(&str, String)], lang: &str) -> &'a str {
+    let value = args
+        .iter()
+        .find(|(key, _)| *key == variable(selector))
+        .map(|(_, value)| value.as_str());
+    let variants: Vec<(bool, &str, &str)> = variants
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim();
+            let (default, line) = match line.strip_prefix('*') {
+                Some(line) => (true, line),
+                None => (false, line),
+            };
+            let (key, text) = line.strip_prefix('[')?.split_once(']')?;
+            Some((default, key.trim(), text.trim()))
+        })
+        .collect();
+    let category = value.and_then(|value| plural(lang, value));
+    [value, category]
+        .into_iter()
+        .flatten()
+        .find_map(|wanted| variants.iter().find(|(_, key, _)| *key == wanted))
+        .or_else(|| variants.iter().find(|(default, ..)| *default))
+        .map_or("", |(_, _, text)| text)
+}
+
+// The CLDR plural category of a count in `lang`: French puts 0 with 1, the others only 1
+fn plural(lang: &str, value: &str) -> Option<&'static str> {
+    let n: f64 = value.parse().ok()?;
+    let one = match lang {
+        "fr" => (0.0..2.0).contains(&n),
+        _ => value == "1",
+    };
+    Some(if one { "one" } else { "other" })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_fill_placeables_and_span_lines() {
+        let messages = parse("# comment\nhello = Hi { $name }!\nlong =\n    one\n    two\n");
+        assert_eq!(messages["long"], "one\ntwo");
+        assert_eq!(
+            format(&messages["hello"], &[("name", "Ana".to_string())], "en"),
+            "Hi Ana!"
+        );
+        assert_eq!(format("{ $missing } left", &[], "en"), "{ $missing } left");
+        assert_eq!(find("de_DE.UTF-8").map(|c| c.0), Some("de"));
+        assert_eq!(find("pt_BR"), None);
+    }
+
+    #[test]
+    fn selects_pick_exact_values_then_plural_categories() {
+        let messages = parse(
+            "files = { $count ->\n        [0] no files\n        [one] one file\n       *[other] { $count } files\n    } left\n",
+        );
+        let files = |count: u32, lang: &str| {
+            format(&messages["files"], &[("count", count.to_string())], lang)
+        };
+        assert_eq!(files(0, "en"), "no files left");
+        assert_eq!(files(1, "en"), "one file left");
+        assert_eq!(files(7, "en"), "7 files left");
+        let no_exact = "{ $n ->\n[one] un\n*[other] des\n}";
+        assert_eq!(format(no_exact, &[("n", "0".to_string())], "fr"), "un");
+        assert_eq!(format(no_exact, &[("n", "0".to_string())], "de"), "des");
+        // Without the argument: the default
+        assert_eq!(format(no_exact, &[], "en"), "des");
+
+        let english = parse(CATALOGS[0].1);
+        let denied =
+            |count: &str| format(&english["issues-denied"], &[("count", count.into())], "en");
+        assert_eq!(denied("1"), "Permission denied for one path:");
+        assert_eq!(denied("3"), "Permission denied for 3 paths:");
+    }
+
+    #[test]
+    fn catalogs_match_english() {
+        let english = parse(CATALOGS[0].1);
+        // The variables a message uses, however its variants word them
+        let names = |text: &str| {
+            let mut names: Vec<String> = text
+                .split('$')
+                .skip(1)
+                .map(|p| {
+                    p.chars()
+                        .take_while(|c| c.is_alphanumeric() || *c == '-' || *c == '_')
+                        .collect()
+                })
+                .collect();
+            names.sort();
+            names.dedup();
+            names
+        };
+        for (lang, text) in &CATALOGS[1..] {
+            let messages = parse(text);
+            for (id, value) in &messages {
+                let source = english
+                    .get(id)
+                    .unwrap_or_else(|| panic!("{}: '{}' is not an English message", lang, id));
+                assert_eq!(names(value), names(source), "{}: '{}'", lang, id);
+            }
+            for id in english.keys() {
+                assert!(messages.contains_key(id), "{}: '{}' is missing", lang, id);
+            }
+        }
+    }
+}
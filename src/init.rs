@@ -0,0 +1,491 @@
+// file: src/init.rs
+// version: 0.2.2
+// guid: 1ce1f5a7-b3a1-4e53-a9c7-321216fe6fe2
+
+//! `init`: a first-run setup wizard for people who do not know ffmpeg's options.
+//!
+//! It looks for ffmpeg and the hardware encoders it was built with, asks a few
+//! questions (library folders, target codec, quality, whether to replace the originals
+//! or mirror them into another folder) and writes a commented config file whose
+//! `preset` is the recommendation. When no built-in preset fits the answers, one is
+//! written to the presets directory as well. The commands to run each library go into
+//! the file's comments and are printed at the end.
+//!
+//! Answers are read a line at a time from stdin, so the wizard can be scripted; an
+//! empty line or the end of input takes the default shown in brackets.
+
+use std::fmt::Write as _;
+use std::fs;
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result, bail};
+use clap::Args;
+
+use crate::presets::{self, Preset, Presets};
+use crate::prompt::Prompt;
+use crate::report::utc_timestamp;
+use crate::selftest::tool_version;
+use crate::style::Marker;
+use crate::tools::{self, Tool};
+use crate::whatif::WhatIf;
+
+const CODECS: [&str; 3] = ["h265", "h264", "av1"];
+const QUALITIES: [&str; 3] = ["smaller", "balanced", "best"];
+// Hardware encoders the wizard knows good quality settings for, per target codec.
+// VAAPI and AMF need device options that depend on the machine, so they are left out.
+const HARDWARE: [(&str, [&str; 3]); 3] = [
+    ("h265", ["hevc_nvenc", "hevc_qsv", "hevc_videotoolbox"]),
+    ("h264", ["h264_nvenc", "h264_qsv", "h264_videotoolbox"]),
+    ("av1", ["av1_nvenc", "av1_qsv", ""]),
+];
+
+#[derive(Args, Debug)]
+pub struct InitArgs {
+    /// Replace an existing config file
+    #[arg(long)]
+    pub force: bool,
+}
+
+// What was found on this machine
+struct Setup {
+    ffmpeg: Option<String>,
+    encoders: Vec<String>,
+}
+
+// The answers to the wizard's questions
+struct Answers {
+    libraries: Vec<(PathBuf, PathBuf)>,
+    codec: String,
+    quality: String,
+    hardware: Option<String>,
+    replace: bool,
+}
+
+// The preset the answers lead to; `preset` is set when it has to be written
+struct Choice {
+    name: String,
+    summary: String,
+    preset: Option<Preset>,
+}
+
+pub fn run(
+    args: &InitArgs,
+    path: Option<&Path>,
+    presets: &Presets,
+    presets_dir: Option<&Path>,
+    what_if: Option<&WhatIf>,
+) -> Result<()> {
+    let path = path.context("cannot locate the config directory; pass --config PATH")?;
+    if path.exists() && !args.force {
+        bail!(
+            "{} already exists; rerun with --force to replace it",
+            path.display()
+        );
+    }
+
+    let setup = detect();
+    match &setup.ffmpeg {
+        Some(version) => println!("Found {}", version),
+        None => println!(
+            "{} ffmpeg was not found on PATH; install it before transcoding",
+            Marker::Warning
+        ),
+    }
+    match setup.encoders.is_empty() {
+        true => println!("Hardware encoders: none found\n"),
+        false => println!("Hardware encoders: {}\n", setup.encoders.join(", ")),
+    }
+
+    let answers = ask(&mut Prompt::stdin(), &setup)?;
+    let choice = recommend(&answers, presets);
+    let text = render(&setup, &answers, &choice);
+
+    if let Some(preset) = &choice.preset {
+        let file = presets::save(presets, presets_dir, preset, "init", what_if)?;
+        if what_if.is_none() {
+            println!("\nWrote preset {}", file.display());
+        }
+    }
+    if let Some(what_if) = what_if {
+        what_if.write(path, path.exists());
+        return Ok(());
+    }
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    }
+    fs::write(path, &text).with_context(|| format!("failed to write {}", path.display()))?;
+    println!(
+        "\nWrote {} (preset {}: {})",
+        path.display(),
+        choice.name,
+        choice.summary
+    );
+    let commands = commands(&answers);
+    if !commands.is_empty() {
+        println!("Next, run:");
+        for command in commands {
+            println!("  {}", command);
+        }
+    }
+    Ok(())
+}
+
+fn detect() -> Setup {
+    let encoders = tools::output(Tool::Ffmpeg, &["-hide_banner".into(), "-encoders".into()])
+        .map(|output| hardware_encoders(&String::from_utf8_lossy(&output.stdout)))
+        .unwrap_or_default();
+    Setup {
+        ffmpeg: tool_version(Tool::Ffmpeg).ok(),
+        encoders,
+    }
+}
+
+// ` V....D hevc_nvenc           NVIDIA NVENC hevc encoder` -> `hevc_nvenc`
+fn hardware_encoders(listing: &str) -> Vec<String> {
+    let known: Vec<&str> = HARDWARE.iter().flat_map(|(_, names)| *names).collect();
+    listing
+        .lines()
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .filter(|name| !name.is_empty() && known.contains(name))
+        .map(str::to_string)
+        .collect()
+}
+
+fn ask<R: BufRead>(prompt: &mut Prompt<R>, setup: &Setup) -> Result<Answers> {
+    let mut folders = Vec::new();
+    loop {
+        let question = match folders.is_empty() {
+            true => "Library folder to transcode (empty to skip)",
+            false => "Another library folder (empty to finish)",
+        };
+        match prompt.ask(question, "")?.as_str() {
+            "" => break,
+            folder => folders.push(PathBuf::from(folder)),
+        }
+    }
+    let codec = prompt.choose("Target video codec", &CODECS, "h265")?;
+    let quality = prompt.choose("Quality preference", &QUALITIES, "balanced")?;
+    let available = HARDWARE
+        .iter()
+        .find(|(target, _)| *target == codec)
+        .and_then(|(_, names)| {
+            names
+                .iter()
+                .find(|name| setup.encoders.iter().any(|found| found == *name))
+        });
+    let hardware = match available {
+        Some(encoder) => {
+            let question = format!(
+                "Use the {} hardware encoder (much faster, larger files)",
+                encoder
+            );
+            match prompt.choose(&question, &["y", "n"], "n")?.as_str() {
+                "y" => Some(encoder.to_string()),
+                _ => None,
+            }
+        }
+        None => None,
+    };
+    let replace = match folders.is_empty() {
+        true => false,
+        false => {
+            let answer = prompt.choose(
+                "Replace the originals, or mirror the library into another folder",
+                &["mirror", "replace"],
+                "mirror",
+            )?;
+            answer == "replace"
+        }
+    };
+    let mut libraries = Vec::new();
+    for folder in folders {
+        let output = match replace {
+            true => folder.clone(),
+            false => {
+                let default = mirror_of(&folder, &codec);
+                let question = format!("Output folder for {}", folder.display());
+                PathBuf::from(prompt.ask(&question, &default.to_string_lossy())?)
+            }
+        };
+        libraries.push((folder, output));
+    }
+    Ok(Answers {
+        libraries,
+        codec,
+        quality,
+        hardware,
+        replace,
+    })
+}
+
+// `/media/tv` -> `/media/tv-h265`
+fn mirror_of(folder: &Path, codec: &str) -> PathBuf {
+    let name = folder
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "library".to_string());
+    folder.with_file_name(format!("{}-{}", name, codec))
+}
+
+fn recommend(answers: &Answers, presets: &Presets) -> Choice {
+    let quality = QUALITIES
+        .iter()
+        .position(|q| *q == answers.quality)
+        .unwrap_or(1);
+    // The built-ins cover software h265 at the two higher qualities
+    let builtin = match (answers.codec.as_str(), quality, &answers.hardware) {
+        ("h265", 1, None) => Some("tv-h265-fast"),
+        ("h265", 2, None) => Some("original-h265"),
+        _ => None,
+    };
+    if let Some(preset) = builtin.and_then(|name| presets.find(name)) {
+        return Choice {
+            name: preset.name.clone(),
+            summary: preset.description.clone().unwrap_or_default(),
+            preset: None,
+        };
+    }
+
+    // Constant-quality settings per encoder family, smaller to best
+    let (vcodec, video) = match answers.hardware.as_deref() {
+        Some(encoder) if encoder.ends_with("_nvenc") => (
+            encoder,
+            [
+                "-rc",
+                "vbr",
+                "-cq",
+                ["30", "26", "22"][quality],
+                "-b:v",
+                "0",
+                "-preset",
+                "p5",
+            ]
+            .to_vec(),
+        ),
+        Some(encoder) if encoder.ends_with("_qsv") => (
+            encoder,
+            [
+                "-global_quality",
+                ["28", "24", "20"][quality],
+                "-preset",
+                "slow",
+            ]
+            .to_vec(),
+        ),
+        Some(encoder) => (encoder, ["-q:v", ["50", "60", "70"][quality]].to_vec()),
+        None => match answers.codec.as_str() {
+            "h264" => (
+                "libx264",
+                ["-crf", ["24", "21", "18"][quality], "-preset", "slow"].to_vec(),
+            ),
+            "av1" => (
+                "libsvtav1",
+                ["-crf", ["36", "30", "24"][quality], "-preset", "6"].to_vec(),
+            ),
+            _ => (
+                "libx265",
+                ["-crf", ["26", "22", "18"][quality], "-preset", "medium"].to_vec(),
+            ),
+        },
+    };
+    let audio = ["128k", "160k", "256k"][quality];
+    let mut extra: Vec<String> = video.iter().map(|arg| arg.to_string()).collect();
+    extra.extend(["-b:a".to_string(), audio.to_string()]);
+    let summary = format!("{} {} with AAC {}", vcodec, video.join(" "), audio);
+    let name = match &answers.hardware {
+        Some(encoder) => format!("{}-{}", encoder.replace('_', "-"), answers.quality),
+        None => format!("{}-{}", answers.codec, answers.quality),
+    };
+    Choice {
+        name: name.clone(),
+        summary: summary.clone(),
+        preset: Some(Preset {
+            name,
+            description: Some(format!("Written by `transcoderr init`: {}", summary)),
+            aliases: Vec::new(),
+            vcodec: Some(vcodec.to_string()),
+            acodec: Some("aac".to_string()),
+            extra,
+            container: None,
+            containers: Vec::new(),
+            cwd: None,
+            env: Default::default(),
+        }),
+    }
+}
+
+// The commands that run the libraries with this setup
+fn commands(answers: &Answers) -> Vec<String> {
+    let mut commands = Vec::new();
+    for (input, output) in &answers.libraries {
+        commands.push(format!(
+            "transcoderr batch {} {}",
+            quote(input),
+            quote(output)
+        ));
+        if answers.replace {
+            commands.push(format!(
+                "transcoderr migrate-suffixed {} --swap",
+                quote(input)
+            ));
+        }
+    }
+    commands
+}
+
+fn quote(path: &Path) -> String {
+    let text = path.to_string_lossy();
+    match text.contains(|c: char| c.is_whitespace() || "'\"$`\\".contains(c)) {
+        true => format!("'{}'", text.replace('\'', r"'\''")),
+        false => text.into_owned(),
+    }
+}
+
+fn render(setup: &Setup, answers: &Answers, choice: &Choice) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0);
+    let mut text = String::new();
+    let _ = writeln!(
+        text,
+        "# transcoderr config, written by `transcoderr init` on {} UTC",
+        utc_timestamp(now)
+    );
+    let _ = writeln!(text, "#");
+    let _ = writeln!(
+        text,
+        "# ffmpeg: {}",
+        setup.ffmpeg.as_deref().unwrap_or("not found on PATH")
+    );
+    let _ = writeln!(
+        text,
+        "# Hardware encoders: {}",
+        match setup.encoders.is_empty() {
+            true => "none found".to_string(),
+            false => setup.encoders.join(", "),
+        }
+    );
+    if !answers.libraries.is_empty() {
+        let _ = writeln!(text, "#");
+        match answers.replace {
+            true => {
+                let _ = writeln!(
+                    text,
+                    "# Your libraries, replaced in place: batch writes `_transcoded` files next"
+                );
+                let _ = writeln!(
+                    text,
+                    "# to the originals, then migrate-suffixed swaps them in (originals go to"
+                );
+                let _ = writeln!(text, "# the trash):");
+            }
+            false => {
+                let _ = writeln!(
+                    text,
+                    "# Your libraries, mirrored into other folders (originals are kept):"
+                );
+            }
+        }
+        for command in commands(answers) {
+            let _ = writeln!(text, "#   {}", command);
+        }
+    }
+    let _ = writeln!(text);
+    let _ = writeln!(
+        text,
+        "# Preset for transcode and batch when --preset is not given ({} video,",
+        answers.codec
+    );
+    let _ = writeln!(text, "# {} quality): {}", answers.quality, choice.summary);
+    let _ = writeln!(text, "preset = \"{}\"", choice.name);
+    let _ = writeln!(text);
+    let _ = writeln!(text, "# More settings, all optional:");
+    let _ = writeln!(
+        text,
+        "# lang = \"de\"                            # summaries and errors in en, de, es or fr"
+    );
+    let _ = writeln!(
+        text,
+        "# event-log = \"/var/log/transcoderr.ndjson\" # lifecycle events for automation"
+    );
+    let _ = writeln!(text, "# subtitle-default = \"forced:eng, else none\"");
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn answer(lines: &str) -> Answers {
+        let setup = Setup {
+            ffmpeg: Some("ffmpeg version 7.0".to_string()),
+            encoders: vec!["hevc_nvenc".to_string()],
+        };
+        ask(&mut Prompt::new(lines.as_bytes()), &setup).unwrap()
+    }
+
+    #[test]
+    fn defaults_pick_a_builtin_preset_and_mirror() {
+        let answers = answer("/media/tv\n\n\n\n\n\n");
+        assert_eq!(
+            answers.libraries,
+            [(PathBuf::from("/media/tv"), PathBuf::from("/media/tv-h265"))]
+        );
+        assert!(!answers.replace && answers.hardware.is_none());
+        let choice = recommend(&answers, &Presets::default());
+        assert_eq!(choice.name, "tv-h265-fast");
+        assert!(choice.preset.is_none());
+        assert_eq!(
+            commands(&answers),
+            ["transcoderr batch /media/tv /media/tv-h265"]
+        );
+    }
+
+    #[test]
+    fn other_answers_write_a_preset() {
+        // A bad answer is asked again
+        let answers = answer("/media/my films\n\nh265\nbetter\nsmaller\ny\nreplace\n");
+        assert!(answers.replace);
+        let choice = recommend(&answers, &Presets::default());
+        assert_eq!(choice.name, "hevc-nvenc-smaller");
+        let preset = choice.preset.as_ref().unwrap();
+        assert_eq!(preset.vcodec.as_deref(), Some("hevc_nvenc"));
+        assert!(preset.extra.join(" ").contains("-cq 30"));
+        assert_eq!(
+            commands(&answers),
+            [
+                "transcoderr batch '/media/my films' '/media/my films'",
+                "transcoderr migrate-suffixed '/media/my films' --swap",
+            ]
+        );
+
+        let av1 = answer("\nav1\nbest\n");
+        assert_eq!(recommend(&av1, &Presets::default()).name, "av1-best");
+    }
+
+    #[test]
+    fn written_configs_load() {
+        let answers = answer("/media/tv\n\nh264\n");
+        let choice = recommend(&answers, &Presets::default());
+        let setup = Setup {
+            ffmpeg: None,
+            encoders: Vec::new(),
+        };
+        let text = render(&setup, &answers, &choice);
+        let config: Config = toml::from_str(&text).unwrap();
+        assert_eq!(config.preset.as_deref(), Some("h264-balanced"));
+        assert!(
+            text.contains("#   transcoderr batch /media/tv /media/tv-h264\n"),
+            "{}",
+            text
+        );
+        assert_eq!(
+            hardware_encoders(" V....D libx265  x265\n V....D hevc_nvenc  NVIDIA\n"),
+            ["hevc_nvenc"]
+        );
+    }
+}
@@ -0,0 +1,277 @@
+// file: src/inotify.rs
+// version: 0.2.0
+// guid: c09dfe4e-19b5-44c6-978f-e73048163a6d
+
+//! Linux filesystem notifications for `watch`: a directory tree watched through
+//! inotify, so new files are seen as they land instead of at the next scan.
+//!
+//! Only what `watch` needs: files created, closed after writing, moved in or out and
+//! deleted, and new subdirectories, which are watched too as they appear.
+//!
+//! This is the inotify API called directly (the `notify` crate is not among this
+//! build's dependencies): an fd, `poll` and `read`. The event parsing is kept apart
+//! from the syscalls so the awkward cases (queue overflow, a buffer ending partway
+//! through an event) are tested without a kernel.
+
+use std::collections::HashMap;
+use std::ffi::{CString, OsStr};
+use std::fs;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const MASK: u32 = libc::IN_CREATE
+    | libc::IN_CLOSE_WRITE
+    | libc::IN_MOVED_TO
+    | libc::IN_MOVED_FROM
+    | libc::IN_DELETE
+    | libc::IN_ONLYDIR;
+const HEADER: usize = size_of::<libc::inotify_event>();
+// Room for many events at once; each is at most a header and a 256-byte name
+const BUFFER: usize = 16 * 1024;
+
+pub struct Inotify {
+    fd: OwnedFd,
+    root: PathBuf,
+    // Watch descriptors to the directories they watch
+    dirs: HashMap<i32, PathBuf>,
+}
+
+impl Inotify {
+    // Watch `root` and every directory under it
+    pub fn new(root: &Path) -> io::Result<Self> {
+        // SAFETY: no pointers are passed; the descriptor is checked before it is owned
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut inotify = Self {
+            // SAFETY: a fresh descriptor nothing else holds
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+            root: root.to_path_buf(),
+            dirs: HashMap::new(),
+        };
+        inotify.watch_tree(root)?;
+        Ok(inotify)
+    }
+
+    fn watch_tree(&mut self, dir: &Path) -> io::Result<()> {
+        let path = CString::new(dir.as_os_str().as_bytes())?;
+        // SAFETY: `path` is a NUL-terminated string that outlives the call
+        let wd = unsafe { libc::inotify_add_watch(self.fd.as_raw_fd(), path.as_ptr(), MASK) };
+        if wd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        self.dirs.insert(wd, dir.to_path_buf());
+        for entry in fs::read_dir(dir)?.flatten() {
+            if entry.file_type().is_ok_and(|kind| kind.is_dir()) {
+                match self.watch_tree(&entry.path()) {
+                    // Removed while being walked
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                    result => result?,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Block until something changes under the tree or `timeout` passes (`None`: no
+    // limit). Returns whether anything changed.
+    pub fn wait(&mut self, timeout: Option<Duration>) -> io::Result<bool> {
+        let mut poll = libc::pollfd {
+            fd: self.fd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // Rounded up, so a deadline is never woken for a moment early
+        let timeout = timeout.map_or(-1, |t| {
+            t.as_nanos().div_ceil(1_000_000).min(i32::MAX as u128) as i32
+        });
+        // SAFETY: one valid pollfd, as the count says
+        match unsafe { libc::poll(&mut poll, 1, timeout) } {
+            0 => return Ok(false),
+            ready if ready < 0 => {
+                let e = io::Error::last_os_error();
+                return match e.kind() {
+                    io::ErrorKind::Interrupted => Ok(false),
+                    _ => Err(e),
+                };
+            }
+            _ => {}
+        }
+
+        let mut new_dirs = Vec::new();
+        let mut buffer = vec![0u8; BUFFER];
+        loop {
+            // SAFETY: reads at most the buffer's length into it
+            let read = unsafe {
+                libc::read(
+                    self.fd.as_raw_fd(),
+                    buffer.as_mut_ptr().cast(),
+                    buffer.len(),
+                )
+            };
+            if read < 0 {
+                let e = io::Error::last_os_error();
+                match e.kind() {
+                    io::ErrorKind::WouldBlock => break,
+                    io::ErrorKind::Interrupted => continue,
+                    _ => return Err(e),
+                }
+            }
+            if read == 0 {
+                break;
+            }
+            let changes = parse(&buffer[..read as usize], &self.root, &self.dirs);
+            for wd in changes.gone {
+                self.dirs.remove(&wd);
+            }
+            new_dirs.extend(changes.new_dirs);
+        }
+        for dir in new_dirs {
+            match self.watch_tree(&dir) {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                result => result?,
+            }
+        }
+        Ok(true)
+    }
+}
+
+// What one read of events asks of the watch list
+#[derive(Debug, Default, PartialEq)]
+struct Changes {
+    // Directories to watch, with everything under them
+    new_dirs: Vec<PathBuf>,
+    // Watch descriptors the kernel dropped (their directory went)
+    gone: Vec<i32>,
+}
+
+// The events in `events`, watched through `dirs`. The kernel only returns whole events,
+// but a header or name running past the end is ignored rather than trusted.
+fn parse(events: &[u8], root: &Path, dirs: &HashMap<i32, PathBuf>) -> Changes {
+    let mut changes = Changes::default();
+    let mut offset = 0;
+    while offset + HEADER <= events.len() {
+        // SAFETY: a whole header is in bounds, and read_unaligned needs no alignment
+        let event: libc::inotify_event =
+            unsafe { std::ptr::read_unaligned(events[offset..].as_ptr().cast()) };
+        let name_at = offset + HEADER;
+        let Some(next) = name_at
+            .checked_add(event.len as usize)
+            .filter(|end| *end <= events.len())
+        else {
+            break;
+        };
+        offset = next;
+        // The name is padded with NULs to the length
+        let name = events[name_at..next]
+            .split(|b| *b == 0)
+            .next()
+            .filter(|name| !name.is_empty());
+
+        if event.mask & libc::IN_Q_OVERFLOW != 0 {
+            // Lost events may have included new directories
+            changes.new_dirs.push(root.to_path_buf());
+        } else if event.mask & libc::IN_IGNORED != 0 {
+            changes.gone.push(event.wd);
+        } else if event.mask & libc::IN_ISDIR != 0
+            && event.mask & (libc::IN_CREATE | libc::IN_MOVED_TO) != 0
+        {
+            if let (Some(dir), Some(name)) = (dirs.get(&event.wd), name) {
+                changes.new_dirs.push(dir.join(OsStr::from_bytes(name)));
+            }
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sees_files_land_in_new_subdirectories() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut inotify = Inotify::new(dir.path()).unwrap();
+        let quiet = Some(Duration::from_millis(50));
+        assert!(!inotify.wait(quiet).unwrap());
+
+        fs::create_dir(dir.path().join("show")).unwrap();
+        assert!(inotify.wait(quiet).unwrap());
+        fs::write(dir.path().join("show").join("e1.mkv"), "x").unwrap();
+        assert!(inotify.wait(quiet).unwrap());
+        assert!(!inotify.wait(quiet).unwrap());
+        // Files leaving wake the watch too
+        fs::remove_file(dir.path().join("show").join("e1.mkv")).unwrap();
+        assert!(inotify.wait(quiet).unwrap());
+    }
+
+    // One event as the kernel lays it out, the name NUL-padded to `padded` bytes
+    fn event(wd: i32, mask: u32, name: &str, padded: usize) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend(wd.to_ne_bytes());
+        bytes.extend(mask.to_ne_bytes());
+        bytes.extend(0u32.to_ne_bytes());
+        bytes.extend((padded as u32).to_ne_bytes());
+        bytes.extend(name.as_bytes());
+        bytes.resize(HEADER + padded, 0);
+        bytes
+    }
+
+    fn dirs() -> HashMap<i32, PathBuf> {
+        HashMap::from([(1, PathBuf::from("/in")), (2, PathBuf::from("/in/show"))])
+    }
+
+    #[test]
+    fn new_directories_are_watched_and_dropped_ones_forgotten() {
+        let root = Path::new("/in");
+        let events = [
+            event(1, libc::IN_CREATE | libc::IN_ISDIR, "show 2", 16),
+            event(2, libc::IN_CLOSE_WRITE, "e1.mkv", 16),
+            event(2, libc::IN_DELETE | libc::IN_ISDIR, "extras", 16),
+            event(7, libc::IN_MOVED_TO | libc::IN_ISDIR, "unknown wd", 16),
+            event(2, libc::IN_IGNORED, "", 0),
+        ]
+        .concat();
+        assert_eq!(
+            parse(&events, root, &dirs()),
+            Changes {
+                new_dirs: vec![PathBuf::from("/in/show 2")],
+                gone: vec![2],
+            }
+        );
+    }
+
+    #[test]
+    fn an_overflow_rescans_the_whole_tree() {
+        let events = [
+            event(-1, libc::IN_Q_OVERFLOW, "", 0),
+            event(1, libc::IN_CREATE, "a.mkv", 16),
+        ]
+        .concat();
+        let changes = parse(&events, Path::new("/in"), &dirs());
+        assert_eq!(changes.new_dirs, vec![PathBuf::from("/in")]);
+    }
+
+    #[test]
+    fn events_cut_off_by_the_end_of_the_read_are_ignored() {
+        let root = Path::new("/in");
+        let whole = event(1, libc::IN_CREATE | libc::IN_ISDIR, "a", 16);
+        let next = event(1, libc::IN_CREATE | libc::IN_ISDIR, "b", 16);
+        // Partway through the next header, then partway through its name
+        for cut in [HEADER / 2, HEADER + 4] {
+            let events = [&whole[..], &next[..cut]].concat();
+            assert_eq!(
+                parse(&events, root, &dirs()).new_dirs,
+                vec![PathBuf::from("/in/a")]
+            );
+        }
+        // A length past the end of the buffer is not followed
+        let mut huge = event(1, libc::IN_CREATE | libc::IN_ISDIR, "c", 16);
+        huge[12..16].copy_from_slice(&u32::MAX.to_ne_bytes());
+        assert_eq!(parse(&huge, root, &dirs()), Changes::default());
+    }
+}
@@ -0,0 +1,416 @@
+// file: src/journal.rs
+// version: 0.2.0
+// guid: 581d0efe-c19a-475b-8992-98adad9c8006
+
+//! Write-ahead journal for swaps that replace an original with its transcoded file.
+//!
+//! Every step is a rename within one directory, so each is atomic: the original moves
+//! aside into `.transcoderr-swap/` next to it, the transcoded file takes its new name,
+//! and only once that is checked does the original go (trash, quarantine or purge).
+//! The intent is written and synced to `.transcoderr-swaps.ndjson` in the library
+//! before anything moves, and the commit after the check. A crash at any point thus
+//! leaves the original or the checked new file in place, and `recover` puts back an
+//! uncommitted swap or finishes removing a committed one's original. `roll_back` (for
+//! `doctor --roll-back`) puts back every unfinished swap whose original is still there.
+
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::removal::Removal;
+
+const FILE_NAME: &str = ".transcoderr-swaps.ndjson";
+const BACKUP_DIR: &str = ".transcoderr-swap";
+
+// Swaps are keyed by their original, which has one swap in flight at most
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "lowercase")]
+enum Entry {
+    // Nothing has moved yet
+    Begin {
+        original: PathBuf,
+        transcoded: PathBuf,
+        target: PathBuf,
+        backup: PathBuf,
+        bytes: u64,
+    },
+    // The new file is in place and checked; the original only waits for removal
+    Committed {
+        original: PathBuf,
+    },
+    Done {
+        original: PathBuf,
+    },
+}
+
+// A swap begun but not done
+struct Pending {
+    original: PathBuf,
+    transcoded: PathBuf,
+    target: PathBuf,
+    backup: PathBuf,
+    bytes: u64,
+    committed: bool,
+}
+
+pub struct Journal {
+    path: PathBuf,
+}
+
+impl Journal {
+    // The journal of the library at `root`
+    pub fn open(root: &Path) -> Self {
+        Self {
+            path: root.join(FILE_NAME),
+        }
+    }
+
+    // Replace `original` with `transcoded` under the name `target`, removing the original
+    // as `removal` says. `root` is the library, for quarantine paths.
+    pub fn swap(
+        &self,
+        original: &Path,
+        transcoded: &Path,
+        target: &Path,
+        removal: &Removal,
+        root: &Path,
+    ) -> Result<()> {
+        let name = original.file_name().context("original has no file name")?;
+        let backup = original.with_file_name(BACKUP_DIR).join(name);
+        if backup.exists() {
+            bail!(
+                "{} is left from an earlier swap; move it back or away first",
+                backup.display()
+            );
+        }
+        let bytes = fs::metadata(transcoded)
+            .with_context(|| format!("failed to read {}", transcoded.display()))?
+            .len();
+        self.append(&Entry::Begin {
+            original: original.to_path_buf(),
+            transcoded: transcoded.to_path_buf(),
+            target: target.to_path_buf(),
+            backup: backup.clone(),
+            bytes,
+        })?;
+
+        let moved = backup
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|()| fs::rename(original, &backup))
+            .and_then(|()| fs::rename(transcoded, target));
+        let checked = moved
+            .with_context(|| format!("failed to swap in {}", target.display()))
+            .and_then(|()| check(target, bytes));
+        if let Err(e) = checked {
+            roll_back(original, transcoded, target, &backup);
+            self.done(original)?;
+            return Err(e);
+        }
+        self.append(&Entry::Committed {
+            original: original.to_path_buf(),
+        })?;
+        dispose(&backup, original, removal, root)?;
+        self.done(original)
+    }
+
+    // Settle swaps a crash left unfinished: put uncommitted ones back, remove committed
+    // ones' originals. Returns what was done, one line per swap.
+    pub fn recover(&self, removal: &Removal, root: &Path) -> Result<Vec<String>> {
+        let mut settled = Vec::new();
+        for swap in self.pending() {
+            if swap.committed {
+                if swap.backup.exists() {
+                    dispose(&swap.backup, &swap.original, removal, root)?;
+                }
+                settled.push(format!(
+                    "Finished swapping in {} (original: {})",
+                    swap.target.display(),
+                    removal.describe()
+                ));
+            } else {
+                roll_back(&swap.original, &swap.transcoded, &swap.target, &swap.backup);
+                settled.push(format!(
+                    "Rolled back the interrupted swap of {}",
+                    swap.original.display()
+                ));
+            }
+            self.done(&swap.original)?;
+        }
+        self.close();
+        Ok(settled)
+    }
+
+    // Undo every swap a crash left unfinished, committed or not, as long as its original
+    // was not removed yet. Returns what was done, one line per swap.
+    pub fn roll_back(&self) -> Result<Vec<String>> {
+        let mut settled = Vec::new();
+        for swap in self.pending() {
+            if swap.committed && !swap.backup.exists() {
+                settled.push(format!(
+                    "Kept {}: the original is already gone",
+                    swap.target.display()
+                ));
+            } else {
+                roll_back(&swap.original, &swap.transcoded, &swap.target, &swap.backup);
+                settled.push(format!(
+                    "Rolled back the interrupted swap of {}",
+                    swap.original.display()
+                ));
+            }
+            self.done(&swap.original)?;
+        }
+        self.close();
+        Ok(settled)
+    }
+
+    // Swaps a crash left unfinished
+    pub fn interrupted(&self) -> usize {
+        self.pending().len()
+    }
+
+    // Remove the journal once every swap in it is settled
+    pub fn close(&self) {
+        if self.pending().is_empty() {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+
+    // Swaps begun but not done, in the order they began
+    fn pending(&self) -> Vec<Pending> {
+        let mut pending: BTreeMap<PathBuf, (usize, Pending)> = BTreeMap::new();
+        for (n, entry) in self.entries().into_iter().enumerate() {
+            match entry {
+                Entry::Begin {
+                    original,
+                    transcoded,
+                    target,
+                    backup,
+                    bytes,
+                } => {
+                    let swap = Pending {
+                        original: original.clone(),
+                        transcoded,
+                        target,
+                        backup,
+                        bytes,
+                        committed: false,
+                    };
+                    pending.insert(original, (n, swap));
+                }
+                Entry::Committed { original } => {
+                    if let Some((_, swap)) = pending.get_mut(&original) {
+                        // A commit is only written once the new file was checked
+                        swap.committed = check(&swap.target, swap.bytes).is_ok();
+                    }
+                }
+                Entry::Done { original } => {
+                    pending.remove(&original);
+                }
+            }
+        }
+        let mut pending: Vec<(usize, Pending)> = pending.into_values().collect();
+        pending.sort_by_key(|(n, _)| *n);
+        pending.into_iter().map(|(_, swap)| swap).collect()
+    }
+
+    // Entries in order; a line cut short by a crash is skipped
+    fn entries(&self) -> Vec<Entry> {
+        fs::read_to_string(&self.path)
+            .map(|text| {
+                text.lines()
+                    .filter_map(|line| serde_json::from_str(line).ok())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn done(&self, original: &Path) -> Result<()> {
+        self.append(&Entry::Done {
+            original: original.to_path_buf(),
+        })
+    }
+
+    // Append and sync, so the entry is on disk before the step it announces
+    fn append(&self, entry: &Entry) -> Result<()> {
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("failed to open the swap journal {}", self.path.display()))?;
+        file.write_all(line.as_bytes())
+            .and_then(|()| file.sync_all())
+            .with_context(|| format!("failed to write the swap journal {}", self.path.display()))
+    }
+}
+
+// The new file is in place with all of its bytes
+fn check(target: &Path, bytes: u64) -> Result<()> {
+    let len = fs::metadata(target)
+        .with_context(|| format!("{} is missing after the swap", target.display()))?
+        .len();
+    if len != bytes {
+        bail!(
+            "{} has {} bytes after the swap, expected {}",
+            target.display(),
+            len,
+            bytes
+        );
+    }
+    Ok(())
+}
+
+// Undo whichever renames happened; the new file goes first, as it may hold the
+// original's name
+fn roll_back(original: &Path, transcoded: &Path, target: &Path, backup: &Path) {
+    if backup.exists() && !transcoded.exists() && target.exists() {
+        let _ = fs::rename(target, transcoded);
+    }
+    if backup.exists() && !original.exists() {
+        let _ = fs::rename(backup, original);
+    }
+    remove_backup_dir(backup);
+}
+
+fn dispose(backup: &Path, original: &Path, removal: &Removal, root: &Path) -> Result<()> {
+    removal.remove_as(backup, original, root)?;
+    remove_backup_dir(backup);
+    Ok(())
+}
+
+// remove_dir only succeeds once no other swap's backup is in it
+fn remove_backup_dir(backup: &Path) {
+    if let Some(dir) = backup.parent() {
+        let _ = fs::remove_dir(dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interrupted_swaps_roll_back_and_committed_ones_finish() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let (original, transcoded) = (root.join("a.avi"), root.join("a_transcoded.mkv"));
+        let target = root.join("a.mkv");
+        let backup = root.join(BACKUP_DIR).join("a.avi");
+        let removal = Removal {
+            quarantine: Some(root.join("q")),
+            purge: false,
+        };
+        let journal = Journal::open(root);
+        // A crash after both renames: the original aside, the new file in place
+        let crash = |committed: bool| {
+            fs::create_dir(root.join(BACKUP_DIR)).unwrap();
+            fs::write(&backup, b"original").unwrap();
+            fs::write(&target, b"new").unwrap();
+            journal
+                .append(&Entry::Begin {
+                    original: original.clone(),
+                    transcoded: transcoded.clone(),
+                    target: target.clone(),
+                    backup: backup.clone(),
+                    bytes: 3,
+                })
+                .unwrap();
+            if committed {
+                journal
+                    .append(&Entry::Committed {
+                        original: original.clone(),
+                    })
+                    .unwrap();
+            }
+        };
+
+        crash(false);
+        let settled = journal.recover(&removal, root).unwrap();
+        assert_eq!(
+            settled,
+            [format!(
+                "Rolled back the interrupted swap of {}",
+                original.display()
+            )]
+        );
+        assert_eq!(fs::read(&original).unwrap(), b"original");
+        assert_eq!(fs::read(&transcoded).unwrap(), b"new");
+        assert!(!target.exists() && !root.join(BACKUP_DIR).exists());
+        assert!(!root.join(FILE_NAME).exists());
+
+        fs::remove_file(&original).unwrap();
+        fs::remove_file(&transcoded).unwrap();
+        crash(true);
+        journal.recover(&removal, root).unwrap();
+        assert_eq!(fs::read(&target).unwrap(), b"new");
+        assert!(!backup.exists() && !original.exists());
+        // Quarantined under the original's own path
+        assert_eq!(fs::read(root.join("q/a.avi")).unwrap(), b"original");
+        assert!(!root.join(FILE_NAME).exists());
+
+        // With the same extension the new file takes the original's own name
+        let same = root.join("b.mkv");
+        fs::write(&same, b"original").unwrap();
+        fs::write(root.join("b_transcoded.mkv"), b"new").unwrap();
+        let purge = Removal {
+            quarantine: None,
+            purge: true,
+        };
+        journal
+            .swap(&same, &root.join("b_transcoded.mkv"), &same, &purge, root)
+            .unwrap();
+        assert_eq!(fs::read(&same).unwrap(), b"new");
+        journal.close();
+        assert!(!root.join(FILE_NAME).exists());
+        assert!(!root.join(BACKUP_DIR).exists());
+    }
+
+    #[test]
+    fn roll_back_puts_back_committed_swaps_too() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let journal = Journal::open(root);
+        fs::create_dir(root.join(BACKUP_DIR)).unwrap();
+        // Same extension: the new file holds the original's name, the original aside
+        for (name, committed) in [("a.mkv", false), ("b.mkv", true)] {
+            let original = root.join(name);
+            fs::write(root.join(BACKUP_DIR).join(name), b"original").unwrap();
+            fs::write(&original, b"new").unwrap();
+            journal
+                .append(&Entry::Begin {
+                    original: original.clone(),
+                    transcoded: root.join(name.replace('.', "_transcoded.")),
+                    target: original.clone(),
+                    backup: root.join(BACKUP_DIR).join(name),
+                    bytes: 3,
+                })
+                .unwrap();
+            if committed {
+                journal.append(&Entry::Committed { original }).unwrap();
+            }
+        }
+        assert_eq!(journal.interrupted(), 2);
+
+        let settled = journal.roll_back().unwrap();
+        assert_eq!(settled.len(), 2);
+        for name in ["a", "b"] {
+            assert_eq!(
+                fs::read(root.join(format!("{}.mkv", name))).unwrap(),
+                b"original"
+            );
+            assert_eq!(
+                fs::read(root.join(format!("{}_transcoded.mkv", name))).unwrap(),
+                b"new"
+            );
+        }
+        assert_eq!(journal.interrupted(), 0);
+        assert!(!root.join(FILE_NAME).exists());
+        assert!(!root.join(BACKUP_DIR).exists());
+    }
+}
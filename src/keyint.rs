@@ -0,0 +1,118 @@
+// file: src/keyint.rs
+// version: 0.1.0
+// guid: f2689c85-9857-468c-8e30-1a07b4e296c7
+
+//! Keyframe interval (`--keyint auto|<seconds>`).
+//!
+//! Encoders disagree wildly on their default GOP length (x264 uses 250 frames, x265 and
+//! SVT-AV1 their own rules), which decides how precisely players can seek and where HLS
+//! segments may be cut. `--keyint` sets it in seconds and converts to frames from the
+//! input's frame rate. `auto` picks 2 seconds for streaming containers (mp4, m4v, ts,
+//! m3u8) and 10 seconds for archives (everything else, such as mkv).
+
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{Result, bail};
+
+use crate::cache::AnalysisCache;
+use crate::probe;
+
+const STREAMING_SECONDS: f64 = 2.0;
+const ARCHIVE_SECONDS: f64 = 10.0;
+const STREAMING_EXTS: [&str; 4] = ["mp4", "m4v", "ts", "m3u8"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Keyint {
+    Auto,
+    Seconds(f64),
+}
+
+impl FromStr for Keyint {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        if value.eq_ignore_ascii_case("auto") {
+            return Ok(Keyint::Auto);
+        }
+        match value.trim_end_matches('s').parse::<f64>() {
+            Ok(seconds) if seconds > 0.0 && seconds.is_finite() => Ok(Keyint::Seconds(seconds)),
+            _ => bail!(
+                "invalid key interval '{}': expected 'auto' or seconds (e.g. 2)",
+                value
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Keyint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Keyint::Auto => write!(f, "auto"),
+            Keyint::Seconds(seconds) => write!(f, "{}s", seconds),
+        }
+    }
+}
+
+impl Keyint {
+    pub fn seconds(&self, output: &Path) -> f64 {
+        match self {
+            Keyint::Seconds(seconds) => *seconds,
+            Keyint::Auto => {
+                let streaming = output
+                    .extension()
+                    .map(|ext| ext.to_string_lossy().to_lowercase())
+                    .is_some_and(|ext| STREAMING_EXTS.contains(&ext.as_str()));
+                if streaming {
+                    STREAMING_SECONDS
+                } else {
+                    ARCHIVE_SECONDS
+                }
+            }
+        }
+    }
+
+    // Encoder args for this interval; they go after the preset's so they replace any
+    // GOP length it sets. Inputs that cannot be probed get time-based keyframes instead.
+    pub fn args(&self, input: &Path, output: &Path, cache: &AnalysisCache) -> Vec<String> {
+        let fps = probe::probe_cached(input, cache)
+            .ok()
+            .and_then(|info| info.video_stream()?.frame_rate());
+        gop_args(self.seconds(output), fps)
+    }
+}
+
+fn gop_args(seconds: f64, fps: Option<f64>) -> Vec<String> {
+    match fps {
+        Some(fps) => vec![
+            "-g".to_string(),
+            ((fps * seconds).round() as u64).max(1).to_string(),
+        ],
+        None => vec![
+            "-force_key_frames".to_string(),
+            format!("expr:gte(t,n_forced*{})", seconds),
+        ],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_sizes_gops() {
+        assert_eq!("auto".parse::<Keyint>().unwrap(), Keyint::Auto);
+        assert_eq!("4s".parse::<Keyint>().unwrap(), Keyint::Seconds(4.0));
+        assert!("0".parse::<Keyint>().is_err());
+        assert!("fast".parse::<Keyint>().is_err());
+
+        assert_eq!(Keyint::Auto.seconds(Path::new("a.mp4")), 2.0);
+        assert_eq!(Keyint::Auto.seconds(Path::new("a.MKV")), 10.0);
+        assert_eq!(gop_args(2.0, Some(24000.0 / 1001.0)), ["-g", "48"]);
+        assert_eq!(
+            gop_args(2.0, None),
+            ["-force_key_frames", "expr:gte(t,n_forced*2)"]
+        );
+    }
+}
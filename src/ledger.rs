@@ -0,0 +1,302 @@
+// file: src/ledger.rs
+// version: 0.1.1
+// guid: 7ead64ca-00ba-4e7d-af06-e66d71b00a3d
+
+//! Shared work ledger (`batch --ledger DIR`) so several hosts can run batches over the
+//! same library without encoding the same file twice.
+//!
+//! The directory holds one claim file per job in progress (`claims/<key>.claim`) and an
+//! append-only `ledger.ndjson` of finished jobs, written under `ledger.lock`. Jobs are
+//! keyed by their output path relative to the output root, so hosts may mount the share
+//! at different paths. A running job refreshes its claim every minute; a claim left
+//! untouched for ten minutes belongs to a crashed host and is taken over.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::presets::sha256_hex;
+
+const HEARTBEAT: Duration = Duration::from_secs(60);
+const STALE_CLAIM: Duration = Duration::from_secs(10 * 60);
+// Appends take milliseconds; a lock this old was left by a crash
+const STALE_LOCK: Duration = Duration::from_secs(60);
+const LOCK_WAIT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    key: String,
+    status: String,
+    host: String,
+    ts_ms: u128,
+}
+
+pub struct Ledger {
+    dir: PathBuf,
+    // `host:pid`, written into claims and entries
+    owner: String,
+}
+
+pub enum ClaimResult {
+    Claimed(Claim),
+    // Finished by an earlier run on this or another host
+    Done { host: String },
+    // Another host is working on it right now
+    Busy { owner: String },
+}
+
+// A job this host is working on. Dropping it stops the heartbeat and releases the claim.
+pub struct Claim {
+    path: PathBuf,
+    key: String,
+    stop: Arc<AtomicBool>,
+    heartbeat: Option<JoinHandle<()>>,
+}
+
+impl Ledger {
+    pub fn open(dir: &Path) -> Result<Self> {
+        fs::create_dir_all(dir.join("claims"))
+            .with_context(|| format!("failed to create ledger directory {}", dir.display()))?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            owner: format!("{}:{}", hostname(), std::process::id()),
+        })
+    }
+
+    pub fn claim(&self, key: &str) -> Result<ClaimResult> {
+        if let Some(host) = self.finished_by(key)? {
+            return Ok(ClaimResult::Done { host });
+        }
+        let path = self
+            .dir
+            .join("claims")
+            .join(format!("{}.claim", sha256_hex(key.as_bytes())));
+        // A stale claim is replaced under `ledger.lock`, where its staleness is checked
+        // again: of two hosts taking it over, the second finds the first's claim fresh
+        let takeover = match fs::read_to_string(&path) {
+            Ok(owner) => {
+                if !is_stale(&path, STALE_CLAIM) {
+                    return Ok(ClaimResult::Busy {
+                        owner: owner.trim().to_string(),
+                    });
+                }
+                let lock = self.lock()?;
+                if is_stale(&path, STALE_CLAIM) {
+                    println!(
+                        "  Taking over stale claim from {} (no heartbeat for {} minutes)",
+                        owner.trim(),
+                        STALE_CLAIM.as_secs() / 60
+                    );
+                    let _ = fs::remove_file(&path);
+                }
+                Some(lock)
+            }
+            Err(_) => None,
+        };
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => file
+                .write_all(self.owner.as_bytes())
+                .with_context(|| format!("failed to write claim {}", path.display()))?,
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                let owner = fs::read_to_string(&path).unwrap_or_default();
+                return Ok(ClaimResult::Busy {
+                    owner: owner.trim().to_string(),
+                });
+            }
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("failed to create claim {}", path.display()));
+            }
+        }
+        drop(takeover);
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let heartbeat = {
+            let (stop, path) = (Arc::clone(&stop), path.clone());
+            thread::spawn(move || beat(&path, &stop))
+        };
+        let claim = Claim {
+            path,
+            key: key.to_string(),
+            stop,
+            heartbeat: Some(heartbeat),
+        };
+        // A host that finished the job between the check above and the claim recorded it
+        // before releasing its own claim, so it shows now
+        if let Some(host) = self.finished_by(key)? {
+            return Ok(ClaimResult::Done { host });
+        }
+        Ok(ClaimResult::Claimed(claim))
+    }
+
+    // Record the outcome and release the claim. Only successes stop other hosts from
+    // trying the file again.
+    pub fn finish(&self, claim: Claim, succeeded: bool) -> Result<()> {
+        let entry = Entry {
+            key: claim.key.clone(),
+            status: if succeeded { "done" } else { "failed" }.to_string(),
+            host: self.owner.clone(),
+            ts_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis())
+                .unwrap_or(0),
+        };
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+        let _lock = self.lock()?;
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join("ledger.ndjson"))
+            .and_then(|mut file| file.write_all(line.as_bytes()))
+            .context("failed to append to the work ledger")?;
+        drop(claim);
+        Ok(())
+    }
+
+    fn finished_by(&self, key: &str) -> Result<Option<String>> {
+        let text = match fs::read_to_string(self.dir.join("ledger.ndjson")) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).context("failed to read the work ledger"),
+        };
+        // A torn last line from a crashed writer is skipped, not fatal
+        Ok(text
+            .lines()
+            .rev()
+            .filter_map(|line| serde_json::from_str::<Entry>(line).ok())
+            .filter(|entry| entry.key == key && entry.status == "done")
+            .map(|entry| entry.host)
+            .next())
+    }
+
+    fn lock(&self) -> Result<LockFile> {
+        let path = self.dir.join("ledger.lock");
+        let started = SystemTime::now();
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    let _ = file.write_all(self.owner.as_bytes());
+                    return Ok(LockFile(path));
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    if is_stale(&path, STALE_LOCK) {
+                        let _ = fs::remove_file(&path);
+                        continue;
+                    }
+                    if started.elapsed().unwrap_or_default() > LOCK_WAIT {
+                        bail!("timed out waiting for {}", path.display());
+                    }
+                    thread::sleep(Duration::from_millis(100));
+                }
+                Err(e) => {
+                    return Err(e).with_context(|| format!("failed to create {}", path.display()));
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Claim {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(heartbeat) = self.heartbeat.take() {
+            let _ = heartbeat.join();
+        }
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+struct LockFile(PathBuf);
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+// Refresh the claim's modification time until told to stop; checks the flag every
+// second so finishing a job is not held up by a full heartbeat interval
+fn beat(path: &Path, stop: &AtomicBool) {
+    let mut last = SystemTime::now();
+    while !stop.load(Ordering::Relaxed) {
+        thread::sleep(Duration::from_secs(1));
+        if last.elapsed().unwrap_or_default() >= HEARTBEAT {
+            if let Ok(file) = File::options().append(true).open(path) {
+                let _ = file.set_modified(SystemTime::now());
+            }
+            last = SystemTime::now();
+        }
+    }
+}
+
+fn is_stale(path: &Path, age: Duration) -> bool {
+    fs::metadata(path)
+        .and_then(|m| m.modified())
+        .map(|modified| modified.elapsed().unwrap_or_default() > age)
+        .unwrap_or(false)
+}
+
+fn hostname() -> String {
+    std::env::var("HOSTNAME")
+        .or_else(|_| std::env::var("COMPUTERNAME"))
+        .ok()
+        .or_else(|| fs::read_to_string("/etc/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "unknown-host".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ledger(dir: &Path, owner: &str) -> Ledger {
+        Ledger {
+            owner: owner.to_string(),
+            ..Ledger::open(dir).unwrap()
+        }
+    }
+
+    #[test]
+    fn one_host_takes_over_a_stale_claim() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (ledger(dir.path(), "a:1"), ledger(dir.path(), "b:2"));
+        let path = dir
+            .path()
+            .join("claims")
+            .join(format!("{}.claim", sha256_hex(b"show/e1.mkv")));
+        fs::write(&path, "crashed:3").unwrap();
+        File::options()
+            .append(true)
+            .open(&path)
+            .and_then(|file| file.set_modified(SystemTime::now() - 2 * STALE_CLAIM))
+            .unwrap();
+
+        let (first, second) = thread::scope(|scope| {
+            let first = scope.spawn(|| a.claim("show/e1.mkv").unwrap());
+            let second = scope.spawn(|| b.claim("show/e1.mkv").unwrap());
+            (first.join().unwrap(), second.join().unwrap())
+        });
+        let (claim, owner) = match (first, second) {
+            (ClaimResult::Claimed(claim), ClaimResult::Busy { owner }) => (claim, owner),
+            (ClaimResult::Busy { owner }, ClaimResult::Claimed(claim)) => (claim, owner),
+            _ => panic!("exactly one host should hold the claim"),
+        };
+        assert_eq!(fs::read_to_string(&path).unwrap(), owner);
+
+        a.finish(claim, true).unwrap();
+        assert!(!path.exists());
+        assert!(matches!(
+            b.claim("show/e1.mkv").unwrap(),
+            ClaimResult::Done { .. }
+        ));
+    }
+}
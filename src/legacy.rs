@@ -0,0 +1,70 @@
+// file: src/legacy.rs
+// version: 0.2.0
+// guid: c24e579c-d24c-42eb-9476-c4bba5cf3477
+
+//! Rescue settings for ancient sources (`--legacy-source`): digitized archives of AVI,
+//! DivX/Xvid, RealMedia and WMV files whose indexes, timestamps and bitstreams break
+//! strict demuxing.
+//!
+//! On the input side the demuxer reads past a broken index instead of trusting it,
+//! invents missing timestamps, drops corrupt packets and probes further for streams
+//! that start late; the decoders conceal errors instead of stopping. On the output
+//! side packed B-frames (the DivX 5 hack) are unpacked when the video is copied and
+//! muxing tolerates the long gaps such files have between audio and video packets.
+//! Their odd frame sizes are `--odd-size`'s to fix, like any other input's.
+
+// Options for the input, before its `-i`
+pub fn input_args() -> Vec<String> {
+    [
+        "-fflags",
+        "+genpts+igndts+ignidx+discardcorrupt",
+        "-err_detect",
+        "ignore_err",
+        "-analyzeduration",
+        "100M",
+        "-probesize",
+        "100M",
+    ]
+    .map(String::from)
+    .to_vec()
+}
+
+// Add the output-side fixes to `extra`; `video_codec` is the probed codec of the main
+// video stream, if the probe got that far
+pub fn apply(vcodec: &str, video_codec: Option<&str>, extra: &mut Vec<String>) {
+    // The filter rejects anything but MPEG-4 Part 2 (DivX, Xvid)
+    if vcodec == "copy" && video_codec == Some("mpeg4") {
+        extra.extend(["-bsf:v", "mpeg4_unpack_bframes"].map(String::from));
+    }
+    extra.extend(
+        [
+            // Keep going past decoding errors, however many
+            "-max_error_rate",
+            "1",
+            "-max_muxing_queue_size",
+            "4096",
+            "-avoid_negative_ts",
+            "make_zero",
+        ]
+        .map(String::from),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_copied_mpeg4_unpacks_bframes() {
+        let mut extra = Vec::new();
+        apply("libx264", Some("mpeg4"), &mut extra);
+        assert!(!extra.contains(&"-bsf:v".to_string()));
+
+        let mut extra = Vec::new();
+        apply("copy", Some("mpeg4"), &mut extra);
+        assert_eq!(extra[..2], ["-bsf:v", "mpeg4_unpack_bframes"]);
+        let mut extra = Vec::new();
+        apply("copy", Some("wmv3"), &mut extra);
+        assert_eq!(extra[0], "-max_error_rate");
+    }
+}
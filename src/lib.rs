@@ -0,0 +1,559 @@
+// file: src/lib.rs
+// version: 0.6.0
+// guid: 314495ec-48e8-4eb3-8a3c-c7f38eca80df
+
+//! transcoderr as a library, for Rust programs that would otherwise shell out to the
+//! CLI: transcode a file with a preset, run a batch over a directory, look presets up
+//! and probe media.
+//!
+//! ```no_run
+//! use transcoderr::{BatchOptions, TranscodeJob};
+//!
+//! let output = transcoderr::transcode(&TranscodeJob::new("show.mp4").preset("tv-h265-fast"))?;
+//! println!("wrote {}", output.display());
+//! let options = BatchOptions::from_args(["/media/in", "/media/out", "--preset", "anime"])?;
+//! transcoderr::batch_transcode(&options)?;
+//! # Ok::<(), transcoderr::TranscodeError>(())
+//! ```
+//!
+//! Runs use the built-in presets and the user's (`presets.toml` and the presets
+//! directory) but not the config file, and keep no cache, history or events. ffmpeg
+//! and ffprobe are found on PATH. The items at the crate root are the whole API; the
+//! modules behind them are private to the crate.
+
+use std::ffi::OsString;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::anyhow;
+use clap::Parser;
+
+mod alignment;
+mod args;
+mod audiobook;
+mod batch;
+mod cache;
+mod checkpoint;
+mod cli;
+mod config;
+mod cue;
+mod deterministic;
+mod diagnostics;
+mod doctor;
+mod encode;
+mod events;
+mod explain;
+mod export;
+mod features;
+mod filters;
+mod gpu;
+mod hdr;
+mod history;
+mod http;
+mod hwaccel;
+mod i18n;
+mod init;
+#[cfg(target_os = "linux")]
+mod inotify;
+mod journal;
+mod keyint;
+mod ledger;
+mod legacy;
+#[cfg(feature = "mediainfo")]
+mod mediainfo;
+mod metrics;
+mod migrate;
+mod mqtt;
+mod ownership;
+mod paths;
+mod plugins;
+mod presets;
+mod preview;
+mod probe;
+mod prompt;
+mod quality;
+mod recommend;
+mod removal;
+mod remux;
+mod report;
+mod results;
+mod resume;
+mod rules;
+mod schedule;
+mod schema;
+mod selftest;
+mod serve;
+mod shows;
+mod sidecar;
+mod silence;
+mod smart;
+mod snapshots;
+mod speed;
+mod spotcheck;
+mod stabilize;
+mod staging;
+mod stats;
+mod stereo;
+mod streamcopy;
+mod streams;
+mod style;
+mod subtiming;
+mod subtitles;
+mod synth;
+mod thumbnail;
+mod tools;
+mod tune;
+mod units;
+mod upgrades;
+mod vbv;
+mod watch;
+mod whatif;
+mod workers;
+
+use batch::BatchCommand;
+use cache::AnalysisCache;
+use diagnostics::Diagnostics;
+use events::Events;
+use history::History;
+use hwaccel::Backend;
+use metrics::Metrics;
+use plugins::Plugins;
+use presets::Presets;
+use preview::Preview;
+use report::FfmpegFailed;
+use results::Results;
+use schedule::Gate;
+use sidecar::FileArgs;
+use stats::Stats;
+use streams::Policies;
+use units::Units;
+use whatif::WhatIf;
+
+pub use paths::resolve_output_path;
+pub use presets::Preset;
+pub use probe::{Format, MediaInfo, SideData, Stream};
+
+// Services shared by every job of a run
+pub(crate) struct Runtime {
+    pub events: Events,
+    pub gate: Gate,
+    pub presets: Presets,
+    pub cache: AnalysisCache,
+    pub probe_workers: usize,
+    pub history: History,
+    pub stats: Stats,
+    pub snapshot_every: Option<Duration>,
+    pub preview: Option<Preview>,
+    pub remux_fallback: bool,
+    pub hwaccel: Option<Backend>,
+    pub what_if: Option<WhatIf>,
+    pub units: Units,
+    pub file_args: FileArgs,
+    pub diagnostics: Diagnostics,
+    pub plugins: Arc<Plugins>,
+    // What the run did, for --json
+    pub results: Option<Results>,
+    // `watch --metrics`: what it serves, with the running encode's speed and frame rate
+    pub metrics: Option<Arc<Mutex<Metrics>>>,
+}
+
+impl Runtime {
+    // What library callers run with: the user's presets and otherwise the defaults,
+    // with no cache, history, events or plugins, and only ffmpeg's last stats line
+    fn embedded() -> anyhow::Result<Self> {
+        Ok(Runtime {
+            events: Events::default(),
+            gate: Gate::default(),
+            presets: user_presets()?,
+            cache: AnalysisCache::open(None),
+            probe_workers: 1,
+            history: History::open(None),
+            stats: Stats::None,
+            snapshot_every: None,
+            preview: None,
+            remux_fallback: false,
+            hwaccel: None,
+            what_if: None,
+            units: Units::default(),
+            file_args: FileArgs::default(),
+            diagnostics: Diagnostics::default(),
+            plugins: Arc::new(Plugins::default()),
+            results: None,
+            metrics: None,
+        })
+    }
+}
+
+/// The `transcoderr` binary's entry point; not part of the API
+#[doc(hidden)]
+pub fn cli_main() -> std::process::ExitCode {
+    cli::main()
+}
+
+fn user_presets() -> anyhow::Result<Presets> {
+    Presets::load(
+        config::presets_file().as_deref(),
+        config::presets_dir().as_deref(),
+    )
+}
+
+/// Why a transcode or batch failed
+#[derive(Debug)]
+pub enum TranscodeError {
+    /// ffmpeg exited with an error; `log` holds the last lines it printed
+    Ffmpeg {
+        code: Option<i32>,
+        command: String,
+        log: Vec<String>,
+    },
+    /// No built-in or user preset has this name
+    UnknownPreset(String),
+    /// Anything else: arguments, probing, paths, I/O
+    Other(anyhow::Error),
+}
+
+impl fmt::Display for TranscodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TranscodeError::Ffmpeg { code, command, log } => FfmpegFailed {
+                code: *code,
+                command: command.clone(),
+                log: log.clone(),
+            }
+            .fmt(f),
+            TranscodeError::UnknownPreset(name) => f.write_str(&presets::unknown_preset(name)),
+            TranscodeError::Other(e) => write!(f, "{:#}", e),
+        }
+    }
+}
+
+impl std::error::Error for TranscodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TranscodeError::Other(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<anyhow::Error> for TranscodeError {
+    fn from(e: anyhow::Error) -> Self {
+        match e.downcast::<FfmpegFailed>() {
+            Ok(failed) => TranscodeError::Ffmpeg {
+                code: failed.code,
+                command: failed.command,
+                log: failed.log,
+            },
+            Err(e) => TranscodeError::Other(e),
+        }
+    }
+}
+
+/// One file to transcode, as `transcoderr transcode` takes it: without an output it is
+/// written next to the input, in the preset's container
+#[derive(Debug, Clone, Default)]
+pub struct TranscodeJob {
+    pub input: PathBuf,
+    pub output: Option<PathBuf>,
+    pub preset: Option<String>,
+    pub vcodec: Option<String>,
+    pub acodec: Option<String>,
+    /// ffmpeg args after the preset's
+    pub extra: Vec<String>,
+}
+
+impl TranscodeJob {
+    pub fn new(input: impl Into<PathBuf>) -> Self {
+        Self {
+            input: input.into(),
+            ..Self::default()
+        }
+    }
+
+    pub fn output(mut self, output: impl Into<PathBuf>) -> Self {
+        self.output = Some(output.into());
+        self
+    }
+
+    pub fn preset(mut self, name: impl Into<String>) -> Self {
+        self.preset = Some(name.into());
+        self
+    }
+
+    pub fn vcodec(mut self, vcodec: impl Into<String>) -> Self {
+        self.vcodec = Some(vcodec.into());
+        self
+    }
+
+    pub fn acodec(mut self, acodec: impl Into<String>) -> Self {
+        self.acodec = Some(acodec.into());
+        self
+    }
+
+    pub fn extra<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.extra.extend(args.into_iter().map(Into::into));
+        self
+    }
+}
+
+/// Options for a batch run, as `transcoderr batch` takes them
+#[derive(Debug)]
+pub struct BatchOptions {
+    command: BatchCommand,
+}
+
+#[derive(Parser)]
+#[command(name = "batch")]
+struct BatchLine {
+    #[command(flatten)]
+    command: BatchCommand,
+}
+
+impl BatchOptions {
+    /// A batch from `input_dir` into `output_dir` with the defaults
+    pub fn new(
+        input_dir: impl Into<PathBuf>,
+        output_dir: impl Into<PathBuf>,
+    ) -> Result<Self, TranscodeError> {
+        let dirs = ["--".into(), input_dir.into(), output_dir.into()];
+        Self::from_args(dirs.map(PathBuf::into_os_string))
+    }
+
+    /// A batch from `batch`'s command-line arguments, e.g. `["/in", "/out", "--preset",
+    /// "anime", "--jobs", "2"]`
+    pub fn from_args<I, T>(args: I) -> Result<Self, TranscodeError>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString>,
+    {
+        let args = std::iter::once(OsString::from("batch")).chain(args.into_iter().map(Into::into));
+        let line = BatchLine::try_parse_from(args).map_err(|e| TranscodeError::Other(e.into()))?;
+        if line.command.action.is_some() {
+            return Err(TranscodeError::Other(anyhow!(
+                "batch missing and batch prune are only available from the command line"
+            )));
+        }
+        Ok(Self {
+            command: line.command,
+        })
+    }
+
+    pub fn preset(self, name: impl Into<String>) -> Self {
+        self.with(|args| args.preset = Some(name.into()))
+    }
+
+    pub fn vcodec(self, vcodec: impl Into<String>) -> Self {
+        self.with(|args| args.vcodec = Some(vcodec.into()))
+    }
+
+    pub fn acodec(self, acodec: impl Into<String>) -> Self {
+        self.with(|args| args.acodec = Some(acodec.into()))
+    }
+
+    /// The output extension, without the dot
+    pub fn ext(self, ext: impl Into<String>) -> Self {
+        self.with(|args| args.ext = Some(ext.into()))
+    }
+
+    /// ffmpeg args after the preset's
+    pub fn extra<I, S>(self, extra: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.with(|args| args.extra.extend(extra.into_iter().map(Into::into)))
+    }
+
+    /// Audio and subtitle languages to keep, e.g. `["eng", "jpn"]`
+    pub fn languages<I, S>(self, languages: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.with(|args| args.languages = languages.into_iter().map(Into::into).collect())
+    }
+
+    /// Files transcoded at once, at least 1
+    pub fn jobs(self, jobs: u64) -> Self {
+        self.with(|args| args.jobs = Some(jobs.max(1)))
+    }
+
+    pub fn skip_existing(self, skip: bool) -> Self {
+        self.with(|args| args.skip_existing = skip)
+    }
+
+    pub fn dry_run(self, dry_run: bool) -> Self {
+        self.with(|args| args.dry_run = dry_run)
+    }
+
+    fn with(mut self, set: impl FnOnce(&mut batch::BatchArgs)) -> Self {
+        if let Some(args) = self.command.args.as_mut() {
+            set(args);
+        }
+        self
+    }
+}
+
+/// The video codec, audio codec and ffmpeg args a transcode with preset `name` runs
+/// (the transcode defaults without one); `vcodec`, `acodec` and `extra` take precedence
+/// as they do on the command line
+pub fn apply_preset(
+    name: Option<&str>,
+    vcodec: Option<&str>,
+    acodec: Option<&str>,
+    extra: &[String],
+) -> Result<(String, String, Vec<String>), TranscodeError> {
+    apply_from(&user_presets()?, name, vcodec, acodec, extra)
+}
+
+fn apply_from(
+    presets: &Presets,
+    name: Option<&str>,
+    vcodec: Option<&str>,
+    acodec: Option<&str>,
+    extra: &[String],
+) -> Result<(String, String, Vec<String>), TranscodeError> {
+    if let Some(name) = name.filter(|name| presets.find(name).is_none()) {
+        return Err(TranscodeError::UnknownPreset(name.to_string()));
+    }
+    Ok(presets.apply(name, vcodec, acodec, "libx264", extra))
+}
+
+/// Transcode `job`, keeping every stream and its metadata; returns the output path
+pub fn transcode(job: &TranscodeJob) -> Result<PathBuf, TranscodeError> {
+    transcode_with(job, &Runtime::embedded()?)
+}
+
+// `transcode` with the caller's runtime, e.g. `watch`'s from the command line
+pub(crate) fn transcode_with(
+    job: &TranscodeJob,
+    runtime: &Runtime,
+) -> Result<PathBuf, TranscodeError> {
+    let preset = job.preset.as_deref();
+    let (vcodec, acodec, mut extra) = apply_from(
+        &runtime.presets,
+        preset,
+        job.vcodec.as_deref(),
+        job.acodec.as_deref(),
+        &job.extra,
+    )?;
+    let given_ext = job
+        .output
+        .as_deref()
+        .and_then(Path::extension)
+        .map(|ext| ext.to_string_lossy());
+    let ext = runtime.presets.output_ext(preset, given_ext.as_deref())?;
+    let output = resolve_output_path(&job.input, job.output.as_deref(), Some(&ext))?;
+    vbv::adapt(&vcodec, &mut extra)?;
+    let policies = Policies {
+        languages: &[],
+        subtitle_default: None,
+        stereo_compat: false,
+        subtitle_timing: None,
+    };
+    let mut args = streams::plan_args(&job.input, &runtime.cache, &policies);
+    args.extend(extra);
+    encode::transcode(
+        &job.input,
+        &[],
+        &output,
+        (&vcodec, &acodec),
+        &args,
+        &runtime.presets.run_env(preset),
+        runtime,
+    )?;
+    Ok(output)
+}
+
+/// Run a batch: every input under the input directory, into the mirrored output tree
+pub fn batch_transcode(options: &BatchOptions) -> Result<(), TranscodeError> {
+    let runtime = Runtime::embedded()?;
+    let command = &options.command;
+    let args = command
+        .args
+        .as_ref()
+        .ok_or_else(|| anyhow!("missing batch directories"))?;
+    batch::batch_transcode(
+        args,
+        &command.ownership,
+        args.subtitle_default.as_ref(),
+        &runtime,
+    )?;
+    Ok(())
+}
+
+/// Probe a media file with ffprobe
+pub fn probe(path: &Path) -> Result<MediaInfo, TranscodeError> {
+    Ok(probe::probe(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_resolve_and_errors_keep_ffmpeg_failures() {
+        let presets = Presets::load(None, None).unwrap();
+        let (vcodec, acodec, extra) = apply_from(
+            &presets,
+            None,
+            Some("libx265"),
+            None,
+            &["-crf".into(), "20".into()],
+        )
+        .unwrap();
+        assert_eq!((vcodec.as_str(), acodec.as_str()), ("libx265", "aac"));
+        assert_eq!(extra, ["-crf", "20"]);
+        assert!(matches!(
+            apply_from(&presets, Some("no-such-preset"), None, None, &[]),
+            Err(TranscodeError::UnknownPreset(name)) if name == "no-such-preset"
+        ));
+
+        let failed = anyhow::Error::from(FfmpegFailed {
+            code: Some(1),
+            command: "-i in.mkv out.mkv".to_string(),
+            log: vec!["Invalid data found when processing input".to_string()],
+        });
+        assert!(matches!(
+            TranscodeError::from(failed),
+            TranscodeError::Ffmpeg { code: Some(1), .. }
+        ));
+    }
+
+    #[test]
+    fn runs_can_move_to_other_threads() {
+        fn send<T: Send>() {}
+        send::<Runtime>();
+        send::<TranscodeJob>();
+        send::<BatchOptions>();
+        send::<TranscodeError>();
+    }
+
+    #[test]
+    fn batch_options_take_the_command_line() {
+        let options =
+            BatchOptions::from_args(["/in", "/out", "--preset", "anime", "--jobs", "2"]).unwrap();
+        let args = options.command.args.unwrap();
+        assert_eq!(args.preset.as_deref(), Some("anime"));
+        assert_eq!(args.jobs, Some(2));
+        assert!(BatchOptions::from_args(["/in", "/out", "--no-such-flag"]).is_err());
+        let args = BatchOptions::new("-odd", "/out")
+            .unwrap()
+            .preset("anime")
+            .extra(["-crf", "20"])
+            .jobs(3)
+            .dry_run(true)
+            .command
+            .args
+            .unwrap();
+        assert_eq!(args.preset.as_deref(), Some("anime"));
+        assert_eq!(args.extra, ["-crf", "20"]);
+        assert_eq!((args.jobs, args.dry_run), (Some(3), true));
+    }
+}
@@ -0,0 +1,222 @@
+// file: src/mediainfo.rs
+// version: 0.1.0
+// guid: a76099d3-e7a8-46f7-a12c-483e00a59fd0
+
+//! mediainfo as a second probe backend (the `mediainfo` feature), for what ffprobe
+//! reports poorly: Dolby Atmos in E-AC-3 (JOC) and TrueHD, Dolby Vision profile strings
+//! where the container has no configuration record, and the delay each track starts at.
+//!
+//! ffprobe stays the source of the stream list; mediainfo's tracks are matched to its
+//! streams by `StreamOrder` and only fill in the extra fields. It is best effort: without
+//! a `mediainfo` binary, or for tracks it cannot place, the probe is ffprobe's alone.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::probe::{MediaInfo, Stream};
+use crate::tools::{self, Tool};
+
+#[derive(Debug, Default, Deserialize)]
+struct Output {
+    #[serde(default)]
+    media: Option<Media>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Media {
+    #[serde(default)]
+    track: Vec<Track>,
+}
+
+// mediainfo prints every value as a string; fields vary by track type
+#[derive(Debug, Default, Deserialize)]
+struct Track {
+    #[serde(rename = "@type", default)]
+    kind: String,
+    #[serde(flatten)]
+    fields: BTreeMap<String, serde_json::Value>,
+}
+
+impl Track {
+    fn field(&self, key: &str) -> Option<&str> {
+        self.fields.get(key)?.as_str()
+    }
+
+    // Multi-format values such as `Dolby Vision / SMPTE ST 2086` list the main one first
+    fn first(&self, key: &str) -> Option<&str> {
+        self.field(key)?.split(" / ").next().map(str::trim)
+    }
+
+    // The ffprobe stream index; program streams print `0-1`, which cannot be placed
+    fn stream_order(&self) -> Option<usize> {
+        self.field("StreamOrder")?.parse().ok()
+    }
+
+    fn codec_type(&self) -> Option<&'static str> {
+        match self.kind.as_str() {
+            "Video" => Some("video"),
+            "Audio" => Some("audio"),
+            "Text" => Some("subtitle"),
+            _ => None,
+        }
+    }
+
+    fn atmos(&self) -> bool {
+        let has = |key: &str, text: &str| self.field(key).is_some_and(|v| v.contains(text));
+        has("Format_Commercial_IfAny", "Atmos")
+            || has("Format_AdditionalFeatures", "JOC")
+            || (has("Format", "MLP FBA") && has("Format_AdditionalFeatures", "16-ch"))
+    }
+
+    // `dvhe.08.06, HDR10 compatible`
+    fn dolby_vision(&self) -> Option<String> {
+        if !self.first("HDR_Format")?.starts_with("Dolby Vision") {
+            return None;
+        }
+        let mut profile = self.first("HDR_Format_Profile")?.to_string();
+        if let Some(level) = self.first("HDR_Format_Level") {
+            profile = format!("{}.{}", profile, level);
+        }
+        match self.first("HDR_Format_Compatibility") {
+            Some(compat) if !compat.is_empty() => {
+                Some(format!("{}, {} compatible", profile, compat))
+            }
+            _ => Some(profile),
+        }
+    }
+
+    fn delay(&self) -> Option<f64> {
+        self.field("Delay")?.trim().parse().ok()
+    }
+
+    fn supplement(&self, stream: &mut Stream) {
+        if stream.is_type("audio") {
+            stream.atmos = Some(self.atmos());
+        }
+        if stream.is_type("video") {
+            stream.dolby_vision = self.dolby_vision();
+        }
+        stream.delay = self.delay();
+    }
+}
+
+// Fill in `info`'s streams from mediainfo's report on `path`
+pub fn supplement(path: &Path, info: &mut MediaInfo) {
+    let output = tools::output(Tool::Mediainfo, &["--Output=JSON".into(), path.into()]);
+    let Some(tracks) = output
+        .ok()
+        .filter(|o| o.status.success())
+        .and_then(|o| serde_json::from_slice::<Output>(&o.stdout).ok())
+        .and_then(|o| o.media)
+    else {
+        return;
+    };
+    merge(&tracks.track, info);
+}
+
+fn merge(tracks: &[Track], info: &mut MediaInfo) {
+    for track in tracks {
+        let (Some(n), Some(kind)) = (track.stream_order(), track.codec_type()) else {
+            continue;
+        };
+        if let Some(stream) = info.streams.get_mut(n).filter(|s| s.is_type(kind)) {
+            track.supplement(stream);
+        }
+    }
+}
+
+// Add the filled-in fields to ffprobe's JSON for `info --json`
+pub fn add_to_json(info: &MediaInfo, value: &mut serde_json::Value) {
+    let Some(streams) = value["streams"].as_array_mut() else {
+        return;
+    };
+    for (json, stream) in streams.iter_mut().zip(&info.streams) {
+        if let Some(atmos) = stream.atmos {
+            json["atmos"] = atmos.into();
+        }
+        if let Some(dolby_vision) = &stream.dolby_vision {
+            json["dolby_vision"] = dolby_vision.as_str().into();
+        }
+        if let Some(delay) = stream.delay {
+            json["delay"] = delay.into();
+        }
+    }
+}
+
+// Lines for `info`'s human output: what mediainfo added per audio track
+pub fn lines(info: &MediaInfo) -> Vec<String> {
+    let mut lines = Vec::new();
+    for (n, stream) in info
+        .streams
+        .iter()
+        .filter(|s| s.is_type("audio"))
+        .enumerate()
+    {
+        let mut notes = Vec::new();
+        if stream.atmos == Some(true) {
+            notes.push("Dolby Atmos".to_string());
+        }
+        if let Some(delay) = stream.delay.filter(|d| *d != 0.0) {
+            notes.push(format!("starts {:.0} ms in", delay * 1000.0));
+        }
+        if !notes.is_empty() {
+            lines.push(format!("Audio {}: {}", n, notes.join(", ")));
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REPORT: &str = r#"{"media": {"@ref": "movie.mkv", "track": [
+        {"@type": "General", "Format": "Matroska"},
+        {"@type": "Video", "StreamOrder": "0", "Format": "HEVC",
+         "HDR_Format": "Dolby Vision / SMPTE ST 2086", "HDR_Format_Profile": "dvhe.08 / ",
+         "HDR_Format_Level": "06", "HDR_Format_Compatibility": "HDR10 / HDR10"},
+        {"@type": "Audio", "StreamOrder": "1", "Format": "MLP FBA",
+         "Format_AdditionalFeatures": "16-ch", "Delay": "0.042"},
+        {"@type": "Audio", "StreamOrder": "2", "Format": "E-AC-3",
+         "Format_Commercial_IfAny": "Dolby Digital Plus"},
+        {"@type": "Text", "StreamOrder": "0-3", "Format": "UTF-8"}
+    ]}}"#;
+
+    #[test]
+    fn tracks_fill_in_their_streams() {
+        let mut info: MediaInfo = serde_json::from_value(serde_json::json!({
+            "streams": [
+                {"codec_type": "video"},
+                {"codec_type": "audio"},
+                {"codec_type": "audio"},
+                {"codec_type": "subtitle"}
+            ]
+        }))
+        .unwrap();
+        let mock = tools::Mock::new(|_, _| (0, REPORT.to_string(), String::new()));
+        tools::with_runner(mock, || supplement(Path::new("movie.mkv"), &mut info));
+
+        let streams = &info.streams;
+        assert_eq!(
+            streams[0].dolby_vision.as_deref(),
+            Some("dvhe.08.06, HDR10 compatible")
+        );
+        assert_eq!(streams[1].atmos, Some(true));
+        assert_eq!(streams[1].delay, Some(0.042));
+        assert_eq!(streams[2].atmos, Some(false));
+        // `0-3` cannot be placed, so the subtitle keeps ffprobe's view
+        assert_eq!(streams[3].delay, None);
+        assert_eq!(
+            lines(&info),
+            ["Audio 0: Dolby Atmos, starts 42 ms in".to_string()]
+        );
+
+        // No mediainfo: the probe is left as it was
+        let mock = tools::Mock::new(|_, _| (127, String::new(), String::new()));
+        let mut plain = MediaInfo::default();
+        tools::with_runner(mock, || supplement(Path::new("movie.mkv"), &mut plain));
+        assert!(plain.streams.is_empty());
+    }
+}
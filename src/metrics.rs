@@ -0,0 +1,192 @@
+// file: src/metrics.rs
+// version: 0.1.1
+// guid: b30e78d9-70fc-4887-ae73-db32f1bbd6a1
+
+//! `/metrics` for long-running modes (`serve`, `watch --metrics`), in Prometheus's text
+//! format: totals since the process started and gauges for what is running now.
+
+use std::fmt::Write as _;
+use std::io::BufReader;
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+use crate::http::{Response, read_request};
+
+// What Prometheus expects the text format to be served as
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Metrics {
+    pub completed: u64,
+    pub failed: u64,
+    pub cancelled: u64,
+    // Of completed encodes
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+    pub bytes_saved: u64,
+    pub encode_seconds: f64,
+    // Waiting to start
+    pub queued: usize,
+    pub running: usize,
+    // Summed over the running encodes, where ffmpeg reports them
+    pub speed: f64,
+    pub fps: f64,
+}
+
+impl Metrics {
+    // A completed encode
+    pub fn done(&mut self, input_bytes: u64, output_bytes: u64, seconds: f64) {
+        self.completed += 1;
+        self.input_bytes += input_bytes;
+        self.output_bytes += output_bytes;
+        self.bytes_saved += input_bytes.saturating_sub(output_bytes);
+        self.encode_seconds += seconds;
+    }
+
+    pub fn render(&self) -> String {
+        let metrics: [(&str, &str, &str, f64); 11] = [
+            (
+                "jobs_completed_total",
+                "counter",
+                "Encodes that finished",
+                self.completed as f64,
+            ),
+            (
+                "jobs_failed_total",
+                "counter",
+                "Encodes that failed",
+                self.failed as f64,
+            ),
+            (
+                "jobs_cancelled_total",
+                "counter",
+                "Encodes cancelled",
+                self.cancelled as f64,
+            ),
+            (
+                "input_bytes_total",
+                "counter",
+                "Size of the inputs of finished encodes",
+                self.input_bytes as f64,
+            ),
+            (
+                "output_bytes_total",
+                "counter",
+                "Size of the outputs of finished encodes",
+                self.output_bytes as f64,
+            ),
+            (
+                "bytes_saved_total",
+                "counter",
+                "Bytes finished encodes saved over their inputs (none for outputs that grew)",
+                self.bytes_saved as f64,
+            ),
+            (
+                "encode_seconds_total",
+                "counter",
+                "Wall time spent on finished encodes",
+                self.encode_seconds,
+            ),
+            (
+                "queue_depth",
+                "gauge",
+                "Encodes waiting to start",
+                self.queued as f64,
+            ),
+            (
+                "jobs_running",
+                "gauge",
+                "Encodes running",
+                self.running as f64,
+            ),
+            (
+                "encode_speed",
+                "gauge",
+                "Encode speed as a multiple of real time, summed over running encodes",
+                self.speed,
+            ),
+            (
+                "encode_fps",
+                "gauge",
+                "Frames per second, summed over running encodes",
+                self.fps,
+            ),
+        ];
+        let mut text = String::new();
+        for (name, kind, help, value) in metrics {
+            let _ = writeln!(text, "# HELP transcoderr_{} {}.", name, help);
+            let _ = writeln!(text, "# TYPE transcoderr_{} {}", name, kind);
+            let _ = writeln!(text, "transcoderr_{} {}", name, value);
+        }
+        text
+    }
+
+    pub fn response(&self) -> Response {
+        Response {
+            status: 200,
+            content_type: CONTENT_TYPE,
+            body: self.render().into_bytes(),
+        }
+    }
+}
+
+// Serve `GET /metrics` on `listen` from a thread of its own, for modes without a server
+pub fn listen(listen: &str, metrics: Arc<Mutex<Metrics>>) -> Result<()> {
+    let listener =
+        TcpListener::bind(listen).with_context(|| format!("failed to listen on {}", listen))?;
+    println!("Metrics on http://{}/metrics", listener.local_addr()?);
+    thread::spawn(move || {
+        // A connection each, so a stalled client holds up no scrape
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else {
+                continue;
+            };
+            let metrics = Arc::clone(&metrics);
+            thread::spawn(move || {
+                let _ = stream.set_read_timeout(Some(Duration::from_secs(10)));
+                let response = match read_request(&mut BufReader::new(&stream)) {
+                    Ok(request) if request.method == "GET" && request.path == "/metrics" => metrics
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .response(),
+                    Ok(request) => Response::error(404, format!("nothing at {}", request.path)),
+                    Err(e) => Response::error(400, format!("{:#}", e)),
+                };
+                let _ = response.write_to(&mut stream);
+            });
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_the_text_format() {
+        let mut metrics = Metrics {
+            queued: 2,
+            running: 1,
+            speed: 1.5,
+            ..Metrics::default()
+        };
+        metrics.done(1000, 400, 12.5);
+        metrics.done(100, 300, 1.0);
+        let text = metrics.render();
+        assert!(text.contains(
+            "# HELP transcoderr_jobs_completed_total Encodes that finished.\n\
+             # TYPE transcoderr_jobs_completed_total counter\n\
+             transcoderr_jobs_completed_total 2\n"
+        ));
+        assert!(text.contains("\ntranscoderr_bytes_saved_total 600\n"));
+        assert!(text.contains("\ntranscoderr_encode_seconds_total 13.5\n"));
+        assert!(text.contains("# TYPE transcoderr_queue_depth gauge\ntranscoderr_queue_depth 2\n"));
+        assert!(text.contains("\ntranscoderr_encode_speed 1.5\n"));
+        assert!(text.ends_with("transcoderr_encode_fps 0\n"));
+    }
+}
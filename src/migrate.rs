@@ -0,0 +1,307 @@
+// file: src/migrate.rs
+// version: 0.7.1
+// guid: aa0e4ba9-d416-4029-ba5e-ba5c811325ab
+
+//! `migrate-suffixed`: clean up libraries left with `<name>_transcoded.<ext>` files by
+//! earlier in-place runs.
+//!
+//! Each suffixed file is matched to the original next to it and verified against it
+//! (the streams are there and the duration matches). By default the result is only
+//! listed; `--swap` renames the transcoded file to the original's name, removes the
+//! verified original (OS trash, `--quarantine DIR` as a backup, or `--purge`) and
+//! records the pair in the encode history. Swaps go through the library's write-ahead
+//! journal, so a run interrupted mid-swap is rolled back or finished by the next
+//! `--swap` (or rolled back by `doctor --roll-back`); a run without `--swap` only says so.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use clap::Args;
+
+use crate::Runtime;
+use crate::batch::{print_issue_summary, scan_inputs};
+use crate::history::{Record, SETTINGS_TAG};
+use crate::i18n;
+use crate::journal::Journal;
+use crate::paths::{is_suffixed_output, paths_equivalent, strict_stem, unsuffixed_stem};
+use crate::probe::{self, MediaInfo};
+use crate::removal::Removal;
+use crate::style::Marker;
+use crate::units::porcelain_line;
+
+// Container durations round differently; more than this is a cut-short encode
+const DURATION_TOLERANCE: f64 = 1.0;
+
+#[derive(Args, Debug)]
+pub struct MigrateArgs {
+    /// Library directory to search recursively for `<name>_transcoded.*` files
+    pub dir: PathBuf,
+    /// Extensions of the originals and transcoded files (comma-separated)
+    #[arg(long, default_value = "mp4,mkv,avi,mov,m4v,ts")]
+    pub input_exts: String,
+    /// Replace each verified original with its transcoded file (default: only list)
+    #[arg(long)]
+    pub swap: bool,
+    #[command(flatten)]
+    pub removal: Removal,
+    /// Dry run: print what --swap would do
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+// A transcoded file and the original it replaces
+struct Pair {
+    original: PathBuf,
+    transcoded: PathBuf,
+    // The original's name with the transcoded file's extension
+    target: PathBuf,
+    settings: Option<String>,
+}
+
+pub fn run(args: &MigrateArgs, runtime: &Runtime) -> Result<()> {
+    let journal = Journal::open(&args.dir);
+    // Settling a swap moves and removes files, which only `--swap` may
+    if args.swap && !args.dry_run && runtime.what_if.is_none() {
+        for line in journal.recover(&args.removal, &args.dir)? {
+            println!("{}", line);
+        }
+    } else if journal.interrupted() > 0 {
+        let interrupted = i18n::t(
+            "migrate-interrupted",
+            &[
+                ("count", journal.interrupted().to_string()),
+                ("dir", args.dir.display().to_string()),
+            ],
+        );
+        println!("{} {}", Marker::Warning, interrupted);
+    }
+    let scan = scan_inputs(&args.dir, &args.input_exts)?;
+    let suffixed: Vec<&PathBuf> = scan
+        .files
+        .iter()
+        .filter(|f| is_suffixed_output(f))
+        .collect();
+    println!(
+        "Found {} transcoded files in {}",
+        suffixed.len(),
+        args.dir.display()
+    );
+
+    let mut verified = Vec::new();
+    let mut skipped = 0usize;
+    for transcoded in suffixed {
+        match pair_for(transcoded, &scan.files, runtime) {
+            Ok(pair) => {
+                println!(
+                    "  {:5} {} -> {}",
+                    Marker::Ok,
+                    pair.transcoded.display(),
+                    pair.target.display()
+                );
+                verified.push(pair);
+            }
+            Err(why) => {
+                println!("  {:5} {}: {}", Marker::Skip, transcoded.display(), why);
+                skipped += 1;
+            }
+        }
+    }
+    match runtime.units.porcelain() {
+        true => println!(
+            "{}",
+            porcelain_line(
+                "migrate",
+                &[
+                    ("verified", verified.len().to_string()),
+                    ("skipped", skipped.to_string()),
+                ]
+            )
+        ),
+        false => println!(
+            "\n{}",
+            i18n::t(
+                "migrate-verified",
+                &[
+                    ("verified", verified.len().to_string()),
+                    ("skipped", skipped.to_string()),
+                ]
+            )
+        ),
+    }
+
+    if !args.swap || verified.is_empty() {
+        if !verified.is_empty() {
+            println!(
+                "Re-run with --swap to replace the verified originals (originals: {})",
+                args.removal.describe()
+            );
+        }
+        print_issue_summary(&scan.issues);
+        return Ok(());
+    }
+
+    let (mut swapped, mut failed) = (0usize, 0usize);
+    for pair in &verified {
+        if let Some(what_if) = &runtime.what_if {
+            what_if.record(args.removal.action(&pair.original, &args.dir));
+            what_if.rename(&pair.transcoded, &pair.target);
+            continue;
+        }
+        if args.dry_run {
+            println!(
+                "{} Would {} {} and rename {} -> {}",
+                Marker::DryRun,
+                args.removal.describe(),
+                pair.original.display(),
+                pair.transcoded.display(),
+                pair.target.display()
+            );
+            continue;
+        }
+        let swapped_in = journal.swap(
+            &pair.original,
+            &pair.transcoded,
+            &pair.target,
+            &args.removal,
+            &args.dir,
+        );
+        match swapped_in {
+            Ok(()) => {
+                let bytes = fs::metadata(&pair.target).map(|m| m.len()).unwrap_or(0);
+                let settings = pair.settings.as_deref().unwrap_or("migrated");
+                runtime.history.append(
+                    &Record::new(&pair.original, &pair.target, settings, None)
+                        .finished(true, 0.0, bytes),
+                );
+                swapped += 1;
+            }
+            Err(e) => {
+                eprintln!("  {} {:#}", Marker::Error, e);
+                failed += 1;
+            }
+        }
+    }
+    if !args.dry_run && runtime.what_if.is_none() {
+        match runtime.units.porcelain() {
+            true => println!(
+                "{}",
+                porcelain_line(
+                    "swap",
+                    &[
+                        ("swapped", swapped.to_string()),
+                        ("failed", failed.to_string()),
+                    ]
+                )
+            ),
+            false => println!(
+                "\n{}",
+                i18n::t(
+                    "migrate-completed",
+                    &[
+                        ("removal", args.removal.summary()),
+                        ("swapped", swapped.to_string()),
+                        ("failed", failed.to_string()),
+                    ]
+                )
+            ),
+        }
+    }
+    print_issue_summary(&scan.issues);
+    Ok(())
+}
+
+// Find and verify the original for `transcoded`; the error says why it is left alone
+fn pair_for(transcoded: &Path, files: &[PathBuf], runtime: &Runtime) -> Result<Pair, String> {
+    let stem = unsuffixed_stem(transcoded).ok_or("not a suffixed file")?;
+    let originals: Vec<&PathBuf> = files
+        .iter()
+        .filter(|f| f.parent() == transcoded.parent() && !is_suffixed_output(f))
+        .filter(|f| strict_stem(f) == stem)
+        .collect();
+    let original = match originals[..] {
+        [original] => original,
+        [] => return Err("no original next to it".to_string()),
+        _ => return Err(format!("{} possible originals", originals.len())),
+    };
+
+    let target = target_path(original, transcoded);
+    if target.exists() && !paths_equivalent(&target, original) {
+        return Err(format!("{} already exists", target.display()));
+    }
+    let probe =
+        |path: &Path| probe::probe_cached(path, &runtime.cache).map_err(|e| format!("{:#}", e));
+    let (source, result) = (probe(original)?, probe(transcoded)?);
+    verify(&source, &result)?;
+    Ok(Pair {
+        original: original.clone(),
+        transcoded: transcoded.to_path_buf(),
+        target,
+        settings: result.format.tag(SETTINGS_TAG).map(str::to_string),
+    })
+}
+
+// `Show 1.11.avi` + `Show 1.11_transcoded.mkv` -> `Show 1.11.mkv`
+fn target_path(original: &Path, transcoded: &Path) -> PathBuf {
+    let mut name = strict_stem(original);
+    if let Some(ext) = transcoded.extension() {
+        name.push(".");
+        name.push(ext);
+    }
+    original.with_file_name(name)
+}
+
+// The transcoded file must keep the original's kinds of streams and its length
+fn verify(source: &MediaInfo, result: &MediaInfo) -> Result<(), String> {
+    if source.video_stream().is_some() && result.video_stream().is_none() {
+        return Err("video stream missing".to_string());
+    }
+    let has_audio = |info: &MediaInfo| info.streams.iter().any(|s| s.is_type("audio"));
+    if has_audio(source) && !has_audio(result) {
+        return Err("audio missing".to_string());
+    }
+    match (
+        source.format.duration_seconds(),
+        result.format.duration_seconds(),
+    ) {
+        (Some(expected), Some(actual)) if (expected - actual).abs() > DURATION_TOLERANCE => Err(
+            format!("duration {:.1}s, original {:.1}s", actual, expected),
+        ),
+        (Some(_), None) => Err("no duration (incomplete file?)".to_string()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(duration: &str, kinds: &[&str]) -> MediaInfo {
+        serde_json::from_value(serde_json::json!({
+            "streams": kinds.iter().map(|k| serde_json::json!({"codec_type": k})).collect::<Vec<_>>(),
+            "format": {"duration": duration},
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn verify_wants_streams_and_length() {
+        let source = info("1320.5", &["video", "audio", "subtitle"]);
+        assert_eq!(
+            verify(&source, &info("1320.9", &["video", "audio"])),
+            Ok(())
+        );
+        assert_eq!(
+            verify(&source, &info("600.0", &["video", "audio"])),
+            Err("duration 600.0s, original 1320.5s".to_string())
+        );
+        assert!(verify(&source, &info("1320.5", &["video"])).is_err());
+        assert_eq!(
+            target_path(
+                Path::new("/tv/Show 1.11.avi"),
+                Path::new("/tv/Show 1.11_transcoded.mkv")
+            ),
+            Path::new("/tv/Show 1.11.mkv")
+        );
+    }
+}
@@ -0,0 +1,423 @@
+// file: src/mqtt.rs
+// version: 0.3.0
+// guid: c5a7e913-2d84-4b6f-9e10-7f3a8d25b94c
+
+//! Minimal MQTT 3.1.1 publisher (QoS 0, plain TCP) for lifecycle events.
+//!
+//! The publisher is the `mqtt` feature; without it the `[mqtt]` table still parses, and
+//! connecting only reports that this build cannot publish.
+
+#[cfg(feature = "mqtt")]
+use std::io::{self, Read, Write};
+#[cfg(feature = "mqtt")]
+use std::net::{TcpStream, ToSocketAddrs};
+#[cfg(feature = "mqtt")]
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "mqtt")]
+use anyhow::Context;
+use anyhow::{Result, bail};
+use serde::Deserialize;
+
+#[cfg(feature = "mqtt")]
+const DEFAULT_PORT: u16 = 1883;
+#[cfg(feature = "mqtt")]
+const TIMEOUT: Duration = Duration::from_secs(5);
+// Idle longer than this and a publish first checks the broker answers a PINGREQ: a
+// connection a NAT or the broker dropped quietly still takes writes into the kernel
+#[cfg(feature = "mqtt")]
+const PING_AFTER: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+// Parsed either way, so a build without the feature can say why it ignores the table
+#[cfg_attr(not(feature = "mqtt"), allow(dead_code))]
+pub struct MqttConfig {
+    /// Broker address: `host`, `host:port` or `mqtt://host:port`
+    pub broker: String,
+    /// Events are published to `<topic-prefix>/<event>`
+    #[serde(default = "default_topic_prefix")]
+    pub topic_prefix: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Client identifier (default: `transcoderr-<pid>`)
+    pub client_id: Option<String>,
+}
+
+fn default_topic_prefix() -> String {
+    "transcoderr".to_string()
+}
+
+impl MqttConfig {
+    // MQTT 3.1.1 (3.1.2.9) only allows a password after a username
+    pub fn validate(&self) -> Result<()> {
+        if self.password.is_some() && self.username.is_none() {
+            bail!("[mqtt] password is set without a username, which MQTT 3.1.1 does not allow");
+        }
+        Ok(())
+    }
+
+    // `host:port` for the broker, rejecting schemes we cannot speak
+    #[cfg(feature = "mqtt")]
+    fn address(&self) -> Result<String> {
+        let broker = self.broker.trim();
+        let rest = match broker.split_once("://") {
+            Some(("mqtt" | "tcp", rest)) => rest,
+            Some((scheme, _)) => bail!(
+                "unsupported MQTT broker scheme '{}://' (only plain mqtt:// is supported)",
+                scheme
+            ),
+            None => broker,
+        };
+        let rest = rest.trim_end_matches('/');
+        if rest.is_empty() {
+            bail!("MQTT broker address is empty");
+        }
+        // Bare hosts (including bracketed IPv6) get the default port
+        let has_port = match rest.rsplit_once(':') {
+            Some((host, port)) => !host.ends_with(':') && port.parse::<u16>().is_ok(),
+            None => false,
+        };
+        Ok(if has_port {
+            rest.to_string()
+        } else {
+            format!("{}:{}", rest, DEFAULT_PORT)
+        })
+    }
+
+    pub fn topic(&self, event: &str) -> String {
+        format!("{}/{}", self.topic_prefix.trim_end_matches('/'), event)
+    }
+}
+
+#[cfg(feature = "mqtt")]
+pub struct MqttClient {
+    config: MqttConfig,
+    stream: Option<TcpStream>,
+    last_used: Instant,
+}
+
+#[cfg(not(feature = "mqtt"))]
+pub struct MqttClient {
+    never: std::convert::Infallible,
+}
+
+#[cfg(not(feature = "mqtt"))]
+impl MqttClient {
+    pub fn connect(_config: &MqttConfig) -> Result<Self> {
+        crate::features::require(crate::features::Feature::Mqtt, "[mqtt] in the config file")?;
+        unreachable!("the mqtt feature is not compiled in")
+    }
+
+    pub fn publish(&mut self, _topic: &str, _payload: &[u8]) -> Result<()> {
+        match self.never {}
+    }
+}
+
+#[cfg(feature = "mqtt")]
+impl MqttClient {
+    pub fn connect(config: &MqttConfig) -> Result<Self> {
+        let mut client = Self {
+            config: config.clone(),
+            stream: None,
+            last_used: Instant::now(),
+        };
+        client.stream = Some(client.open()?);
+        Ok(client)
+    }
+
+    fn open(&self) -> Result<TcpStream> {
+        let address = self.config.address()?;
+        let socket = address
+            .to_socket_addrs()
+            .with_context(|| format!("failed to resolve MQTT broker {}", address))?
+            .next()
+            .with_context(|| format!("MQTT broker {} has no address", address))?;
+        let mut stream = TcpStream::connect_timeout(&socket, TIMEOUT)
+            .with_context(|| format!("failed to connect to MQTT broker {}", address))?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+
+        let client_id = self
+            .config
+            .client_id
+            .clone()
+            .unwrap_or_else(|| format!("transcoderr-{}", std::process::id()));
+        stream.write_all(&connect_packet(
+            &client_id,
+            self.config.username.as_deref(),
+            self.config.password.as_deref(),
+        ))?;
+
+        let mut connack = [0u8; 4];
+        stream
+            .read_exact(&mut connack)
+            .context("MQTT broker did not acknowledge the connection")?;
+        if connack[0] != 0x20 {
+            bail!(
+                "unexpected MQTT packet 0x{:02x} instead of CONNACK",
+                connack[0]
+            );
+        }
+        match connack[3] {
+            0 => Ok(stream),
+            4 | 5 => bail!("MQTT broker rejected the credentials"),
+            code => bail!("MQTT broker refused the connection (code {})", code),
+        }
+    }
+
+    // Publish at QoS 0. Brokers drop idle connections during long encodes, and a write
+    // to a closed socket usually still succeeds, so a connection that is not `alive`
+    // (or a failed write) reconnects once and retries.
+    pub fn publish(&mut self, topic: &str, payload: &[u8]) -> Result<()> {
+        let packet = publish_packet(topic, payload);
+        let ping = self.last_used.elapsed() >= PING_AFTER;
+        self.last_used = Instant::now();
+        if let Some(stream) = self.stream.as_mut() {
+            if alive(stream, ping) && stream.write_all(&packet).is_ok() {
+                return Ok(());
+            }
+        }
+        self.stream = None;
+        let mut stream = self.open()?;
+        stream.write_all(&packet)?;
+        self.stream = Some(stream);
+        Ok(())
+    }
+}
+
+// Whether the broker still holds the connection. A broker that closed it left an end
+// of file to read (a 3.1.1 broker sends a publisher nothing else); `ping` also waits
+// for a PINGRESP, for connections dropped without a word.
+#[cfg(feature = "mqtt")]
+fn alive(stream: &mut TcpStream, ping: bool) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let pending = stream.peek(&mut [0u8; 1]);
+    if stream.set_nonblocking(false).is_err() {
+        return false;
+    }
+    match pending {
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+        _ => return false,
+    }
+    if !ping {
+        return true;
+    }
+    let mut pong = [0u8; 2];
+    stream.write_all(&[0xc0, 0x00]).is_ok()
+        && stream.read_exact(&mut pong).is_ok()
+        && pong == [0xd0, 0x00]
+}
+
+#[cfg(feature = "mqtt")]
+impl Drop for MqttClient {
+    fn drop(&mut self) {
+        if let Some(stream) = self.stream.as_mut() {
+            // DISCONNECT
+            let _ = stream.write_all(&[0xe0, 0x00]);
+        }
+    }
+}
+
+#[cfg(feature = "mqtt")]
+fn connect_packet(client_id: &str, username: Option<&str>, password: Option<&str>) -> Vec<u8> {
+    // Clean session; keep-alive 0 because events can be hours apart
+    let mut flags = 0x02u8;
+    if username.is_some() {
+        flags |= 0x80;
+    }
+    // A password alone is a protocol error (`validate` rejects that config)
+    let password = password.filter(|_| username.is_some());
+    if password.is_some() {
+        flags |= 0x40;
+    }
+    let mut body = Vec::new();
+    push_str(&mut body, "MQTT");
+    body.extend([0x04, flags, 0x00, 0x00]);
+    push_str(&mut body, client_id);
+    for field in [username, password].into_iter().flatten() {
+        push_str(&mut body, field);
+    }
+    packet(0x10, body)
+}
+
+#[cfg(feature = "mqtt")]
+fn publish_packet(topic: &str, payload: &[u8]) -> Vec<u8> {
+    let mut body = Vec::with_capacity(topic.len() + payload.len() + 2);
+    push_str(&mut body, topic);
+    body.extend_from_slice(payload);
+    packet(0x30, body)
+}
+
+#[cfg(feature = "mqtt")]
+fn packet(header: u8, body: Vec<u8>) -> Vec<u8> {
+    let mut out = vec![header];
+    // Remaining length: 7 bits per byte, high bit set while more bytes follow
+    let mut len = body.len();
+    loop {
+        let mut byte = (len % 128) as u8;
+        len /= 128;
+        if len > 0 {
+            byte |= 0x80;
+        }
+        out.push(byte);
+        if len == 0 {
+            break;
+        }
+    }
+    out.extend(body);
+    out
+}
+
+#[cfg(feature = "mqtt")]
+fn push_str(buf: &mut Vec<u8>, s: &str) {
+    // MQTT strings are length-prefixed and capped at 64 KiB
+    let bytes = &s.as_bytes()[..s.len().min(u16::MAX as usize)];
+    buf.extend((bytes.len() as u16).to_be_bytes());
+    buf.extend_from_slice(bytes);
+}
+
+#[cfg(test)]
+mod config_tests {
+    use super::*;
+
+    #[test]
+    fn a_password_needs_a_username() {
+        let mut config: MqttConfig = toml::from_str("broker = \"nas\"\npassword = \"p\"").unwrap();
+        assert!(config.validate().is_err());
+        config.username = Some("u".to_string());
+        assert!(config.validate().is_ok());
+    }
+}
+
+#[cfg(all(test, feature = "mqtt"))]
+mod tests {
+    use std::net::TcpListener;
+    use std::thread;
+
+    use super::*;
+
+    // Accept a client and acknowledge its CONNECT, returning the connection and packet
+    fn handshake(listener: &TcpListener) -> (TcpStream, Vec<u8>) {
+        let (mut conn, _) = listener.accept().unwrap();
+        let mut header = [0u8; 2];
+        conn.read_exact(&mut header).unwrap();
+        let mut connect = vec![0u8; header[1] as usize];
+        conn.read_exact(&mut connect).unwrap();
+        conn.write_all(&[0x20, 0x02, 0x00, 0x00]).unwrap();
+        (conn, connect)
+    }
+
+    fn config(broker: &str) -> MqttConfig {
+        MqttConfig {
+            broker: broker.to_string(),
+            topic_prefix: default_topic_prefix(),
+            username: None,
+            password: None,
+            client_id: None,
+        }
+    }
+
+    #[test]
+    fn broker_addresses() {
+        assert_eq!(config("nas").address().unwrap(), "nas:1883");
+        assert_eq!(config("mqtt://nas:1884/").address().unwrap(), "nas:1884");
+        assert_eq!(config("[::1]").address().unwrap(), "[::1]:1883");
+        assert_eq!(config("[::1]:1884").address().unwrap(), "[::1]:1884");
+        assert!(config("mqtts://nas").address().is_err());
+    }
+
+    #[test]
+    fn remaining_length_uses_continuation_bytes() {
+        assert_eq!(packet(0x30, vec![0; 127])[..2], [0x30, 0x7f]);
+        assert_eq!(packet(0x30, vec![0; 321])[..3], [0x30, 0xc1, 0x02]);
+    }
+
+    #[test]
+    fn publishes_to_a_broker() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let broker = thread::spawn(move || {
+            let (mut conn, _) = listener.accept().unwrap();
+            let mut header = [0u8; 2];
+            conn.read_exact(&mut header).unwrap();
+            let mut connect = vec![0u8; header[1] as usize];
+            conn.read_exact(&mut connect).unwrap();
+            conn.write_all(&[0x20, 0x02, 0x00, 0x00]).unwrap();
+            let mut rest = Vec::new();
+            conn.read_to_end(&mut rest).unwrap();
+            (header[0], connect, rest)
+        });
+
+        let mut cfg = config(&format!("127.0.0.1:{}", port));
+        cfg.username = Some("user".to_string());
+        cfg.password = Some("secret".to_string());
+        let mut client = MqttClient::connect(&cfg).unwrap();
+        client.publish(&cfg.topic("done"), b"{}").unwrap();
+        drop(client);
+
+        let (kind, connect, rest) = broker.join().unwrap();
+        assert_eq!(kind, 0x10);
+        assert_eq!(
+            connect[7], 0xc2,
+            "username, password and clean session flags"
+        );
+        assert_eq!(rest[0], 0x30);
+        assert_eq!(&rest[4..20], b"transcoderr/done");
+        assert_eq!(&rest[20..22], b"{}");
+        assert_eq!(&rest[22..], [0xe0, 0x00]);
+    }
+
+    #[test]
+    fn no_password_flag_without_a_username() {
+        let packet = connect_packet("c", None, Some("secret"));
+        assert_eq!(packet[9], 0x02, "only the clean session flag");
+        assert!(!packet.windows(6).any(|w| w == b"secret"));
+    }
+
+    #[test]
+    fn reconnects_after_the_broker_closed_the_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let cfg = config(&listener.local_addr().unwrap().to_string());
+        let broker = thread::spawn(move || {
+            // The first connection is closed as a broker does an idle one
+            drop(handshake(&listener));
+            let (mut conn, _) = handshake(&listener);
+            let mut rest = Vec::new();
+            conn.read_to_end(&mut rest).unwrap();
+            rest
+        });
+
+        let mut client = MqttClient::connect(&cfg).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        client.publish(&cfg.topic("done"), b"{}").unwrap();
+        drop(client);
+        let rest = broker.join().unwrap();
+        assert_eq!(rest[0], 0x30, "published on the new connection");
+        assert_eq!(&rest[rest.len() - 2..], [0xe0, 0x00]);
+    }
+
+    #[test]
+    fn idle_connections_are_checked_with_a_ping() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut conn, _) = listener.accept().unwrap();
+        let pong = thread::spawn(move || {
+            let mut ping = [0u8; 2];
+            conn.read_exact(&mut ping).unwrap();
+            conn.write_all(&[0xd0, 0x00]).unwrap();
+            (conn, ping)
+        });
+        client.set_read_timeout(Some(TIMEOUT)).unwrap();
+        let mut client = client;
+        assert!(alive(&mut client, true));
+        let (conn, ping) = pong.join().unwrap();
+        assert_eq!(ping, [0xc0, 0x00]);
+        assert!(alive(&mut client, false));
+        drop(conn);
+        thread::sleep(Duration::from_millis(50));
+        assert!(!alive(&mut client, false));
+    }
+}
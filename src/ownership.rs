@@ -0,0 +1,152 @@
+// file: src/ownership.rs
+// version: 0.2.0
+// guid: 10d60fc5-a549-4e2d-9526-e7ea167d9157
+
+//! Ownership and permission bits applied to written outputs (`--chown`, `--chmod`)
+
+use std::fmt;
+use std::path::Path;
+
+use anyhow::{Context, Result, anyhow, bail};
+use clap::Args;
+
+use crate::style::Marker;
+
+#[derive(Args, Debug, Clone, Default)]
+pub struct OutputOwnership {
+    /// Change owner of written outputs (USER:GROUP, USER or :GROUP; names or numeric ids)
+    #[arg(long, value_name = "USER:GROUP", value_parser = parse_owner)]
+    pub chown: Option<Owner>,
+    /// Set permission bits on written outputs (octal, e.g. 0664)
+    #[arg(long, value_name = "MODE", value_parser = parse_mode)]
+    pub chmod: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Owner {
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+impl fmt::Display for Owner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.uid, self.gid) {
+            (Some(u), Some(g)) => write!(f, "{}:{}", u, g),
+            (Some(u), None) => write!(f, "{}", u),
+            (None, Some(g)) => write!(f, ":{}", g),
+            (None, None) => write!(f, "-"),
+        }
+    }
+}
+
+impl OutputOwnership {
+    pub fn is_set(&self) -> bool {
+        self.chown.is_some() || self.chmod.is_some()
+    }
+
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(owner) = self.chown {
+            parts.push(format!("owner {}", owner));
+        }
+        if let Some(mode) = self.chmod {
+            parts.push(format!("mode {:04o}", mode));
+        }
+        parts.join(" and ")
+    }
+
+    // Apply the requested owner/mode to a finished output. Failures (typically EPERM when
+    // not running as root) are reported as warnings; the encode itself already succeeded.
+    pub fn apply(&self, path: &Path) {
+        if let Err(e) = self.try_apply(path) {
+            eprintln!(
+                "  {} could not set {} on {}: {:#}",
+                Marker::Warning,
+                self.describe(),
+                path.display(),
+                e
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    fn try_apply(&self, path: &Path) -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        if let Some(owner) = self.chown {
+            std::os::unix::fs::chown(path, owner.uid, owner.gid).context("chown failed")?;
+        }
+        if let Some(mode) = self.chmod {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+                .context("chmod failed")?;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn try_apply(&self, _path: &Path) -> Result<()> {
+        if self.is_set() {
+            bail!("--chown/--chmod are only supported on Unix platforms");
+        }
+        Ok(())
+    }
+}
+
+fn parse_mode(value: &str) -> Result<u32> {
+    let digits = value.strip_prefix("0o").unwrap_or(value);
+    let mode = u32::from_str_radix(digits, 8)
+        .map_err(|_| anyhow!("'{}' is not an octal mode like 0664", value))?;
+    if mode > 0o7777 {
+        bail!("mode {:o} is out of range (max 7777)", mode);
+    }
+    Ok(mode)
+}
+
+fn parse_owner(value: &str) -> Result<Owner> {
+    let (user, group) = match value.split_once(':') {
+        Some((u, g)) => (u, Some(g)),
+        None => (value, None),
+    };
+    let uid = match user {
+        "" => None,
+        u => Some(resolve_id(u, "/etc/passwd", "user")?),
+    };
+    let gid = match group {
+        None | Some("") => None,
+        Some(g) => Some(resolve_id(g, "/etc/group", "group")?),
+    };
+    if uid.is_none() && gid.is_none() {
+        bail!("expected USER:GROUP, USER or :GROUP");
+    }
+    Ok(Owner { uid, gid })
+}
+
+// Numeric ids pass through; names are looked up in the local passwd/group database
+// (`name:x:id:...`). Directory-service accounts should be given numerically.
+fn resolve_id(name: &str, database: &str, kind: &str) -> Result<u32> {
+    if let Ok(id) = name.parse::<u32>() {
+        return Ok(id);
+    }
+    let contents = std::fs::read_to_string(database).with_context(|| {
+        format!(
+            "cannot resolve {} '{}': failed to read {}",
+            kind, name, database
+        )
+    })?;
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(':');
+            let entry = fields.next()?;
+            let id = fields.nth(1)?.parse::<u32>().ok()?;
+            (entry == name).then_some(id)
+        })
+        .next()
+        .ok_or_else(|| {
+            anyhow!(
+                "unknown {} '{}' (use a numeric id for non-local accounts)",
+                kind,
+                name
+            )
+        })
+}
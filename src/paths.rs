@@ -0,0 +1,272 @@
+// file: src/paths.rs
+// version: 0.5.0
+// guid: 02553e2c-7350-4f23-9f8e-ac613168acac
+
+//! Output path planning: safe default names and platform-aware path comparison
+
+use std::ffi::{OsStr, OsString};
+use std::path::{Component, Path, PathBuf, Prefix};
+
+use anyhow::Result;
+
+// What `suffixed_output` adds to the input's stem
+const SUFFIX: &str = "_transcoded";
+
+// Resolve a safe output path based on input and optional user-provided output.
+// Rules:
+// - If user output is provided and is not identical to input path, use it.
+// - If user output is identical to input (same full path), or not provided,
+//   create `<stem>_transcoded.<ext>` next to the input. Default ext is `mkv`.
+pub fn resolve_output_path(
+    input: &Path,
+    output_opt: Option<&Path>,
+    default_ext: Option<&str>,
+) -> Result<PathBuf> {
+    let in_path = input.canonicalize().unwrap_or_else(|_| input.to_path_buf());
+
+    if let Some(out_path_try) = output_opt {
+        let out_path_abs = if out_path_try.is_absolute() {
+            out_path_try.to_path_buf()
+        } else {
+            // resolve relative to current dir
+            std::env::current_dir()
+                .unwrap_or_else(|_| PathBuf::from("."))
+                .join(out_path_try)
+        };
+
+        // If identical to input, compute a safe sibling with suffix
+        if paths_equivalent(&in_path, &out_path_abs) {
+            return Ok(suffixed_output(&in_path, default_ext.unwrap_or("mkv")));
+        }
+        return Ok(out_path_abs);
+    }
+
+    // No output provided: compute default sibling with suffix and mkv
+    Ok(suffixed_output(&in_path, default_ext.unwrap_or("mkv")))
+}
+
+pub fn paths_equivalent(a: &Path, b: &Path) -> bool {
+    // Try canonicalize to compare real paths, fall back to normalized comparison
+    let ca = a.canonicalize().unwrap_or_else(|_| a.to_path_buf());
+    let cb = b.canonicalize().unwrap_or_else(|_| b.to_path_buf());
+    ca == cb || comparison_key(&ca) == comparison_key(&cb)
+}
+
+// `path` with `.` and `..` resolved without touching the disk; None if a `..` climbs
+// above its start
+pub fn normalize(path: &Path) -> Option<PathBuf> {
+    let mut normal = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match normal.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    normal.pop();
+                }
+                _ => return None,
+            },
+            component => normal.push(component),
+        }
+    }
+    Some(normal)
+}
+
+// Path of `path` relative to `base`. Falls back to a component-wise comparison so that
+// `D:\Media\show.mkv` still resolves against a base given as `d:/media` on Windows.
+pub fn relative_to(path: &Path, base: &Path) -> Option<PathBuf> {
+    if let Ok(rel) = path.strip_prefix(base) {
+        return Some(rel.to_path_buf());
+    }
+
+    let mut remaining = path.components();
+    for base_component in base.components() {
+        let path_component = remaining.next()?;
+        if component_key(base_component) != component_key(path_component) {
+            return None;
+        }
+    }
+    Some(remaining.as_path().to_path_buf())
+}
+
+// `output`'s place under `output_dir` with `/` separators, which every host agrees on
+// however the share is mounted; the whole path if it is not under it
+pub fn output_key(output: &Path, output_dir: &Path) -> String {
+    relative_to(output, output_dir)
+        .unwrap_or_else(|| output.to_path_buf())
+        .to_string_lossy()
+        .replace('\\', "/")
+}
+
+// Key used to decide whether two paths name the same file. On Windows drive letters,
+// verbatim prefixes (`\\?\`), separators and case are normalized; elsewhere the
+// components are compared exactly, since those filesystems are usually case-sensitive.
+pub fn comparison_key(path: &Path) -> OsString {
+    let mut key = OsString::new();
+    for component in path.components() {
+        if !key.is_empty() {
+            key.push("/");
+        }
+        key.push(component_key(component));
+    }
+    key
+}
+
+fn component_key(component: Component<'_>) -> OsString {
+    match component {
+        Component::Prefix(prefix) => match prefix.kind() {
+            Prefix::Disk(drive) | Prefix::VerbatimDisk(drive) => {
+                OsString::from(format!("{}:", drive.to_ascii_lowercase() as char))
+            }
+            _ => fold_case(prefix.as_os_str()),
+        },
+        other => fold_case(other.as_os_str()),
+    }
+}
+
+#[cfg(windows)]
+fn fold_case(part: &OsStr) -> OsString {
+    OsString::from(part.to_string_lossy().to_lowercase())
+}
+
+#[cfg(not(windows))]
+fn fold_case(part: &OsStr) -> OsString {
+    part.to_os_string()
+}
+
+pub fn suffixed_output(input_path: &Path, out_ext: &str) -> PathBuf {
+    let parent = input_path.parent().unwrap_or_else(|| Path::new("."));
+    let stem = strict_stem(input_path);
+    let mut final_name = OsString::with_capacity(stem.len() + 1 + 12 + out_ext.len());
+    final_name.push(&stem);
+    final_name.push(SUFFIX);
+    final_name.push(".");
+    final_name.push(out_ext);
+    parent.join(final_name)
+}
+
+// Whether `path` looks like an in-place output written by `suffixed_output`
+pub fn is_suffixed_output(path: &Path) -> bool {
+    unsuffixed_stem(path).is_some()
+}
+
+// The stem of the input an in-place output was made from: `a_transcoded.mkv` -> `a`.
+// Non-UTF8 stems keep their raw bytes, since migrate renames files after them.
+pub fn unsuffixed_stem(path: &Path) -> Option<OsString> {
+    let stem = strict_stem(path);
+    let kept = stem.as_encoded_bytes().strip_suffix(SUFFIX.as_bytes())?;
+    // SAFETY: the bytes are cut right before an ASCII suffix, a boundary the encoding
+    // allows splitting at
+    Some(unsafe { OsStr::from_encoded_bytes_unchecked(kept) }.to_os_string())
+}
+
+// Derive the filename stem using the LAST '.' before the extension.
+// This avoids truncating names that legitimately contain dots (e.g., "Episode 1.11 ... .mkv").
+// For dotfiles (e.g., ".bashrc"), or names without extension, returns the whole name.
+// Non-UTF8 names keep their raw bytes via `file_stem` instead of being replaced.
+pub fn strict_stem(path: &Path) -> OsString {
+    if let (Some(name_os), Some(ext_os)) = (path.file_name(), path.extension()) {
+        if let (Some(name), Some(ext)) = (name_os.to_str(), ext_os.to_str()) {
+            if !ext.is_empty() {
+                let needle = format!(".{}", ext);
+                if let Some(pos) = name.rfind(&needle) {
+                    if pos > 0 {
+                        return OsString::from(&name[..pos]);
+                    }
+                }
+            }
+            // Fallback: no recognizable extension position; return full name
+            return OsString::from(name);
+        }
+    }
+    // Ultimate fallback
+    path.file_stem()
+        .map(OsStr::to_os_string)
+        .unwrap_or_else(|| OsString::from("output"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_resolves_dots_without_leaving_the_start() {
+        assert_eq!(
+            normalize(Path::new("/media/./tv/../movies/a.mkv")),
+            Some(PathBuf::from("/media/movies/a.mkv"))
+        );
+        assert_eq!(normalize(Path::new("/media/..")), Some(PathBuf::from("/")));
+        assert_eq!(normalize(Path::new("/media/../..")), None);
+        assert_eq!(normalize(Path::new("../a.mkv")), None);
+    }
+
+    #[test]
+    fn relative_to_strips_the_base() {
+        let path = Path::new("/media/tv/Show/e1.mkv");
+        assert_eq!(
+            relative_to(path, Path::new("/media/tv")),
+            Some(PathBuf::from("Show/e1.mkv"))
+        );
+        assert_eq!(relative_to(path, Path::new("/media/movies")), None);
+        assert_eq!(
+            relative_to(Path::new("/media"), Path::new("/media/tv")),
+            None
+        );
+        assert_eq!(
+            output_key(Path::new("/out/Show/e1.mkv"), Path::new("/out")),
+            "Show/e1.mkv"
+        );
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn comparison_keys_keep_case_off_windows() {
+        assert_eq!(
+            comparison_key(Path::new("/media//tv/./Show")),
+            comparison_key(Path::new("/media/tv/Show"))
+        );
+        assert_ne!(
+            comparison_key(Path::new("/Media/TV")),
+            comparison_key(Path::new("/media/tv"))
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn windows_paths_match_across_drive_case_separators_and_verbatim_prefixes() {
+        let key = comparison_key(Path::new(r"D:\Media\Show"));
+        assert_eq!(key, comparison_key(Path::new("d:/media/show")));
+        assert_eq!(key, comparison_key(Path::new(r"\\?\D:\Media\Show")));
+        assert_ne!(key, comparison_key(Path::new(r"E:\Media\Show")));
+        assert_eq!(
+            relative_to(Path::new(r"D:\Media\Show\e1.mkv"), Path::new("d:/media")),
+            Some(PathBuf::from(r"Show\e1.mkv"))
+        );
+        assert_eq!(
+            relative_to(Path::new(r"\\?\D:\Media\e1.mkv"), Path::new(r"d:\media")),
+            Some(PathBuf::from("e1.mkv"))
+        );
+        assert!(paths_equivalent(
+            Path::new(r"D:\Missing\A.mkv"),
+            Path::new("d:/missing/a.mkv")
+        ));
+    }
+
+    #[test]
+    fn suffixed_outputs_map_back_to_their_stems() {
+        let output = suffixed_output(Path::new("/tv/Show 1.11.avi"), "mkv");
+        assert_eq!(output, Path::new("/tv/Show 1.11_transcoded.mkv"));
+        assert!(is_suffixed_output(&output));
+        assert_eq!(unsuffixed_stem(&output), Some(OsString::from("Show 1.11")));
+        assert!(!is_suffixed_output(Path::new("/tv/Show 1.11.avi")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_stems_keep_their_bytes() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let output = suffixed_output(Path::new(OsStr::from_bytes(b"/tv/Caf\xe9.avi")), "mkv");
+        assert_eq!(output.as_os_str().as_bytes(), b"/tv/Caf\xe9_transcoded.mkv");
+        assert_eq!(unsuffixed_stem(&output).unwrap().as_bytes(), b"Caf\xe9");
+    }
+}
@@ -0,0 +1,495 @@
+// file: src/plugins.rs
+// version: 0.2.0
+// guid: 9c3e71a4-5b2d-4f86-a0e7-d41b6c8f2a59
+
+//! Plugins: external programs that add per-file analyzers (in-house QC), output naming
+//! and notifier backends without changes to transcoderr.
+//!
+//! Each `[[plugins]]` entry of the config names a command, started once per run on first
+//! use and spoken to in JSON-RPC 2.0, one message per line on its stdin and stdout. The
+//! `initialize` call passes the protocol version and gets back what the plugin provides:
+//!
+//! - `analyze` `{input, output}` after each successful encode, answered `{ok, message}`;
+//!   `ok: false` fails the job
+//! - `name` `{input, relative, ext}` while a batch is planned, answered `{relative}` (a
+//!   path under the output root ending in `.ext`) or `null` for the usual name
+//! - `notify`: every lifecycle event as a notification, as written to `--event-log`
+//!
+//! Anything else a plugin sends is a `log` notification (`{message}`), shown on stderr.
+//! `scripts/plugins/example_plugin.py` implements all three.
+
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result, anyhow, bail};
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+use crate::events::path_str;
+use crate::schema;
+use crate::style::Marker;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct PluginConfig {
+    /// Shown in messages and by `transcoderr plugins`
+    pub name: String,
+    /// Program and its arguments, e.g. `["python3", "/opt/qc/plugin.py"]`
+    pub command: Vec<String>,
+    /// Seconds to wait for each reply (an analyzer may take a while per file)
+    #[serde(default = "default_timeout_seconds")]
+    pub timeout_seconds: u64,
+}
+
+fn default_timeout_seconds() -> u64 {
+    300
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    Analyze,
+    Name,
+    Notify,
+}
+
+impl Capability {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "analyze" => Some(Capability::Analyze),
+            "name" => Some(Capability::Name),
+            "notify" => Some(Capability::Notify),
+            _ => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Capability::Analyze => "analyze",
+            Capability::Name => "name",
+            Capability::Notify => "notify",
+        }
+    }
+}
+
+// One line from a plugin: a reply (`id`) or a notification (`method`)
+#[derive(Deserialize)]
+struct Message {
+    id: Option<u64>,
+    method: Option<String>,
+    #[serde(default)]
+    params: Value,
+    #[serde(default)]
+    result: Value,
+    error: Option<RpcError>,
+}
+
+#[derive(Deserialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct Initialized {
+    #[serde(default)]
+    provides: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Analysis {
+    ok: bool,
+    message: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Naming {
+    relative: String,
+}
+
+// A running plugin
+struct Plugin {
+    config: PluginConfig,
+    child: Child,
+    stdin: Option<ChildStdin>,
+    // stdout lines, read on their own thread so replies can time out
+    lines: Receiver<String>,
+    provides: Vec<Capability>,
+    next_id: u64,
+}
+
+impl Plugin {
+    fn start(config: &PluginConfig) -> Result<Self> {
+        let (program, args) = config
+            .command
+            .split_first()
+            .context("plugin command is empty")?;
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .with_context(|| format!("failed to start {}", program))?;
+        let stdout = child.stdout.take().context("plugin has no stdout")?;
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else { break };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        let mut plugin = Self {
+            config: config.clone(),
+            stdin: child.stdin.take(),
+            child,
+            lines,
+            provides: Vec::new(),
+            next_id: 0,
+        };
+        let reply = plugin.call(
+            "initialize",
+            json!({ "protocol": schema::PLUGINS, "version": env!("CARGO_PKG_VERSION") }),
+        )?;
+        let initialized: Initialized =
+            serde_json::from_value(reply).context("unexpected reply to 'initialize'")?;
+        for name in &initialized.provides {
+            match Capability::parse(name) {
+                Some(capability) => plugin.provides.push(capability),
+                None => eprintln!(
+                    "{} plugin '{}' provides '{}', which this version does not use",
+                    Marker::Warning,
+                    config.name,
+                    name
+                ),
+            }
+        }
+        Ok(plugin)
+    }
+
+    fn provides(&self, capability: Capability) -> bool {
+        self.provides.contains(&capability)
+    }
+
+    fn send(&mut self, message: &Value) -> Result<()> {
+        let stdin = self.stdin.as_mut().context("plugin is not running")?;
+        let mut line = serde_json::to_vec(message)?;
+        line.push(b'\n');
+        stdin
+            .write_all(&line)
+            .and_then(|()| stdin.flush())
+            .context("plugin is not running")
+    }
+
+    fn notify(&mut self, method: &str, params: Value) -> Result<()> {
+        self.send(&json!({ "jsonrpc": "2.0", "method": method, "params": params }))
+    }
+
+    // Send a request and wait for its reply, showing `log` notifications meanwhile.
+    // Replies to earlier requests that timed out are dropped.
+    fn call(&mut self, method: &str, params: Value) -> Result<Value> {
+        self.next_id += 1;
+        let id = self.next_id;
+        self.send(&json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }))?;
+        let timeout = Duration::from_secs(self.config.timeout_seconds);
+        let deadline = Instant::now() + timeout;
+        loop {
+            let line = match self
+                .lines
+                .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            {
+                Ok(line) => line,
+                Err(RecvTimeoutError::Timeout) => {
+                    bail!("no reply to '{}' within {} s", method, timeout.as_secs())
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    bail!("exited before replying to '{}'", method)
+                }
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            let message: Message = serde_json::from_str(&line)
+                .with_context(|| format!("sent a line that is not JSON-RPC: {}", line))?;
+            match (message.id, message.method.as_deref()) {
+                (Some(reply), _) if reply == id => {
+                    return match message.error {
+                        Some(error) => bail!("{} (code {})", error.message, error.code),
+                        None => Ok(message.result),
+                    };
+                }
+                (None, Some("log")) => {
+                    let text = match &message.params["message"] {
+                        Value::String(text) => text.clone(),
+                        other => other.to_string(),
+                    };
+                    eprintln!("  [{}] {}", self.config.name, text);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Drop for Plugin {
+    // Closing stdin asks the plugin to exit; one that does not is killed after a moment
+    fn drop(&mut self) {
+        self.stdin.take();
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while Instant::now() < deadline {
+            match self.child.try_wait() {
+                Ok(Some(_)) | Err(_) => return,
+                Ok(None) => thread::sleep(Duration::from_millis(20)),
+            }
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// The configured plugins, started together the first time one is needed
+#[derive(Default)]
+pub struct Plugins {
+    configs: Vec<PluginConfig>,
+    running: Mutex<Option<Vec<Plugin>>>,
+    warned: AtomicBool,
+}
+
+impl Plugins {
+    pub fn new(configs: Vec<PluginConfig>) -> Result<Self> {
+        for (i, config) in configs.iter().enumerate() {
+            if config.name.trim().is_empty() {
+                bail!("[[plugins]] entry {} needs a `name`", i + 1);
+            }
+            if config.command.is_empty() {
+                bail!("plugin '{}' needs a `command`", config.name);
+            }
+            if config.timeout_seconds == 0 {
+                bail!(
+                    "plugin '{}': timeout-seconds must be at least 1",
+                    config.name
+                );
+            }
+            if configs[..i].iter().any(|c| c.name == config.name) {
+                bail!("two plugins are named '{}'", config.name);
+            }
+        }
+        Ok(Self {
+            configs,
+            running: Mutex::new(None),
+            warned: AtomicBool::new(false),
+        })
+    }
+
+    // Run `f` on every started plugin that provides `capability`, in config order,
+    // until it returns a value
+    fn first<T>(
+        &self,
+        capability: Capability,
+        mut f: impl FnMut(&mut Plugin) -> Result<Option<T>>,
+    ) -> Result<Option<T>> {
+        if self.configs.is_empty() {
+            return Ok(None);
+        }
+        let mut running = self.running.lock().unwrap_or_else(PoisonError::into_inner);
+        if running.is_none() {
+            let started = self
+                .configs
+                .iter()
+                .map(|config| {
+                    Plugin::start(config).map_err(|e| anyhow!("plugin '{}': {:#}", config.name, e))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            *running = Some(started);
+        }
+        for plugin in running.iter_mut().flatten() {
+            if !plugin.provides(capability) {
+                continue;
+            }
+            // Flattened rather than a context, so one-line error reports keep the cause
+            let value =
+                f(plugin).map_err(|e| anyhow!("plugin '{}': {:#}", plugin.config.name, e))?;
+            if let Some(value) = value {
+                return Ok(Some(value));
+            }
+        }
+        Ok(None)
+    }
+
+    // A plugin's name for a batch output, relative to the output root; `None` keeps
+    // the usual mirrored name
+    pub fn output_name(&self, input: &Path, relative: &Path, ext: &str) -> Result<Option<PathBuf>> {
+        let params = json!({
+            "input": path_str(input),
+            "relative": path_str(relative).replace('\\', "/"),
+            "ext": ext,
+        });
+        self.first(Capability::Name, |plugin| {
+            let reply = plugin.call("name", params.clone())?;
+            if reply.is_null() {
+                return Ok(None);
+            }
+            let naming: Naming =
+                serde_json::from_value(reply).context("unexpected reply to 'name'")?;
+            valid_name(&naming.relative, ext).map(Some)
+        })
+    }
+
+    // Run every analyzer on a finished output. The first rejection is the error; notes
+    // from analyzers that pass are printed.
+    pub fn analyze(&self, input: &Path, output: &Path) -> Result<()> {
+        let params = json!({ "input": path_str(input), "output": path_str(output) });
+        let rejected = self.first(Capability::Analyze, |plugin| {
+            let reply = plugin.call("analyze", params.clone())?;
+            let analysis: Analysis =
+                serde_json::from_value(reply).context("unexpected reply to 'analyze'")?;
+            let message = analysis.message.unwrap_or_default();
+            if analysis.ok {
+                if !message.is_empty() {
+                    println!("  {}: {}", plugin.config.name, message);
+                }
+                return Ok(None);
+            }
+            Ok(Some(format!(
+                "rejected by plugin '{}': {}",
+                plugin.config.name,
+                match message.is_empty() {
+                    true => "no reason given",
+                    false => &message,
+                }
+            )))
+        })?;
+        match rejected {
+            Some(reason) => bail!(reason),
+            None => Ok(()),
+        }
+    }
+
+    // Pass an event record to every notifier. Like the other event sinks a failing one
+    // must not fail the encode, so errors are only reported once on stderr.
+    pub fn notify(&self, record: &Value) {
+        let result = self.first(Capability::Notify, |plugin| {
+            plugin.notify("notify", record.clone())?;
+            Ok(None::<()>)
+        });
+        if let Err(e) = result {
+            if !self.warned.swap(true, Ordering::Relaxed) {
+                eprintln!("{} could not notify plugins: {:#}", Marker::Warning, e);
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.configs.is_empty()
+    }
+
+    // `transcoderr plugins`: start each plugin and show what it provides
+    pub fn list(&self) -> Result<()> {
+        if self.configs.is_empty() {
+            println!("No plugins configured (add [[plugins]] entries to the config file)");
+            return Ok(());
+        }
+        for config in &self.configs {
+            println!("{}: {}", config.name, config.command.join(" "));
+            match Plugin::start(config) {
+                Ok(plugin) => {
+                    let provides: Vec<&str> = plugin.provides.iter().map(|c| c.as_str()).collect();
+                    match provides.is_empty() {
+                        true => println!("  provides nothing"),
+                        false => println!("  provides: {}", provides.join(", ")),
+                    }
+                }
+                Err(e) => println!("  {} {:#}", Marker::Error, e),
+            }
+        }
+        Ok(())
+    }
+}
+
+// A plugin-chosen output name must stay under the output root and keep the extension
+fn valid_name(relative: &str, ext: &str) -> Result<PathBuf> {
+    let path = PathBuf::from(relative);
+    let inside = path.components().count() > 0
+        && path
+            .components()
+            .all(|part| matches!(part, Component::Normal(_)));
+    if !inside {
+        bail!("'{}' is not a path inside the output directory", relative);
+    }
+    let has_ext = path
+        .extension()
+        .is_some_and(|e| e.to_string_lossy().eq_ignore_ascii_case(ext));
+    if !has_ext {
+        bail!("'{}' does not end in .{}", relative, ext);
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_stay_under_the_output_root() {
+        assert_eq!(
+            valid_name("Show/Season 01/Show - S01E02.mkv", "mkv").unwrap(),
+            PathBuf::from("Show/Season 01/Show - S01E02.mkv")
+        );
+        assert!(valid_name("../escape.mkv", "mkv").is_err());
+        assert!(valid_name("Show/../../escape.mkv", "mkv").is_err());
+        assert!(valid_name("/abs/path.mkv", "mkv").is_err());
+        assert!(valid_name("", "mkv").is_err());
+        assert!(valid_name("Show/Episode.mp4", "mkv").is_err());
+    }
+
+    #[test]
+    fn configs_are_checked() {
+        let plugin = |name: &str, command: &[&str]| PluginConfig {
+            name: name.to_string(),
+            command: command.iter().map(|s| s.to_string()).collect(),
+            timeout_seconds: 1,
+        };
+        assert!(Plugins::new(vec![plugin("qc", &["qc-plugin"])]).is_ok());
+        assert!(Plugins::new(vec![plugin("qc", &[])]).is_err());
+        assert!(Plugins::new(vec![plugin("qc", &["a"]), plugin("qc", &["b"])]).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn speaks_json_rpc_over_stdio() {
+        // Replies with the request's id; logs once, then rejects every output
+        let script = r#"
+while read -r line; do
+  id=$(printf '%s' "$line" | sed -n 's/.*"id":\([0-9]*\).*/\1/p')
+  case "$line" in
+    *'"initialize"'*) echo "{\"jsonrpc\":\"2.0\",\"id\":$id,\"result\":{\"provides\":[\"analyze\",\"name\",\"teleport\"]}}" ;;
+    *'"name"'*) echo "{\"jsonrpc\":\"2.0\",\"id\":$id,\"result\":{\"relative\":\"renamed/x.mkv\"}}" ;;
+    *'"analyze"'*)
+      echo '{"jsonrpc":"2.0","method":"log","params":{"message":"checking"}}'
+      echo "{\"jsonrpc\":\"2.0\",\"id\":$id,\"result\":{\"ok\":false,\"message\":\"too dark\"}}" ;;
+  esac
+done
+"#;
+        let plugins = Plugins::new(vec![PluginConfig {
+            name: "qc".to_string(),
+            command: vec!["sh".to_string(), "-c".to_string(), script.to_string()],
+            timeout_seconds: 10,
+        }])
+        .unwrap();
+        let name = plugins
+            .output_name(Path::new("/in/x.mp4"), Path::new("x.mp4"), "mkv")
+            .unwrap();
+        assert_eq!(name, Some(PathBuf::from("renamed/x.mkv")));
+        let error = plugins
+            .analyze(Path::new("/in/x.mp4"), Path::new("/out/x.mkv"))
+            .unwrap_err();
+        assert_eq!(format!("{:#}", error), "rejected by plugin 'qc': too dark");
+    }
+}
@@ -0,0 +1,874 @@
+// file: src/presets.rs
+// version: 0.15.1
+// guid: 5e8b3f17-a2c6-4d94-8f01-b7d6c4e29a35
+
+//! Encoding presets: the built-in table, the user's own in
+//! `~/.config/transcoderr/presets.toml` (`[[preset]]` entries, which may replace a
+//! built-in of the same name) and presets installed with `presets import` (one TOML
+//! file each in `~/.config/transcoderr/presets/`).
+//!
+//! A preset may name the container it is written to by default (`container`) and the
+//! only ones its streams fit in (`containers`); batch's `--ext` and transcode's output
+//! name follow the first and are checked against the second.
+//!
+//! It may also set variables (`[env]`) and a working directory (`cwd`) for its ffmpeg
+//! runs, e.g. `CUDA_VISIBLE_DEVICES` or the folder its shader files are relative to.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use clap::Subcommand;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::i18n;
+use crate::style::{Marker, Table};
+use crate::tools::RunEnv;
+use crate::whatif::WhatIf;
+
+pub const DEFAULT_ACODEC: &str = "aac";
+// Output extension when neither the command nor the preset names one
+pub const DEFAULT_CONTAINER: &str = "mkv";
+// Not a table entry: picks a preset per file from probed content (see recommend.rs)
+pub const AUTO: &str = "auto";
+
+#[derive(Subcommand, Debug)]
+pub enum PresetsAction {
+    /// Print what a preset expands to: codecs, container, ffmpeg args and environment
+    Show {
+        /// Preset name or alias
+        name: String,
+    },
+    /// Print a preset as a shareable TOML file (checksum goes to stderr)
+    Export {
+        /// Preset name or alias
+        name: String,
+    },
+    /// Install a preset file from a path or http(s) URL
+    Import {
+        /// Path or URL of a preset file
+        source: String,
+        /// Expected SHA-256 of the file; the import fails if it does not match
+        #[arg(long, value_name = "HEX")]
+        sha256: Option<String>,
+        /// Install without asking for confirmation
+        #[arg(long, short = 'y')]
+        yes: bool,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Preset {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vcodec: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acodec: Option<String>,
+    /// ffmpeg args added after the standard ones
+    #[serde(default)]
+    pub extra: Vec<String>,
+    /// Output extension used when none is given, e.g. `mp4`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub container: Option<String>,
+    /// The only output extensions the preset's streams fit in (empty: any), e.g. `mkv`
+    /// for ASS subtitles with their fonts
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub containers: Vec<String>,
+    /// Working directory of its ffmpeg runs (relative paths in `extra` start here)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<PathBuf>,
+    /// Variables set for its ffmpeg runs, e.g. `CUDA_VISIBLE_DEVICES = "1"`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+}
+
+impl Preset {
+    fn matches(&self, name: &str) -> bool {
+        self.name == name || self.aliases.iter().any(|alias| alias == name)
+    }
+
+    fn prefer(mut self, container: &str) -> Self {
+        self.container = Some(container.to_string());
+        self
+    }
+
+    fn only_in(mut self, containers: &[&str]) -> Self {
+        self.containers = containers.iter().map(|c| c.to_string()).collect();
+        self
+    }
+
+    fn fits(&self, ext: &str) -> bool {
+        self.containers.is_empty() || self.containers.iter().any(|c| c.eq_ignore_ascii_case(ext))
+    }
+}
+
+fn builtin(
+    name: &str,
+    aliases: &[&str],
+    description: &str,
+    vcodec: &str,
+    acodec: &str,
+    extra: &[&str],
+) -> Preset {
+    Preset {
+        name: name.to_string(),
+        description: Some(description.to_string()),
+        aliases: aliases.iter().map(|a| a.to_string()).collect(),
+        vcodec: Some(vcodec.to_string()),
+        acodec: Some(acodec.to_string()),
+        extra: extra.iter().map(|a| a.to_string()).collect(),
+        container: None,
+        containers: Vec::new(),
+        cwd: None,
+        env: BTreeMap::new(),
+    }
+}
+
+const ANIME_X265: [&str; 18] = [
+    "-map",
+    "0:V?",
+    "-map",
+    "0:a?",
+    "-map",
+    "0:s?",
+    "-map",
+    "0:t?",
+    "-crf",
+    "19",
+    "-preset",
+    "slow",
+    "-tune",
+    "animation",
+    "-x265-params",
+    "aq-mode=3:psy-rd=1.0:psy-rdoq=1.0:deblock=-1,-1:bframes=8",
+    "-b:a",
+    "160k",
+];
+
+// Phone footage: variable frame rate and per-stream creation dates. passthrough keeps
+// the original timestamps instead of duplicating or dropping frames to a constant rate.
+const HOME_VIDEO: [&str; 12] = [
+    "-crf",
+    "22",
+    "-preset",
+    "slow",
+    "-fps_mode",
+    "passthrough",
+    "-map_metadata:s:v",
+    "0:s:v",
+    "-map_metadata:s:a",
+    "0:s:a",
+    "-b:a",
+    "192k",
+];
+
+// Empty values remove the location tags phones write (QuickTime and Android style)
+const STRIP_GPS: [&str; 6] = [
+    "-metadata",
+    "location=",
+    "-metadata",
+    "location-eng=",
+    "-metadata",
+    "com.apple.quicktime.location.ISO6709=",
+];
+
+const SCREENCAST: [&str; 20] = [
+    "-crf",
+    "23",
+    "-preset",
+    "slow",
+    "-tune",
+    "stillimage",
+    "-g",
+    "600",
+    "-keyint_min",
+    "30",
+    "-sc_threshold",
+    "40",
+    "-pix_fmt",
+    "yuv420p",
+    "-b:a",
+    "48k",
+    "-ac",
+    "1",
+    "-application",
+    "voip",
+];
+
+fn builtins() -> Vec<Preset> {
+    vec![
+        // "Original quality" intent: visually lossless-ish h265 and high-quality audio.
+        // x265 CRF 18 is commonly considered visually lossless; preset slow for quality.
+        // AAC at 256k for high-quality, universally compatible audio.
+        builtin(
+            "original-h265",
+            &["original"],
+            "Visually lossless h265 (CRF 18, slow) with AAC 256k",
+            "libx265",
+            "aac",
+            &["-crf", "18", "-preset", "slow", "-b:a", "256k"],
+        ),
+        builtin(
+            "tv-h265-fast",
+            &["tv-fast"],
+            "Faster h265 for TV episodes (CRF 22, medium) with AAC 160k",
+            "libx265",
+            "aac",
+            &["-crf", "22", "-preset", "medium", "-b:a", "160k"],
+        ),
+        builtin(
+            "movie-quality",
+            &["movie"],
+            "High-quality h265 for films (CRF 16, slow) with AAC 320k",
+            "libx265",
+            "aac",
+            &["-crf", "16", "-preset", "slow", "-b:a", "320k"],
+        ),
+        // Animation: flat areas and sharp line art. x265's animation tune plus stronger
+        // adaptive quantization and softer psy settings avoid banding and ringing; all
+        // subtitle and attachment streams are kept because ASS styling needs its fonts,
+        // which only Matroska holds.
+        builtin(
+            "anime",
+            &[],
+            "h265 tuned for animation with Opus audio; keeps ASS subtitles and fonts",
+            "libx265",
+            "libopus",
+            &ANIME_X265,
+        )
+        .only_in(&["mkv"]),
+        builtin(
+            "anime-denoise",
+            &[],
+            "anime plus a light hqdn3d denoise for noisy or upscaled sources",
+            "libx265",
+            "libopus",
+            &[&ANIME_X265[..], &["-vf", "hqdn3d=1.5:1.5:6:6"]].concat(),
+        )
+        .only_in(&["mkv"]),
+        builtin(
+            "anime-av1",
+            &[],
+            "SVT-AV1 tuned for animation with Opus audio; keeps ASS subtitles and fonts",
+            "libsvtav1",
+            "libopus",
+            &[
+                "-map",
+                "0:V?",
+                "-map",
+                "0:a?",
+                "-map",
+                "0:s?",
+                "-map",
+                "0:t?",
+                "-crf",
+                "30",
+                "-preset",
+                "6",
+                "-svtav1-params",
+                "tune=0:enable-overlays=1:scd=1",
+                "-b:a",
+                "160k",
+            ],
+        )
+        .only_in(&["mkv"]),
+        // Desktop captures: mostly static frames with sharp text. x264's stillimage tune
+        // keeps text crisp, a long GOP lets static stretches cost next to nothing, and
+        // speech-only audio goes to low-bitrate mono Opus. Captures are often RGB/4:4:4,
+        // which many players cannot decode, hence yuv420p.
+        builtin(
+            "screencast",
+            &[],
+            "Desktop captures: crisp text, long GOP, low-bitrate mono Opus speech",
+            "libx264",
+            "libopus",
+            &SCREENCAST,
+        ),
+        builtin(
+            "screencast-30fps",
+            &[],
+            "screencast reduced to 30 fps for high-refresh captures",
+            "libx264",
+            "libopus",
+            &[&SCREENCAST[..], &["-r", "30"]].concat(),
+        ),
+        // Archiving phone footage: creation dates and camera tags come along via the
+        // standard -map_metadata 0, GPS only on request. ffmpeg's autorotate bakes the
+        // rotation flag into the encoded frames, so players without rotation support
+        // still show the clip upright. Shaky clips can add --stabilize.
+        builtin(
+            "home-video",
+            &[],
+            "Phone/camcorder archive: h265, keeps VFR timing and creation dates, drops GPS",
+            "libx265",
+            "aac",
+            &[&HOME_VIDEO[..], &STRIP_GPS[..]].concat(),
+        ),
+        builtin(
+            "home-video-gps",
+            &[],
+            "home-video that also keeps GPS location tags",
+            "libx265",
+            "aac",
+            &HOME_VIDEO,
+        ),
+        // Remote streaming over capped uplinks: capped CRF so busy scenes never exceed
+        // what the link carries (video cap plus audio stays under the name's rate), a
+        // two-second buffer, at most the height that rate can carry cleanly, and
+        // 8-bit 4:2:0 that every client decodes, in MP4 unless told otherwise. vbv.rs
+        // checks the cap against the encoder actually used.
+        builtin(
+            "stream-2mbps",
+            &[],
+            "Remote streaming under 2 Mb/s: capped h264 at up to 480p, AAC stereo 96k",
+            "libx264",
+            "aac",
+            &[
+                "-crf",
+                "23",
+                "-maxrate",
+                "1800k",
+                "-bufsize",
+                "3600k",
+                "-vf",
+                "scale=-2:'min(480,ih)'",
+                "-pix_fmt",
+                "yuv420p",
+                "-b:a",
+                "96k",
+                "-ac",
+                "2",
+            ],
+        )
+        .prefer("mp4"),
+        builtin(
+            "stream-4mbps",
+            &[],
+            "Remote streaming under 4 Mb/s: capped h264 at up to 720p, AAC stereo 128k",
+            "libx264",
+            "aac",
+            &[
+                "-crf",
+                "21",
+                "-maxrate",
+                "3600k",
+                "-bufsize",
+                "7200k",
+                "-vf",
+                "scale=-2:'min(720,ih)'",
+                "-pix_fmt",
+                "yuv420p",
+                "-b:a",
+                "128k",
+                "-ac",
+                "2",
+            ],
+        )
+        .prefer("mp4"),
+        builtin(
+            "stream-8mbps",
+            &[],
+            "Remote streaming under 8 Mb/s: capped h264 at up to 1080p, AAC stereo 160k",
+            "libx264",
+            "aac",
+            &[
+                "-crf",
+                "20",
+                "-maxrate",
+                "7500k",
+                "-bufsize",
+                "15000k",
+                "-vf",
+                "scale=-2:'min(1080,ih)'",
+                "-pix_fmt",
+                "yuv420p",
+                "-b:a",
+                "160k",
+                "-ac",
+                "2",
+            ],
+        )
+        .prefer("mp4"),
+    ]
+}
+
+#[derive(Debug)]
+pub struct Presets {
+    builtin: Vec<Preset>,
+    // From the user's presets file
+    defined: Vec<Preset>,
+    installed: Vec<Preset>,
+    defaults: Defaults,
+}
+
+// What the config file sets in place of the built-in codecs and container; flags and
+// presets still win over these
+#[derive(Debug, Clone, Default)]
+pub struct Defaults {
+    pub vcodec: Option<String>,
+    pub acodec: Option<String>,
+    pub container: Option<String>,
+}
+
+impl Default for Presets {
+    fn default() -> Self {
+        Self {
+            builtin: builtins(),
+            defined: Vec::new(),
+            installed: Vec::new(),
+            defaults: Defaults::default(),
+        }
+    }
+}
+
+impl Presets {
+    // Built-ins plus the entries of the user's presets file and every `*.toml` in the
+    // installed-presets directory, where they exist
+    pub fn load(file: Option<&Path>, dir: Option<&Path>) -> Result<Self> {
+        let mut presets = Self::default();
+        if let Some(file) = file.filter(|f| f.is_file()) {
+            let text = fs::read_to_string(file)
+                .with_context(|| format!("failed to read presets file {}", file.display()))?;
+            presets.defined = parse_file(&text)
+                .with_context(|| format!("invalid presets file {}", file.display()))?;
+        }
+        let Some(dir) = dir.filter(|d| d.is_dir()) else {
+            return Ok(presets);
+        };
+        let mut files: Vec<PathBuf> = fs::read_dir(dir)
+            .with_context(|| format!("failed to read presets directory {}", dir.display()))?
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.extension().is_some_and(|ext| ext == "toml"))
+            .collect();
+        files.sort();
+        for file in files {
+            let text = fs::read_to_string(&file)
+                .with_context(|| format!("failed to read preset {}", file.display()))?;
+            let preset =
+                parse(&text).with_context(|| format!("invalid preset {}", file.display()))?;
+            presets.installed.push(preset);
+        }
+        Ok(presets)
+    }
+
+    pub fn with_defaults(mut self, defaults: Defaults) -> Self {
+        self.defaults = defaults;
+        self
+    }
+
+    // User presets are looked up first, so a presets file entry replaces the built-in of
+    // its name; `import` refuses to shadow a built-in.
+    pub fn find(&self, name: &str) -> Option<&Preset> {
+        self.defined
+            .iter()
+            .chain(&self.installed)
+            .chain(&self.builtin)
+            .find(|p| p.matches(name))
+    }
+
+    // Every preset `--preset` can reach, with where it comes from, in lookup order
+    pub(crate) fn available(&self) -> Vec<(&Preset, &'static str)> {
+        let sources = [
+            (&self.defined, "presets.toml"),
+            (&self.installed, "installed"),
+            (&self.builtin, "built-in"),
+        ];
+        sources
+            .into_iter()
+            .flat_map(|(presets, source)| presets.iter().map(move |p| (p, source)))
+            .filter(|(preset, _)| {
+                self.find(&preset.name)
+                    .is_some_and(|p| std::ptr::eq(p, *preset))
+            })
+            .collect()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.available()
+            .into_iter()
+            .map(|(preset, _)| preset.name.clone())
+            .collect()
+    }
+
+    fn is_builtin(&self, name: &str) -> bool {
+        name == AUTO || self.builtin.iter().any(|p| p.matches(name))
+    }
+
+    // The output extension for a run with preset `name`: `ext` as given, else the
+    // preset's container, else mkv. Fails when the preset's streams do not fit in it.
+    pub fn output_ext(&self, name: Option<&str>, ext: Option<&str>) -> Result<String> {
+        let preset = name.and_then(|name| self.find(name));
+        let ext = ext
+            .or(preset.and_then(|p| p.container.as_deref()))
+            .or(preset.and_then(|p| p.containers.first().map(String::as_str)))
+            .or(self.defaults.container.as_deref())
+            .unwrap_or(DEFAULT_CONTAINER)
+            .trim_start_matches('.');
+        if let Some(preset) = preset.filter(|p| !p.fits(ext)) {
+            bail!(
+                "preset '{}' cannot be written to .{}; it only fits in {}",
+                preset.name,
+                ext,
+                preset.containers.join(", ")
+            );
+        }
+        Ok(ext.to_string())
+    }
+
+    // Variables and working directory for ffmpeg runs with preset `name`
+    pub fn run_env(&self, name: Option<&str>) -> RunEnv {
+        match name.and_then(|name| self.find(name)) {
+            Some(preset) => RunEnv::default().layer(&preset.env, preset.cwd.as_ref()),
+            None => RunEnv::default(),
+        }
+    }
+
+    // Compute effective codecs and args based on an optional preset.
+    // Precedence rules:
+    // - Explicit --vcodec/--acodec win over the preset's codecs
+    // - The preset's codecs win over the config's defaults, and those over the command's
+    // - User --extra are appended after preset extras so they override
+    pub fn apply(
+        &self,
+        name: Option<&str>,
+        vcodec: Option<&str>,
+        acodec: Option<&str>,
+        default_vcodec: &str,
+        extra: &[String],
+    ) -> (String, String, Vec<String>) {
+        let default_vcodec = self.defaults.vcodec.as_deref().unwrap_or(default_vcodec);
+        let default_acodec = self.defaults.acodec.as_deref().unwrap_or(DEFAULT_ACODEC);
+        let preset = name.and_then(|name| {
+            let found = self.find(name);
+            if found.is_none() {
+                // Unknown preset: keep explicit codecs but tell the user
+                eprintln!(
+                    "Warning: unknown preset '{}'; using vcodec={} acodec={}",
+                    name,
+                    vcodec.unwrap_or(default_vcodec),
+                    acodec.unwrap_or(default_acodec)
+                );
+            }
+            found
+        });
+
+        let out_v = vcodec
+            .or(preset.and_then(|p| p.vcodec.as_deref()))
+            .unwrap_or(default_vcodec);
+        let out_a = acodec
+            .or(preset.and_then(|p| p.acodec.as_deref()))
+            .unwrap_or(default_acodec);
+        let mut out_extra: Vec<String> = preset.map(|p| p.extra.clone()).unwrap_or_default();
+        // Append user extras last to allow override
+        out_extra.extend(extra.iter().cloned());
+
+        (out_v.to_string(), out_a.to_string(), out_extra)
+    }
+}
+
+fn parse(text: &str) -> Result<Preset> {
+    validate(toml::from_str(text)?)
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PresetsFile {
+    #[serde(default)]
+    preset: Vec<Preset>,
+}
+
+// The `[[preset]]` entries of a presets file, in order
+fn parse_file(text: &str) -> Result<Vec<Preset>> {
+    let file: PresetsFile = toml::from_str(text)?;
+    let mut presets: Vec<Preset> = Vec::new();
+    for preset in file.preset {
+        let preset = validate(preset)?;
+        if let Some(clash) = presets.iter().find(|p| {
+            p.matches(&preset.name) || preset.aliases.iter().any(|alias| p.matches(alias))
+        }) {
+            bail!("preset '{}' clashes with '{}'", preset.name, clash.name);
+        }
+        presets.push(preset);
+    }
+    Ok(presets)
+}
+
+fn validate(preset: Preset) -> Result<Preset> {
+    // The name becomes a file name when installed
+    let valid = !preset.name.is_empty()
+        && preset
+            .name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        && !preset.name.starts_with('.');
+    if !valid {
+        bail!(
+            "preset name '{}' must use only letters, digits, '-', '_' and '.'",
+            preset.name
+        );
+    }
+    if let Some(container) = preset.container.as_deref().filter(|c| !preset.fits(c)) {
+        bail!(
+            "container '{}' is not one of the preset's containers ({})",
+            container,
+            preset.containers.join(", ")
+        );
+    }
+    Ok(preset)
+}
+
+pub fn unknown_preset(name: &str) -> String {
+    i18n::t("error-unknown-preset", &[("name", name.to_string())])
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+// `presets`: every preset with its codecs, container and where it comes from
+pub fn list(presets: &Presets) -> Result<()> {
+    let mut table = Table::new(&[
+        "Preset",
+        "Aliases",
+        "Video",
+        "Audio",
+        "Container",
+        "From",
+        "Description",
+    ]);
+    for (preset, source) in presets.available() {
+        let container = match (&preset.container, preset.containers.is_empty()) {
+            (Some(container), _) => container.clone(),
+            (None, false) => preset.containers.join("/"),
+            (None, true) => "-".to_string(),
+        };
+        table.row(vec![
+            preset.name.clone(),
+            or_dash(preset.aliases.join(", ")),
+            or_dash(preset.vcodec.clone().unwrap_or_default()),
+            or_dash(preset.acodec.clone().unwrap_or_default()),
+            container,
+            source.to_string(),
+            preset.description.clone().unwrap_or_default(),
+        ]);
+    }
+    print!("{}", table);
+    println!("`transcoderr presets show <name>` prints the ffmpeg args of one");
+    Ok(())
+}
+
+// `presets show`: `command` is the ffmpeg command line a batch encode with the preset
+// would run, with placeholder input and output names
+pub fn show(presets: &Presets, name: &str, command: &str) -> Result<()> {
+    let (preset, source) = presets
+        .available()
+        .into_iter()
+        .find(|(preset, _)| preset.matches(name))
+        .with_context(|| unknown_preset(name))?;
+    println!("Preset: {} ({})", preset.name, source);
+    if let Some(description) = &preset.description {
+        println!("Description: {}", description);
+    }
+    if !preset.aliases.is_empty() {
+        println!("Aliases: {}", preset.aliases.join(", "));
+    }
+    let default = |codec: &Option<String>, fallback: &str| match codec {
+        Some(codec) => codec.clone(),
+        None => format!("{} (not set by the preset)", fallback),
+    };
+    let (vcodec, acodec) = (
+        presets.defaults.vcodec.as_deref(),
+        presets.defaults.acodec.as_deref(),
+    );
+    println!(
+        "Video codec: {}",
+        default(
+            &preset.vcodec,
+            vcodec.unwrap_or("libx265 in batch, libx264 in transcode")
+        )
+    );
+    println!(
+        "Audio codec: {}",
+        default(&preset.acodec, acodec.unwrap_or(DEFAULT_ACODEC))
+    );
+    let ext = presets.output_ext(Some(&preset.name), None)?;
+    match preset.containers.is_empty() {
+        true => println!("Container: {}", ext),
+        false => println!("Container: {} (only {})", ext, preset.containers.join(", ")),
+    }
+    println!("Extra args: {}", or_dash(preset.extra.join(" ")));
+    if let Some(cwd) = &preset.cwd {
+        println!("Working directory: {}", cwd.display());
+    }
+    for (key, value) in &preset.env {
+        println!("Environment: {}={}", key, value);
+    }
+    println!("Command: {}", command);
+    Ok(())
+}
+
+fn or_dash(text: String) -> String {
+    match text.is_empty() {
+        true => "-".to_string(),
+        false => text,
+    }
+}
+
+pub fn export(presets: &Presets, name: &str) -> Result<()> {
+    let preset = presets.find(name).with_context(|| unknown_preset(name))?;
+    let body = toml::to_string(preset).context("failed to serialize preset")?;
+    let text = format!(
+        "# transcoderr preset; install with `transcoderr presets import <file-or-url>`\n{}",
+        body
+    );
+    print!("{}", text);
+    eprintln!("sha256 {}", sha256_hex(text.as_bytes()));
+    Ok(())
+}
+
+// Install a preset made by another command (`init`, `tune-wizard`) as `<name>.toml`,
+// replacing an earlier file of that name
+pub fn save(
+    presets: &Presets,
+    dir: Option<&Path>,
+    preset: &Preset,
+    command: &str,
+    what_if: Option<&WhatIf>,
+) -> Result<PathBuf> {
+    let dir = dir.context("cannot locate the config directory to install presets into")?;
+    let body = toml::to_string(preset).context("failed to serialize preset")?;
+    let text = format!(
+        "# transcoderr preset, written by `transcoderr {}`\n{}",
+        command, body
+    );
+    parse(&text)?;
+    if presets.is_builtin(&preset.name) {
+        bail!(
+            "preset '{}' would shadow a built-in preset; pick another name",
+            preset.name
+        );
+    }
+    let target = dir.join(format!("{}.toml", preset.name));
+    if let Some(what_if) = what_if {
+        what_if.write(&target, target.exists());
+        return Ok(target);
+    }
+    fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    fs::write(&target, &text).with_context(|| format!("failed to write {}", target.display()))?;
+    Ok(target)
+}
+
+pub fn import(
+    presets: &Presets,
+    dir: Option<&Path>,
+    source: &str,
+    expected_sha256: Option<&str>,
+    yes: bool,
+    what_if: Option<&WhatIf>,
+) -> Result<()> {
+    let dir = dir.context("cannot locate the config directory to install presets into")?;
+    let bytes = fetch(source)?;
+    let checksum = sha256_hex(&bytes);
+    if let Some(expected) = expected_sha256 {
+        if !checksum.eq_ignore_ascii_case(expected.trim()) {
+            bail!(
+                "checksum mismatch for {}: expected {}, got {}",
+                source,
+                expected.trim(),
+                checksum
+            );
+        }
+    }
+    let text = String::from_utf8(bytes).context("preset file is not valid UTF-8")?;
+    let preset = parse(&text).with_context(|| format!("invalid preset file {}", source))?;
+    if presets.is_builtin(&preset.name) {
+        bail!(
+            "preset '{}' would shadow a built-in preset; rename it in the file first",
+            preset.name
+        );
+    }
+
+    let target = dir.join(format!("{}.toml", preset.name));
+    println!("Preset '{}' from {}", preset.name, source);
+    println!("  sha256: {}", checksum);
+    if let Some(description) = &preset.description {
+        println!("  description: {}", description);
+    }
+    println!(
+        "  vcodec: {}  acodec: {}",
+        preset.vcodec.as_deref().unwrap_or("(command default)"),
+        preset.acodec.as_deref().unwrap_or("(command default)")
+    );
+    println!("  ffmpeg args: {}", preset.extra.join(" "));
+    if let Some(container) = &preset.container {
+        println!("  container: {}", container);
+    }
+    if !preset.containers.is_empty() {
+        println!("  only fits in: {}", preset.containers.join(", "));
+    }
+    // Variables such as LD_PRELOAD change what ffmpeg runs; make them hard to miss
+    let env = RunEnv::default().layer(&preset.env, preset.cwd.as_ref());
+    if !env.is_empty() {
+        println!("  {} runs ffmpeg with: {}", Marker::Warning, env.describe());
+    }
+    if target.exists() {
+        println!("  replaces: {}", target.display());
+    }
+
+    if let Some(what_if) = what_if {
+        what_if.write(&target, target.exists());
+        return Ok(());
+    }
+    if !yes && !confirm(&format!("Install to {}?", target.display()))? {
+        println!("Not installed.");
+        return Ok(());
+    }
+    fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    fs::write(&target, &text).with_context(|| format!("failed to write {}", target.display()))?;
+    println!("Installed {}", target.display());
+    Ok(())
+}
+
+fn fetch(source: &str) -> Result<Vec<u8>> {
+    if source.starts_with("http://") || source.starts_with("https://") {
+        let response = ureq::get(source)
+            .timeout(Duration::from_secs(30))
+            .call()
+            .with_context(|| format!("failed to download {}", source))?;
+        let mut bytes = Vec::new();
+        // Preset files are tiny; refuse anything that is clearly not one
+        response
+            .into_reader()
+            .take(1024 * 1024)
+            .read_to_end(&mut bytes)
+            .with_context(|| format!("failed to download {}", source))?;
+        Ok(bytes)
+    } else {
+        fs::read(source).with_context(|| format!("failed to read {}", source))
+    }
+}
+
+// Ask on the terminal; without one (scripts, CI) the answer has to be given with --yes
+fn confirm(question: &str) -> Result<bool> {
+    if !io::stdin().is_terminal() {
+        bail!("refusing to install without confirmation; re-run with --yes");
+    }
+    print!("{} [y/N] ", question);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes" | "YES" | "Yes"))
+}
@@ -0,0 +1,148 @@
+// file: src/preview.rs
+// version: 0.1.0
+// guid: ee9ca0c8-56f7-4b1b-850d-cfd10dc867f8
+
+//! A low-bitrate preview of every encode, streamed while it runs (`--preview-stream`),
+//! so a bad crop or a garbled filter shows up minutes in rather than at hour six.
+//!
+//! The same ffmpeg that encodes also writes a second, small output: the first video
+//! stream through the encode's own `-vf` chain, scaled to 360 lines, and the first
+//! audio stream in stereo. It goes to a UDP, RTP, TCP or SRT address as MPEG-TS (watch
+//! with `ffplay udp://@:1234`), to an RTMP server as FLV, or is pushed to an HTTP
+//! server. The preview goes through ffmpeg's `tee` muxer with failures ignored, so a
+//! viewer or server going away never fails the encode. It plays at encode speed.
+
+use std::ffi::OsString;
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{Result, bail};
+
+use crate::filters::FilterGraph;
+
+const SCHEMES: [&str; 7] = ["udp", "rtp", "tcp", "srt", "rtmp", "http", "https"];
+const HEIGHT: &str = "360";
+const VIDEO_BITRATE: &str = "800k";
+const AUDIO_BITRATE: &str = "96k";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Preview {
+    url: String,
+}
+
+impl FromStr for Preview {
+    type Err = anyhow::Error;
+
+    fn from_str(url: &str) -> Result<Self> {
+        match url.split_once("://") {
+            Some((scheme, rest)) if SCHEMES.contains(&scheme) && !rest.is_empty() => Ok(Self {
+                url: url.to_string(),
+            }),
+            _ => bail!(
+                "invalid preview address '{}': expected a {} URL, e.g. udp://127.0.0.1:1234",
+                url,
+                SCHEMES.join("/")
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Preview {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.url)
+    }
+}
+
+impl Preview {
+    // The preview output's options and path, given the main output's extra args
+    pub fn output(&self, extra: &[String]) -> (Vec<OsString>, OsString) {
+        // The encode's own video filters, so the preview shows what the output will
+        let main_filters = extra
+            .iter()
+            .rposition(|arg| ["-vf", "-filter:v", "-filter:v:0"].contains(&arg.as_str()))
+            .and_then(|i| extra.get(i + 1));
+        let mut graph = FilterGraph::new();
+        graph.raw(main_filters.map_or("", String::as_str));
+        graph.filter("scale", &["-2", HEIGHT]);
+        let format = match self.url.starts_with("rtmp://") {
+            true => "flv",
+            false => "mpegts",
+        };
+        let opts = [
+            "-map",
+            "0:V:0?",
+            "-map",
+            "0:a:0?",
+            "-vf",
+            &graph.to_string(),
+            "-c:v",
+            "libx264",
+            "-preset",
+            "ultrafast",
+            "-tune",
+            "zerolatency",
+            "-b:v",
+            VIDEO_BITRATE,
+            "-c:a",
+            "aac",
+            "-b:a",
+            AUDIO_BITRATE,
+            "-ac",
+            "2",
+            "-f",
+            "tee",
+        ]
+        .map(OsString::from)
+        .to_vec();
+        let target = format!("[f={}:onfail=ignore]{}", format, tee_escape(&self.url));
+        (opts, target.into())
+    }
+}
+
+// Characters the tee muxer's slave list treats as syntax
+fn tee_escape(url: &str) -> String {
+    let mut escaped = String::with_capacity(url.len());
+    for c in url.chars() {
+        if matches!(c, '|' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn previews_reuse_the_encode_filters_at_low_resolution() {
+        let preview: Preview = "udp://127.0.0.1:1234?pkt_size=1316".parse().unwrap();
+        let extra: Vec<String> = ["-vf", "crop=1920:800:0:140", "-crf", "20"]
+            .map(String::from)
+            .to_vec();
+        let (opts, target) = preview.output(&extra);
+        let opts: Vec<String> = opts
+            .iter()
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect();
+        assert!(
+            opts.join(" ")
+                .contains("-vf crop=1920:800:0:140,scale=-2:360 -c:v libx264"),
+            "{:?}",
+            opts
+        );
+        assert_eq!(
+            target,
+            "[f=mpegts:onfail=ignore]udp://127.0.0.1:1234?pkt_size=1316"
+        );
+
+        let (_, target) = "rtmp://live/app|key"
+            .parse::<Preview>()
+            .unwrap()
+            .output(&[]);
+        assert_eq!(target, "[f=flv:onfail=ignore]rtmp://live/app\\|key");
+        assert!("preview.ts".parse::<Preview>().is_err());
+        assert!("ftp://host/x".parse::<Preview>().is_err());
+    }
+}
@@ -0,0 +1,328 @@
+// file: src/probe.rs
+// version: 0.10.0
+// guid: 01e15fbc-29c0-478e-a375-27b154495498
+
+//! Typed ffprobe output (`ffprobe -print_format json -show_format -show_streams`), with
+//! a few fields filled in by mediainfo when built with the `mediainfo` feature.
+//!
+//! ffprobe reads one input per run, so scans of large libraries probe ahead on a pool
+//! of worker threads (`--probe-workers`) that fill the analysis cache; the per-file
+//! pass that follows then reads every result from there.
+
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::cache::AnalysisCache;
+use crate::tools::{self, Tool};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MediaInfo {
+    #[serde(default)]
+    pub streams: Vec<Stream>,
+    #[serde(default)]
+    pub format: Format,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Format {
+    // ffprobe prints durations as strings, e.g. "1843.512000"
+    #[serde(default)]
+    pub duration: Option<String>,
+    #[serde(default)]
+    pub bit_rate: Option<String>,
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Stream {
+    #[serde(default)]
+    pub codec_type: Option<String>,
+    #[serde(default)]
+    pub codec_name: Option<String>,
+    #[serde(default)]
+    pub width: Option<u32>,
+    #[serde(default)]
+    pub height: Option<u32>,
+    #[serde(default)]
+    pub bit_rate: Option<String>,
+    // Seconds, as for the format; Matroska leaves it out for a `DURATION` tag
+    #[serde(default)]
+    pub duration: Option<String>,
+    // A fraction such as "24000/1001"; "0/0" when unknown
+    #[serde(default)]
+    pub avg_frame_rate: Option<String>,
+    #[serde(default)]
+    pub channels: Option<u32>,
+    // e.g. "5.1(side)"; absent for streams ffprobe cannot name a layout for
+    #[serde(default)]
+    pub channel_layout: Option<String>,
+    // e.g. "smpte2084" (PQ), "arib-std-b67" (HLG), "bt709"
+    #[serde(default)]
+    pub color_transfer: Option<String>,
+    #[serde(default)]
+    pub color_primaries: Option<String>,
+    #[serde(default)]
+    pub color_space: Option<String>,
+    #[serde(default)]
+    pub side_data_list: Vec<SideData>,
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+    #[serde(default)]
+    pub disposition: BTreeMap<String, i64>,
+    // From mediainfo (see `mediainfo.rs`); None when it did not run or could not tell
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub atmos: Option<bool>,
+    // e.g. "dvhe.08.06, HDR10 compatible"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dolby_vision: Option<String>,
+    // Seconds after the start of the file the track starts at
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delay: Option<f64>,
+}
+
+// One entry of a stream's or frame's side data, e.g. "Mastering display metadata";
+// the fields depend on the type
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SideData {
+    #[serde(default)]
+    pub side_data_type: Option<String>,
+    #[serde(flatten)]
+    pub fields: BTreeMap<String, serde_json::Value>,
+}
+
+impl MediaInfo {
+    pub fn subtitle_streams(&self) -> impl Iterator<Item = &Stream> {
+        self.streams.iter().filter(|s| s.is_type("subtitle"))
+    }
+
+    // The main video stream, skipping cover art
+    pub fn video_stream(&self) -> Option<&Stream> {
+        self.streams
+            .iter()
+            .find(|s| s.is_type("video") && !s.has_disposition("attached_pic"))
+    }
+}
+
+impl Format {
+    pub fn duration_seconds(&self) -> Option<f64> {
+        self.duration.as_deref()?.trim().parse().ok()
+    }
+
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        find_tag(&self.tags, key)
+    }
+
+    pub fn bit_rate(&self) -> Option<u64> {
+        self.bit_rate.as_deref()?.trim().parse().ok()
+    }
+}
+
+impl Stream {
+    pub fn bit_rate(&self) -> Option<u64> {
+        self.bit_rate.as_deref()?.trim().parse().ok()
+    }
+
+    // The stream's own length: ffprobe's `duration`, else Matroska's `DURATION` tag
+    // ("00:42:13.120000000")
+    pub fn duration_seconds(&self) -> Option<f64> {
+        if let Some(seconds) = self.duration.as_deref().and_then(|d| d.trim().parse().ok()) {
+            return Some(seconds);
+        }
+        let mut parts = self.tag("DURATION")?.trim().splitn(3, ':');
+        let mut next = || parts.next()?.parse::<f64>().ok();
+        let (hours, minutes, seconds) = (next()?, next()?, next()?);
+        Some(hours * 3600.0 + minutes * 60.0 + seconds)
+    }
+
+    pub fn frame_rate(&self) -> Option<f64> {
+        let (num, den) = self.avg_frame_rate.as_deref()?.split_once('/')?;
+        let (num, den): (f64, f64) = (num.parse().ok()?, den.parse().ok()?);
+        (num > 0.0 && den > 0.0).then(|| num / den)
+    }
+
+    pub fn is_type(&self, codec_type: &str) -> bool {
+        self.codec_type.as_deref() == Some(codec_type)
+    }
+
+    // Tag lookup is case-insensitive: Matroska files often carry `LANGUAGE`/`TITLE`.
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        find_tag(&self.tags, key)
+    }
+
+    pub fn language(&self) -> Option<&str> {
+        self.tag("language")
+    }
+
+    pub fn has_disposition(&self, flag: &str) -> bool {
+        self.disposition.get(flag).copied().unwrap_or(0) != 0
+    }
+
+    pub fn side_data(&self, side_data_type: &str) -> Option<&SideData> {
+        find_side_data(&self.side_data_list, side_data_type)
+    }
+}
+
+impl SideData {
+    // A number, or a rational that ffprobe prints as a string such as "34000/50000"
+    pub fn number(&self, key: &str) -> Option<f64> {
+        match self.fields.get(key)? {
+            serde_json::Value::Number(n) => n.as_f64(),
+            serde_json::Value::String(s) => match s.split_once('/') {
+                Some((num, den)) => {
+                    let (num, den): (f64, f64) = (num.parse().ok()?, den.parse().ok()?);
+                    (den != 0.0).then(|| num / den)
+                }
+                None => s.parse().ok(),
+            },
+            _ => None,
+        }
+    }
+}
+
+pub fn find_side_data<'a>(list: &'a [SideData], side_data_type: &str) -> Option<&'a SideData> {
+    list.iter()
+        .find(|s| s.side_data_type.as_deref() == Some(side_data_type))
+}
+
+fn find_tag<'a>(tags: &'a BTreeMap<String, String>, key: &str) -> Option<&'a str> {
+    tags.iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(key))
+        .map(|(_, v)| v.as_str())
+}
+
+// Bumped whenever `MediaInfo` gains fields, so older cached probes are not reused
+// with those fields missing; mediainfo builds keep their own entries
+#[cfg(not(feature = "mediainfo"))]
+const CACHE_SCHEMA: &str = "4";
+#[cfg(feature = "mediainfo")]
+const CACHE_SCHEMA: &str = "4+mediainfo";
+
+// Probe through the analysis cache; slow network shares make repeated probes expensive
+pub fn probe_cached(path: &Path, cache: &AnalysisCache) -> Result<MediaInfo> {
+    if let Some(info) = cache.get("probe", CACHE_SCHEMA, path) {
+        return Ok(info);
+    }
+    let info = probe(path)?;
+    cache.put("probe", CACHE_SCHEMA, path, &info);
+    Ok(info)
+}
+
+// Probe every path not in the cache yet, `workers` at a time, into the cache. Failures
+// are left for the caller's own probe of the file to report. Without a cache to keep
+// the results in there is nothing to gain, and nothing is probed.
+pub fn probe_all(paths: &[&Path], cache: &AnalysisCache, workers: usize) {
+    let mut missing: Vec<&Path> = paths
+        .iter()
+        .copied()
+        .filter(|path| {
+            cache
+                .entry("probe", CACHE_SCHEMA, path, "json")
+                .is_some_and(|entry| !entry.exists())
+        })
+        .collect();
+    missing.sort();
+    missing.dedup();
+    if workers < 2 || missing.len() < 2 {
+        return;
+    }
+    eprintln!(
+        "Probing {} files, {} at a time",
+        missing.len(),
+        workers.min(missing.len())
+    );
+    let next = AtomicUsize::new(0);
+    thread::scope(|scope| {
+        for _ in 0..workers.min(missing.len()) {
+            scope.spawn(|| {
+                while let Some(path) = missing.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let _ = probe_cached(path, cache);
+                }
+            });
+        }
+    });
+}
+
+pub fn probe(path: &Path) -> Result<MediaInfo> {
+    let output = tools::output(Tool::Ffprobe, &probe_args(path))?;
+    if !output.status.success() {
+        bail!(
+            "ffprobe failed for {}: {}",
+            path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let info: MediaInfo = serde_json::from_slice(&output.stdout)
+        .with_context(|| format!("failed to parse ffprobe output for {}", path.display()))?;
+    #[cfg(feature = "mediainfo")]
+    let info = {
+        let mut info = info;
+        crate::mediainfo::supplement(path, &mut info);
+        info
+    };
+    Ok(info)
+}
+
+pub fn probe_args(path: &Path) -> Vec<OsString> {
+    let mut args: Vec<OsString> = [
+        "-v",
+        "error",
+        "-print_format",
+        "json",
+        "-show_format",
+        "-show_streams",
+    ]
+    .map(OsString::from)
+    .into();
+    args.push(path.into());
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::Mock;
+
+    #[test]
+    fn probe_parses_ffprobe_and_reports_its_errors() {
+        let mock = Mock::new(|_, args| match args.last().map(String::as_str) {
+            Some("movie.mkv") => (
+                0,
+                r#"{"streams": [{"codec_type": "video"}], "format": {"duration": "60.5"}}"#
+                    .to_string(),
+                String::new(),
+            ),
+            _ => (1, String::new(), "missing.mkv: No such file".to_string()),
+        });
+        tools::with_runner(mock.clone(), || {
+            let info = probe(Path::new("movie.mkv")).unwrap();
+            assert!(info.video_stream().is_some());
+            assert_eq!(info.format.duration_seconds(), Some(60.5));
+            let err = probe(Path::new("missing.mkv")).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "ffprobe failed for missing.mkv: missing.mkv: No such file"
+            );
+        });
+        let calls = mock.calls.borrow();
+        let stream = |json: serde_json::Value| serde_json::from_value::<Stream>(json).unwrap();
+        assert_eq!(
+            stream(serde_json::json!({"duration": "1843.512000"})).duration_seconds(),
+            Some(1843.512)
+        );
+        assert_eq!(
+            stream(serde_json::json!({"tags": {"DURATION": "01:02:03.500000000"}}))
+                .duration_seconds(),
+            Some(3723.5)
+        );
+        assert_eq!(stream(serde_json::json!({})).duration_seconds(), None);
+        assert_eq!(calls[0].0, Tool::Ffprobe);
+        assert!(calls[0].1.contains(&"-show_streams".to_string()));
+    }
+}
@@ -0,0 +1,67 @@
+// file: src/prompt.rs
+// version: 0.1.0
+// guid: e292b4c7-62af-4bec-ab3c-f047a02aa451
+
+//! Questions asked on the terminal by the wizards (`init`, `tune-wizard`).
+//!
+//! Answers are read a line at a time from stdin, so a wizard can be scripted by piping
+//! its answers in; an empty line or the end of input takes the default shown in
+//! brackets.
+
+use std::io::{self, BufRead, Write};
+
+use anyhow::{Result, bail};
+
+// Reads answers a line at a time
+pub struct Prompt<R: BufRead> {
+    input: R,
+}
+
+impl Prompt<io::StdinLock<'static>> {
+    pub fn stdin() -> Self {
+        Self {
+            input: io::stdin().lock(),
+        }
+    }
+}
+
+impl<R: BufRead> Prompt<R> {
+    #[cfg(test)]
+    pub fn new(input: R) -> Self {
+        Self { input }
+    }
+
+    // The trimmed answer, or `default` for an empty line or the end of input
+    pub fn ask(&mut self, question: &str, default: &str) -> Result<String> {
+        match default {
+            "" => print!("{}: ", question),
+            _ => print!("{} [{}]: ", question, default),
+        }
+        io::stdout().flush()?;
+        let mut line = String::new();
+        let answer = match self.input.read_line(&mut line)? {
+            0 => {
+                println!();
+                default
+            }
+            _ => match line.trim() {
+                "" => default,
+                answer => answer,
+            },
+        };
+        Ok(answer.to_string())
+    }
+
+    // Ask until the answer is one of `options`
+    pub fn choose(&mut self, question: &str, options: &[&str], default: &str) -> Result<String> {
+        let question = format!("{} ({})", question, options.join("/"));
+        for _ in 0..3 {
+            let answer = self.ask(&question, default)?.to_ascii_lowercase();
+            if options.contains(&answer.as_str()) {
+                return Ok(answer);
+            }
+            println!("  Please answer one of: {}", options.join(", "));
+        }
+        bail!("no valid answer to '{}'", question)
+    }
+}
@@ -0,0 +1,207 @@
+// file: src/quality.rs
+// version: 0.3.0
+// guid: c4c40230-34e1-4da3-afa7-85594dd22578
+
+//! Quality gate (`batch --min-vmaf SCORE`): every encode is scored with VMAF against
+//! its input, and an output scoring below the minimum is deleted so the original stays
+//! the only copy. With `--vmaf-retries N` a miss is first re-encoded at a lower CRF,
+//! up to N times, before the output is rejected.
+//!
+//! Scoring needs the `vmaf` feature and an ffmpeg built with libvmaf. A score that
+//! cannot be measured counts as a miss: an unattended sweep must not keep outputs
+//! nobody checked.
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+#[cfg(feature = "vmaf")]
+use std::thread;
+
+#[cfg(feature = "vmaf")]
+use anyhow::Context;
+use anyhow::Result;
+
+#[cfg(feature = "vmaf")]
+use crate::args::FfmpegArgs;
+#[cfg(feature = "vmaf")]
+use crate::tools::{self, Tool};
+
+// How far each retry lowers the CRF; about one visible quality step on x264/x265
+const CRF_STEP: f64 = 2.0;
+// Score every Nth frame: a full-length VMAF pass can take longer than the encode
+#[cfg(feature = "vmaf")]
+const SUBSAMPLE: usize = 5;
+
+pub struct QualityGate {
+    min: f64,
+    retries: u32,
+}
+
+// The error a rejected output fails its job with
+#[derive(Debug)]
+pub struct BelowMinimum {
+    pub score: f64,
+    pub min: f64,
+}
+
+impl fmt::Display for BelowMinimum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "VMAF {:.2} is below --min-vmaf {} (output removed, original kept)",
+            self.score, self.min
+        )
+    }
+}
+
+impl std::error::Error for BelowMinimum {}
+
+impl QualityGate {
+    pub fn new(min: f64, retries: u32) -> Self {
+        Self { min, retries }
+    }
+
+    pub fn describe(&self) -> String {
+        match self.retries {
+            0 => format!("VMAF >= {}", self.min),
+            n => format!(
+                "VMAF >= {} (up to {} retries, CRF -{} each)",
+                self.min, n, CRF_STEP
+            ),
+        }
+    }
+
+    // Run `encode` with `extra` and score its output, lowering the CRF in `extra` for
+    // each retry. A rejected or unscored output is removed before the error returns.
+    pub fn encode(
+        &self,
+        input: &Path,
+        output: &Path,
+        extra: &[String],
+        mut encode: impl FnMut(&[String]) -> Result<()>,
+    ) -> Result<()> {
+        let mut extra = extra.to_vec();
+        let mut attempt = 0;
+        loop {
+            encode(&extra)?;
+            let score = match measure_vmaf(input, output) {
+                Ok(score) => score,
+                Err(e) => {
+                    let _ = fs::remove_file(output);
+                    return Err(e.context(
+                        "could not score the output for --min-vmaf (needs ffmpeg with libvmaf)",
+                    ));
+                }
+            };
+            if score >= self.min {
+                println!("  VMAF {:.2} (minimum {})", score, self.min);
+                return Ok(());
+            }
+            let retry = match attempt < self.retries {
+                true => lower_crf(&mut extra, CRF_STEP),
+                false => None,
+            };
+            let Some(crf) = retry else {
+                let _ = fs::remove_file(output);
+                return Err(BelowMinimum {
+                    score,
+                    min: self.min,
+                }
+                .into());
+            };
+            attempt += 1;
+            println!(
+                "  VMAF {:.2} is below {}; retrying at CRF {} ({}/{})",
+                score, self.min, crf, attempt, self.retries
+            );
+        }
+    }
+}
+
+// Mean VMAF of `output` against `input`. The output is scaled to the input's size first
+// since presets may downscale, and both start at zero so trimmed leading frames don't
+// shift every comparison.
+#[cfg(feature = "vmaf")]
+pub fn measure_vmaf(input: &Path, output: &Path) -> Result<f64> {
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let graph = format!(
+        "[0:v]setpts=PTS-STARTPTS[dist];[1:v]setpts=PTS-STARTPTS[ref];\
+         [dist][ref]scale2ref=flags=bicubic[scaled][base];\
+         [scaled][base]libvmaf=n_subsample={}:n_threads={}",
+        SUBSAMPLE, threads
+    );
+    let args = FfmpegArgs::new("-")
+        .input(output)
+        .input(input)
+        .filter("-lavfi", graph)
+        .option("-f", "null")
+        .build();
+    let result = tools::output(Tool::Ffmpeg, &args)?;
+    parse_vmaf(&String::from_utf8_lossy(&result.stderr))
+        .context("ffmpeg did not report a VMAF score")
+}
+
+#[cfg(not(feature = "vmaf"))]
+pub fn measure_vmaf(_input: &Path, _output: &Path) -> Result<f64> {
+    crate::features::require(crate::features::Feature::Vmaf, "VMAF scoring")?;
+    unreachable!("the vmaf feature is not compiled in")
+}
+
+// `[Parsed_libvmaf_4 @ 0x...] VMAF score: 94.871302`
+#[cfg(feature = "vmaf")]
+fn parse_vmaf(stderr: &str) -> Option<f64> {
+    let line = stderr
+        .lines()
+        .rev()
+        .find(|line| line.contains("VMAF score"))?;
+    line.rsplit(':').next()?.trim().parse().ok()
+}
+
+// Lower the last `-crf` in `extra` (the one ffmpeg uses) by `step`; `None` when there
+// is none or it is already at the bottom
+fn lower_crf(extra: &mut [String], step: f64) -> Option<String> {
+    let flag = extra
+        .iter()
+        .rposition(|arg| arg == "-crf" || arg.starts_with("-crf:"))?;
+    let value = extra.get_mut(flag + 1)?;
+    let crf: f64 = value.parse().ok()?;
+    if crf <= 0.0 {
+        return None;
+    }
+    let lowered = (crf - step).max(0.0);
+    *value = match lowered.fract() == 0.0 {
+        true => format!("{}", lowered as u64),
+        false => format!("{}", lowered),
+    };
+    Some(value.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "vmaf")]
+    #[test]
+    fn reads_the_vmaf_score() {
+        let stderr = "Input #0, matroska,webm, from 'out.mkv':\n\
+                      [Parsed_libvmaf_4 @ 0x55d0] VMAF score: 94.871302\n";
+        assert_eq!(parse_vmaf(stderr), Some(94.871302));
+        assert_eq!(parse_vmaf("No such filter: 'libvmaf'\n"), None);
+    }
+
+    #[test]
+    fn retries_lower_the_effective_crf() {
+        let strings = |args: &[&str]| args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        let mut extra = strings(&["-crf", "28", "-preset", "slow", "-crf:v", "23.5"]);
+        assert_eq!(lower_crf(&mut extra, 2.0).as_deref(), Some("21.5"));
+        assert_eq!(
+            extra,
+            strings(&["-crf", "28", "-preset", "slow", "-crf:v", "21.5"])
+        );
+
+        let mut extra = strings(&["-crf", "1"]);
+        assert_eq!(lower_crf(&mut extra, 2.0).as_deref(), Some("0"));
+        assert_eq!(lower_crf(&mut extra, 2.0), None);
+        assert_eq!(lower_crf(&mut strings(&["-b:v", "4M"]), 2.0), None);
+    }
+}
@@ -0,0 +1,284 @@
+// file: src/recommend.rs
+// version: 0.4.0
+// guid: c675eb74-eab6-4dfa-93f5-708a88d1ce5b
+
+//! Preset recommendation from probed content (`recommend`, `--preset auto`)
+
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::Result;
+
+use crate::batch::{print_issue_summary, scan_inputs};
+use crate::cache::AnalysisCache;
+use crate::probe::{self, MediaInfo};
+use crate::shows;
+use crate::style::Marker;
+
+// Bits per pixel per frame; above this a source is carrying grain or fine detail
+const GRAINY_BPP: f64 = 0.2;
+// Below this it has already been squeezed hard and re-encoding gains little
+const STARVED_BPP: f64 = 0.05;
+// Shorter features exist, but few episodes run this long
+const FEATURE_SECONDS: f64 = 70.0 * 60.0;
+
+// Tags cameras and phones write; their presence means personal footage
+const CAMERA_TAGS: [&str; 6] = [
+    "com.apple.quicktime.make",
+    "com.apple.quicktime.model",
+    "com.android.version",
+    "make",
+    "model",
+    "location",
+];
+const CAPTURE_ENCODERS: [&str; 4] = ["obs", "screenflow", "camtasia", "kap"];
+const CAPTURE_NAMES: [&str; 3] = ["screen recording", "screencast", "screen capture"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Recommendation {
+    pub preset: &'static str,
+    pub reasons: Vec<String>,
+}
+
+pub fn recommend(path: &Path, cache: &AnalysisCache) -> Result<Recommendation> {
+    Ok(choose(path, &probe::probe_cached(path, cache)?))
+}
+
+// `recommend <file|dir>`: print the preset each file would get and why
+pub fn run(path: &Path, input_exts: &str, cache: &AnalysisCache, workers: usize) -> Result<()> {
+    if path.is_file() {
+        print(path, &recommend(path, cache)?);
+        return Ok(());
+    }
+    let scan = scan_inputs(path, input_exts)?;
+    let files: Vec<&Path> = scan.files.iter().map(PathBuf::as_path).collect();
+    probe::probe_all(&files, cache, workers);
+    for (i, file) in scan.files.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        match recommend(file, cache) {
+            Ok(rec) => print(file, &rec),
+            Err(e) => eprintln!("{}: {} {:#}", file.display(), Marker::Error, e),
+        }
+    }
+    println!("\nUse --preset auto to apply these choices");
+    print_issue_summary(&scan.issues);
+    Ok(())
+}
+
+fn print(path: &Path, rec: &Recommendation) {
+    println!("{}: {}", path.display(), rec.preset);
+    for reason in &rec.reasons {
+        println!("  - {}", reason);
+    }
+}
+
+// The decision itself, kept free of I/O. Checks run from the most specific kind of
+// content to the most general; every observation is reported, not just the deciding one.
+pub fn choose(path: &Path, info: &MediaInfo) -> Recommendation {
+    let mut reasons = Vec::new();
+    let video = info.video_stream();
+    let duration = info.format.duration_seconds();
+    let name = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    let fps = video.and_then(|v| v.frame_rate());
+    let bpp = video.and_then(|v| {
+        let bits = v.bit_rate().or_else(|| {
+            // Containers like MKV only report an overall rate; audio is a small share
+            let audio: u64 = info
+                .streams
+                .iter()
+                .filter(|s| s.is_type("audio"))
+                .filter_map(|s| s.bit_rate())
+                .sum();
+            Some(info.format.bit_rate()?.saturating_sub(audio))
+        })?;
+        let pixels = f64::from(v.width?) * f64::from(v.height?) * fps?;
+        (pixels > 0.0).then(|| bits as f64 / pixels)
+    });
+
+    let Some(video) = video else {
+        reasons.push("no video stream; keeping the default preset".to_string());
+        return done("original-h265", reasons);
+    };
+    let codec = video.codec_name.as_deref().unwrap_or("unknown");
+    let mut summary = format!(
+        "video: {} {}x{}",
+        codec,
+        video.width.unwrap_or(0),
+        video.height.unwrap_or(0)
+    );
+    if let Some(fps) = fps {
+        summary.push_str(&format!(" at {:.3} fps", fps));
+    }
+    reasons.push(summary);
+    let grainy = bpp.is_some_and(|bpp| bpp > GRAINY_BPP);
+    if let Some(bpp) = bpp {
+        reasons.push(format!(
+            "grain estimate: {:.3} bits/pixel ({})",
+            bpp,
+            if grainy {
+                "high: grain or fine detail"
+            } else if bpp < STARVED_BPP {
+                "low: already heavily compressed"
+            } else {
+                "normal"
+            }
+        ));
+    }
+    if matches!(codec, "hevc" | "av1") && bpp.is_some_and(|bpp| bpp < STARVED_BPP) {
+        reasons.push(format!(
+            "already {} at a low bitrate; re-encoding will save little",
+            codec
+        ));
+    }
+
+    let encoder = info.format.tag("encoder").unwrap_or("").to_lowercase();
+    let capture_by_encoder = CAPTURE_ENCODERS.iter().any(|e| encoder.contains(e));
+    let capture_by_name = CAPTURE_NAMES.iter().any(|n| name.contains(n));
+    if capture_by_encoder || capture_by_name {
+        reasons.push(if capture_by_encoder {
+            format!("written by a screen recorder (encoder '{}')", encoder)
+        } else {
+            "file name looks like a screen recording".to_string()
+        });
+        if fps.is_some_and(|fps| fps > 31.0) {
+            reasons.push("high frame rate capture; 30 fps is plenty for a desktop".to_string());
+            return done("screencast-30fps", reasons);
+        }
+        return done("screencast", reasons);
+    }
+
+    if let Some(tag) = CAMERA_TAGS
+        .iter()
+        .find(|tag| info.format.tag(tag).is_some())
+    {
+        reasons.push(format!("camera/phone metadata present ({})", tag));
+        return done("home-video", reasons);
+    }
+
+    if let Some(why) = animation_signal(path, info) {
+        reasons.push(format!("animation: {}", why));
+        if grainy {
+            reasons.push("noisy source; adding a light denoise".to_string());
+            return done("anime-denoise", reasons);
+        }
+        return done("anime", reasons);
+    }
+
+    if let Some(key) = shows::detect(path) {
+        reasons.push(format!("episode of '{}'", key.show));
+        return done("tv-h265-fast", reasons);
+    }
+
+    if let Some(seconds) = duration.filter(|s| *s >= FEATURE_SECONDS) {
+        reasons.push(format!(
+            "feature length ({}h {:02}m)",
+            (seconds / 3600.0) as u64,
+            (seconds % 3600.0 / 60.0) as u64
+        ));
+        if grainy {
+            reasons.push("grainy film; CRF 16 keeps the grain".to_string());
+        }
+        return done("movie-quality", reasons);
+    }
+
+    reasons.push("no specific content detected".to_string());
+    done("original-h265", reasons)
+}
+
+fn done(preset: &'static str, reasons: Vec<String>) -> Recommendation {
+    Recommendation { preset, reasons }
+}
+
+// Fansub releases ship ASS subtitles with their fonts attached; libraries tend to keep
+// anime in a folder saying so; fansub names start with the group in brackets.
+fn animation_signal(path: &Path, info: &MediaInfo) -> Option<String> {
+    let styled = info
+        .subtitle_streams()
+        .any(|s| matches!(s.codec_name.as_deref(), Some("ass" | "ssa")));
+    let fonts = info.streams.iter().any(|s| s.is_type("attachment"));
+    if styled && fonts {
+        return Some("styled ASS subtitles with embedded fonts".to_string());
+    }
+    let in_anime_folder = path.components().any(|c| match c {
+        Component::Normal(part) => part.to_string_lossy().to_lowercase().contains("anime"),
+        _ => false,
+    });
+    if in_anime_folder {
+        return Some("stored under an anime folder".to_string());
+    }
+    let file_name = path.file_name()?.to_string_lossy();
+    if file_name.starts_with('[') && file_name.contains(']') {
+        return Some("fansub-style file name".to_string());
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probe::Stream;
+
+    fn info(codec: &str, width: u32, height: u32, kbps: u64, seconds: u32) -> MediaInfo {
+        let mut info = MediaInfo::default();
+        info.streams.push(Stream {
+            codec_type: Some("video".to_string()),
+            codec_name: Some(codec.to_string()),
+            width: Some(width),
+            height: Some(height),
+            bit_rate: Some((kbps * 1000).to_string()),
+            avg_frame_rate: Some("24000/1001".to_string()),
+            ..Default::default()
+        });
+        info.format.duration = Some(seconds.to_string());
+        info
+    }
+
+    #[test]
+    fn grainy_feature_is_a_movie() {
+        let rec = choose(
+            Path::new("Films/Alien (1979).mkv"),
+            &info("h264", 1920, 800, 14_000, 7000),
+        );
+        assert_eq!(rec.preset, "movie-quality");
+        assert!(
+            rec.reasons.iter().any(|r| r.contains("high: grain")),
+            "{:?}",
+            rec
+        );
+    }
+
+    #[test]
+    fn episodes_anime_and_captures() {
+        let episode = info("h264", 1920, 1080, 5_000, 2600);
+        let rec = choose(Path::new("TV/Show/Season 01/Show - S01E02.mkv"), &episode);
+        assert_eq!(rec.preset, "tv-h265-fast");
+        let rec = choose(Path::new("[Group] Show - 02 [1080p].mkv"), &episode);
+        assert_eq!(rec.preset, "anime");
+
+        let mut capture = info("h264", 2560, 1600, 3_000, 900);
+        capture.streams[0].avg_frame_rate = Some("60/1".to_string());
+        capture
+            .format
+            .tags
+            .insert("encoder".to_string(), "OBS Studio".to_string());
+        let rec = choose(Path::new("2024-05-01 10-00-00.mkv"), &capture);
+        assert_eq!(rec.preset, "screencast-30fps");
+    }
+
+    #[test]
+    fn phone_clips_are_home_video() {
+        let mut clip = info("hevc", 1920, 1080, 9_000, 40);
+        clip.format
+            .tags
+            .insert("com.apple.quicktime.make".to_string(), "Apple".to_string());
+        assert_eq!(
+            choose(Path::new("IMG_0042.MOV"), &clip).preset,
+            "home-video"
+        );
+    }
+}
@@ -0,0 +1,127 @@
+// file: src/removal.rs
+// version: 0.4.0
+// guid: 6f0c2b9e-41d7-4a8e-b3f5-9d2e7c18a604
+
+//! How files are removed: OS trash by default, a quarantine folder, or `--purge`
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use clap::Args;
+
+use crate::i18n;
+use crate::paths::relative_to;
+use crate::whatif::resolved;
+
+#[derive(Args, Debug, Clone, Default)]
+pub struct Removal {
+    /// Move removed files into DIR (keeping their relative paths) instead of the OS trash
+    #[arg(long, value_name = "DIR", conflicts_with = "purge")]
+    pub quarantine: Option<PathBuf>,
+    /// Delete removed files permanently instead of moving them to the OS trash
+    #[arg(long)]
+    pub purge: bool,
+}
+
+impl Removal {
+    pub fn describe(&self) -> String {
+        match (&self.quarantine, self.purge) {
+            (Some(dir), _) => format!("move to quarantine {}", dir.display()),
+            (None, true) => "delete permanently".to_string(),
+            (None, false) => "move to trash".to_string(),
+        }
+    }
+
+    // `describe` in the active language, for translated messages
+    pub fn summary(&self) -> String {
+        match (&self.quarantine, self.purge) {
+            (Some(dir), _) => i18n::t("removal-quarantine", &[("dir", dir.display().to_string())]),
+            (None, true) => i18n::t("removal-purge", &[]),
+            (None, false) => i18n::t("removal-trash", &[]),
+        }
+    }
+
+    // What `remove` would do to `path`, with resolved paths, for `--what-if`
+    pub fn action(&self, path: &Path, root: &Path) -> String {
+        match &self.quarantine {
+            Some(dir) => format!(
+                "move {} to {}",
+                resolved(path),
+                resolved(&quarantine_path(dir, path, root))
+            ),
+            None if self.purge => format!("delete {} permanently", resolved(path)),
+            None => format!("move {} to the trash", resolved(path)),
+        }
+    }
+
+    // Remove `path`, which lives under `root`; quarantined files keep their path relative
+    // to `root` so they can be put back by hand.
+    pub fn remove(&self, path: &Path, root: &Path) -> Result<()> {
+        self.remove_as(path, path, root)
+    }
+
+    // Remove `path` as if it were `as_path`, whose relative path it gets in quarantine;
+    // for a file moved aside before its removal.
+    pub fn remove_as(&self, path: &Path, as_path: &Path, root: &Path) -> Result<()> {
+        if let Some(dir) = &self.quarantine {
+            let dest = quarantine_path(dir, as_path, root);
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("failed to create {}", parent.display()))?;
+            }
+            // rename fails across filesystems; fall back to copy + delete
+            if fs::rename(path, &dest).is_err() {
+                fs::copy(path, &dest).with_context(|| {
+                    format!("failed to copy {} to {}", path.display(), dest.display())
+                })?;
+                fs::remove_file(path)
+                    .with_context(|| format!("failed to remove {}", path.display()))?;
+            }
+            Ok(())
+        } else if self.purge {
+            fs::remove_file(path).with_context(|| format!("failed to delete {}", path.display()))
+        } else {
+            trash::delete(path)
+                .with_context(|| format!("failed to move {} to the trash", path.display()))
+        }
+    }
+}
+
+fn quarantine_path(dir: &Path, path: &Path, root: &Path) -> PathBuf {
+    let rel = relative_to(path, root)
+        .or_else(|| path.file_name().map(PathBuf::from))
+        .unwrap_or_else(|| PathBuf::from("unnamed"));
+    unused_path(&dir.join(rel))
+}
+
+// Never overwrite an earlier quarantined file; append `.1`, `.2`, ... instead
+fn unused_path(path: &Path) -> PathBuf {
+    if !path.exists() {
+        return path.to_path_buf();
+    }
+    (1u32..)
+        .map(|n| {
+            let mut name = path.as_os_str().to_os_string();
+            name.push(format!(".{}", n));
+            PathBuf::from(name)
+        })
+        .find(|candidate| !candidate.exists())
+        .expect("ran out of quarantine names")
+}
+
+// Remove directories left empty under `root` after `path` was removed, walking upwards.
+// `root` itself is never removed.
+pub fn remove_empty_parents(path: &Path, root: &Path) {
+    let mut dir = path.parent();
+    while let Some(current) = dir {
+        if current == root || !current.starts_with(root) {
+            break;
+        }
+        // remove_dir only succeeds on empty directories
+        if fs::remove_dir(current).is_err() {
+            break;
+        }
+        dir = current.parent();
+    }
+}
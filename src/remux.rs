@@ -0,0 +1,127 @@
+// file: src/remux.rs
+// version: 0.4.0
+// guid: 3eabaee3-3e33-4ac7-b249-09285bc902b3
+
+//! Remux-then-encode fallback (`--auto-remux-fallback`) for containers whose broken
+//! timestamps or interleaving make an encode fail part way through.
+//!
+//! When ffmpeg's output shows demuxer or timestamp errors, the input is first copied
+//! into a Matroska file in the temp directory with regenerated timestamps, and the
+//! encode is retried once from that. Stream order is kept, so per-stream maps and
+//! options still apply; data streams (QuickTime timecode tracks and the like), which
+//! Matroska cannot hold, are left out of the copy.
+
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::{Result, bail};
+
+use crate::args::FfmpegArgs;
+use crate::report;
+use crate::stats::Stats;
+use crate::tools::{self, Tool};
+
+// ffmpeg messages that point at the container rather than the encode
+const DEMUX_ERRORS: [&str; 9] = [
+    "non-monoton",
+    "non monoton",
+    "invalid dts",
+    "invalid pts",
+    "pts has no value",
+    "timestamps are unset",
+    "invalid data found when processing input",
+    "error during demuxing",
+    "packet with invalid duration",
+];
+
+// The remuxed copy; removed when dropped
+pub struct Intermediate {
+    path: PathBuf,
+}
+
+impl Intermediate {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for Intermediate {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+// Whether a failed encode's log shows demuxer or timestamp trouble
+pub fn is_demux_failure(log: &[String]) -> bool {
+    log.iter().any(|line| {
+        let line = line.to_lowercase();
+        DEMUX_ERRORS.iter().any(|error| line.contains(error))
+    })
+}
+
+fn remux_args(input: &Path, input_opts: &[String], output: &Path) -> Vec<OsString> {
+    // The input's own options come last, so legacy sources' wider -fflags win
+    let mut opts: Vec<String> = ["-fflags", "+genpts+igndts"].map(String::from).to_vec();
+    opts.extend_from_slice(input_opts);
+    FfmpegArgs::new(output)
+        .global(["-v", "error"])
+        .input_with(opts, input)
+        .map("0")
+        .map("-0:d?")
+        .option("-c", "copy")
+        .option("-avoid_negative_ts", "make_zero")
+        .option("-max_interleave_delta", "0")
+        .build()
+}
+
+// Copy `input` into a fresh Matroska file with regenerated timestamps
+pub fn remux(input: &Path, input_opts: &[String], stats: Stats) -> Result<Intermediate> {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let name = format!(
+        "transcoderr-remux-{}-{}.mkv",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    );
+    let intermediate = Intermediate {
+        path: std::env::temp_dir().join(name),
+    };
+    let args = remux_args(input, input_opts, intermediate.path());
+    let status = tools::stream(Tool::Ffmpeg, &args, &mut |stderr| {
+        report::tee_log(stderr, stats, None);
+    })?;
+    if !status.success() {
+        bail!(
+            "remuxing {} for the fallback failed (ffmpeg status {:?})",
+            input.display(),
+            status.code()
+        );
+    }
+    Ok(intermediate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::display_args;
+
+    #[test]
+    fn timestamp_errors_trigger_a_remux_that_keeps_stream_order() {
+        let log = |line: &str| vec!["frame=  100".to_string(), line.to_string()];
+        assert!(is_demux_failure(&log(
+            "[mp4 @ 0x1] Application provided invalid, non monotonically increasing dts"
+        )));
+        assert!(is_demux_failure(&log(
+            "in.avi: Invalid data found when processing input"
+        )));
+        assert!(!is_demux_failure(&log("Unknown encoder 'libx265'")));
+
+        let args = remux_args(Path::new("in.avi"), &[], Path::new("/tmp/r.mkv"));
+        assert_eq!(
+            display_args(&args),
+            "-hide_banner -y -v error -fflags +genpts+igndts -i in.avi -map 0 -map -0:d? \
+             -c copy -avoid_negative_ts make_zero -max_interleave_delta 0 /tmp/r.mkv"
+        );
+    }
+}
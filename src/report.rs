@@ -0,0 +1,523 @@
+// file: src/report.rs
+// version: 0.8.1
+// guid: 83c676e7-eb73-4ea7-86dd-dc2dc3a46e72
+
+//! Batch report (`batch --report-html PATH`): one standalone HTML page per run, for
+//! sharing or archiving after a large library sweep.
+//!
+//! The page needs nothing but itself: styles are inline, the savings chart is plain
+//! HTML bars, and the only script sorts the table by the clicked column, so the page
+//! still reads well in mail clients that strip scripts. Failures come with the last
+//! lines ffmpeg printed before giving up.
+
+use std::collections::VecDeque;
+use std::fmt::{self, Write as _};
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+
+use crate::snapshots::Snapshots;
+use crate::stats::{Stats, StatsFilter};
+use crate::tools::{self, Tool};
+use crate::units::Units;
+
+// Lines of ffmpeg output kept for a failed encode
+const LOG_LINES: usize = 20;
+// Files in the savings chart, largest savings first
+const CHART_FILES: usize = 20;
+
+pub enum Status {
+    Done,
+    Failed { error: String, log: Vec<String> },
+    // Not encoded, with the reason (up to date, done by another host)
+    Skipped(&'static str),
+}
+
+pub struct Entry {
+    // Output path relative to the output directory
+    pub name: String,
+    pub input: PathBuf,
+    pub preset: Option<String>,
+    pub status: Status,
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+    pub seconds: f64,
+}
+
+impl Entry {
+    // Bytes saved by a finished encode; negative when the output grew
+    fn saved(&self) -> Option<i128> {
+        matches!(self.status, Status::Done)
+            .then(|| i128::from(self.input_bytes) - i128::from(self.output_bytes))
+    }
+}
+
+pub struct Report {
+    path: PathBuf,
+    started_ms: u128,
+    input_dir: PathBuf,
+    output_dir: PathBuf,
+    settings: String,
+    units: Units,
+    entries: Vec<Entry>,
+}
+
+impl Report {
+    pub fn new(
+        path: &Path,
+        input_dir: &Path,
+        output_dir: &Path,
+        settings: &str,
+        units: Units,
+    ) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            started_ms: now_ms(),
+            input_dir: input_dir.to_path_buf(),
+            output_dir: output_dir.to_path_buf(),
+            settings: settings.to_string(),
+            units: units.human(),
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn add(&mut self, entry: Entry) {
+        self.entries.push(entry);
+    }
+
+    pub fn write(&self) -> Result<()> {
+        self.save(self.render(now_ms(), None))
+    }
+
+    // The page so far, marked as in progress (`batch --checkpoint-every`)
+    pub fn write_partial(&self, processed: usize, total: usize) -> Result<()> {
+        self.save(self.render(now_ms(), Some((processed, total))))
+    }
+
+    // Write then rename, so a run stopped mid-write leaves the previous page readable
+    fn save(&self, html: String) -> Result<()> {
+        if let Some(parent) = self.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create {}", parent.display()))?;
+        }
+        let partial = self
+            .path
+            .with_extension(format!("{}.partial", std::process::id()));
+        fs::write(&partial, html)
+            .and_then(|()| fs::rename(&partial, &self.path))
+            .with_context(|| format!("failed to write report {}", self.path.display()))
+    }
+
+    fn render(&self, finished_ms: u128, progress: Option<(usize, usize)>) -> String {
+        let count = |f: fn(&Status) -> bool| self.entries.iter().filter(|e| f(&e.status)).count();
+        let done: Vec<&Entry> = self
+            .entries
+            .iter()
+            .filter(|e| matches!(e.status, Status::Done))
+            .collect();
+        let before: u64 = done.iter().map(|e| e.input_bytes).sum();
+        let after: u64 = done.iter().map(|e| e.output_bytes).sum();
+        let seconds: f64 = self.entries.iter().map(|e| e.seconds).sum();
+
+        let mut html = String::new();
+        let _ = write!(
+            html,
+            "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+             <title>transcoderr batch report</title>\n<style>{}</style>\n</head>\n<body>\n\
+             <h1>Batch report</h1>\n<p class=\"meta\">{} &rarr; {}<br>{}<br>{} &ndash; {}</p>\n",
+            STYLE,
+            escape(&self.input_dir.display().to_string()),
+            escape(&self.output_dir.display().to_string()),
+            escape(&self.settings),
+            format_utc(self.started_ms),
+            match progress {
+                Some((processed, total)) => format!(
+                    "{} (in progress: {} of {} files)",
+                    format_utc(finished_ms),
+                    processed,
+                    total
+                ),
+                None => format_utc(finished_ms),
+            },
+        );
+        let _ = write!(
+            html,
+            "<table class=\"summary\">\n\
+             <tr><th>Succeeded</th><td>{}</td></tr>\n\
+             <tr><th>Failed</th><td>{}</td></tr>\n\
+             <tr><th>Skipped</th><td>{}</td></tr>\n\
+             <tr><th>Size</th><td>{} &rarr; {} ({})</td></tr>\n\
+             <tr><th>Encode time</th><td>{}</td></tr>\n</table>\n",
+            done.len(),
+            count(|s| matches!(s, Status::Failed { .. })),
+            count(|s| matches!(s, Status::Skipped(_))),
+            self.units.bytes(before),
+            self.units.bytes(after),
+            self.units.change(before, after),
+            self.units.duration(seconds),
+        );
+        self.render_chart(&mut html, &done);
+        self.render_table(&mut html);
+        self.render_failures(&mut html);
+        let _ = write!(html, "<script>{}</script>\n</body>\n</html>\n", SORT_SCRIPT);
+        html
+    }
+
+    // Before and after bars for the files that saved the most, scaled to the largest input
+    fn render_chart(&self, html: &mut String, done: &[&Entry]) {
+        let mut top = done.to_vec();
+        top.sort_by_key(|e| std::cmp::Reverse(e.saved()));
+        top.truncate(CHART_FILES);
+        let Some(largest) = top.iter().map(|e| e.input_bytes.max(e.output_bytes)).max() else {
+            return;
+        };
+        let width = |bytes: u64| bytes as f64 * 100.0 / largest.max(1) as f64;
+        html.push_str("<h2>Savings</h2>\n<div class=\"chart\">\n");
+        for entry in top {
+            let _ = writeln!(
+                html,
+                "<div class=\"row\"><span class=\"name\">{}</span><span class=\"bars\">\
+                 <span class=\"before\" style=\"width:{:.1}%\"></span>\
+                 <span class=\"after\" style=\"width:{:.1}%\"></span></span>\
+                 <span class=\"saved\">{}</span></div>",
+                escape(&entry.name),
+                width(entry.input_bytes),
+                width(entry.output_bytes),
+                self.units.change(entry.input_bytes, entry.output_bytes),
+            );
+        }
+        html.push_str("</div>\n");
+    }
+
+    // Every file; each cell carries a sort key so sizes and times sort as numbers
+    fn render_table(&self, html: &mut String) {
+        html.push_str(
+            "<h2>Files</h2>\n<table class=\"files\">\n<thead><tr><th>Status</th><th>File</th>\
+             <th>Preset</th><th>Before</th><th>After</th><th>Change</th><th>Time</th></tr>\
+             </thead>\n<tbody>\n",
+        );
+        for entry in &self.entries {
+            let (status, class) = match &entry.status {
+                Status::Done => ("done", "done"),
+                Status::Failed { .. } => ("failed", "failed"),
+                Status::Skipped(reason) => (*reason, "skipped"),
+            };
+            let after = match entry.status {
+                Status::Done => (
+                    entry.output_bytes.to_string(),
+                    self.units.bytes(entry.output_bytes),
+                ),
+                _ => ("0".to_string(), String::new()),
+            };
+            let change = match entry.saved() {
+                Some(saved) => (
+                    (-saved as f64 / entry.input_bytes.max(1) as f64).to_string(),
+                    self.units.change(entry.input_bytes, entry.output_bytes),
+                ),
+                None => ("0".to_string(), String::new()),
+            };
+            let _ = writeln!(
+                html,
+                "<tr class=\"{}\"><td>{}</td><td title=\"{}\">{}</td><td>{}</td>\
+                 <td data-sort=\"{}\">{}</td><td data-sort=\"{}\">{}</td>\
+                 <td data-sort=\"{}\">{}</td><td data-sort=\"{}\">{}</td></tr>",
+                class,
+                escape(status),
+                escape(&entry.input.display().to_string()),
+                escape(&entry.name),
+                escape(entry.preset.as_deref().unwrap_or("")),
+                entry.input_bytes,
+                self.units.bytes(entry.input_bytes),
+                after.0,
+                after.1,
+                change.0,
+                change.1,
+                entry.seconds,
+                self.units.duration(entry.seconds),
+            );
+        }
+        html.push_str("</tbody>\n</table>\n");
+    }
+
+    fn render_failures(&self, html: &mut String) {
+        let mut failures = self.entries.iter().filter_map(|e| match &e.status {
+            Status::Failed { error, log } => Some((e, error, log)),
+            _ => None,
+        });
+        let Some(first) = failures.next() else {
+            return;
+        };
+        html.push_str("<h2>Failures</h2>\n");
+        for (entry, error, log) in std::iter::once(first).chain(failures) {
+            let _ = write!(
+                html,
+                "<h3>{}</h3>\n<p>{}</p>\n",
+                escape(&entry.name),
+                escape(error)
+            );
+            if !log.is_empty() {
+                let _ = writeln!(html, "<pre>{}</pre>", escape(&log.join("\n")));
+            }
+        }
+    }
+}
+
+// ffmpeg failed; `log` holds the last lines it printed
+#[derive(Debug)]
+pub struct FfmpegFailed {
+    pub code: Option<i32>,
+    // The arguments it ran with, for diagnostic bundles
+    pub command: String,
+    pub log: Vec<String>,
+}
+
+impl fmt::Display for FfmpegFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&tools::exit_error(Tool::Ffmpeg, self.code))
+    }
+}
+
+impl std::error::Error for FfmpegFailed {}
+
+// The last lines of ffmpeg's output, without the progress lines it redraws with `\r`
+#[derive(Default)]
+struct LogTail {
+    lines: VecDeque<String>,
+    partial: Vec<u8>,
+}
+
+impl LogTail {
+    fn push(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            if byte == b'\n' || byte == b'\r' {
+                self.end_line();
+            } else {
+                self.partial.push(byte);
+            }
+        }
+    }
+
+    fn end_line(&mut self) {
+        let line = String::from_utf8_lossy(&self.partial)
+            .trim_end()
+            .to_string();
+        self.partial.clear();
+        if line.is_empty() || line.starts_with("frame=") || line.starts_with("size=") {
+            return;
+        }
+        if self.lines.len() == LOG_LINES {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line);
+    }
+
+    fn finish(mut self) -> Vec<String> {
+        self.end_line();
+        self.lines.into()
+    }
+}
+
+// Pass ffmpeg's stderr through to ours as it arrives (its stats as `--stats` asks),
+// keeping its tail for the report and taking any `--snapshot-every` snapshots
+pub fn tee_log(from: impl Read, stats: Stats, snapshots: Option<&mut Snapshots>) -> Vec<String> {
+    let stderr = Mutex::new(StatsFilter::new(stats.for_stderr(), io::stderr()));
+    let log = tee_log_through(from, &stderr, snapshots);
+    stderr
+        .into_inner()
+        .unwrap_or_else(PoisonError::into_inner)
+        .finish();
+    log
+}
+
+// `tee_log` into a filter shared with `stats::follow_progress`, which the caller
+// finishes once ffmpeg's stdout is done too
+pub fn tee_log_through<W: io::Write>(
+    mut from: impl Read,
+    stderr: &Mutex<StatsFilter<W>>,
+    mut snapshots: Option<&mut Snapshots>,
+) -> Vec<String> {
+    let mut tail = LogTail::default();
+    let mut buf = [0u8; 8192];
+    loop {
+        match from.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                let mut stderr = stderr.lock().unwrap_or_else(PoisonError::into_inner);
+                stderr.push(&buf[..n]);
+                tail.push(&buf[..n]);
+                if let Some(snapshots) = snapshots.as_deref_mut() {
+                    for line in snapshots.push(&buf[..n]) {
+                        stderr.message(&line);
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(_) => break,
+        }
+    }
+    tail.finish()
+}
+
+// Keep the tail of ffmpeg's stderr without showing it, for encodes that run side by
+// side (`batch --jobs`) where passing it through would interleave several at once
+pub fn capture_log(mut from: impl Read) -> Vec<String> {
+    let mut tail = LogTail::default();
+    let mut buf = [0u8; 8192];
+    loop {
+        match from.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => tail.push(&buf[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(_) => break,
+        }
+    }
+    tail.finish()
+}
+
+fn now_ms() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0)
+}
+
+// `2026-10-15 09:30 UTC`
+fn format_utc(ms: u128) -> String {
+    format!("{} UTC", &utc_timestamp(ms)[..16])
+}
+
+// `2026-10-15 09:30:00`, a form spreadsheets read as a date
+pub fn utc_timestamp(ms: u128) -> String {
+    let secs = (ms / 1000) as i64;
+    let (days, rem) = (secs.div_euclid(86_400), secs.rem_euclid(86_400));
+    // Days since 1970-01-01 to a civil date (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+const STYLE: &str = "body{font-family:-apple-system,'Segoe UI',Helvetica,Arial,sans-serif;\
+margin:2em;color:#222}h1{margin-bottom:0}.meta{color:#666}\
+table{border-collapse:collapse;margin:1em 0}th,td{padding:4px 10px;text-align:left;\
+border-bottom:1px solid #ddd}.files th{cursor:pointer;background:#f4f4f4}\
+.files td[data-sort]{text-align:right}tr.failed td{background:#fdecea}\
+tr.skipped td{color:#888}.chart .row{display:flex;align-items:center;margin:2px 0}\
+.chart .name{width:30%;overflow:hidden;text-overflow:ellipsis;white-space:nowrap}\
+.chart .bars{width:55%;display:flex;flex-direction:column}\
+.chart .before,.chart .after{display:block;height:7px}.chart .before{background:#bbb}\
+.chart .after{background:#3a8f4b}.chart .saved{width:15%;text-align:right}\
+pre{background:#f6f6f6;padding:8px;overflow-x:auto;font-size:85%}";
+
+// Click a header to sort by that column, again to reverse
+const SORT_SCRIPT: &str = "document.querySelectorAll('table.files th').forEach(function(th,i){\
+th.addEventListener('click',function(){var body=th.closest('table').tBodies[0];\
+var dir=th.dataset.dir==='asc'?-1:1;th.dataset.dir=dir===1?'asc':'desc';\
+var key=function(tr){var td=tr.cells[i];return td.dataset.sort!==undefined?\
+parseFloat(td.dataset.sort):td.textContent.toLowerCase();};\
+Array.from(body.rows).sort(function(a,b){var x=key(a),y=key(b);\
+return (x<y?-1:x>y?1:0)*dir;}).forEach(function(tr){body.appendChild(tr);});});});";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, status: Status, before: u64, after: u64) -> Entry {
+        Entry {
+            name: name.to_string(),
+            input: PathBuf::from("/in").join(name),
+            preset: Some("anime".to_string()),
+            status,
+            input_bytes: before,
+            output_bytes: after,
+            seconds: 90.0,
+        }
+    }
+
+    #[test]
+    fn report_lists_files_savings_and_escaped_failures() {
+        let mut report = Report::new(
+            Path::new("report.html"),
+            Path::new("/in"),
+            Path::new("/out"),
+            "vcodec=libx265, acodec=aac, ext=mkv",
+            Units::default(),
+        );
+        report.add(entry("a.mkv", Status::Done, 4 << 30, 1 << 30));
+        report.add(entry(
+            "<b>.mkv",
+            Status::Failed {
+                error: "ffmpeg exited with status: Some(1)".to_string(),
+                log: vec!["[matroska] Invalid data & more".to_string()],
+            },
+            100,
+            0,
+        ));
+        report.add(entry("c.mkv", Status::Skipped("up to date"), 100, 0));
+        let html = report.render(0, None);
+
+        assert!(html.contains("<td>4.0 GiB &rarr; 1.0 GiB (-75.0%)</td>"));
+        assert!(html.contains("<span class=\"saved\">-75.0%</span>"));
+        assert!(html.contains("<h3>&lt;b&gt;.mkv</h3>"));
+        assert!(html.contains("<pre>[matroska] Invalid data &amp; more</pre>"));
+        assert!(html.contains("<tr class=\"skipped\"><td>up to date</td>"));
+        assert!(html.contains("1970-01-01 00:00 UTC"));
+        assert!(!html.contains("<b>"));
+        assert!(
+            report
+                .render(0, Some((3, 10)))
+                .contains("(in progress: 3 of 10 files)")
+        );
+    }
+
+    #[test]
+    fn log_tail_drops_progress_and_keeps_the_last_lines() {
+        let mut tail = LogTail::default();
+        for i in 0..30 {
+            tail.push(format!("line {}\n", i).as_bytes());
+        }
+        tail.push(b"frame=  10 fps=5\rframe=  20 fps=5\r\nError while decoding");
+        let lines = tail.finish();
+        assert_eq!(lines.len(), LOG_LINES);
+        assert_eq!(lines[0], "line 11");
+        assert_eq!(lines.last().unwrap(), "Error while decoding");
+        assert_eq!(format_utc(1_792_056_600_000), "2026-10-15 09:30 UTC");
+    }
+}
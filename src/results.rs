@@ -0,0 +1,219 @@
+// file: src/results.rs
+// version: 0.1.0
+// guid: 32b29b38-f278-4e4a-94e5-dad0b6d2415c
+
+//! `--json`: what a `transcode` or `batch` run did, as one JSON document for scripts
+//! and other services: each file with its outcome, time and sizes, then the totals and
+//! whether the run succeeded.
+//!
+//! The document is written once the run ends, failed or not: to the path given with
+//! `--json=PATH`, else as the last line on stdout, after the usual progress output.
+
+use std::cell::RefCell;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::events::path_str;
+use crate::report;
+use crate::schema;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Done,
+    Failed,
+    Skipped,
+    // A dry run's file that would be encoded
+    Planned,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileResult {
+    pub input: String,
+    pub output: String,
+    pub status: Status,
+    // Why a file was skipped
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub preset: Option<String>,
+    pub seconds: f64,
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+}
+
+impl FileResult {
+    pub fn new(input: &Path, output: &Path, preset: Option<&str>, status: Status) -> Self {
+        Self {
+            input: path_str(input),
+            output: path_str(output),
+            status,
+            reason: None,
+            error: None,
+            preset: preset.map(str::to_string),
+            seconds: 0.0,
+            input_bytes: fs::metadata(input).map(|m| m.len()).unwrap_or(0),
+            output_bytes: 0,
+        }
+    }
+
+    // A batch report entry, whose output is named relative to `output_dir`
+    pub fn of(entry: &report::Entry, output_dir: &Path) -> Self {
+        let (status, reason, error) = match &entry.status {
+            report::Status::Done => (Status::Done, None, None),
+            report::Status::Failed { error, .. } => (Status::Failed, None, Some(error.clone())),
+            report::Status::Skipped(reason) => (Status::Skipped, Some(reason.to_string()), None),
+        };
+        Self {
+            input: path_str(&entry.input),
+            output: path_str(&output_dir.join(&entry.name)),
+            status,
+            reason,
+            error,
+            preset: entry.preset.clone(),
+            seconds: entry.seconds,
+            input_bytes: entry.input_bytes,
+            output_bytes: entry.output_bytes,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Document<'a> {
+    schema_version: u32,
+    command: &'a str,
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    seconds: f64,
+    succeeded: usize,
+    failed: usize,
+    skipped: usize,
+    planned: usize,
+    input_bytes: u64,
+    output_bytes: u64,
+    files: &'a [FileResult],
+}
+
+pub struct Results {
+    path: Option<PathBuf>,
+    started: Instant,
+    files: RefCell<Vec<FileResult>>,
+}
+
+impl Results {
+    pub fn new(path: Option<PathBuf>) -> Self {
+        Self {
+            path,
+            started: Instant::now(),
+            files: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn add(&self, file: FileResult) {
+        self.files.borrow_mut().push(file);
+    }
+
+    fn document<'a>(
+        &self,
+        command: &'a str,
+        result: &Result<()>,
+        files: &'a [FileResult],
+    ) -> Document<'a> {
+        let count = |status: Status| files.iter().filter(|f| f.status == status).count();
+        let done = files.iter().filter(|f| f.status == Status::Done);
+        Document {
+            schema_version: schema::RESULTS,
+            command,
+            ok: result.is_ok(),
+            error: result.as_ref().err().map(|e| format!("{:#}", e)),
+            seconds: self.started.elapsed().as_secs_f64(),
+            succeeded: count(Status::Done),
+            failed: count(Status::Failed),
+            skipped: count(Status::Skipped),
+            planned: count(Status::Planned),
+            input_bytes: done.clone().map(|f| f.input_bytes).sum(),
+            output_bytes: done.map(|f| f.output_bytes).sum(),
+            files,
+        }
+    }
+
+    // Write the document for how `command` ended; the run's own error comes first
+    pub fn finish(&self, command: &str, result: Result<()>) -> Result<()> {
+        let files = self.files.borrow();
+        let document = self.document(command, &result, &files);
+        let written = match &self.path {
+            Some(path) => serde_json::to_vec_pretty(&document)
+                .context("failed to encode the results")
+                .and_then(|json| {
+                    fs::write(path, json)
+                        .with_context(|| format!("failed to write results {}", path.display()))
+                }),
+            None => serde_json::to_string(&document)
+                .context("failed to encode the results")
+                .map(|json| println!("{}", json)),
+        };
+        result.and(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn documents_keep_their_schema() {
+        let results = Results::new(None);
+        let mut file = FileResult::new(
+            Path::new("/tv/a.mp4"),
+            Path::new("/out/a.mkv"),
+            Some("original"),
+            Status::Done,
+        );
+        file.output_bytes = 700;
+        results.add(file);
+        results.add(FileResult::of(
+            &report::Entry {
+                name: "b.mkv".to_string(),
+                input: PathBuf::from("/tv/b.mp4"),
+                preset: None,
+                status: report::Status::Skipped("up to date"),
+                input_bytes: 0,
+                output_bytes: 0,
+                seconds: 0.0,
+            },
+            Path::new("/out"),
+        ));
+        let files = results.files.borrow();
+        let value = serde_json::to_value(results.document("batch", &Ok(()), &files)).unwrap();
+        schema::assert_fields(
+            &value,
+            1,
+            &[
+                "command",
+                "ok",
+                "seconds",
+                "succeeded",
+                "failed",
+                "skipped",
+                "planned",
+                "input_bytes",
+                "output_bytes",
+                "files",
+            ],
+        );
+        assert_eq!(value["files"][1]["output"], "/out/b.mkv");
+        assert_eq!(value["files"][1]["status"], "skipped");
+        assert_eq!(value["files"][1]["reason"], "up to date");
+        assert_eq!(
+            (value["succeeded"].as_u64(), value["skipped"].as_u64()),
+            (Some(1), Some(1))
+        );
+        assert_eq!(value["output_bytes"], 700);
+    }
+}
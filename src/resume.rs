@@ -0,0 +1,174 @@
+// file: src/resume.rs
+// version: 0.1.0
+// guid: 9b52e7d3-0c6a-4f18-8e41-d7a3c5f20b96
+
+//! The batch state manifest (`OUTPUT_DIR/.transcoderr-state.json`) and `batch --resume`.
+//!
+//! Every batch run lists its files there as pending when it starts and moves each to
+//! done or failed as it finishes, rewriting the manifest with a synced write-then-rename
+//! so an interrupted run leaves an accurate record. `--resume` reads the previous run's
+//! manifest and skips the files it finished, as long as their outputs are still there;
+//! failed and pending files are transcoded again. Files are keyed by their output's
+//! place under the output directory, so cue sheet tracks of one input are told apart.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::checkpoint::replace_file;
+use crate::report::utc_timestamp;
+use crate::schema;
+
+pub const FILE_NAME: &str = ".transcoderr-state.json";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Manifest {
+    #[serde(default = "schema::unversioned")]
+    schema_version: u32,
+    updated_utc: String,
+    settings: String,
+    done: BTreeSet<String>,
+    failed: BTreeSet<String>,
+    pending: BTreeSet<String>,
+}
+
+pub struct State {
+    path: PathBuf,
+    manifest: Manifest,
+}
+
+// What an earlier run finished, for `--resume`
+pub struct Previous {
+    done: BTreeSet<String>,
+    settings: String,
+}
+
+impl Previous {
+    // The manifest in `output_dir`, or None when there is none
+    pub fn load(output_dir: &Path) -> Result<Option<Self>> {
+        let path = output_dir.join(FILE_NAME);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+        };
+        let manifest: Manifest = serde_json::from_str(&text)
+            .with_context(|| format!("failed to parse {}", path.display()))?;
+        Ok(Some(Self {
+            done: manifest.done,
+            settings: manifest.settings,
+        }))
+    }
+
+    pub fn is_done(&self, key: &str) -> bool {
+        self.done.contains(key)
+    }
+
+    pub fn settings(&self) -> &str {
+        &self.settings
+    }
+}
+
+impl State {
+    // Start this run's manifest with the files in `keys` pending, or done already when
+    // `resumed` says so, and save it
+    pub fn start(
+        output_dir: &Path,
+        settings: &str,
+        keys: impl IntoIterator<Item = String>,
+        resumed: impl Fn(&str) -> bool,
+    ) -> Result<Self> {
+        let (done, pending) = keys.into_iter().partition(|key| resumed(key));
+        let mut state = Self {
+            path: output_dir.join(FILE_NAME),
+            manifest: Manifest {
+                schema_version: schema::STATE,
+                settings: settings.to_string(),
+                done,
+                pending,
+                ..Manifest::default()
+            },
+        };
+        state.write()?;
+        Ok(state)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Move `key` out of pending and save the manifest
+    pub fn finished(&mut self, key: &str, succeeded: bool) -> Result<()> {
+        let manifest = &mut self.manifest;
+        manifest.pending.remove(key);
+        manifest.done.remove(key);
+        manifest.failed.remove(key);
+        match succeeded {
+            true => manifest.done.insert(key.to_string()),
+            false => manifest.failed.insert(key.to_string()),
+        };
+        self.write()
+    }
+
+    fn write(&mut self) -> Result<()> {
+        self.manifest.updated_utc = utc_timestamp(now_ms());
+        let json = serde_json::to_vec_pretty(&self.manifest)?;
+        replace_file(&self.path, &json)
+            .with_context(|| format!("failed to write state manifest {}", self.path.display()))
+    }
+}
+
+fn now_ms() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_resumed_run_sees_what_the_previous_one_finished() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(Previous::load(dir.path()).unwrap().is_none());
+
+        let keys = ["a.mkv", "b.mkv", "c.mkv"].map(String::from);
+        let mut state =
+            State::start(dir.path(), "vcodec=libx265", keys.clone(), |_| false).unwrap();
+        state.finished("a.mkv", true).unwrap();
+        state.finished("b.mkv", false).unwrap();
+
+        let manifest: serde_json::Value =
+            serde_json::from_slice(&fs::read(dir.path().join(FILE_NAME)).unwrap()).unwrap();
+        assert_eq!(manifest["done"], serde_json::json!(["a.mkv"]));
+        assert_eq!(manifest["failed"], serde_json::json!(["b.mkv"]));
+        assert_eq!(manifest["pending"], serde_json::json!(["c.mkv"]));
+        schema::assert_fields(
+            &manifest,
+            1,
+            &["updated_utc", "settings", "done", "failed", "pending"],
+        );
+
+        let previous = Previous::load(dir.path()).unwrap().unwrap();
+        assert!(previous.is_done("a.mkv"));
+        assert!(!previous.is_done("b.mkv") && !previous.is_done("c.mkv"));
+        assert_eq!(previous.settings(), "vcodec=libx265");
+
+        // The next run starts with what it resumed done, and a retried failure moves there
+        let mut state = State::start(dir.path(), "vcodec=libx265", keys, |key| {
+            previous.is_done(key)
+        })
+        .unwrap();
+        state.finished("b.mkv", true).unwrap();
+        let previous = Previous::load(dir.path()).unwrap().unwrap();
+        assert!(previous.is_done("a.mkv") && previous.is_done("b.mkv"));
+        assert!(!previous.is_done("c.mkv"));
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
@@ -0,0 +1,388 @@
+// file: src/rules.rs
+// version: 0.2.0
+// guid: 5b4b2a0f-ff96-49a8-9479-0328f9bb6de4
+
+//! `batch --only-if`: transcode only the files a condition holds for, such as
+//! `vcodec!=hevc && height>1080 || bitrate>15M`, checked against each input's probe.
+//!
+//! A condition compares probe fields with `==`, `!=`, `<`, `<=`, `>` and `>=`, joined
+//! with `&&` (binding tighter) and `||`, negated with `!` and grouped with parentheses.
+//! Numbers take k, M and G suffixes (powers of 1000, as bitrates are written); codec
+//! names compare case-insensitively. A field the file does not have (no audio track,
+//! no bitrate in the container) makes its comparison false. Files the condition does
+//! not hold for are skipped, or remuxed with `--otherwise copy`.
+
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{Result, bail};
+use clap::ValueEnum;
+
+use crate::probe::MediaInfo;
+
+// What happens to the files a condition does not hold for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Otherwise {
+    Skip,
+    Copy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Vcodec,
+    Acodec,
+    Width,
+    Height,
+    Bitrate,
+    Vbitrate,
+    Fps,
+    Duration,
+    Channels,
+    AudioTracks,
+    SubtitleTracks,
+}
+
+const FIELDS: [(&str, Field); 11] = [
+    ("vcodec", Field::Vcodec),
+    ("acodec", Field::Acodec),
+    ("width", Field::Width),
+    ("height", Field::Height),
+    ("bitrate", Field::Bitrate),
+    ("vbitrate", Field::Vbitrate),
+    ("fps", Field::Fps),
+    ("duration", Field::Duration),
+    ("channels", Field::Channels),
+    ("audio_tracks", Field::AudioTracks),
+    ("subtitle_tracks", Field::SubtitleTracks),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Number(f64),
+    Text(String),
+}
+
+impl Field {
+    fn parse(name: &str) -> Result<Self> {
+        match FIELDS.iter().find(|(field, _)| *field == name) {
+            Some((_, field)) => Ok(*field),
+            None => bail!(
+                "unknown field '{}' (expected one of {})",
+                name,
+                FIELDS.map(|(field, _)| field).join(", ")
+            ),
+        }
+    }
+
+    fn is_text(self) -> bool {
+        matches!(self, Field::Vcodec | Field::Acodec)
+    }
+
+    // The field's value for `info`: the main video's, the first audio track's, or the
+    // container's for bitrate and duration (the video's when the container has none)
+    fn value(self, info: &MediaInfo) -> Option<Value> {
+        let video = info.video_stream();
+        let audio = info.streams.iter().find(|s| s.is_type("audio"));
+        let count = |codec_type: &str| {
+            let tracks = info.streams.iter().filter(|s| s.is_type(codec_type));
+            Some(Value::Number(tracks.count() as f64))
+        };
+        let number = |n: Option<f64>| n.map(Value::Number);
+        match self {
+            Field::Vcodec => video?.codec_name.clone().map(Value::Text),
+            Field::Acodec => audio?.codec_name.clone().map(Value::Text),
+            Field::Width => number(video?.width.map(f64::from)),
+            Field::Height => number(video?.height.map(f64::from)),
+            Field::Bitrate => number(info.format.bit_rate().map(|b| b as f64)),
+            Field::Vbitrate => number(video?.bit_rate().map(|b| b as f64)),
+            Field::Fps => number(video?.frame_rate()),
+            Field::Duration => match info.format.duration_seconds() {
+                Some(seconds) => number(Some(seconds)),
+                None => number(video?.duration_seconds()),
+            },
+            Field::Channels => number(audio?.channels.map(f64::from)),
+            Field::AudioTracks => count("audio"),
+            Field::SubtitleTracks => count("subtitle"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Op {
+    fn holds(self, ordering: std::cmp::Ordering) -> bool {
+        use std::cmp::Ordering::*;
+        match self {
+            Op::Eq => ordering == Equal,
+            Op::Ne => ordering != Equal,
+            Op::Lt => ordering == Less,
+            Op::Le => ordering != Greater,
+            Op::Gt => ordering == Greater,
+            Op::Ge => ordering != Less,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Compare(Field, Op, Value),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn eval(&self, info: &MediaInfo) -> bool {
+        match self {
+            Expr::Compare(field, op, expected) => {
+                let ordering = match (field.value(info), expected) {
+                    (Some(Value::Number(n)), Value::Number(m)) => n.partial_cmp(m),
+                    (Some(Value::Text(t)), Value::Text(u)) => {
+                        Some(t.to_lowercase().cmp(&u.to_lowercase()))
+                    }
+                    _ => None,
+                };
+                ordering.is_some_and(|ordering| op.holds(ordering))
+            }
+            Expr::Not(expr) => !expr.eval(info),
+            Expr::And(a, b) => a.eval(info) && b.eval(info),
+            Expr::Or(a, b) => a.eval(info) || b.eval(info),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Op(Op),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let next_is = |chars: &mut std::iter::Peekable<std::str::Chars>, want: char| {
+            chars.next_if_eq(&want).is_some()
+        };
+        let token = match c {
+            _ if c.is_whitespace() => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '&' if next_is(&mut chars, '&') => Token::And,
+            '|' if next_is(&mut chars, '|') => Token::Or,
+            '=' if next_is(&mut chars, '=') => Token::Op(Op::Eq),
+            '=' => Token::Op(Op::Eq),
+            '!' if next_is(&mut chars, '=') => Token::Op(Op::Ne),
+            '!' => Token::Not,
+            '<' if next_is(&mut chars, '=') => Token::Op(Op::Le),
+            '<' => Token::Op(Op::Lt),
+            '>' if next_is(&mut chars, '=') => Token::Op(Op::Ge),
+            '>' => Token::Op(Op::Gt),
+            '"' | '\'' => {
+                let word: String = chars.by_ref().take_while(|d| *d != c).collect();
+                Token::Word(word)
+            }
+            _ if c.is_alphanumeric() || "._-+".contains(c) => {
+                let mut word = c.to_string();
+                while let Some(d) = chars.next_if(|d| d.is_alphanumeric() || "._-+".contains(*d)) {
+                    word.push(d);
+                }
+                Token::Word(word)
+            }
+            _ => bail!("unexpected '{}'", c),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+// 15M -> 15000000, 1.5k -> 1500
+fn number(word: &str) -> Option<f64> {
+    let (digits, scale) = match word.char_indices().last()? {
+        (i, 'k' | 'K') => (&word[..i], 1e3),
+        (i, 'm' | 'M') => (&word[..i], 1e6),
+        (i, 'g' | 'G') => (&word[..i], 1e9),
+        _ => (word, 1.0),
+    };
+    digits.parse::<f64>().ok().map(|n| n * scale)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    at: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.at)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.at).cloned();
+        self.at += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Expr> {
+        let mut expr = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.at += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr> {
+        let mut expr = self.atom()?;
+        while self.peek() == Some(&Token::And) {
+            self.at += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.atom()?));
+        }
+        Ok(expr)
+    }
+
+    fn atom(&mut self) -> Result<Expr> {
+        match self.next() {
+            Some(Token::Not) => Ok(Expr::Not(Box::new(self.atom()?))),
+            Some(Token::Open) => {
+                let expr = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(expr),
+                    _ => bail!("missing ')'"),
+                }
+            }
+            Some(Token::Word(name)) => {
+                let field = Field::parse(&name)?;
+                let Some(Token::Op(op)) = self.next() else {
+                    bail!("expected a comparison after '{}'", name);
+                };
+                let Some(Token::Word(word)) = self.next() else {
+                    bail!("expected a value after '{}'", name);
+                };
+                let value = match (field.is_text(), number(&word)) {
+                    (true, _) if !matches!(op, Op::Eq | Op::Ne) => {
+                        bail!("'{}' can only be compared with == or !=", name)
+                    }
+                    (true, _) => Value::Text(word),
+                    (false, Some(n)) => Value::Number(n),
+                    (false, None) => bail!("'{}' needs a number, not '{}'", name, word),
+                };
+                Ok(Expr::Compare(field, op, value))
+            }
+            Some(token) => bail!("unexpected {:?}", token),
+            None => bail!("unexpected end of condition"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    text: String,
+    expr: Expr,
+}
+
+impl FromStr for Condition {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Self> {
+        let invalid = |e: anyhow::Error| anyhow::anyhow!("invalid condition '{}': {}", text, e);
+        let mut parser = Parser {
+            tokens: tokenize(text).map_err(invalid)?,
+            at: 0,
+        };
+        let expr = parser.or().map_err(invalid)?;
+        if let Some(token) = parser.peek() {
+            return Err(invalid(anyhow::anyhow!("unexpected {:?}", token)));
+        }
+        Ok(Condition {
+            text: text.to_string(),
+            expr,
+        })
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl Condition {
+    pub fn holds(&self, info: &MediaInfo) -> bool {
+        self.expr.eval(info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(vcodec: &str, height: u32, bitrate: &str, audio: &[&str]) -> MediaInfo {
+        let mut streams = vec![
+            serde_json::json!({"codec_type": "video", "codec_name": vcodec, "height": height}),
+        ];
+        streams.extend(
+            audio
+                .iter()
+                .map(|codec| serde_json::json!({"codec_type": "audio", "codec_name": codec})),
+        );
+        serde_json::from_value(serde_json::json!({
+            "streams": streams,
+            "format": {"bit_rate": bitrate},
+        }))
+        .unwrap()
+    }
+
+    fn holds(condition: &str, info: &MediaInfo) -> bool {
+        condition.parse::<Condition>().unwrap().holds(info)
+    }
+
+    #[test]
+    fn conditions_combine_comparisons_on_probe_fields() {
+        let condition = "vcodec!=hevc && height>1080 || bitrate>15M";
+        let uhd_h264 = info("h264", 2160, "12000000", &["aac"]);
+        let hd_h264 = info("h264", 1080, "8000000", &["aac"]);
+        let big_hevc = info("HEVC", 1080, "20000000", &[]);
+        assert!(holds(condition, &uhd_h264));
+        assert!(!holds(condition, &hd_h264));
+        assert!(holds(condition, &big_hevc));
+        assert!(!holds(
+            "vcodec!=hevc && (height>1080 || bitrate>15M)",
+            &big_hevc
+        ));
+        assert!(holds("!(acodec == aac) && audio_tracks >= 0", &big_hevc));
+        // No audio track: the comparison is false either way
+        assert!(!holds("acodec!=aac", &big_hevc));
+        assert!(holds("bitrate<=8000k && height=1080", &hd_h264));
+    }
+
+    #[test]
+    fn malformed_conditions_are_rejected() {
+        for condition in [
+            "",
+            "vcodec",
+            "height>",
+            "height>tall",
+            "vcodec>hevc",
+            "size>1G",
+            "(height>1080",
+            "height>1080 bitrate>1M",
+            "height>1080 & bitrate>1M",
+        ] {
+            assert!(condition.parse::<Condition>().is_err(), "{}", condition);
+        }
+    }
+}
@@ -0,0 +1,442 @@
+// file: src/schedule.rs
+// version: 0.5.0
+// guid: 4d92a6c1-8e5b-4f37-b0d4-21c9e6a7f358
+
+//! Gates consulted before each batch job starts: temperature limits and the `[should-run]` hook.
+//! On Unix the temperature limit also pauses the encodes already running (SIGSTOP) until
+//! the machine has cooled (SIGCONT).
+
+use std::cell::Cell;
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+
+use crate::events::{Event, Events};
+use crate::style::Marker;
+#[cfg(unix)]
+use crate::tools;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ShouldRunConfig {
+    /// Shell command; exit status 0 means "run now", anything else means "wait"
+    pub command: Option<String>,
+    /// HTTP endpoint; a 2xx answer whose body is `yes`, `true`, `1` or `run` means "run now"
+    pub url: Option<String>,
+    /// Seconds to wait before asking again after a "no"
+    #[serde(default = "default_poll_seconds")]
+    pub poll_seconds: u64,
+}
+
+fn default_poll_seconds() -> u64 {
+    60
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct TemperatureConfig {
+    /// Pause the running encodes and hold the next job at or above this temperature
+    pub max_celsius: f64,
+    /// Resume once the hottest sensor has cooled to this (default: 10 below the maximum)
+    pub resume_celsius: Option<f64>,
+    /// Only use hwmon sensors with these names, e.g. `["coretemp", "k10temp", "amdgpu"]`
+    #[serde(default)]
+    pub sensors: Vec<String>,
+    /// Shell command printing a temperature in °C (for platforms without hwmon, e.g. macOS)
+    pub command: Option<String>,
+    /// Seconds between readings while paused
+    #[serde(default = "default_temperature_poll_seconds")]
+    pub poll_seconds: u64,
+}
+
+fn default_temperature_poll_seconds() -> u64 {
+    30
+}
+
+impl TemperatureConfig {
+    fn resume_at(&self) -> f64 {
+        self.resume_celsius.unwrap_or(self.max_celsius - 10.0)
+    }
+
+    // Whether to stay paused at `celsius`: from the maximum up while running, until
+    // cooled to the resume point once paused
+    fn too_hot(&self, celsius: f64, paused: bool) -> bool {
+        if paused {
+            celsius > self.resume_at()
+        } else {
+            celsius >= self.max_celsius
+        }
+    }
+}
+
+// Whether the machine is over the temperature limit and has not cooled to the resume
+// point yet. One state for holding the next job and pausing the running ones, so no job
+// starts while the running encodes are stopped.
+#[derive(Debug, Clone, Default)]
+struct Heat(Arc<Mutex<bool>>);
+
+impl Heat {
+    // Take a reading; on the way over the limit the running encodes are stopped, and on
+    // the way back they are continued. Returns whether it is still too hot.
+    fn observe(&self, limits: &TemperatureConfig, celsius: f64) -> bool {
+        let mut hot = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        let too_hot = limits.too_hot(celsius, *hot);
+        if too_hot != *hot {
+            *hot = too_hot;
+            #[cfg(unix)]
+            match too_hot {
+                true if tools::signal_streaming(libc::SIGSTOP) > 0 => println!(
+                    "  Pausing running encodes: {:.1}°C reached the {:.1}°C limit",
+                    celsius, limits.max_celsius
+                ),
+                false if tools::signal_streaming(libc::SIGCONT) > 0 => {
+                    println!("  Continuing paused encodes at {:.1}°C", celsius)
+                }
+                _ => {}
+            }
+        }
+        too_hot
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Gate {
+    should_run: Option<ShouldRunConfig>,
+    temperature: Option<TemperatureConfig>,
+    heat: Heat,
+    sensor_warned: Cell<bool>,
+    // Whether the thread pausing running encodes while too hot has started
+    #[cfg(unix)]
+    watching: Cell<bool>,
+}
+
+impl Gate {
+    pub fn new(
+        should_run: Option<ShouldRunConfig>,
+        temperature: Option<TemperatureConfig>,
+    ) -> Result<Self> {
+        if let Some(limits) = &temperature {
+            if limits.resume_at() > limits.max_celsius {
+                bail!("[temperature] resume-celsius must not be above max-celsius");
+            }
+            if limits.poll_seconds == 0 {
+                bail!("[temperature] poll-seconds must be at least 1");
+            }
+        }
+        if let Some(hook) = &should_run {
+            match (&hook.command, &hook.url) {
+                (Some(_), Some(_)) => {
+                    bail!("[should-run] takes either `command` or `url`, not both")
+                }
+                (None, None) => bail!("[should-run] needs a `command` or a `url`"),
+                _ => {}
+            }
+            if hook.poll_seconds == 0 {
+                bail!("[should-run] poll-seconds must be at least 1");
+            }
+        }
+        Ok(Self {
+            should_run,
+            temperature,
+            heat: Heat::default(),
+            sensor_warned: Cell::new(false),
+            #[cfg(unix)]
+            watching: Cell::new(false),
+        })
+    }
+
+    // Block until both the temperature limit and the should-run hook allow the next job
+    pub fn wait(&self, input: &Path, output: &Path, events: &Events) {
+        self.wait_until_cool(events);
+        self.wait_for_hook(input, output, events);
+        #[cfg(unix)]
+        self.watch_running();
+    }
+
+    // From the first job on, pause every running encode while the hottest sensor is at
+    // or above the limit, and continue them once it has cooled to the resume temperature
+    #[cfg(unix)]
+    fn watch_running(&self) {
+        let Some(limits) = self.temperature.clone() else {
+            return;
+        };
+        if self.watching.replace(true) {
+            return;
+        }
+        let heat = self.heat.clone();
+        thread::spawn(move || {
+            loop {
+                thread::sleep(Duration::from_secs(limits.poll_seconds));
+                // `wait_until_cool` reports unreadable sensors
+                if let Ok(celsius) = read_temperature(&limits) {
+                    heat.observe(&limits, celsius);
+                }
+            }
+        });
+    }
+
+    // Pause while the hottest sensor is at or above the limit, until it has cooled to the
+    // resume temperature. Unreadable sensors are reported once and never block.
+    fn wait_until_cool(&self, events: &Events) {
+        let Some(limits) = &self.temperature else {
+            return;
+        };
+        let mut paused = false;
+        loop {
+            let celsius = match read_temperature(limits) {
+                Ok(celsius) => celsius,
+                Err(e) => {
+                    if !self.sensor_warned.replace(true) {
+                        eprintln!(
+                            "  {} cannot read temperature ({:#}); not throttling",
+                            Marker::Warning,
+                            e
+                        );
+                    }
+                    break;
+                }
+            };
+            if !self.heat.observe(limits, celsius) {
+                break;
+            }
+            if !paused {
+                println!(
+                    "  Paused: {:.1}°C reached the {:.1}°C limit; resuming at {:.1}°C",
+                    celsius,
+                    limits.max_celsius,
+                    limits.resume_at()
+                );
+                events.emit(Event::Paused {
+                    reason: format!("temperature {:.1}C", celsius),
+                });
+                paused = true;
+            }
+            thread::sleep(Duration::from_secs(limits.poll_seconds));
+        }
+        if paused {
+            println!("  Resuming");
+            events.emit(Event::Resumed);
+        }
+    }
+
+    // Block until the hook allows the next job. A hook that errors (missing script,
+    // unreachable endpoint) is reported and treated as "yes" so the queue never stalls
+    // on a broken hook.
+    fn wait_for_hook(&self, input: &Path, output: &Path, events: &Events) {
+        let Some(hook) = &self.should_run else {
+            return;
+        };
+        let mut paused = false;
+        loop {
+            match ask(hook, input, output) {
+                Ok(true) => break,
+                Ok(false) => {
+                    if !paused {
+                        println!(
+                            "  Paused by should-run hook; asking again every {}s",
+                            hook.poll_seconds
+                        );
+                        events.emit(Event::Paused {
+                            reason: "should-run".to_string(),
+                        });
+                        paused = true;
+                    }
+                    thread::sleep(Duration::from_secs(hook.poll_seconds));
+                }
+                Err(e) => {
+                    eprintln!(
+                        "  {} should-run hook failed ({:#}); running anyway",
+                        Marker::Warning,
+                        e
+                    );
+                    break;
+                }
+            }
+        }
+        if paused {
+            println!("  Resuming");
+            events.emit(Event::Resumed);
+        }
+    }
+}
+
+// Run through the shell so users can write pipelines
+fn shell(command: &str) -> Command {
+    let mut cmd = if cfg!(windows) {
+        let mut cmd = Command::new("cmd");
+        cmd.arg("/C");
+        cmd
+    } else {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c");
+        cmd
+    };
+    cmd.arg(command);
+    cmd
+}
+
+fn ask(hook: &ShouldRunConfig, input: &Path, output: &Path) -> Result<bool> {
+    if let Some(command) = &hook.command {
+        // The next job is passed in the environment
+        let status = shell(command)
+            .env("TRANSCODERR_INPUT", input)
+            .env("TRANSCODERR_OUTPUT", output)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .status()
+            .with_context(|| format!("failed to run '{}'", command))?;
+        return Ok(status.success());
+    }
+
+    let url = hook.url.as_deref().unwrap_or_default();
+    let response = match ureq::get(url).timeout(Duration::from_secs(10)).call() {
+        Ok(response) => response,
+        // A deliberate non-2xx answer is a "no", not a broken hook
+        Err(ureq::Error::Status(_, _)) => return Ok(false),
+        Err(e) => return Err(e).with_context(|| format!("failed to query {}", url)),
+    };
+    let body = response
+        .into_string()
+        .with_context(|| format!("failed to read answer from {}", url))?;
+    Ok(matches!(
+        body.trim().to_ascii_lowercase().as_str(),
+        "yes" | "true" | "1" | "run"
+    ))
+}
+
+// Hottest reading in °C from the sensor command, or from Linux hwmon/thermal zones
+fn read_temperature(limits: &TemperatureConfig) -> Result<f64> {
+    if let Some(command) = &limits.command {
+        let output = shell(command)
+            .stdin(Stdio::null())
+            .stderr(Stdio::inherit())
+            .output()
+            .with_context(|| format!("failed to run '{}'", command))?;
+        let text = String::from_utf8_lossy(&output.stdout);
+        // Accept "71.5", "71.5°C" or "CPU: 71.5 C": the first number printed
+        let number: String = text
+            .trim_start_matches(|c: char| !c.is_ascii_digit() && c != '-')
+            .chars()
+            .take_while(|c| c.is_ascii_digit() || *c == '.' || *c == '-')
+            .collect();
+        return number
+            .parse()
+            .with_context(|| format!("'{}' did not print a temperature", command));
+    }
+    read_sysfs(Path::new("/sys/class"), &limits.sensors)
+}
+
+fn read_sysfs(class: &Path, sensors: &[String]) -> Result<f64> {
+    // Readings are in millidegrees
+    let mut readings: Vec<f64> = Vec::new();
+    for hwmon in read_dir_paths(&class.join("hwmon")) {
+        let name = fs::read_to_string(hwmon.join("name")).unwrap_or_default();
+        if !sensors.is_empty() && !sensors.iter().any(|s| s == name.trim()) {
+            continue;
+        }
+        for entry in read_dir_paths(&hwmon) {
+            let file = entry.file_name().unwrap_or_default().to_string_lossy();
+            if file.starts_with("temp") && file.ends_with("_input") {
+                readings.extend(read_number(&entry));
+            }
+        }
+    }
+    // Thermal zones have no hwmon names, so they only count without a sensor filter
+    if readings.is_empty() && sensors.is_empty() {
+        for zone in read_dir_paths(&class.join("thermal")) {
+            if zone.to_string_lossy().contains("thermal_zone") {
+                readings.extend(read_number(&zone.join("temp")));
+            }
+        }
+    }
+    readings
+        .into_iter()
+        .map(|m| m / 1000.0)
+        .reduce(f64::max)
+        .with_context(|| {
+            if sensors.is_empty() {
+                "no temperature sensors found (set [temperature] command)".to_string()
+            } else {
+                format!("no hwmon sensors named {}", sensors.join(", "))
+            }
+        })
+}
+
+fn read_dir_paths(dir: &Path) -> Vec<std::path::PathBuf> {
+    let mut paths: Vec<_> = fs::read_dir(dir)
+        .map(|entries| entries.filter_map(|e| e.ok()).map(|e| e.path()).collect())
+        .unwrap_or_default();
+    paths.sort();
+    paths
+}
+
+fn read_number(path: &Path) -> Option<f64> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paused_until_cooled_to_the_resume_point() {
+        let limits = TemperatureConfig {
+            max_celsius: 85.0,
+            resume_celsius: None,
+            sensors: Vec::new(),
+            command: None,
+            poll_seconds: 30,
+        };
+        assert!(!limits.too_hot(84.9, false));
+        assert!(limits.too_hot(85.0, false));
+        assert!(limits.too_hot(80.0, true));
+        assert!(!limits.too_hot(75.0, true));
+    }
+
+    #[test]
+    fn jobs_wait_while_the_running_encodes_are_paused() {
+        let limits = TemperatureConfig {
+            max_celsius: 85.0,
+            resume_celsius: None,
+            sensors: Vec::new(),
+            command: None,
+            poll_seconds: 30,
+        };
+        let watcher = Heat::default();
+        let gate = watcher.clone();
+        assert!(watcher.observe(&limits, 90.0));
+        // Under the limit but not yet cooled to the resume point: the next job waits
+        assert!(gate.observe(&limits, 80.0));
+        assert!(!gate.observe(&limits, 75.0));
+        assert!(!watcher.observe(&limits, 80.0));
+    }
+
+    #[test]
+    fn hottest_matching_hwmon_sensor_wins() {
+        let class = tempfile::tempdir().unwrap();
+        for (dir, name, temps) in [
+            ("hwmon0", "acpitz", &["91000"][..]),
+            ("hwmon1", "coretemp", &["54000", "67500"][..]),
+        ] {
+            let hwmon = class.path().join("hwmon").join(dir);
+            fs::create_dir_all(&hwmon).unwrap();
+            fs::write(hwmon.join("name"), format!("{}\n", name)).unwrap();
+            for (i, temp) in temps.iter().enumerate() {
+                fs::write(hwmon.join(format!("temp{}_input", i + 1)), temp).unwrap();
+            }
+        }
+
+        assert_eq!(read_sysfs(class.path(), &[]).unwrap(), 91.0);
+        assert_eq!(
+            read_sysfs(class.path(), &["coretemp".to_string()]).unwrap(),
+            67.5
+        );
+        assert!(read_sysfs(class.path(), &["amdgpu".to_string()]).is_err());
+    }
+}
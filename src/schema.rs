@@ -0,0 +1,64 @@
+// file: src/schema.rs
+// version: 0.4.0
+// guid: 5406e918-77ab-4de2-b4cd-0d8024bf9f69
+
+//! Schema versions of the JSON written for other programs: `info --json`, the
+//! `transcode`/`batch --json` results, the event log and MQTT payloads, the encode history, batch checkpoints and state manifests,
+//! the spot check manifest and the plugin protocol.
+//!
+//! Every document (or NDJSON line) carries a `schema_version` for its surface. Adding a
+//! field leaves the version alone, so readers should ignore keys they do not know;
+//! renaming, removing or retyping one bumps it. Each surface's models are pinned by a
+//! test through `assert_fields`, so a change to the layout cannot land unnoticed.
+
+// `info --json`: ffprobe's JSON plus `hdr` (and mediainfo's fields with that feature)
+pub const INFO: u32 = 1;
+// `transcode --json` and `batch --json` documents
+pub const RESULTS: u32 = 1;
+// `--event-log` lines and MQTT payloads
+pub const EVENTS: u32 = 1;
+// `history.ndjson` records
+pub const HISTORY: u32 = 1;
+// `.transcoderr-checkpoint.json`
+pub const CHECKPOINT: u32 = 1;
+// `.transcoderr-state.json`
+pub const STATE: u32 = 1;
+// `spot-check/manifest.ndjson` lines
+pub const SPOT_CHECK: u32 = 1;
+// The plugin protocol, passed to plugins in `initialize`
+pub const PLUGINS: u32 = 1;
+
+// Lines written before versioning have the version 1 layout
+pub fn unversioned() -> u32 {
+    1
+}
+
+// Add `schema_version` to a JSON object built by hand rather than from a model
+pub fn stamp(value: &mut serde_json::Value, version: u32) {
+    if let Some(object) = value.as_object_mut() {
+        object.insert("schema_version".to_string(), version.into());
+    }
+}
+
+// Compatibility check for a surface's tests: `value` has `version` and exactly these
+// top-level keys. A failure here means the layout changed; bump the version if a key
+// was renamed, removed or retyped, then update the list.
+#[cfg(test)]
+pub fn assert_fields(value: &serde_json::Value, version: u32, fields: &[&str]) {
+    assert_eq!(
+        value["schema_version"], version,
+        "schema_version of {}",
+        value
+    );
+    let mut keys: Vec<&str> = value
+        .as_object()
+        .expect("a JSON object")
+        .keys()
+        .map(String::as_str)
+        .filter(|key| *key != "schema_version")
+        .collect();
+    keys.sort_unstable();
+    let mut fields = fields.to_vec();
+    fields.sort_unstable();
+    assert_eq!(keys, fields);
+}
@@ -0,0 +1,203 @@
+// file: src/selftest.rs
+// version: 0.4.0
+// guid: 6ef0673e-888c-4838-bdf1-067f455af297
+
+//! `selftest`: validate the local ffmpeg setup end to end on synthetic media.
+//!
+//! Each fixture is generated, transcoded with the batch defaults (or the given codecs),
+//! checked for matching streams and duration, and checked for a preserved title tag.
+//! The results are printed as a pass/fail matrix; any failure fails the command.
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+use clap::Args;
+
+use crate::encode::ffmpeg_command;
+use crate::probe::{self, MediaInfo};
+use crate::style::{Marker, Table};
+use crate::synth::{FIXTURES, Fixture};
+use crate::tools::{self, Tool};
+
+// Durations may differ by up to a frame or an audio packet after re-encoding
+const DURATION_TOLERANCE: f64 = 0.5;
+const STEPS: [&str; 4] = ["generate", "transcode", "verify", "metadata"];
+
+#[derive(Args, Debug)]
+pub struct SelftestArgs {
+    /// Video codec to test (default: libx265, as batch uses)
+    #[arg(long, default_value = "libx265")]
+    pub vcodec: String,
+    /// Audio codec to test
+    #[arg(long, default_value = "aac")]
+    pub acodec: String,
+    /// Keep the generated and transcoded files instead of deleting them
+    #[arg(long)]
+    pub keep: bool,
+}
+
+// Outcome of one step for one fixture
+enum Check {
+    Pass,
+    Fail(String),
+    // Not run because an earlier step failed
+    Skipped,
+}
+
+pub fn run(args: &SelftestArgs) -> Result<()> {
+    for tool in [Tool::Ffmpeg, Tool::Ffprobe] {
+        let version = tool_version(tool)
+            .with_context(|| format!("{} is not available on PATH", tool.program()))?;
+        println!("{}", version);
+    }
+    let dir = std::env::temp_dir().join(format!("transcoderr-selftest-{}", std::process::id()));
+    println!(
+        "Testing vcodec={} acodec={} in {}\n",
+        args.vcodec,
+        args.acodec,
+        dir.display()
+    );
+
+    let fixtures: Vec<&Fixture> = FIXTURES.iter().filter(|f| f.selftest).collect();
+    let results: Vec<(&Fixture, Vec<Check>)> = fixtures
+        .iter()
+        .copied()
+        .map(|fixture| (fixture, check_fixture(fixture, &dir, args)))
+        .collect();
+
+    let mut headers = vec!["fixture"];
+    headers.extend(STEPS);
+    headers.push("about");
+    let mut table = Table::new(&headers);
+    let mut failures = Vec::new();
+    for (fixture, checks) in &results {
+        let mut row = vec![fixture.name.to_string()];
+        row.extend(checks.iter().map(|check| match check {
+            Check::Pass => Marker::Pass.to_string(),
+            Check::Fail(_) => Marker::Fail.to_string(),
+            Check::Skipped => "-".to_string(),
+        }));
+        row.push(fixture.about.to_string());
+        table.row(row);
+        for (step, check) in STEPS.iter().zip(checks) {
+            if let Check::Fail(why) = check {
+                failures.push(format!("{} {}: {}", fixture.name, step, why));
+            }
+        }
+    }
+
+    print!("{}", table);
+
+    if args.keep {
+        println!("\nFiles kept in {}", dir.display());
+    } else {
+        let _ = fs::remove_dir_all(&dir);
+    }
+    if !failures.is_empty() {
+        println!();
+        for failure in &failures {
+            println!("  {}", failure);
+        }
+        bail!("selftest failed: {} checks", failures.len());
+    }
+    println!("\nAll checks passed");
+    Ok(())
+}
+
+fn check_fixture(fixture: &Fixture, dir: &Path, args: &SelftestArgs) -> Vec<Check> {
+    let mut checks = Vec::new();
+    let input = match fixture.generate(dir) {
+        Ok(input) => input,
+        Err(e) => return finish(checks, Check::Fail(format!("{:#}", e))),
+    };
+    checks.push(Check::Pass);
+
+    let output = dir.join("out").join(fixture.name).with_extension("mkv");
+    if let Err(e) = encode(&input, &output, args) {
+        return finish(checks, Check::Fail(format!("{:#}", e)));
+    }
+    checks.push(Check::Pass);
+
+    let (source, result) = match (probe::probe(&input), probe::probe(&output)) {
+        (Ok(source), Ok(result)) => (source, result),
+        (Err(e), _) | (_, Err(e)) => return finish(checks, Check::Fail(format!("{:#}", e))),
+    };
+    checks.push(match verify(fixture, &source, &result) {
+        Ok(()) => Check::Pass,
+        Err(why) => Check::Fail(why),
+    });
+    checks.push(match result.format.tag("title") {
+        Some(title) if title == fixture.title => Check::Pass,
+        Some(title) => Check::Fail(format!(
+            "title is '{}', expected '{}'",
+            title, fixture.title
+        )),
+        None => Check::Fail("title tag was dropped".to_string()),
+    });
+    checks
+}
+
+// Record a failing step and mark the rest as not run
+fn finish(mut checks: Vec<Check>, failed: Check) -> Vec<Check> {
+    checks.push(failed);
+    while checks.len() < STEPS.len() {
+        checks.push(Check::Skipped);
+    }
+    checks
+}
+
+// The same command line a transcode would run, with ffmpeg's output kept for the report
+fn encode(input: &Path, output: &Path, args: &SelftestArgs) -> Result<()> {
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let cmd = ffmpeg_command(input, &[], output, &args.vcodec, &args.acodec, &[])
+        .global(["-v", "error"])
+        .build();
+    let result = tools::output(Tool::Ffmpeg, &cmd)?;
+    if !result.status.success() {
+        let stderr = String::from_utf8_lossy(&result.stderr);
+        bail!(
+            "ffmpeg exited with status {:?}: {}",
+            result.status.code(),
+            stderr.lines().last().unwrap_or("").trim()
+        );
+    }
+    Ok(())
+}
+
+fn verify(fixture: &Fixture, source: &MediaInfo, result: &MediaInfo) -> Result<(), String> {
+    for kind in ["video", "audio", "subtitle"] {
+        let count = |info: &MediaInfo| info.streams.iter().filter(|s| s.is_type(kind)).count();
+        if count(source) != count(result) {
+            return Err(format!(
+                "{} {} streams in, {} out",
+                count(source),
+                kind,
+                count(result)
+            ));
+        }
+    }
+    let duration = result
+        .format
+        .duration_seconds()
+        .ok_or("output has no duration")?;
+    if (duration - fixture.seconds()).abs() > DURATION_TOLERANCE {
+        return Err(format!(
+            "duration {:.2}s, expected {:.2}s",
+            duration,
+            fixture.seconds()
+        ));
+    }
+    Ok(())
+}
+
+pub fn tool_version(tool: Tool) -> Result<String> {
+    let output = tools::output(tool, &["-version".into()])?;
+    if !output.status.success() {
+        bail!("{} -version failed", tool.program());
+    }
+    let text = String::from_utf8_lossy(&output.stdout);
+    Ok(text.lines().next().unwrap_or(tool.program()).to_string())
+}
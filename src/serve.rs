@@ -0,0 +1,1170 @@
+// file: src/serve.rs
+// version: 0.9.0
+// guid: c33c665f-6719-4c28-9758-4a7ddd2bbe38
+
+//! `serve`: a small transcoding service over HTTP, e.g. on a NAS.
+//!
+//! Jobs are submitted as JSON, queued, and run `--jobs` at a time, each as a
+//! `transcoderr transcode` child process (with the server's `--config` and the global
+//! options that shape an encode, such as `--hwaccel` and `--event-log`). Its ffmpeg
+//! output gives the job's progress, and the tail of it is kept for failures. Cancelling
+//! a running job stops the child and its ffmpeg and removes the partial output. Jobs live
+//! in memory: the queue is gone when the server stops, and only the latest finished jobs
+//! are listed, the older ones just counted in `/metrics`.
+//!
+//! `/` is a dashboard over the same API, built into the binary from `assets/dashboard`,
+//! and `/metrics` has the job totals and current encode speed for Prometheus.
+//!
+//! Job templates (`[templates.NAME]` in the config) let a client submit just a template
+//! and a path. The config is read again whenever it changes; a bad edit is reported
+//! and the templates from before it stay in use.
+//!
+//! Requests that change anything need the run's token in `X-Transcoderr-Token`, JSON
+//! bodies, and no `Origin` but the server's own, so a web page elsewhere cannot submit
+//! or cancel jobs through a browser on the same network.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::hash::{BuildHasher, Hasher, RandomState};
+use std::io::{BufReader, Read};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::config;
+use crate::http::{Request, Response, read_request};
+use crate::metrics::Metrics;
+use crate::paths::{normalize, resolve_output_path};
+use crate::presets::{self, Preset, Presets};
+use crate::stats::{self, Progress};
+use crate::style::Marker;
+use crate::subtitles::SubtitlePolicy;
+
+// Lines of a job's output kept for when it fails
+const LOG_LINES: usize = 40;
+// Finished jobs kept for `GET /jobs`; older ones only count towards `/metrics`
+const FINISHED_KEPT: usize = 500;
+// A client that connects and sends nothing is dropped after this
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+// The dashboard: path, content type and contents
+const ASSETS: [(&str, &str, &[u8]); 3] = [
+    (
+        "/",
+        "text/html; charset=utf-8",
+        include_bytes!("../assets/dashboard/index.html"),
+    ),
+    (
+        "/app.js",
+        "text/javascript; charset=utf-8",
+        include_bytes!("../assets/dashboard/app.js"),
+    ),
+    (
+        "/style.css",
+        "text/css; charset=utf-8",
+        include_bytes!("../assets/dashboard/style.css"),
+    ),
+];
+
+#[derive(Args, Debug)]
+pub struct ServeArgs {
+    /// Address to listen on; 0.0.0.0:8099 accepts jobs from the whole network
+    #[arg(long, value_name = "ADDR", default_value = "127.0.0.1:8099")]
+    pub listen: String,
+    /// Encodes to run at once
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    pub jobs: u32,
+    /// Preset for jobs that name none (default: the config's `preset`)
+    #[arg(long)]
+    pub preset: Option<String>,
+    /// Token clients send in `X-Transcoderr-Token` to submit, cancel or retry jobs
+    /// (default: a new one each run, printed at startup)
+    #[arg(long, value_parser = parse_token)]
+    pub token: Option<String>,
+}
+
+fn parse_token(token: &str) -> Result<String, String> {
+    match !token.is_empty()
+        && token
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+    {
+        true => Ok(token.to_string()),
+        false => Err("use letters, digits, '-' and '_'".to_string()),
+    }
+}
+
+// 128 random bits from the OS, by way of std's hash keys
+fn new_token() -> String {
+    let bytes: Vec<u8> = (0..2)
+        .flat_map(|_| RandomState::new().build_hasher().finish().to_le_bytes())
+        .collect();
+    presets::sha256_hex(&bytes)[..32].to_string()
+}
+
+// A `[templates.NAME]` table: what jobs submitted with `"template": "NAME"` start from
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Template {
+    /// Preset to transcode with
+    pub preset: Option<String>,
+    /// Directory the outputs go to (default: next to the input)
+    pub output_root: Option<PathBuf>,
+    /// Inputs under this directory keep their folders under `output-root`
+    pub input_root: Option<PathBuf>,
+    /// Keep only audio and subtitle tracks in these languages
+    pub languages: Vec<String>,
+    /// Default-subtitle policy, e.g. `"forced:eng, else none"`
+    pub subtitle_default: Option<SubtitlePolicy>,
+    /// Add a stereo downmix as the default audio track
+    pub stereo_compat: bool,
+}
+
+impl Template {
+    fn view(&self) -> serde_json::Value {
+        json!({
+            "preset": self.preset,
+            "output_root": self.output_root,
+            "input_root": self.input_root,
+            "languages": self.languages,
+            "subtitle_default": self.subtitle_default.as_ref().map(ToString::to_string),
+            "stereo_compat": self.stereo_compat,
+        })
+    }
+
+    // Where `input` goes under `output-root`, if the template has one; `..` in the input
+    // cannot carry it out of there
+    fn output(&self, input: &Path, ext: &str) -> Result<Option<PathBuf>> {
+        let Some(root) = self.output_root.as_deref() else {
+            return Ok(None);
+        };
+        let input = normalize(input).context("the input climbs above the root")?;
+        let relative = self
+            .input_root
+            .as_deref()
+            .and_then(normalize)
+            .and_then(|input_root| input.strip_prefix(input_root).ok())
+            .or_else(|| input.file_name().map(Path::new))
+            .context("the input has no file name")?;
+        let output = root.join(relative).with_extension(ext);
+        self.check_within(&output)?;
+        Ok(Some(output))
+    }
+
+    // Refuse `output` if it would land outside `output-root`
+    fn check_within(&self, output: &Path) -> Result<()> {
+        let Some(root) = self.output_root.as_deref() else {
+            return Ok(());
+        };
+        let inside = normalize(output)
+            .zip(normalize(root))
+            .is_some_and(|(output, root)| output.starts_with(&root) && output != root);
+        if !inside {
+            anyhow::bail!(
+                "{} is outside the template's output-root {}",
+                output.display(),
+                root.display()
+            );
+        }
+        Ok(())
+    }
+}
+
+// The config's templates, read again whenever the file changes
+struct Templates {
+    path: Option<PathBuf>,
+    modified: Option<SystemTime>,
+    templates: BTreeMap<String, Template>,
+}
+
+impl Templates {
+    fn new(path: Option<PathBuf>, templates: BTreeMap<String, Template>) -> Self {
+        Self {
+            modified: path.as_deref().and_then(modified),
+            path,
+            templates,
+        }
+    }
+
+    fn current(&mut self) -> &BTreeMap<String, Template> {
+        let Some(path) = &self.path else {
+            return &self.templates;
+        };
+        let modified = modified(path);
+        if modified != self.modified {
+            self.modified = modified;
+            match config::load(Some(path)) {
+                Ok(config) => {
+                    println!(
+                        "Reloaded {}: {} templates",
+                        path.display(),
+                        config.templates.len()
+                    );
+                    self.templates = config.templates;
+                }
+                Err(e) => eprintln!(
+                    "{} {:#}; keeping the templates from before",
+                    Marker::Warning,
+                    e
+                ),
+            }
+        }
+        &self.templates
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+// The body of `POST /jobs`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Submission {
+    #[serde(alias = "path")]
+    input: PathBuf,
+    #[serde(default)]
+    template: Option<String>,
+    #[serde(default)]
+    output: Option<PathBuf>,
+    #[serde(default)]
+    preset: Option<String>,
+    #[serde(default)]
+    vcodec: Option<String>,
+    #[serde(default)]
+    acodec: Option<String>,
+    #[serde(default)]
+    extra: Vec<String>,
+    #[serde(default)]
+    languages: Vec<String>,
+    #[serde(default)]
+    subtitle_default: Option<SubtitlePolicy>,
+    #[serde(default)]
+    stereo_compat: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum State {
+    Queued,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug)]
+struct Job {
+    id: u64,
+    state: State,
+    input: PathBuf,
+    output: PathBuf,
+    template: Option<String>,
+    preset: Option<String>,
+    vcodec: Option<String>,
+    acodec: Option<String>,
+    extra: Vec<String>,
+    languages: Vec<String>,
+    subtitle_default: Option<SubtitlePolicy>,
+    stereo_compat: bool,
+    submitted: SystemTime,
+    started: Option<Instant>,
+    seconds: f64,
+    progress: Progress,
+    error: Option<String>,
+    log: VecDeque<String>,
+    // At submission, so a replaced or deleted input does not change the totals
+    input_bytes: u64,
+    output_bytes: u64,
+    child: Option<Child>,
+}
+
+impl Job {
+    fn finished(&self) -> bool {
+        matches!(self.state, State::Done | State::Failed | State::Cancelled)
+    }
+
+    fn elapsed(&self) -> f64 {
+        match (self.state, self.started) {
+            (State::Running, Some(started)) => started.elapsed().as_secs_f64(),
+            _ => self.seconds,
+        }
+    }
+
+    fn view(&self) -> serde_json::Value {
+        let progress = &self.progress;
+        let running = self.state == State::Running;
+        let percent = match self.state {
+            State::Done => Some(100.0),
+            State::Running => progress.fraction().map(|f| (f * 1000.0).round() / 10.0),
+            _ => None,
+        };
+        json!({
+            "id": self.id,
+            "state": self.state,
+            "input": self.input,
+            "output": self.output,
+            "template": self.template,
+            "preset": self.preset,
+            "vcodec": self.vcodec,
+            "acodec": self.acodec,
+            "extra": self.extra,
+            "languages": self.languages,
+            "subtitle_default": self.subtitle_default.as_ref().map(ToString::to_string),
+            "stereo_compat": self.stereo_compat,
+            "submitted": self.submitted.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()),
+            "seconds": self.elapsed(),
+            "percent": percent,
+            "position_seconds": progress.time.filter(|_| running),
+            "duration_seconds": progress.duration,
+            "fps": progress.fps.filter(|_| running),
+            "speed": progress.speed.filter(|_| running),
+            "eta_seconds": progress.eta().filter(|_| running),
+            "error": self.error,
+            "log": if self.state == State::Failed { Some(&self.log) } else { None },
+            "input_bytes": self.input_bytes,
+            "output_bytes": self.output_bytes,
+        })
+    }
+
+    // `transcoderr transcode` for the job; ffmpeg's own stats line carries the progress
+    // `global` goes ahead of the subcommand
+    fn command(&self, program: &Path, global: &[OsString]) -> Command {
+        let mut command = Command::new(program);
+        command
+            .args(global)
+            .args(["--stats", "full", "transcode"])
+            .arg(&self.input)
+            .arg(&self.output);
+        for (flag, value) in [
+            ("--preset", &self.preset),
+            ("--vcodec", &self.vcodec),
+            ("--acodec", &self.acodec),
+        ] {
+            if let Some(value) = value {
+                command.arg(flag).arg(value);
+            }
+        }
+        // One by one: `--extra` would split an arg like `title=Two Words` at its spaces
+        for arg in &self.extra {
+            command.arg(format!("--extra-arg={}", arg));
+        }
+        if !self.languages.is_empty() {
+            command.arg("--languages").arg(self.languages.join(","));
+        }
+        if let Some(policy) = &self.subtitle_default {
+            command.arg("--subtitle-default").arg(policy.to_string());
+        }
+        if self.stereo_compat {
+            command.arg("--stereo-compat");
+        }
+        command
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped());
+        // Its own process group, so a cancel reaches ffmpeg as well
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut command, 0);
+        command
+    }
+
+    fn line(&mut self, line: &str) {
+        if stats::is_stats_line(line) {
+            self.progress = Progress {
+                duration: self.progress.duration,
+                ..Progress::of(line.trim())
+            };
+            return;
+        }
+        if self.progress.duration.is_none() {
+            self.progress.duration = stats::header_duration(line).filter(|d| *d > 0.0);
+        }
+        let line = line.trim_end();
+        if !line.trim().is_empty() {
+            if self.log.len() == LOG_LINES {
+                self.log.pop_front();
+            }
+            self.log.push_back(line.to_string());
+        }
+    }
+}
+
+// Stop a running job's transcode and everything it started
+fn stop(child: &mut Child) {
+    #[cfg(unix)]
+    {
+        let group = format!("-{}", child.id());
+        let stopped = Command::new("kill")
+            .args(["-TERM", "--", &group])
+            .stderr(Stdio::null())
+            .status();
+        if stopped.is_ok_and(|status| status.success()) {
+            return;
+        }
+    }
+    let _ = child.kill();
+}
+
+#[derive(Default)]
+struct Queue {
+    jobs: Mutex<Vec<Job>>,
+    // Totals of the finished jobs no longer kept; taken after `jobs`
+    retired: Mutex<Metrics>,
+    ready: Condvar,
+    // Of the last job queued
+    last_id: AtomicU64,
+}
+
+impl Queue {
+    fn lock(&self) -> MutexGuard<'_, Vec<Job>> {
+        self.jobs.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Number and queue `job`, dropping the oldest finished jobs beyond `FINISHED_KEPT`;
+    // its view as queued
+    fn push(&self, mut job: Job) -> serde_json::Value {
+        let mut jobs = self.lock();
+        // Under the lock, so the list stays in order of the ids
+        job.id = self.last_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let view = job.view();
+        jobs.push(job);
+        // A cancelled job is not done with until its transcode has been waited for
+        let done_with = |job: &Job| job.finished() && job.child.is_none();
+        let mut excess = jobs
+            .iter()
+            .filter(|job| done_with(job))
+            .count()
+            .saturating_sub(FINISHED_KEPT);
+        if excess > 0 {
+            let mut retired = self.retired.lock().unwrap_or_else(PoisonError::into_inner);
+            jobs.retain(|job| {
+                if excess == 0 || !done_with(job) {
+                    return true;
+                }
+                excess -= 1;
+                count(&mut retired, job);
+                false
+            });
+        }
+        self.ready.notify_one();
+        view
+    }
+
+    fn metrics(&self) -> Metrics {
+        let jobs = self.lock();
+        let mut metrics = self
+            .retired
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        for job in jobs.iter() {
+            count(&mut metrics, job);
+        }
+        metrics
+    }
+
+    // One encode slot: run queued jobs, oldest first, for as long as the server runs
+    fn work(&self, program: &Path, global: &[OsString]) {
+        loop {
+            let (id, stderr) = {
+                let mut jobs = self.lock();
+                let index = loop {
+                    match jobs.iter().position(|job| job.state == State::Queued) {
+                        Some(index) => break index,
+                        None => {
+                            jobs = self
+                                .ready
+                                .wait(jobs)
+                                .unwrap_or_else(PoisonError::into_inner)
+                        }
+                    }
+                };
+                let job = &mut jobs[index];
+                job.state = State::Running;
+                job.started = Some(Instant::now());
+                // A template's output root may not have the input's folders yet
+                if let Some(parent) = job.output.parent() {
+                    let _ = fs::create_dir_all(parent);
+                }
+                match job.command(program, global).spawn() {
+                    Ok(mut child) => {
+                        let stderr = child.stderr.take();
+                        job.child = Some(child);
+                        (job.id, stderr)
+                    }
+                    Err(e) => {
+                        job.state = State::Failed;
+                        job.error = Some(format!("failed to run {}: {}", program.display(), e));
+                        continue;
+                    }
+                }
+            };
+            if let Some(stderr) = stderr {
+                self.follow(id, stderr);
+            }
+            self.finish(id);
+        }
+    }
+
+    // Feed the job's output to it, a line (or redrawn stats line) at a time
+    fn follow(&self, id: u64, mut stderr: impl Read) {
+        let mut buffer = [0u8; 4096];
+        let mut partial = Vec::new();
+        while let Ok(read) = stderr.read(&mut buffer) {
+            if read == 0 {
+                break;
+            }
+            let mut jobs = self.lock();
+            let Some(job) = jobs.iter_mut().find(|job| job.id == id) else {
+                return;
+            };
+            for &byte in &buffer[..read] {
+                if byte == b'\n' || byte == b'\r' {
+                    job.line(&String::from_utf8_lossy(&partial));
+                    partial.clear();
+                } else {
+                    partial.push(byte);
+                }
+            }
+        }
+        if let Some(job) = self.lock().iter_mut().find(|job| job.id == id) {
+            job.line(&String::from_utf8_lossy(&partial));
+        }
+    }
+
+    fn finish(&self, id: u64) {
+        let mut jobs = self.lock();
+        let Some(job) = jobs.iter_mut().find(|job| job.id == id) else {
+            return;
+        };
+        let status = job.child.take().map(|mut child| child.wait());
+        job.seconds = job
+            .started
+            .map_or(0.0, |started| started.elapsed().as_secs_f64());
+        match status {
+            _ if job.state == State::Cancelled => {
+                let _ = fs::remove_file(&job.output);
+            }
+            Some(Ok(status)) if status.success() => {
+                job.state = State::Done;
+                job.output_bytes = fs::metadata(&job.output).map_or(0, |m| m.len());
+                job.log.clear();
+            }
+            status => {
+                job.state = State::Failed;
+                job.error = Some(match (status, job.log.back()) {
+                    (Some(Err(e)), _) => format!("failed to wait for the transcode: {}", e),
+                    (_, Some(last)) => last.trim().to_string(),
+                    (Some(Ok(status)), None) => format!("transcode exited with {}", status),
+                    (None, None) => "transcode produced no output".to_string(),
+                });
+            }
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct PresetView<'a> {
+    #[serde(flatten)]
+    preset: &'a Preset,
+    source: &'static str,
+}
+
+// Shared by the connections, each handled on a thread of its own
+struct Server<'a> {
+    args: &'a ServeArgs,
+    presets: &'a Presets,
+    queue: &'a Queue,
+    templates: Mutex<Templates>,
+    token: String,
+}
+
+impl Server<'_> {
+    fn handle(&self, request: &Request) -> Response {
+        if request.method == "GET" {
+            let asset = ASSETS.iter().find(|(path, ..)| *path == request.path);
+            if let Some(&(path, content_type, body)) = asset {
+                // The dashboard's page carries the token for its own requests
+                let body = match path {
+                    "/" => String::from_utf8_lossy(body)
+                        .replace("%TOKEN%", &self.token)
+                        .into_bytes(),
+                    _ => body.to_vec(),
+                };
+                return Response {
+                    status: 200,
+                    content_type,
+                    body,
+                };
+            }
+        } else if let Some(refused) = self.refuse(request) {
+            return refused;
+        }
+        let segments: Vec<&str> = request.path.split('/').filter(|s| !s.is_empty()).collect();
+        match (request.method.as_str(), segments.as_slice()) {
+            ("GET", ["presets"]) => {
+                let presets: Vec<_> = self
+                    .presets
+                    .available()
+                    .into_iter()
+                    .map(|(preset, source)| PresetView { preset, source })
+                    .collect();
+                Response::json(200, &presets)
+            }
+            ("GET", ["templates"]) => {
+                let mut templates = self.templates();
+                let templates: BTreeMap<_, _> = templates
+                    .current()
+                    .iter()
+                    .map(|(name, template)| (name, template.view()))
+                    .collect();
+                Response::json(200, &templates)
+            }
+            ("GET", ["metrics"]) => self.queue.metrics().response(),
+            ("GET", ["jobs"]) => {
+                let jobs: Vec<_> = self.queue.lock().iter().map(Job::view).collect();
+                Response::json(200, &jobs)
+            }
+            ("POST", ["jobs"]) => {
+                self.submit(serde_json::from_slice(&request.body).context("bad job"))
+            }
+            ("POST", ["jobs", id, "retry"]) => self.retry(id),
+            ("GET", ["jobs", id]) => self.with_job(id, |job| Response::json(200, &job.view())),
+            ("DELETE", ["jobs", id]) => self.with_job(id, cancel),
+            (
+                _,
+                ["presets"]
+                | ["templates"]
+                | ["metrics"]
+                | ["jobs"]
+                | ["jobs", _]
+                | ["jobs", _, "retry"],
+            ) => Response::error(
+                405,
+                format!("{} is not supported on {}", request.method, request.path),
+            ),
+            _ => Response::error(404, format!("nothing at {}", request.path)),
+        }
+    }
+
+    // A request that changes anything, unless it may
+    fn refuse(&self, request: &Request) -> Option<Response> {
+        // Browsers send the page's origin; behind a proxy the scheme may be https
+        let origin = request.header("origin");
+        let own =
+            |origin: &str| origin.split_once("://").map(|(_, host)| host) == request.header("host");
+        if origin.is_some_and(|origin| !own(origin)) {
+            return Some(Response::error(403, "cross-origin requests are refused"));
+        }
+        if request.header("x-transcoderr-token") != Some(self.token.as_str()) {
+            return Some(Response::error(403, "missing or wrong X-Transcoderr-Token"));
+        }
+        let json = request.header("content-type").is_some_and(|content_type| {
+            let essence = content_type.split(';').next().unwrap_or_default();
+            essence.trim().eq_ignore_ascii_case("application/json")
+        });
+        if request.method == "POST" && !json {
+            return Some(Response::error(
+                415,
+                "send a body of Content-Type: application/json",
+            ));
+        }
+        None
+    }
+
+    fn with_job(&self, id: &str, then: impl FnOnce(&mut Job) -> Response) -> Response {
+        let mut jobs = self.queue.lock();
+        match jobs.iter_mut().find(|job| id.parse() == Ok(job.id)) {
+            Some(job) => then(job),
+            None => Response::error(404, format!("no job {}", id)),
+        }
+    }
+
+    fn templates(&self) -> MutexGuard<'_, Templates> {
+        self.templates
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn submit(&self, submission: Result<Submission>) -> Response {
+        let job = match submission.and_then(|submission| self.job(submission)) {
+            Ok(job) => job,
+            Err(e) => return Response::error(400, format!("{:#}", e)),
+        };
+        Response::json(201, &self.queue.push(job))
+    }
+
+    // `POST /jobs/ID/retry`: a failed or cancelled job again, as a new job
+    fn retry(&self, id: &str) -> Response {
+        let submission = match self
+            .queue
+            .lock()
+            .iter()
+            .find(|job| id.parse() == Ok(job.id))
+        {
+            None => return Response::error(404, format!("no job {}", id)),
+            Some(job) if !matches!(job.state, State::Failed | State::Cancelled) => {
+                return Response::error(
+                    409,
+                    format!("job {} has not failed or been cancelled", id),
+                );
+            }
+            // As it was resolved, whatever its template says now
+            Some(job) => Submission {
+                input: job.input.clone(),
+                template: None,
+                output: Some(job.output.clone()),
+                preset: job.preset.clone(),
+                vcodec: job.vcodec.clone(),
+                acodec: job.acodec.clone(),
+                extra: job.extra.clone(),
+                languages: job.languages.clone(),
+                subtitle_default: job.subtitle_default.clone(),
+                stereo_compat: job.stereo_compat,
+            },
+        };
+        self.submit(Ok(submission))
+    }
+
+    fn job(&self, submission: Submission) -> Result<Job> {
+        let Submission {
+            input,
+            template: template_name,
+            output,
+            preset,
+            vcodec,
+            acodec,
+            extra,
+            mut languages,
+            subtitle_default,
+            stereo_compat,
+        } = submission;
+        let template = match &template_name {
+            Some(name) => self
+                .templates()
+                .current()
+                .get(name)
+                .cloned()
+                .with_context(|| format!("unknown template '{}'", name))?,
+            None => Template::default(),
+        };
+        // No output is the same as none given
+        let output = output.filter(|output| !output.as_os_str().is_empty());
+        // The server's working directory means nothing to a client
+        if !input.is_absolute() || !output.as_deref().is_none_or(Path::is_absolute) {
+            anyhow::bail!("input and output must be absolute paths");
+        }
+        if !template
+            .output_root
+            .as_deref()
+            .is_none_or(Path::is_absolute)
+        {
+            anyhow::bail!(
+                "template '{}' has a relative output-root",
+                template_name.unwrap_or_default()
+            );
+        }
+        if !input.is_file() {
+            anyhow::bail!("no such file: {}", input.display());
+        }
+        let presets = self.presets;
+        let preset = preset
+            .or(template.preset.clone())
+            .or_else(|| self.args.preset.clone());
+        if let Some(name) = preset
+            .as_deref()
+            .filter(|name| presets.find(name).is_none())
+        {
+            anyhow::bail!("unknown preset '{}'", name);
+        }
+        let given_ext = output
+            .as_deref()
+            .and_then(Path::extension)
+            .map(|ext| ext.to_string_lossy());
+        let ext = presets.output_ext(preset.as_deref(), given_ext.as_deref())?;
+        let output = match output {
+            Some(output) => {
+                template.check_within(&output)?;
+                Some(output)
+            }
+            None => template.output(&input, &ext)?,
+        };
+        let output = resolve_output_path(&input, output.as_deref(), Some(&ext))?;
+        if languages.is_empty() {
+            languages = template.languages;
+        }
+        let input_bytes = fs::metadata(&input).map_or(0, |m| m.len());
+        Ok(Job {
+            // Numbered once queued
+            id: 0,
+            state: State::Queued,
+            input,
+            output,
+            template: template_name,
+            preset,
+            vcodec,
+            acodec,
+            extra,
+            languages,
+            subtitle_default: subtitle_default.or(template.subtitle_default),
+            stereo_compat: stereo_compat || template.stereo_compat,
+            submitted: SystemTime::now(),
+            started: None,
+            seconds: 0.0,
+            progress: Progress::default(),
+            error: None,
+            log: VecDeque::new(),
+            input_bytes,
+            output_bytes: 0,
+            child: None,
+        })
+    }
+}
+
+// Add `job` to the totals
+fn count(metrics: &mut Metrics, job: &Job) {
+    match job.state {
+        State::Queued => metrics.queued += 1,
+        State::Running => {
+            metrics.running += 1;
+            metrics.speed += job.progress.speed.unwrap_or(0.0);
+            metrics.fps += job.progress.fps.unwrap_or(0.0);
+        }
+        State::Done => metrics.done(job.input_bytes, job.output_bytes, job.seconds),
+        State::Failed => metrics.failed += 1,
+        State::Cancelled => metrics.cancelled += 1,
+    }
+}
+
+// `DELETE /jobs/ID`: a queued job is dropped from the queue, a running one stopped
+fn cancel(job: &mut Job) -> Response {
+    if job.finished() {
+        return Response::error(409, format!("job {} has already finished", job.id));
+    }
+    if let Some(child) = &mut job.child {
+        stop(child);
+    }
+    job.state = State::Cancelled;
+    Response::json(200, &job.view())
+}
+
+// `templates` are the config's as loaded at startup, and `global` the server's own
+// options each job's transcode runs with
+pub fn run(
+    args: &ServeArgs,
+    config: Option<&Path>,
+    global: &[OsString],
+    templates: BTreeMap<String, Template>,
+    presets: &Presets,
+) -> Result<()> {
+    let listener = TcpListener::bind(&args.listen)
+        .with_context(|| format!("failed to listen on {}", args.listen))?;
+    let program = env::current_exe().context("cannot find the transcoderr executable")?;
+    let queue = Arc::new(Queue::default());
+    let mut global = global.to_vec();
+    if let Some(config) = config {
+        global.splice(0..0, ["--config".into(), config.into()]);
+    }
+    for _ in 0..args.jobs {
+        let (queue, program, global) = (Arc::clone(&queue), program.clone(), global.clone());
+        thread::spawn(move || queue.work(&program, &global));
+    }
+    let token = args.token.clone().unwrap_or_else(new_token);
+    println!(
+        "Serving on http://{} ({} at a time), token {}",
+        listener.local_addr()?,
+        args.jobs,
+        token
+    );
+
+    let config_path = config.map(Path::to_path_buf).or_else(config::default_path);
+    let server = Server {
+        args,
+        presets,
+        queue: &queue,
+        templates: Mutex::new(Templates::new(config_path, templates)),
+        token,
+    };
+    // A client that is slow to send its request holds up only its own connection
+    thread::scope(|scope| {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else {
+                continue;
+            };
+            let server = &server;
+            scope.spawn(move || {
+                let _ = stream.set_read_timeout(Some(READ_TIMEOUT));
+                let response = match read_request(&mut BufReader::new(&stream)) {
+                    Ok(request) => server.handle(&request),
+                    Err(e) => Response::error(400, format!("{:#}", e)),
+                };
+                let _ = response.write_to(&mut stream);
+            });
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job() -> Job {
+        Job {
+            id: 1,
+            state: State::Running,
+            input: PathBuf::from("/in/a.mkv"),
+            output: PathBuf::from("/out/a.mkv"),
+            template: None,
+            preset: None,
+            vcodec: None,
+            acodec: None,
+            extra: Vec::new(),
+            languages: Vec::new(),
+            subtitle_default: None,
+            stereo_compat: false,
+            submitted: SystemTime::now(),
+            started: Some(Instant::now()),
+            seconds: 0.0,
+            progress: Progress::default(),
+            error: None,
+            log: VecDeque::new(),
+            input_bytes: 0,
+            output_bytes: 0,
+            child: None,
+        }
+    }
+
+    #[test]
+    fn progress_comes_from_ffmpeg_output() {
+        let mut job = job();
+        job.line("  Duration: 00:01:40.00, start: 0.000000, bitrate: 4000 kb/s");
+        job.line("frame= 1200 fps= 48 q=28.0 size=  2048kB time=00:00:25.00 bitrate= 671.1kbits/s speed=1.9x");
+        let view = job.view();
+        assert_eq!(view["percent"], 25.0);
+        assert_eq!(view["fps"], 48.0);
+        assert_eq!(view["duration_seconds"], 100.0);
+        // Stats lines are not log lines, and a running job shows no log
+        assert_eq!(
+            job.log,
+            ["  Duration: 00:01:40.00, start: 0.000000, bitrate: 4000 kb/s"]
+        );
+        assert!(view["log"].is_null());
+
+        job.state = State::Failed;
+        for n in 0..LOG_LINES + 5 {
+            job.line(&format!("error {}", n));
+        }
+        let view = job.view();
+        assert_eq!(view["log"].as_array().unwrap().len(), LOG_LINES);
+        assert!(view["percent"].is_null());
+    }
+
+    #[test]
+    fn template_outputs_keep_the_folders_under_the_input_root() {
+        let template = Template {
+            output_root: Some(PathBuf::from("/media/tv")),
+            input_root: Some(PathBuf::from("/downloads")),
+            ..Template::default()
+        };
+        let output = |input: &str| template.output(Path::new(input), "mkv").unwrap();
+        assert_eq!(
+            output("/downloads/Show/e1.mp4"),
+            Some(PathBuf::from("/media/tv/Show/e1.mkv"))
+        );
+        assert_eq!(
+            output("/elsewhere/e2.mp4"),
+            Some(PathBuf::from("/media/tv/e2.mkv"))
+        );
+        // Not `/media/tv/../../etc/e3.mkv`
+        assert_eq!(
+            output("/downloads/../etc/e3.mp4"),
+            Some(PathBuf::from("/media/tv/e3.mkv"))
+        );
+        assert_eq!(
+            Template::default()
+                .output(Path::new("/in/a.mp4"), "mkv")
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn outputs_stay_within_the_output_root() {
+        let template = Template {
+            output_root: Some(PathBuf::from("/media/tv")),
+            ..Template::default()
+        };
+        assert!(
+            template
+                .check_within(Path::new("/media/tv/./Show/e1.mkv"))
+                .is_ok()
+        );
+        for outside in ["/media/tv/../movies/a.mkv", "/etc/passwd", "/media/tv"] {
+            assert!(
+                template.check_within(Path::new(outside)).is_err(),
+                "{}",
+                outside
+            );
+        }
+        assert!(
+            Template::default()
+                .check_within(Path::new("/anywhere/a.mkv"))
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn a_bad_config_edit_keeps_the_templates() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, "[templates.tv]\npreset = \"tv-h265-fast\"\n").unwrap();
+        let mut templates = Templates::new(Some(path.clone()), BTreeMap::new());
+        // Loaded at startup by the caller; only a change is read
+        assert!(templates.current().is_empty());
+
+        let edit = |text: &str, age: u64| {
+            fs::write(&path, text).unwrap();
+            let file = fs::File::options().append(true).open(&path).unwrap();
+            file.set_modified(SystemTime::now() - Duration::from_secs(age))
+                .unwrap();
+        };
+        edit("[templates.movies]\npreset = \"movie-quality\"\n", 20);
+        assert_eq!(
+            templates.current()["movies"].preset.as_deref(),
+            Some("movie-quality")
+        );
+        edit("[templates.movies\n", 10);
+        assert!(templates.current().contains_key("movies"));
+    }
+
+    #[test]
+    fn stream_policies_reach_transcode() {
+        let mut job = job();
+        job.languages = vec!["eng".to_string(), "jpn".to_string()];
+        job.subtitle_default = Some("forced:eng, else none".parse().unwrap());
+        job.stereo_compat = true;
+        let command = job.command(Path::new("transcoderr"), &[]);
+        let args: Vec<_> = command
+            .get_args()
+            .map(|arg| arg.to_string_lossy())
+            .collect();
+        assert!(args.ends_with(&[
+            "--languages".into(),
+            "eng,jpn".into(),
+            "--subtitle-default".into(),
+            "forced:eng, none".into(),
+            "--stereo-compat".into(),
+        ]));
+    }
+
+    #[test]
+    fn global_options_come_before_transcode() {
+        let global = ["--hwaccel", "vaapi", "--no-cache"].map(OsString::from);
+        let command = job().command(Path::new("transcoderr"), &global);
+        let args: Vec<_> = command
+            .get_args()
+            .map(|arg| arg.to_string_lossy())
+            .collect();
+        assert_eq!(
+            args[..6],
+            [
+                "--hwaccel",
+                "vaapi",
+                "--no-cache",
+                "--stats",
+                "full",
+                "transcode"
+            ]
+        );
+    }
+
+    #[test]
+    fn extra_args_reach_transcode_whole() {
+        let mut job = job();
+        job.extra = vec!["-metadata".to_string(), "title=Two Words".to_string()];
+        let command = job.command(Path::new("transcoderr"), &[]);
+        let args: Vec<_> = command
+            .get_args()
+            .map(|arg| arg.to_string_lossy())
+            .collect();
+        assert!(args.ends_with(&[
+            "--extra-arg=-metadata".into(),
+            "--extra-arg=title=Two Words".into()
+        ]));
+    }
+
+    #[test]
+    fn metrics_count_jobs_by_state() {
+        let mut running = job();
+        running.line("frame=  240 fps= 60 q=28.0 size=512kB time=00:00:10.00 speed=2.5x");
+        let mut done = job();
+        (done.state, done.input_bytes, done.output_bytes) = (State::Done, 900, 300);
+        let mut queued = job();
+        queued.state = State::Queued;
+        let queue = Queue::default();
+        for job in [running, done, queued] {
+            queue.push(job);
+        }
+        let metrics = queue.metrics();
+        assert_eq!(
+            (metrics.running, metrics.queued, metrics.completed),
+            (1, 1, 1)
+        );
+        assert_eq!((metrics.speed, metrics.fps), (2.5, 60.0));
+        assert_eq!(metrics.bytes_saved, 600);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn old_finished_jobs_are_only_counted() {
+        let queue = Queue::default();
+        let mut stopping = job();
+        stopping.state = State::Cancelled;
+        stopping.child = Some(Command::new("true").spawn().unwrap());
+        queue.push(stopping);
+        for _ in 0..FINISHED_KEPT + 10 {
+            let mut done = job();
+            (done.state, done.input_bytes) = (State::Done, 10);
+            queue.push(done);
+        }
+        let mut queued = job();
+        queued.state = State::Queued;
+        queue.push(queued);
+
+        let jobs = queue.lock();
+        // The oldest done jobs went; the one still being stopped and the queued one stay
+        assert_eq!(jobs.len(), FINISHED_KEPT + 2);
+        assert_eq!(jobs[0].state, State::Cancelled);
+        assert_eq!(jobs[1].id, 12);
+        assert_eq!(jobs.last().unwrap().id, FINISHED_KEPT as u64 + 12);
+        drop(jobs);
+        let metrics = queue.metrics();
+        assert_eq!(
+            (metrics.completed, metrics.cancelled, metrics.queued),
+            (FINISHED_KEPT as u64 + 10, 1, 1)
+        );
+        assert_eq!(metrics.input_bytes, 10 * (FINISHED_KEPT as u64 + 10));
+        queue.lock()[0].child.take().unwrap().wait().unwrap();
+    }
+
+    #[test]
+    fn finished_jobs_cannot_be_cancelled() {
+        let mut job = job();
+        job.state = State::Done;
+        assert_eq!(cancel(&mut job).status, 409);
+        job.state = State::Queued;
+        assert_eq!(cancel(&mut job).status, 200);
+        assert_eq!(job.state, State::Cancelled);
+    }
+}
@@ -0,0 +1,284 @@
+// file: src/shows.rs
+// version: 0.3.0
+// guid: a8ecb7a9-b2f2-4533-a2e0-2262dcbc5952
+
+//! TV show/season detection from library paths and per-show overrides (`--shows shows.toml`).
+//!
+//! ```toml
+//! ["Breaking Bad"]
+//! preset = "movie-quality"
+//! crop = "1920:800:0:140"
+//!
+//! ["Cowboy Bebop"]
+//! acodec = "libopus"
+//! audio-bitrate = "128k"
+//! subtitle-default = "forced:eng, else none"
+//! extra = ["-tune", "grain"]
+//!
+//! ["Planet Earth"]
+//! cwd = "/opt/placebo"                  # ffmpeg runs here, so relative shader paths work
+//! env = { CUDA_VISIBLE_DEVICES = "1" }  # over the preset's variables
+//! ```
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::filters::{FilterGraph, add_filter};
+use crate::presets::Presets;
+use crate::subtitles::SubtitlePolicy;
+use crate::tools::RunEnv;
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ShowKey {
+    pub show: String,
+    pub season: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ShowOverrides {
+    pub preset: Option<String>,
+    pub vcodec: Option<String>,
+    pub acodec: Option<String>,
+    pub audio_bitrate: Option<String>,
+    /// ffmpeg crop filter arguments `w:h:x:y`
+    pub crop: Option<String>,
+    pub subtitle_default: Option<SubtitlePolicy>,
+    pub extra: Vec<String>,
+    /// Working directory of ffmpeg for this show's files
+    pub cwd: Option<PathBuf>,
+    /// Variables for ffmpeg, set over the preset's
+    pub env: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Default)]
+pub struct ShowsFile {
+    shows: BTreeMap<String, ShowOverrides>,
+}
+
+impl ShowsFile {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("failed to read shows file {}", path.display()))?;
+        let shows = toml::from_str(&text)
+            .with_context(|| format!("invalid shows file {}", path.display()))?;
+        Ok(Self { shows })
+    }
+
+    // Case-insensitive lookup that also ignores a trailing year, so an entry for
+    // "Breaking Bad" matches a "Breaking Bad (2008)" folder.
+    pub fn lookup(&self, show: &str) -> Option<&ShowOverrides> {
+        let wanted = strip_year(show);
+        self.shows
+            .iter()
+            .find(|(name, _)| strip_year(name).eq_ignore_ascii_case(wanted))
+            .map(|(_, overrides)| overrides)
+    }
+}
+
+impl ShowOverrides {
+    // Effective codecs and extra args for a file of this show. Show settings are more
+    // specific than batch-wide flags, so they win: codecs replace, args are appended last.
+    pub fn apply(
+        &self,
+        presets: &Presets,
+        preset: Option<&str>,
+        vcodec: Option<&str>,
+        acodec: Option<&str>,
+        default_vcodec: &str,
+        extra: &[String],
+    ) -> (String, String, Vec<String>) {
+        let (mut v, mut a, mut args) = presets.apply(
+            self.preset.as_deref().or(preset),
+            vcodec,
+            acodec,
+            default_vcodec,
+            extra,
+        );
+        if let Some(codec) = &self.vcodec {
+            v = codec.clone();
+        }
+        if let Some(codec) = &self.acodec {
+            a = codec.clone();
+        }
+        if let Some(crop) = &self.crop {
+            add_filter(
+                &mut args,
+                "-vf",
+                FilterGraph::new().raw(&format!("crop={}", crop)),
+            );
+        }
+        if let Some(bitrate) = &self.audio_bitrate {
+            args.extend(["-b:a".to_string(), bitrate.clone()]);
+        }
+        args.extend(self.extra.iter().cloned());
+        (v, a, args)
+    }
+
+    // The show's variables and directory over those of the preset in effect
+    pub fn run_env(&self, presets: &Presets, preset: Option<&str>) -> RunEnv {
+        presets
+            .run_env(self.preset.as_deref().or(preset))
+            .layer(&self.env, self.cwd.as_ref())
+    }
+}
+
+fn strip_year(name: &str) -> &str {
+    let trimmed = name.trim();
+    match trimmed.rsplit_once(" (") {
+        Some((title, year))
+            if year.len() == 5
+                && year.ends_with(')')
+                && year[..4].chars().all(|c| c.is_ascii_digit()) =>
+        {
+            title.trim()
+        }
+        _ => trimmed,
+    }
+}
+
+// Detect show and season from a path relative to the batch root. Folder layouts
+// (`Show/Season 01/…`, `Show/S01/…`, `Show/Specials/…`) take precedence over episode
+// markers in the file name (`Show Name - S01E02`, `Show.Name.S01E02`).
+pub fn detect(rel_path: &Path) -> Option<ShowKey> {
+    let dirs: Vec<&str> = rel_path
+        .parent()
+        .map(|p| {
+            p.components()
+                .filter_map(|c| match c {
+                    Component::Normal(s) => s.to_str(),
+                    _ => None,
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    for (i, dir) in dirs.iter().enumerate().rev() {
+        if let Some(season) = parse_season_dir(dir) {
+            if i > 0 {
+                return Some(ShowKey {
+                    show: dirs[i - 1].trim().to_string(),
+                    season: Some(season),
+                });
+            }
+        }
+    }
+
+    let stem = rel_path.file_stem()?.to_str()?;
+    let (prefix, season) = find_episode_marker(stem)?;
+    let from_name = clean_title(prefix);
+    let show = if from_name.is_empty() {
+        dirs.last()?.trim().to_string()
+    } else {
+        from_name
+    };
+    Some(ShowKey {
+        show,
+        season: Some(season),
+    })
+}
+
+fn parse_season_dir(dir: &str) -> Option<u32> {
+    let lower = dir.trim().to_ascii_lowercase();
+    if lower == "specials" {
+        return Some(0);
+    }
+    let rest = lower
+        .strip_prefix("season")
+        .or_else(|| lower.strip_prefix('s'))?
+        .trim_start_matches([' ', '_', '.', '-']);
+    if rest.is_empty() || !rest.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    rest.parse().ok()
+}
+
+// Find an `SxxEyy` marker that starts a word; returns the text before it and the season.
+fn find_episode_marker(stem: &str) -> Option<(&str, u32)> {
+    let bytes = stem.as_bytes();
+    for start in 0..bytes.len() {
+        if !matches!(bytes[start], b's' | b'S') {
+            continue;
+        }
+        if start > 0 && bytes[start - 1].is_ascii_alphanumeric() {
+            continue;
+        }
+        let season_end = digits_end(bytes, start + 1);
+        if season_end == start + 1 || season_end - start > 3 {
+            continue;
+        }
+        if !matches!(bytes.get(season_end), Some(b'e' | b'E')) {
+            continue;
+        }
+        if digits_end(bytes, season_end + 1) == season_end + 1 {
+            continue;
+        }
+        let season = stem[start + 1..season_end].parse().ok()?;
+        return Some((&stem[..start], season));
+    }
+    None
+}
+
+fn digits_end(bytes: &[u8], from: usize) -> usize {
+    let mut end = from;
+    while end < bytes.len() && bytes[end].is_ascii_digit() {
+        end += 1;
+    }
+    end
+}
+
+fn clean_title(raw: &str) -> String {
+    raw.replace(['.', '_'], " ")
+        .trim_matches(|c: char| c == '-' || c.is_whitespace())
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(show: &str, season: u32) -> Option<ShowKey> {
+        Some(ShowKey {
+            show: show.to_string(),
+            season: Some(season),
+        })
+    }
+
+    #[test]
+    fn detects_folder_layouts() {
+        assert_eq!(
+            detect(Path::new("Breaking Bad (2008)/Season 02/Episode 3.mkv")),
+            key("Breaking Bad (2008)", 2)
+        );
+        assert_eq!(detect(Path::new("Bebop/S1/ep.mkv")), key("Bebop", 1));
+        assert_eq!(detect(Path::new("Bebop/Specials/ep.mkv")), key("Bebop", 0));
+    }
+
+    #[test]
+    fn detects_episode_markers_in_names() {
+        assert_eq!(
+            detect(Path::new("The.Wire.S03E07.720p.mkv")),
+            key("The Wire", 3)
+        );
+        assert_eq!(
+            detect(Path::new("Dark/S02E01 - Beginnings.mkv")),
+            key("Dark", 2)
+        );
+        assert_eq!(detect(Path::new("Movies/Seven (1995).mkv")), None);
+    }
+
+    #[test]
+    fn lookup_ignores_case_and_year() {
+        let file = ShowsFile {
+            shows: [("breaking bad".to_string(), ShowOverrides::default())].into(),
+        };
+        assert!(file.lookup("Breaking Bad (2008)").is_some());
+        assert!(file.lookup("Better Call Saul").is_none());
+    }
+}
@@ -0,0 +1,152 @@
+// file: src/sidecar.rs
+// version: 0.1.0
+// guid: 1ab91489-c4f8-403e-9797-f439e4b58a94
+
+//! Per-file extra ffmpeg args, for the few files in every library that need special
+//! flags (a broken timestamp, an odd field order) without leaving them out of a batch.
+//!
+//! Args come from a `<name>.transcoderr-args` file next to the input (`Movie.mkv` ->
+//! `Movie.transcoderr-args`), written like a command line: whitespace separates args,
+//! quotes group them, `#` starts a comment. The config file can hold them too, keyed by
+//! file name or by the end of the input's path:
+//!
+//! ```toml
+//! [file-args]
+//! "Blade Runner (1982).mkv" = ["-vf", "yadif"]
+//! "Firefly/Season 1/Firefly S01E03.avi" = ["-fflags", "+genpts"]
+//! ```
+//!
+//! Config entries apply first, most specific last, then the sidecar; all go after the
+//! batch, preset and per-show args so they win where ffmpeg takes the last value.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+
+pub const EXTENSION: &str = "transcoderr-args";
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(transparent)]
+pub struct FileArgs {
+    by_path: BTreeMap<String, Vec<String>>,
+}
+
+impl FileArgs {
+    pub fn for_input(&self, input: &Path) -> Result<Vec<String>> {
+        let mut matches: Vec<(&String, &Vec<String>)> = self
+            .by_path
+            .iter()
+            .filter(|(key, _)| input.ends_with(key.as_str()))
+            .collect();
+        matches.sort_by_key(|(key, _)| Path::new(key.as_str()).components().count());
+        let mut args: Vec<String> = matches.into_iter().flat_map(|(_, a)| a.clone()).collect();
+
+        let sidecar = sidecar_path(input);
+        match fs::read_to_string(&sidecar) {
+            Ok(text) => {
+                args.extend(parse(&text).with_context(|| format!("invalid {}", sidecar.display()))?)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to read {}", sidecar.display()));
+            }
+        }
+        Ok(args)
+    }
+}
+
+pub fn sidecar_path(input: &Path) -> PathBuf {
+    input.with_extension(EXTENSION)
+}
+
+// `-vf "yadif=mode=1" # deinterlace` -> [-vf, yadif=mode=1]
+fn parse(text: &str) -> Result<Vec<String>> {
+    let mut args = Vec::new();
+    let mut current: Option<String> = None;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                args.extend(current.take());
+            }
+            '#' if current.is_none() => {
+                // Comment to the end of the line
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '\'' | '"' => {
+                let arg = current.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some(q) if q == c => break,
+                        Some('\\') if c == '"' => arg.extend(chars.next()),
+                        Some(other) => arg.push(other),
+                        None => bail!("unclosed {} quote", c),
+                    }
+                }
+            }
+            '\\' => current.get_or_insert_with(String::new).extend(chars.next()),
+            c => current.get_or_insert_with(String::new).push(c),
+        }
+    }
+    args.extend(current);
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sidecars_read_like_a_command_line() {
+        let text = "# This rip has broken timestamps\n\
+                    -fflags +genpts\n\
+                    -vf \"yadif=mode=1, scale=1280:-2\"  -metadata 'title=It'\\''s'\n\
+                    -x265-params \"\" # empty on purpose\n";
+        assert_eq!(
+            parse(text).unwrap(),
+            [
+                "-fflags",
+                "+genpts",
+                "-vf",
+                "yadif=mode=1, scale=1280:-2",
+                "-metadata",
+                "title=It's",
+                "-x265-params",
+                "",
+            ]
+        );
+        assert!(parse("-vf \"yadif").is_err());
+    }
+
+    #[test]
+    fn config_entries_match_the_end_of_the_path_then_the_sidecar_adds() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("Firefly/Season 1/Firefly S01E03.avi");
+        fs::create_dir_all(input.parent().unwrap()).unwrap();
+        fs::write(sidecar_path(&input), "-r 24000/1001\n").unwrap();
+        let file_args: FileArgs = toml::from_str(
+            "\"Season 1/Firefly S01E03.avi\" = [\"-vf\", \"yadif\"]\n\
+             \"Firefly S01E03.avi\" = [\"-fflags\", \"+genpts\"]\n\
+             \"S01E03.avi\" = [\"-an\"]\n",
+        )
+        .unwrap();
+        assert_eq!(
+            file_args.for_input(&input).unwrap(),
+            ["-fflags", "+genpts", "-vf", "yadif", "-r", "24000/1001"]
+        );
+        assert!(
+            file_args
+                .for_input(&dir.path().join("other.mkv"))
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
@@ -0,0 +1,266 @@
+// file: src/silence.rs
+// version: 0.4.1
+// guid: a247abd0-1805-4f90-b29a-7a3b8c02fbf0
+
+//! Leading and trailing silence trimmed from recordings (`--trim-silence`), for
+//! lectures and audio captures that start before the speaker and run on after them.
+//!
+//! A silencedetect pass over the first audio stream finds the silence touching either
+//! end; the encode then starts after it (`-ss`) and stops before it (`-t`). Both cuts
+//! are output options, so a re-encode is exact to the frame. `--silence-threshold`
+//! sets what counts as silence (default -50 dB) and `--silence-min` how long it must
+//! last (default 0.5 s), so pauses inside the recording never count.
+
+use std::ffi::OsString;
+use std::path::Path;
+
+use anyhow::{Result, bail};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::args::FfmpegArgs;
+use crate::cache::AnalysisCache;
+use crate::encode::display_args;
+use crate::filters::FilterGraph;
+use crate::probe;
+use crate::stats::clock;
+use crate::tools::{self, Tool};
+
+// Silence this close to an end still counts as touching it: detection starts and
+// stops on audio frame boundaries
+const EDGE: f64 = 0.05;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TrimEnds {
+    Start,
+    End,
+    Both,
+}
+
+#[derive(Debug, Clone)]
+pub struct SilenceTrim {
+    ends: TrimEnds,
+    // dB
+    threshold: f64,
+    // Seconds
+    min_duration: f64,
+}
+
+// The part of the input to keep, in seconds; `end` is None to keep the rest
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct Span {
+    start: f64,
+    end: Option<f64>,
+}
+
+impl SilenceTrim {
+    pub fn new(ends: TrimEnds, threshold: f64, min_duration: f64) -> Self {
+        Self {
+            ends,
+            threshold,
+            min_duration,
+        }
+    }
+
+    // `settings_hash` input
+    pub fn option(&self) -> String {
+        format!(
+            "trim-silence={}:{}dB:{}s",
+            format!("{:?}", self.ends).to_lowercase(),
+            self.threshold,
+            self.min_duration
+        )
+    }
+
+    fn detect_args(&self, input: &Path) -> Vec<OsString> {
+        let graph = FilterGraph::new()
+            .filter_opts(
+                "silencedetect",
+                &[
+                    ("noise", &format!("{}dB", self.threshold)),
+                    ("d", &self.min_duration.to_string()),
+                ],
+            )
+            .to_string();
+        FfmpegArgs::new("-")
+            .global(["-nostats"])
+            .input(input)
+            .map("0:a:0")
+            .filter("-af", graph)
+            .option("-f", "null")
+            .build()
+    }
+
+    pub fn describe_detect(&self, input: &Path) -> String {
+        display_args(&self.detect_args(input))
+    }
+
+    // Find the silence at the ends of `input` and return the output options that cut it.
+    // Output options count output time, which `--speed` runs at `speed` times the input's.
+    pub fn args(&self, input: &Path, cache: &AnalysisCache, speed: f64) -> Result<Vec<String>> {
+        let span = self.span(input, cache)?;
+        let start = match self.ends {
+            TrimEnds::Start | TrimEnds::Both => span.start,
+            TrimEnds::End => 0.0,
+        };
+        let end = match self.ends {
+            TrimEnds::End | TrimEnds::Both => span.end,
+            TrimEnds::Start => None,
+        };
+        if end.is_some_and(|end| end <= start) {
+            bail!("{} is silent throughout", input.display());
+        }
+        let mut args = Vec::new();
+        let mut trimmed = Vec::new();
+        if start > 0.0 {
+            args.extend(["-ss".to_string(), format!("{:.3}", start / speed)]);
+            trimmed.push(format!("{:.1}s at the start", start));
+        }
+        if let Some(end) = end {
+            args.extend(["-t".to_string(), format!("{:.3}", (end - start) / speed)]);
+            trimmed.push(format!("from {} on", clock(end)));
+        }
+        match trimmed.is_empty() {
+            true => println!("  No silence to trim"),
+            false => println!("  Trimming silence: {}", trimmed.join(", ")),
+        }
+        Ok(args)
+    }
+
+    fn span(&self, input: &Path, cache: &AnalysisCache) -> Result<Span> {
+        let params = format!("{}dB:{}s", self.threshold, self.min_duration);
+        if let Some(span) = cache.get("silence", &params, input) {
+            return Ok(span);
+        }
+        let args = self.detect_args(input);
+        let output = tools::output(Tool::Ffmpeg, &args)?;
+        if !output.status.success() {
+            bail!(
+                "silence detection failed on {} (ffmpeg status {:?}; does it have audio?)",
+                input.display(),
+                output.status.code()
+            );
+        }
+        let duration = probe::probe_cached(input, cache)
+            .ok()
+            .and_then(|info| info.format.duration_seconds());
+        let span = find_span(&String::from_utf8_lossy(&output.stderr), duration);
+        cache.put("silence", &params, input, &span);
+        Ok(span)
+    }
+}
+
+// The span between silence touching the start and silence touching the end, from
+// silencedetect's log:
+//   [silencedetect @ 0x1] silence_start: 0
+//   [silencedetect @ 0x1] silence_end: 4.21 | silence_duration: 4.21
+// Silence still running at the end of the input may have no `silence_end`.
+fn find_span(stderr: &str, duration: Option<f64>) -> Span {
+    let field = |line: &str, name: &str| -> Option<f64> {
+        let (_, rest) = line.split_once(name)?;
+        rest.split_whitespace().next()?.parse().ok()
+    };
+    let mut runs: Vec<(f64, Option<f64>)> = Vec::new();
+    for line in stderr.lines() {
+        if let Some(start) = field(line, "silence_start: ") {
+            runs.push((start.max(0.0), None));
+        } else if let Some(end) = field(line, "silence_end: ") {
+            if let Some(run) = runs.last_mut().filter(|run| run.1.is_none()) {
+                run.1 = Some(end);
+            }
+        }
+    }
+    let start = runs
+        .first()
+        .filter(|(start, _)| *start <= EDGE)
+        .and_then(|(_, end)| *end)
+        .unwrap_or(0.0);
+    let end = runs
+        .last()
+        .filter(|(_, end)| match (end, duration) {
+            (None, _) => true,
+            (Some(end), Some(duration)) => *end >= duration - EDGE,
+            (Some(_), None) => false,
+        })
+        .map(|(start, _)| *start);
+    Span { start, end }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LECTURE: &str = "Input #0, wav, from 'lecture.wav':\n\
+        [silencedetect @ 0x1] silence_start: 0\n\
+        [silencedetect @ 0x1] silence_end: 4.21 | silence_duration: 4.21\n\
+        [silencedetect @ 0x1] silence_start: 600.5\n\
+        [silencedetect @ 0x1] silence_end: 601.75 | silence_duration: 1.25\n\
+        [silencedetect @ 0x1] silence_start: 3540.02\n\
+        [silencedetect @ 0x1] silence_end: 3600 | silence_duration: 59.98\n\
+        size=N/A time=01:00:00.00 bitrate=N/A speed= 900x\n";
+
+    #[test]
+    fn only_silence_touching_an_end_is_trimmed() {
+        assert_eq!(
+            find_span(LECTURE, Some(3600.0)),
+            Span {
+                start: 4.21,
+                end: Some(3540.02)
+            }
+        );
+        // Without a duration, a run that ends is not known to reach the end
+        assert_eq!(find_span(LECTURE, None).end, None);
+        // Silence still running when the input ends has no silence_end
+        let open = "[silencedetect @ 0x1] silence_start: 12.5\n";
+        assert_eq!(
+            find_span(open, None),
+            Span {
+                start: 0.0,
+                end: Some(12.5)
+            }
+        );
+        assert_eq!(
+            find_span("", Some(60.0)),
+            Span {
+                start: 0.0,
+                end: None
+            }
+        );
+    }
+
+    #[test]
+    fn span_reads_silencedetect_and_the_duration() {
+        let mock = tools::Mock::new(|tool, _| match tool {
+            Tool::Ffprobe => (
+                0,
+                r#"{"format": {"duration": "3600.0"}}"#.to_string(),
+                String::new(),
+            ),
+            _ => (0, String::new(), LECTURE.to_string()),
+        });
+        let trim = SilenceTrim::new(TrimEnds::Both, -50.0, 1.0);
+        let span = tools::with_runner(mock.clone(), || {
+            trim.span(Path::new("lecture.wav"), &AnalysisCache::default())
+        });
+        assert_eq!(
+            span.unwrap(),
+            Span {
+                start: 4.21,
+                end: Some(3540.02)
+            }
+        );
+        let calls = mock.calls.borrow();
+        assert!(calls[0].1.iter().any(|a| a.contains("silencedetect")));
+
+        // No audio: ffmpeg fails and nothing is trimmed
+        let mock = tools::Mock::new(|_, _| (1, String::new(), String::new()));
+        let span = tools::with_runner(mock, || {
+            trim.span(Path::new("silent.mkv"), &AnalysisCache::default())
+        });
+        assert!(
+            span.unwrap_err()
+                .to_string()
+                .contains("does it have audio?")
+        );
+    }
+}
@@ -0,0 +1,190 @@
+// file: src/smart.rs
+// version: 0.2.0
+// guid: 4d82b7e1-93c6-4a5f-8b20-e6f1c3a97d58
+
+//! `batch --smart`: copy streams that are already in the codec the batch would encode
+//! them to, instead of re-encoding them at a loss.
+//!
+//! Each input is probed; its main video is copied when ffprobe names the same format
+//! the video encoder writes (an HEVC file under libx265 or hevc_nvenc), and its audio
+//! when every audio track is already in the audio encoder's format. The args that only
+//! make sense for an encode of that stream (quality, speed, bitrate caps, filters) are
+//! dropped with it, since ffmpeg refuses filters on a copied stream.
+
+use crate::probe::MediaInfo;
+
+// Args for the video encoder, each with its value
+const VIDEO_ARGS: [&str; 16] = [
+    "-crf",
+    "-cq",
+    "-qp",
+    "-global_quality",
+    "-q:v",
+    "-rc",
+    "-preset",
+    "-tune",
+    "-x264-params",
+    "-x265-params",
+    "-svtav1-params",
+    "-pix_fmt",
+    "-maxrate",
+    "-bufsize",
+    "-b:v",
+    "-vf",
+];
+
+// Args for the audio encoder, each with its value
+const AUDIO_ARGS: [&str; 6] = ["-b:a", "-ac", "-ar", "-af", "-application", "-vbr"];
+
+// The format ffprobe reports for what `encoder` writes, e.g. `libx265` -> `hevc`
+fn format(encoder: &str) -> Option<&str> {
+    let format = match encoder {
+        "libx265" | "libkvazaar" => "hevc",
+        "libx264" | "libopenh264" => "h264",
+        "libsvtav1" | "libaom-av1" | "librav1e" => "av1",
+        "libvpx-vp9" => "vp9",
+        "libopus" => "opus",
+        "libfdk_aac" => "aac",
+        "libmp3lame" => "mp3",
+        "libvorbis" => "vorbis",
+        "copy" => return None,
+        // Hardware encoders are named after their format: hevc_nvenc, h264_qsv, av1_vaapi
+        encoder => encoder.split('_').next().unwrap_or(encoder),
+    };
+    Some(format)
+}
+
+// What `--smart` copies for `info`: the video and the audio, as (video, audio)
+pub fn copies(info: &MediaInfo, vcodec: &str, acodec: &str) -> (bool, bool) {
+    let is = |codec: Option<&String>, encoder: &str| {
+        codec.is_some_and(|codec| format(encoder) == Some(codec.as_str()))
+    };
+    let video = info
+        .video_stream()
+        .is_some_and(|video| is(video.codec_name.as_ref(), vcodec));
+    let mut audio = info
+        .streams
+        .iter()
+        .filter(|s| s.is_type("audio"))
+        .peekable();
+    let audio = audio.peek().is_some() && audio.all(|a| is(a.codec_name.as_ref(), acodec));
+    (video, audio)
+}
+
+// Switch the streams `--smart` copies to `copy` and drop their encoder args; returns
+// what was copied, e.g. "video (hevc)", for the job's output
+pub fn apply(
+    info: &MediaInfo,
+    vcodec: &mut String,
+    acodec: &mut String,
+    extra: &mut Vec<String>,
+) -> Vec<String> {
+    let (video, audio) = copies(info, vcodec, acodec);
+    let mut copied = Vec::new();
+    if video {
+        copied.push(format!("video ({})", format(vcodec).unwrap_or_default()));
+        copy_video(vcodec, extra);
+    }
+    if audio {
+        copied.push(format!("audio ({})", format(acodec).unwrap_or_default()));
+        copy_audio(acodec, extra);
+    }
+    copied
+}
+
+// Copy the video instead of encoding it, without the args for its encoder
+pub fn copy_video(vcodec: &mut String, extra: &mut Vec<String>) {
+    *vcodec = "copy".to_string();
+    drop_args(extra, |arg| {
+        VIDEO_ARGS.contains(&arg) || arg.starts_with("-filter:v") || arg.starts_with("-profile:v")
+    });
+}
+
+// Copy the audio instead of encoding it, without the args for its encoder
+pub fn copy_audio(acodec: &mut String, extra: &mut Vec<String>) {
+    *acodec = "copy".to_string();
+    drop_args(extra, |arg| {
+        AUDIO_ARGS.contains(&arg) || arg.starts_with("-filter:a")
+    });
+}
+
+// Remove each arg `matches` picks, with the value after it
+fn drop_args(extra: &mut Vec<String>, matches: impl Fn(&str) -> bool) {
+    let mut kept = Vec::with_capacity(extra.len());
+    let mut args = extra.drain(..);
+    while let Some(arg) = args.next() {
+        match matches(&arg) {
+            true => {
+                args.next();
+            }
+            false => kept.push(arg),
+        }
+    }
+    drop(args);
+    *extra = kept;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(video: &str, audio: &[&str]) -> MediaInfo {
+        let mut streams = vec![serde_json::json!({"codec_type": "video", "codec_name": video})];
+        streams.extend(
+            audio
+                .iter()
+                .map(|codec| serde_json::json!({"codec_type": "audio", "codec_name": codec})),
+        );
+        serde_json::from_value(serde_json::json!({ "streams": streams })).unwrap()
+    }
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn streams_already_in_the_target_format_are_copied_without_their_args() {
+        let preset = strings(&[
+            "-crf", "18", "-preset", "slow", "-vf", "hqdn3d", "-b:a", "256k", "-map", "0",
+        ]);
+        let (mut vcodec, mut acodec, mut extra) =
+            ("libx265".to_string(), "aac".to_string(), preset.clone());
+        let copied = apply(
+            &info("hevc", &["aac", "aac"]),
+            &mut vcodec,
+            &mut acodec,
+            &mut extra,
+        );
+        assert_eq!(copied, ["video (hevc)", "audio (aac)"]);
+        assert_eq!((vcodec.as_str(), acodec.as_str()), ("copy", "copy"));
+        assert_eq!(extra, ["-map", "0"]);
+
+        // One track in another format keeps the audio encode
+        let (mut vcodec, mut acodec, mut extra) =
+            ("hevc_nvenc".to_string(), "aac".to_string(), preset.clone());
+        let copied = apply(
+            &info("hevc", &["aac", "ac3"]),
+            &mut vcodec,
+            &mut acodec,
+            &mut extra,
+        );
+        assert_eq!(copied, ["video (hevc)"]);
+        assert_eq!(acodec, "aac");
+        assert_eq!(extra, ["-b:a", "256k", "-map", "0"]);
+
+        // Nothing matches: untouched
+        let (mut vcodec, mut acodec, mut extra) =
+            ("libx265".to_string(), "libopus".to_string(), preset.clone());
+        assert!(
+            apply(
+                &info("h264", &["aac"]),
+                &mut vcodec,
+                &mut acodec,
+                &mut extra
+            )
+            .is_empty()
+        );
+        assert_eq!(extra, preset);
+        assert_eq!(copies(&info("hevc", &[]), "copy", "aac"), (false, false));
+    }
+}
@@ -0,0 +1,244 @@
+// file: src/snapshots.rs
+// version: 0.1.0
+// guid: da405d6f-e012-4b5b-a54c-49a05fe59c3c
+
+//! Checkpoint snapshots of long encodes (`--snapshot-every MINUTES`).
+//!
+//! A progress bar only says where a multi-hour encode is now. With this option every
+//! MINUTES of wall time an encode also records where it got to: the position in the
+//! input, the bitrate and size so far, the size the output is heading for and the time
+//! left. Each snapshot is printed as a `  Snapshot:` line among ffmpeg's output and,
+//! with `--event-log` or `[mqtt]`, emitted as a `snapshot` event, so the history of an
+//! encode can be read back after the fact.
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::events::{Event, Events, path_str};
+use crate::stats::{clock, parse_clock};
+use crate::units::Units;
+
+// Takes the snapshots of one ffmpeg run from its stderr
+pub struct Snapshots<'a> {
+    every: Duration,
+    events: &'a Events,
+    units: Units,
+    input: String,
+    output: String,
+    started: Instant,
+    // Wall time into the encode when the next snapshot is due
+    next: Duration,
+    partial: Vec<u8>,
+    // Of the first input, from its `Duration:` header line
+    duration: Option<f64>,
+}
+
+// Where an encode was at one moment
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub elapsed: f64,
+    pub out_time: f64,
+    pub duration: Option<f64>,
+    pub bitrate_kbps: Option<f64>,
+    pub size_bytes: Option<u64>,
+}
+
+impl<'a> Snapshots<'a> {
+    pub fn new(
+        every: Duration,
+        events: &'a Events,
+        units: Units,
+        input: &Path,
+        output: &Path,
+    ) -> Self {
+        Self {
+            every,
+            events,
+            // A status line, not a summary: sizes for people even with --porcelain
+            units: units.human(),
+            input: path_str(input),
+            output: path_str(output),
+            started: Instant::now(),
+            next: every,
+            partial: Vec::new(),
+            duration: None,
+        }
+    }
+
+    // Read more of ffmpeg's stderr; returns the status lines of snapshots that came due
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<String> {
+        let mut lines = Vec::new();
+        for &byte in bytes {
+            if byte == b'\n' || byte == b'\r' {
+                let line = String::from_utf8_lossy(&self.partial).into_owned();
+                self.partial.clear();
+                lines.extend(self.line(line.trim()));
+            } else {
+                self.partial.push(byte);
+            }
+        }
+        lines
+    }
+
+    fn line(&mut self, line: &str) -> Option<String> {
+        if self.duration.is_none() {
+            self.duration = line
+                .strip_prefix("Duration: ")
+                .and_then(|rest| parse_clock(rest.split(',').next()?));
+        }
+        if !line.starts_with("frame=") && !line.starts_with("size=") {
+            return None;
+        }
+        let elapsed = self.started.elapsed();
+        if elapsed < self.next {
+            return None;
+        }
+        let snapshot = Snapshot::of(elapsed.as_secs_f64(), self.duration, line)?;
+        self.next = elapsed + self.every;
+        self.events.emit(snapshot.event(&self.input, &self.output));
+        Some(format!("  Snapshot: {}", snapshot.text(&self.units)))
+    }
+}
+
+impl Snapshot {
+    // From one of ffmpeg's stats lines; None before the first frame is written
+    pub fn of(elapsed: f64, duration: Option<f64>, stats: &str) -> Option<Self> {
+        let field = |name: &str| {
+            let (_, rest) = stats.split_once(name)?;
+            rest.split_whitespace().next()
+        };
+        Some(Self {
+            elapsed,
+            out_time: field("time=").and_then(parse_clock)?,
+            duration: duration.filter(|d| *d > 0.0),
+            bitrate_kbps: field("bitrate=")
+                .and_then(|b| b.strip_suffix("kbits/s"))
+                .and_then(|b| b.parse().ok()),
+            size_bytes: field("size=").and_then(parse_size),
+        })
+    }
+
+    fn fraction(&self) -> Option<f64> {
+        self.duration
+            .map(|duration| (self.out_time / duration).clamp(0.0, 1.0))
+            .filter(|f| *f > 0.0)
+    }
+
+    // The output's size at this rate by the end of the input
+    pub fn projected_bytes(&self) -> Option<u64> {
+        Some((self.size_bytes? as f64 / self.fraction()?) as u64)
+    }
+
+    // Wall time left at the pace so far
+    pub fn eta(&self) -> Option<f64> {
+        let fraction = self.fraction()?;
+        Some(self.elapsed * (1.0 - fraction) / fraction)
+    }
+
+    // `00:42:10 / 02:10:00 (32%), 4521.3 kbit/s, 1.4 GiB so far, 4.3 GiB projected,
+    // 1h 28m left (after 42m 05s)`
+    pub fn text(&self, units: &Units) -> String {
+        let mut parts = vec![match (self.duration, self.fraction()) {
+            (Some(duration), Some(fraction)) => format!(
+                "{} / {} ({:.0}%)",
+                clock(self.out_time),
+                clock(duration),
+                fraction * 100.0
+            ),
+            _ => clock(self.out_time),
+        }];
+        if let Some(kbps) = self.bitrate_kbps {
+            parts.push(format!("{:.1} kbit/s", kbps));
+        }
+        if let Some(bytes) = self.size_bytes {
+            parts.push(format!("{} so far", units.bytes(bytes)));
+        }
+        if let Some(bytes) = self.projected_bytes() {
+            parts.push(format!("{} projected", units.bytes(bytes)));
+        }
+        let after = format!("after {}", units.duration(self.elapsed));
+        match self.eta() {
+            Some(eta) => parts.push(format!("{} left ({})", units.duration(eta), after)),
+            None => parts.push(after),
+        }
+        parts.join(", ")
+    }
+
+    pub fn event(&self, input: &str, output: &str) -> Event {
+        Event::Snapshot {
+            input: input.to_string(),
+            output: output.to_string(),
+            elapsed_seconds: self.elapsed,
+            out_time_seconds: self.out_time,
+            percent: self.fraction().map(|f| f * 100.0),
+            bitrate_kbps: self.bitrate_kbps,
+            size_bytes: self.size_bytes,
+            projected_bytes: self.projected_bytes(),
+            eta_seconds: self.eta(),
+        }
+    }
+}
+
+// `1024kB`, `1024KiB`, `3MiB`: ffmpeg's sizes are binary whichever way it spells them
+fn parse_size(text: &str) -> Option<u64> {
+    let split = text.find(|c: char| !c.is_ascii_digit() && c != '.')?;
+    let (number, unit) = text.split_at(split);
+    let scale = match unit {
+        "B" => 1,
+        "kB" | "KiB" => 1 << 10,
+        "mB" | "MB" | "MiB" => 1 << 20,
+        "GB" | "GiB" => 1 << 30,
+        _ => return None,
+    };
+    Some((number.parse::<f64>().ok()? * scale as f64) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATS: &str = "frame=54000 fps= 30 q=28.0 size= 1468006kB time=00:30:00.00 \
+                         bitrate=6681.6kbits/s speed=1.00x";
+
+    #[test]
+    fn snapshots_project_size_and_time_left() {
+        let snapshot = Snapshot::of(1800.0, Some(7200.0), STATS).unwrap();
+        assert_eq!(snapshot.size_bytes, Some(1_503_238_144));
+        assert_eq!(snapshot.projected_bytes(), Some(6_012_952_576));
+        assert_eq!(snapshot.eta(), Some(5400.0));
+        assert_eq!(
+            snapshot.text(&Units::default()),
+            "00:30:00 / 02:00:00 (25%), 6681.6 kbit/s, 1.4 GiB so far, 5.6 GiB projected, \
+             1h 30m left (after 30m 00s)"
+        );
+        // Without the input's length there is nothing to project
+        let snapshot = Snapshot::of(60.0, None, STATS).unwrap();
+        assert_eq!((snapshot.projected_bytes(), snapshot.eta()), (None, None));
+        assert!(Snapshot::of(1.0, None, "frame=0 size=N/A time=N/A bitrate=N/A").is_none());
+        assert_eq!(parse_size("3MiB"), Some(3 << 20));
+    }
+
+    #[test]
+    fn snapshots_come_due_with_wall_time() {
+        let events = Events::default();
+        let mut snapshots = Snapshots::new(
+            Duration::from_secs(3600),
+            &events,
+            Units::default(),
+            Path::new("in.mkv"),
+            Path::new("out.mkv"),
+        );
+        assert!(snapshots.push(format!("{}\r", STATS).as_bytes()).is_empty());
+        snapshots.next = Duration::ZERO;
+        let lines = snapshots
+            .push(format!("  Duration: 02:00:00.00, start: 0.000000\n{}\r", STATS).as_bytes());
+        assert_eq!(lines.len(), 1);
+        assert!(
+            lines[0].starts_with("  Snapshot: 00:30:00 / 02:00:00 (25%)"),
+            "{}",
+            lines[0]
+        );
+        // The next one is an hour away
+        assert!(snapshots.push(format!("{}\r", STATS).as_bytes()).is_empty());
+    }
+}
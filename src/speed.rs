@@ -0,0 +1,130 @@
+// file: src/speed.rs
+// version: 0.1.0
+// guid: 66b2f693-e82b-4952-8971-6e6cd1dfd655
+
+//! Playback speed changes (`--speed 1.25x`) for sped-up lecture and audiobook copies.
+//!
+//! Video is retimed with `setpts`, audio with `atempo`, which keeps the pitch: voices
+//! sound faster, not higher. One atempo instance only accepts 0.5 to 2.0 in older
+//! ffmpeg builds, so larger changes are a chain (`2.5x` is `atempo=2,atempo=1.25`).
+
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{Result, bail};
+
+use crate::filters::{FilterGraph, add_filter};
+
+const MIN: f64 = 0.25;
+const MAX: f64 = 8.0;
+// The range a single atempo takes in every ffmpeg version
+const ATEMPO_MIN: f64 = 0.5;
+const ATEMPO_MAX: f64 = 2.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Speed(f64);
+
+impl FromStr for Speed {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let number = value.trim_end_matches(['x', 'X']);
+        match number.parse::<f64>() {
+            Ok(factor) if (MIN..=MAX).contains(&factor) => Ok(Speed(factor)),
+            _ => bail!(
+                "invalid speed '{}': expected a factor from {}x to {}x (e.g. 1.25x)",
+                value,
+                MIN,
+                MAX
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Speed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x", self.0)
+    }
+}
+
+impl Speed {
+    pub fn factor(self) -> f64 {
+        self.0
+    }
+
+    // Add the retiming filters to the end of the `-vf` and `-af` chains in `extra`.
+    // Returns a warning when the video is copied and so keeps its own speed.
+    pub fn apply(self, vcodec: &str, extra: &mut Vec<String>) -> Option<String> {
+        let mut audio = FilterGraph::new();
+        for tempo in atempo_chain(self.0) {
+            audio.filter("atempo", &[&tempo.to_string()]);
+        }
+        add_filter(extra, "-af", &audio);
+        if vcodec == "copy" {
+            return Some(format!(
+                "--speed {} only changes the audio while the video is copied; \
+                 pick a video codec, or drop the video with -vn",
+                self
+            ));
+        }
+        let mut video = FilterGraph::new();
+        video.filter("setpts", &[&format!("PTS/{}", self.0)]);
+        add_filter(extra, "-vf", &video);
+        None
+    }
+}
+
+// atempo factors that multiply to `factor`, each within the range every ffmpeg takes
+fn atempo_chain(factor: f64) -> Vec<f64> {
+    let mut chain = Vec::new();
+    let mut rest = factor;
+    while rest > ATEMPO_MAX {
+        chain.push(ATEMPO_MAX);
+        rest /= ATEMPO_MAX;
+    }
+    while rest < ATEMPO_MIN {
+        chain.push(ATEMPO_MIN);
+        rest /= ATEMPO_MIN;
+    }
+    chain.push(rest);
+    chain
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn large_changes_chain_atempo_within_its_range() {
+        assert_eq!(atempo_chain(1.25), [1.25]);
+        assert_eq!(atempo_chain(2.5), [2.0, 1.25]);
+        assert_eq!(atempo_chain(8.0), [2.0, 2.0, 2.0]);
+        assert_eq!(atempo_chain(0.25), [0.5, 0.5]);
+        assert!("1.5X".parse::<Speed>().is_ok());
+        assert!("0x".parse::<Speed>().is_err());
+        assert!("fast".parse::<Speed>().is_err());
+    }
+
+    #[test]
+    fn retiming_extends_existing_filter_chains() {
+        let mut extra: Vec<String> = ["-vf", "scale=1280:-2", "-crf", "23"]
+            .map(String::from)
+            .to_vec();
+        let speed: Speed = "2.5x".parse().unwrap();
+        assert_eq!(speed.apply("libx264", &mut extra), None);
+        assert_eq!(
+            extra,
+            [
+                "-vf",
+                "scale=1280:-2,setpts=PTS/2.5",
+                "-crf",
+                "23",
+                "-af",
+                "atempo=2,atempo=1.25"
+            ]
+        );
+        let mut extra = Vec::new();
+        assert!(speed.apply("copy", &mut extra).is_some());
+        assert_eq!(extra, ["-af", "atempo=2,atempo=1.25"]);
+    }
+}
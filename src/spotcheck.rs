@@ -0,0 +1,234 @@
+// file: src/spotcheck.rs
+// version: 0.4.0
+// guid: 6be919e4-7ac0-4b8d-8a41-785682c13648
+
+//! Post-encode spot checks (`batch --spot-check N`): matched frames from input and
+//! output for a quick look at a batch's quality, optionally scored with SSIM.
+//!
+//! Frames go to `<output dir>/spot-check/<output path>/NN-input.png` and
+//! `NN-output.png`; every pair is listed in `spot-check/manifest.ndjson`.
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use serde::Serialize;
+
+use crate::args::FfmpegArgs;
+use crate::cache::AnalysisCache;
+use crate::probe;
+use crate::schema;
+use crate::style::Marker;
+use crate::tools::{self, Tool};
+
+pub struct SpotCheck {
+    dir: PathBuf,
+    frames: usize,
+    min_ssim: Option<f64>,
+}
+
+#[derive(Serialize)]
+struct Pair<'a> {
+    schema_version: u32,
+    output: &'a str,
+    seconds: f64,
+    input_frame: String,
+    output_frame: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ssim: Option<f64>,
+    flagged: bool,
+}
+
+impl SpotCheck {
+    pub fn new(output_root: &Path, frames: usize, min_ssim: Option<f64>) -> Self {
+        Self {
+            dir: output_root.join("spot-check"),
+            frames,
+            min_ssim,
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    // Extract the frame pairs for one encode and return how many fell below the SSIM
+    // threshold. `name` is the output's path under the output root.
+    pub fn check(
+        &self,
+        cache: &AnalysisCache,
+        input: &Path,
+        output: &Path,
+        name: &Path,
+    ) -> Result<usize> {
+        let duration = probe::probe_cached(input, cache)?
+            .format
+            .duration_seconds()
+            .context("input has no duration to pick frames from")?;
+        let dir = self.dir.join(name);
+        fs::create_dir_all(&dir).with_context(|| format!("failed to create {}", dir.display()))?;
+        let output_name = name.to_string_lossy().replace('\\', "/");
+
+        let mut flagged = 0;
+        for (i, seconds) in spots(duration, self.frames).into_iter().enumerate() {
+            let input_frame = dir.join(format!("{:02}-input.png", i + 1));
+            let output_frame = dir.join(format!("{:02}-output.png", i + 1));
+            extract(input, seconds, &input_frame)?;
+            extract(output, seconds, &output_frame)?;
+            let ssim = match self.min_ssim {
+                Some(_) => Some(measure_ssim(&input_frame, &output_frame)?),
+                None => None,
+            };
+            let low = match (ssim, self.min_ssim) {
+                (Some(ssim), Some(min)) if ssim < min => {
+                    println!(
+                        "  {} spot check frame {} at {} has SSIM {:.3} (below {:.3})",
+                        Marker::Warning,
+                        i + 1,
+                        timestamp(seconds),
+                        ssim,
+                        min
+                    );
+                    flagged += 1;
+                    true
+                }
+                _ => false,
+            };
+            self.record(&Pair {
+                schema_version: schema::SPOT_CHECK,
+                output: &output_name,
+                seconds,
+                input_frame: self.relative(&input_frame),
+                output_frame: self.relative(&output_frame),
+                ssim,
+                flagged: low,
+            })?;
+        }
+        Ok(flagged)
+    }
+
+    fn record(&self, pair: &Pair) -> Result<()> {
+        let mut line = serde_json::to_string(pair)?;
+        line.push('\n');
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join("manifest.ndjson"))
+            .and_then(|mut file| file.write_all(line.as_bytes()))
+            .context("failed to write the spot check manifest")
+    }
+
+    fn relative(&self, frame: &Path) -> String {
+        frame
+            .strip_prefix(&self.dir)
+            .unwrap_or(frame)
+            .to_string_lossy()
+            .replace('\\', "/")
+    }
+}
+
+// Evenly spaced times that stay clear of the first and last frames, where fades and
+// black frames say little about quality
+fn spots(duration: f64, frames: usize) -> Vec<f64> {
+    (1..=frames)
+        .map(|i| duration * i as f64 / (frames + 1) as f64)
+        .collect()
+}
+
+fn extract(video: &Path, seconds: f64, frame: &Path) -> Result<()> {
+    // Seeking before -i is frame-accurate, so input and output land on the same frame
+    let args = FfmpegArgs::new(frame)
+        .global(["-v", "error"])
+        .input_with(["-ss".to_string(), format!("{:.3}", seconds)], video)
+        .option("-frames:v", "1")
+        .build();
+    let status = tools::status(Tool::Ffmpeg, &args)?;
+    if !status.success() {
+        bail!(
+            "failed to extract a frame at {} from {} (ffmpeg status {:?})",
+            timestamp(seconds),
+            video.display(),
+            status.code()
+        );
+    }
+    Ok(())
+}
+
+// SSIM of the output frame against the input frame, scaled to the input's size first
+// since presets may downscale
+fn measure_ssim(input_frame: &Path, output_frame: &Path) -> Result<f64> {
+    let args = FfmpegArgs::new("-")
+        .input(input_frame)
+        .input(output_frame)
+        .filter("-lavfi", "[1:v][0:v]scale2ref[out][ref];[ref][out]ssim")
+        .option("-f", "null")
+        .build();
+    let result = tools::output(Tool::Ffmpeg, &args)?;
+    parse_ssim(&String::from_utf8_lossy(&result.stderr))
+        .context("ffmpeg did not report an SSIM score")
+}
+
+// `[Parsed_ssim_1 @ 0x...] SSIM Y:0.98 U:0.99 V:0.99 All:0.985 (18.2)`
+fn parse_ssim(stderr: &str) -> Option<f64> {
+    let line = stderr.lines().rev().find(|line| line.contains("SSIM "))?;
+    let all = line
+        .split_whitespace()
+        .find_map(|w| w.strip_prefix("All:"))?;
+    all.parse().ok()
+}
+
+fn timestamp(seconds: f64) -> String {
+    let seconds = seconds as u64;
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spots_avoid_the_ends() {
+        assert_eq!(spots(100.0, 3), vec![25.0, 50.0, 75.0]);
+        assert_eq!(spots(60.0, 1), vec![30.0]);
+        assert_eq!(timestamp(3725.4), "01:02:05");
+    }
+
+    #[test]
+    fn manifest_lines_keep_their_schema() {
+        let pair = Pair {
+            schema_version: schema::SPOT_CHECK,
+            output: "show/e01.mkv",
+            seconds: 30.0,
+            input_frame: "show/e01.mkv/01-input.png".to_string(),
+            output_frame: "show/e01.mkv/01-output.png".to_string(),
+            ssim: Some(0.98),
+            flagged: false,
+        };
+        schema::assert_fields(
+            &serde_json::to_value(&pair).unwrap(),
+            1,
+            &[
+                "output",
+                "seconds",
+                "input_frame",
+                "output_frame",
+                "ssim",
+                "flagged",
+            ],
+        );
+    }
+
+    #[test]
+    fn reads_the_combined_ssim() {
+        let stderr = "Input #0, png_pipe, from 'a.png':\n\
+                      [Parsed_ssim_2 @ 0x5581] SSIM Y:0.973 U:0.988 V:0.990 All:0.979 (16.8)\n";
+        assert_eq!(parse_ssim(stderr), Some(0.979));
+        assert_eq!(parse_ssim("Conversion failed!\n"), None);
+    }
+}
@@ -0,0 +1,140 @@
+// file: src/stabilize.rs
+// version: 0.6.0
+// guid: 0b7d3e95-c461-4a2f-8d7e-6f19a2c85b03
+
+//! Two-pass vid.stab stabilization (`--stabilize`) for shaky phone and camcorder footage
+
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::{Context, Result, bail};
+
+use crate::args::FfmpegArgs;
+use crate::cache::AnalysisCache;
+use crate::encode::display_args;
+use crate::filters::{FilterGraph, add_filter, filter_path};
+use crate::report;
+use crate::stats::Stats;
+use crate::tools::{self, Tool};
+
+// vidstabdetect settings; part of the cache key, so changing them redoes the analysis
+const DETECT_OPTS: [(&str, &str); 2] = [("shakiness", "5"), ("accuracy", "15")];
+
+// One shaky clip at a time: pass 1 analyses the whole input into a transforms file,
+// pass 2 (the real encode) smooths the camera path with it.
+pub struct Stabilizer {
+    transforms: PathBuf,
+    // Cached transforms outlive the run; temporary ones are removed on drop
+    cached: bool,
+}
+
+impl Stabilizer {
+    pub fn new(cache: &AnalysisCache, input: &Path) -> Self {
+        let params: Vec<String> = DETECT_OPTS
+            .iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect();
+        if let Some(transforms) = cache.entry("vidstab", &params.join(":"), input, "trf") {
+            return Self {
+                transforms,
+                cached: true,
+            };
+        }
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        // A plain name in the temp dir keeps the path easy to quote inside a filtergraph
+        let name = format!(
+            "transcoderr-stab-{}-{}.trf",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        );
+        Self {
+            transforms: std::env::temp_dir().join(name),
+            cached: false,
+        }
+    }
+
+    fn detect_args(&self, input: &Path, result: &Path) -> Vec<OsString> {
+        FfmpegArgs::new("-")
+            .input(input)
+            .filter(
+                "-vf",
+                FilterGraph::new()
+                    .filter_opts(
+                        "vidstabdetect",
+                        &[
+                            DETECT_OPTS[0],
+                            DETECT_OPTS[1],
+                            ("result", &filter_path(result)),
+                        ],
+                    )
+                    .to_string(),
+            )
+            .option("-f", "null")
+            .build()
+    }
+
+    pub fn describe_detect(&self, input: &Path) -> String {
+        display_args(&self.detect_args(input, &self.partial()))
+    }
+
+    // Analysis goes to a side file that is moved into place only once complete, so an
+    // interrupted pass never leaves a truncated analysis for the cache to reuse
+    fn partial(&self) -> PathBuf {
+        let mut partial = self.transforms.clone().into_os_string();
+        partial.push(".partial");
+        PathBuf::from(partial)
+    }
+
+    pub fn detect(&self, input: &Path, stats: Stats) -> Result<()> {
+        if self.cached && fs::metadata(&self.transforms).is_ok_and(|m| m.len() > 0) {
+            println!("  Reusing cached stabilization analysis");
+            return Ok(());
+        }
+        let partial = self.partial();
+        let args = self.detect_args(input, &partial);
+        let status = tools::stream(Tool::Ffmpeg, &args, &mut |stderr| {
+            report::tee_log(stderr, stats, None);
+        })?;
+        if !status.success() {
+            let _ = fs::remove_file(&partial);
+            bail!(
+                "stabilization analysis failed (ffmpeg status {:?}; is ffmpeg built with libvidstab?)",
+                status.code()
+            );
+        }
+        fs::rename(&partial, &self.transforms).with_context(|| {
+            format!(
+                "failed to move stabilization analysis to {}",
+                self.transforms.display()
+            )
+        })
+    }
+
+    // Add the transform (plus the light sharpening vid.stab recommends) to the encode args
+    pub fn apply(&self, extra: &mut Vec<String>) {
+        add_filter(
+            extra,
+            "-vf",
+            FilterGraph::new()
+                .filter_opts(
+                    "vidstabtransform",
+                    &[
+                        ("input", &filter_path(&self.transforms)),
+                        ("smoothing", "10"),
+                        ("zoom", "0"),
+                    ],
+                )
+                .filter("unsharp", &["5", "5", "0.8", "3", "3", "0.4"]),
+        );
+    }
+}
+
+impl Drop for Stabilizer {
+    fn drop(&mut self) {
+        if !self.cached {
+            let _ = fs::remove_file(&self.transforms);
+        }
+    }
+}
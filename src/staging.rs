@@ -0,0 +1,442 @@
+// file: src/staging.rs
+// version: 0.2.0
+// guid: 2fa0d623-95d6-4357-885f-6a5120f6567c
+
+//! Local scratch staging for batches over slow network storage (`batch --stage-inputs`).
+//!
+//! While one file encodes, the next queued input is copied to a local work directory in
+//! the background, so ffmpeg reads from local disk instead of waiting on the network.
+//! Encodes also write locally; a finished output is copied back, checked against the
+//! local copy and only then renamed into place. Staged files are removed as soon as
+//! they are no longer needed, and the whole work directory when the batch ends.
+//!
+//! With `--stage-cache DIR` staged inputs are kept between runs instead, named by the
+//! SHA-256 of their contents (hashed while they copy). An input seen before, same path,
+//! size and modification time, is read from the cache without touching the network,
+//! so retrying a failed file, or encoding it again at another CRF, does not transfer it
+//! twice. Identical files under different names are stored once. The least recently
+//! used entries are dropped when the batch ends, once the cache is over its size limit.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::thread::{self, JoinHandle};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result, bail};
+use sha2::{Digest, Sha256};
+
+use crate::presets::sha256_hex;
+
+pub struct Stager {
+    dir: PathBuf,
+    // Distinguishes staged files with the same name from different folders
+    next: usize,
+    inputs: HashMap<PathBuf, StagedInput>,
+    cache: Option<StageCache>,
+    // Inputs read from the cache instead of copied, and their bytes
+    reused: (usize, u64),
+}
+
+struct StagedInput {
+    path: PathBuf,
+    // The background copy, until someone waits for it; yields the contents' hash when
+    // the copy is headed for the cache
+    copy: Option<JoinHandle<io::Result<Option<String>>>>,
+    // In the cache, so it outlives the job
+    cached: bool,
+}
+
+impl Stager {
+    // Work files go under the cache when there is one, so finished copies can be
+    // renamed into it
+    pub fn new(scratch: &Path, cache: Option<StageCache>) -> Result<Self> {
+        let root = cache.as_ref().map_or(scratch, |cache| cache.dir.as_path());
+        let dir = root.join(format!("transcoderr-stage-{}", std::process::id()));
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("failed to create staging directory {}", dir.display()))?;
+        Ok(Self {
+            dir,
+            next: 0,
+            inputs: HashMap::new(),
+            cache,
+            reused: (0, 0),
+        })
+    }
+
+    // `(inputs, bytes)` read from the cache rather than transferred
+    pub fn reused(&self) -> (usize, u64) {
+        self.reused
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    // Start copying `input` in the background unless it is already staged
+    pub fn prefetch(&mut self, input: &Path) {
+        if self.inputs.contains_key(input) {
+            return;
+        }
+        if let Some((path, len)) = self.cache.as_ref().and_then(|cache| cache.lookup(input)) {
+            self.reused = (self.reused.0 + 1, self.reused.1 + len);
+            self.inputs.insert(
+                input.to_path_buf(),
+                StagedInput {
+                    path,
+                    copy: None,
+                    cached: true,
+                },
+            );
+            return;
+        }
+        let path = self.slot(input);
+        let copy = {
+            let (from, to) = (input.to_path_buf(), path.clone());
+            match self.cache.is_some() {
+                true => thread::spawn(move || copy_hashing(&from, &to).map(Some)),
+                false => thread::spawn(move || fs::copy(from, to).map(|_| None)),
+            }
+        };
+        self.inputs.insert(
+            input.to_path_buf(),
+            StagedInput {
+                path,
+                copy: Some(copy),
+                cached: false,
+            },
+        );
+    }
+
+    // The local copy of `input`, waiting for (or starting) its copy as needed
+    fn input(&mut self, input: &Path) -> Result<PathBuf> {
+        self.prefetch(input);
+        if let Some(copy) = self.inputs.get_mut(input).and_then(|s| s.copy.take()) {
+            let copied = copy
+                .join()
+                .unwrap_or_else(|_| Err(io::Error::other("copy thread panicked")));
+            match copied {
+                Err(e) => {
+                    self.release(input);
+                    return Err(e).with_context(|| format!("failed to stage {}", input.display()));
+                }
+                Ok(Some(hash)) => {
+                    let staged = self.inputs.get_mut(input).expect("staged input");
+                    // Best effort: a copy that cannot be cached is still good for this job
+                    if let Some(path) = self
+                        .cache
+                        .as_ref()
+                        .and_then(|cache| cache.store(input, &staged.path, &hash))
+                    {
+                        staged.path = path;
+                        staged.cached = true;
+                    }
+                }
+                Ok(None) => {}
+            }
+        }
+        Ok(self.inputs[input].path.clone())
+    }
+
+    // Run `encode` from the local copy of `input` into a local output, then deliver the
+    // result to `output`
+    pub fn run(
+        &mut self,
+        input: &Path,
+        output: &Path,
+        encode: impl FnOnce(&Path, &Path) -> Result<()>,
+    ) -> Result<()> {
+        let staged = self.input(input)?;
+        let local = self.output(output);
+        match encode(&staged, &local) {
+            Ok(()) => self.deliver(&local, output),
+            Err(e) => {
+                let _ = fs::remove_file(&local);
+                Err(e)
+            }
+        }
+    }
+
+    // Drop every staged input except `input`: earlier jobs are done with theirs, and a
+    // prefetched file may belong to a job that was skipped
+    pub fn keep_only(&mut self, input: &Path) {
+        let stale: Vec<PathBuf> = self
+            .inputs
+            .keys()
+            .filter(|staged| staged.as_path() != input)
+            .cloned()
+            .collect();
+        for staged in stale {
+            self.release(&staged);
+        }
+    }
+
+    fn release(&mut self, input: &Path) {
+        if let Some(mut staged) = self.inputs.remove(input) {
+            if let Some(copy) = staged.copy.take() {
+                let _ = copy.join();
+            }
+            if !staged.cached {
+                let _ = fs::remove_file(&staged.path);
+            }
+        }
+    }
+
+    // Where to encode `output` locally; the name keeps the extension ffmpeg picks the
+    // muxer from
+    fn output(&mut self, output: &Path) -> PathBuf {
+        self.slot(output)
+    }
+
+    // Copy a finished local output to its real location. The copy goes to a partial
+    // file that only replaces `output` once its size matches the local file.
+    fn deliver(&self, local: &Path, output: &Path) -> Result<()> {
+        let expected = fs::metadata(local)
+            .with_context(|| format!("encoded output missing from {}", local.display()))?
+            .len();
+        if expected == 0 {
+            bail!("encoded output {} is empty", local.display());
+        }
+        let mut partial = output.as_os_str().to_owned();
+        partial.push(".partial");
+        let partial = PathBuf::from(partial);
+        let copied = fs::copy(local, &partial)
+            .and_then(|_| fs::metadata(&partial))
+            .map(|m| m.len());
+        let result = match copied {
+            Ok(len) if len == expected => fs::rename(&partial, output)
+                .with_context(|| format!("failed to move output into {}", output.display())),
+            Ok(len) => Err(anyhow::anyhow!(
+                "copy of {} is {} bytes, expected {}",
+                output.display(),
+                len,
+                expected
+            )),
+            Err(e) => Err(e).with_context(|| format!("failed to copy back {}", output.display())),
+        };
+        if result.is_err() {
+            let _ = fs::remove_file(&partial);
+        }
+        let _ = fs::remove_file(local);
+        result
+    }
+
+    fn slot(&mut self, file: &Path) -> PathBuf {
+        self.next += 1;
+        let name = file
+            .file_name()
+            .map(|n| n.to_os_string())
+            .unwrap_or_default();
+        let mut slot = std::ffi::OsString::from(format!("{}-", self.next));
+        slot.push(name);
+        self.dir.join(slot)
+    }
+}
+
+impl Drop for Stager {
+    fn drop(&mut self) {
+        for staged in self.inputs.values_mut() {
+            if let Some(copy) = staged.copy.take() {
+                let _ = copy.join();
+            }
+        }
+        let _ = fs::remove_dir_all(&self.dir);
+        if let Some(cache) = &self.cache {
+            cache.trim();
+        }
+    }
+}
+
+// Staged inputs kept between runs (`--stage-cache DIR`). Files are `<sha256>.<ext>`;
+// `refs/` maps an input's path, size and modification time to the file holding its
+// contents.
+pub struct StageCache {
+    dir: PathBuf,
+    max_bytes: u64,
+}
+
+impl StageCache {
+    pub fn open(dir: &Path, max_bytes: u64) -> Result<Self> {
+        fs::create_dir_all(dir.join("refs"))
+            .with_context(|| format!("failed to create stage cache {}", dir.display()))?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            max_bytes,
+        })
+    }
+
+    // The cached copy of `input` and its size, if its contents are known and still here
+    fn lookup(&self, input: &Path) -> Option<(PathBuf, u64)> {
+        let name = fs::read_to_string(self.reference(input)?).ok()?;
+        let path = self.dir.join(name.trim());
+        let len = fs::metadata(&path).ok()?.len();
+        if len != fs::metadata(input).ok()?.len() {
+            return None;
+        }
+        touch(&path);
+        Some((path, len))
+    }
+
+    // Move a fresh copy of `input` with contents `hash` into the cache; a file with
+    // the same contents already there is kept and the copy dropped
+    fn store(&self, input: &Path, copy: &Path, hash: &str) -> Option<PathBuf> {
+        let mut name = hash.to_string();
+        if let Some(ext) = input.extension() {
+            name.push('.');
+            name.push_str(&ext.to_string_lossy());
+        }
+        let path = self.dir.join(&name);
+        if path.is_file() {
+            let _ = fs::remove_file(copy);
+            touch(&path);
+        } else {
+            fs::rename(copy, &path).ok()?;
+        }
+        let reference = self.reference(input)?;
+        let partial = reference.with_extension(format!("{}.partial", std::process::id()));
+        if fs::write(&partial, &name).is_err() || fs::rename(&partial, &reference).is_err() {
+            let _ = fs::remove_file(&partial);
+        }
+        Some(path)
+    }
+
+    fn reference(&self, input: &Path) -> Option<PathBuf> {
+        let path = fs::canonicalize(input).ok()?;
+        let meta = fs::metadata(&path).ok()?;
+        let modified = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        let key = format!(
+            "{}\0{}\0{}",
+            path.to_string_lossy(),
+            meta.len(),
+            modified.as_nanos()
+        );
+        Some(self.dir.join("refs").join(sha256_hex(key.as_bytes())))
+    }
+
+    // Drop the least recently used files until the cache fits its limit, then the
+    // references to them
+    fn trim(&self) {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return;
+        };
+        let mut files: Vec<(SystemTime, u64, PathBuf)> = entries
+            .flatten()
+            .filter_map(|entry| {
+                let meta = entry.metadata().ok()?;
+                let used = meta.modified().ok()?;
+                meta.is_file().then(|| (used, meta.len(), entry.path()))
+            })
+            .collect();
+        files.sort();
+        let mut total: u64 = files.iter().map(|(_, len, _)| len).sum();
+        for (_, len, path) in files {
+            if total <= self.max_bytes {
+                break;
+            }
+            if fs::remove_file(&path).is_ok() {
+                total -= len;
+            }
+        }
+        let Ok(refs) = fs::read_dir(self.dir.join("refs")) else {
+            return;
+        };
+        for reference in refs.flatten() {
+            let gone = fs::read_to_string(reference.path())
+                .is_ok_and(|name| !self.dir.join(name.trim()).is_file());
+            if gone {
+                let _ = fs::remove_file(reference.path());
+            }
+        }
+    }
+}
+
+// Copy `from` to `to`, hashing the contents on the way
+fn copy_hashing(from: &Path, to: &Path) -> io::Result<String> {
+    let (mut reader, mut writer) = (File::open(from)?, File::create(to)?);
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 1 << 20];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        writer.write_all(&buf[..n])?;
+    }
+    writer.flush()?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+// Mark a cached file as just used, for trimming
+fn touch(path: &Path) {
+    if let Ok(file) = File::options().append(true).open(path) {
+        let _ = file.set_modified(SystemTime::now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cached_inputs_are_reused_across_runs() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let (remote, cache) = (temp.path().join("remote"), temp.path().join("cache"));
+        fs::create_dir_all(&remote).unwrap();
+        let (a, b) = (remote.join("a.mkv"), remote.join("b.mkv"));
+        fs::write(&a, b"same").unwrap();
+        fs::write(&b, b"same").unwrap();
+        let copy = |input: &Path, output: &Path| {
+            fs::copy(input, output)?;
+            Ok(())
+        };
+
+        let mut first = Stager::new(
+            temp.path(),
+            Some(StageCache::open(&cache, 1 << 30).unwrap()),
+        )
+        .unwrap();
+        first.run(&a, &temp.path().join("a.out"), copy).unwrap();
+        first.run(&b, &temp.path().join("b.out"), copy).unwrap();
+        assert_eq!(first.reused(), (0, 0));
+        drop(first);
+        // Identical contents are stored once
+        let blob = cache.join(format!("{}.mkv", sha256_hex(b"same")));
+        let files: Vec<PathBuf> = fs::read_dir(&cache)
+            .unwrap()
+            .flatten()
+            .map(|e| e.path())
+            .filter(|p| p.is_file())
+            .collect();
+        assert_eq!(files, std::slice::from_ref(&blob));
+
+        let mut second = Stager::new(
+            temp.path(),
+            Some(StageCache::open(&cache, 1 << 30).unwrap()),
+        )
+        .unwrap();
+        second
+            .run(&a, &temp.path().join("a.out"), |input, output| {
+                assert_eq!(input, blob);
+                copy(input, output)
+            })
+            .unwrap();
+        assert_eq!(second.reused(), (1, 4));
+        drop(second);
+
+        // A changed input is copied again; the cache over its limit is trimmed
+        fs::write(&a, b"changed").unwrap();
+        let mut third =
+            Stager::new(temp.path(), Some(StageCache::open(&cache, 0).unwrap())).unwrap();
+        third.run(&a, &temp.path().join("a.out"), copy).unwrap();
+        assert_eq!(third.reused(), (0, 0));
+        drop(third);
+        assert!(!blob.exists());
+        assert_eq!(fs::read_dir(cache.join("refs")).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,480 @@
+// file: src/stats.rs
+// version: 0.6.0
+// guid: 440ce175-a69b-4043-989e-cf40cd6b0876
+
+//! How ffmpeg's progress reaches the terminal (`--stats`).
+//!
+//! ffmpeg redraws its stats line (`frame= ... time=... speed=...`) with a carriage
+//! return several times a second. A terminal shows one updating line, but a file or CI
+//! log keeps every redraw. `full` passes ffmpeg's output through unchanged; `line`
+//! replaces the stats with a progress bar worked out from the input's duration and
+//! `time=`; `plain` (what `--no-fancy` picks) prints an ordinary status line every 10%
+//! of the input, or every 30 seconds when its length is unknown, with no carriage
+//! returns or escape codes for screen readers and log collectors to trip over; `none`
+//! leaves them out and prints only the last one when ffmpeg exits, so a log gets one
+//! summary line per encode. ffmpeg's other messages pass through in every mode.
+//!
+//! For an encode, `line` and `plain` read ffmpeg's machine-readable report
+//! (`-progress pipe:1`: key=value blocks on stdout) rather than the stats line, which
+//! gives the frame rate and lets them show an ETA.
+//!
+//! Under `watch --metrics` the filter also reads the speed and frame rate off the
+//! running encode into the metrics, whatever the mode.
+
+use std::env;
+use std::io::{self, BufRead, BufReader, IsTerminal, Read, Write};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+
+use crate::metrics::Metrics;
+
+const BAR_WIDTH: usize = 30;
+// `plain` status lines when the input's length is unknown
+const PLAIN_PERIOD: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Stats {
+    None,
+    Line,
+    Plain,
+    #[default]
+    Full,
+}
+
+impl Stats {
+    // `--no-fancy`: ffmpeg's redrawn line and the bar become plain status lines
+    pub fn plain(self) -> Self {
+        match self {
+            Stats::Line | Stats::Full => Stats::Plain,
+            mode => mode,
+        }
+    }
+
+    // A progress bar redrawn into a file is the spam `line` is meant to avoid; a dumb
+    // terminal cannot redraw it at all
+    pub fn for_stderr(self) -> Self {
+        match self {
+            Stats::Line if !io::stderr().is_terminal() => Stats::None,
+            Stats::Line if env::var("TERM").is_ok_and(|term| term == "dumb") => Stats::Plain,
+            mode => mode,
+        }
+    }
+
+    // Modes that draw their own progress, and so want ffmpeg's `-progress` report
+    pub fn follows_progress(self) -> bool {
+        matches!(self, Stats::Line | Stats::Plain)
+    }
+}
+
+// Rewrites ffmpeg's stderr, as it arrives, for the chosen mode
+pub struct StatsFilter<W: Write> {
+    mode: Stats,
+    out: W,
+    partial: Vec<u8>,
+    // Of the first input, from its `Duration:` header line
+    duration: Option<f64>,
+    last: Option<String>,
+    // Progress comes from `-progress` blocks, not the stats line
+    following: bool,
+    drawn: bool,
+    // Tenths of the input covered by the last `plain` status line, and when it was printed
+    reported: usize,
+    reported_at: Instant,
+    // Where the speed and frame rate go as the encode runs
+    metrics: Option<Arc<Mutex<Metrics>>>,
+}
+
+impl<W: Write> StatsFilter<W> {
+    pub fn new(mode: Stats, out: W) -> Self {
+        Self {
+            mode,
+            out,
+            partial: Vec::new(),
+            duration: None,
+            last: None,
+            following: false,
+            drawn: false,
+            reported: 0,
+            reported_at: Instant::now(),
+            metrics: None,
+        }
+    }
+
+    // Progress will be fed in by `follow_progress`
+    pub fn following_progress(mut self, following: bool) -> Self {
+        self.following = following;
+        self
+    }
+
+    pub fn reporting_to(mut self, metrics: Option<Arc<Mutex<Metrics>>>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        if self.mode == Stats::Full {
+            let _ = self.out.write_all(bytes);
+            // Read on only for the stats lines
+            if self.metrics.is_none() {
+                return;
+            }
+        }
+        for &byte in bytes {
+            if byte == b'\n' || byte == b'\r' {
+                let line = String::from_utf8_lossy(&self.partial).into_owned();
+                self.partial.clear();
+                self.line(&line);
+            } else {
+                self.partial.push(byte);
+            }
+        }
+    }
+
+    pub fn finish(mut self) {
+        if !self.partial.is_empty() {
+            let line = String::from_utf8_lossy(&self.partial).into_owned();
+            self.line(&line);
+        }
+        self.record(&Progress::default());
+        match self.last.take() {
+            Some(last) if matches!(self.mode, Stats::None | Stats::Plain) => {
+                let _ = writeln!(self.out, "{}", last);
+            }
+            _ => self.end_bar(),
+        }
+        let _ = self.out.flush();
+    }
+
+    // A line of our own among ffmpeg's messages
+    pub fn message(&mut self, line: &str) {
+        self.end_bar();
+        let _ = writeln!(self.out, "{}", line);
+    }
+
+    fn line(&mut self, line: &str) {
+        let trimmed = line.trim();
+        if is_stats_line(trimmed) {
+            if !self.following {
+                self.progress(Progress::of(trimmed));
+            }
+            self.last = Some(trimmed.to_string());
+            return;
+        }
+        // `full` passed it through in `push`
+        if trimmed.is_empty() || self.mode == Stats::Full {
+            return;
+        }
+        if self.duration.is_none() {
+            self.duration = header_duration(trimmed);
+        }
+        self.end_bar();
+        let _ = writeln!(self.out, "{}", line.trim_end());
+    }
+
+    fn progress(&mut self, progress: Progress) {
+        let progress = Progress {
+            duration: self.duration.filter(|d| *d > 0.0),
+            ..progress
+        };
+        self.record(&progress);
+        match self.mode {
+            Stats::Line => {
+                let _ = write!(self.out, "\r{}\x1b[K", progress_bar(&progress));
+                let _ = self.out.flush();
+                self.drawn = true;
+            }
+            Stats::Plain => self.status_line(&progress),
+            _ => {}
+        }
+    }
+
+    fn status_line(&mut self, progress: &Progress) {
+        let due = match progress.fraction() {
+            Some(fraction) => {
+                let tenths = (fraction * 10.0) as usize;
+                let due = tenths > self.reported;
+                self.reported = self.reported.max(tenths);
+                due
+            }
+            None => self.reported_at.elapsed() >= PLAIN_PERIOD,
+        };
+        if due {
+            self.reported_at = Instant::now();
+            let _ = writeln!(self.out, "  Progress: {}", progress.text());
+            let _ = self.out.flush();
+        }
+    }
+
+    fn record(&self, progress: &Progress) {
+        if let Some(metrics) = &self.metrics {
+            let mut metrics = metrics.lock().unwrap_or_else(PoisonError::into_inner);
+            metrics.speed = progress.speed.unwrap_or(0.0);
+            metrics.fps = progress.fps.unwrap_or(0.0);
+        }
+    }
+
+    // Messages go below the bar; the next stats line starts a new one
+    fn end_bar(&mut self) {
+        if self.drawn {
+            let _ = writeln!(self.out);
+            self.drawn = false;
+        }
+    }
+}
+
+// Feed ffmpeg's `-progress` report to `filter` as each block of it completes
+pub fn follow_progress<W: Write>(from: &mut dyn Read, filter: &Mutex<StatsFilter<W>>) {
+    let mut progress = Progress::default();
+    for line in BufReader::new(from).lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        let Some((key, value)) = line.trim().split_once('=') else {
+            continue;
+        };
+        match key {
+            "out_time" => progress.time = parse_clock(value),
+            "fps" => progress.fps = value.parse().ok(),
+            "speed" => progress.speed = parse_speed(value),
+            // Ends each block
+            "progress" => filter
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .progress(progress),
+            _ => {}
+        }
+    }
+}
+
+// ffmpeg's stats line, `frame=  120 fps= 48 ... time=00:00:05.00 ... speed=1.9x`
+pub fn is_stats_line(line: &str) -> bool {
+    let line = line.trim();
+    line.starts_with("frame=") || line.starts_with("size=")
+}
+
+// The input's length from ffmpeg's `Duration: 00:22:01.00, start: ...` header line
+pub fn header_duration(line: &str) -> Option<f64> {
+    let rest = line.trim().strip_prefix("Duration: ")?;
+    parse_clock(rest.split(',').next()?)
+}
+
+// Where an encode is, from one of ffmpeg's stats lines or `-progress` blocks
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Progress {
+    pub time: Option<f64>,
+    pub fps: Option<f64>,
+    pub speed: Option<f64>,
+    // Of the input, filled in by the filter
+    pub duration: Option<f64>,
+}
+
+impl Progress {
+    pub fn of(stats: &str) -> Self {
+        let field = |name: &str| {
+            let (_, rest) = stats.split_once(name)?;
+            rest.split_whitespace().next()
+        };
+        Self {
+            time: field("time=").and_then(parse_clock),
+            fps: field("fps=").and_then(|fps| fps.parse().ok()),
+            speed: field("speed=").and_then(parse_speed),
+            duration: None,
+        }
+    }
+
+    pub fn fraction(&self) -> Option<f64> {
+        let (time, duration) = self.time.zip(self.duration)?;
+        Some((time / duration).clamp(0.0, 1.0))
+    }
+
+    // Wall time left at the current speed
+    pub fn eta(&self) -> Option<f64> {
+        let left = self.duration? - self.time?;
+        let speed = self.speed.filter(|s| *s > 0.0)?;
+        (left > 0.0).then(|| left / speed)
+    }
+
+    // ` 42% 00:09:15 / 00:22:01 48 fps 1.83x ETA 00:07:02`; without the length, just
+    // the position, frame rate and speed
+    fn text(&self) -> String {
+        let mut parts = Vec::new();
+        match (self.time, self.duration, self.fraction()) {
+            (Some(time), Some(duration), Some(fraction)) => parts.push(format!(
+                "{:>3.0}% {} / {}",
+                fraction * 100.0,
+                clock(time),
+                clock(duration)
+            )),
+            (Some(time), _, _) => parts.push(clock(time)),
+            _ => {}
+        }
+        parts.extend(self.fps.map(|fps| format!("{:.0} fps", fps)));
+        parts.push(self.speed.map_or("N/A".to_string(), |s| format!("{}x", s)));
+        parts.extend(self.eta().map(|eta| format!("ETA {}", clock(eta))));
+        parts.join(" ")
+    }
+}
+
+// `  [=========>           ]  42% 00:09:15 / 00:22:01 48 fps 1.83x ETA 00:07:02`
+fn progress_bar(progress: &Progress) -> String {
+    let Some(fraction) = progress.fraction() else {
+        return format!("  {}", progress.text());
+    };
+    let filled = (fraction * BAR_WIDTH as f64) as usize;
+    let head = if filled < BAR_WIDTH { ">" } else { "" };
+    format!(
+        "  [{}{}{}] {}",
+        "=".repeat(filled),
+        head,
+        " ".repeat(BAR_WIDTH - filled - head.len()),
+        progress.text()
+    )
+}
+
+// `1.83x` -> 1.83; `N/A` is None
+fn parse_speed(text: &str) -> Option<f64> {
+    text.trim().strip_suffix('x')?.trim().parse().ok()
+}
+
+// `00:22:01.12` -> 1321.12; `N/A` and negative times (before the first frame) are None
+pub fn parse_clock(text: &str) -> Option<f64> {
+    let text = text.trim();
+    if text.starts_with('-') {
+        return None;
+    }
+    let mut seconds = 0.0;
+    for part in text.split(':') {
+        seconds = seconds * 60.0 + part.parse::<f64>().ok()?;
+    }
+    Some(seconds)
+}
+
+pub fn clock(seconds: f64) -> String {
+    let seconds = seconds as u64;
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STDERR: &str = "Input #0, matroska,webm, from 'in.mkv':\n  \
+        Duration: 00:01:40.00, start: 0.000000, bitrate: 838 kb/s\n\
+        frame=  240 fps= 48 q=28.0 size=    1024kB time=00:00:10.00 bitrate= 838.9kbits/s speed=1.99x\r\
+        [libx265 @ 0x1] some warning\n\
+        frame= 2400 fps= 48 q=28.0 Lsize=   10240kB time=00:01:40.00 bitrate= 838.9kbits/s speed=2.01x\n";
+
+    fn filtered(mode: Stats) -> String {
+        let mut out = Vec::new();
+        let mut filter = StatsFilter::new(mode, &mut out);
+        // In pieces, as a pipe delivers it
+        for chunk in STDERR.as_bytes().chunks(7) {
+            filter.push(chunk);
+        }
+        filter.finish();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn full_passes_everything_through() {
+        assert_eq!(filtered(Stats::Full), STDERR);
+    }
+
+    #[test]
+    fn metrics_get_the_running_speed_until_the_encode_ends() {
+        let metrics = Arc::new(Mutex::new(Metrics::default()));
+        let mut out = Vec::new();
+        let mut filter =
+            StatsFilter::new(Stats::Full, &mut out).reporting_to(Some(Arc::clone(&metrics)));
+        let (first_stats, rest) = STDERR.split_at(STDERR.find('\r').unwrap() + 1);
+        filter.push(first_stats.as_bytes());
+        assert_eq!(metrics.lock().unwrap().speed, 1.99);
+        assert_eq!(metrics.lock().unwrap().fps, 48.0);
+        filter.push(rest.as_bytes());
+        assert_eq!(metrics.lock().unwrap().speed, 2.01);
+        filter.finish();
+        assert_eq!(String::from_utf8(out).unwrap(), STDERR);
+        assert_eq!(*metrics.lock().unwrap(), Metrics::default());
+    }
+
+    #[test]
+    fn none_keeps_messages_and_the_final_stats_line() {
+        let out = filtered(Stats::None);
+        assert!(!out.contains('\r'));
+        assert_eq!(out.matches("frame=").count(), 1);
+        assert!(out.contains("[libx265 @ 0x1] some warning\n"));
+        assert!(out.ends_with("time=00:01:40.00 bitrate= 838.9kbits/s speed=2.01x\n"));
+    }
+
+    #[test]
+    fn plain_prints_status_lines_every_tenth() {
+        let out = filtered(Stats::Plain);
+        assert!(!out.contains('\r') && !out.contains('\x1b'), "{:?}", out);
+        assert_eq!(
+            out.lines()
+                .filter(|l| l.starts_with("  Progress: "))
+                .count(),
+            2
+        );
+        assert!(out.contains("  Progress:  10% 00:00:10 / 00:01:40 48 fps 1.99x ETA 00:00:45\n"));
+        assert!(out.ends_with("time=00:01:40.00 bitrate= 838.9kbits/s speed=2.01x\n"));
+        assert_eq!(Stats::Line.plain(), Stats::Plain);
+        assert_eq!(Stats::None.plain(), Stats::None);
+    }
+
+    #[test]
+    fn line_draws_a_bar_from_the_duration() {
+        let out = filtered(Stats::Line);
+        assert!(!out.contains("frame="));
+        assert!(
+            out.contains(&format!(
+                "\r  [==={}{}]  10% 00:00:10 / 00:01:40 48 fps 1.99x ETA 00:00:45\x1b[K\n[libx265 @ 0x1] some warning",
+                ">",
+                " ".repeat(26)
+            )),
+            "{:?}",
+            out
+        );
+        assert!(out.ends_with(&format!(
+            "[{}] 100% 00:01:40 / 00:01:40 48 fps 2.01x\x1b[K\n",
+            "=".repeat(30)
+        )));
+        assert_eq!(parse_clock("N/A"), None);
+        assert_eq!(parse_clock("-00:00:00.04"), None);
+    }
+
+    #[test]
+    fn line_follows_the_progress_report_instead_of_the_stats_line() {
+        let mut out = Vec::new();
+        let filter = Mutex::new(StatsFilter::new(Stats::Line, &mut out).following_progress(true));
+        filter.lock().unwrap().push(STDERR.as_bytes());
+        let report = "frame=1200\nfps=60.00\nout_time_us=50000000\nout_time=00:00:50.000000\n\
+                      speed=2.50x\nprogress=continue\nframe=2400\nfps=59.50\n\
+                      out_time=00:01:40.000000\nspeed=2.48x\nprogress=end\n";
+        follow_progress(&mut report.as_bytes(), &filter);
+        filter.into_inner().unwrap().finish();
+        let out = String::from_utf8(out).unwrap();
+
+        // Only the report draws: its frame rate, speed and what is left at that speed
+        assert_eq!(out.matches('\r').count(), 2, "{:?}", out);
+        assert!(
+            out.contains(&format!(
+                "\r  [{}>{}]  50% 00:00:50 / 00:01:40 60 fps 2.5x ETA 00:00:20\x1b[K",
+                "=".repeat(15),
+                " ".repeat(14)
+            )),
+            "{:?}",
+            out
+        );
+        assert!(out.ends_with("] 100% 00:01:40 / 00:01:40 60 fps 2.48x\x1b[K\n"));
+        assert_eq!(parse_speed("1.83x"), Some(1.83));
+        assert_eq!(parse_speed("N/A"), None);
+    }
+}
@@ -0,0 +1,119 @@
+// file: src/stereo.rs
+// version: 0.2.1
+// guid: 6f97fead-b236-456c-966e-2f16676c95af
+
+//! Stereo compatibility track (`--stereo-compat`) for TVs and soundbars that cannot
+//! decode surround audio.
+//!
+//! The first surround track is downmixed to AAC stereo with the centre (dialogue)
+//! channel weighted up and loud effects evened out, and placed first with the default
+//! flag so players pick it without asking. The original tracks follow untouched.
+
+use crate::filters::FilterGraph;
+use crate::streams::{StreamPlan, disposition_flags};
+
+const BITRATE: &str = "192k";
+const TITLE: &str = "Stereo (dialogue boost)";
+// Evens out loudness over ~250 ms windows: explosions come down, speech comes up
+const NORMALIZE: [(&str, &str); 2] = [("f", "250"), ("g", "15")];
+
+// Downmix the first surround track into a new first audio stream, flagged default; the
+// original tracks follow untouched. Files without surround audio are left alone.
+pub fn add_downmix(plan: &mut StreamPlan) {
+    let Some(source) = plan
+        .audio()
+        .iter()
+        .find(|s| s.stream.channels.is_some_and(|c| c > 2))
+    else {
+        return;
+    };
+    let mut downmix = source.derive();
+    downmix.codec = Some("aac".to_string());
+    downmix.options = vec![
+        ("-b".to_string(), BITRATE.to_string()),
+        ("-ac".to_string(), "2".to_string()),
+        (
+            "-filter".to_string(),
+            downmix_filter(source.stream.channel_layout.as_deref()),
+        ),
+    ];
+    downmix.metadata = vec![("title".to_string(), TITLE.to_string())];
+    downmix.disposition = Some("default".to_string());
+
+    plan.set_audio_codec("copy");
+    for stream in plan.audio_mut() {
+        stream.disposition = Some(disposition_flags(&stream.stream, &["default"]));
+    }
+    plan.insert_audio(downmix);
+}
+
+// A centre-heavy pan for the common layouts; other layouts are left to ffmpeg's
+// standard downmix (`-ac 2`) and only get the normalization
+fn downmix_filter(layout: Option<&str>) -> String {
+    let surround = match layout.unwrap_or("") {
+        l if l.starts_with("7.1") => Some(("BL+0.3*SL", "BR+0.3*SR")),
+        l if l.starts_with("5.1(side)") => Some(("SL", "SR")),
+        l if l.starts_with("5.1") => Some(("BL", "BR")),
+        _ => None,
+    };
+    let mut graph = FilterGraph::new();
+    if let Some((left, right)) = surround {
+        // `<` renormalizes the gains so the mix cannot clip
+        graph.filter(
+            "pan",
+            &[&format!(
+                "stereo|FL<FC+0.6*FL+0.4*{}|FR<FC+0.6*FR+0.4*{}",
+                left, right
+            )],
+        );
+    }
+    graph.filter_opts("dynaudnorm", &NORMALIZE).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probe::{MediaInfo, Stream};
+
+    fn audio(channels: u32, layout: &str) -> Stream {
+        Stream {
+            codec_type: Some("audio".to_string()),
+            channels: Some(channels),
+            channel_layout: Some(layout.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn downmix_goes_first_and_is_default() {
+        let mut commentary = audio(2, "stereo");
+        commentary.disposition = [("default".to_string(), 1), ("comment".to_string(), 1)].into();
+        let info = MediaInfo {
+            streams: vec![commentary, audio(6, "5.1(side)")],
+            ..Default::default()
+        };
+        let mut plan = StreamPlan::new(&info);
+        add_downmix(&mut plan);
+        let joined = plan.args().join(" ");
+        assert!(joined.starts_with("-map 0:V? -map 0:a:1 -map 0:a? -map 0:s? -c:a copy"));
+        // The downmix takes its source's tags (language) before its own title
+        assert!(joined.contains(
+            "-map_metadata:s:a:0 0:s:a:1 -map_metadata:s:a:1 0:s:a:0 -map_metadata:s:a:2 0:s:a:1"
+        ));
+        assert!(joined.contains("-filter:a:0 pan=stereo|FL<FC+0.6*FL+0.4*SL|"));
+        // Only the default flag moves; the commentary stays a commentary
+        assert!(
+            joined
+                .ends_with("-disposition:a:0 default -disposition:a:1 comment -disposition:a:2 0")
+        );
+
+        let stereo_only = MediaInfo {
+            streams: vec![audio(2, "stereo")],
+            ..Default::default()
+        };
+        let mut plan = StreamPlan::new(&stereo_only);
+        add_downmix(&mut plan);
+        assert_eq!(plan.audio().len(), 1);
+        assert_eq!(downmix_filter(Some("6.1")), "dynaudnorm=f=250:g=15");
+    }
+}
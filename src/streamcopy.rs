@@ -0,0 +1,60 @@
+// file: src/streamcopy.rs
+// version: 0.1.0
+// guid: ae8a855b-759f-482b-b6d6-c005bba349f6
+
+//! Stream copy fast path: batch jobs whose every stream is copied, so that only the
+//! container changes.
+//!
+//! Such a job is a remux, which takes seconds where an encode takes hours, but the
+//! steps around an encode would still cost their own decode passes: the `--stabilize`
+//! shake analysis and the `--spot-check` frame comparisons. A batch skips them for
+//! these jobs, along with `--deterministic`'s encoder pinning, since nothing is
+//! encoded, filtered or changed in the frames.
+
+// Whether ffmpeg with these codecs and args only copies streams: both codecs are
+// `copy`, and no arg picks another codec for some stream or adds a filter
+pub fn is_stream_copy(vcodec: &str, acodec: &str, extra: &[String]) -> bool {
+    if vcodec != "copy" || acodec != "copy" {
+        return false;
+    }
+    let mut args = extra.iter();
+    while let Some(arg) = args.next() {
+        // `-c:a:1` and `-filter:v` apply to some streams; what matters is the option
+        match arg.split(':').next().unwrap_or(arg) {
+            "-c" | "-codec" | "-vcodec" | "-acodec" | "-scodec"
+                if args.next().is_some_and(|codec| codec != "copy") =>
+            {
+                return false;
+            }
+            "-vf" | "-af" | "-filter" | "-filter_complex" | "-lavfi" => return false,
+            _ => {}
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(text: &str) -> Vec<String> {
+        text.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn only_copies_of_every_stream_take_the_fast_path() {
+        let mapping = args("-map 0:v -map 0:a:1 -c:s copy -disposition:s:0 default");
+        assert!(is_stream_copy("copy", "copy", &mapping));
+        assert!(!is_stream_copy("copy", "aac", &mapping));
+        assert!(!is_stream_copy("libx265", "copy", &[]));
+        // A per-stream encode (a stereo downmix, mov_text subtitles) or any filter
+        assert!(!is_stream_copy("copy", "copy", &args("-c:a:1 aac")));
+        assert!(!is_stream_copy("copy", "copy", &args("-c:s mov_text")));
+        assert!(!is_stream_copy("copy", "copy", &args("-af atempo=1.25")));
+        assert!(!is_stream_copy(
+            "copy",
+            "copy",
+            &args("-filter:v:0 scale=1280:-2")
+        ));
+    }
+}
@@ -0,0 +1,459 @@
+// file: src/streams.rs
+// version: 0.5.0
+// guid: c9dcacf5-6b39-4fa6-92d4-6d753ed13de3
+
+//! Stream mapping: which input streams reach the output, in which order, and with which
+//! codec, options, tags and dispositions.
+//!
+//! `StreamPlan` is built once per file from the probe and the user's policies
+//! (`--languages`, `--subtitle-default`, `--stereo-compat`, the subtitle retiming), so features that touch
+//! streams agree on output indices. Streams are mapped by type (`0:V?`, `0:a?`, `0:s?`),
+//! the same specs the presets use, and left out with negative maps (`-map -0:a:2`).
+//!
+//! The global `-map_metadata 0` does not reach stream tags in every container pairing
+//! (mp4 to mkv loses audio titles such as "Director's Commentary"), and output indices
+//! shift once a stream is added or dropped. So every planned stream copies its tags from
+//! its own input stream (`-map_metadata:s:a:1 0:s:a:0`) and gets its dispositions set
+//! explicitly, whether or not a policy is in effect.
+
+use std::path::Path;
+
+use crate::cache::AnalysisCache;
+use crate::probe::{self, MediaInfo, Stream};
+use crate::stereo;
+use crate::style::Marker;
+use crate::subtiming::{self, SubTiming};
+use crate::subtitles::{Selection, SubtitlePolicy, same_language};
+
+// The user's stream policies for one file
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Policies<'a> {
+    // Audio and subtitle languages to keep; empty keeps everything
+    pub languages: &'a [String],
+    pub subtitle_default: Option<&'a SubtitlePolicy>,
+    pub stereo_compat: bool,
+    pub subtitle_timing: Option<SubTiming>,
+}
+
+impl Policies<'_> {
+    fn is_empty(&self) -> bool {
+        self.languages.is_empty()
+            && self.subtitle_default.is_none()
+            && !self.stereo_compat
+            && self.subtitle_timing.is_none()
+    }
+}
+
+// One output video, audio or subtitle stream
+#[derive(Debug, Clone)]
+pub struct OutputStream {
+    // Index among the input's streams of this type (`0:a:<input>`)
+    pub input: usize,
+    pub stream: Stream,
+    // An extra stream made from `input` (the stereo downmix), mapped on its own
+    derived: bool,
+    pub codec: Option<String>,
+    // Per-stream options by flag (`-b`, `-ac`, `-filter`), suffixed with the stream spec
+    pub options: Vec<(String, String)>,
+    // Tags set on top of the ones copied from `input`
+    pub metadata: Vec<(String, String)>,
+    // Replaces the input's dispositions; `+flag`/`-flag` adjust them instead
+    pub disposition: Option<String>,
+}
+
+impl OutputStream {
+    fn new(input: usize, stream: &Stream) -> Self {
+        Self {
+            input,
+            stream: stream.clone(),
+            derived: false,
+            codec: None,
+            options: Vec::new(),
+            metadata: Vec::new(),
+            disposition: None,
+        }
+    }
+
+    // A new stream made from this one; it starts with none of this one's settings
+    pub fn derive(&self) -> Self {
+        Self {
+            derived: true,
+            ..Self::new(self.input, &self.stream)
+        }
+    }
+
+    fn map_metadata(&self, spec: &str, n: usize, args: &mut Vec<String>) {
+        args.extend([
+            format!("-map_metadata:s:{}:{}", spec, n),
+            format!("0:s:{}:{}", spec, self.input),
+        ]);
+    }
+
+    fn args(&self, spec: &str, n: usize, args: &mut Vec<String>) {
+        if let Some(codec) = &self.codec {
+            args.extend([format!("-c:{}:{}", spec, n), codec.clone()]);
+        }
+        for (flag, value) in &self.options {
+            args.extend([format!("{}:{}:{}", flag, spec, n), value.clone()]);
+        }
+        for (key, value) in &self.metadata {
+            args.extend([
+                format!("-metadata:s:{}:{}", spec, n),
+                format!("{}={}", key, value),
+            ]);
+        }
+        let disposition = match &self.disposition {
+            Some(disposition) => disposition.clone(),
+            None => disposition_flags(&self.stream, &[]),
+        };
+        args.extend([format!("-disposition:{}:{}", spec, n), disposition]);
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct StreamPlan {
+    // Output order within each type; video leaves out cover art, as `0:V?` does
+    video: Vec<OutputStream>,
+    audio: Vec<OutputStream>,
+    subtitles: Vec<OutputStream>,
+    // Codec for every audio stream without its own (`copy` next to a downmix)
+    audio_codec: Option<String>,
+    // Input streams left out, as `a:<n>` / `s:<n>`
+    dropped: Vec<String>,
+}
+
+impl StreamPlan {
+    pub fn new(info: &MediaInfo) -> Self {
+        let of_type = |kind: &str| -> Vec<OutputStream> {
+            info.streams
+                .iter()
+                .filter(|s| s.is_type(kind))
+                .enumerate()
+                .map(|(i, s)| OutputStream::new(i, s))
+                .collect()
+        };
+        Self {
+            video: of_type("video")
+                .into_iter()
+                .filter(|s| !s.stream.has_disposition("attached_pic"))
+                .collect(),
+            audio: of_type("audio"),
+            subtitles: of_type("subtitle"),
+            ..Self::default()
+        }
+    }
+
+    pub fn audio(&self) -> &[OutputStream] {
+        &self.audio
+    }
+
+    pub fn subtitles(&self) -> &[OutputStream] {
+        &self.subtitles
+    }
+
+    pub fn audio_mut(&mut self) -> &mut [OutputStream] {
+        &mut self.audio
+    }
+
+    // Put an extra audio stream first, so players and the default flag see it first
+    pub fn insert_audio(&mut self, stream: OutputStream) {
+        self.audio.insert(0, stream);
+    }
+
+    pub fn set_audio_codec(&mut self, codec: &str) {
+        self.audio_codec = Some(codec.to_string());
+    }
+
+    // Leave out audio and subtitle streams in other languages. Untagged streams are kept
+    // (their language is unknown, not different), and so is all audio when none matches.
+    pub fn keep_languages(&mut self, languages: &[String]) {
+        if languages.is_empty() {
+            return;
+        }
+        let wanted = |s: &OutputStream| match s.stream.language() {
+            None | Some("und") => true,
+            Some(lang) => languages.iter().any(|l| same_language(l, lang)),
+        };
+        if self
+            .audio
+            .iter()
+            .any(|s| wanted(s) && s.stream.language().is_some())
+        {
+            for s in self.audio.iter().filter(|s| !wanted(s)) {
+                self.dropped.push(format!("a:{}", s.input));
+            }
+            self.audio.retain(wanted);
+        }
+        for s in self.subtitles.iter().filter(|s| !wanted(s)) {
+            self.dropped.push(format!("s:{}", s.input));
+        }
+        self.subtitles.retain(wanted);
+    }
+
+    // Flag the subtitle the policy picks as the only default
+    pub fn default_subtitle(&mut self, policy: &SubtitlePolicy) {
+        let streams: Vec<&Stream> = self.subtitles.iter().map(|s| &s.stream).collect();
+        let chosen = match policy.select(&streams) {
+            Selection::Keep => return,
+            Selection::Default(chosen) => chosen,
+        };
+        for (n, s) in self.subtitles.iter_mut().enumerate() {
+            s.disposition = Some(
+                if chosen == Some(n) {
+                    "+default"
+                } else {
+                    "-default"
+                }
+                .to_string(),
+            );
+        }
+    }
+
+    // Retime the text subtitles; bitmap ones keep their timing
+    pub fn retime_subtitles(&mut self, timing: &SubTiming) {
+        for s in self
+            .subtitles
+            .iter_mut()
+            .filter(|s| subtiming::is_text(&s.stream))
+        {
+            s.options.push(("-bsf".to_string(), timing.bsf()));
+        }
+    }
+
+    // Every stream of each type in output order, with its type's stream spec
+    fn outputs(&self) -> impl Iterator<Item = (&'static str, usize, &OutputStream)> {
+        let video = self.video.iter().enumerate().map(|(n, s)| ("v", n, s));
+        let audio = self.audio.iter().enumerate().map(|(n, s)| ("a", n, s));
+        let subtitles = self.subtitles.iter().enumerate().map(|(n, s)| ("s", n, s));
+        video.chain(audio).chain(subtitles)
+    }
+
+    // For `explain`: each output stream, the input stream it comes from and its codec
+    // (`vcodec`/`acodec` for streams the plan leaves to `-c:v`/`-c:a`), then those left out
+    pub fn describe(&self, vcodec: &str, acodec: &str) -> Vec<String> {
+        let mut lines = Vec::new();
+        for (spec, n, s) in self.outputs() {
+            let codec = match (&s.codec, spec) {
+                (Some(codec), _) => codec.as_str(),
+                (None, "v") => vcodec,
+                (None, "a") => self.audio_codec.as_deref().unwrap_or(acodec),
+                (None, _) => "copy",
+            };
+            let source = s.stream.codec_name.as_deref().unwrap_or("unknown");
+            let mut parts = vec![match codec {
+                "copy" => format!("copy of {}", source),
+                codec => format!("{} from {}", codec, source),
+            }];
+            parts.extend(s.stream.language().map(str::to_string));
+            parts.extend(
+                s.options
+                    .iter()
+                    .map(|(flag, value)| format!("{} {}", flag, value)),
+            );
+            parts.extend(
+                s.metadata
+                    .iter()
+                    .map(|(key, value)| format!("{}=\"{}\"", key, value)),
+            );
+            if let Some(disposition) = &s.disposition {
+                parts.push(format!("disposition {}", disposition));
+            }
+            let added = if s.derived { " (added)" } else { "" };
+            lines.push(format!(
+                "{}:{} <- input {}:{}{}: {}",
+                spec,
+                n,
+                spec,
+                s.input,
+                added,
+                parts.join(", ")
+            ));
+        }
+        if !self.dropped.is_empty() {
+            lines.push(format!("Left out: {}", self.dropped.join(", ")));
+        }
+        lines
+    }
+
+    // ffmpeg args for the plan; none for a file without streams
+    pub fn args(&self) -> Vec<String> {
+        if self.outputs().next().is_none() {
+            return Vec::new();
+        }
+        let mut args: Vec<String> = vec!["-map".into(), "0:V?".into()];
+        for s in self.audio.iter().filter(|s| s.derived) {
+            args.extend(["-map".to_string(), format!("0:a:{}", s.input)]);
+        }
+        args.extend(["-map", "0:a?", "-map", "0:s?"].map(String::from));
+        for spec in &self.dropped {
+            args.extend(["-map".to_string(), format!("-0:{}", spec)]);
+        }
+        if let Some(codec) = &self.audio_codec {
+            args.extend(["-c:a".to_string(), codec.clone()]);
+        }
+        for (spec, n, s) in self.outputs() {
+            s.map_metadata(spec, n, &mut args);
+        }
+        for (spec, n, s) in self.outputs() {
+            s.args(spec, n, &mut args);
+        }
+        args
+    }
+}
+
+// Apply `policies` to a probe, in a fixed order: languages decide which streams exist,
+// then the downmix is added ahead of them, then the subtitle default is picked and the
+// subtitles retimed
+pub fn plan(info: &MediaInfo, policies: &Policies) -> StreamPlan {
+    let mut plan = StreamPlan::new(info);
+    plan.keep_languages(policies.languages);
+    if policies.stereo_compat {
+        stereo::add_downmix(&mut plan);
+    }
+    if let Some(policy) = policies.subtitle_default {
+        plan.default_subtitle(policy);
+    }
+    if let Some(timing) = &policies.subtitle_timing {
+        plan.retime_subtitles(timing);
+    }
+    plan
+}
+
+// The input's dispositions as an ffmpeg flag list (`default+forced`), `0` for none
+pub fn disposition_flags(stream: &Stream, except: &[&str]) -> String {
+    let flags: Vec<&str> = stream
+        .disposition
+        .iter()
+        .filter(|(flag, set)| **set != 0 && !except.contains(&flag.as_str()))
+        .map(|(flag, _)| flag.as_str())
+        .collect();
+    if flags.is_empty() {
+        "0".to_string()
+    } else {
+        flags.join("+")
+    }
+}
+
+// Probe `input` and build the plan's args. A failed probe leaves the file to the global
+// `-map_metadata 0`; it is only worth a warning when policies were asked for.
+pub fn plan_args(input: &Path, cache: &AnalysisCache, policies: &Policies) -> Vec<String> {
+    match probe::probe_cached(input, cache) {
+        Ok(info) => plan(&info, policies).args(),
+        Err(_) if policies.is_empty() => Vec::new(),
+        Err(e) => {
+            eprintln!(
+                "  {} stream policies not applied to {}: {:#}",
+                Marker::Warning,
+                input.display(),
+                e
+            );
+            Vec::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `ffprobe -print_format json -show_format -show_streams` output for the common
+    // kinds of real-world files: an anime BD encode with fonts, a UHD Blu-ray remux, an
+    // iPhone clip with metadata tracks and an mp4 episode with cover art
+    const SAMPLES: [(&str, &str); 4] = [
+        (
+            "anime_bd",
+            include_str!("../tests/probe_samples/anime_bd.json"),
+        ),
+        (
+            "bluray_remux",
+            include_str!("../tests/probe_samples/bluray_remux.json"),
+        ),
+        ("iphone", include_str!("../tests/probe_samples/iphone.json")),
+        (
+            "episode_cover",
+            include_str!("../tests/probe_samples/episode_cover.json"),
+        ),
+    ];
+
+    fn sample(name: &str) -> MediaInfo {
+        let (_, json) = SAMPLES.iter().find(|(n, _)| *n == name).unwrap();
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn args(name: &str, languages: &[&str], policy: Option<&str>, stereo: bool) -> String {
+        let languages: Vec<String> = languages.iter().map(|l| l.to_string()).collect();
+        let policy: Option<SubtitlePolicy> = policy.map(|p| p.parse().unwrap());
+        let policies = Policies {
+            languages: &languages,
+            subtitle_default: policy.as_ref(),
+            stereo_compat: stereo,
+            subtitle_timing: None,
+        };
+        plan(&sample(name), &policies).args().join(" ")
+    }
+
+    #[test]
+    fn remux_keeps_english_with_downmix_and_forced_subtitle() {
+        assert_eq!(
+            args("bluray_remux", &["en"], Some("forced:eng, else none"), true),
+            "-map 0:V? -map 0:a:0 -map 0:a? -map 0:s? -map -0:a:2 -map -0:s:2 -map -0:s:3 \
+             -c:a copy -map_metadata:s:v:0 0:s:v:0 -map_metadata:s:a:0 0:s:a:0 \
+             -map_metadata:s:a:1 0:s:a:0 -map_metadata:s:a:2 0:s:a:1 -map_metadata:s:a:3 0:s:a:3 \
+             -map_metadata:s:s:0 0:s:s:0 -map_metadata:s:s:1 0:s:s:1 -disposition:v:0 default \
+             -c:a:0 aac -b:a:0 192k -ac:a:0 2 \
+             -filter:a:0 pan=stereo|FL<FC+0.6*FL+0.4*BL+0.3*SL|FR<FC+0.6*FR+0.4*BR+0.3*SR,dynaudnorm=f=250:g=15 \
+             -metadata:s:a:0 title=Stereo (dialogue boost) -disposition:a:0 default \
+             -disposition:a:1 original -disposition:a:2 0 -disposition:a:3 comment \
+             -disposition:s:0 -default -disposition:s:1 +default"
+        );
+    }
+
+    #[test]
+    fn streams_without_policies_keep_their_tags_and_dispositions() {
+        // Stereo only, so no downmix; `eng` picks the full subtitles over the signs
+        assert_eq!(
+            args("anime_bd", &[], Some("eng"), true),
+            "-map 0:V? -map 0:a? -map 0:s? -map_metadata:s:v:0 0:s:v:0 \
+             -map_metadata:s:a:0 0:s:a:0 -map_metadata:s:a:1 0:s:a:1 \
+             -map_metadata:s:s:0 0:s:s:0 -map_metadata:s:s:1 0:s:s:1 -disposition:v:0 default \
+             -disposition:a:0 default+original -disposition:a:1 dub \
+             -disposition:s:0 +default -disposition:s:1 -default"
+        );
+        // iPhone audio is tagged `und`, so a language filter keeps it
+        assert_eq!(
+            args("iphone", &["fre"], None, true),
+            args("iphone", &[], None, false)
+        );
+        // Cover art is not a video stream of the output
+        let cover = args("episode_cover", &[], None, false);
+        assert!(cover.contains("-map_metadata:s:v:0 0:s:v:0 -map_metadata:s:a:0"));
+        assert!(!cover.contains("s:v:1"));
+        // No audio in the wanted language: all audio stays, other subtitles go
+        assert!(
+            args("anime_bd", &["de"], None, false)
+                .starts_with("-map 0:V? -map 0:a? -map 0:s? -map -0:s:0 -map -0:s:1 -map_metadata")
+        );
+    }
+
+    #[test]
+    fn every_sample_plans_the_same_way_twice() {
+        for (name, _) in SAMPLES {
+            let info = sample(name);
+            assert!(!info.streams.is_empty(), "{}", name);
+            let policy: SubtitlePolicy = "forced, eng, else first".parse().unwrap();
+            let languages = vec!["eng".to_string(), "jpn".to_string()];
+            let policies = Policies {
+                languages: &languages,
+                subtitle_default: Some(&policy),
+                stereo_compat: true,
+                subtitle_timing: None,
+            };
+            assert_eq!(
+                plan(&info, &policies).args(),
+                plan(&info, &policies).args(),
+                "{}",
+                name
+            );
+        }
+    }
+}
@@ -0,0 +1,307 @@
+// file: src/style.rs
+// version: 0.1.0
+// guid: 15e0c4fb-b3d1-44eb-ba22-7a0c49f5e689
+
+//! Terminal styling: colored status markers (`WARNING:`, `ERROR:`, `[DRY RUN]`, `OK`,
+//! `SKIP`, `PASS`, `FAIL`) and column-aligned tables for listings and summaries.
+//!
+//! `--color auto` (the default) colors only when stdout and stderr are both terminals,
+//! `NO_COLOR` is unset and `TERM` is not `dumb`; `--no-fancy` turns it off as well.
+//! `always` and `never` decide regardless. Colors come from the config's `[theme]`
+//! table. Tables pad cells by their visible width, so colored cells line up too.
+
+use std::env;
+use std::fmt;
+use std::io::{self, IsTerminal};
+use std::sync::OnceLock;
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+static THEME: OnceLock<Option<Theme>> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ColorChoice {
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+// What a piece of text is, which picks its color from the theme
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Ok,
+    Warning,
+    Error,
+    Skip,
+    DryRun,
+    Heading,
+}
+
+/// Colors per role (`[theme]` table): a color name (`red`, `bright-blue`, ...), optionally
+/// with `bold`, `dim`, `italic` or `underline`, e.g. `"bold red"`; `"none"` for plain
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Theme {
+    pub ok: Color,
+    pub warning: Color,
+    pub error: Color,
+    pub skip: Color,
+    pub dry_run: Color,
+    pub heading: Color,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        let color = |name: &str| Color::try_from(name.to_string()).expect("a known color");
+        Self {
+            ok: color("green"),
+            warning: color("yellow"),
+            error: color("bold red"),
+            skip: color("dim"),
+            dry_run: color("cyan"),
+            heading: color("bold"),
+        }
+    }
+}
+
+impl Theme {
+    fn color(&self, role: Role) -> &Color {
+        match role {
+            Role::Ok => &self.ok,
+            Role::Warning => &self.warning,
+            Role::Error => &self.error,
+            Role::Skip => &self.skip,
+            Role::DryRun => &self.dry_run,
+            Role::Heading => &self.heading,
+        }
+    }
+}
+
+// The SGR parameters of a color, e.g. `1;31`; empty for plain text
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Color(String);
+
+impl TryFrom<String> for Color {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, String> {
+        let mut codes = Vec::new();
+        for word in text.split_whitespace() {
+            let code = match word {
+                "none" => continue,
+                "bold" => 1,
+                "dim" => 2,
+                "italic" => 3,
+                "underline" => 4,
+                word => {
+                    let (bright, name) = match word.strip_prefix("bright-") {
+                        Some(name) => (true, name),
+                        None => (false, word),
+                    };
+                    let Some(n) = COLORS.iter().position(|c| *c == name) else {
+                        return Err(format!(
+                            "unknown color '{}' (use {}, bright-<color>, bold, dim, italic, \
+                             underline or none)",
+                            word,
+                            COLORS.join(", ")
+                        ));
+                    };
+                    n as u8 + if bright { 90 } else { 30 }
+                }
+            };
+            codes.push(code.to_string());
+        }
+        Ok(Self(codes.join(";")))
+    }
+}
+
+const COLORS: [&str; 8] = [
+    "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+];
+
+// Decide once per run whether to color, and with what
+pub fn init(choice: ColorChoice, no_fancy: bool, theme: Theme) {
+    let color = match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => {
+            !no_fancy
+                && io::stdout().is_terminal()
+                && io::stderr().is_terminal()
+                && env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+                && env::var("TERM").map_or(true, |term| term != "dumb")
+        }
+    };
+    let _ = THEME.set(color.then_some(theme));
+}
+
+// `text` in the role's color, or as it is when not coloring
+pub fn paint(role: Role, text: &str) -> String {
+    match THEME.get().and_then(Option::as_ref) {
+        Some(theme) => with_color(theme.color(role), text),
+        None => text.to_string(),
+    }
+}
+
+fn with_color(color: &Color, text: &str) -> String {
+    match color.0.as_str() {
+        "" => text.to_string(),
+        codes => format!("\x1b[{}m{}\x1b[0m", codes, text),
+    }
+}
+
+// Status markers; `{:6}` pads by the visible text
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Marker {
+    Ok,
+    Skip,
+    Pass,
+    Fail,
+    Warning,
+    Error,
+    DryRun,
+}
+
+impl Marker {
+    fn text(self) -> &'static str {
+        match self {
+            Marker::Ok => "OK",
+            Marker::Skip => "SKIP",
+            Marker::Pass => "PASS",
+            Marker::Fail => "FAIL",
+            Marker::Warning => "WARNING:",
+            Marker::Error => "ERROR:",
+            Marker::DryRun => "[DRY RUN]",
+        }
+    }
+
+    fn role(self) -> Role {
+        match self {
+            Marker::Ok | Marker::Pass => Role::Ok,
+            Marker::Skip => Role::Skip,
+            Marker::Fail | Marker::Error => Role::Error,
+            Marker::Warning => Role::Warning,
+            Marker::DryRun => Role::DryRun,
+        }
+    }
+}
+
+impl fmt::Display for Marker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = self.text();
+        let pad = f.width().unwrap_or(0).saturating_sub(text.len());
+        write!(f, "{}{}", paint(self.role(), text), " ".repeat(pad))
+    }
+}
+
+// Rows of cells printed in aligned columns under a header, indented two spaces.
+// Columns are left-aligned unless marked with `right` (counts and sizes).
+pub struct Table {
+    headers: Vec<String>,
+    right: Vec<bool>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn new(headers: &[&str]) -> Self {
+        Self {
+            headers: headers.iter().map(|h| h.to_string()).collect(),
+            right: vec![false; headers.len()],
+            rows: Vec::new(),
+        }
+    }
+
+    pub fn right(mut self, columns: &[usize]) -> Self {
+        for &column in columns {
+            self.right[column] = true;
+        }
+        self
+    }
+
+    pub fn row(&mut self, cells: Vec<String>) {
+        self.rows.push(cells);
+    }
+}
+
+impl fmt::Display for Table {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut widths: Vec<usize> = self.headers.iter().map(|h| visible_width(h)).collect();
+        for row in &self.rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(visible_width(cell));
+            }
+        }
+        let headers: Vec<String> = self
+            .headers
+            .iter()
+            .map(|h| paint(Role::Heading, h))
+            .collect();
+        for row in std::iter::once(&headers).chain(&self.rows) {
+            let mut line = String::from(" ");
+            for (n, cell) in row.iter().enumerate() {
+                let pad = " ".repeat(widths[n].saturating_sub(visible_width(cell)));
+                line.push(' ');
+                match self.right[n] {
+                    true => line.push_str(&format!("{}{}", pad, cell)),
+                    false if n + 1 == row.len() => line.push_str(cell),
+                    false => line.push_str(&format!("{}{}", cell, pad)),
+                }
+                line.push(' ');
+            }
+            writeln!(f, "{}", line.trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+// Characters on screen, leaving out color escapes
+fn visible_width(text: &str) -> usize {
+    let mut width = 0;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            chars.by_ref().find(|c| *c == 'm');
+        } else {
+            width += 1;
+        }
+    }
+    width
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tables_align_by_visible_width() {
+        let mut table = Table::new(&["Show", "Files", "Seasons"]).right(&[1]);
+        table.row(vec!["Show A".into(), "12".into(), "S01: 12".into()]);
+        let ok = with_color(&Color::try_from("green".to_string()).unwrap(), "OK");
+        table.row(vec![ok.clone(), "3".into(), String::new()]);
+        assert_eq!(
+            table.to_string(),
+            format!(
+                "  Show    Files  Seasons\n  Show A     12  S01: 12\n  {}          3\n",
+                ok
+            )
+        );
+        assert_eq!(visible_width(&ok), 2);
+    }
+
+    #[test]
+    fn colors_parse_from_names() {
+        let color = |text: &str| Color::try_from(text.to_string()).map(|c| c.0);
+        assert_eq!(color("bold red").unwrap(), "1;31");
+        assert_eq!(color("bright-blue underline").unwrap(), "94;4");
+        assert_eq!(color("none").unwrap(), "");
+        assert!(
+            color("mauve")
+                .unwrap_err()
+                .contains("unknown color 'mauve'")
+        );
+        assert_eq!(format!("[{:6}]", Marker::Ok), "[OK    ]");
+    }
+}
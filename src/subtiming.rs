@@ -0,0 +1,178 @@
+// file: src/subtiming.rs
+// version: 0.1.0
+// guid: 44ea3308-a9aa-4386-bee5-f297009a2056
+
+//! Subtitle retiming (`--sub-shift 2.5s`, `--sub-fps-from 25 --sub-fps-to 23.976`) for
+//! subtitles made for another release of the same video.
+//!
+//! A shift moves every cue by a fixed time. A frame-rate change fixes subtitles that
+//! drift further off as the film goes on, as ones timed for a PAL (25 fps) release do
+//! against the 23.976 fps original: cue times and durations are stretched by
+//! `from / to`, then shifted. Text subtitles (SRT, ASS, WebVTT, mov_text) are retimed
+//! with ffmpeg's `setts` bitstream filter on their output streams; bitmap subtitles
+//! (PGS, VobSub) are left as they are. A negative shift should not move cues before
+//! the start of the file, as muxers reject negative timestamps or shift every stream.
+
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{Result, bail};
+
+use crate::probe::Stream;
+
+const TEXT_CODECS: [&str; 12] = [
+    "subrip",
+    "srt",
+    "ass",
+    "ssa",
+    "webvtt",
+    "mov_text",
+    "text",
+    "microdvd",
+    "subviewer",
+    "sami",
+    "mpl2",
+    "ttml",
+];
+
+// A time to move cues by: seconds, with an optional `s` or `ms` unit and a sign
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SubShift(f64);
+
+impl FromStr for SubShift {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let (number, per_second) = match value.strip_suffix("ms") {
+            Some(number) => (number, 1000.0),
+            None => (value.strip_suffix('s').unwrap_or(value), 1.0),
+        };
+        match number.parse::<f64>() {
+            Ok(seconds) if seconds.is_finite() => Ok(SubShift(seconds / per_second)),
+            _ => bail!(
+                "invalid subtitle shift '{}': expected a time such as 2.5s, -800ms or 1.2",
+                value
+            ),
+        }
+    }
+}
+
+// A frame rate, as a number (23.976) or a fraction (24000/1001)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fps(f64);
+
+impl FromStr for Fps {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let rate = match value.split_once('/') {
+            Some((num, den)) => num
+                .parse::<f64>()
+                .ok()
+                .zip(den.parse::<f64>().ok())
+                .map(|(num, den)| num / den),
+            None => value.parse::<f64>().ok(),
+        };
+        match rate {
+            Some(rate) if rate.is_finite() && rate > 0.0 => Ok(Fps(rate)),
+            _ => bail!(
+                "invalid frame rate '{}': expected e.g. 25, 23.976 or 24000/1001",
+                value
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Fps {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SubTiming {
+    // Seconds added after the rate change
+    shift: f64,
+    // The rate the subtitles were timed for, and the rate of the video
+    fps: Option<(Fps, Fps)>,
+}
+
+impl SubTiming {
+    // None when nothing would change
+    pub fn new(
+        shift: Option<SubShift>,
+        fps_from: Option<Fps>,
+        fps_to: Option<Fps>,
+    ) -> Option<Self> {
+        let timing = Self {
+            shift: shift.map_or(0.0, |s| s.0),
+            fps: fps_from.zip(fps_to).filter(|(from, to)| from != to),
+        };
+        (timing.shift != 0.0 || timing.fps.is_some()).then_some(timing)
+    }
+
+    // `settings_hash` input
+    pub fn option(&self) -> String {
+        match self.fps {
+            Some((from, to)) => format!("sub-timing={}s:{}fps>{}fps", self.shift, from, to),
+            None => format!("sub-timing={}s", self.shift),
+        }
+    }
+
+    // The bitstream filter for one text subtitle stream (`-bsf:s:<n>`)
+    pub fn bsf(&self) -> String {
+        let mut ts = "TS".to_string();
+        let mut duration = None;
+        if let Some((from, to)) = self.fps {
+            ts = format!("TS*{}/{}", from, to);
+            duration = Some(format!("DURATION*{}/{}", from, to));
+        }
+        if self.shift > 0.0 {
+            ts.push_str(&format!("+{}/TB", self.shift));
+        } else if self.shift < 0.0 {
+            ts.push_str(&format!("-{}/TB", -self.shift));
+        }
+        match duration {
+            Some(duration) => format!("setts=ts={}:duration={}", ts, duration),
+            None => format!("setts=ts={}", ts),
+        }
+    }
+}
+
+// Whether `stream` is a subtitle whose cues are text, which `setts` can retime
+pub fn is_text(stream: &Stream) -> bool {
+    stream
+        .codec_name
+        .as_deref()
+        .is_some_and(|codec| TEXT_CODECS.contains(&codec))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timing(shift: &str, fps: Option<(&str, &str)>) -> Option<SubTiming> {
+        SubTiming::new(
+            Some(shift.parse().unwrap()),
+            fps.map(|(from, _)| from.parse().unwrap()),
+            fps.map(|(_, to)| to.parse().unwrap()),
+        )
+    }
+
+    #[test]
+    fn shifts_and_rate_changes_become_one_setts_filter() {
+        assert_eq!(timing("2.5s", None).unwrap().bsf(), "setts=ts=TS+2.5/TB");
+        assert_eq!(
+            timing("-800ms", Some(("25", "23.976"))).unwrap().bsf(),
+            "setts=ts=TS*25/23.976-0.8/TB:duration=DURATION*25/23.976"
+        );
+        assert_eq!(
+            timing("0", Some(("24000/1001", "25"))).unwrap().option(),
+            "sub-timing=0s:23.976023976023978fps>25fps"
+        );
+        // Nothing to change
+        assert_eq!(timing("0s", Some(("25", "25"))), None);
+        assert!("2.5 s".parse::<SubShift>().is_err());
+        assert!("0/0".parse::<Fps>().is_err());
+    }
+}
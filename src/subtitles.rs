@@ -0,0 +1,244 @@
+// file: src/subtitles.rs
+// version: 0.2.1
+// guid: a3449e9e-6469-43fc-b7a4-04e83453a31d
+
+//! Per-file default subtitle selection.
+//!
+//! A policy is a comma-separated list of alternatives tried in order, e.g.
+//! `"forced:eng, else none"`. Alternatives:
+//! - `forced` / `forced:<lang>`: a subtitle flagged forced (optionally in that language)
+//! - `<lang>` / `lang:<lang>`: any subtitle in that language
+//! - `first`: the first subtitle stream
+//! - `none`: no subtitle is marked default
+//! - `keep`: leave the source dispositions untouched
+//!
+//! A leading `else` is allowed for readability.
+
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{Result, anyhow, bail};
+use serde::Deserialize;
+
+use crate::probe::Stream;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct SubtitlePolicy {
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Rule {
+    Forced(Option<String>),
+    Language(String),
+    First,
+    None,
+    Keep,
+}
+
+// Outcome of evaluating a policy against one file's subtitle streams
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Selection {
+    // Leave dispositions as they are in the source
+    Keep,
+    // Mark this subtitle (relative index) default, or none of them
+    Default(Option<usize>),
+}
+
+impl FromStr for SubtitlePolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let mut rules = Vec::new();
+        for raw in value.split(',') {
+            let token = raw.trim();
+            let token = token.strip_prefix("else ").unwrap_or(token).trim();
+            if let Some(last) = rules.last() {
+                if matches!(last, Rule::None | Rule::Keep) {
+                    bail!(
+                        "'{}' can never match: 'none'/'keep' must be the last alternative",
+                        token
+                    );
+                }
+            }
+            rules.push(parse_rule(token)?);
+        }
+        Ok(Self { rules })
+    }
+}
+
+impl TryFrom<String> for SubtitlePolicy {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+impl fmt::Display for SubtitlePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts: Vec<String> = self
+            .rules
+            .iter()
+            .map(|rule| match rule {
+                Rule::Forced(None) => "forced".to_string(),
+                Rule::Forced(Some(lang)) => format!("forced:{}", lang),
+                Rule::Language(lang) => lang.clone(),
+                Rule::First => "first".to_string(),
+                Rule::None => "none".to_string(),
+                Rule::Keep => "keep".to_string(),
+            })
+            .collect();
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+fn parse_rule(token: &str) -> Result<Rule> {
+    let lower = token.to_ascii_lowercase();
+    let rule = match lower.as_str() {
+        "" => bail!("empty alternative in subtitle policy"),
+        "none" => Rule::None,
+        "keep" => Rule::Keep,
+        "first" => Rule::First,
+        "forced" => Rule::Forced(None),
+        other => {
+            if let Some(lang) = other.strip_prefix("forced:") {
+                Rule::Forced(Some(parse_language(lang)?))
+            } else if let Some(lang) = other.strip_prefix("lang:") {
+                Rule::Language(parse_language(lang)?)
+            } else {
+                Rule::Language(parse_language(other).map_err(|_| {
+                    anyhow!(
+                        "unknown subtitle policy alternative '{}' (expected forced[:lang], <lang>, first, none or keep)",
+                        token
+                    )
+                })?)
+            }
+        }
+    };
+    Ok(rule)
+}
+
+fn parse_language(lang: &str) -> Result<String> {
+    let lang = lang.trim();
+    if !(2..=3).contains(&lang.len()) || !lang.chars().all(|c| c.is_ascii_alphabetic()) {
+        bail!("'{}' is not an ISO 639 language code", lang);
+    }
+    Ok(lang.to_string())
+}
+
+// ISO 639-1 codes and the 639-2 bibliographic/terminology variants seen in real files
+const LANGUAGE_ALIASES: &[&[&str]] = &[
+    &["en", "eng"],
+    &["fr", "fre", "fra"],
+    &["de", "ger", "deu"],
+    &["es", "spa"],
+    &["it", "ita"],
+    &["pt", "por"],
+    &["nl", "dut", "nld"],
+    &["ru", "rus"],
+    &["ja", "jpn"],
+    &["zh", "chi", "zho"],
+    &["ko", "kor"],
+    &["sv", "swe"],
+    &["pl", "pol"],
+];
+
+pub fn same_language(wanted: &str, actual: &str) -> bool {
+    if wanted.eq_ignore_ascii_case(actual) {
+        return true;
+    }
+    LANGUAGE_ALIASES.iter().any(|group| {
+        group.iter().any(|c| c.eq_ignore_ascii_case(wanted))
+            && group.iter().any(|c| c.eq_ignore_ascii_case(actual))
+    })
+}
+
+impl SubtitlePolicy {
+    pub fn select(&self, subtitles: &[&Stream]) -> Selection {
+        for rule in &self.rules {
+            let found = match rule {
+                Rule::Keep => return Selection::Keep,
+                Rule::None => return Selection::Default(None),
+                Rule::First => (!subtitles.is_empty()).then_some(0),
+                Rule::Forced(lang) => subtitles.iter().position(|s| {
+                    s.has_disposition("forced")
+                        && lang
+                            .as_deref()
+                            .is_none_or(|l| s.language().is_some_and(|a| same_language(l, a)))
+                }),
+                Rule::Language(lang) => subtitles
+                    .iter()
+                    .position(|s| s.language().is_some_and(|a| same_language(lang, a))),
+            };
+            if found.is_some() {
+                return Selection::Default(found);
+            }
+        }
+        // Nothing matched and no explicit fallback: behave like `none`
+        Selection::Default(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probe::MediaInfo;
+    use crate::streams::StreamPlan;
+
+    fn sub(lang: &str, forced: bool) -> Stream {
+        let mut stream = Stream {
+            codec_type: Some("subtitle".to_string()),
+            ..Default::default()
+        };
+        stream.tags.insert("language".to_string(), lang.to_string());
+        stream
+            .disposition
+            .insert("forced".to_string(), i64::from(forced));
+        stream
+    }
+
+    #[test]
+    fn forced_english_else_none() {
+        let policy: SubtitlePolicy = "forced:eng, else none".parse().unwrap();
+        let full = sub("eng", false);
+        let forced = sub("en", true);
+        let french = sub("fre", true);
+
+        assert_eq!(
+            policy.select(&[&full, &french, &forced]),
+            Selection::Default(Some(2))
+        );
+        assert_eq!(policy.select(&[&full, &french]), Selection::Default(None));
+    }
+
+    #[test]
+    fn args_flag_only_the_chosen_stream() {
+        let policy: SubtitlePolicy = "forced:eng, else none".parse().unwrap();
+        let info = MediaInfo {
+            streams: vec![sub("eng", false), sub("eng", true)],
+            ..Default::default()
+        };
+        let mut plan = StreamPlan::new(&info);
+        plan.default_subtitle(&policy);
+        let args = plan.args();
+        let tail: Vec<&str> = args.iter().skip(10).map(String::as_str).collect();
+        assert_eq!(
+            tail,
+            [
+                "-disposition:s:0",
+                "-default",
+                "-disposition:s:1",
+                "+default"
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_unreachable_and_unknown_alternatives() {
+        assert!("none, eng".parse::<SubtitlePolicy>().is_err());
+        assert!("forced:english".parse::<SubtitlePolicy>().is_err());
+        assert!("".parse::<SubtitlePolicy>().is_err());
+    }
+}
@@ -0,0 +1,225 @@
+// file: src/synth.rs
+// version: 0.3.0
+// guid: 0cb9381e-2cc8-4ca7-8e67-ae1b916744d9
+
+//! Synthetic media from ffmpeg's own sources (testsrc, smptebars, sine), so tests and
+//! environment checks need no binary assets. `generate-fixtures` writes the whole set,
+//! edge cases included, for the test suite and for reproducing bug reports.
+//!
+//! Fixtures are encoded with ffmpeg's built-in encoders (mpeg4, aac) so generating them
+//! works with any ffmpeg build; the encoders under test are only needed afterwards.
+
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+
+use crate::args::FfmpegArgs;
+use crate::tools::{self, Tool};
+
+pub struct Fixture {
+    // File name, including the container extension
+    pub name: &'static str,
+    pub about: &'static str,
+    // Tagged as the file's title so metadata preservation can be checked
+    pub title: &'static str,
+    // Part of the `selftest` run; edge cases are only generated
+    pub selftest: bool,
+    seconds: u32,
+    // lavfi source graphs, one input each
+    sources: &'static [&'static str],
+    // Also mux a short English SRT subtitle track
+    subtitles: bool,
+    args: &'static [&'static str],
+}
+
+pub const FIXTURES: &[Fixture] = &[
+    Fixture {
+        name: "color_mpeg4_aac.mp4",
+        about: "test pattern with a sine tone",
+        title: "Synthetic Color",
+        selftest: true,
+        seconds: 2,
+        sources: &["testsrc=size=320x240:rate=30", "sine=frequency=1000"],
+        subtitles: false,
+        args: &["-c:v", "mpeg4", "-q:v", "5", "-c:a", "aac"],
+    },
+    Fixture {
+        name: "bars_subs_mpeg4_aac.mkv",
+        about: "SMPTE bars with an English subtitle track",
+        title: "Synthetic Bars",
+        selftest: true,
+        seconds: 2,
+        sources: &["smptebars=size=320x240:rate=25", "sine=frequency=500"],
+        subtitles: true,
+        args: &["-c:v", "mpeg4", "-q:v", "5", "-c:a", "aac", "-c:s", "srt"],
+    },
+    Fixture {
+        name: "sine_aac.m4a",
+        about: "audio only",
+        title: "Synthetic Sine",
+        selftest: true,
+        seconds: 2,
+        sources: &["sine=frequency=440"],
+        subtitles: false,
+        args: &["-c:a", "aac", "-b:a", "96k"],
+    },
+    Fixture {
+        name: "hdr10_flags_mpeg4.mkv",
+        about: "BT.2020/PQ colour tags on 8-bit video (flags only, no real HDR)",
+        title: "Synthetic HDR Flags",
+        selftest: false,
+        seconds: 2,
+        sources: &["testsrc2=size=320x240:rate=24"],
+        subtitles: false,
+        args: &[
+            "-c:v",
+            "mpeg4",
+            "-q:v",
+            "5",
+            "-color_primaries",
+            "bt2020",
+            "-color_trc",
+            "smpte2084",
+            "-colorspace",
+            "bt2020nc",
+            "-color_range",
+            "tv",
+        ],
+    },
+    Fixture {
+        name: "vfr_mpeg4_aac.mkv",
+        about: "variable frame rate: 30 fps for a second, then 10 fps",
+        title: "Synthetic VFR",
+        selftest: false,
+        seconds: 3,
+        sources: &["testsrc=size=320x240:rate=30", "sine=frequency=700"],
+        subtitles: false,
+        args: &[
+            "-vf",
+            "select='lt(t,1)+not(mod(n,3))'",
+            "-fps_mode",
+            "vfr",
+            "-c:v",
+            "mpeg4",
+            "-q:v",
+            "5",
+            "-c:a",
+            "aac",
+        ],
+    },
+    Fixture {
+        name: "multi_audio_51_stereo_subs.mkv",
+        about: "5.1 English track, stereo French commentary and English subtitles",
+        title: "Synthetic Multi Audio",
+        selftest: false,
+        seconds: 2,
+        sources: &[
+            "smptehdbars=size=320x180:rate=25",
+            "aevalsrc=sin(440*2*PI*t)|sin(550*2*PI*t)|sin(660*2*PI*t)|0.2*sin(55*2*PI*t)\
+             |sin(330*2*PI*t)|sin(370*2*PI*t):c=5.1:s=48000",
+            "sine=frequency=300:sample_rate=48000",
+        ],
+        subtitles: true,
+        args: &[
+            "-ac:a:1",
+            "2",
+            "-metadata:s:a:0",
+            "language=eng",
+            "-metadata:s:a:1",
+            "language=fra",
+            "-metadata:s:a:1",
+            "title=Commentary",
+            "-c:v",
+            "mpeg4",
+            "-q:v",
+            "5",
+            "-c:a",
+            "aac",
+            "-c:s",
+            "srt",
+        ],
+    },
+    Fixture {
+        name: "mov_text_subs.mp4",
+        about: "mp4 with mov_text subtitles",
+        title: "Synthetic mov_text",
+        selftest: false,
+        seconds: 2,
+        sources: &["testsrc=size=320x240:rate=30", "sine=frequency=900"],
+        subtitles: true,
+        args: &[
+            "-c:v", "mpeg4", "-q:v", "5", "-c:a", "aac", "-c:s", "mov_text",
+        ],
+    },
+];
+
+const SRT: &str = "1\n00:00:00,000 --> 00:00:01,000\nSynthetic subtitle one\n\n\
+                   2\n00:00:01,000 --> 00:00:02,000\nSynthetic subtitle two\n";
+
+impl Fixture {
+    pub fn seconds(&self) -> f64 {
+        f64::from(self.seconds)
+    }
+
+    fn ffmpeg_args(&self, srt: &Path, output: &Path) -> Vec<OsString> {
+        let mut args = FfmpegArgs::new(output);
+        args.global(["-v", "error"]);
+        for source in self.sources {
+            args.input_with(["-f", "lavfi"], source);
+        }
+        if self.subtitles {
+            args.input(srt).option("-metadata:s:s:0", "language=eng");
+        }
+        // Every input is mapped explicitly so the subtitle file is not dropped
+        for i in 0..self.sources.len() + usize::from(self.subtitles) {
+            args.map(&format!("{}:0", i));
+        }
+        args.option("-t", self.seconds.to_string())
+            .option("-metadata", format!("title={}", self.title))
+            .extra(self.args);
+        args.build()
+    }
+
+    pub fn generate(&self, dir: &Path) -> Result<PathBuf> {
+        fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+        let output = dir.join(self.name);
+        let srt = dir.join(format!(".{}.srt", self.name));
+        if self.subtitles {
+            fs::write(&srt, SRT).with_context(|| format!("failed to write {}", srt.display()))?;
+        }
+        let args = self.ffmpeg_args(&srt, &output);
+        let result = tools::output(Tool::Ffmpeg, &args);
+        let _ = fs::remove_file(&srt);
+        let result = result?;
+        if !result.status.success() {
+            bail!(
+                "ffmpeg could not generate {}: {}",
+                self.name,
+                String::from_utf8_lossy(&result.stderr).trim()
+            );
+        }
+        Ok(output)
+    }
+}
+
+// `generate-fixtures`: write every fixture into `out`
+pub fn run(out: &Path) -> Result<()> {
+    let width = FIXTURES.iter().map(|f| f.name.len()).max().unwrap_or(0);
+    for fixture in FIXTURES {
+        let path = fixture.generate(out)?;
+        println!(
+            "{:width$}  {}",
+            path.file_name().unwrap_or_default().to_string_lossy(),
+            fixture.about,
+            width = width
+        );
+    }
+    println!(
+        "\nGenerated {} fixtures in {}",
+        FIXTURES.len(),
+        out.display()
+    );
+    Ok(())
+}
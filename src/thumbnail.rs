@@ -0,0 +1,492 @@
+// file: src/thumbnail.rs
+// version: 0.2.1
+// guid: 61c1d93d-6da4-425c-a14e-dd54e61e5c64
+
+//! Cover images for outputs (`--embed-thumbnail`), so media servers and file browsers
+//! show a picture rather than a black first frame.
+//!
+//! `auto` uses a poster next to the input (`Movie-poster.jpg`, `Movie.jpg`, then
+//! `poster`, `cover` or `folder` images in its directory) and otherwise grabs a frame
+//! 10% of the way in, past logos and fades. `at=00:05:00` grabs the frame at that time
+//! and `from=cover.jpg` uses the given JPEG or PNG.
+//!
+//! Each container stores covers its own way: mp4, m4v and mov take an extra video stream
+//! marked `attached_pic`, Matroska an attachment named `cover.jpg` (or `cover.png`).
+
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::{Context, Result, bail};
+
+use crate::args::FfmpegArgs;
+use crate::cache::AnalysisCache;
+use crate::probe::{self, MediaInfo};
+use crate::stats::{clock, parse_clock};
+use crate::tools::{self, Tool};
+
+const IMAGE_EXTS: [&str; 3] = ["jpg", "jpeg", "png"];
+// Directory-wide posters, in the order Kodi and Jellyfin look for them
+const POSTER_NAMES: [&str; 3] = ["poster", "cover", "folder"];
+// Where `auto` grabs a frame, as a fraction of the duration
+const AUTO_POSITION: f64 = 0.1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Thumbnail {
+    Auto,
+    At(f64),
+    From(PathBuf),
+}
+
+impl FromStr for Thumbnail {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        if value.eq_ignore_ascii_case("auto") {
+            return Ok(Thumbnail::Auto);
+        }
+        if let Some(time) = value.strip_prefix("at=") {
+            return match parse_clock(time) {
+                Some(seconds) if seconds.is_finite() => Ok(Thumbnail::At(seconds)),
+                _ => bail!(
+                    "invalid thumbnail time '{}': expected HH:MM:SS or seconds",
+                    time
+                ),
+            };
+        }
+        match value.strip_prefix("from=") {
+            Some(path) if !path.is_empty() => Ok(Thumbnail::From(PathBuf::from(path))),
+            _ => bail!(
+                "invalid thumbnail '{}': expected auto, at=HH:MM:SS or from=IMAGE",
+                value
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Thumbnail {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Thumbnail::Auto => write!(f, "auto"),
+            Thumbnail::At(seconds) => write!(f, "at={}", clock(*seconds)),
+            Thumbnail::From(path) => write!(f, "from={}", path.display()),
+        }
+    }
+}
+
+// How the container stores its cover
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Container {
+    Mp4,
+    Matroska,
+}
+
+impl Container {
+    fn of(output: &Path) -> Result<Self> {
+        let ext = output
+            .extension()
+            .map(|e| e.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default();
+        match ext.as_str() {
+            "mp4" | "m4v" | "mov" => Ok(Container::Mp4),
+            "mkv" | "mka" => Ok(Container::Matroska),
+            _ => bail!(
+                "--embed-thumbnail needs an mp4, m4v, mov or mkv output, not '{}'",
+                output.display()
+            ),
+        }
+    }
+}
+
+// The cover for one job: an existing image, or a frame grabbed into a temporary file
+// that is removed on drop
+pub struct Cover {
+    image: PathBuf,
+    // Input and time of a frame still to grab into `image`
+    grab: Option<(PathBuf, f64)>,
+    // An image whose path is not UTF-8, still to copy into `image`: ffmpeg's extra args
+    // are strings, and a lossy path would name some other file
+    copy: Option<PathBuf>,
+    container: Container,
+    // Streams of the input, to number the cover after the ones already mapped
+    info: MediaInfo,
+}
+
+impl Cover {
+    pub fn new(
+        thumbnail: &Thumbnail,
+        input: &Path,
+        output: &Path,
+        cache: &AnalysisCache,
+    ) -> Result<Self> {
+        let container = Container::of(output)?;
+        let info = probe::probe_cached(input, cache)
+            .with_context(|| format!("cannot place a cover in {}", output.display()))?;
+        let at = match thumbnail {
+            Thumbnail::From(image) => {
+                mime_type(image)?;
+                if !image.is_file() {
+                    bail!("thumbnail image {} does not exist", image.display());
+                }
+                return Ok(Self::existing(image.clone(), container, info));
+            }
+            Thumbnail::Auto => {
+                if let Some(image) = find_poster(input) {
+                    return Ok(Self::existing(image, container, info));
+                }
+                info.format.duration_seconds().unwrap_or(0.0) * AUTO_POSITION
+            }
+            Thumbnail::At(seconds) => *seconds,
+        };
+        Ok(Self {
+            image: temp_image("jpg"),
+            grab: Some((input.to_path_buf(), at)),
+            copy: None,
+            container,
+            info,
+        })
+    }
+
+    fn existing(image: PathBuf, container: Container, info: MediaInfo) -> Self {
+        let (image, copy) = match image.to_str() {
+            Some(_) => (image, None),
+            None => {
+                let ext = image.extension().map(|e| e.to_string_lossy().into_owned());
+                (temp_image(&ext.unwrap_or_default()), Some(image))
+            }
+        };
+        Self {
+            image,
+            grab: None,
+            copy,
+            container,
+            info,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match (&self.grab, &self.copy) {
+            (Some((_, at)), _) => format!("the frame at {}", clock(*at)),
+            (None, Some(source)) => source.display().to_string(),
+            (None, None) => self.image.display().to_string(),
+        }
+    }
+
+    // Grab the frame or copy the image, if the cover needs either; a no-op otherwise
+    pub fn prepare(&self) -> Result<()> {
+        if let Some(source) = &self.copy {
+            fs::copy(source, &self.image).with_context(|| {
+                format!(
+                    "failed to copy thumbnail {} to {}",
+                    source.display(),
+                    self.image.display()
+                )
+            })?;
+            return Ok(());
+        }
+        let Some((input, at)) = &self.grab else {
+            return Ok(());
+        };
+        let args = FfmpegArgs::new(&self.image)
+            .global(["-v", "error"])
+            .input_with(["-ss".to_string(), format!("{:.3}", at)], input)
+            .option("-frames:v", "1")
+            .option("-q:v", "2")
+            .build();
+        let status = tools::status(Tool::Ffmpeg, &args)?;
+        if !status.success() || !fs::metadata(&self.image).is_ok_and(|m| m.len() > 0) {
+            bail!(
+                "failed to grab a cover frame at {} from {} (ffmpeg status {:?})",
+                clock(*at),
+                input.display(),
+                status.code()
+            );
+        }
+        Ok(())
+    }
+
+    // Add the cover to the encode args
+    pub fn apply(&self, extra: &mut Vec<String>) -> Result<()> {
+        let mime = mime_type(&self.image)?;
+        let image = self
+            .image
+            .to_str()
+            .with_context(|| format!("thumbnail path {} is not UTF-8", self.image.display()))?
+            .to_string();
+        let maps: Vec<String> = extra
+            .windows(2)
+            .filter(|pair| pair[0] == "-map")
+            .map(|pair| pair[1].clone())
+            .collect();
+        match self.container {
+            Container::Mp4 => {
+                // Mapping the cover turns off ffmpeg's automatic stream selection, so a
+                // file without a stream plan gets the plan's usual maps
+                if maps.is_empty() {
+                    extra
+                        .extend(["-map", "0:V?", "-map", "0:a?", "-map", "0:s?"].map(String::from));
+                }
+                let videos = mapped_videos(&self.info, &maps);
+                scope_video_filters(extra, videos);
+                let file = 1 + extra.iter().filter(|arg| *arg == "-i").count();
+                extra.extend([
+                    "-i".to_string(),
+                    image,
+                    "-map".to_string(),
+                    format!("{}:v:0", file),
+                    format!("-c:v:{}", videos),
+                    "copy".to_string(),
+                    format!("-disposition:v:{}", videos),
+                    "attached_pic".to_string(),
+                ]);
+            }
+            Container::Matroska => {
+                let attachments = match maps.iter().any(|m| m == "0" || m.starts_with("0:t")) {
+                    true => self
+                        .info
+                        .streams
+                        .iter()
+                        .filter(|s| s.is_type("attachment"))
+                        .count(),
+                    false => 0,
+                };
+                let ext = if mime == "image/png" { "png" } else { "jpg" };
+                extra.extend([
+                    "-attach".to_string(),
+                    image,
+                    format!("-metadata:s:t:{}", attachments),
+                    format!("mimetype={}", mime),
+                    format!("-metadata:s:t:{}", attachments),
+                    format!("filename=cover.{}", ext),
+                ]);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Cover {
+    fn drop(&mut self) {
+        if self.grab.is_some() || self.copy.is_some() {
+            let _ = fs::remove_file(&self.image);
+        }
+    }
+}
+
+fn mime_type(image: &Path) -> Result<&'static str> {
+    let ext = image
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    match ext.as_str() {
+        "jpg" | "jpeg" => Ok("image/jpeg"),
+        "png" => Ok("image/png"),
+        _ => bail!("thumbnail {} must be a JPEG or PNG image", image.display()),
+    }
+}
+
+// A temporary file for a cover, removed when the cover is dropped
+fn temp_image(ext: &str) -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let name = format!(
+        "transcoderr-cover-{}-{}.{}",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed),
+        ext
+    );
+    std::env::temp_dir().join(name)
+}
+
+// `Movie-poster.jpg`, `Movie.jpg`, then `poster.jpg`, `cover.jpg`, `folder.jpg` beside it
+fn find_poster(input: &Path) -> Option<PathBuf> {
+    let dir = input.parent()?;
+    let stem = input.file_stem()?;
+    let mut poster = stem.to_os_string();
+    poster.push("-poster");
+    let names = [poster, stem.to_os_string()]
+        .into_iter()
+        .chain(POSTER_NAMES.iter().map(OsString::from));
+    names
+        .flat_map(|name| {
+            IMAGE_EXTS.map(|ext| {
+                let mut file = name.clone();
+                file.push(".");
+                file.push(ext);
+                dir.join(file)
+            })
+        })
+        .find(|path| path.is_file())
+}
+
+// Video streams the maps put in the output, which the cover comes after. The plan's
+// `0:V?` skips existing cover art; `-map 0` and `0:v` keep it.
+fn mapped_videos(info: &MediaInfo, maps: &[String]) -> usize {
+    let all = maps
+        .iter()
+        .any(|m| m == "0" || m.trim_end_matches('?') == "0:v");
+    info.streams
+        .iter()
+        .filter(|s| s.is_type("video") && (all || !s.has_disposition("attached_pic")))
+        .count()
+}
+
+// A plain `-vf` applies to every video stream, and ffmpeg refuses to filter the copied
+// cover, so the filters are pinned to the streams before it
+fn scope_video_filters(extra: &mut Vec<String>, videos: usize) {
+    let mut scoped = Vec::with_capacity(extra.len());
+    let mut args = extra.drain(..);
+    while let Some(arg) = args.next() {
+        if arg != "-vf" && arg != "-filter:v" {
+            scoped.push(arg);
+            continue;
+        }
+        let Some(graph) = args.next() else {
+            scoped.push(arg);
+            break;
+        };
+        for n in 0..videos {
+            scoped.extend([format!("-filter:v:{}", n), graph.clone()]);
+        }
+    }
+    drop(args);
+    *extra = scoped;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probe::Stream;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    fn cover(image: &str, container: Container) -> Cover {
+        let stream = |codec_type: &str, attached: bool| Stream {
+            codec_type: Some(codec_type.to_string()),
+            disposition: [("attached_pic".to_string(), attached as i64)].into(),
+            ..Default::default()
+        };
+        Cover {
+            image: PathBuf::from(image),
+            grab: None,
+            copy: None,
+            container,
+            info: MediaInfo {
+                streams: vec![
+                    stream("video", false),
+                    stream("video", true),
+                    stream("audio", false),
+                    stream("attachment", false),
+                ],
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn parses_thumbnail_specs() {
+        assert_eq!("auto".parse::<Thumbnail>().unwrap(), Thumbnail::Auto);
+        assert_eq!(
+            "at=00:05:00".parse::<Thumbnail>().unwrap(),
+            Thumbnail::At(300.0)
+        );
+        assert_eq!("at=90".parse::<Thumbnail>().unwrap(), Thumbnail::At(90.0));
+        assert_eq!(
+            "from=art/cover.jpg".parse::<Thumbnail>().unwrap(),
+            Thumbnail::From(PathBuf::from("art/cover.jpg"))
+        );
+        assert!("at=soon".parse::<Thumbnail>().is_err());
+        assert!("cover.jpg".parse::<Thumbnail>().is_err());
+        assert_eq!(Thumbnail::At(300.0).to_string(), "at=00:05:00");
+    }
+
+    #[test]
+    fn mp4_covers_follow_the_mapped_video_as_attached_pictures() {
+        let mut extra = strings(&["-map", "0:V?", "-map", "0:a?", "-vf", "scale=1280:-2"]);
+        cover("/art/cover.jpg", Container::Mp4)
+            .apply(&mut extra)
+            .unwrap();
+        assert_eq!(
+            extra.join(" "),
+            "-map 0:V? -map 0:a? -filter:v:0 scale=1280:-2 -i /art/cover.jpg -map 1:v:0 \
+             -c:v:1 copy -disposition:v:1 attached_pic"
+        );
+
+        // Without a plan the usual maps come first; `-map 0` keeps the old cover too
+        let mut extra = Vec::new();
+        cover("/art/cover.png", Container::Mp4)
+            .apply(&mut extra)
+            .unwrap();
+        assert!(extra.starts_with(&strings(&["-map", "0:V?", "-map", "0:a?", "-map", "0:s?"])));
+        let mut extra = strings(&["-map", "0"]);
+        cover("/art/cover.png", Container::Mp4)
+            .apply(&mut extra)
+            .unwrap();
+        assert!(extra.ends_with(&strings(&["-disposition:v:2", "attached_pic"])));
+    }
+
+    #[test]
+    fn matroska_covers_are_attachments_after_the_kept_ones() {
+        let mut extra = strings(&["-map", "0:V?"]);
+        cover("/art/poster.png", Container::Matroska)
+            .apply(&mut extra)
+            .unwrap();
+        assert_eq!(
+            extra.join(" "),
+            "-map 0:V? -attach /art/poster.png -metadata:s:t:0 mimetype=image/png \
+             -metadata:s:t:0 filename=cover.png"
+        );
+        let mut extra = strings(&["-map", "0:t?"]);
+        cover("/art/poster.jpg", Container::Matroska)
+            .apply(&mut extra)
+            .unwrap();
+        assert!(extra.ends_with(&strings(&["-metadata:s:t:1", "filename=cover.jpg"])));
+        assert!(Container::of(Path::new("out.webm")).is_err());
+    }
+
+    #[test]
+    fn auto_prefers_the_most_specific_poster() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("Movie (1999).mkv");
+        assert_eq!(find_poster(&input), None);
+        fs::write(dir.path().join("folder.jpg"), "").unwrap();
+        assert_eq!(find_poster(&input), Some(dir.path().join("folder.jpg")));
+        fs::write(dir.path().join("Movie (1999)-poster.png"), "").unwrap();
+        assert_eq!(
+            find_poster(&input),
+            Some(dir.path().join("Movie (1999)-poster.png"))
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_posters_are_found_and_copied_byte_for_byte() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let name = |suffix: &str| {
+            let mut name = std::ffi::OsStr::from_bytes(b"Caf\xe9").to_os_string();
+            name.push(suffix);
+            dir.path().join(name)
+        };
+        let poster = name("-poster.jpg");
+        fs::write(&poster, "jpeg").unwrap();
+        assert_eq!(find_poster(&name(".mkv")), Some(poster.clone()));
+
+        let cover = Cover::existing(poster.clone(), Container::Matroska, MediaInfo::default());
+        assert_eq!(cover.describe(), poster.display().to_string());
+        cover.prepare().unwrap();
+        let copied = cover.image.clone();
+        assert_eq!(fs::read(&copied).unwrap(), b"jpeg");
+        let mut extra = Vec::new();
+        cover.apply(&mut extra).unwrap();
+        assert_eq!(extra[1], copied.to_str().unwrap());
+        drop(cover);
+        assert!(!copied.exists());
+        assert!(poster.exists());
+    }
+}
@@ -0,0 +1,443 @@
+// file: src/tools.rs
+// version: 0.6.2
+// guid: a5b815e6-5bb2-4acc-91c5-8bddc8f867df
+
+//! External tools (ffmpeg, ffprobe, mediainfo) behind the `ToolRunner` trait.
+//!
+//! Every run goes through `output`, `status` or `stream` here rather than a `Command`
+//! of its own, so the runner can be swapped: `System` runs the real binaries from PATH,
+//! and tests install a `Mock` that answers with canned output and records each call,
+//! which lets the code around ffmpeg be unit-tested without it. Another backend (remote
+//! execution, say) only has to implement the trait. A new tool gets a `Tool` variant.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsString;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Output, Stdio};
+use std::rc::Rc;
+use std::sync::{Mutex, OnceLock, PoisonError};
+use std::thread;
+
+use anyhow::{Context, Result};
+
+use crate::i18n;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tool {
+    Ffmpeg,
+    Ffprobe,
+    #[cfg(feature = "mediainfo")]
+    Mediainfo,
+}
+
+impl Tool {
+    pub fn program(self) -> &'static str {
+        match self {
+            Tool::Ffmpeg => "ffmpeg",
+            Tool::Ffprobe => "ffprobe",
+            #[cfg(feature = "mediainfo")]
+            Tool::Mediainfo => "mediainfo",
+        }
+    }
+
+    // The binary to run: the one the config names, else `program` from PATH
+    pub fn path(self) -> &'static Path {
+        let binaries = BINARIES.get();
+        let configured = match self {
+            Tool::Ffmpeg => binaries.and_then(|b| b.ffmpeg.as_deref()),
+            Tool::Ffprobe => binaries.and_then(|b| b.ffprobe.as_deref()),
+            #[cfg(feature = "mediainfo")]
+            Tool::Mediainfo => None,
+        };
+        configured.unwrap_or(Path::new(self.program()))
+    }
+}
+
+// ffmpeg and ffprobe binaries named in the config file
+#[derive(Debug, Clone, Default)]
+pub struct Binaries {
+    pub ffmpeg: Option<PathBuf>,
+    pub ffprobe: Option<PathBuf>,
+}
+
+static BINARIES: OnceLock<Binaries> = OnceLock::new();
+
+// Process ids of the tools `stream` is running, for `signal_streaming`
+static STREAMING: Mutex<BTreeSet<u32>> = Mutex::new(BTreeSet::new());
+
+// Run these binaries from now on; set once, at startup
+pub fn use_binaries(binaries: Binaries) {
+    let _ = BINARIES.set(binaries);
+}
+
+// Variables and working directory for one encode, from its preset and per-show
+// settings (GPU pinning, shader or license paths); an empty one inherits ours
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunEnv {
+    pub vars: BTreeMap<String, String>,
+    pub cwd: Option<PathBuf>,
+}
+
+impl RunEnv {
+    // `vars` are added over ours (a later layer wins per variable), `cwd` replaces it
+    pub fn layer(mut self, vars: &BTreeMap<String, String>, cwd: Option<&PathBuf>) -> Self {
+        self.vars
+            .extend(vars.iter().map(|(k, v)| (k.clone(), v.clone())));
+        if let Some(cwd) = cwd {
+            self.cwd = Some(cwd.clone());
+        }
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vars.is_empty() && self.cwd.is_none()
+    }
+
+    // `CUDA_VISIBLE_DEVICES=1 in /opt/shaders`
+    pub fn describe(&self) -> String {
+        let mut parts: Vec<String> = self
+            .vars
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        if let Some(cwd) = &self.cwd {
+            parts.push(format!("in {}", cwd.display()));
+        }
+        parts.join(" ")
+    }
+
+    fn apply(&self, command: &mut Command) {
+        command.envs(&self.vars);
+        if let Some(cwd) = &self.cwd {
+            command.current_dir(cwd);
+        }
+    }
+}
+
+// Runs are always given a closed stdin; tools never wait on the terminal
+pub trait ToolRunner {
+    // Run to completion with stdout and stderr captured
+    fn output(&self, tool: Tool, args: &[OsString]) -> io::Result<Output>;
+
+    // Run to completion with stdout and stderr passed through
+    fn status(&self, tool: Tool, args: &[OsString]) -> io::Result<ExitStatus>;
+
+    // Run with stderr handed to `stderr` as it arrives. stdout is passed through, or
+    // handed to `stdout` on a thread of its own when given (for `-progress pipe:1`).
+    fn stream(
+        &self,
+        tool: Tool,
+        args: &[OsString],
+        env: &RunEnv,
+        stderr: &mut dyn FnMut(&mut dyn Read),
+        stdout: Option<Reader>,
+    ) -> io::Result<ExitStatus>;
+}
+
+// Reads a pipe of a `stream` run while the caller reads the other one
+pub type Reader<'a> = &'a mut (dyn FnMut(&mut dyn Read) + Send);
+
+// The real binaries, found on PATH
+pub struct System;
+
+impl System {
+    fn command(tool: Tool, args: &[OsString]) -> Command {
+        let mut command = Command::new(tool.path());
+        command.args(args).stdin(Stdio::null());
+        command
+    }
+}
+
+impl ToolRunner for System {
+    fn output(&self, tool: Tool, args: &[OsString]) -> io::Result<Output> {
+        Self::command(tool, args).output()
+    }
+
+    fn status(&self, tool: Tool, args: &[OsString]) -> io::Result<ExitStatus> {
+        Self::command(tool, args)
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit())
+            .status()
+    }
+
+    fn stream(
+        &self,
+        tool: Tool,
+        args: &[OsString],
+        env: &RunEnv,
+        stderr: &mut dyn FnMut(&mut dyn Read),
+        stdout: Option<Reader>,
+    ) -> io::Result<ExitStatus> {
+        let mut command = Self::command(tool, args);
+        env.apply(&mut command);
+        let mut child = command
+            .stdout(match stdout {
+                Some(_) => Stdio::piped(),
+                None => Stdio::inherit(),
+            })
+            .stderr(Stdio::piped())
+            .spawn()?;
+        streaming().insert(child.id());
+        // Both pipes are drained at once, so neither can fill up and stall the tool
+        let out_pipe = child.stdout.take();
+        thread::scope(|scope| {
+            if let (Some(reader), Some(mut pipe)) = (stdout, out_pipe) {
+                scope.spawn(move || reader(&mut pipe));
+            }
+            if let Some(mut pipe) = child.stderr.take() {
+                stderr(&mut pipe);
+            }
+        });
+        // Before `wait` reaps it, so no signal can reach another process given its id
+        streaming().remove(&child.id());
+        child.wait()
+    }
+}
+
+fn streaming() -> std::sync::MutexGuard<'static, BTreeSet<u32>> {
+    STREAMING.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// Send `signal` (`SIGSTOP`, `SIGCONT`) to every tool `stream` is running, e.g. to pause
+// the running encodes while the machine is too hot. Returns how many got it.
+#[cfg(unix)]
+pub fn signal_streaming(signal: libc::c_int) -> usize {
+    // The lock is held while signalling, and `stream` takes it to drop an id before it
+    // reaps the child, so every id here is still an unreaped child of ours: none can
+    // have been reused by another process
+    let running = streaming();
+    running
+        .iter()
+        .filter(|pid| {
+            // SAFETY: kill takes no pointers; the pid is a live child of ours (see above)
+            unsafe { libc::kill(**pid as libc::pid_t, signal) == 0 }
+        })
+        .count()
+}
+
+thread_local! {
+    // Set by tests; `System` otherwise
+    static RUNNER: RefCell<Option<Rc<dyn ToolRunner>>> = const { RefCell::new(None) };
+}
+
+fn runner() -> Rc<dyn ToolRunner> {
+    RUNNER
+        .with(|runner| runner.borrow().clone())
+        .unwrap_or_else(|| Rc::new(System))
+}
+
+pub fn output(tool: Tool, args: &[OsString]) -> Result<Output> {
+    runner()
+        .output(tool, args)
+        .with_context(|| spawn_error(tool, args))
+}
+
+pub fn status(tool: Tool, args: &[OsString]) -> Result<ExitStatus> {
+    runner()
+        .status(tool, args)
+        .with_context(|| spawn_error(tool, args))
+}
+
+pub fn stream(
+    tool: Tool,
+    args: &[OsString],
+    stderr: &mut dyn FnMut(&mut dyn Read),
+) -> Result<ExitStatus> {
+    stream_in(tool, args, &RunEnv::default(), stderr)
+}
+
+// `stream` with the job's own variables and working directory
+pub fn stream_in(
+    tool: Tool,
+    args: &[OsString],
+    env: &RunEnv,
+    stderr: &mut dyn FnMut(&mut dyn Read),
+) -> Result<ExitStatus> {
+    stream_with(tool, args, env, stderr, None)
+}
+
+// `stream_in` with stdout handed to `stdout` rather than passed through
+pub fn stream_with(
+    tool: Tool,
+    args: &[OsString],
+    env: &RunEnv,
+    stderr: &mut dyn FnMut(&mut dyn Read),
+    stdout: Option<Reader>,
+) -> Result<ExitStatus> {
+    runner()
+        .stream(tool, args, env, stderr, stdout)
+        .with_context(|| match env.cwd.as_ref() {
+            Some(cwd) => format!("{} (in {})", spawn_error(tool, args), cwd.display()),
+            None => spawn_error(tool, args),
+        })
+}
+
+fn spawn_error(tool: Tool, args: &[OsString]) -> String {
+    i18n::t(
+        "error-tool-failed",
+        &[
+            ("tool", tool.path().display().to_string()),
+            ("args", format!("{:?}", args)),
+        ],
+    )
+}
+
+// `ffmpeg exited with status: Some(1)`
+pub fn exit_error(tool: Tool, code: Option<i32>) -> String {
+    i18n::t(
+        "error-exit-status",
+        &[
+            ("tool", tool.program().to_string()),
+            ("code", format!("{:?}", code)),
+        ],
+    )
+}
+
+// Run `f` with `runner` in place of the real tools on this thread
+#[cfg(test)]
+pub fn with_runner<T>(runner: Rc<dyn ToolRunner>, f: impl FnOnce() -> T) -> T {
+    let previous = RUNNER.with(|current| current.replace(Some(runner)));
+    let result = f();
+    RUNNER.with(|current| *current.borrow_mut() = previous);
+    result
+}
+
+// A mocked run's exit code, stdout and stderr
+#[cfg(test)]
+pub type Reply = (i32, String, String);
+
+#[cfg(test)]
+type Responder = dyn Fn(Tool, &[String]) -> Reply;
+
+// Canned answers for tests: `reply` picks each run's `Reply` from the tool and its
+// arguments; every call is kept for assertions.
+#[cfg(test)]
+pub struct Mock {
+    reply: Box<Responder>,
+    pub calls: RefCell<Vec<(Tool, Vec<String>)>>,
+    // The environment of each `stream` run
+    pub envs: RefCell<Vec<RunEnv>>,
+}
+
+#[cfg(test)]
+impl Mock {
+    pub fn new(reply: impl Fn(Tool, &[String]) -> Reply + 'static) -> Rc<Self> {
+        Rc::new(Self {
+            reply: Box::new(reply),
+            calls: RefCell::new(Vec::new()),
+            envs: RefCell::new(Vec::new()),
+        })
+    }
+
+    fn run(&self, tool: Tool, args: &[OsString]) -> (ExitStatus, String, String) {
+        let args: Vec<String> = args
+            .iter()
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect();
+        let (code, stdout, stderr) = (self.reply)(tool, &args);
+        self.calls.borrow_mut().push((tool, args));
+        (exit_status(code), stdout, stderr)
+    }
+}
+
+#[cfg(test)]
+impl ToolRunner for Mock {
+    fn output(&self, tool: Tool, args: &[OsString]) -> io::Result<Output> {
+        let (status, stdout, stderr) = self.run(tool, args);
+        Ok(Output {
+            status,
+            stdout: stdout.into_bytes(),
+            stderr: stderr.into_bytes(),
+        })
+    }
+
+    fn status(&self, tool: Tool, args: &[OsString]) -> io::Result<ExitStatus> {
+        Ok(self.run(tool, args).0)
+    }
+
+    fn stream(
+        &self,
+        tool: Tool,
+        args: &[OsString],
+        env: &RunEnv,
+        stderr: &mut dyn FnMut(&mut dyn Read),
+        stdout: Option<Reader>,
+    ) -> io::Result<ExitStatus> {
+        self.envs.borrow_mut().push(env.clone());
+        let (status, out, log) = self.run(tool, args);
+        if let Some(reader) = stdout {
+            reader(&mut out.as_bytes());
+        }
+        stderr(&mut log.as_bytes());
+        Ok(status)
+    }
+}
+
+#[cfg(all(test, unix))]
+fn exit_status(code: i32) -> ExitStatus {
+    use std::os::unix::process::ExitStatusExt;
+    ExitStatus::from_raw(code << 8)
+}
+
+#[cfg(all(test, windows))]
+fn exit_status(code: i32) -> ExitStatus {
+    use std::os::windows::process::ExitStatusExt;
+    ExitStatus::from_raw(code as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mock_answers_and_records_runs() {
+        let mock = Mock::new(
+            |tool, args| match (tool, args.first().map(String::as_str)) {
+                (Tool::Ffprobe, _) => (0, "{}".to_string(), String::new()),
+                (Tool::Ffmpeg, Some("-bad")) => {
+                    (1, String::new(), "Unrecognized option".to_string())
+                }
+                _ => (0, String::new(), "frame=1\n".to_string()),
+            },
+        );
+        with_runner(mock.clone(), || {
+            let out = output(Tool::Ffprobe, &["a.mkv".into()]).unwrap();
+            assert!(out.status.success());
+            assert_eq!(out.stdout, b"{}");
+            assert_eq!(
+                status(Tool::Ffmpeg, &["-bad".into()]).unwrap().code(),
+                Some(1)
+            );
+            let mut log = String::new();
+            let done = stream(Tool::Ffmpeg, &["-i".into()], &mut |from| {
+                from.read_to_string(&mut log).unwrap();
+            })
+            .unwrap();
+            assert!(done.success());
+            assert_eq!(log, "frame=1\n");
+        });
+        let calls = mock.calls.borrow();
+        assert_eq!(calls.len(), 3);
+        assert_eq!(calls[0], (Tool::Ffprobe, vec!["a.mkv".to_string()]));
+        assert_eq!(mock.envs.borrow()[0], RunEnv::default());
+    }
+
+    #[test]
+    fn later_env_layers_win() {
+        let preset = BTreeMap::from([
+            ("CUDA_VISIBLE_DEVICES".to_string(), "0".to_string()),
+            ("LICENSE_FILE".to_string(), "/opt/lic".to_string()),
+        ]);
+        let show = BTreeMap::from([("CUDA_VISIBLE_DEVICES".to_string(), "1".to_string())]);
+        let env = RunEnv::default()
+            .layer(&preset, Some(&PathBuf::from("/opt/shaders")))
+            .layer(&show, None);
+        assert_eq!(
+            env.describe(),
+            "CUDA_VISIBLE_DEVICES=1 LICENSE_FILE=/opt/lic in /opt/shaders"
+        );
+        assert!(RunEnv::default().layer(&BTreeMap::new(), None).is_empty());
+    }
+}
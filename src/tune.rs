@@ -0,0 +1,268 @@
+// file: src/tune.rs
+// version: 0.2.0
+// guid: 1440ed96-ca47-414e-add7-c39d1e07edf1
+
+//! `tune-wizard`: pick a CRF and encoder speed by looking at the results, not the docs.
+//!
+//! A short clip from the middle of the file is cut once as a lossless reference, then
+//! encoded at every combination of `--crf` and `--speeds`. The candidates are listed
+//! with their sample size, the size the whole file would come to, the encode time and,
+//! with `--vmaf`, their VMAF score against the reference. The one picked is saved as a
+//! user preset in the presets directory, ready for `--preset NAME`.
+
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use anyhow::{Context, Result, bail};
+use clap::Args;
+
+use crate::args::FfmpegArgs;
+use crate::cache::AnalysisCache;
+use crate::features::{self, Feature};
+use crate::presets::{self, Preset, Presets};
+use crate::probe;
+use crate::prompt::Prompt;
+use crate::quality::measure_vmaf;
+use crate::stats::clock;
+use crate::style::Table;
+use crate::tools::{self, Tool};
+use crate::units::Units;
+
+#[derive(Args, Debug)]
+pub struct TuneArgs {
+    /// Media file to take the sample from
+    pub input: PathBuf,
+    /// Video encoder to tune
+    #[arg(long, default_value = "libx265")]
+    pub vcodec: String,
+    /// CRF values to try (comma-separated)
+    #[arg(long, value_delimiter = ',', default_value = "18,22,26")]
+    pub crf: Vec<String>,
+    /// Encoder speed presets to try (comma-separated; 0-13 for libsvtav1)
+    #[arg(long, value_delimiter = ',', default_value = "medium,slow")]
+    pub speeds: Vec<String>,
+    /// Length of the sample, in seconds
+    #[arg(long, value_name = "SECONDS", default_value_t = 10.0)]
+    pub sample_seconds: f64,
+    /// Score each candidate with VMAF (needs ffmpeg with libvmaf)
+    #[arg(long)]
+    pub vmaf: bool,
+    /// Name of the saved preset (asked for when not given)
+    #[arg(long)]
+    pub name: Option<String>,
+}
+
+// One encode of the sample
+struct Candidate {
+    crf: String,
+    speed: String,
+    bytes: u64,
+    seconds: f64,
+    vmaf: Option<f64>,
+}
+
+pub fn run(
+    args: &TuneArgs,
+    cache: &AnalysisCache,
+    presets: &Presets,
+    presets_dir: Option<&Path>,
+    units: &Units,
+) -> Result<()> {
+    if args.vmaf {
+        features::require(Feature::Vmaf, "--vmaf")?;
+    }
+    let duration = probe::probe_cached(&args.input, cache)?
+        .format
+        .duration_seconds()
+        .context("input has no duration to take a sample from")?;
+    let dir = std::env::temp_dir().join(format!("transcoderr-tune-{}", std::process::id()));
+    fs::create_dir_all(&dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    let candidates = sample(args, duration, &dir);
+    let _ = fs::remove_dir_all(&dir);
+    let candidates = candidates?;
+
+    let length = args.sample_seconds.min(duration);
+    let mut headers = vec!["#", "CRF", "Speed", "Sample", "Whole file (est.)", "Time"];
+    if args.vmaf {
+        headers.push("VMAF");
+    }
+    let mut table = Table::new(&headers).right(&[0, 3, 4, 5]);
+    for (n, candidate) in candidates.iter().enumerate() {
+        let whole = (candidate.bytes as f64 * duration / length) as u64;
+        let mut row = vec![
+            (n + 1).to_string(),
+            candidate.crf.clone(),
+            candidate.speed.clone(),
+            units.bytes(candidate.bytes),
+            units.bytes(whole),
+            units.duration(candidate.seconds),
+        ];
+        if let Some(score) = candidate.vmaf {
+            row.push(format!("{:.2}", score));
+        }
+        table.row(row);
+    }
+    println!("\n{}", table);
+
+    let mut prompt = Prompt::stdin();
+    let mut options: Vec<String> = (1..=candidates.len()).map(|n| n.to_string()).collect();
+    options.push("none".to_string());
+    let options: Vec<&str> = options.iter().map(String::as_str).collect();
+    let answer = prompt.choose("Save which one as a preset", &options, "none")?;
+    let Some(picked) = answer.parse::<usize>().ok().map(|n| &candidates[n - 1]) else {
+        println!("Nothing saved.");
+        return Ok(());
+    };
+    let name = match &args.name {
+        Some(name) => name.clone(),
+        None => prompt.ask(
+            "Preset name",
+            &format!("tuned-{}-crf{}-{}", args.vcodec, picked.crf, picked.speed),
+        )?,
+    };
+    let file = args
+        .input
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let preset = Preset {
+        name,
+        description: Some(format!(
+            "{} CRF {}, preset {}; picked with tune-wizard on {}",
+            args.vcodec, picked.crf, picked.speed, file
+        )),
+        aliases: Vec::new(),
+        vcodec: Some(args.vcodec.clone()),
+        acodec: None,
+        extra: ["-crf", &picked.crf, "-preset", &picked.speed]
+            .map(str::to_string)
+            .to_vec(),
+        container: None,
+        containers: Vec::new(),
+        cwd: None,
+        env: Default::default(),
+    };
+    let path = presets::save(presets, presets_dir, &preset, "tune-wizard", None)?;
+    println!(
+        "Saved preset '{}' to {}; use it with --preset {}",
+        preset.name,
+        path.display(),
+        preset.name
+    );
+    Ok(())
+}
+
+// Cut the reference and encode every candidate from it
+fn sample(args: &TuneArgs, duration: f64, dir: &Path) -> Result<Vec<Candidate>> {
+    if args.crf.is_empty() || args.speeds.is_empty() {
+        bail!("--crf and --speeds need at least one value each");
+    }
+    let length = args.sample_seconds.min(duration);
+    let start = (duration - length) / 2.0;
+    println!(
+        "Sample: {} from {} ({:.0}s), encoded with {} at {} settings",
+        clock(start),
+        args.input.display(),
+        length,
+        args.vcodec,
+        args.crf.len() * args.speeds.len()
+    );
+    // Video only, lossless, so every candidate starts from the same frames
+    let reference = dir.join("reference.mkv");
+    let cut = FfmpegArgs::new(&reference)
+        .global(["-v", "error"])
+        .input_with(["-ss".to_string(), format!("{:.3}", start)], &args.input)
+        .option("-t", format!("{:.3}", length))
+        .map("0:v:0")
+        .codec("v", "ffv1")
+        .build();
+    run_ffmpeg(&cut).context("failed to cut the sample")?;
+
+    let mut candidates = Vec::new();
+    for speed in &args.speeds {
+        for crf in &args.crf {
+            println!("  Encoding CRF {}, speed {}", crf, speed);
+            let output = dir.join(format!("crf{}-{}.mkv", crf, speed));
+            let encode = FfmpegArgs::new(&output)
+                .global(["-v", "error"])
+                .input(&reference)
+                .codec("v", &args.vcodec)
+                .extra(&["-crf", crf, "-preset", speed])
+                .build();
+            let started = Instant::now();
+            run_ffmpeg(&encode)
+                .with_context(|| format!("failed to encode at CRF {}, speed {}", crf, speed))?;
+            let seconds = started.elapsed().as_secs_f64();
+            let vmaf = match args.vmaf {
+                true => Some(measure_vmaf(&reference, &output).context(
+                    "could not score the sample for --vmaf (needs ffmpeg with libvmaf)",
+                )?),
+                false => None,
+            };
+            candidates.push(Candidate {
+                crf: crf.clone(),
+                speed: speed.clone(),
+                bytes: fs::metadata(&output).map(|m| m.len()).unwrap_or(0),
+                seconds,
+                vmaf,
+            });
+        }
+    }
+    Ok(candidates)
+}
+
+fn run_ffmpeg(args: &[OsString]) -> Result<()> {
+    let output = tools::output(Tool::Ffmpeg, args)?;
+    if !output.status.success() {
+        bail!(
+            "ffmpeg status {:?}: {}",
+            output.status.code(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        args: TuneArgs,
+    }
+
+    #[test]
+    fn every_combination_encodes_the_same_reference() {
+        let cli = Cli::parse_from(["tune", "film.mkv", "--crf", "20,24", "--speeds", "slow"]);
+        let dir = tempfile::tempdir().unwrap();
+        let mock = tools::Mock::new(|_, _| (0, String::new(), String::new()));
+        let candidates =
+            tools::with_runner(mock.clone(), || sample(&cli.args, 600.0, dir.path())).unwrap();
+        assert_eq!(
+            candidates
+                .iter()
+                .map(|c| format!("{} {}", c.crf, c.speed))
+                .collect::<Vec<_>>(),
+            ["20 slow", "24 slow"]
+        );
+        let calls = mock.calls.borrow();
+        let commands: Vec<String> = calls.iter().map(|(_, args)| args.join(" ")).collect();
+        // The middle ten seconds, cut once
+        assert!(
+            commands[0].contains("-ss 295.000 -i film.mkv"),
+            "{}",
+            commands[0]
+        );
+        assert!(commands[0].contains("-c:v ffv1"), "{}", commands[0]);
+        let reference = dir.path().join("reference.mkv");
+        assert!(commands[2].starts_with(&format!(
+            "-hide_banner -y -v error -i {} -c:v libx265 -crf 24 -preset slow",
+            reference.display()
+        )));
+    }
+}
@@ -0,0 +1,170 @@
+// file: src/units.rs
+// version: 0.1.0
+// guid: cc836bc7-ae95-48bb-a0ee-d34a434c5b6a
+
+//! Sizes and durations in summaries, for people or for scripts.
+//!
+//! People get `4.3 GiB` and `2h 13m`, with the decimal separator of their locale
+//! (`LC_ALL`, then `LC_NUMERIC`, then `LANG`: `de_DE.UTF-8` prints `4,3 GiB`). With
+//! `--porcelain` sizes are raw bytes, durations raw seconds, and each summary is one
+//! tab-separated line of `key=value` fields in a fixed order, whatever the locale.
+
+use std::env;
+
+const KIB: f64 = 1024.0;
+const SIZE_UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+// Languages that write 4,3 rather than 4.3
+const DECIMAL_COMMA: [&str; 34] = [
+    "bg", "ca", "cs", "da", "de", "el", "es", "et", "eu", "fi", "fr", "gl", "hr", "hu", "id", "is",
+    "it", "lt", "lv", "nb", "nl", "nn", "no", "pl", "pt", "ro", "ru", "sk", "sl", "sr", "sv", "tr",
+    "uk", "vi",
+];
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Units {
+    porcelain: bool,
+    decimal_comma: bool,
+}
+
+impl Units {
+    pub fn from_env(porcelain: bool) -> Self {
+        let locale = ["LC_ALL", "LC_NUMERIC", "LANG"]
+            .iter()
+            .find_map(|var| env::var(var).ok().filter(|value| !value.is_empty()))
+            .unwrap_or_default();
+        let language = locale.split(['_', '.', '@']).next().unwrap_or_default();
+        Self {
+            porcelain,
+            decimal_comma: DECIMAL_COMMA.contains(&language),
+        }
+    }
+
+    pub fn porcelain(&self) -> bool {
+        self.porcelain
+    }
+
+    // For pages only people read (the HTML report), whatever --porcelain says
+    pub fn human(self) -> Self {
+        Self {
+            porcelain: false,
+            ..self
+        }
+    }
+
+    // `4.3 GiB`, `512 B`
+    pub fn bytes(&self, bytes: u64) -> String {
+        if self.porcelain {
+            return bytes.to_string();
+        }
+        let mut value = bytes as f64;
+        if value < KIB {
+            return format!("{} B", bytes);
+        }
+        let mut unit = SIZE_UNITS[0];
+        for next in SIZE_UNITS {
+            value /= KIB;
+            unit = next;
+            if value < KIB {
+                break;
+            }
+        }
+        format!("{} {}", self.decimal(value, 1), unit)
+    }
+
+    // `42s`, `4m 05s`, `2h 13m`, `3d 04h`: the two largest units
+    pub fn duration(&self, seconds: f64) -> String {
+        if self.porcelain {
+            return format!("{:.3}", seconds);
+        }
+        let total = seconds.max(0.0).round() as u64;
+        let (days, hours, minutes, secs) = (
+            total / 86_400,
+            total % 86_400 / 3600,
+            total % 3600 / 60,
+            total % 60,
+        );
+        match (days, hours, minutes) {
+            (0, 0, 0) => format!("{}s", secs),
+            (0, 0, _) => format!("{}m {:02}s", minutes, secs),
+            (0, _, _) => format!("{}h {:02}m", hours, minutes),
+            _ => format!("{}d {:02}h", days, hours),
+        }
+    }
+
+    // `-42.0%`: the output's size relative to the input's
+    pub fn change(&self, before: u64, after: u64) -> String {
+        if before == 0 {
+            return "n/a".to_string();
+        }
+        let percent = (after as f64 / before as f64 - 1.0) * 100.0;
+        let sign = if percent < 0.0 { "-" } else { "+" };
+        format!("{}{}%", sign, self.decimal(percent.abs(), 1))
+    }
+
+    fn decimal(&self, value: f64, places: usize) -> String {
+        let text = format!("{:.*}", places, value);
+        match self.decimal_comma {
+            true => text.replace('.', ","),
+            false => text,
+        }
+    }
+}
+
+// `batch\tsucceeded=3\tfailed=1`: one summary for --porcelain. Tabs and newlines in
+// values become spaces so every field stays on its line.
+pub fn porcelain_line(kind: &str, fields: &[(&str, String)]) -> String {
+    let mut line = kind.to_string();
+    for (key, value) in fields {
+        line.push('\t');
+        line.push_str(key);
+        line.push('=');
+        line.push_str(&value.replace(['\t', '\n', '\r'], " "));
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn people_get_short_units_in_their_locale() {
+        let units = Units::default();
+        assert_eq!(units.bytes(512), "512 B");
+        assert_eq!(units.bytes(4_617_089_843), "4.3 GiB");
+        assert_eq!(units.bytes(3 << 40), "3.0 TiB");
+        assert_eq!(units.duration(42.4), "42s");
+        assert_eq!(units.duration(245.0), "4m 05s");
+        assert_eq!(units.duration(7980.0), "2h 13m");
+        assert_eq!(units.duration(273_600.0), "3d 04h");
+        assert_eq!(units.change(400, 100), "-75.0%");
+
+        let german = Units {
+            decimal_comma: true,
+            ..units
+        };
+        assert_eq!(german.bytes(4_617_089_843), "4,3 GiB");
+        assert_eq!(german.change(1000, 1005), "+0,5%");
+    }
+
+    #[test]
+    fn porcelain_is_raw_and_one_line() {
+        let units = Units {
+            porcelain: true,
+            decimal_comma: true,
+        };
+        assert_eq!(units.bytes(4_617_089_843), "4617089843");
+        assert_eq!(units.duration(7980.25), "7980.250");
+        assert_eq!(units.human().bytes(2048), "2,0 KiB");
+        assert_eq!(
+            porcelain_line(
+                "show",
+                &[
+                    ("name", "Twin\tPeaks".to_string()),
+                    ("files", "3".to_string())
+                ]
+            ),
+            "show\tname=Twin Peaks\tfiles=3"
+        );
+    }
+}
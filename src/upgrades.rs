@@ -0,0 +1,280 @@
+// file: src/upgrades.rs
+// version: 0.3.0
+// guid: 5ea7dec6-bfed-4aa6-93de-0bd895f807af
+
+//! Upgrade report (`upgrades <dir>`): titles a library holds several sources of, such
+//! as a new 4K remux beside the old 1080p copy, and the outputs that were made from
+//! something other than the best of them.
+//!
+//! Files are grouped by title: the file name up to its first release tag (resolution,
+//! source, codec) or its SxxEyy marker, so `Seven.1995.2160p.UHD.Remux.mkv` and
+//! `Seven (1995) 1080p.mkv` are one title. Sources are ranked by frame height, then
+//! HDR, then video bitrate, from the cached probe. The encode history says which source
+//! each existing output was made from; those made from a lesser or vanished source are
+//! listed for regeneration.
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use crate::batch::{print_issue_summary, scan_inputs};
+use crate::cache::AnalysisCache;
+use crate::history::{History, Record};
+use crate::probe::{self, MediaInfo};
+use crate::style::Marker;
+
+// Words that start the release details of a file name; the title is what comes before
+const RELEASE_TAGS: [&str; 34] = [
+    "480p",
+    "576p",
+    "720p",
+    "1080p",
+    "1080i",
+    "2160p",
+    "4320p",
+    "4k",
+    "uhd",
+    "remux",
+    "bluray",
+    "blu",
+    "bdrip",
+    "brrip",
+    "web",
+    "webdl",
+    "webrip",
+    "hdtv",
+    "dvdrip",
+    "dvd",
+    "x264",
+    "x265",
+    "h264",
+    "h265",
+    "hevc",
+    "avc",
+    "av1",
+    "hdr",
+    "hdr10",
+    "hdr10+",
+    "dv",
+    "proper",
+    "repack",
+    "transcoded",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+struct Quality {
+    height: u32,
+    hdr: bool,
+    codec: String,
+    // Bits per second of the video, or of the whole file when only that is known
+    bit_rate: Option<u64>,
+}
+
+impl Quality {
+    fn of(info: &MediaInfo) -> Option<Self> {
+        let video = info.video_stream()?;
+        Some(Self {
+            height: video.height?,
+            hdr: matches!(
+                video.color_transfer.as_deref(),
+                Some("smpte2084" | "arib-std-b67")
+            ),
+            codec: video
+                .codec_name
+                .clone()
+                .unwrap_or_else(|| "unknown".to_string()),
+            bit_rate: video.bit_rate().or_else(|| info.format.bit_rate()),
+        })
+    }
+
+    fn rank(&self) -> (u32, bool, u64) {
+        (self.height, self.hdr, self.bit_rate.unwrap_or(0))
+    }
+}
+
+impl fmt::Display for Quality {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}p {}", self.height, self.codec)?;
+        if self.hdr {
+            write!(f, " HDR")?;
+        }
+        if let Some(bits) = self.bit_rate {
+            write!(f, " {:.1} Mb/s", bits as f64 / 1e6)?;
+        }
+        Ok(())
+    }
+}
+
+struct Source {
+    path: PathBuf,
+    quality: Option<Quality>,
+}
+
+// `upgrades <dir>`: print titles with several sources, and the outputs made from
+// sources in `dir` that should be made again
+pub fn run(
+    dir: &Path,
+    input_exts: &str,
+    cache: &AnalysisCache,
+    workers: usize,
+    history: &History,
+) -> Result<()> {
+    // The latest successful encode of each output that still exists
+    let mut outputs: BTreeMap<String, Record> = BTreeMap::new();
+    for record in history.records() {
+        if record.status == "done" {
+            outputs.insert(record.output.clone(), record);
+        }
+    }
+    outputs.retain(|output, _| Path::new(output).exists());
+
+    let scan = scan_inputs(dir, input_exts)?;
+    let files: Vec<&Path> = scan.files.iter().map(PathBuf::as_path).collect();
+    probe::probe_all(&files, cache, workers);
+    let root = std::path::absolute(dir)?;
+    let mut titles: BTreeMap<String, Vec<Source>> = BTreeMap::new();
+    for file in &scan.files {
+        let path = std::path::absolute(file).unwrap_or_else(|_| file.clone());
+        // Outputs living beside their sources are not sources themselves
+        if outputs.contains_key(path.to_string_lossy().as_ref()) {
+            continue;
+        }
+        let quality = match probe::probe_cached(file, cache) {
+            Ok(info) => Quality::of(&info),
+            Err(e) => {
+                eprintln!("{}: {} {:#}", file.display(), Marker::Error, e);
+                None
+            }
+        };
+        titles
+            .entry(title_key(file))
+            .or_default()
+            .push(Source { path, quality });
+    }
+
+    let (mut duplicated, mut stale) = (0usize, 0usize);
+    for (title, mut sources) in titles {
+        sources.sort_by_key(|s| Reverse(s.quality.as_ref().map(Quality::rank)));
+        let best = &sources[0];
+        let Some(best_quality) = &best.quality else {
+            continue;
+        };
+        let regenerate: Vec<(&Record, String)> = outputs
+            .values()
+            .filter(|r| {
+                let input = Path::new(&r.input);
+                input.starts_with(&root) && title_key(input) == title
+            })
+            .filter_map(|r| {
+                let made_from = sources
+                    .iter()
+                    .find(|s| s.path.as_os_str() == r.input.as_str());
+                match made_from {
+                    None => Some((r, format!("{} (no longer present)", r.input))),
+                    Some(s) => match &s.quality {
+                        Some(q) if q.rank() >= best_quality.rank() => None,
+                        Some(q) => Some((r, format!("{} ({})", r.input, q))),
+                        None => Some((r, format!("{} (unknown quality)", r.input))),
+                    },
+                }
+            })
+            .collect();
+        if sources.len() < 2 && regenerate.is_empty() {
+            continue;
+        }
+        duplicated += usize::from(sources.len() > 1);
+        stale += regenerate.len();
+        println!("{}", title);
+        println!("  Best: {} ({})", best.path.display(), best_quality);
+        for other in &sources[1..] {
+            match &other.quality {
+                Some(q) => println!("  Also: {} ({})", other.path.display(), q),
+                None => println!("  Also: {}", other.path.display()),
+            }
+        }
+        for (record, made_from) in regenerate {
+            println!("  Regenerate {}: made from {}", record.output, made_from);
+        }
+    }
+    println!(
+        "\n{} titles with several sources, {} outputs to regenerate from a better one",
+        duplicated, stale
+    );
+    print_issue_summary(&scan.issues);
+    Ok(())
+}
+
+// A file's title: its name, lowercased and split into words, up to the first release
+// tag; episodes end at their SxxEyy marker so episode titles do not split them
+fn title_key(path: &Path) -> String {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let words = stem
+        .split(['.', '_', '-', '(', ')', '[', ']', ' '])
+        .filter(|w| !w.is_empty());
+    let mut title = Vec::new();
+    for word in words {
+        if RELEASE_TAGS.contains(&word) {
+            break;
+        }
+        title.push(word);
+        if is_episode_marker(word) {
+            break;
+        }
+    }
+    title.join(" ")
+}
+
+fn is_episode_marker(word: &str) -> bool {
+    let Some(rest) = word.strip_prefix('s') else {
+        return false;
+    };
+    match rest.split_once('e') {
+        Some((season, episode)) => {
+            !season.is_empty()
+                && !episode.is_empty()
+                && season.bytes().all(|b| b.is_ascii_digit())
+                && episode.bytes().all(|b| b.is_ascii_digit())
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn releases_of_one_title_share_a_key() {
+        let key = |name: &str| title_key(Path::new(name));
+        assert_eq!(key("Seven.1995.2160p.UHD.BluRay.Remux.mkv"), "seven 1995");
+        assert_eq!(key("Movies/Seven (1995) [1080p].mkv"), "seven 1995");
+        assert_eq!(key("The.Wire.S03E07.720p.mkv"), "the wire s03e07");
+        assert_eq!(
+            key("The Wire - S03E07 - Back Burners.mkv"),
+            "the wire s03e07"
+        );
+        assert_ne!(key("Se7en.mkv"), key("Seven.mkv"));
+    }
+
+    #[test]
+    fn height_outranks_hdr_and_bitrate() {
+        let quality = |height, hdr, bit_rate| Quality {
+            height,
+            hdr,
+            codec: "hevc".to_string(),
+            bit_rate: Some(bit_rate),
+        };
+        assert!(quality(2160, false, 20_000_000).rank() > quality(1080, true, 40_000_000).rank());
+        assert!(quality(2160, true, 20_000_000).rank() > quality(2160, false, 60_000_000).rank());
+        assert_eq!(
+            quality(2160, true, 58_200_000).to_string(),
+            "2160p hevc HDR 58.2 Mb/s"
+        );
+    }
+}
@@ -0,0 +1,95 @@
+// file: src/vbv.rs
+// version: 0.1.0
+// guid: 30d9aad9-1feb-465d-bc29-50f9f478ca9e
+
+//! Bitrate caps (VBV: `-maxrate` with `-bufsize`) checked against the video encoder,
+//! for the `stream-*` presets and caps given in the extra args.
+//!
+//! Plain CRF output spikes far above its average in busy scenes, which stalls remote
+//! Plex/Jellyfin streams on slow uplinks. Capped CRF keeps the quality target but never
+//! lets the bitrate over the cap for longer than the buffer absorbs. Encoders spell this
+//! differently: x264, x265 and SVT-AV1 take the pair as is, VP9 also needs the cap as its
+//! target bitrate, and NVENC needs VBR mode with `-cq` for the quality. Encoders that
+//! cannot be relied on to honour a cap, and copied video, are refused rather than
+//! producing an output that only looks capped.
+
+use anyhow::{Result, bail};
+
+const NVENC: [&str; 3] = ["h264_nvenc", "hevc_nvenc", "av1_nvenc"];
+
+// Rewrite the cap in `extra` for `vcodec`; fails when the encoder cannot honour it.
+// Args without both -maxrate and -bufsize are left alone.
+pub fn adapt(vcodec: &str, extra: &mut Vec<String>) -> Result<()> {
+    let (Some(maxrate), Some(_)) = (value(extra, "-maxrate"), value(extra, "-bufsize")) else {
+        return Ok(());
+    };
+    let maxrate = maxrate.to_string();
+    match vcodec {
+        "libx264" | "libx265" | "libsvtav1" => {}
+        // Constrained quality: the CRF applies below the -b:v ceiling
+        "libvpx-vp9" => {
+            if value(extra, "-b:v").is_none() {
+                extra.extend(["-b:v".to_string(), maxrate]);
+            }
+        }
+        codec if NVENC.contains(&codec) => {
+            for arg in extra.iter_mut().filter(|arg| *arg == "-crf") {
+                *arg = "-cq".to_string();
+            }
+            extra.extend(["-rc", "vbr"].map(String::from));
+        }
+        "copy" => bail!(
+            "a bitrate cap (-maxrate {}) needs the video encoded, not copied",
+            maxrate
+        ),
+        codec => bail!(
+            "{} cannot be relied on to honour a bitrate cap (-maxrate {}); use libx264, \
+             libx265, libsvtav1, libvpx-vp9 or an NVENC encoder",
+            codec,
+            maxrate
+        ),
+    }
+    Ok(())
+}
+
+// The value of the last `key` in `args`
+fn value<'a>(args: &'a [String], key: &str) -> Option<&'a str> {
+    let i = args.iter().rposition(|arg| arg == key)?;
+    args.get(i + 1).map(String::as_str)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capped() -> Vec<String> {
+        ["-crf", "21", "-maxrate", "3500k", "-bufsize", "7000k"]
+            .map(String::from)
+            .to_vec()
+    }
+
+    #[test]
+    fn caps_are_spelled_per_encoder() {
+        let mut extra = capped();
+        adapt("libx265", &mut extra).unwrap();
+        assert_eq!(extra, capped());
+
+        let mut extra = capped();
+        adapt("hevc_nvenc", &mut extra).unwrap();
+        assert_eq!(
+            extra,
+            [
+                "-cq", "21", "-maxrate", "3500k", "-bufsize", "7000k", "-rc", "vbr"
+            ]
+        );
+        let mut extra = capped();
+        adapt("libvpx-vp9", &mut extra).unwrap();
+        assert_eq!(extra[6..], ["-b:v", "3500k"]);
+
+        assert!(adapt("copy", &mut capped()).is_err());
+        assert!(adapt("h264_videotoolbox", &mut capped()).is_err());
+        // Without a buffer size there is no cap to check
+        let mut extra = vec!["-maxrate".to_string(), "5M".to_string()];
+        adapt("copy", &mut extra).unwrap();
+    }
+}
@@ -0,0 +1,429 @@
+// file: src/watch.rs
+// version: 0.3.2
+// guid: a19be118-bc0a-4883-bd45-ab88638ae2c7
+
+//! `watch`: transcode media as it lands in a directory, e.g. a download folder.
+//!
+//! On Linux the directory is looked at again whenever inotify reports a file landing in
+//! it. Network shares and container mounts often never deliver those notifications;
+//! `--poll` looks every `--interval` seconds instead, as other systems always do. A file
+//! is picked up once its size and modification time have held for `--settle`
+//! seconds, so one still being downloaded or copied is left alone. A file that fails is
+//! tried again only once it changes. `--metrics` serves the counts for Prometheus.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{Context, Result, bail};
+use clap::Args;
+
+use crate::events::{Event, path_str};
+#[cfg(target_os = "linux")]
+use crate::inotify::Inotify;
+use crate::metrics::{self, Metrics};
+use crate::style::Marker;
+use crate::{Runtime, TranscodeJob, batch, transcode_with};
+
+#[derive(Args, Debug)]
+pub struct WatchArgs {
+    /// Directory to watch, with its subfolders
+    pub input_dir: PathBuf,
+    /// Output directory (mirrors the folder structure)
+    pub output_dir: PathBuf,
+    /// Preset to transcode with (default: the config's `preset`)
+    #[arg(long)]
+    pub preset: Option<String>,
+    /// File extensions to pick up (comma-separated; default: the config's `input-exts`,
+    /// else mp4,mkv,avi,mov,m4v,ts)
+    #[arg(long)]
+    pub input_exts: Option<String>,
+    /// Look at the directory every --interval seconds instead of waiting for change
+    /// notifications, which network shares and container mounts rarely deliver
+    #[arg(long)]
+    pub poll: bool,
+    /// Seconds between looks at the directory with --poll (or without inotify)
+    #[arg(long, value_name = "SECONDS", default_value_t = 5.0)]
+    pub interval: f64,
+    /// Seconds a file's size and modification time must hold before it is transcoded
+    #[arg(long, value_name = "SECONDS", default_value_t = 30.0)]
+    pub settle: f64,
+    /// Also transcode the files already there when the watch starts
+    #[arg(long)]
+    pub existing: bool,
+    /// Transcode the files there once they settle, then exit instead of watching (implies
+    /// --existing)
+    #[arg(long)]
+    pub once: bool,
+    /// Print what would be transcoded without encoding
+    #[arg(long)]
+    pub dry_run: bool,
+    /// Serve Prometheus metrics on ADDR/metrics, e.g. 0.0.0.0:9099
+    #[arg(long, value_name = "ADDR")]
+    pub metrics: Option<String>,
+}
+
+// A file's size and modification time, which change while it is still being written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Stamp {
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+impl Stamp {
+    fn of(path: &Path) -> Option<Self> {
+        let meta = fs::metadata(path).ok()?;
+        Some(Self {
+            size: meta.len(),
+            modified: meta.modified().ok(),
+        })
+    }
+}
+
+#[derive(Debug)]
+enum Seen {
+    // Unchanged since the instant
+    Settling(Stamp, Instant),
+    // Transcoded, skipped or failed as it was; looked at again only if it changes
+    Handled(Stamp),
+}
+
+#[derive(Debug, Default)]
+struct Tracker {
+    files: HashMap<PathBuf, Seen>,
+}
+
+impl Tracker {
+    // Whether `path`, now at `stamp`, has held still for `settle`
+    fn settled(&mut self, path: &Path, stamp: Stamp, settle: Duration, now: Instant) -> bool {
+        match self.files.get(path) {
+            Some(Seen::Handled(last)) if *last == stamp => false,
+            Some(Seen::Settling(last, since)) if *last == stamp => {
+                now.saturating_duration_since(*since) >= settle
+            }
+            _ => {
+                self.files
+                    .insert(path.to_path_buf(), Seen::Settling(stamp, now));
+                false
+            }
+        }
+    }
+
+    fn handled(&mut self, path: &Path, stamp: Stamp) {
+        self.files.insert(path.to_path_buf(), Seen::Handled(stamp));
+    }
+
+    // Forget files no longer in the scan (deleted or renamed away), so one that went
+    // while settling is not waited on forever
+    fn keep_only(&mut self, present: &[PathBuf]) {
+        let present: HashSet<&Path> = present.iter().map(PathBuf::as_path).collect();
+        self.files
+            .retain(|path, _| present.contains(path.as_path()));
+    }
+
+    // Files waiting to settle
+    fn settling(&self) -> usize {
+        self.files
+            .values()
+            .filter(|seen| matches!(seen, Seen::Settling(..)))
+            .count()
+    }
+
+    // How long until the first of the files waiting to settle has held still for `settle`
+    fn next_settled(&self, settle: Duration, now: Instant) -> Option<Duration> {
+        self.files
+            .values()
+            .filter_map(|seen| match seen {
+                Seen::Settling(_, since) => Some((*since + settle).saturating_duration_since(now)),
+                Seen::Handled(_) => None,
+            })
+            .min()
+    }
+}
+
+// How the loop waits before looking at the directory again
+enum Waiter {
+    Poll(Duration),
+    #[cfg(target_os = "linux")]
+    Notify(Inotify),
+}
+
+impl Waiter {
+    fn new(args: &WatchArgs, interval: Duration) -> Self {
+        #[cfg(target_os = "linux")]
+        if !args.poll {
+            match Inotify::new(&args.input_dir) {
+                Ok(inotify) => return Self::Notify(inotify),
+                Err(e) => eprintln!(
+                    "{} No change notifications for {} ({}); looking every {:?} instead",
+                    Marker::Warning,
+                    args.input_dir.display(),
+                    e,
+                    interval
+                ),
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = args;
+        Self::Poll(interval)
+    }
+
+    // Until a file lands, or for notifications at most until `next_settled`
+    fn wait(&mut self, next_settled: Option<Duration>) -> Result<()> {
+        #[cfg(not(target_os = "linux"))]
+        let _ = next_settled;
+        match self {
+            Self::Poll(interval) => thread::sleep(*interval),
+            #[cfg(target_os = "linux")]
+            Self::Notify(inotify) => {
+                inotify
+                    .wait(next_settled)
+                    .context("failed to wait for changes")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn lock(metrics: &Mutex<Metrics>) -> MutexGuard<'_, Metrics> {
+    metrics.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn seconds(flag: &str, value: f64) -> Result<Duration> {
+    Duration::try_from_secs_f64(value)
+        .ok()
+        .with_context(|| format!("{} must be zero or more seconds, not {}", flag, value))
+}
+
+// The inputs under the watched directory, less anything in the output directory
+fn inputs(args: &WatchArgs, input_exts: &str, output_dir: Option<&Path>) -> Result<Vec<PathBuf>> {
+    let scan = batch::scan_inputs(&args.input_dir, input_exts)?;
+    Ok(scan
+        .files
+        .into_iter()
+        .filter(|file| {
+            let file = fs::canonicalize(file).unwrap_or_else(|_| file.clone());
+            output_dir.is_none_or(|dir| !file.starts_with(dir))
+        })
+        .collect())
+}
+
+pub fn run(args: &WatchArgs, input_exts: &str, runtime: &Runtime) -> Result<()> {
+    let interval = seconds("--interval", args.interval)?;
+    let settle = seconds("--settle", args.settle)?;
+    if !args.input_dir.is_dir() {
+        bail!(
+            "Input directory does not exist: {}",
+            args.input_dir.display()
+        );
+    }
+    let ext = runtime.presets.output_ext(args.preset.as_deref(), None)?;
+    let output_dir = fs::canonicalize(&args.output_dir).ok();
+    let events = &runtime.events;
+    // Shared with the encodes, which keep its speed and frame rate current
+    let metrics = runtime.metrics.clone().unwrap_or_default();
+    if let Some(listen) = &args.metrics {
+        metrics::listen(listen, Arc::clone(&metrics))?;
+    }
+
+    let mut waiter = Waiter::new(args, interval);
+    let mut tracker = Tracker::default();
+    if args.existing || args.once {
+        println!("Watching {}", args.input_dir.display());
+    } else {
+        let present = inputs(args, input_exts, output_dir.as_deref())?;
+        for file in &present {
+            if let Some(stamp) = Stamp::of(file) {
+                tracker.handled(file, stamp);
+            }
+        }
+        println!(
+            "Watching {} (leaving the {} files already there alone)",
+            args.input_dir.display(),
+            present.len()
+        );
+    }
+
+    let (mut succeeded, mut failed, mut index) = (0usize, 0usize, 0usize);
+    loop {
+        let files = inputs(args, input_exts, output_dir.as_deref())?;
+        tracker.keep_only(&files);
+        for file in files {
+            let Some(stamp) = Stamp::of(&file) else {
+                continue;
+            };
+            if !tracker.settled(&file, stamp, settle, Instant::now()) {
+                continue;
+            }
+            tracker.handled(&file, stamp);
+
+            let relative = file.strip_prefix(&args.input_dir).unwrap_or(&file);
+            let output = args.output_dir.join(relative).with_extension(&ext);
+            if output.exists() {
+                println!(
+                    "\n{} {}: {} already exists",
+                    Marker::Skip,
+                    file.display(),
+                    output.display()
+                );
+                continue;
+            }
+            index += 1;
+            println!("\n[{}] {} -> {}", index, file.display(), output.display());
+            if args.dry_run {
+                println!("  {} Would transcode", Marker::DryRun);
+                continue;
+            }
+
+            runtime.gate.wait(&file, &output, events);
+            events.emit(Event::Start {
+                input: path_str(&file),
+                output: path_str(&output),
+                index,
+                total: index,
+                device: None,
+            });
+            let started = Instant::now();
+            lock(&metrics).running = 1;
+            let mut job = TranscodeJob::new(&file).output(&output);
+            job.preset = args.preset.clone();
+            let result = output
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .with_context(|| format!("failed to create {}", output.display()))
+                .and_then(|()| Ok(transcode_with(&job, runtime)?));
+            lock(&metrics).running = 0;
+            match result {
+                Ok(_) => {
+                    succeeded += 1;
+                    let output_bytes = fs::metadata(&output).map(|m| m.len()).unwrap_or(0);
+                    let seconds = started.elapsed().as_secs_f64();
+                    lock(&metrics).done(stamp.size, output_bytes, seconds);
+                    println!("  {} {}", Marker::Ok, output.display());
+                    events.emit(Event::Done {
+                        input: path_str(&file),
+                        output: path_str(&output),
+                        seconds,
+                        output_bytes,
+                    });
+                }
+                Err(e) => {
+                    failed += 1;
+                    lock(&metrics).failed += 1;
+                    eprintln!("  {} {:#}", Marker::Error, e);
+                    eprintln!("  Leaving it until it changes...");
+                    events.emit(Event::Fail {
+                        input: path_str(&file),
+                        output: path_str(&output),
+                        error: format!("{:#}", e),
+                    });
+                }
+            }
+        }
+        let settling = tracker.settling();
+        lock(&metrics).queued = settling;
+        if args.once && settling == 0 {
+            break;
+        }
+        waiter.wait(tracker.next_settled(settle, Instant::now()))?;
+    }
+
+    println!(
+        "\nWatch finished: {} transcoded, {} failed",
+        succeeded, failed
+    );
+    if !args.dry_run {
+        events.emit(Event::BatchDone { succeeded, failed });
+    }
+    if failed > 0 {
+        bail!("{} of {} files failed", failed, succeeded + failed);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stamp(size: u64) -> Stamp {
+        Stamp {
+            size,
+            modified: None,
+        }
+    }
+
+    #[test]
+    fn files_settle_once_unchanged_for_the_settle_time() {
+        let mut tracker = Tracker::default();
+        let (path, settle, start) = (
+            Path::new("/in/a.mkv"),
+            Duration::from_secs(10),
+            Instant::now(),
+        );
+        assert!(!tracker.settled(path, stamp(1), settle, start));
+        // Still growing: the clock starts again
+        assert!(!tracker.settled(path, stamp(2), settle, start + Duration::from_secs(8)));
+        assert!(!tracker.settled(path, stamp(2), settle, start + Duration::from_secs(16)));
+        assert!(tracker.settled(path, stamp(2), settle, start + Duration::from_secs(18)));
+        assert_eq!(tracker.settling(), 1);
+
+        tracker.handled(path, stamp(2));
+        assert_eq!(tracker.settling(), 0);
+        assert!(!tracker.settled(path, stamp(2), settle, start + Duration::from_secs(60)));
+        // Replaced: watched again
+        assert!(!tracker.settled(path, stamp(3), settle, start + Duration::from_secs(61)));
+        assert!(tracker.settled(path, stamp(3), settle, start + Duration::from_secs(71)));
+    }
+
+    #[test]
+    fn waits_until_the_first_file_settles() {
+        let mut tracker = Tracker::default();
+        let (settle, start) = (Duration::from_secs(30), Instant::now());
+        assert_eq!(tracker.next_settled(settle, start), None);
+        tracker.settled(Path::new("/in/a.mkv"), stamp(1), settle, start);
+        tracker.settled(
+            Path::new("/in/b.mkv"),
+            stamp(1),
+            settle,
+            start + Duration::from_secs(10),
+        );
+        tracker.handled(Path::new("/in/c.mkv"), stamp(1));
+        let now = start + Duration::from_secs(12);
+        assert_eq!(
+            tracker.next_settled(settle, now),
+            Some(Duration::from_secs(18))
+        );
+        assert_eq!(
+            tracker.next_settled(settle, start + Duration::from_secs(90)),
+            Some(Duration::ZERO)
+        );
+    }
+
+    #[test]
+    fn files_gone_while_settling_are_forgotten() {
+        let mut tracker = Tracker::default();
+        let (settle, start) = (Duration::from_secs(30), Instant::now());
+        let (a, b) = (PathBuf::from("/in/a.mkv"), PathBuf::from("/in/b.mkv"));
+        tracker.settled(&a, stamp(1), settle, start);
+        tracker.handled(&b, stamp(1));
+        tracker.keep_only(std::slice::from_ref(&b));
+        assert_eq!(tracker.settling(), 0);
+        assert_eq!(tracker.next_settled(settle, start), None);
+        // Back under the same name: a new file to settle
+        tracker.keep_only(&[]);
+        assert!(!tracker.settled(&b, stamp(1), settle, start));
+        assert_eq!(tracker.settling(), 1);
+    }
+
+    #[test]
+    fn negative_intervals_are_refused() {
+        assert!(seconds("--settle", -1.0).is_err());
+        assert_eq!(
+            seconds("--settle", 0.5).unwrap(),
+            Duration::from_millis(500)
+        );
+    }
+}
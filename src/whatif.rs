@@ -0,0 +1,95 @@
+// file: src/whatif.rs
+// version: 0.1.0
+// guid: f00180f9-fecd-443f-a4ea-d8ceaec6b2b6
+
+//! Simulation of destructive operations (`--what-if`).
+//!
+//! Unlike each command's `--dry-run`, this is one switch for the whole run: encodes
+//! become dry runs, and pruning, migration swaps and preset installs go through all of
+//! their checks but record each removal, rename and write (with resolved paths) instead
+//! of doing it. The recorded actions are printed as they happen and written to an action
+//! log for review, the only file the run writes.
+
+use std::cell::RefCell;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+
+use crate::config;
+
+pub struct WhatIf {
+    log: PathBuf,
+    actions: RefCell<Vec<String>>,
+}
+
+impl WhatIf {
+    // Log to `log`, else to a new file under the data directory's `what-if/`
+    pub fn new(log: Option<PathBuf>) -> Self {
+        let log = log.unwrap_or_else(|| {
+            let secs = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            let name = format!("{}.log", secs);
+            match config::data_dir() {
+                Some(dir) => dir.join("what-if").join(name),
+                None => PathBuf::from(format!("transcoderr-what-if-{}", name)),
+            }
+        });
+        Self {
+            log,
+            actions: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn record(&self, action: String) {
+        println!("  [WHAT IF] Would {}", action);
+        self.actions.borrow_mut().push(action);
+    }
+
+    pub fn rename(&self, from: &Path, to: &Path) {
+        self.record(format!("rename {} -> {}", resolved(from), resolved(to)));
+    }
+
+    pub fn write(&self, path: &Path, replaces: bool) {
+        match replaces {
+            true => self.record(format!("replace {}", resolved(path))),
+            false => self.record(format!("write {}", resolved(path))),
+        }
+    }
+
+    // Write the action log, one action per line
+    pub fn finish(&self, command: &str) -> Result<()> {
+        let actions = self.actions.borrow();
+        let mut text = format!(
+            "# transcoderr --what-if {}: {} actions\n",
+            command,
+            actions.len()
+        );
+        for action in actions.iter() {
+            text.push_str(action);
+            text.push('\n');
+        }
+        if let Some(parent) = self.log.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create {}", parent.display()))?;
+        }
+        fs::write(&self.log, text)
+            .with_context(|| format!("failed to write {}", self.log.display()))?;
+        println!(
+            "\nWhat if: nothing was changed; {} actions logged to {}",
+            actions.len(),
+            self.log.display()
+        );
+        Ok(())
+    }
+}
+
+pub fn resolved(path: &Path) -> String {
+    std::path::absolute(path)
+        .unwrap_or_else(|_| path.to_path_buf())
+        .display()
+        .to_string()
+}
@@ -0,0 +1,119 @@
+// file: src/workers.rs
+// version: 0.2.0
+// guid: 3f9a6c21-8d4e-47b5-a1c0-6e2b95d7f418
+
+//! Encodes running side by side for `batch --jobs`.
+//!
+//! Each encode runs on its own thread; the batch loop keeps what it needs to finish the
+//! job (history, report, events) and gets it back with the result when the thread is
+//! done. Only the loop's thread prints, so status lines of different files never mix.
+
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+use anyhow::{Result, anyhow};
+
+pub struct Workers<T> {
+    limit: usize,
+    // What each running encode was started with, by id
+    running: HashMap<usize, T>,
+    next_id: usize,
+    sender: Sender<(usize, Result<()>)>,
+    receiver: Receiver<(usize, Result<()>)>,
+}
+
+impl<T> Workers<T> {
+    pub fn new(limit: usize) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Self {
+            limit: limit.max(1),
+            running: HashMap::new(),
+            next_id: 0,
+            sender,
+            receiver,
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.running.len() >= self.limit
+    }
+
+    // Run `work` on a new thread; `job` comes back from `next_finished` with its result
+    pub fn spawn(&mut self, job: T, work: impl FnOnce() -> Result<()> + Send + 'static) {
+        let (id, sender) = (self.next_id, self.sender.clone());
+        self.next_id += 1;
+        self.running.insert(id, job);
+        thread::spawn(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(work))
+                .unwrap_or_else(|_| Err(anyhow!("the encode thread panicked")));
+            let _ = sender.send((id, result));
+        });
+    }
+
+    // Wait for any running encode to end; None once none are left
+    pub fn next_finished(&mut self) -> Option<(T, Result<()>)> {
+        if self.running.is_empty() {
+            return None;
+        }
+        // The loop holds a sender too, so this only fails if a thread vanished unsent
+        let (id, result) = self.receiver.recv().ok()?;
+        self.running.remove(&id).map(|job| (job, result))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[test]
+    fn runs_at_most_limit_at_once_and_returns_every_job() {
+        let mut workers = Workers::new(2);
+        let (active, peak) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let mut finished = Vec::new();
+        for job in 0..5 {
+            while workers.is_full() {
+                finished.push(workers.next_finished().unwrap());
+            }
+            let (active, peak) = (active.clone(), peak.clone());
+            workers.spawn(job, move || {
+                let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(20));
+                active.fetch_sub(1, Ordering::SeqCst);
+                match job {
+                    3 => Err(anyhow!("job 3 failed")),
+                    _ => Ok(()),
+                }
+            });
+        }
+        while let Some(done) = workers.next_finished() {
+            finished.push(done);
+        }
+
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+        let mut jobs: Vec<(usize, bool)> = finished
+            .iter()
+            .map(|(job, result)| (*job, result.is_ok()))
+            .collect();
+        jobs.sort();
+        assert_eq!(
+            jobs,
+            vec![(0, true), (1, true), (2, true), (3, false), (4, true)]
+        );
+    }
+
+    #[test]
+    fn a_panicking_encode_fails_its_job() {
+        let mut workers = Workers::new(1);
+        workers.spawn("job", || panic!("boom"));
+        let (job, result) = workers.next_finished().unwrap();
+        assert_eq!(job, "job");
+        assert!(result.unwrap_err().to_string().contains("panicked"));
+        assert!(workers.next_finished().is_none());
+    }
+}
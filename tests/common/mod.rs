@@ -1,9 +1,14 @@
 // file: tests/common/mod.rs
-// version: 1.0.0
+// version: 1.5.0
 // guid: 1a2b3c4d-5e6f-7890-abcd-ef1234567890
 
 //! Common test utilities and helpers for integration tests
 
+#![allow(dead_code)]
+
+pub mod scenario;
+
+use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 
@@ -39,6 +44,56 @@ pub fn run_transcoderr(args: &[&str]) -> Result<std::process::Output, std::io::E
     Command::new(binary_path()).args(args).output()
 }
 
+/// Run the transcoderr binary with raw OS string arguments (e.g. non-UTF8 paths)
+pub fn run_transcoderr_os<S: AsRef<OsStr>>(
+    args: &[S],
+) -> Result<std::process::Output, std::io::Error> {
+    Command::new(binary_path()).args(args).output()
+}
+
+/// Install a stand-in `ffprobe` in `dir` that prints `json` for any file, and return a
+/// PATH value that finds it first
+#[cfg(unix)]
+pub fn fake_ffprobe(dir: &Path, json: &str) -> std::ffi::OsString {
+    use std::os::unix::fs::PermissionsExt;
+
+    std::fs::write(dir.join("ffprobe.json"), json).expect("write probe output");
+    let script = dir.join("ffprobe");
+    std::fs::write(
+        &script,
+        format!("#!/bin/sh\ncat '{}'\n", dir.join("ffprobe.json").display()),
+    )
+    .expect("write fake ffprobe");
+    std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).expect("chmod");
+    let mut path = dir.as_os_str().to_owned();
+    path.push(":");
+    path.push(std::env::var_os("PATH").unwrap_or_default());
+    path
+}
+
+/// Install a stand-in `ffmpeg` in `dir` that copies its `-i` input to its last argument
+/// and logs each input to `dir/ffmpeg.log`, and return a PATH value that finds it first
+#[cfg(unix)]
+pub fn fake_ffmpeg(dir: &Path) -> std::ffi::OsString {
+    use std::os::unix::fs::PermissionsExt;
+
+    let script = dir.join("ffmpeg");
+    std::fs::write(
+        &script,
+        format!(
+            "#!/bin/sh\nprev=\nfor arg in \"$@\"; do\n  [ \"$prev\" = -i ] && input=\"$arg\"\n  \
+             prev=\"$arg\"\ndone\necho \"$input\" >> '{}'\ncp \"$input\" \"$prev\"\n",
+            dir.join("ffmpeg.log").display()
+        ),
+    )
+    .expect("write fake ffmpeg");
+    std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).expect("chmod");
+    let mut path = dir.as_os_str().to_owned();
+    path.push(":");
+    path.push(std::env::var_os("PATH").unwrap_or_default());
+    path
+}
+
 /// Check if ffmpeg is available on PATH
 pub fn ffmpeg_available() -> bool {
     Command::new("ffmpeg")
@@ -83,12 +138,16 @@ pub fn file_exists_and_valid(path: &Path) -> bool {
 
 /// List all test media files in testdata directory
 pub fn list_test_media() -> Vec<PathBuf> {
-    let testdata = testdata_dir();
-    if !testdata.exists() {
+    list_media(&testdata_dir())
+}
+
+/// List the media files directly inside `dir`
+pub fn list_media(dir: &Path) -> Vec<PathBuf> {
+    if !dir.exists() {
         return vec![];
     }
 
-    std::fs::read_dir(testdata)
+    std::fs::read_dir(dir)
         .ok()
         .map(|entries| {
             entries
@@ -105,6 +164,29 @@ pub fn list_test_media() -> Vec<PathBuf> {
         .unwrap_or_default()
 }
 
+/// Write the synthetic fixtures into `dir` with `generate-fixtures` and list them;
+/// empty if ffmpeg could not make them
+pub fn generate_fixtures(dir: &Path) -> Vec<PathBuf> {
+    let generated = run_transcoderr_os(&[
+        OsStr::new("generate-fixtures"),
+        OsStr::new("--out"),
+        dir.as_os_str(),
+    ])
+    .map(|output| output.status.success())
+    .unwrap_or(false);
+    if generated { list_media(dir) } else { vec![] }
+}
+
+/// Test media from testdata/, or freshly generated fixtures in `scratch` when there is none
+pub fn test_media_or_fixtures(scratch: &Path) -> Vec<PathBuf> {
+    let media = list_test_media();
+    if media.is_empty() {
+        generate_fixtures(scratch)
+    } else {
+        media
+    }
+}
+
 /// Cleanup temporary test output files
 pub fn cleanup_temp_files(prefix: &str) -> std::io::Result<()> {
     let temp_dir = std::env::temp_dir();
@@ -0,0 +1,274 @@
+// file: tests/common/scenario.rs
+// version: 1.2.0
+// guid: 5d2e8a94-71c3-4f06-b9e5-3a8c17d4f260
+
+//! Synthetic libraries for end-to-end batch scenarios.
+//!
+//! A `Scenario` is a scratch directory holding a library to encode (nested shows, odd
+//! names, sidecars, outputs of earlier runs), an output directory and its own config,
+//! data and cache directories, so runs never see the real user's history or presets.
+//! By default a stand-in `ffmpeg` copies each input to its output and logs it, and
+//! `crash_on` makes it kill transcoderr part way through a file, as a power cut would;
+//! `probe_as_media` adds a stand-in ffprobe for code that checks outputs.
+//! `with_real_ffmpeg` leaves the system's ffmpeg in charge, for generated media.
+
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Output, Stdio};
+
+use tempfile::TempDir;
+
+use super::binary_path;
+
+pub struct Scenario {
+    temp: TempDir,
+    pub library: PathBuf,
+    pub output: PathBuf,
+    // PATH for runs: the stand-in ffmpeg first, when there is one
+    path: OsString,
+}
+
+/// What one transcoderr run printed and how it ended
+pub struct Run {
+    pub output: Output,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl Run {
+    /// Fail the test, with the run's output, unless it succeeded
+    pub fn success(self) -> Self {
+        assert!(
+            self.output.status.success(),
+            "status {:?}\nstdout: {}\nstderr: {}",
+            self.output.status,
+            self.stdout,
+            self.stderr
+        );
+        self
+    }
+
+    /// Assert that stdout or stderr mentions `text`
+    pub fn says(&self, text: &str) -> &Self {
+        assert!(
+            self.stdout.contains(text) || self.stderr.contains(text),
+            "no {:?} in\nstdout: {}\nstderr: {}",
+            text,
+            self.stdout,
+            self.stderr
+        );
+        self
+    }
+}
+
+impl Scenario {
+    /// An empty library with the stand-in ffmpeg
+    #[cfg(unix)]
+    pub fn new() -> Self {
+        use std::os::unix::fs::PermissionsExt;
+
+        let scenario = Self::with_real_ffmpeg();
+        let bin = scenario.temp.path().join("bin");
+        fs::create_dir_all(&bin).expect("create bin");
+        let script = bin.join("ffmpeg");
+        fs::write(
+            &script,
+            format!(
+                "#!/bin/sh\nprev=\nfor arg in \"$@\"; do\n  [ \"$prev\" = -i ] && input=\"$arg\"\n  \
+                 prev=\"$arg\"\ndone\necho \"$input\" >> '{log}'\n\
+                 if [ -f '{crash}' ] && [ \"$(cat '{crash}')\" = \"$(basename \"$input\")\" ]; then\n  \
+                 rm '{crash}'\n  head -c 1 \"$input\" > \"$prev\"\n  kill -9 $PPID\n  exit 1\nfi\n\
+                 cp \"$input\" \"$prev\"\n",
+                log = bin.join("ffmpeg.log").display(),
+                crash = bin.join("crash-on").display(),
+            ),
+        )
+        .expect("write stand-in ffmpeg");
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).expect("chmod");
+        let mut path = bin.into_os_string();
+        path.push(":");
+        path.push(&scenario.path);
+        Self { path, ..scenario }
+    }
+
+    /// An empty library encoded by the ffmpeg on PATH
+    pub fn with_real_ffmpeg() -> Self {
+        let temp = TempDir::new().expect("temp dir");
+        let (library, output) = (temp.path().join("library"), temp.path().join("out"));
+        fs::create_dir_all(&library).expect("create library");
+        Self {
+            temp,
+            library,
+            output,
+            path: std::env::var_os("PATH").unwrap_or_default(),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        self.temp.path()
+    }
+
+    /// Write `contents` to `relative` inside the library, creating its folders
+    pub fn file(&self, relative: &str, contents: &[u8]) -> PathBuf {
+        let path = self.library.join(relative);
+        fs::create_dir_all(path.parent().unwrap()).expect("create folders");
+        fs::write(&path, contents).expect("write library file");
+        path
+    }
+
+    /// Copy a media file (e.g. from `generate_fixtures`) to `relative` in the library
+    pub fn media(&self, relative: &str, source: &Path) -> PathBuf {
+        self.file(relative, &fs::read(source).expect("read media"))
+    }
+
+    /// `Show/Season 01/Show - S01E01.mkv` and on, each with distinct contents
+    pub fn episodes(&self, show: &str, season: u32, count: u32) -> Vec<PathBuf> {
+        (1..=count)
+            .map(|episode| {
+                let name = format!("{} - S{:02}E{:02}.mkv", show, season, episode);
+                let relative = format!("{}/Season {:02}/{}", show, season, name);
+                self.file(&relative, name.as_bytes())
+            })
+            .collect()
+    }
+
+    /// Add a stand-in ffprobe that reads any non-empty file as a 60-second video and
+    /// fails on empty ones
+    #[cfg(unix)]
+    pub fn probe_as_media(&self) {
+        use std::os::unix::fs::PermissionsExt;
+
+        let script = self.temp.path().join("bin").join("ffprobe");
+        fs::write(
+            &script,
+            "#!/bin/sh
+for arg in \"$@\"; do file=\"$arg\"; done
+\
+             [ -s \"$file\" ] || { echo \"$file: Invalid data found\" >&2; exit 1; }
+\
+             echo '{\"streams\": [], \"format\": {\"duration\": \"60.000000\"}}'
+",
+        )
+        .expect("write stand-in ffprobe");
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).expect("chmod");
+    }
+
+    /// Make the stand-in ffmpeg write a truncated output for the input named `name` and
+    /// kill transcoderr, once
+    pub fn crash_on(&self, name: &str) {
+        fs::write(self.temp.path().join("bin").join("crash-on"), name).expect("arm crash");
+    }
+
+    /// Run `transcoderr batch LIBRARY OUT` with `args` after the directories
+    pub fn batch(&self, args: &[&str]) -> Run {
+        let mut command = self.command();
+        command
+            .arg("batch")
+            .arg(&self.library)
+            .arg(&self.output)
+            .args(args);
+        self.run(command)
+    }
+
+    /// Run `transcoderr` with `args` as given
+    pub fn transcoderr(&self, args: &[&str]) -> Run {
+        let mut command = self.command();
+        command.args(args);
+        self.run(command)
+    }
+
+    /// Start `transcoderr` with `args` in the background, its stdout piped to the test
+    pub fn spawn(&self, args: &[&str]) -> Child {
+        self.command()
+            .args(args)
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("start transcoderr")
+    }
+
+    fn command(&self) -> Command {
+        let mut command = Command::new(binary_path());
+        command
+            .current_dir(self.temp.path())
+            .env("PATH", &self.path)
+            .env("XDG_CONFIG_HOME", self.temp.path().join("config"))
+            .env("XDG_DATA_HOME", self.temp.path().join("data"))
+            .env("XDG_CACHE_HOME", self.temp.path().join("cache"));
+        command
+    }
+
+    fn run(&self, mut command: Command) -> Run {
+        let output = command.output().expect("run transcoderr");
+        Run {
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            output,
+        }
+    }
+
+    /// Inputs the stand-in ffmpeg was run on, relative to the library, oldest first
+    pub fn encoded(&self) -> Vec<String> {
+        fs::read_to_string(self.temp.path().join("bin").join("ffmpeg.log"))
+            .unwrap_or_default()
+            .lines()
+            .map(|line| {
+                Path::new(line)
+                    .strip_prefix(&self.library)
+                    .map_or(line.to_string(), |p| p.to_string_lossy().into_owned())
+            })
+            .collect()
+    }
+
+    /// Every file under `dir`, relative to it with `/` separators, sorted
+    pub fn tree(&self, dir: &Path) -> Vec<String> {
+        fn walk(dir: &Path, base: &Path, files: &mut Vec<String>) {
+            for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+                let path = entry.path();
+                if path.is_dir() {
+                    walk(&path, base, files);
+                } else {
+                    let relative = path.strip_prefix(base).unwrap();
+                    let parts: Vec<String> = relative
+                        .components()
+                        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                        .collect();
+                    files.push(parts.join("/"));
+                }
+            }
+        }
+        let mut files = Vec::new();
+        walk(dir, dir, &mut files);
+        files.sort();
+        files
+    }
+
+    /// The encode history's records, oldest first
+    pub fn history(&self) -> Vec<serde_json::Value> {
+        let path = self
+            .temp
+            .path()
+            .join("data")
+            .join("transcoderr")
+            .join("history.ndjson");
+        fs::read_to_string(path)
+            .unwrap_or_default()
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("history record"))
+            .collect()
+    }
+
+    /// Inputs the history records with `status`, relative to the library, oldest first
+    pub fn history_inputs(&self, status: &str) -> Vec<String> {
+        self.history()
+            .iter()
+            .filter(|record| record["status"] == status)
+            .map(|record| {
+                let input = record["input"].as_str().unwrap_or_default();
+                Path::new(input)
+                    .strip_prefix(&self.library)
+                    .map_or(input.to_string(), |p| p.to_string_lossy().into_owned())
+            })
+            .collect()
+    }
+}
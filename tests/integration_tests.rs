@@ -1,5 +1,5 @@
 // file: tests/integration_tests.rs
-// version: 1.4.0
+// version: 1.97.6
 // guid: 2b3c4d5e-6f78-90ab-cdef-0123456789ab
 
 //! Integration tests for transcoderr CLI
@@ -10,6 +10,8 @@ mod common;
 use std::fs;
 use tempfile::TempDir;
 
+use common::scenario::Scenario;
+
 #[test]
 fn test_help_command() {
     let output = common::run_transcoderr(&["--help"]).expect("Failed to run transcoderr --help");
@@ -349,7 +351,8 @@ fn test_transcode_actual_execution() {
         return;
     }
 
-    let test_files = common::list_test_media();
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let test_files = common::test_media_or_fixtures(&temp_dir.path().join("fixtures"));
     if test_files.is_empty() {
         eprintln!("SKIP: No test media files found");
         return;
@@ -357,7 +360,6 @@ fn test_transcode_actual_execution() {
 
     // Use the smallest test file
     let test_file = &test_files[0];
-    let temp_dir = TempDir::new().expect("Failed to create temp dir");
     let output_path = temp_dir.path().join("output.mkv");
 
     let output = common::run_transcoderr(&[
@@ -479,13 +481,16 @@ fn test_batch_actual_execution() {
         return;
     }
 
-    let testdata_dir = common::testdata_dir();
-    if !testdata_dir.exists() || common::list_test_media().is_empty() {
-        eprintln!("SKIP: No test media files found");
-        return;
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let mut testdata_dir = common::testdata_dir();
+    if common::list_test_media().is_empty() {
+        testdata_dir = temp_dir.path().join("fixtures");
+        if common::generate_fixtures(&testdata_dir).is_empty() {
+            eprintln!("SKIP: No test media files found");
+            return;
+        }
     }
 
-    let temp_dir = TempDir::new().expect("Failed to create temp dir");
     let output_dir = temp_dir.path().join("output");
 
     let output = common::run_transcoderr(&[
@@ -553,3 +558,5470 @@ fn test_invalid_preset_shows_error() {
         "Invalid preset should produce error or warning"
     );
 }
+
+#[cfg(unix)]
+#[test]
+fn test_batch_dry_run_continues_past_unreadable_subdir() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp = TempDir::new().expect("temp dir");
+    fs::write(temp.path().join("readable.mp4"), b"\n").expect("write file");
+    let locked = temp.path().join("locked");
+    fs::create_dir(&locked).expect("create locked dir");
+    fs::write(locked.join("hidden.mp4"), b"\n").expect("write hidden file");
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).expect("chmod");
+
+    // Privileged users (e.g. root in containers) bypass mode bits; nothing to test then
+    if fs::read_dir(&locked).is_ok() {
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).ok();
+        eprintln!("SKIP: permissions are not enforced for this user");
+        return;
+    }
+
+    let out_dir = temp.path().join("out");
+    let output = common::run_transcoderr(&[
+        "batch",
+        temp.path().to_str().unwrap(),
+        out_dir.to_str().unwrap(),
+        "--dry-run",
+    ])
+    .expect("run batch");
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).ok();
+
+    assert!(
+        output.status.success(),
+        "Unreadable subdir should not abort"
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("readable.mp4"), "stdout: {}", stdout);
+    assert!(stdout.contains("Permission denied"), "stdout: {}", stdout);
+    assert!(stdout.contains("locked"), "stdout: {}", stdout);
+}
+
+#[test]
+fn test_batch_dry_run_unicode_filenames() {
+    let temp = TempDir::new().expect("temp dir");
+    let names = ["🎬 Movie Night 🍿.mp4", "千と千尋の神隠し (2001).mkv"];
+    for name in names.iter() {
+        fs::write(temp.path().join(name), b"\n").expect("write file");
+    }
+
+    let output = common::run_transcoderr(&[
+        "batch",
+        temp.path().to_str().unwrap(),
+        temp.path().to_str().unwrap(),
+        "--dry-run",
+    ])
+    .expect("run batch");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Found 2 files"), "stdout: {}", stdout);
+    assert!(
+        stdout.contains("🎬 Movie Night 🍿_transcoded.mkv"),
+        "stdout: {}",
+        stdout
+    );
+    assert!(
+        stdout.contains("千と千尋の神隠し (2001)_transcoded.mkv"),
+        "stdout: {}",
+        stdout
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_non_utf8_filenames_are_not_mangled() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let temp = TempDir::new().expect("temp dir");
+    // "café.mkv" encoded as Latin-1: 0xE9 is not valid UTF-8 on its own
+    let latin1 = OsStr::from_bytes(b"caf\xe9.mkv");
+    let input = temp.path().join(latin1);
+    if fs::write(&input, b"\n").is_err() {
+        eprintln!("SKIP: filesystem rejects non-UTF8 filenames");
+        return;
+    }
+
+    let batch = common::run_transcoderr_os(&[
+        OsStr::new("batch"),
+        temp.path().as_os_str(),
+        temp.path().as_os_str(),
+        OsStr::new("--dry-run"),
+    ])
+    .expect("run batch");
+    assert!(batch.status.success());
+    let stdout = String::from_utf8_lossy(&batch.stdout);
+    assert!(stdout.contains("Found 1 files"), "stdout: {}", stdout);
+
+    let transcode = common::run_transcoderr_os(&[
+        OsStr::new("transcode"),
+        input.as_os_str(),
+        OsStr::new("--dry-run"),
+    ])
+    .expect("run transcode");
+    assert!(transcode.status.success());
+    let stdout = String::from_utf8_lossy(&transcode.stdout);
+    // The stem keeps its raw bytes (shown lossily) instead of falling back to "output"
+    assert!(
+        stdout.contains("caf\u{FFFD}_transcoded.mkv"),
+        "stdout: {}",
+        stdout
+    );
+}
+
+#[test]
+fn test_batch_dry_run_detects_output_collisions() {
+    let temp = TempDir::new().expect("temp dir");
+    fs::write(temp.path().join("clip.mp4"), b"\n").expect("write mp4");
+    fs::write(temp.path().join("clip.mkv"), b"\n").expect("write mkv");
+    let out_dir = temp.path().join("out");
+
+    let output = common::run_transcoderr(&[
+        "batch",
+        temp.path().to_str().unwrap(),
+        out_dir.to_str().unwrap(),
+        "--dry-run",
+    ])
+    .expect("run batch");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("both map to"), "stderr: {}", stderr);
+    assert!(stdout.contains("[1/1]"), "stdout: {}", stdout);
+    assert!(
+        stdout.contains("1 skipped due to output name collisions"),
+        "stdout: {}",
+        stdout
+    );
+}
+
+#[test]
+fn test_read_only_refuses_encoding_but_allows_dry_run() {
+    let temp = TempDir::new().expect("temp dir");
+    let input = temp.path().join("input.mp4");
+    fs::write(&input, b"\n").expect("write input");
+    let out = temp.path().join("out.mkv");
+
+    let refused = common::run_transcoderr(&[
+        "--read-only",
+        "transcode",
+        input.to_str().unwrap(),
+        out.to_str().unwrap(),
+    ])
+    .expect("run transcode");
+    assert!(!refused.status.success(), "read-only must refuse encodes");
+    let stderr = String::from_utf8_lossy(&refused.stderr);
+    assert!(stderr.contains("read-only"), "stderr: {}", stderr);
+    assert!(!out.exists());
+
+    let batch = common::run_transcoderr(&[
+        "batch",
+        temp.path().to_str().unwrap(),
+        temp.path().join("out").to_str().unwrap(),
+        "--offline",
+    ])
+    .expect("run batch");
+    assert!(!batch.status.success(), "--offline alias must refuse batch");
+
+    let dry = common::run_transcoderr(&[
+        "--read-only",
+        "transcode",
+        input.to_str().unwrap(),
+        out.to_str().unwrap(),
+        "--dry-run",
+    ])
+    .expect("run dry-run");
+    assert!(
+        dry.status.success(),
+        "dry runs are allowed in read-only mode"
+    );
+}
+
+#[test]
+fn test_chown_chmod_dry_run_and_validation() {
+    let temp = TempDir::new().expect("temp dir");
+    let input = temp.path().join("input.mp4");
+    fs::write(&input, b"\n").expect("write input");
+    let out = temp.path().join("out.mkv");
+
+    let output = common::run_transcoderr(&[
+        "transcode",
+        input.to_str().unwrap(),
+        out.to_str().unwrap(),
+        "--chown",
+        "1000:1000",
+        "--chmod",
+        "0664",
+        "--dry-run",
+    ])
+    .expect("run transcode");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("owner 1000:1000 and mode 0664"),
+        "stdout: {}",
+        stdout
+    );
+
+    let bad_mode = common::run_transcoderr(&[
+        "transcode",
+        input.to_str().unwrap(),
+        "--chmod",
+        "0999",
+        "--dry-run",
+    ])
+    .expect("run transcode");
+    assert!(
+        !bad_mode.status.success(),
+        "non-octal mode must be rejected"
+    );
+
+    let bad_user = common::run_transcoderr(&[
+        "transcode",
+        input.to_str().unwrap(),
+        "--chown",
+        "no-such-user-transcoderr:no-such-group",
+        "--dry-run",
+    ])
+    .expect("run transcode");
+    assert!(!bad_user.status.success(), "unknown user must be rejected");
+}
+
+#[test]
+fn test_subtitle_default_policy_from_cli_and_config() {
+    let temp = TempDir::new().expect("temp dir");
+    let input = temp.path().join("input.mkv");
+    fs::write(&input, b"\n").expect("write input");
+
+    let invalid = common::run_transcoderr(&[
+        "transcode",
+        input.to_str().unwrap(),
+        "--subtitle-default",
+        "forced:english",
+        "--dry-run",
+    ])
+    .expect("run transcode");
+    assert!(!invalid.status.success(), "invalid policy must be rejected");
+
+    let config = temp.path().join("config.toml");
+    fs::write(&config, "subtitle-default = \"forced:eng, else none\"\n").expect("write config");
+    let valid = common::run_transcoderr(&[
+        "--config",
+        config.to_str().unwrap(),
+        "transcode",
+        input.to_str().unwrap(),
+        "--dry-run",
+    ])
+    .expect("run transcode");
+    assert!(
+        valid.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&valid.stderr)
+    );
+
+    fs::write(&config, "subtitle-defualt = \"none\"\n").expect("write config");
+    let typo = common::run_transcoderr(&[
+        "--config",
+        config.to_str().unwrap(),
+        "transcode",
+        input.to_str().unwrap(),
+        "--dry-run",
+    ])
+    .expect("run transcode");
+    assert!(
+        !typo.status.success(),
+        "unknown config keys must be rejected"
+    );
+}
+
+#[test]
+fn test_batch_dry_run_groups_by_show_with_overrides() {
+    let temp = TempDir::new().expect("temp dir");
+    let library = temp.path().join("tv");
+    for rel in [
+        "Show A/Season 01/Show A - S01E01.mkv",
+        "Show A/Season 02/Show A - S02E01.mkv",
+        "Other.Show.S03E04.mkv",
+    ] {
+        let path = library.join(rel);
+        fs::create_dir_all(path.parent().unwrap()).expect("create dirs");
+        fs::write(&path, b"\n").expect("write file");
+    }
+    let shows = temp.path().join("shows.toml");
+    fs::write(
+        &shows,
+        "[\"show a\"]\nacodec = \"libopus\"\ncrop = \"1920:800:0:140\"\n",
+    )
+    .expect("write shows");
+
+    let output = common::run_transcoderr(&[
+        "batch",
+        library.to_str().unwrap(),
+        temp.path().join("out").to_str().unwrap(),
+        "--shows",
+        shows.to_str().unwrap(),
+        "--dry-run",
+    ])
+    .expect("run batch");
+
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("== Show A / Season 1 =="),
+        "stdout: {}",
+        stdout
+    );
+    assert!(
+        stdout.contains("== Other Show / Season 3 =="),
+        "stdout: {}",
+        stdout
+    );
+    assert!(stdout.contains("acodec=libopus"), "stdout: {}", stdout);
+    assert!(stdout.contains("crop=1920:800:0:140"), "stdout: {}", stdout);
+    // The per-show summary is a table: show, files, size, then the seasons
+    assert!(
+        stdout.lines().any(|line| line.starts_with("  Show A ")
+            && line.contains("  2  ")
+            && line.ends_with("  S01: 1, S02: 1")),
+        "stdout: {}",
+        stdout
+    );
+}
+
+#[test]
+fn test_batch_missing_lists_inputs_without_outputs() {
+    let temp = TempDir::new().expect("temp dir");
+    let library = temp.path().join("library");
+    let out = temp.path().join("out");
+    for rel in ["a.mp4", "season/b.mp4", "season/c.mkv"] {
+        let path = library.join(rel);
+        fs::create_dir_all(path.parent().unwrap()).expect("create dirs");
+        fs::write(&path, b"\n").expect("write input");
+    }
+    // a.mp4 is done; c.mkv only has the empty leftover of a failed encode
+    fs::create_dir_all(out.join("season")).expect("create out");
+    fs::write(out.join("a.mkv"), b"done").expect("write output");
+    fs::write(out.join("season/c.mkv"), b"").expect("write output");
+
+    let output = common::run_transcoderr(&[
+        "batch",
+        "missing",
+        library.to_str().unwrap(),
+        out.to_str().unwrap(),
+    ])
+    .expect("run batch missing");
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("2 of 3 inputs have no output"),
+        "stdout: {}",
+        stdout
+    );
+    assert!(stdout.contains("b.mp4"), "stdout: {}", stdout);
+    assert!(!stdout.contains("a.mp4"), "stdout: {}", stdout);
+
+    // --run queues only the missing inputs
+    let output = common::run_transcoderr(&[
+        "batch",
+        "missing",
+        library.to_str().unwrap(),
+        out.to_str().unwrap(),
+        "--run",
+        "--dry-run",
+    ])
+    .expect("run batch missing --run");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Found 2 files"), "stdout: {}", stdout);
+    assert!(stdout.contains("[2/2]"), "stdout: {}", stdout);
+
+    // Listing is analysis-only, so it is allowed in read-only mode; queueing is not
+    let listing = common::run_transcoderr(&[
+        "--read-only",
+        "batch",
+        "missing",
+        library.to_str().unwrap(),
+        out.to_str().unwrap(),
+    ])
+    .expect("run read-only listing");
+    assert!(listing.status.success());
+    let queued = common::run_transcoderr(&[
+        "--read-only",
+        "batch",
+        "missing",
+        library.to_str().unwrap(),
+        out.to_str().unwrap(),
+        "--run",
+    ])
+    .expect("run read-only queue");
+    assert!(!queued.status.success());
+}
+
+#[test]
+fn test_batch_in_place_skips_previous_outputs() {
+    let temp = TempDir::new().expect("temp dir");
+    let dir = temp.path();
+    fs::write(dir.join("clip.mp4"), b"\n").expect("write input");
+    fs::write(dir.join("clip_transcoded.mkv"), b"done").expect("write output");
+
+    let output = common::run_transcoderr(&[
+        "batch",
+        "missing",
+        dir.to_str().unwrap(),
+        dir.to_str().unwrap(),
+    ])
+    .expect("run batch missing");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("0 of 1 inputs have no output"),
+        "stdout: {}",
+        stdout
+    );
+}
+
+#[test]
+fn test_batch_prune_removes_orphaned_outputs() {
+    let temp = TempDir::new().expect("temp dir");
+    let library = temp.path().join("library");
+    let out = temp.path().join("out");
+    fs::create_dir_all(&library).expect("create library");
+    fs::write(library.join("kept.mp4"), b"\n").expect("write input");
+    fs::create_dir_all(out.join("gone")).expect("create out");
+    fs::write(out.join("kept.mkv"), b"done").expect("write output");
+    fs::write(out.join("gone/renamed.mkv"), b"done").expect("write orphan");
+    fs::write(out.join("gone/notes.txt"), b"not an output").expect("write other");
+
+    let lib = library.to_str().unwrap();
+    let out_str = out.to_str().unwrap();
+    let listing = common::run_transcoderr(&["batch", "prune", lib, out_str]).expect("run prune");
+    assert!(listing.status.success());
+    let stdout = String::from_utf8_lossy(&listing.stdout);
+    assert!(stdout.contains("1 of 2 outputs"), "stdout: {}", stdout);
+    assert!(stdout.contains("renamed.mkv"), "stdout: {}", stdout);
+    assert!(
+        out.join("gone/renamed.mkv").exists(),
+        "listing must not remove"
+    );
+
+    // Removing is refused in read-only mode
+    let refused =
+        common::run_transcoderr(&["--read-only", "batch", "prune", lib, out_str, "--delete"])
+            .expect("run read-only prune");
+    assert!(!refused.status.success());
+
+    let quarantine = temp.path().join("quarantine");
+    let removed = common::run_transcoderr(&[
+        "batch",
+        "prune",
+        lib,
+        out_str,
+        "--delete",
+        "--quarantine",
+        quarantine.to_str().unwrap(),
+    ])
+    .expect("run prune --delete");
+    assert!(
+        removed.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&removed.stderr)
+    );
+    assert!(!out.join("gone/renamed.mkv").exists());
+    assert!(quarantine.join("gone/renamed.mkv").exists());
+    assert!(out.join("kept.mkv").exists());
+    assert!(out.join("gone/notes.txt").exists());
+}
+
+#[test]
+fn test_batch_prune_refuses_when_source_tree_is_empty() {
+    let temp = TempDir::new().expect("temp dir");
+    let library = temp.path().join("library");
+    let out = temp.path().join("out");
+    fs::create_dir_all(&library).expect("create library");
+    fs::create_dir_all(&out).expect("create out");
+    fs::write(out.join("episode.mkv"), b"done").expect("write output");
+
+    let output = common::run_transcoderr(&[
+        "batch",
+        "prune",
+        library.to_str().unwrap(),
+        out.to_str().unwrap(),
+        "--delete",
+        "--purge",
+    ])
+    .expect("run prune");
+    assert!(
+        !output.status.success(),
+        "an empty source tree must not wipe outputs"
+    );
+    assert!(out.join("episode.mkv").exists());
+}
+
+#[test]
+fn test_batch_event_log_records_lifecycle() {
+    let temp = TempDir::new().expect("temp dir");
+    let library = temp.path().join("library");
+    fs::create_dir_all(&library).expect("create library");
+    // Not real media: the encode fails whether or not ffmpeg is installed
+    fs::write(library.join("broken.mp4"), b"not media").expect("write input");
+    let log = temp.path().join("events.ndjson");
+
+    let dry = common::run_transcoderr(&[
+        "batch",
+        library.to_str().unwrap(),
+        temp.path().join("out").to_str().unwrap(),
+        "--event-log",
+        log.to_str().unwrap(),
+        "--dry-run",
+    ])
+    .expect("run dry batch");
+    assert!(dry.status.success());
+    assert!(!log.exists(), "dry runs must not write events");
+
+    let output = common::run_transcoderr(&[
+        "batch",
+        library.to_str().unwrap(),
+        temp.path().join("out").to_str().unwrap(),
+        "--event-log",
+        log.to_str().unwrap(),
+    ])
+    .expect("run batch");
+    assert!(output.status.success());
+
+    let text = fs::read_to_string(&log).expect("read event log");
+    let events: Vec<String> = text
+        .lines()
+        .map(|line| {
+            let value: serde_json::Value = serde_json::from_str(line).expect("valid JSON line");
+            value["event"].as_str().expect("event name").to_string()
+        })
+        .collect();
+    assert_eq!(
+        events,
+        ["scan", "plan", "start", "fail", "progress", "batch_done"]
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_batch_should_run_hook_pauses_and_resumes() {
+    let temp = TempDir::new().expect("temp dir");
+    let library = temp.path().join("library");
+    fs::create_dir_all(&library).expect("create library");
+    fs::write(library.join("broken.mp4"), b"not media").expect("write input");
+    let flag = temp.path().join("asked");
+    let seen = temp.path().join("seen");
+    let log = temp.path().join("events.ndjson");
+    // Says "no" the first time it is asked, "yes" afterwards
+    let config = temp.path().join("config.toml");
+    fs::write(
+        &config,
+        format!(
+            "[should-run]\ncommand = \"echo $TRANSCODERR_INPUT > '{}'; test -f '{}' || {{ touch '{}'; exit 1; }}\"\npoll-seconds = 1\n",
+            seen.display(),
+            flag.display(),
+            flag.display()
+        ),
+    )
+    .expect("write config");
+
+    let output = common::run_transcoderr(&[
+        "--config",
+        config.to_str().unwrap(),
+        "--event-log",
+        log.to_str().unwrap(),
+        "batch",
+        library.to_str().unwrap(),
+        temp.path().join("out").to_str().unwrap(),
+    ])
+    .expect("run batch");
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Paused by should-run hook"),
+        "stdout: {}",
+        stdout
+    );
+    assert!(stdout.contains("Resuming"), "stdout: {}", stdout);
+    let seen = fs::read_to_string(&seen).expect("hook saw the job");
+    assert!(seen.contains("broken.mp4"), "hook env: {}", seen);
+    let events = fs::read_to_string(&log).expect("read event log");
+    assert!(events.contains("\"event\":\"paused\""), "{}", events);
+    assert!(events.contains("\"event\":\"resumed\""), "{}", events);
+}
+
+#[test]
+fn test_should_run_hook_requires_one_source() {
+    let temp = TempDir::new().expect("temp dir");
+    let config = temp.path().join("config.toml");
+    fs::write(&config, "[should-run]\npoll-seconds = 5\n").expect("write config");
+    let output = common::run_transcoderr(&[
+        "--config",
+        config.to_str().unwrap(),
+        "batch",
+        temp.path().to_str().unwrap(),
+        temp.path().join("out").to_str().unwrap(),
+        "--dry-run",
+    ])
+    .expect("run batch");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("should-run"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_batch_pauses_while_too_hot() {
+    let temp = TempDir::new().expect("temp dir");
+    let library = temp.path().join("library");
+    fs::create_dir_all(&library).expect("create library");
+    fs::write(library.join("broken.mp4"), b"not media").expect("write input");
+    let flag = temp.path().join("read");
+    // 95°C on the first reading, cooled down afterwards
+    let config = temp.path().join("config.toml");
+    fs::write(
+        &config,
+        format!(
+            "[temperature]\nmax-celsius = 80\nresume-celsius = 60\npoll-seconds = 1\ncommand = \"test -f '{}' && echo 45 || {{ touch '{}'; echo 'CPU: 95.0 C'; }}\"\n",
+            flag.display(),
+            flag.display()
+        ),
+    )
+    .expect("write config");
+
+    let output = common::run_transcoderr(&[
+        "--config",
+        config.to_str().unwrap(),
+        "batch",
+        library.to_str().unwrap(),
+        temp.path().join("out").to_str().unwrap(),
+    ])
+    .expect("run batch");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Paused: 95.0°C reached the 80.0°C limit"),
+        "stdout: {}",
+        stdout
+    );
+    assert!(stdout.contains("Resuming"), "stdout: {}", stdout);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_running_encodes_pause_while_too_hot() {
+    use std::io::Read;
+    use std::os::unix::fs::PermissionsExt;
+    use std::time::{Duration, Instant};
+
+    let scenario = Scenario::new();
+    scenario.file("a.mkv", b"first");
+    let (bin, root) = (scenario.root().join("bin"), scenario.root());
+    let (pid, reading) = (root.join("ffmpeg.pid"), root.join("celsius"));
+    // An encode long enough to be caught running
+    let ffmpeg = bin.join("ffmpeg");
+    fs::write(
+        &ffmpeg,
+        format!(
+            "#!/bin/sh
+echo $$ > '{}'
+for arg in \"$@\"; do out=\"$arg\"; done
+             for i in 1 2 3 4 5 6 7 8 9 10; do sleep 0.2; done
+echo done > \"$out\"
+",
+            pid.display()
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&ffmpeg, fs::Permissions::from_mode(0o755)).unwrap();
+    fs::write(&reading, "45").unwrap();
+    let config = root.join("config.toml");
+    fs::write(
+        &config,
+        format!(
+            "[temperature]\nmax-celsius = 80\nresume-celsius = 60\npoll-seconds = 1\ncommand = \"cat '{}'\"\n",
+            reading.display()
+        ),
+    )
+    .unwrap();
+
+    let mut watch = scenario.spawn(&[
+        "--config",
+        config.to_str().unwrap(),
+        "watch",
+        root.join("library").to_str().unwrap(),
+        root.join("out").to_str().unwrap(),
+        "--once",
+        "--settle",
+        "0",
+    ]);
+    // ffmpeg's state letter from /proc, `T` when stopped
+    let state = |pid: &str| {
+        let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).unwrap_or_default();
+        stat.rsplit(") ")
+            .next()
+            .and_then(|rest| rest.chars().next())
+    };
+    let wait_for = |what: &dyn Fn() -> bool| {
+        let started = Instant::now();
+        while !what() && started.elapsed() < Duration::from_secs(10) {
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        what()
+    };
+    assert!(wait_for(&|| pid.exists()), "ffmpeg never started");
+    let ffmpeg_pid = fs::read_to_string(&pid).unwrap().trim().to_string();
+    fs::write(&reading, "95").unwrap();
+    assert!(wait_for(&|| state(&ffmpeg_pid) == Some('T')), "not paused");
+    fs::write(&reading, "55").unwrap();
+    assert!(watch.wait().unwrap().success());
+    let mut stdout = String::new();
+    watch
+        .stdout
+        .take()
+        .unwrap()
+        .read_to_string(&mut stdout)
+        .unwrap();
+    assert!(
+        stdout.contains("Pausing running encodes: 95.0°C reached the 80.0°C limit"),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains("Continuing paused encodes at 55.0°C"),
+        "{}",
+        stdout
+    );
+    assert_eq!(fs::read(root.join("out/a.mkv")).unwrap(), b"done\n");
+}
+
+#[test]
+fn test_presets_export_import_round_trip() {
+    let temp = TempDir::new().expect("temp dir");
+    let config_home = temp.path().join("config");
+    let run = |args: &[&str]| {
+        std::process::Command::new(common::binary_path())
+            .args(args)
+            .env("XDG_CONFIG_HOME", &config_home)
+            .output()
+            .expect("run transcoderr")
+    };
+
+    let exported = run(&["presets", "export", "movie"]);
+    assert!(exported.status.success());
+    let text = String::from_utf8_lossy(&exported.stdout).to_string();
+    assert!(
+        text.contains("name = \"movie-quality\""),
+        "export: {}",
+        text
+    );
+    let stderr = String::from_utf8_lossy(&exported.stderr);
+    assert!(stderr.starts_with("sha256 "), "stderr: {}", stderr);
+
+    // Re-importing a built-in under its own name would shadow it
+    let file = temp.path().join("shared.toml");
+    fs::write(&file, &text).expect("write preset");
+    assert!(
+        !run(&["presets", "import", file.to_str().unwrap(), "--yes"])
+            .status
+            .success()
+    );
+
+    let shared = "name = \"grainy-film\"\nvcodec = \"libsvtav1\"\nextra = [\"-crf\", \"30\"]\n";
+    fs::write(&file, shared).expect("write preset");
+    // No terminal to confirm on and no --yes
+    assert!(
+        !run(&["presets", "import", file.to_str().unwrap()])
+            .status
+            .success()
+    );
+    assert!(
+        !run(&[
+            "presets",
+            "import",
+            file.to_str().unwrap(),
+            "--yes",
+            "--sha256",
+            "00"
+        ])
+        .status
+        .success()
+    );
+    let imported = run(&["presets", "import", file.to_str().unwrap(), "--yes"]);
+    assert!(
+        imported.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&imported.stderr)
+    );
+    assert!(
+        config_home
+            .join("transcoderr/presets/grainy-film.toml")
+            .exists()
+    );
+
+    let dry = run(&[
+        "transcode",
+        "in.mp4",
+        "--preset",
+        "grainy-film",
+        "--dry-run",
+    ]);
+    let stdout = String::from_utf8_lossy(&dry.stdout);
+    assert!(stdout.contains("vcodec=libsvtav1"), "stdout: {}", stdout);
+    assert!(stdout.contains("-crf 30"), "stdout: {}", stdout);
+}
+
+#[test]
+fn test_anime_preset_keeps_attachments_without_duplicate_maps() {
+    let output = common::run_transcoderr(&[
+        "transcode",
+        "episode.mkv",
+        "--preset",
+        "anime",
+        "--extra=-map 0:s?",
+        "--dry-run",
+    ])
+    .expect("run transcode");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("acodec=libopus"), "stdout: {}", stdout);
+    let command = stdout
+        .lines()
+        .find(|line| line.trim_start().starts_with("ffmpeg "))
+        .expect("ffmpeg command line");
+    assert!(command.contains("-tune animation"), "command: {}", command);
+    assert!(command.contains("-map 0:t?"), "command: {}", command);
+    assert_eq!(
+        command.matches("-map 0:s?").count(),
+        1,
+        "command: {}",
+        command
+    );
+}
+
+#[test]
+fn test_screencast_preset_dry_run() {
+    let output = common::run_transcoderr(&[
+        "transcode",
+        "capture.mov",
+        "--preset",
+        "screencast-30fps",
+        "--dry-run",
+    ])
+    .expect("run transcode");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("vcodec=libx264"), "stdout: {}", stdout);
+    assert!(stdout.contains("-tune stillimage"), "stdout: {}", stdout);
+    assert!(stdout.contains("-ac 1"), "stdout: {}", stdout);
+    assert!(stdout.contains("-r 30"), "stdout: {}", stdout);
+}
+
+#[test]
+fn test_home_video_stabilize_dry_run() {
+    let output = common::run_transcoderr(&[
+        "transcode",
+        "clip.mov",
+        "--preset",
+        "home-video",
+        "--stabilize",
+        "--extra=-vf crop=1920:1080",
+        "--dry-run",
+    ])
+    .expect("run transcode");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let commands: Vec<&str> = stdout
+        .lines()
+        .filter(|line| line.trim_start().starts_with("ffmpeg "))
+        .collect();
+    assert_eq!(commands.len(), 2, "stdout: {}", stdout);
+    assert!(commands[0].contains("vidstabdetect="), "stdout: {}", stdout);
+    assert!(commands[0].ends_with("-f null -"), "stdout: {}", stdout);
+    let encode = commands[1];
+    assert!(
+        encode.contains("-fps_mode passthrough"),
+        "command: {}",
+        encode
+    );
+    assert!(
+        encode.contains("-metadata com.apple.quicktime.location.ISO6709="),
+        "command: {}",
+        encode
+    );
+    // The transform joins the user's filter instead of replacing it
+    assert_eq!(encode.matches("-vf ").count(), 1, "command: {}", encode);
+    assert!(
+        encode.contains("-vf crop=1920:1080,vidstabtransform="),
+        "command: {}",
+        encode
+    );
+
+    let gps = common::run_transcoderr(&[
+        "transcode",
+        "clip.mov",
+        "--preset",
+        "home-video-gps",
+        "--dry-run",
+    ])
+    .expect("run transcode");
+    let stdout = String::from_utf8_lossy(&gps.stdout);
+    assert!(!stdout.contains("location"), "stdout: {}", stdout);
+}
+
+#[test]
+fn test_audiobook_dry_run_groups_folders() {
+    let temp = tempfile::tempdir().expect("create temp dir");
+    let books = temp.path().join("books");
+    let dune = books.join("Herbert").join("Dune");
+    let single = books.join("Single");
+    std::fs::create_dir_all(&dune).unwrap();
+    std::fs::create_dir_all(&single).unwrap();
+    for name in ["Part 1.mp3", "Part 2.mp3", "Part 10.mp3", "cover.jpg"] {
+        std::fs::write(dune.join(name), b"").unwrap();
+    }
+    std::fs::write(single.join("book.flac"), b"").unwrap();
+    std::fs::write(
+        single.join("book.cue"),
+        "FILE \"book.flac\" WAVE\n  TRACK 01 AUDIO\n    INDEX 01 00:00:00\n  TRACK 02 AUDIO\n    INDEX 01 10:00:00\n",
+    )
+    .unwrap();
+    let out = temp.path().join("out");
+
+    let output = common::run_transcoderr(&[
+        "audiobook",
+        books.to_str().unwrap(),
+        out.to_str().unwrap(),
+        "--dry-run",
+    ])
+    .expect("run audiobook");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Found 2 books in 4 audio files (codec=aac, bitrate=64k, mono)"),
+        "stdout: {}",
+        stdout
+    );
+    assert!(
+        stdout.contains("Would join 3 files (3 chapters)"),
+        "stdout: {}",
+        stdout
+    );
+    assert!(
+        stdout.contains("Would join 1 files (2 chapters from the cue sheet)"),
+        "stdout: {}",
+        stdout
+    );
+    let dune_out = out.join("Herbert").join("Dune.m4b");
+    let command = stdout
+        .lines()
+        .find(|line| line.contains(dune_out.to_str().unwrap()) && line.contains("ffmpeg "))
+        .expect("ffmpeg command for Dune");
+    assert!(command.contains("cover.jpg"), "command: {}", command);
+    assert!(command.contains("attached_pic"), "command: {}", command);
+    assert!(command.contains("-ac 1 -f ipod"), "command: {}", command);
+    assert!(!out.exists(), "dry run must not create outputs");
+}
+
+#[test]
+fn test_batch_splits_cue_sheet_albums() {
+    let temp = tempfile::tempdir().expect("create temp dir");
+    let music = temp.path().join("music");
+    let album = music.join("Artist").join("Album");
+    std::fs::create_dir_all(&album).unwrap();
+    std::fs::write(album.join("album.flac"), b"").unwrap();
+    // Rippers often name the uncompressed image; the scanned FLAC with the same stem
+    // next to the sheet is used instead
+    std::fs::write(
+        album.join("album.cue"),
+        "REM DATE 1999\nREM GENRE Classic Rock\nPERFORMER \"The Band\"\nTITLE \"Album\"\n\
+         FILE \"album.wav\" WAVE\n\
+           TRACK 01 AUDIO\n    TITLE \"Intro\"\n    INDEX 01 00:00:00\n\
+           TRACK 02 AUDIO\n    TITLE \"What/Why?\"\n    INDEX 00 03:10:00\n    INDEX 01 03:12:30\n",
+    )
+    .unwrap();
+    let out = temp.path().join("out");
+
+    let output = common::run_transcoderr(&[
+        "batch",
+        music.to_str().unwrap(),
+        out.to_str().unwrap(),
+        "--input-exts",
+        "flac",
+        "--acodec",
+        "libopus",
+        "--ext",
+        "opus",
+        "--dry-run",
+    ])
+    .expect("run batch");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Found 2 files"), "stdout: {}", stdout);
+    let second = out.join("Artist").join("Album").join("02 - What_Why_.opus");
+    assert!(
+        stdout.contains(second.to_str().unwrap()),
+        "stdout: {}",
+        stdout
+    );
+    assert!(
+        stdout.contains(r#""-ss", "0.000", "-to", "192.400""#),
+        "stdout: {}",
+        stdout
+    );
+    assert!(
+        stdout.contains(r#""-ss", "192.400", "-metadata""#),
+        "stdout: {}",
+        stdout
+    );
+    assert!(stdout.contains(r#""track=2/2""#), "stdout: {}", stdout);
+    assert!(
+        stdout.contains(r#""genre=Classic Rock""#),
+        "stdout: {}",
+        stdout
+    );
+    assert!(
+        stdout.contains(r#""album_artist=The Band""#),
+        "stdout: {}",
+        stdout
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_recommend_and_auto_preset() {
+    let temp = TempDir::new().expect("temp dir");
+    let bin = temp.path().join("bin");
+    fs::create_dir(&bin).unwrap();
+    let path = common::fake_ffprobe(
+        &bin,
+        r#"{"streams": [
+            {"codec_type": "video", "codec_name": "h264", "width": 1920, "height": 1080,
+             "avg_frame_rate": "24000/1001", "bit_rate": "6000000"},
+            {"codec_type": "subtitle", "codec_name": "ass"},
+            {"codec_type": "attachment", "codec_name": "ttf"}
+        ], "format": {"duration": "1420.0"}}"#,
+    );
+    let input = temp.path().join("episode.mkv");
+    fs::write(&input, b"\n").unwrap();
+
+    let output = std::process::Command::new(common::binary_path())
+        .args(["recommend", input.to_str().unwrap()])
+        .env("PATH", &path)
+        .env("XDG_CACHE_HOME", temp.path().join("cache"))
+        .output()
+        .expect("run recommend");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("episode.mkv: anime"), "stdout: {}", stdout);
+    assert!(
+        stdout.contains("animation: styled ASS subtitles with embedded fonts"),
+        "stdout: {}",
+        stdout
+    );
+    assert!(
+        stdout.contains("grain estimate: 0.121"),
+        "stdout: {}",
+        stdout
+    );
+
+    let output = std::process::Command::new(common::binary_path())
+        .args([
+            "transcode",
+            input.to_str().unwrap(),
+            "--preset",
+            "auto",
+            "--dry-run",
+        ])
+        .env("PATH", &path)
+        .env("XDG_CACHE_HOME", temp.path().join("cache"))
+        .output()
+        .expect("run transcode");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("auto preset: anime ("),
+        "stdout: {}",
+        stdout
+    );
+    assert!(stdout.contains("-tune animation"), "stdout: {}", stdout);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_batch_auto_preset_per_file() {
+    let temp = TempDir::new().expect("temp dir");
+    let bin = temp.path().join("bin");
+    fs::create_dir(&bin).unwrap();
+    let path = common::fake_ffprobe(
+        &bin,
+        r#"{"streams": [
+            {"codec_type": "video", "codec_name": "h264", "width": 1920, "height": 1080,
+             "avg_frame_rate": "24/1", "bit_rate": "8000000"}
+        ], "format": {"duration": "7000.0"}}"#,
+    );
+    let library = temp.path().join("library");
+    fs::create_dir_all(library.join("Anime")).unwrap();
+    fs::create_dir_all(library.join("Films")).unwrap();
+    fs::write(library.join("Anime").join("Film.mkv"), b"\n").unwrap();
+    fs::write(library.join("Films").join("Film.mkv"), b"\n").unwrap();
+
+    let output = std::process::Command::new(common::binary_path())
+        .args([
+            "batch",
+            library.to_str().unwrap(),
+            temp.path().join("out").to_str().unwrap(),
+            "--preset",
+            "auto",
+            "--dry-run",
+        ])
+        .env("PATH", &path)
+        .env("XDG_CACHE_HOME", temp.path().join("cache"))
+        .output()
+        .expect("run batch");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stdout.contains("Found 2 files to transcode (preset=auto, ext=mkv)"),
+        "stdout: {}",
+        stdout
+    );
+    assert!(
+        stdout.contains("auto preset: anime ("),
+        "stdout: {}",
+        stdout
+    );
+    assert!(
+        stdout.contains("auto preset: movie-quality ("),
+        "stdout: {}",
+        stdout
+    );
+    assert!(
+        stdout.contains("-tune\", \"animation"),
+        "stdout: {}",
+        stdout
+    );
+    assert!(
+        stdout.contains("Auto presets: anime 1, movie-quality 1"),
+        "stdout: {}",
+        stdout
+    );
+    assert!(!stderr.contains("unknown preset"), "stderr: {}", stderr);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_probe_results_are_cached_per_input() {
+    let temp = TempDir::new().expect("temp dir");
+    let bin = temp.path().join("bin");
+    fs::create_dir(&bin).unwrap();
+    let path = common::fake_ffprobe(
+        &bin,
+        r#"{"streams": [{"codec_type": "video", "codec_name": "h264", "width": 1280,
+            "height": 720, "avg_frame_rate": "30/1"}], "format": {"duration": "60.0"}}"#,
+    );
+    let input = temp.path().join("Screen Recording 1.mov");
+    fs::write(&input, b"\n").unwrap();
+    let recommend = |path: &std::ffi::OsStr, extra: &[&str]| {
+        std::process::Command::new(common::binary_path())
+            .arg("recommend")
+            .arg(&input)
+            .args(extra)
+            .env("PATH", path)
+            .env("XDG_CACHE_HOME", temp.path().join("cache"))
+            .output()
+            .expect("run recommend")
+    };
+
+    assert!(recommend(&path, &[]).status.success());
+    // Without ffprobe the cached probe still answers; --no-cache has to probe again
+    fs::remove_file(bin.join("ffprobe")).unwrap();
+    let cached = recommend(&path, &[]);
+    let stdout = String::from_utf8_lossy(&cached.stdout);
+    assert!(
+        cached.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&cached.stderr)
+    );
+    assert!(stdout.contains(": screencast"), "stdout: {}", stdout);
+    assert!(!recommend(&path, &["--no-cache"]).status.success());
+
+    // A changed input is probed again
+    fs::write(&input, b"changed\n").unwrap();
+    assert!(!recommend(&path, &[]).status.success());
+}
+
+#[test]
+fn test_batch_ledger_skips_work_of_other_hosts() {
+    use sha2::{Digest, Sha256};
+    use std::time::{Duration, SystemTime};
+
+    let temp = TempDir::new().expect("temp dir");
+    let (input, output, ledger) = (
+        temp.path().join("in"),
+        temp.path().join("out"),
+        temp.path().join("ledger"),
+    );
+    fs::create_dir(&input).unwrap();
+    for name in ["a.mkv", "b.mkv", "c.mkv", "d.mkv"] {
+        fs::write(input.join(name), b"\n").unwrap();
+    }
+    fs::create_dir_all(ledger.join("claims")).unwrap();
+    let claim = |key: &str| {
+        let hash: String = Sha256::digest(key.as_bytes())
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        ledger.join("claims").join(format!("{}.claim", hash))
+    };
+    fs::write(
+        ledger.join("ledger.ndjson"),
+        "{\"key\":\"a.mkv\",\"status\":\"done\",\"host\":\"nas:1\",\"ts_ms\":1}\n",
+    )
+    .unwrap();
+    fs::write(claim("b.mkv"), "desktop:2").unwrap();
+    // A claim nobody has refreshed for an hour was left by a crashed host
+    fs::write(claim("c.mkv"), "crashed:3").unwrap();
+    fs::File::options()
+        .append(true)
+        .open(claim("c.mkv"))
+        .unwrap()
+        .set_modified(SystemTime::now() - Duration::from_secs(3600))
+        .unwrap();
+
+    let output_run = common::run_transcoderr(&[
+        "batch",
+        input.to_str().unwrap(),
+        output.to_str().unwrap(),
+        "--ledger",
+        ledger.to_str().unwrap(),
+    ])
+    .expect("Failed to run batch with a ledger");
+    let stdout = String::from_utf8_lossy(&output_run.stdout);
+    assert!(
+        stdout.contains("Skipping: already transcoded by nas:1"),
+        "stdout: {}",
+        stdout
+    );
+    assert!(
+        stdout.contains("Skipping: being transcoded by desktop:2"),
+        "stdout: {}",
+        stdout
+    );
+    assert!(
+        stdout.contains("Taking over stale claim from crashed:3"),
+        "stdout: {}",
+        stdout
+    );
+    assert!(
+        stdout.contains("2 skipped (claimed or done by other hosts)"),
+        "stdout: {}",
+        stdout
+    );
+
+    // The attempted files were released and recorded; the live claim was left alone
+    assert!(claim("b.mkv").exists());
+    assert!(!claim("c.mkv").exists());
+    assert!(!claim("d.mkv").exists());
+    let entries = fs::read_to_string(ledger.join("ledger.ndjson")).unwrap();
+    assert_eq!(entries.lines().count(), 3, "ledger: {}", entries);
+    assert!(
+        entries.contains("\"key\":\"c.mkv\",\"status\":\"failed\""),
+        "ledger: {}",
+        entries
+    );
+    assert!(!ledger.join("ledger.lock").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_batch_stages_inputs_through_local_scratch() {
+    let temp = TempDir::new().expect("temp dir");
+    let (bin, input, output, scratch) = (
+        temp.path().join("bin"),
+        temp.path().join("in"),
+        temp.path().join("out"),
+        temp.path().join("scratch"),
+    );
+    for dir in [&bin, &input.join("sub"), &scratch] {
+        fs::create_dir_all(dir).unwrap();
+    }
+    fs::write(input.join("a.mkv"), b"alpha").unwrap();
+    fs::write(input.join("sub").join("b.mkv"), b"bravo").unwrap();
+    let path = common::fake_ffmpeg(&bin);
+
+    let run = std::process::Command::new(common::binary_path())
+        .arg("batch")
+        .arg(&input)
+        .arg(&output)
+        .arg("--stage-inputs")
+        .arg("--stage-dir")
+        .arg(&scratch)
+        .env("PATH", path)
+        .output()
+        .expect("run batch");
+    let stdout = String::from_utf8_lossy(&run.stdout);
+    assert!(
+        run.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&run.stderr)
+    );
+    assert!(
+        stdout.contains("Staging inputs through"),
+        "stdout: {}",
+        stdout
+    );
+    assert!(
+        stdout.contains("2 succeeded, 0 failed"),
+        "stdout: {}",
+        stdout
+    );
+
+    // ffmpeg only ever read the local copies, the outputs were copied back, and the
+    // scratch directory was cleaned up
+    let log = fs::read_to_string(bin.join("ffmpeg.log")).unwrap();
+    assert_eq!(log.lines().count(), 2, "log: {}", log);
+    assert!(
+        log.lines()
+            .all(|line| line.starts_with(scratch.to_str().unwrap())),
+        "log: {}",
+        log
+    );
+    assert_eq!(fs::read(output.join("a.mkv")).unwrap(), b"alpha");
+    assert_eq!(
+        fs::read(output.join("sub").join("b.mkv")).unwrap(),
+        b"bravo"
+    );
+    assert_eq!(fs::read_dir(&scratch).unwrap().count(), 0);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_batch_spot_check_extracts_frame_pairs() {
+    let temp = TempDir::new().expect("temp dir");
+    let (bin, input, output) = (
+        temp.path().join("bin"),
+        temp.path().join("in"),
+        temp.path().join("out"),
+    );
+    fs::create_dir(&bin).unwrap();
+    fs::create_dir(&input).unwrap();
+    fs::write(input.join("a.mkv"), b"alpha").unwrap();
+    common::fake_ffprobe(&bin, r#"{"streams": [], "format": {"duration": "90.0"}}"#);
+    let path = common::fake_ffmpeg(&bin);
+
+    let run = std::process::Command::new(common::binary_path())
+        .arg("batch")
+        .arg(&input)
+        .arg(&output)
+        .args(["--spot-check", "2"])
+        .env("PATH", path)
+        .env("XDG_CACHE_HOME", temp.path().join("cache"))
+        .output()
+        .expect("run batch");
+    let stdout = String::from_utf8_lossy(&run.stdout);
+    assert!(
+        run.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&run.stderr)
+    );
+    assert!(
+        stdout.contains("Spot-checked 1 outputs in"),
+        "stdout: {}",
+        stdout
+    );
+
+    let frames = output.join("spot-check").join("a.mkv");
+    for frame in [
+        "01-input.png",
+        "01-output.png",
+        "02-input.png",
+        "02-output.png",
+    ] {
+        assert!(frames.join(frame).exists(), "missing {}", frame);
+    }
+    let manifest = fs::read_to_string(output.join("spot-check").join("manifest.ndjson")).unwrap();
+    assert_eq!(manifest.lines().count(), 2, "manifest: {}", manifest);
+    assert!(
+        manifest.contains(r#""seconds":60.0,"input_frame":"a.mkv/02-input.png""#),
+        "manifest: {}",
+        manifest
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_keyint_sets_gop_from_frame_rate() {
+    let temp = TempDir::new().expect("temp dir");
+    let (bin, input) = (temp.path().join("bin"), temp.path().join("in"));
+    fs::create_dir(&bin).unwrap();
+    fs::create_dir(&input).unwrap();
+    fs::write(input.join("a.mkv"), b"\n").unwrap();
+    let path = common::fake_ffprobe(
+        &bin,
+        r#"{"streams": [{"codec_type": "video", "avg_frame_rate": "25/1"}], "format": {}}"#,
+    );
+    let run = |args: &[&std::ffi::OsStr]| {
+        let output = std::process::Command::new(common::binary_path())
+            .args(args)
+            .arg("--dry-run")
+            .env("PATH", &path)
+            .env("XDG_CACHE_HOME", temp.path().join("cache"))
+            .output()
+            .expect("run transcoderr");
+        assert!(
+            output.status.success(),
+            "stderr: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8_lossy(&output.stdout).to_string()
+    };
+
+    // auto: 2-second GOPs for a streaming container
+    let stdout = run(&[
+        "batch".as_ref(),
+        input.as_os_str(),
+        temp.path().join("out").as_os_str(),
+        "--ext".as_ref(),
+        "mp4".as_ref(),
+        "--keyint".as_ref(),
+        "auto".as_ref(),
+    ]);
+    assert!(stdout.contains(r#""-g", "50""#), "stdout: {}", stdout);
+
+    // An explicit interval replaces the GOP a preset sets
+    let stdout = run(&[
+        "transcode".as_ref(),
+        input.join("a.mkv").as_os_str(),
+        temp.path().join("a.mkv").as_os_str(),
+        "--preset".as_ref(),
+        "screencast".as_ref(),
+        "--keyint".as_ref(),
+        "4".as_ref(),
+    ]);
+    assert!(stdout.contains("-g 600 "), "stdout: {}", stdout);
+    assert!(stdout.contains("-g 100 "), "stdout: {}", stdout);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_stereo_compat_adds_default_downmix() {
+    let temp = TempDir::new().expect("temp dir");
+    let input = temp.path().join("film.mkv");
+    fs::write(&input, b"\n").unwrap();
+    let path = common::fake_ffprobe(
+        temp.path(),
+        r#"{"streams": [{"codec_type": "video"},
+            {"codec_type": "audio", "codec_name": "dts", "channels": 6, "channel_layout": "5.1"}],
+            "format": {}}"#,
+    );
+    let output = std::process::Command::new(common::binary_path())
+        .arg("transcode")
+        .arg(&input)
+        .arg(temp.path().join("out.mkv"))
+        .args(["--stereo-compat", "--dry-run"])
+        .env("PATH", path)
+        .env("XDG_CACHE_HOME", temp.path().join("cache"))
+        .output()
+        .expect("run transcode");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success());
+    assert!(
+        stdout.contains("-map 0:V? -map 0:a:0 -map 0:a? -map 0:s? "),
+        "stdout: {}",
+        stdout
+    );
+    assert!(
+        stdout.contains("-c:a aac -c:s copy -c:a copy -c:a:0 aac "),
+        "stdout: {}",
+        stdout
+    );
+    assert!(
+        stdout.contains("-disposition:a:0 default -disposition:a:1 0"),
+        "stdout: {}",
+        stdout
+    );
+}
+
+#[test]
+fn test_deterministic_pins_threads_and_bitexact() {
+    let output = common::run_transcoderr(&[
+        "transcode",
+        "input.mkv",
+        "output.mkv",
+        "--preset",
+        "anime",
+        "--deterministic",
+        "--dry-run",
+    ])
+    .expect("Failed to run deterministic transcode");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success());
+    // The preset's x265 params are extended rather than replaced
+    assert!(
+        stdout.contains("bframes=8:pools=4:frame-threads=2"),
+        "stdout: {}",
+        stdout
+    );
+    assert!(
+        stdout.contains("-threads 4 -filter_threads 1 -fflags +bitexact"),
+        "stdout: {}",
+        stdout
+    );
+    let command = stdout
+        .lines()
+        .find(|line| line.trim_start().starts_with("ffmpeg"))
+        .expect("ffmpeg command line");
+    assert_eq!(command.matches("-x265-params").count(), 1, "{}", command);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_refresh_if_settings_changed_uses_settings_hash() {
+    let temp = TempDir::new().expect("temp dir");
+    let (bin, input, output) = (
+        temp.path().join("bin"),
+        temp.path().join("in"),
+        temp.path().join("out"),
+    );
+    fs::create_dir(&bin).unwrap();
+    fs::create_dir(&input).unwrap();
+    fs::write(input.join("a.mkv"), b"alpha").unwrap();
+    fs::write(input.join("b.mkv"), b"bravo").unwrap();
+    let path = common::fake_ffmpeg(&bin);
+    let batch = |extra: &[&str]| {
+        let run = std::process::Command::new(common::binary_path())
+            .arg("batch")
+            .arg(&input)
+            .arg(&output)
+            .args(extra)
+            .env("PATH", &path)
+            .env("XDG_DATA_HOME", temp.path().join("data"))
+            .output()
+            .expect("run batch");
+        assert!(
+            run.status.success(),
+            "stderr: {}",
+            String::from_utf8_lossy(&run.stderr)
+        );
+        String::from_utf8_lossy(&run.stdout).to_string()
+    };
+
+    let stdout = batch(&[]);
+    assert!(
+        stdout.contains("2 succeeded, 0 failed"),
+        "stdout: {}",
+        stdout
+    );
+    let history = fs::read_to_string(temp.path().join("data/transcoderr/history.ndjson")).unwrap();
+    assert_eq!(
+        history.matches(r#""status":"done""#).count(),
+        2,
+        "{}",
+        history
+    );
+
+    // Same settings: nothing to do. One output removed: only it is redone.
+    fs::remove_file(output.join("b.mkv")).unwrap();
+    let stdout = batch(&["--refresh-if-settings-changed"]);
+    assert!(
+        stdout.contains("1 up to date (same settings)"),
+        "stdout: {}",
+        stdout
+    );
+    assert!(
+        stdout.contains("1 succeeded, 0 failed"),
+        "stdout: {}",
+        stdout
+    );
+
+    // A different preset changes the hash, so both are transcoded again
+    let stdout = batch(&["--refresh-if-settings-changed", "--preset", "tv-h265-fast"]);
+    assert_eq!(
+        stdout.matches("Settings changed (").count(),
+        2,
+        "stdout: {}",
+        stdout
+    );
+    assert!(
+        stdout.contains("2 succeeded, 0 failed"),
+        "stdout: {}",
+        stdout
+    );
+}
+
+#[test]
+fn test_settings_hash_is_written_to_output_metadata() {
+    let output = common::run_transcoderr(&[
+        "transcode",
+        "input.mkv",
+        "output.mkv",
+        "--preset",
+        "original-h265",
+        "--dry-run",
+    ])
+    .expect("Failed to run transcode");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("-metadata transcoderr_settings="),
+        "stdout: {}",
+        stdout
+    );
+}
+
+#[test]
+#[ignore] // Slow test - run with: cargo test -- --ignored
+fn test_selftest_passes_with_local_ffmpeg() {
+    if !common::ffmpeg_available() {
+        eprintln!("SKIP: ffmpeg not available");
+        return;
+    }
+    let output = common::run_transcoderr(&["selftest"]).expect("Failed to run selftest");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "stdout: {}", stdout);
+    assert!(stdout.contains("All checks passed"), "stdout: {}", stdout);
+}
+
+#[test]
+fn test_selftest_reports_missing_ffmpeg() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let output = std::process::Command::new(common::binary_path())
+        .arg("selftest")
+        .env("PATH", temp_dir.path())
+        .output()
+        .expect("Failed to run selftest");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("ffmpeg is not available on PATH"),
+        "stderr: {}",
+        stderr
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn test_generate_fixtures_covers_edge_cases() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    // Stand-in ffmpeg that logs its args and creates the output (the last arg)
+    let tools = temp_dir.path().join("bin");
+    fs::create_dir_all(&tools).unwrap();
+    let script = tools.join("ffmpeg");
+    fs::write(
+        &script,
+        format!(
+            "#!/bin/sh\necho \"$@\" >> '{}'\nfor arg in \"$@\"; do last=\"$arg\"; done\n: > \"$last\"\n",
+            tools.join("ffmpeg.log").display()
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    let mut path = tools.as_os_str().to_owned();
+    path.push(":");
+    path.push(std::env::var_os("PATH").unwrap_or_default());
+
+    let out = temp_dir.path().join("fixtures");
+    let output = std::process::Command::new(common::binary_path())
+        .args(["generate-fixtures", "--out"])
+        .arg(&out)
+        .env("PATH", path)
+        .output()
+        .expect("Failed to run generate-fixtures");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "stdout: {}", stdout);
+    for name in [
+        "color_mpeg4_aac.mp4",
+        "hdr10_flags_mpeg4.mkv",
+        "vfr_mpeg4_aac.mkv",
+        "multi_audio_51_stereo_subs.mkv",
+        "mov_text_subs.mp4",
+    ] {
+        assert!(
+            out.join(name).exists(),
+            "missing {}; stdout: {}",
+            name,
+            stdout
+        );
+    }
+
+    let log = fs::read_to_string(tools.join("ffmpeg.log")).unwrap();
+    assert!(log.contains("-color_trc smpte2084"), "log: {}", log);
+    assert!(log.contains("-fps_mode vfr"), "log: {}", log);
+    assert!(log.contains("c=5.1"), "log: {}", log);
+    // The subtitle files are only needed while muxing
+    assert!(
+        !fs::read_dir(&out).unwrap().any(|e| e
+            .unwrap()
+            .path()
+            .extension()
+            .is_some_and(|x| x == "srt")),
+        "leftover subtitle files"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_languages_drop_other_audio_and_subtitles() {
+    let temp = TempDir::new().expect("temp dir");
+    let input = temp.path().join("film.mkv");
+    fs::write(&input, b"\n").unwrap();
+    let path = common::fake_ffprobe(
+        temp.path(),
+        r#"{"streams": [{"codec_type": "video"},
+            {"codec_type": "audio", "tags": {"language": "eng"}},
+            {"codec_type": "audio", "tags": {"language": "fre"}},
+            {"codec_type": "subtitle", "tags": {"language": "fre"}},
+            {"codec_type": "subtitle", "tags": {"language": "en"}}],
+            "format": {}}"#,
+    );
+    let output = std::process::Command::new(common::binary_path())
+        .arg("transcode")
+        .arg(&input)
+        .arg(temp.path().join("out.mkv"))
+        .args(["--languages", "en", "--preset", "anime", "--dry-run"])
+        .env("PATH", path)
+        .env("XDG_CACHE_HOME", temp.path().join("cache"))
+        .output()
+        .expect("run transcode");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success());
+    // The preset's own maps are not repeated, and the drops come after every map
+    assert!(
+        stdout.contains("-map 0:V? -map 0:a? -map 0:s? -map 0:t? -map -0:a:1 -map -0:s:0 "),
+        "stdout: {}",
+        stdout
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_stream_tags_and_dispositions_are_copied_per_stream() {
+    let temp = TempDir::new().expect("temp dir");
+    let input = temp.path().join("film.mp4");
+    fs::write(&input, b"\n").unwrap();
+    let path = common::fake_ffprobe(
+        temp.path(),
+        r#"{"streams": [{"codec_type": "video", "disposition": {"default": 1}},
+            {"codec_type": "audio", "channels": 6, "disposition": {"default": 1}},
+            {"codec_type": "audio", "channels": 2, "disposition": {"default": 0, "comment": 1},
+             "tags": {"title": "Director's Commentary"}}],
+            "format": {}}"#,
+    );
+    let output = std::process::Command::new(common::binary_path())
+        .arg("transcode")
+        .arg(&input)
+        .arg(temp.path().join("out.mkv"))
+        .args(["--stereo-compat", "--dry-run"])
+        .env("PATH", path)
+        .env("XDG_CACHE_HOME", temp.path().join("cache"))
+        .output()
+        .expect("run transcode");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success());
+    // The downmix shifts the commentary to a:2; its tags still come from a:1
+    assert!(
+        stdout.contains(
+            "-map_metadata:s:v:0 0:s:v:0 -map_metadata:s:a:0 0:s:a:0 \
+             -map_metadata:s:a:1 0:s:a:0 -map_metadata:s:a:2 0:s:a:1 "
+        ),
+        "stdout: {}",
+        stdout
+    );
+    assert!(
+        stdout.contains("-disposition:a:1 0 -disposition:a:2 comment "),
+        "stdout: {}",
+        stdout
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_report_html_lists_savings_and_failure_logs() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp = TempDir::new().expect("temp dir");
+    let (bin, input, output) = (
+        temp.path().join("bin"),
+        temp.path().join("in"),
+        temp.path().join("out"),
+    );
+    fs::create_dir(&bin).unwrap();
+    fs::create_dir(&input).unwrap();
+    fs::write(input.join("good.mkv"), vec![0u8; 4096]).unwrap();
+    fs::write(input.join("broken.mkv"), b"not media").unwrap();
+    // Stand-in ffmpeg: halves good inputs and fails on broken ones like a real demuxer
+    let script = bin.join("ffmpeg");
+    fs::write(
+        &script,
+        "#!/bin/sh\nprev=\nfor arg in \"$@\"; do\n  [ \"$prev\" = -i ] && input=\"$arg\"\n  \
+         prev=\"$arg\"\ndone\ncase \"$input\" in\n  *broken*) printf 'frame=    0 fps=0\\r' >&2\n    \
+         echo \"$input: Invalid data found when processing input\" >&2; exit 1;;\nesac\n\
+         head -c 2048 \"$input\" > \"$prev\"\n",
+    )
+    .unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    let mut path = bin.as_os_str().to_owned();
+    path.push(":");
+    path.push(std::env::var_os("PATH").unwrap_or_default());
+
+    let report = temp.path().join("reports/run.html");
+    let run = std::process::Command::new(common::binary_path())
+        .arg("batch")
+        .arg(&input)
+        .arg(&output)
+        .arg("--report-html")
+        .arg(&report)
+        .env("PATH", path)
+        .env("XDG_DATA_HOME", temp.path().join("data"))
+        .output()
+        .expect("run batch");
+    let stdout = String::from_utf8_lossy(&run.stdout);
+    let stderr = String::from_utf8_lossy(&run.stderr);
+    assert!(
+        stdout.contains("1 succeeded, 1 failed"),
+        "stdout: {}",
+        stdout
+    );
+    assert!(stdout.contains("Report written to"), "stdout: {}", stdout);
+    // ffmpeg's output still reaches the terminal
+    assert!(stderr.contains("Invalid data found"), "stderr: {}", stderr);
+
+    let html = fs::read_to_string(&report).expect("report written");
+    assert!(html.starts_with("<!DOCTYPE html>"));
+    assert!(
+        html.contains("<span class=\"saved\">-50.0%</span>"),
+        "{}",
+        html
+    );
+    assert!(html.contains("<h3>broken.mkv</h3>"), "{}", html);
+    assert!(
+        html.contains("broken.mkv: Invalid data found when processing input</pre>"),
+        "{}",
+        html
+    );
+    assert!(!html.contains("frame="), "{}", html);
+}
+
+#[test]
+fn test_history_export_csv_and_parquet() {
+    let temp = TempDir::new().expect("temp dir");
+    let data = temp.path().join("data/transcoderr");
+    fs::create_dir_all(&data).unwrap();
+    fs::write(
+        data.join("history.ndjson"),
+        "{\"ts_ms\":1792056600000,\"input\":\"/in/a.mkv\",\"output\":\"/out/a.mkv\",\
+         \"status\":\"done\",\"settings\":\"abc\",\"preset\":\"anime\",\"seconds\":12.5,\
+         \"output_bytes\":1000}\nnot json\n",
+    )
+    .unwrap();
+    let export = |format: &str, out: &std::path::Path| {
+        let run = std::process::Command::new(common::binary_path())
+            .args(["history", "export", "--format", format, "--out"])
+            .arg(out)
+            .env("XDG_DATA_HOME", temp.path().join("data"))
+            .output()
+            .expect("run history export");
+        assert!(
+            run.status.success(),
+            "stderr: {}",
+            String::from_utf8_lossy(&run.stderr)
+        );
+        String::from_utf8_lossy(&run.stdout).to_string()
+    };
+
+    let csv = temp.path().join("exports/history.csv");
+    assert!(export("csv", &csv).contains("Exported 1 records"));
+    assert_eq!(
+        fs::read_to_string(&csv).unwrap(),
+        "time_utc,input,output,status,preset,settings,seconds,output_bytes\n\
+         2026-10-15 09:30:00,/in/a.mkv,/out/a.mkv,done,anime,abc,12.5,1000\n"
+    );
+
+    let parquet = temp.path().join("history.parquet");
+    export("parquet", &parquet);
+    let bytes = fs::read(&parquet).unwrap();
+    assert!(bytes.starts_with(b"PAR1") && bytes.ends_with(b"PAR1"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_migrate_suffixed_swaps_verified_outputs() {
+    let temp = TempDir::new().expect("temp dir");
+    let lib = temp.path().join("lib");
+    let show = lib.join("Show");
+    fs::create_dir_all(&show).unwrap();
+    fs::write(show.join("Show 1.11.avi"), b"original").unwrap();
+    fs::write(show.join("Show 1.11_transcoded.mkv"), b"transcoded").unwrap();
+    fs::write(show.join("Gone_transcoded.mkv"), b"orphan").unwrap();
+    let path = common::fake_ffprobe(
+        temp.path(),
+        r#"{"streams": [{"codec_type": "video"}, {"codec_type": "audio"}],
+            "format": {"duration": "1320.0"}}"#,
+    );
+    let migrate = |extra: &[&str]| {
+        let run = std::process::Command::new(common::binary_path())
+            .arg("migrate-suffixed")
+            .arg(&lib)
+            .args(extra)
+            .env("PATH", &path)
+            .env("XDG_CACHE_HOME", temp.path().join("cache"))
+            .env("XDG_DATA_HOME", temp.path().join("data"))
+            .output()
+            .expect("run migrate-suffixed");
+        assert!(
+            run.status.success(),
+            "stderr: {}",
+            String::from_utf8_lossy(&run.stderr)
+        );
+        String::from_utf8_lossy(&run.stdout).to_string()
+    };
+
+    // Listing changes nothing
+    let stdout = migrate(&[]);
+    assert!(
+        stdout.contains("1 verified, 1 skipped"),
+        "stdout: {}",
+        stdout
+    );
+    assert!(
+        stdout.contains("Gone_transcoded.mkv: no original next to it"),
+        "stdout: {}",
+        stdout
+    );
+    assert!(show.join("Show 1.11.avi").exists());
+
+    let backup = temp.path().join("backup");
+    let quarantine = backup.to_str().unwrap();
+    let stdout = migrate(&["--swap", "--quarantine", quarantine, "--lang", "de"]);
+    // The removal mode is translated along with the rest of the summary
+    assert!(
+        stdout.contains(&format!(
+            "Migration abgeschlossen (Originale: in die Quarantäne {} verschieben): 1 ersetzt, 0 fehlgeschlagen",
+            quarantine
+        )),
+        "stdout: {}",
+        stdout
+    );
+    assert_eq!(fs::read(show.join("Show 1.11.mkv")).unwrap(), b"transcoded");
+    assert!(!show.join("Show 1.11_transcoded.mkv").exists());
+    assert_eq!(
+        fs::read(backup.join("Show/Show 1.11.avi")).unwrap(),
+        b"original"
+    );
+    let history = fs::read_to_string(temp.path().join("data/transcoderr/history.ndjson")).unwrap();
+    assert!(history.contains("Show 1.11.mkv"), "{}", history);
+    assert!(history.contains(r#""settings":"migrated""#), "{}", history);
+}
+
+#[cfg(feature = "vmaf")]
+#[test]
+fn test_min_vmaf_retries_at_lower_crf_then_rejects() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp = TempDir::new().expect("temp dir");
+    let (bin, input) = (temp.path().join("bin"), temp.path().join("in"));
+    fs::create_dir(&bin).unwrap();
+    fs::create_dir(&input).unwrap();
+    fs::write(input.join("clip.mkv"), vec![0u8; 4096]).unwrap();
+    // Stand-in ffmpeg: encodes copy the input and note the CRF; a VMAF pass scores
+    // 94.5 from CRF 26 down and 90.25 above it
+    let crf = bin.join("crf");
+    let script = bin.join("ffmpeg");
+    fs::write(
+        &script,
+        format!(
+            "#!/bin/sh\ncase \"$*\" in\n  *libvmaf*) score=90.25\n    \
+             [ \"$(cat '{crf}')\" -le 26 ] && score=94.5\n    \
+             echo \"[Parsed_libvmaf_4 @ 0x1] VMAF score: $score\" >&2; exit 0;;\nesac\n\
+             prev=\nfor arg in \"$@\"; do\n  [ \"$prev\" = -i ] && input=\"$arg\"\n  \
+             [ \"$prev\" = -crf ] && echo \"$arg\" > '{crf}'\n  prev=\"$arg\"\ndone\n\
+             cp \"$input\" \"$prev\"\n",
+            crf = crf.display()
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    let mut path = bin.as_os_str().to_owned();
+    path.push(":");
+    path.push(std::env::var_os("PATH").unwrap_or_default());
+
+    let batch = |output: &str, retries: &str| {
+        let run = std::process::Command::new(common::binary_path())
+            .arg("batch")
+            .arg(&input)
+            .arg(temp.path().join(output))
+            .args([
+                "--extra=-crf 28",
+                "--min-vmaf",
+                "93",
+                "--vmaf-retries",
+                retries,
+            ])
+            .env("PATH", &path)
+            .env("XDG_CACHE_HOME", temp.path().join("cache"))
+            .env("XDG_DATA_HOME", temp.path().join("data"))
+            .output()
+            .expect("run batch");
+        String::from_utf8_lossy(&run.stdout).into_owned() + &String::from_utf8_lossy(&run.stderr)
+    };
+
+    // One retry at CRF 26 clears the minimum
+    let log = batch("retried", "1");
+    assert!(log.contains("retrying at CRF 26 (1/1)"), "{}", log);
+    assert!(log.contains("VMAF 94.50 (minimum 93)"), "{}", log);
+    assert!(log.contains("1 succeeded, 0 failed"), "{}", log);
+    assert!(temp.path().join("retried/clip.mkv").exists());
+
+    // Without retries the output is rejected and removed; the original stays
+    let log = batch("rejected", "0");
+    assert!(
+        log.contains("VMAF 90.25 is below --min-vmaf 93 (output removed, original kept)"),
+        "{}",
+        log
+    );
+    assert!(
+        log.contains("1 of the failures missed --min-vmaf"),
+        "{}",
+        log
+    );
+    assert!(!temp.path().join("rejected/clip.mkv").exists());
+    assert!(input.join("clip.mkv").exists());
+    let history = fs::read_to_string(temp.path().join("data/transcoderr/history.ndjson")).unwrap();
+    assert!(history.contains("\"status\":\"rejected\""), "{}", history);
+}
+
+#[test]
+fn test_stats_none_keeps_only_the_final_stats_line() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp = TempDir::new().expect("temp dir");
+    let (bin, input) = (temp.path().join("bin"), temp.path().join("in"));
+    fs::create_dir(&bin).unwrap();
+    fs::create_dir(&input).unwrap();
+    fs::write(input.join("clip.mkv"), vec![0u8; 4096]).unwrap();
+    // Stand-in ffmpeg: redraws a stats line a few times like a real encode
+    let script = bin.join("ffmpeg");
+    fs::write(
+        &script,
+        "#!/bin/sh\nprev=\nfor arg in \"$@\"; do\n  [ \"$prev\" = -i ] && input=\"$arg\"\n  \
+         prev=\"$arg\"\ndone\necho '  Duration: 00:00:04.00, start: 0.000000' >&2\n\
+         for t in 1 2 3; do printf 'frame=%s0 fps=25 time=00:00:0%s.00 speed=2.0x\\r' $t $t >&2; done\n\
+         echo '[libx265 @ 0x1] encoder warning' >&2\n\
+         printf 'frame=100 fps=25 Lsize=4kB time=00:00:04.00 speed=2.0x\\n' >&2\n\
+         cp \"$input\" \"$prev\"\n",
+    )
+    .unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    let mut path = bin.as_os_str().to_owned();
+    path.push(":");
+    path.push(std::env::var_os("PATH").unwrap_or_default());
+
+    let batch = |output: &str, stats: &str| {
+        let run = std::process::Command::new(common::binary_path())
+            .arg("batch")
+            .arg(&input)
+            .arg(temp.path().join(output))
+            .args(["--stats", stats])
+            .env("PATH", &path)
+            .env("XDG_CACHE_HOME", temp.path().join("cache"))
+            .env("XDG_DATA_HOME", temp.path().join("data"))
+            .output()
+            .expect("run batch");
+        assert!(run.status.success());
+        String::from_utf8_lossy(&run.stderr).into_owned()
+    };
+
+    let full = batch("full", "full");
+    assert_eq!(full.matches("frame=").count(), 4, "{}", full);
+    assert!(full.contains('\r'), "{:?}", full);
+
+    // `line` is only drawn on a terminal; piped, it behaves like `none`
+    for mode in ["none", "line"] {
+        let stderr = batch(mode, mode);
+        assert!(!stderr.contains('\r'), "{:?}", stderr);
+        assert!(
+            stderr.contains("[libx265 @ 0x1] encoder warning\n"),
+            "{}",
+            stderr
+        );
+        assert_eq!(stderr.matches("frame=").count(), 1, "{}", stderr);
+        assert!(
+            stderr.contains("frame=100 fps=25 Lsize=4kB time=00:00:04.00 speed=2.0x\n"),
+            "{}",
+            stderr
+        );
+    }
+}
+
+#[test]
+fn test_porcelain_summary_is_raw_and_locale_free() {
+    let temp = TempDir::new().expect("temp dir");
+    let input = temp.path().join("in");
+    fs::create_dir(&input).unwrap();
+    fs::write(input.join("clip.mkv"), vec![0u8; 4096]).unwrap();
+    let path = common::fake_ffmpeg(temp.path());
+
+    let batch = |output: &str, porcelain: bool| {
+        let mut cmd = std::process::Command::new(common::binary_path());
+        cmd.arg("batch")
+            .arg(&input)
+            .arg(temp.path().join(output))
+            .env("PATH", &path)
+            .env("LC_ALL", "de_DE.UTF-8")
+            .env("XDG_CACHE_HOME", temp.path().join("cache"))
+            .env("XDG_DATA_HOME", temp.path().join("data"));
+        if porcelain {
+            cmd.arg("--porcelain");
+        }
+        let run = cmd.output().expect("run batch");
+        assert!(run.status.success());
+        String::from_utf8_lossy(&run.stdout).into_owned()
+    };
+
+    // People get short units with the locale's decimal comma
+    let human = batch("human", false);
+    assert!(
+        human.contains("  4,0 KiB -> 4,0 KiB (+0,0%) in "),
+        "{}",
+        human
+    );
+
+    let porcelain = batch("porcelain", true);
+    let line = porcelain
+        .lines()
+        .find(|l| l.starts_with("batch\t"))
+        .expect("batch summary line");
+    let fields: Vec<&str> = line.split('\t').collect();
+    assert_eq!(
+        fields[..10],
+        [
+            "batch",
+            "dry_run=false",
+            "succeeded=1",
+            "failed=0",
+            "rejected=0",
+            "up_to_date=0",
+            "elsewhere=0",
+            "collisions=0",
+            "prior_outputs=0",
+            "input_bytes=4096",
+        ]
+    );
+    assert_eq!(fields[10], "output_bytes=4096");
+    let seconds = fields[11].strip_prefix("seconds=").expect("seconds field");
+    assert!(seconds.parse::<f64>().is_ok(), "{}", seconds);
+    assert!(!porcelain.contains("succeeded, "), "{}", porcelain);
+}
+
+#[test]
+fn test_checkpoint_every_writes_partial_summaries_and_snapshots() {
+    let temp = TempDir::new().expect("temp dir");
+    let (input, output) = (temp.path().join("in"), temp.path().join("out"));
+    fs::create_dir(&input).unwrap();
+    for name in ["a.mkv", "b.mkv", "c.mkv"] {
+        fs::write(input.join(name), vec![0u8; 2048]).unwrap();
+    }
+    let run = std::process::Command::new(common::binary_path())
+        .arg("batch")
+        .arg(&input)
+        .arg(&output)
+        .args(["--checkpoint-every", "2", "--report-html"])
+        .arg(temp.path().join("run.html"))
+        .env("PATH", common::fake_ffmpeg(temp.path()))
+        .env("XDG_CACHE_HOME", temp.path().join("cache"))
+        .env("XDG_DATA_HOME", temp.path().join("data"))
+        .output()
+        .expect("run batch");
+    let stdout = String::from_utf8_lossy(&run.stdout);
+    assert!(run.status.success(), "{}", stdout);
+    assert!(
+        stdout.contains("Checkpoint: 2 of 3 files (2 succeeded, 0 failed), 4.0 KiB -> 4.0 KiB"),
+        "{}",
+        stdout
+    );
+    assert_eq!(stdout.matches("Checkpoint:").count(), 1, "{}", stdout);
+
+    let snapshot: serde_json::Value = serde_json::from_slice(
+        &fs::read(output.join(".transcoderr-checkpoint.json")).expect("snapshot written"),
+    )
+    .unwrap();
+    assert_eq!(snapshot["complete"], true);
+    assert_eq!(snapshot["processed"], 3);
+    assert_eq!(snapshot["succeeded"].as_array().map(Vec::len), Some(3));
+    assert_eq!(snapshot["input_bytes"], 6144);
+    // The last write is the finished report
+    let html = fs::read_to_string(temp.path().join("run.html")).unwrap();
+    assert!(!html.contains("in progress"), "{}", html);
+}
+
+#[test]
+fn test_per_file_args_from_sidecars_and_config() {
+    let temp = TempDir::new().expect("temp dir");
+    let input = temp.path().join("in");
+    fs::create_dir_all(input.join("Show")).unwrap();
+    for name in ["Show/ep1.mkv", "Show/ep2.mkv", "broken.mkv"] {
+        fs::write(input.join(name), b"data").unwrap();
+    }
+    fs::write(
+        input.join("Show/ep1.transcoderr-args"),
+        "# bad timestamps\n-fflags +genpts\n",
+    )
+    .unwrap();
+    fs::write(input.join("broken.transcoderr-args"), "-vf \"yadif").unwrap();
+    let config = temp.path().join("config.toml");
+    fs::write(
+        &config,
+        "[file-args]\n\"Show/ep1.mkv\" = [\"-vf\", \"yadif\"]\n",
+    )
+    .unwrap();
+
+    let run = std::process::Command::new(common::binary_path())
+        .arg("--config")
+        .arg(&config)
+        .arg("batch")
+        .arg(&input)
+        .arg(temp.path().join("out"))
+        .arg("--dry-run")
+        .env("XDG_CACHE_HOME", temp.path().join("cache"))
+        .env("XDG_DATA_HOME", temp.path().join("data"))
+        .output()
+        .expect("run batch");
+    let stdout = String::from_utf8_lossy(&run.stdout);
+    assert!(run.status.success(), "{}", stdout);
+    assert!(
+        stdout.contains("Per-file args: -vf yadif -fflags +genpts"),
+        "{}",
+        stdout
+    );
+    assert_eq!(stdout.matches("Per-file args").count(), 1, "{}", stdout);
+    assert!(
+        stdout.contains("\"-vf\", \"yadif\", \"-fflags\", \"+genpts\""),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains("Would fail: invalid") && stdout.contains("unclosed \" quote"),
+        "{}",
+        stdout
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_embed_thumbnail_per_container() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp = TempDir::new().expect("temp dir");
+    let (bin, input, tmp) = (
+        temp.path().join("bin"),
+        temp.path().join("in"),
+        temp.path().join("tmp"),
+    );
+    for dir in [&bin, &input, &tmp] {
+        fs::create_dir(dir).unwrap();
+    }
+    let path = common::fake_ffprobe(
+        &bin,
+        r#"{"streams": [
+            {"codec_type": "video", "codec_name": "h264", "width": 1920, "height": 1080},
+            {"codec_type": "audio", "codec_name": "aac", "channels": 2}
+        ], "format": {"duration": "100.0"}}"#,
+    );
+    // Stand-in ffmpeg: logs its args and copies the first input to the output
+    let script = bin.join("ffmpeg");
+    fs::write(
+        &script,
+        format!(
+            "#!/bin/sh\necho \"$*\" >> '{log}'\nprev=\nfor arg in \"$@\"; do\n  \
+             [ \"$prev\" = -i ] && [ -z \"$input\" ] && input=\"$arg\"\n  prev=\"$arg\"\ndone\n\
+             cp \"$input\" \"$prev\"\n",
+            log = bin.join("ffmpeg.log").display()
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    fs::write(input.join("clip.mkv"), b"video").unwrap();
+
+    let batch = |output: &str, ext: &str, thumbnail: &str| {
+        let _ = fs::remove_file(bin.join("ffmpeg.log"));
+        let run = std::process::Command::new(common::binary_path())
+            .arg("batch")
+            .arg(&input)
+            .arg(temp.path().join(output))
+            .args(["--ext", ext, "--embed-thumbnail", thumbnail])
+            .env("PATH", &path)
+            .env("TMPDIR", &tmp)
+            .env("XDG_CACHE_HOME", temp.path().join("cache"))
+            .env("XDG_DATA_HOME", temp.path().join("data"))
+            .output()
+            .expect("run batch");
+        let stdout = String::from_utf8_lossy(&run.stdout).into_owned();
+        assert!(run.status.success(), "{}", stdout);
+        fs::read_to_string(bin.join("ffmpeg.log")).unwrap()
+    };
+
+    // mp4: a frame 10% in becomes an attached picture after the one video stream, and
+    // the grabbed frame is cleaned up afterwards
+    let log = batch("mp4", "mp4", "auto");
+    assert!(log.contains("-ss 10.000 -i"), "{}", log);
+    assert!(
+        log.contains("-map 1:v:0 -c:v libx265 -c:a aac -c:s copy -c:v:1 copy"),
+        "{}",
+        log
+    );
+    assert!(log.contains("-disposition:v:1 attached_pic"), "{}", log);
+    assert_eq!(fs::read_dir(&tmp).unwrap().count(), 0);
+
+    // mkv: a poster beside the input becomes a cover attachment
+    fs::write(input.join("clip-poster.png"), b"png").unwrap();
+    let log = batch("mkv", "mkv", "auto");
+    assert!(!log.contains("-ss"), "{}", log);
+    assert!(
+        log.contains(&format!(
+            "-attach {} -metadata:s:t:0 mimetype=image/png -metadata:s:t:0 filename=cover.png",
+            input.join("clip-poster.png").display()
+        )),
+        "{}",
+        log
+    );
+
+    // Containers without cover support fail the job up front
+    let run = std::process::Command::new(common::binary_path())
+        .arg("batch")
+        .arg(&input)
+        .arg(temp.path().join("webm"))
+        .args([
+            "--ext",
+            "webm",
+            "--embed-thumbnail",
+            "at=00:00:05",
+            "--dry-run",
+        ])
+        .env("PATH", &path)
+        .env("XDG_CACHE_HOME", temp.path().join("cache"))
+        .output()
+        .expect("run batch");
+    let stdout = String::from_utf8_lossy(&run.stdout);
+    assert!(
+        stdout.contains("Would fail: --embed-thumbnail needs an mp4, m4v, mov or mkv output"),
+        "{}",
+        stdout
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_trim_silence_cuts_both_ends() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp = TempDir::new().expect("temp dir");
+    let (bin, input) = (temp.path().join("bin"), temp.path().join("in"));
+    fs::create_dir(&bin).unwrap();
+    fs::create_dir(&input).unwrap();
+    let path = common::fake_ffprobe(
+        &bin,
+        r#"{"streams": [{"codec_type": "audio", "codec_name": "pcm_s16le", "channels": 1}],
+            "format": {"duration": "60.0"}}"#,
+    );
+    // Stand-in ffmpeg: a silencedetect pass reports silence at both ends and in the
+    // middle; encodes log their args and copy the input
+    let script = bin.join("ffmpeg");
+    fs::write(
+        &script,
+        format!(
+            "#!/bin/sh\ncase \"$*\" in\n  *silencedetect*)\n    \
+             printf '%s\\n' 'silence_start: 0' 'silence_end: 2.5 | silence_duration: 2.5' \
+             'silence_start: 30' 'silence_end: 31 | silence_duration: 1' \
+             'silence_start: 55' 'silence_end: 60 | silence_duration: 5' >&2; exit 0;;\nesac\n\
+             echo \"$*\" >> '{log}'\nprev=\nfor arg in \"$@\"; do\n  \
+             [ \"$prev\" = -i ] && input=\"$arg\"\n  prev=\"$arg\"\ndone\ncp \"$input\" \"$prev\"\n",
+            log = bin.join("ffmpeg.log").display()
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    fs::write(input.join("lecture.wav"), b"audio").unwrap();
+
+    let run = std::process::Command::new(common::binary_path())
+        .arg("batch")
+        .arg(&input)
+        .arg(temp.path().join("out"))
+        .args(["--input-exts", "wav", "--ext", "m4a", "--vcodec", "copy"])
+        .args(["--trim-silence", "both", "--silence-threshold", "-40"])
+        .env("PATH", &path)
+        .env("XDG_CACHE_HOME", temp.path().join("cache"))
+        .env("XDG_DATA_HOME", temp.path().join("data"))
+        .output()
+        .expect("run batch");
+    let stdout = String::from_utf8_lossy(&run.stdout);
+    assert!(run.status.success(), "{}", stdout);
+    assert!(
+        stdout.contains("Trimming silence: 2.5s at the start, from 00:00:55 on"),
+        "{}",
+        stdout
+    );
+    let log = fs::read_to_string(bin.join("ffmpeg.log")).unwrap();
+    assert!(log.contains("-ss 2.500 -t 52.500"), "{}", log);
+    assert!(temp.path().join("out/lecture.m4a").exists());
+}
+
+#[test]
+fn test_speed_retimes_video_and_chains_atempo() {
+    let output = common::run_transcoderr(&[
+        "transcode",
+        "lecture.mkv",
+        "lecture-fast.mkv",
+        "--extra=-vf scale=1280:-2",
+        "--speed",
+        "2.5x",
+        "--dry-run",
+    ])
+    .expect("Failed to run transcode");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", stdout);
+    let command = stdout
+        .lines()
+        .find(|line| line.trim_start().starts_with("ffmpeg"))
+        .expect("ffmpeg command line");
+    assert!(
+        command.contains("-vf scale=1280:-2,setpts=PTS/2.5 -af atempo=2,atempo=1.25"),
+        "{}",
+        command
+    );
+
+    // Copied video keeps its speed, which is worth a warning
+    let output = common::run_transcoderr(&[
+        "transcode",
+        "lecture.mkv",
+        "lecture-fast.mkv",
+        "--vcodec",
+        "copy",
+        "--speed",
+        "1.5",
+        "--dry-run",
+    ])
+    .expect("Failed to run transcode");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("only changes the audio"), "{}", stderr);
+    assert!(
+        !common::run_transcoderr(&["transcode", "a.mkv", "--speed", "20x", "--dry-run"])
+            .unwrap()
+            .status
+            .success()
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_preview_stream_adds_a_tee_output() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp = TempDir::new().expect("temp dir");
+    let script = temp.path().join("ffmpeg");
+    fs::write(
+        &script,
+        format!(
+            "#!/bin/sh\necho \"$*\" >> '{}'\n",
+            temp.path().join("ffmpeg.log").display()
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    let mut path = temp.path().as_os_str().to_owned();
+    path.push(":");
+    path.push(std::env::var_os("PATH").unwrap_or_default());
+    let input = temp.path().join("movie.mkv");
+    fs::write(&input, b"video").unwrap();
+
+    let run = std::process::Command::new(common::binary_path())
+        .arg("transcode")
+        .arg(&input)
+        .arg(temp.path().join("out.mkv"))
+        .args([
+            "--extra=-vf yadif",
+            "--preview-stream",
+            "udp://127.0.0.1:1234",
+        ])
+        .env("PATH", &path)
+        .env("XDG_CACHE_HOME", temp.path().join("cache"))
+        .env("XDG_DATA_HOME", temp.path().join("data"))
+        .output()
+        .expect("run transcode");
+    assert!(
+        run.status.success(),
+        "{}",
+        String::from_utf8_lossy(&run.stderr)
+    );
+    let log = fs::read_to_string(temp.path().join("ffmpeg.log")).unwrap();
+    // The preview follows the main output, through the same filters
+    let (main, preview) = log.split_once("out.mkv ").expect("main output first");
+    assert!(main.contains("-vf yadif"), "{}", log);
+    assert!(
+        preview.contains("-vf yadif,scale=-2:360 -c:v libx264"),
+        "{}",
+        log
+    );
+    assert!(
+        preview
+            .trim_end()
+            .ends_with("-f tee [f=mpegts:onfail=ignore]udp://127.0.0.1:1234"),
+        "{}",
+        log
+    );
+
+    let bad = common::run_transcoderr(&["--preview-stream", "out.ts", "info", "x.mkv"]).unwrap();
+    assert!(!bad.status.success());
+}
+
+#[test]
+fn test_legacy_source_tolerates_broken_inputs() {
+    let output = common::run_transcoderr(&[
+        "transcode",
+        "tape-1998.avi",
+        "tape-1998.mkv",
+        "--legacy-source",
+        "--dry-run",
+    ])
+    .expect("Failed to run transcode");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", stdout);
+    let command = stdout
+        .lines()
+        .find(|line| line.trim_start().starts_with("ffmpeg"))
+        .expect("ffmpeg command line");
+    // Tolerance flags go before the input they apply to
+    assert!(
+        command.contains(
+            "-fflags +genpts+igndts+ignidx+discardcorrupt -err_detect ignore_err \
+             -analyzeduration 100M -probesize 100M -i tape-1998.avi"
+        ),
+        "{}",
+        command
+    );
+    assert!(command.contains("-max_error_rate 1"), "{}", command);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_odd_sizes_are_padded_and_recorded() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp = TempDir::new().expect("temp dir");
+    let (bin, input) = (temp.path().join("bin"), temp.path().join("in"));
+    fs::create_dir(&bin).unwrap();
+    fs::create_dir(&input).unwrap();
+    let path = common::fake_ffprobe(
+        &bin,
+        r#"{"streams": [{"codec_type": "video", "codec_name": "h264", "width": 1919, "height": 1079}],
+            "format": {"duration": "60.0"}}"#,
+    );
+    // Stand-in ffmpeg: log the args and copy the input
+    let script = bin.join("ffmpeg");
+    fs::write(
+        &script,
+        format!(
+            "#!/bin/sh\necho \"$*\" >> '{log}'\nprev=\nfor arg in \"$@\"; do\n  \
+             [ \"$prev\" = -i ] && input=\"$arg\"\n  prev=\"$arg\"\ndone\ncp \"$input\" \"$prev\"\n",
+            log = bin.join("ffmpeg.log").display()
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    fs::write(input.join("capture.mkv"), b"video").unwrap();
+
+    let run = std::process::Command::new(common::binary_path())
+        .arg("batch")
+        .arg(&input)
+        .arg(temp.path().join("out"))
+        .args(["--vcodec", "libx264"])
+        .env("PATH", &path)
+        .env("XDG_CACHE_HOME", temp.path().join("cache"))
+        .env("XDG_DATA_HOME", temp.path().join("data"))
+        .output()
+        .expect("run batch");
+    let stdout = String::from_utf8_lossy(&run.stdout);
+    assert!(run.status.success(), "{}", stdout);
+    assert!(
+        stdout.contains("Frame size: padded 1919x1079 to 1920x1080"),
+        "{}",
+        stdout
+    );
+    let log = fs::read_to_string(bin.join("ffmpeg.log")).unwrap();
+    assert!(log.contains("-vf pad=ceil(iw/2)*2:ceil(ih/2)*2"), "{}", log);
+    let history = fs::read_to_string(temp.path().join("data/transcoderr/history.ndjson")).unwrap();
+    assert!(
+        history.contains(r#""adjustments":["padded 1919x1079 to 1920x1080"]"#),
+        "{}",
+        history
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_info_reports_hdr_metadata() {
+    let temp = TempDir::new().expect("temp dir");
+    let path = common::fake_ffprobe(
+        temp.path(),
+        r#"{"streams": [{"codec_type": "video", "codec_name": "hevc",
+              "color_transfer": "smpte2084", "color_primaries": "bt2020",
+              "side_data_list": [
+                {"side_data_type": "DOVI configuration record", "dv_profile": 7,
+                 "dv_level": 6, "rpu_present_flag": 1, "el_present_flag": 1,
+                 "bl_present_flag": 1, "dv_bl_signal_compatibility_id": 6},
+                {"side_data_type": "Content light level metadata",
+                 "max_content": 1000, "max_average": 400}]}],
+            "format": {"duration": "60.0"}}"#,
+    );
+    let info = |json: bool| {
+        let mut command = std::process::Command::new(common::binary_path());
+        command.args(["info", "movie.mkv"]).env("PATH", &path);
+        if json {
+            command.arg("--json");
+        }
+        let output = command.output().expect("run info");
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    let stdout = info(false);
+    assert!(stdout.contains("HDR: Dolby Vision, HDR10"), "{}", stdout);
+    assert!(
+        stdout
+            .contains("Dolby Vision: profile 7.6 (dual layer, UHD Blu-ray HDR10-compatible base)"),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains("MaxCLL 1000 nits, MaxFALL 400 nits"),
+        "{}",
+        stdout
+    );
+
+    let json: serde_json::Value = serde_json::from_str(&info(true)).expect("valid JSON");
+    assert_eq!(json["streams"][0]["codec_name"], "hevc");
+    assert_eq!(json["schema_version"], 1);
+    assert_eq!(json["hdr"]["content_light_level"]["max_fall"], 400);
+    assert_eq!(json["hdr"]["dolby_vision"]["enhancement_layer"], true);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_refresh_notices_replaced_sources() {
+    let temp = TempDir::new().expect("temp dir");
+    let (bin, input, output) = (
+        temp.path().join("bin"),
+        temp.path().join("in"),
+        temp.path().join("out"),
+    );
+    fs::create_dir(&bin).unwrap();
+    fs::create_dir(&input).unwrap();
+    fs::write(input.join("a.mkv"), b"alpha").unwrap();
+    fs::write(input.join("b.mkv"), b"bravo").unwrap();
+    let path = common::fake_ffmpeg(&bin);
+    let events = temp.path().join("events.ndjson");
+    let batch = |extra: &[&str]| {
+        let run = std::process::Command::new(common::binary_path())
+            .arg("--event-log")
+            .arg(&events)
+            .arg("batch")
+            .arg(&input)
+            .arg(&output)
+            .arg("--refresh-if-settings-changed")
+            .args(extra)
+            .env("PATH", &path)
+            .env("XDG_DATA_HOME", temp.path().join("data"))
+            .output()
+            .expect("run batch");
+        assert!(run.status.success());
+        String::from_utf8_lossy(&run.stdout).to_string()
+    };
+    batch(&[]);
+
+    // An upgraded source is transcoded again; the untouched one is up to date
+    fs::write(input.join("a.mkv"), b"alpha, remuxed").unwrap();
+    let stdout = batch(&[]);
+    assert!(
+        stdout.contains("1 redone as their sources changed"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("1 up to date"), "{}", stdout);
+    assert_eq!(fs::read(output.join("a.mkv")).unwrap(), b"alpha, remuxed");
+    let log = fs::read_to_string(&events).unwrap();
+    assert_eq!(
+        log.matches(r#""event":"source_changed""#).count(),
+        1,
+        "{}",
+        log
+    );
+
+    // With notify, the change is reported and the output kept
+    fs::write(input.join("b.mkv"), b"bravo, re-downloaded").unwrap();
+    let stdout = batch(&["--on-source-change", "notify"]);
+    assert!(
+        stdout.contains("WARNING: source changed since its output was made"),
+        "{}",
+        stdout
+    );
+    assert_eq!(fs::read(output.join("b.mkv")).unwrap(), b"bravo");
+    let log = fs::read_to_string(&events).unwrap();
+    assert_eq!(
+        log.matches(r#""event":"source_changed""#).count(),
+        2,
+        "{}",
+        log
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_upgrades_suggests_outputs_to_regenerate() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp = TempDir::new().expect("temp dir");
+    let (bin, library, out) = (
+        temp.path().join("bin"),
+        temp.path().join("library"),
+        temp.path().join("out"),
+    );
+    for dir in [&bin, &library, &out] {
+        fs::create_dir(dir).unwrap();
+    }
+    // Stand-in ffprobe: 2160p HDR for the remux, 1080p for anything else
+    let script = bin.join("ffprobe");
+    fs::write(
+        &script,
+        "#!/bin/sh\ncase \"$*\" in\n  *2160p*) echo '{\"streams\": [{\"codec_type\": \"video\", \
+         \"codec_name\": \"hevc\", \"height\": 2160, \"color_transfer\": \"smpte2084\", \
+         \"bit_rate\": \"58200000\"}]}';;\n  *) echo '{\"streams\": [{\"codec_type\": \"video\", \
+         \"codec_name\": \"h264\", \"height\": 1080, \"bit_rate\": \"8000000\"}]}';;\nesac\n",
+    )
+    .unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    let old = library.join("Seven (1995) 1080p.mkv");
+    let remux = library.join("Seven.1995.2160p.UHD.Remux.mkv");
+    fs::write(&old, b"old").unwrap();
+    fs::write(&remux, b"new").unwrap();
+    fs::write(library.join("Heat.1995.1080p.mkv"), b"only one").unwrap();
+    // The existing output was made from the 1080p copy
+    let output = out.join("Seven (1995).mkv");
+    fs::write(&output, b"encoded").unwrap();
+    let data = temp.path().join("data/transcoderr");
+    fs::create_dir_all(&data).unwrap();
+    fs::write(
+        data.join("history.ndjson"),
+        format!(
+            "{}\n",
+            serde_json::json!({
+                "ts_ms": 1, "input": old, "output": output, "status": "done",
+                "settings": "0123456789abcdef", "seconds": 1.0, "output_bytes": 7
+            })
+        ),
+    )
+    .unwrap();
+
+    let mut path = bin.as_os_str().to_owned();
+    path.push(":");
+    path.push(std::env::var_os("PATH").unwrap_or_default());
+    let run = std::process::Command::new(common::binary_path())
+        .arg("upgrades")
+        .arg(&library)
+        .env("PATH", &path)
+        .env("XDG_CACHE_HOME", temp.path().join("cache"))
+        .env("XDG_DATA_HOME", temp.path().join("data"))
+        .output()
+        .expect("run upgrades");
+    let stdout = String::from_utf8_lossy(&run.stdout);
+    assert!(run.status.success(), "{}", stdout);
+    assert!(
+        stdout.contains(&format!(
+            "Best: {} (2160p hevc HDR 58.2 Mb/s)",
+            remux.display()
+        )),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains(&format!(
+            "Regenerate {}: made from {} (1080p h264 8.0 Mb/s)",
+            output.display(),
+            old.display()
+        )),
+        "{}",
+        stdout
+    );
+    assert!(!stdout.contains("heat"), "{}", stdout);
+    assert!(
+        stdout.contains("1 titles with several sources, 1 outputs to regenerate"),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn test_stream_presets_cap_the_bitrate_per_encoder() {
+    let transcode = |vcodec: &str| {
+        common::run_transcoderr(&[
+            "transcode",
+            "movie.mkv",
+            "movie-remote.mkv",
+            "--preset",
+            "stream-4mbps",
+            "--vcodec",
+            vcodec,
+            "--dry-run",
+        ])
+        .expect("Failed to run transcode")
+    };
+    let output = transcode("h264_nvenc");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", stdout);
+    let command = stdout
+        .lines()
+        .find(|line| line.trim_start().starts_with("ffmpeg"))
+        .expect("ffmpeg command line");
+    assert!(
+        command.contains("-cq 21 -maxrate 3600k -bufsize 7200k"),
+        "{}",
+        command
+    );
+    assert!(command.contains("-rc vbr"), "{}", command);
+
+    // A copied stream cannot be capped
+    let output = transcode("copy");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("needs the video encoded"), "{}", stderr);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_remux_fallback_retries_demuxing_failures() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp = TempDir::new().expect("temp dir");
+    let bin = temp.path().join("bin");
+    fs::create_dir(&bin).unwrap();
+    let path = common::fake_ffprobe(
+        &bin,
+        r#"{"streams": [{"codec_type": "video", "codec_name": "mpeg4", "width": 720, "height": 480}],
+            "format": {"duration": "60.0"}}"#,
+    );
+    // Stand-in ffmpeg: the AVI's timestamps break the encode, its MKV remux does not
+    let script = bin.join("ffmpeg");
+    fs::write(
+        &script,
+        format!(
+            "#!/bin/sh\necho \"$*\" >> '{log}'\nprev=\nfor arg in \"$@\"; do\n  \
+             [ \"$prev\" = -i ] && input=\"$arg\"\n  prev=\"$arg\"\ndone\n\
+             case \"$input $*\" in\n  *.avi*libx264*)\n    \
+             echo '[mp4 @ 0x1] Application provided invalid, non monotonically increasing dts' >&2\n    \
+             exit 1 ;;\nesac\ncp \"$input\" \"$prev\"\n",
+            log = bin.join("ffmpeg.log").display()
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    let input = temp.path().join("old.avi");
+    fs::write(&input, b"video").unwrap();
+    let output = temp.path().join("old.mkv");
+    let transcode = |fallback: bool| {
+        let mut command = std::process::Command::new(common::binary_path());
+        command
+            .arg("transcode")
+            .arg(&input)
+            .arg(&output)
+            .args(["--vcodec", "libx264"])
+            .env("PATH", &path)
+            .env("XDG_CACHE_HOME", temp.path().join("cache"))
+            .env("XDG_DATA_HOME", temp.path().join("data"));
+        if fallback {
+            command.arg("--auto-remux-fallback");
+        }
+        command.output().expect("run transcode")
+    };
+
+    let run = transcode(false);
+    assert!(!run.status.success());
+    assert!(!output.exists());
+
+    fs::remove_file(bin.join("ffmpeg.log")).unwrap();
+    let run = transcode(true);
+    let stderr = String::from_utf8_lossy(&run.stderr);
+    assert!(run.status.success(), "{}", stderr);
+    assert!(stderr.contains("remuxing it to MKV"), "{}", stderr);
+    assert_eq!(fs::read(&output).unwrap(), b"video");
+    let log = fs::read_to_string(bin.join("ffmpeg.log")).unwrap();
+    let runs: Vec<&str> = log.lines().collect();
+    assert_eq!(runs.len(), 3, "{}", log);
+    assert!(
+        runs[1].contains("-fflags +genpts+igndts") && runs[1].contains("-c copy"),
+        "{}",
+        log
+    );
+    // The retry reads the remux, which is gone afterwards
+    let remuxed = runs[2]
+        .split(' ')
+        .find(|arg| arg.contains("transcoderr-remux-"));
+    let remuxed = remuxed.expect("retry from the remux");
+    assert!(!std::path::Path::new(remuxed).exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_sub_timing_retimes_text_subtitles_only() {
+    let temp = TempDir::new().expect("temp dir");
+    let path = common::fake_ffprobe(
+        temp.path(),
+        r#"{"streams": [{"codec_type": "video", "codec_name": "h264", "width": 1920, "height": 1080},
+              {"codec_type": "subtitle", "codec_name": "hdmv_pgs_subtitle"},
+              {"codec_type": "subtitle", "codec_name": "subrip"}],
+            "format": {"duration": "60.0"}}"#,
+    );
+    let output = std::process::Command::new(common::binary_path())
+        .args(["transcode", "pal.mkv", "film.mkv", "--dry-run"])
+        .args([
+            "--sub-shift",
+            "-1.5s",
+            "--sub-fps-from",
+            "25",
+            "--sub-fps-to",
+            "23.976",
+        ])
+        .env("PATH", &path)
+        .env("XDG_CACHE_HOME", temp.path().join("cache"))
+        .env("XDG_DATA_HOME", temp.path().join("data"))
+        .output()
+        .expect("run transcode");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", stdout);
+    assert!(
+        stdout.contains("-bsf:s:1 setts=ts=TS*25/23.976-1.5/TB:duration=DURATION*25/23.976"),
+        "{}",
+        stdout
+    );
+    // The PGS track keeps its timing
+    assert!(!stdout.contains("-bsf:s:0"), "{}", stdout);
+
+    // Both rates or neither
+    let output = common::run_transcoderr(&[
+        "transcode",
+        "pal.mkv",
+        "film.mkv",
+        "--sub-fps-from",
+        "25",
+        "--dry-run",
+    ])
+    .expect("run transcode");
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_batch_runs_several_roots_with_their_own_presets() {
+    let temp = TempDir::new().expect("temp dir");
+    // A comma inside a path does not start a new key
+    let (tv, movies) = (temp.path().join("tv, kids"), temp.path().join("movies"));
+    fs::create_dir_all(tv.join("Season 1")).unwrap();
+    fs::create_dir(&movies).unwrap();
+    fs::write(tv.join("Season 1/ep.mkv"), b"video").unwrap();
+    fs::write(movies.join("film.mp4"), b"video").unwrap();
+    let root = |input: &std::path::Path, output: &str, preset: &str| {
+        let mut root = format!(
+            "in={},out={}",
+            input.display(),
+            temp.path().join(output).display()
+        );
+        if !preset.is_empty() {
+            root.push_str(&format!(",preset={}", preset));
+        }
+        root
+    };
+
+    let output = common::run_transcoderr(&[
+        "batch",
+        "--root",
+        &root(&tv, "out-tv", "tv-h265-fast"),
+        "--root",
+        &root(&movies, "out-movies", ""),
+        "--dry-run",
+    ])
+    .expect("run batch");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", stdout);
+    let sections: Vec<&str> = stdout.split("\n=== ").skip(1).collect();
+    assert_eq!(sections.len(), 2, "{}", stdout);
+    assert!(
+        sections[0].contains(&format!(
+            "-> {}",
+            temp.path().join("out-tv/Season 1/ep.mkv").display()
+        )),
+        "{}",
+        stdout
+    );
+    assert!(sections[0].contains(r#""-crf", "22""#), "{}", stdout);
+    assert!(
+        sections[1].contains(&format!(
+            "-> {}",
+            temp.path().join("out-movies/film.mkv").display()
+        )),
+        "{}",
+        stdout
+    );
+    assert!(!sections[1].contains("-crf"), "{}", stdout);
+
+    // A root needs both directories
+    let output = common::run_transcoderr(&["batch", "--root", "in=/media/tv", "--dry-run"])
+        .expect("run batch");
+    assert!(!output.status.success());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_what_if_logs_swaps_without_touching_files() {
+    let temp = TempDir::new().expect("temp dir");
+    let show = temp.path().join("lib/Show");
+    fs::create_dir_all(&show).unwrap();
+    fs::write(show.join("Show 1.11.avi"), b"original").unwrap();
+    fs::write(show.join("Show 1.11_transcoded.mkv"), b"transcoded").unwrap();
+    let path = common::fake_ffprobe(
+        temp.path(),
+        r#"{"streams": [{"codec_type": "video"}, {"codec_type": "audio"}],
+            "format": {"duration": "1320.0"}}"#,
+    );
+    let log = temp.path().join("what-if.log");
+    let run = |args: &[&str]| {
+        std::process::Command::new(common::binary_path())
+            .arg(format!("--what-if={}", log.display()))
+            .args(args)
+            .env("PATH", &path)
+            .env("XDG_CACHE_HOME", temp.path().join("cache"))
+            .env("XDG_DATA_HOME", temp.path().join("data"))
+            .output()
+            .expect("run transcoderr")
+    };
+
+    // Even with --read-only, as nothing is changed
+    let output = run(&[
+        "--read-only",
+        "migrate-suffixed",
+        temp.path().join("lib").to_str().unwrap(),
+        "--swap",
+    ]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", stdout);
+    assert!(show.join("Show 1.11.avi").exists());
+    assert!(show.join("Show 1.11_transcoded.mkv").exists());
+    assert!(!show.join("Show 1.11.mkv").exists());
+    assert!(!temp.path().join("data/transcoderr/history.ndjson").exists());
+    let logged = fs::read_to_string(&log).unwrap();
+    assert_eq!(
+        logged,
+        format!(
+            "# transcoderr --what-if migrate-suffixed: 2 actions\n\
+             move {dir}/Show 1.11.avi to the trash\n\
+             rename {dir}/Show 1.11_transcoded.mkv -> {dir}/Show 1.11.mkv\n",
+            dir = show.display()
+        )
+    );
+    assert!(stdout.contains("[WHAT IF] Would move"), "{}", stdout);
+
+    // Encodes become dry runs; commands that cannot be simulated refuse
+    let output = run(&["transcode", "in.mkv", "out.mkv"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("[DRY RUN]"));
+    assert!(
+        !run(&["generate-fixtures", "--out", "fixtures"])
+            .status
+            .success()
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_doctor_rolls_back_a_swap_interrupted_by_a_crash() {
+    let temp = TempDir::new().expect("temp dir");
+    let lib = temp.path().join("lib");
+    let show = lib.join("Show");
+    fs::create_dir_all(show.join(".transcoderr-swap")).unwrap();
+    // Killed after moving the original aside and the new file into place
+    fs::write(show.join(".transcoderr-swap/Show 1.11.avi"), b"original").unwrap();
+    fs::write(show.join("Show 1.11.mkv"), b"transcoded").unwrap();
+    let begin = serde_json::json!({
+        "state": "begin",
+        "original": show.join("Show 1.11.avi"),
+        "transcoded": show.join("Show 1.11_transcoded.mkv"),
+        "target": show.join("Show 1.11.mkv"),
+        "backup": show.join(".transcoderr-swap/Show 1.11.avi"),
+        "bytes": 10,
+    });
+    fs::write(
+        lib.join(".transcoderr-swaps.ndjson"),
+        format!("{}\n", begin),
+    )
+    .unwrap();
+    let path = common::fake_ffprobe(
+        temp.path(),
+        r#"{"streams": [{"codec_type": "video"}, {"codec_type": "audio"}],
+            "format": {"duration": "1320.0"}}"#,
+    );
+
+    let run = |args: &[&std::ffi::OsStr]| {
+        let output = std::process::Command::new(common::binary_path())
+            .args(args)
+            .env("PATH", &path)
+            .env("XDG_CACHE_HOME", temp.path().join("cache"))
+            .env("XDG_DATA_HOME", temp.path().join("data"))
+            .output()
+            .expect("run transcoderr");
+        let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+        assert!(output.status.success(), "{}", stdout);
+        stdout
+    };
+
+    // Only listing, even read-only: the interrupted swap is reported and left alone
+    let stdout = run(&[
+        "--read-only".as_ref(),
+        "migrate-suffixed".as_ref(),
+        lib.as_ref(),
+    ]);
+    assert!(
+        stdout.contains("A swap in") && stdout.contains("doctor --roll-back"),
+        "{}",
+        stdout
+    );
+    assert!(show.join(".transcoderr-swap/Show 1.11.avi").exists());
+    assert!(lib.join(".transcoderr-swaps.ndjson").exists());
+
+    let stdout = run(&["doctor".as_ref(), "--roll-back".as_ref(), lib.as_ref()]);
+    assert!(
+        stdout.contains("Rolled back the interrupted swap of"),
+        "{}",
+        stdout
+    );
+    // Back as before the swap, so it is found and verified again
+    assert_eq!(fs::read(show.join("Show 1.11.avi")).unwrap(), b"original");
+    assert_eq!(
+        fs::read(show.join("Show 1.11_transcoded.mkv")).unwrap(),
+        b"transcoded"
+    );
+    assert!(!show.join("Show 1.11.mkv").exists());
+    assert!(!show.join(".transcoderr-swap").exists());
+    assert!(!lib.join(".transcoderr-swaps.ndjson").exists());
+    let stdout = run(&["migrate-suffixed".as_ref(), lib.as_ref()]);
+    assert!(stdout.contains("1 verified, 0 skipped"), "{}", stdout);
+    assert!(!stdout.contains("interrupted"), "{}", stdout);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_explain_narrates_the_plan_without_encoding() {
+    let temp = TempDir::new().expect("temp dir");
+    let input = temp.path().join("movie.mkv");
+    fs::write(&input, b"not really video").unwrap();
+    let path = common::fake_ffprobe(
+        temp.path(),
+        r#"{"streams": [
+              {"codec_type": "video", "codec_name": "h264", "width": 1920, "height": 1080},
+              {"codec_type": "audio", "codec_name": "ac3", "channels": 6,
+               "tags": {"language": "eng"}},
+              {"codec_type": "audio", "codec_name": "aac", "channels": 2,
+               "tags": {"language": "fre"}}],
+            "format": {"duration": "1320.0"}}"#,
+    );
+    let output = std::process::Command::new(common::binary_path())
+        .arg("explain")
+        .arg(&input)
+        .args(["--preset", "tv-h265-fast", "--languages", "eng"])
+        .env("PATH", &path)
+        .env("XDG_CACHE_HOME", temp.path().join("cache"))
+        .env("XDG_DATA_HOME", temp.path().join("data"))
+        .output()
+        .expect("run transcoderr");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", stdout);
+    for expected in [
+        "1. Probe",
+        "Audio 0: ac3, 6 channels, eng",
+        "Preset: tv-h265-fast",
+        "Video codec: libx265 (preset)",
+        "Languages: eng",
+        "v:0 <- input v:0: libx265 from h264",
+        "Left out: a:1",
+        "ffmpeg -hide_banner",
+        "8. Predicted output",
+        "Video: libx265, 1920x1080 unless the filters scale it",
+        "Duration: 00:22:00",
+    ] {
+        assert!(
+            stdout.contains(expected),
+            "missing {:?} in {}",
+            expected,
+            stdout
+        );
+    }
+    assert!(!temp.path().join("movie_transcoded.mkv").exists());
+    assert!(!temp.path().join("data/transcoderr/history.ndjson").exists());
+}
+
+#[test]
+fn test_lang_translates_errors_and_rejects_unknown_languages() {
+    let temp = TempDir::new().expect("temp dir");
+    let input = temp.path().join("input.mp4");
+    fs::write(&input, b"\n").expect("write input");
+    let out = temp.path().join("out.mkv");
+    let refuse = |lang: &[&str]| {
+        let output = std::process::Command::new(common::binary_path())
+            .args(lang)
+            .args(["--read-only", "transcode"])
+            .args([&input, &out])
+            .env("LANG", "de_DE.UTF-8")
+            .env_remove("LC_ALL")
+            .env_remove("LC_MESSAGES")
+            .output()
+            .expect("run transcode");
+        assert!(!output.status.success());
+        String::from_utf8_lossy(&output.stderr).into_owned()
+    };
+
+    // The locale picks German; --lang overrides it
+    let stderr = refuse(&[]);
+    assert!(
+        stderr.starts_with("Fehler: Nur-Lese-Modus: 'transcode'"),
+        "{}",
+        stderr
+    );
+    let stderr = refuse(&["--lang", "en"]);
+    assert!(
+        stderr.starts_with("Error: read-only mode: refusing to run 'transcode'"),
+        "{}",
+        stderr
+    );
+    let stderr = refuse(&["--lang", "tlh"]);
+    assert!(
+        stderr.contains("no translation for language 'tlh' (available: en, de, es, fr)"),
+        "{}",
+        stderr
+    );
+
+    // Errors from deeper down come from the catalog too
+    let output = std::process::Command::new(common::binary_path())
+        .args(["--lang", "fr", "presets", "export", "nope"])
+        .env("XDG_CONFIG_HOME", temp.path().join("config"))
+        .output()
+        .expect("run presets export");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.starts_with("Erreur: préréglage inconnu 'nope'"),
+        "{}",
+        stderr
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_no_fancy_prints_plain_status_lines() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp = TempDir::new().expect("temp dir");
+    let (bin, input) = (temp.path().join("bin"), temp.path().join("in"));
+    fs::create_dir(&bin).unwrap();
+    fs::create_dir(&input).unwrap();
+    fs::write(input.join("clip.mkv"), vec![0u8; 4096]).unwrap();
+    let script = bin.join("ffmpeg");
+    fs::write(
+        &script,
+        "#!/bin/sh\nprev=\nfor arg in \"$@\"; do\n  [ \"$prev\" = -i ] && input=\"$arg\"\n  \
+         prev=\"$arg\"\ndone\necho '  Duration: 00:00:04.00, start: 0.000000' >&2\n\
+         for t in 1 2 3; do printf 'frame=%s0 fps=25 time=00:00:0%s.00 speed=2.0x\\r' $t $t >&2\n  \
+         printf 'fps=25.00\\nout_time=00:00:0%s.000000\\nspeed=2.0x\\nprogress=continue\\n' $t; done\n\
+         printf 'frame=100 fps=25 Lsize=4kB time=00:00:04.00 speed=2.0x\\n' >&2\n\
+         printf 'fps=25.00\\nout_time=00:00:04.000000\\nspeed=2.0x\\nprogress=end\\n'\n\
+         cp \"$input\" \"$prev\"\n",
+    )
+    .unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    let mut path = bin.as_os_str().to_owned();
+    path.push(":");
+    path.push(std::env::var_os("PATH").unwrap_or_default());
+
+    let run = std::process::Command::new(common::binary_path())
+        .arg("batch")
+        .arg(&input)
+        .arg(temp.path().join("out"))
+        .arg("--no-fancy")
+        .env("PATH", &path)
+        .env("XDG_CACHE_HOME", temp.path().join("cache"))
+        .env("XDG_DATA_HOME", temp.path().join("data"))
+        .output()
+        .expect("run batch");
+    assert!(run.status.success());
+    let stderr = String::from_utf8_lossy(&run.stderr);
+    assert!(
+        !stderr.contains('\r') && !stderr.contains('\x1b'),
+        "{:?}",
+        stderr
+    );
+    let progress: Vec<&str> = stderr
+        .lines()
+        .filter(|l| l.starts_with("  Progress: "))
+        .collect();
+    assert_eq!(
+        progress,
+        [
+            "  Progress:  25% 00:00:01 / 00:00:04 25 fps 2x ETA 00:00:01",
+            "  Progress:  50% 00:00:02 / 00:00:04 25 fps 2x ETA 00:00:01",
+            "  Progress:  75% 00:00:03 / 00:00:04 25 fps 2x ETA 00:00:00",
+            "  Progress: 100% 00:00:04 / 00:00:04 25 fps 2x",
+        ]
+    );
+    // ffmpeg's own summary is still kept once, and its report on stdout is not shown
+    assert_eq!(stderr.matches("frame=").count(), 1, "{}", stderr);
+    assert!(!String::from_utf8_lossy(&run.stdout).contains("out_time="));
+}
+
+#[test]
+fn test_color_and_theme_style_tables() {
+    let temp = TempDir::new().expect("temp dir");
+    let library = temp.path().join("library");
+    fs::create_dir(&library).expect("create library");
+    fs::write(library.join("a.mp4"), b"\n").expect("write input");
+    let config = temp.path().join("config.toml");
+    fs::write(&config, "[theme]\nheading = \"underline bright-blue\"\n").expect("write config");
+    let missing = |extra: &[&str]| {
+        let mut args = vec![
+            "batch",
+            "missing",
+            library.to_str().unwrap(),
+            temp.path().join("out").to_str().unwrap(),
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect::<Vec<_>>();
+        args.extend(extra.iter().map(|a| a.to_string()));
+        let output = std::process::Command::new(common::binary_path())
+            .args(&args)
+            .env_remove("NO_COLOR")
+            .output()
+            .expect("run batch missing");
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    // Piped output is plain unless color is forced
+    let plain = missing(&[]);
+    assert!(!plain.contains('\x1b'), "{:?}", plain);
+    assert!(plain.contains("  Input"), "{}", plain);
+    let colored = missing(&["--color", "always"]);
+    assert!(colored.contains("\x1b[1mInput\x1b[0m"), "{:?}", colored);
+    let themed = missing(&["--color", "always", "--config", config.to_str().unwrap()]);
+    assert!(themed.contains("\x1b[4;94mInput\x1b[0m"), "{:?}", themed);
+
+    fs::write(&config, "[theme]\nok = \"mauve\"\n").expect("write config");
+    let output = common::run_transcoderr(&["--config", config.to_str().unwrap(), "info", "a.mkv"])
+        .expect("run with a bad theme");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unknown color 'mauve'"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_repeated_failures_write_a_diagnostic_bundle() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp = TempDir::new().expect("temp dir");
+    let (bin, input) = (temp.path().join("bin"), temp.path().join("in"));
+    fs::create_dir(&bin).unwrap();
+    fs::create_dir(&input).unwrap();
+    for name in ["a.mkv", "b.mkv", "c.mkv"] {
+        fs::write(input.join(name), b"video").unwrap();
+    }
+    let ffmpeg = bin.join("ffmpeg");
+    fs::write(
+        &ffmpeg,
+        "#!/bin/sh\n[ \"$1\" = -version ] && { echo 'ffmpeg version 9.9'; exit 0; }\n\
+         echo 'Conversion failed!' >&2\nexit 1\n",
+    )
+    .unwrap();
+    fs::set_permissions(&ffmpeg, fs::Permissions::from_mode(0o755)).unwrap();
+    let mut path = bin.as_os_str().to_owned();
+    path.push(":");
+    path.push(std::env::var_os("PATH").unwrap_or_default());
+    let config = temp.path().join("config.toml");
+    fs::write(
+        &config,
+        "[mqtt]\nbroker = \"127.0.0.1:1\"\nusername = \"me\"\npassword = \"hunter2\"\n",
+    )
+    .unwrap();
+    let bundles = temp.path().join("bundles");
+    let batch = |extra: &[&str]| {
+        std::process::Command::new(common::binary_path())
+            .arg("--config")
+            .arg(&config)
+            .args(extra)
+            .arg("batch")
+            .arg(&input)
+            .arg(temp.path().join("out"))
+            .env("PATH", &path)
+            .env("XDG_DATA_HOME", temp.path().join("data"))
+            .output()
+            .expect("run batch")
+    };
+
+    // Without a directory, only the hint
+    let output = batch(&[]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("3 jobs in a row failed; rerun with --diagnostics-dir"),
+        "{}",
+        stderr
+    );
+
+    let output = batch(&["--diagnostics-dir", bundles.to_str().unwrap()]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Wrote a diagnostic bundle"), "{}", stderr);
+    let zips: Vec<_> = fs::read_dir(&bundles).unwrap().flatten().collect();
+    assert_eq!(zips.len(), 1);
+    // Entries are stored, so their text can be read straight from the file
+    let bundle = String::from_utf8_lossy(&fs::read(zips[0].path()).unwrap()).into_owned();
+    for text in [
+        "summary.txt",
+        "Reason: 3 jobs in a row failed",
+        "ffmpeg version 9.9",
+        "command.txt",
+        "Conversion failed!",
+        "password = \"<redacted>\"",
+    ] {
+        assert!(bundle.contains(text), "{} not in {}", text, bundle);
+    }
+    assert!(!bundle.contains("hunter2"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_snapshot_every_logs_progress_history() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp = TempDir::new().expect("temp dir");
+    let (bin, input) = (temp.path().join("bin"), temp.path().join("in"));
+    fs::create_dir(&bin).unwrap();
+    fs::create_dir(&input).unwrap();
+    fs::write(input.join("film.mkv"), vec![0u8; 4096]).unwrap();
+    // A slow encode: a stats line every 100ms
+    let script = bin.join("ffmpeg");
+    fs::write(
+        &script,
+        "#!/bin/sh\nprev=\nfor arg in \"$@\"; do\n  [ \"$prev\" = -i ] && input=\"$arg\"\n  \
+         prev=\"$arg\"\ndone\necho '  Duration: 00:00:04.00, start: 0.000000' >&2\n\
+         for t in 1 2 3; do sleep 0.1; printf 'frame=%s0 size=%s00kB time=00:00:0%s.00 \
+         bitrate=800.0kbits/s speed=1.0x\\r' $t $t $t >&2; done\n\
+         printf 'frame=100 Lsize=400kB time=00:00:04.00 bitrate=800.0kbits/s speed=1.0x\\n' >&2\n\
+         cp \"$input\" \"$prev\"\n",
+    )
+    .unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    let mut path = bin.as_os_str().to_owned();
+    path.push(":");
+    path.push(std::env::var_os("PATH").unwrap_or_default());
+    let events = temp.path().join("events.ndjson");
+
+    let run = std::process::Command::new(common::binary_path())
+        .arg("--event-log")
+        .arg(&events)
+        .args(["--snapshot-every", "0.001", "--stats", "none"])
+        .arg("batch")
+        .arg(&input)
+        .arg(temp.path().join("out"))
+        .env("PATH", &path)
+        .env("XDG_CACHE_HOME", temp.path().join("cache"))
+        .env("XDG_DATA_HOME", temp.path().join("data"))
+        .output()
+        .expect("run batch");
+    assert!(run.status.success());
+    let stderr = String::from_utf8_lossy(&run.stderr);
+    let snapshots: Vec<&str> = stderr
+        .lines()
+        .filter(|l| l.starts_with("  Snapshot: "))
+        .collect();
+    assert!(snapshots.len() >= 3, "{}", stderr);
+    assert!(
+        snapshots[0].starts_with(
+            "  Snapshot: 00:00:01 / 00:00:04 (25%), 800.0 kbit/s, 100.0 KiB so far, \
+             400.0 KiB projected,"
+        ),
+        "{}",
+        stderr
+    );
+    let log = fs::read_to_string(&events).unwrap();
+    let snapshot = log
+        .lines()
+        .find(|l| l.contains(r#""event":"snapshot""#))
+        .expect("a snapshot event");
+    assert!(snapshot.contains(r#""percent":25.0"#), "{}", snapshot);
+    assert!(
+        snapshot.contains(r#""projected_bytes":409600"#),
+        "{}",
+        snapshot
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_init_writes_a_config_with_a_recommended_preset() {
+    use std::io::Write;
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp = TempDir::new().expect("temp dir");
+    let bin = temp.path().join("bin");
+    fs::create_dir(&bin).unwrap();
+    let ffmpeg = bin.join("ffmpeg");
+    fs::write(
+        &ffmpeg,
+        "#!/bin/sh\ncase \"$*\" in\n  -version) echo 'ffmpeg version 7.0' ;;\n  \
+         *-encoders*) echo ' V....D libx264  libx264'; echo ' V....D hevc_nvenc  NVIDIA' ;;\n\
+         esac\n",
+    )
+    .unwrap();
+    fs::set_permissions(&ffmpeg, fs::Permissions::from_mode(0o755)).unwrap();
+    let mut path = bin.as_os_str().to_owned();
+    path.push(":");
+    path.push(std::env::var_os("PATH").unwrap_or_default());
+    let config = temp.path().join("transcoderr.toml");
+    let library = temp.path().join("tv");
+    let init = |answers: &str| {
+        let mut child = std::process::Command::new(common::binary_path())
+            .arg("--config")
+            .arg(&config)
+            .arg("init")
+            .env("PATH", &path)
+            .env("XDG_CONFIG_HOME", temp.path().join("xdg"))
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .expect("run init");
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(answers.as_bytes())
+            .unwrap();
+        child.wait_with_output().expect("wait for init")
+    };
+
+    let output = init(&format!("{}\n\nh264\nbest\n", library.display()));
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Found ffmpeg version 7.0"), "{}", stdout);
+    assert!(
+        stdout.contains("Hardware encoders: hevc_nvenc"),
+        "{}",
+        stdout
+    );
+    let text = fs::read_to_string(&config).unwrap();
+    assert!(text.contains("preset = \"h264-best\""), "{}", text);
+    assert!(
+        text.contains(&format!(
+            "#   transcoderr batch {} {}-h264",
+            library.display(),
+            library.display()
+        )),
+        "{}",
+        text
+    );
+    let preset = temp.path().join("xdg/transcoderr/presets/h264-best.toml");
+    assert!(fs::read_to_string(preset).unwrap().contains("libx264"));
+
+    // The written config and preset are what later runs use
+    let export = std::process::Command::new(common::binary_path())
+        .args([
+            "--config",
+            config.to_str().unwrap(),
+            "presets",
+            "export",
+            "h264-best",
+        ])
+        .env("XDG_CONFIG_HOME", temp.path().join("xdg"))
+        .output()
+        .expect("run presets export");
+    assert!(export.status.success(), "{:?}", export);
+    fs::write(temp.path().join("a.mkv"), b"video").unwrap();
+    let dry_run = std::process::Command::new(common::binary_path())
+        .arg("--config")
+        .arg(&config)
+        .arg("transcode")
+        .arg(temp.path().join("a.mkv"))
+        .arg("--dry-run")
+        .env("XDG_CONFIG_HOME", temp.path().join("xdg"))
+        .env("XDG_CACHE_HOME", temp.path().join("cache"))
+        .output()
+        .expect("run transcode");
+    let stdout = String::from_utf8_lossy(&dry_run.stdout);
+    assert!(stdout.contains("-c:v libx264"), "{}", stdout);
+    assert!(stdout.contains("-crf 18 -preset slow"), "{}", stdout);
+
+    // An existing config is only replaced on request
+    let again = init("\n");
+    assert!(!again.status.success());
+    assert!(String::from_utf8_lossy(&again.stderr).contains("rerun with --force"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_tune_wizard_saves_the_picked_settings_as_a_preset() {
+    use std::io::Write;
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp = TempDir::new().expect("temp dir");
+    let bin = temp.path().join("bin");
+    fs::create_dir(&bin).unwrap();
+    let path = common::fake_ffprobe(&bin, r#"{"streams": [], "format": {"duration": "100.0"}}"#);
+    // Lower CRFs make bigger files
+    let ffmpeg = bin.join("ffmpeg");
+    fs::write(
+        &ffmpeg,
+        "#!/bin/sh\nprev=\ncrf=\nfor arg in \"$@\"; do\n  [ \"$prev\" = -crf ] && crf=\"$arg\"\n  \
+         prev=\"$arg\"\ndone\nn=1000\n[ -n \"$crf\" ] && n=$(( (40 - crf) * 100 ))\n\
+         head -c $n /dev/zero > \"$prev\"\n",
+    )
+    .unwrap();
+    fs::set_permissions(&ffmpeg, fs::Permissions::from_mode(0o755)).unwrap();
+    let input = temp.path().join("film.mkv");
+    fs::write(&input, b"video").unwrap();
+
+    let mut child = std::process::Command::new(common::binary_path())
+        .arg("tune-wizard")
+        .arg(&input)
+        .args(["--crf", "20,30", "--speeds", "slow"])
+        .env("PATH", &path)
+        .env("XDG_CONFIG_HOME", temp.path().join("xdg"))
+        .env("XDG_CACHE_HOME", temp.path().join("cache"))
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .expect("run tune-wizard");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"2\nsmall-films\n")
+        .unwrap();
+    let output = child.wait_with_output().expect("wait for tune-wizard");
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    // 2000 bytes for 10 seconds of a 100 second file
+    assert!(stdout.contains("  1  20   slow   2.0 KiB  "), "{}", stdout);
+    assert!(stdout.contains(" 19.5 KiB "), "{}", stdout);
+    assert!(stdout.contains("Saved preset 'small-films'"), "{}", stdout);
+    let preset =
+        fs::read_to_string(temp.path().join("xdg/transcoderr/presets/small-films.toml")).unwrap();
+    assert!(
+        preset.contains(r#"extra = ["-crf", "30", "-preset", "slow"]"#),
+        "{}",
+        preset
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_trust_history_skips_inputs_already_transcoded_elsewhere() {
+    let temp = TempDir::new().expect("temp dir");
+    let (bin, input, output) = (
+        temp.path().join("bin"),
+        temp.path().join("in"),
+        temp.path().join("out"),
+    );
+    fs::create_dir(&bin).unwrap();
+    fs::create_dir(&input).unwrap();
+    fs::write(input.join("a.mkv"), b"alpha").unwrap();
+    let path = common::fake_ffmpeg(&bin);
+    let run = |args: &[&std::ffi::OsStr]| {
+        let run = std::process::Command::new(common::binary_path())
+            .args(args)
+            .env("PATH", &path)
+            .env("XDG_DATA_HOME", temp.path().join("data"))
+            .output()
+            .expect("run transcoderr");
+        assert!(
+            run.status.success(),
+            "stderr: {}",
+            String::from_utf8_lossy(&run.stderr)
+        );
+        (
+            String::from_utf8_lossy(&run.stdout).to_string(),
+            String::from_utf8_lossy(&run.stderr).to_string(),
+        )
+    };
+    let batch = |extra: &str| {
+        let mut args = vec!["batch".as_ref(), input.as_os_str(), output.as_os_str()];
+        if !extra.is_empty() {
+            args.push(extra.as_ref());
+        }
+        run(&args).0
+    };
+
+    let stdout = batch("");
+    assert!(
+        stdout.contains("1 succeeded, 0 failed"),
+        "stdout: {}",
+        stdout
+    );
+    // The output is filed away elsewhere, so the batch would do it all again
+    fs::rename(output.join("a.mkv"), temp.path().join("filed.mkv")).unwrap();
+    let stdout = batch("--trust-history");
+    assert!(
+        stdout.contains("already transcoded with these settings on ")
+            && stdout.contains("(since moved or removed)"),
+        "stdout: {}",
+        stdout
+    );
+    assert!(
+        stdout.contains("1 skipped as already transcoded with the same settings"),
+        "stdout: {}",
+        stdout
+    );
+    assert!(!output.join("a.mkv").exists());
+
+    // Without the flag it is only a warning; other settings are a different encode
+    let transcode = [
+        std::ffi::OsString::from("transcode"),
+        input.join("a.mkv").into_os_string(),
+        temp.path().join("t.mkv").into_os_string(),
+    ];
+    let transcode: Vec<&std::ffi::OsStr> = transcode.iter().map(AsRef::as_ref).collect();
+    let (_, stderr) = run(&transcode);
+    assert!(!stderr.contains("already transcoded"), "stderr: {}", stderr);
+    let (_, stderr) = run(&transcode);
+    assert!(stderr.contains("already transcoded"), "stderr: {}", stderr);
+    let (_, stderr) = run(&[
+        "transcode".as_ref(),
+        input.join("a.mkv").as_os_str(),
+        "--preset".as_ref(),
+        "tv-h265-fast".as_ref(),
+    ]);
+    assert!(!stderr.contains("already transcoded"), "stderr: {}", stderr);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_probe_workers_probe_a_directory_in_parallel() {
+    let temp = TempDir::new().expect("temp dir");
+    let (bin, library) = (temp.path().join("bin"), temp.path().join("library"));
+    fs::create_dir(&bin).unwrap();
+    fs::create_dir(&library).unwrap();
+    for name in ["a.mkv", "b.mkv", "c.mkv"] {
+        fs::write(library.join(name), name).unwrap();
+    }
+    let path = common::fake_ffprobe(
+        &bin,
+        r#"{"streams": [{"codec_type": "video", "codec_name": "h264", "width": 1920,
+            "height": 1080}], "format": {"duration": "60.0"}}"#,
+    );
+    // Each probe only answers once all three are running, so one at a time fails
+    let running = temp.path().join("running");
+    fs::create_dir(&running).unwrap();
+    fs::write(
+        bin.join("ffprobe"),
+        format!(
+            "#!/bin/sh\ntouch '{running}'/$$\nfor i in $(seq 50); do\n  \
+             [ $(ls '{running}' | wc -l) -ge 3 ] && exec cat '{json}'\n  sleep 0.1\ndone\n\
+             echo 'no company' >&2\nexit 1\n",
+            running = running.display(),
+            json = bin.join("ffprobe.json").display()
+        ),
+    )
+    .unwrap();
+
+    let output = std::process::Command::new(common::binary_path())
+        .args(["recommend", "--probe-workers", "3"])
+        .arg(&library)
+        .env("PATH", &path)
+        .env("XDG_CACHE_HOME", temp.path().join("cache"))
+        .output()
+        .expect("run recommend");
+    let (stdout, stderr) = (
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr),
+    );
+    assert!(output.status.success(), "stderr: {}", stderr);
+    assert!(
+        stderr.contains("Probing 3 files, 3 at a time"),
+        "stderr: {}",
+        stderr
+    );
+    assert!(!stderr.contains("no company"), "stderr: {}", stderr);
+    for name in ["a.mkv", "b.mkv", "c.mkv"] {
+        assert!(
+            stdout.contains(&format!("{}: ", name)),
+            "stdout: {}",
+            stdout
+        );
+    }
+    // Each file was probed once, in the parallel pass
+    assert_eq!(fs::read_dir(&running).unwrap().count(), 3);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_stream_copies_skip_decode_passes() {
+    let temp = TempDir::new().expect("temp dir");
+    let (bin, input, output) = (
+        temp.path().join("bin"),
+        temp.path().join("in"),
+        temp.path().join("out"),
+    );
+    fs::create_dir(&bin).unwrap();
+    fs::create_dir(&input).unwrap();
+    fs::write(input.join("a.mkv"), b"alpha").unwrap();
+    let path = common::fake_ffmpeg(&bin);
+    let run = std::process::Command::new(common::binary_path())
+        .arg("batch")
+        .arg(&input)
+        .arg(&output)
+        .args(["--vcodec", "copy", "--acodec", "copy", "--ext", "mp4"])
+        .args(["--stabilize", "--spot-check", "3"])
+        .env("PATH", &path)
+        .env("XDG_CACHE_HOME", temp.path().join("cache"))
+        .env("XDG_DATA_HOME", temp.path().join("data"))
+        .output()
+        .expect("run batch");
+    let (stdout, stderr) = (
+        String::from_utf8_lossy(&run.stdout),
+        String::from_utf8_lossy(&run.stderr),
+    );
+    assert!(run.status.success(), "stderr: {}", stderr);
+    assert!(
+        stdout.contains("Stream copy: only the container changes"),
+        "stdout: {}",
+        stdout
+    );
+    assert!(
+        stderr.contains("--stabilize needs the video encoded"),
+        "stderr: {}",
+        stderr
+    );
+    assert!(
+        stdout.contains("1 succeeded, 0 failed"),
+        "stdout: {}",
+        stdout
+    );
+    // The remux is the only ffmpeg run: no shake analysis, no spot check frames
+    let runs = fs::read_to_string(bin.join("ffmpeg.log")).unwrap();
+    assert_eq!(runs.lines().count(), 1, "{}", runs);
+}
+
+#[test]
+fn test_batch_ext_defaults_to_preset_container() {
+    let temp = TempDir::new().expect("temp dir");
+    let library = temp.path().join("library");
+    fs::create_dir(&library).unwrap();
+    fs::write(library.join("Episode.mkv"), b"\n").unwrap();
+    let out = temp.path().join("out");
+
+    let output = common::run_transcoderr(&[
+        "batch",
+        library.to_str().unwrap(),
+        out.to_str().unwrap(),
+        "--preset",
+        "stream-4mbps",
+        "--dry-run",
+    ])
+    .expect("run batch");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains(", ext=mp4)"), "stdout: {}", stdout);
+    assert!(
+        stdout.contains(out.join("Episode.mp4").to_str().unwrap()),
+        "stdout: {}",
+        stdout
+    );
+
+    // `--ext` still wins over the preference
+    let output = common::run_transcoderr(&[
+        "batch",
+        library.to_str().unwrap(),
+        out.to_str().unwrap(),
+        "--preset",
+        "stream-4mbps",
+        "--ext",
+        "mkv",
+        "--dry-run",
+    ])
+    .expect("run batch");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains(", ext=mkv)"), "stdout: {}", stdout);
+}
+
+#[test]
+fn test_incompatible_ext_for_preset_is_refused() {
+    let temp = TempDir::new().expect("temp dir");
+    let library = temp.path().join("library");
+    fs::create_dir(&library).unwrap();
+    fs::write(library.join("Episode.mkv"), b"\n").unwrap();
+    let out = temp.path().join("out");
+
+    let output = common::run_transcoderr(&[
+        "batch",
+        library.to_str().unwrap(),
+        out.to_str().unwrap(),
+        "--preset",
+        "anime",
+        "--ext",
+        "mp4",
+        "--dry-run",
+    ])
+    .expect("run batch");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("preset 'anime' cannot be written to .mp4; it only fits in mkv"),
+        "stderr: {}",
+        stderr
+    );
+
+    let output = common::run_transcoderr(&[
+        "transcode",
+        library.join("Episode.mkv").to_str().unwrap(),
+        out.join("Episode.mp4").to_str().unwrap(),
+        "--preset",
+        "anime",
+        "--dry-run",
+    ])
+    .expect("run transcode");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("cannot be written to .mp4"),
+        "stderr: {}",
+        stderr
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_batch_runs_example_plugin() {
+    if std::process::Command::new("python3")
+        .arg("--version")
+        .output()
+        .is_err()
+    {
+        eprintln!("SKIP: python3 not found");
+        return;
+    }
+    let temp = TempDir::new().expect("temp dir");
+    let (bin, input, output) = (
+        temp.path().join("bin"),
+        temp.path().join("in"),
+        temp.path().join("out"),
+    );
+    for dir in [&bin, &input.join("Show").join("Season 1")] {
+        fs::create_dir_all(dir).unwrap();
+    }
+    fs::write(
+        input.join("Show").join("Season 1").join("big.mkv"),
+        [0u8; 2048],
+    )
+    .unwrap();
+    fs::write(input.join("tiny.mkv"), b"tiny").unwrap();
+    let path = common::fake_ffmpeg(&bin);
+    let events = temp.path().join("events.ndjson");
+    let plugin = common::project_root()
+        .join("scripts")
+        .join("plugins")
+        .join("example_plugin.py");
+    let config = temp.path().join("config.toml");
+    fs::write(
+        &config,
+        format!(
+            "[[plugins]]\nname = \"example\"\ncommand = [\"python3\", {:?}, \"--min-kb\", \"1\", \
+             \"--flat\", \"--event-file\", {:?}]\n",
+            plugin.to_str().unwrap(),
+            events.to_str().unwrap()
+        ),
+    )
+    .unwrap();
+
+    let list = common::run_transcoderr(&["--config", config.to_str().unwrap(), "plugins"])
+        .expect("run plugins");
+    assert!(list.status.success());
+    let stdout = String::from_utf8_lossy(&list.stdout);
+    assert!(
+        stdout.contains("provides: analyze, name, notify"),
+        "stdout: {}",
+        stdout
+    );
+
+    let run = std::process::Command::new(common::binary_path())
+        .arg("--config")
+        .arg(&config)
+        .arg("batch")
+        .arg(&input)
+        .arg(&output)
+        .env("PATH", path)
+        .output()
+        .expect("run batch");
+    let stdout = String::from_utf8_lossy(&run.stdout);
+    let stderr = String::from_utf8_lossy(&run.stderr);
+    assert!(run.status.success(), "stderr: {}", stderr);
+    // Named flat by the plugin, then judged by its analyzer
+    assert!(output.join("big.mkv").exists());
+    assert!(
+        stdout.contains("1 succeeded, 1 failed"),
+        "stdout: {}",
+        stdout
+    );
+    assert!(
+        stderr.contains("rejected by plugin 'example': output is only 4 bytes"),
+        "stderr: {}",
+        stderr
+    );
+    assert!(
+        stderr.contains("[example] example plugin ready (protocol 1)"),
+        "stderr: {}",
+        stderr
+    );
+    // The notifier saw the same events as an event log would
+    let log = fs::read_to_string(&events).unwrap();
+    assert!(log.contains(r#""event": "batch_done""#), "log: {}", log);
+    assert!(log.contains(r#""schema_version": 1"#), "log: {}", log);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_batch_runs_ffmpeg_with_preset_and_show_environment() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp = TempDir::new().expect("temp dir");
+    let (bin, library, output, shaders) = (
+        temp.path().join("bin"),
+        temp.path().join("tv"),
+        temp.path().join("out"),
+        temp.path().join("shaders"),
+    );
+    let presets_dir = temp.path().join("xdg").join("transcoderr").join("presets");
+    for dir in [
+        &bin,
+        &library.join("Show A").join("Season 01"),
+        &shaders,
+        &presets_dir,
+    ] {
+        fs::create_dir_all(dir).unwrap();
+    }
+    fs::write(
+        library
+            .join("Show A")
+            .join("Season 01")
+            .join("Show A - S01E01.mkv"),
+        b"a",
+    )
+    .unwrap();
+    fs::write(library.join("Other.Show.S01E01.mkv"), b"b").unwrap();
+    // Logs the pinned device and its working directory, then copies input to output
+    let log = bin.join("ffmpeg.log");
+    let script = bin.join("ffmpeg");
+    fs::write(
+        &script,
+        format!(
+            "#!/bin/sh\nprev=\nfor arg in \"$@\"; do\n  [ \"$prev\" = -i ] && input=\"$arg\"\n  \
+             prev=\"$arg\"\ndone\necho \"$CUDA_VISIBLE_DEVICES $LICENSE $(pwd)\" >> '{}'\n\
+             cp \"$input\" \"$prev\"\n",
+            log.display()
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    let mut path = bin.as_os_str().to_owned();
+    path.push(":");
+    path.push(std::env::var_os("PATH").unwrap_or_default());
+    fs::write(
+        presets_dir.join("gpu.toml"),
+        format!(
+            "name = \"gpu\"\nvcodec = \"libx265\"\nextra = []\ncwd = {:?}\n\n[env]\n\
+             CUDA_VISIBLE_DEVICES = \"0\"\nLICENSE = \"site\"\n",
+            shaders.to_str().unwrap()
+        ),
+    )
+    .unwrap();
+    let shows = temp.path().join("shows.toml");
+    fs::write(
+        &shows,
+        "[\"Show A\"]\nenv = { CUDA_VISIBLE_DEVICES = \"1\" }\n",
+    )
+    .unwrap();
+
+    let dry = std::process::Command::new(common::binary_path())
+        .args(["batch", "tv", "out", "--preset", "gpu", "--dry-run"])
+        .current_dir(temp.path())
+        .env("XDG_CONFIG_HOME", temp.path().join("xdg"))
+        .output()
+        .expect("run batch");
+    let stdout = String::from_utf8_lossy(&dry.stdout);
+    assert!(
+        stdout.contains(&format!(
+            "Would run ffmpeg with CUDA_VISIBLE_DEVICES=0 LICENSE=site in {}",
+            shaders.display()
+        )),
+        "stdout: {}",
+        stdout
+    );
+
+    // Relative input and output directories still work from the preset's cwd
+    let run = std::process::Command::new(common::binary_path())
+        .args(["batch", "tv", "out", "--preset", "gpu", "--shows"])
+        .arg(&shows)
+        .current_dir(temp.path())
+        .env("XDG_CONFIG_HOME", temp.path().join("xdg"))
+        .env("PATH", path)
+        .output()
+        .expect("run batch");
+    assert!(
+        run.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&run.stderr)
+    );
+    let stdout = String::from_utf8_lossy(&run.stdout);
+    assert!(
+        stdout.contains("2 succeeded, 0 failed"),
+        "stdout: {}",
+        stdout
+    );
+    let lines: Vec<String> = fs::read_to_string(&log)
+        .unwrap()
+        .lines()
+        .map(str::to_string)
+        .collect();
+    let shaders = shaders.canonicalize().unwrap();
+    assert_eq!(
+        lines,
+        [
+            format!("0 site {}", shaders.display()),
+            format!("1 site {}", shaders.display()),
+        ],
+    );
+    assert_eq!(
+        fs::read(output.join("Other.Show.S01E01.mkv")).unwrap(),
+        b"b"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_batch_alternates_jobs_between_gpus() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp = TempDir::new().expect("temp dir");
+    let (bin, input, output) = (
+        temp.path().join("bin"),
+        temp.path().join("in"),
+        temp.path().join("out"),
+    );
+    for dir in [&bin, &input] {
+        fs::create_dir_all(dir).unwrap();
+    }
+    for name in ["a.mkv", "b.mkv", "c.mkv"] {
+        fs::write(input.join(name), name).unwrap();
+    }
+    // Logs the device it was pinned to, then copies input to output
+    let log = bin.join("ffmpeg.log");
+    let script = bin.join("ffmpeg");
+    fs::write(
+        &script,
+        format!(
+            "#!/bin/sh\nprev=\nfor arg in \"$@\"; do\n  [ \"$prev\" = -i ] && input=\"$arg\"\n  \
+             prev=\"$arg\"\ndone\necho \"$CUDA_VISIBLE_DEVICES\" >> '{}'\ncp \"$input\" \"$prev\"\n",
+            log.display()
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    let mut path = bin.as_os_str().to_owned();
+    path.push(":");
+    path.push(std::env::var_os("PATH").unwrap_or_default());
+    let events = temp.path().join("events.ndjson");
+    let config = temp.path().join("config.toml");
+    fs::write(
+        &config,
+        format!("event-log = {:?}\n", events.to_str().unwrap()),
+    )
+    .unwrap();
+
+    let run = std::process::Command::new(common::binary_path())
+        .arg("--config")
+        .arg(&config)
+        .args(["batch", "in", "out", "--vcodec", "hevc_nvenc"])
+        .args(["--gpu-devices", "0,1"])
+        .current_dir(temp.path())
+        .env("PATH", path)
+        .output()
+        .expect("run batch");
+    assert!(
+        run.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&run.stderr)
+    );
+    let stdout = String::from_utf8_lossy(&run.stdout);
+    assert!(
+        stdout.contains("On GPU 1 (CUDA_VISIBLE_DEVICES)"),
+        "stdout: {}",
+        stdout
+    );
+    assert!(
+        stdout.contains("Jobs per GPU: 0: 2, 1: 1"),
+        "stdout: {}",
+        stdout
+    );
+    assert_eq!(fs::read_to_string(&log).unwrap(), "0\n1\n0\n");
+    let devices: Vec<String> = fs::read_to_string(&events)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .filter(|event| event["event"] == "start")
+        .map(|event| event["device"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(devices, ["0", "1", "0"]);
+    assert_eq!(fs::read(output.join("b.mkv")).unwrap(), b"b.mkv");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_batch_stage_cache_skips_second_transfer() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp = TempDir::new().expect("temp dir");
+    let (bin, input, cache) = (
+        temp.path().join("bin"),
+        temp.path().join("in"),
+        temp.path().join("cache"),
+    );
+    for dir in [&bin, &input] {
+        fs::create_dir_all(dir).unwrap();
+    }
+    fs::write(input.join("a.mkv"), b"first").unwrap();
+    fs::write(input.join("b.mkv"), b"second").unwrap();
+    // Logs where it read from, then copies input to output
+    let log = bin.join("ffmpeg.log");
+    let script = bin.join("ffmpeg");
+    fs::write(
+        &script,
+        format!(
+            "#!/bin/sh\nprev=\nfor arg in \"$@\"; do\n  [ \"$prev\" = -i ] && input=\"$arg\"\n  \
+             prev=\"$arg\"\ndone\necho \"$input\" >> '{}'\ncp \"$input\" \"$prev\"\n",
+            log.display()
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    let mut path = bin.as_os_str().to_owned();
+    path.push(":");
+    path.push(std::env::var_os("PATH").unwrap_or_default());
+
+    let run = |output: &str| {
+        let run = std::process::Command::new(common::binary_path())
+            .args(["batch", "in", output, "--stage-inputs", "--stage-cache"])
+            .arg(&cache)
+            .current_dir(temp.path())
+            .env("PATH", &path)
+            .output()
+            .expect("run batch");
+        assert!(
+            run.status.success(),
+            "stderr: {}",
+            String::from_utf8_lossy(&run.stderr)
+        );
+        String::from_utf8_lossy(&run.stdout).into_owned()
+    };
+    let first = run("out1");
+    assert!(!first.contains("stage cache"), "stdout: {}", first);
+    let second = run("out2");
+    assert!(
+        second.contains("2 inputs read from the stage cache (11 B not transferred)"),
+        "stdout: {}",
+        second
+    );
+    assert_eq!(
+        fs::read(temp.path().join("out2").join("b.mkv")).unwrap(),
+        b"second"
+    );
+    // Both runs read the same cached copies
+    let read: Vec<String> = fs::read_to_string(&log)
+        .unwrap()
+        .lines()
+        .map(str::to_string)
+        .collect();
+    assert_eq!(read.len(), 4);
+    assert!(read[2].starts_with(cache.to_str().unwrap()), "{:?}", read);
+    assert_eq!(read[..2], read[2..]);
+}
+
+#[test]
+fn test_features_lists_what_this_build_has() {
+    let output = std::process::Command::new(common::binary_path())
+        .arg("features")
+        .output()
+        .expect("run features");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let compiled = |feature: &str| {
+        stdout
+            .lines()
+            .find(|line| line.trim_start().starts_with(feature))
+            .unwrap_or_else(|| panic!("no {} row in {}", feature, stdout))
+            .split_whitespace()
+            .nth(1)
+            .unwrap()
+            .to_string()
+    };
+    let yes_no = |on: bool| if on { "yes" } else { "no" };
+    assert_eq!(compiled("vmaf"), yes_no(cfg!(feature = "vmaf")));
+    assert_eq!(compiled("mqtt"), yes_no(cfg!(feature = "mqtt")));
+    assert_eq!(compiled("mediainfo"), yes_no(cfg!(feature = "mediainfo")));
+}
+
+#[cfg(not(feature = "vmaf"))]
+#[test]
+fn test_min_vmaf_without_the_feature_names_it() {
+    let temp = TempDir::new().expect("temp dir");
+    let input = temp.path().join("in");
+    fs::create_dir(&input).unwrap();
+    fs::write(input.join("clip.mkv"), b"video").unwrap();
+    let output = std::process::Command::new(common::binary_path())
+        .arg("batch")
+        .arg(&input)
+        .arg(temp.path().join("out"))
+        .args(["--min-vmaf", "93"])
+        .output()
+        .expect("run batch");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("this build was compiled without (rebuild with `--features vmaf`)"),
+        "{}",
+        stderr
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_scenario_library_tree_is_mirrored() {
+    let scenario = Scenario::new();
+    scenario.episodes("Show A", 1, 2);
+    scenario.episodes("Show A", 2, 1);
+    for name in [
+        "-leading dash.mkv",
+        "spaces  and [brackets] (2024).mp4",
+        "ünïcödé – ö.mkv",
+        "it's.avi",
+    ] {
+        scenario.file(&format!("Movies/{}", name), name.as_bytes());
+    }
+    // Sidecars and artwork are not inputs
+    scenario.file("Show A/Season 01/Show A - S01E01.en.srt", b"1");
+    scenario.file("Movies/it's.nfo", b"<movie/>");
+    scenario.file("Movies/folder.jpg", b"jpeg");
+
+    scenario.batch(&[]).success().says("7 succeeded, 0 failed");
+    assert_eq!(
+        scenario.tree(&scenario.output),
+        [
+            ".transcoderr-state.json",
+            "Movies/-leading dash.mkv",
+            "Movies/it's.mkv",
+            "Movies/spaces  and [brackets] (2024).mkv",
+            "Movies/ünïcödé – ö.mkv",
+            "Show A/Season 01/Show A - S01E01.mkv",
+            "Show A/Season 01/Show A - S01E02.mkv",
+            "Show A/Season 02/Show A - S02E01.mkv",
+        ]
+    );
+    assert_eq!(
+        fs::read(scenario.output.join("Movies/it's.mkv")).unwrap(),
+        b"it's.avi"
+    );
+    let mut done = scenario.history_inputs("done");
+    done.sort();
+    let mut encoded = scenario.encoded();
+    encoded.sort();
+    assert_eq!(done, encoded);
+    assert_eq!(done.len(), 7);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_scenario_in_place_skips_earlier_outputs_and_trusts_history() {
+    let scenario = Scenario::new();
+    scenario.file("Clips/a.mp4", b"a");
+    scenario.file("Clips/b.mp4", b"b");
+    // Already encoded by an earlier in-place run
+    scenario.file("Clips/a_transcoded.mkv", b"old");
+
+    let library = scenario.library.to_str().unwrap();
+    scenario
+        .transcoderr(&["batch", library, library])
+        .success()
+        .says("2 succeeded, 0 failed");
+    let mut encoded = scenario.encoded();
+    encoded.sort();
+    assert_eq!(encoded, ["Clips/a.mp4", "Clips/b.mp4"]);
+    assert_eq!(
+        scenario.tree(&scenario.library),
+        [
+            ".transcoderr-state.json",
+            "Clips/a.mp4",
+            "Clips/a_transcoded.mkv",
+            "Clips/b.mp4",
+            "Clips/b_transcoded.mkv",
+        ]
+    );
+    assert_eq!(
+        fs::read(scenario.library.join("Clips/a_transcoded.mkv")).unwrap(),
+        b"a"
+    );
+
+    // Nothing is encoded twice; a replaced source is
+    scenario.file("Clips/b.mp4", b"b, remastered");
+    scenario
+        .transcoderr(&["batch", library, library, "--trust-history"])
+        .success()
+        .says("1 skipped as already transcoded with the same settings");
+    assert_eq!(scenario.encoded()[2..], ["Clips/b.mp4"]);
+    assert_eq!(
+        fs::read(scenario.library.join("Clips/b_transcoded.mkv")).unwrap(),
+        b"b, remastered"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_scenario_resumes_after_a_crash() {
+    let scenario = Scenario::new();
+    scenario.episodes("Show B", 1, 3);
+    scenario.crash_on("Show B - S01E02.mkv");
+
+    let crashed = scenario.batch(&[]);
+    assert!(!crashed.output.status.success());
+    // Files are taken in scan order; those before the crash are done
+    let before = scenario.encoded();
+    let crashed_on = "Show B/Season 01/Show B - S01E02.mkv";
+    assert_eq!(before.last().map(String::as_str), Some(crashed_on));
+    assert_eq!(scenario.history_inputs("done"), before[..before.len() - 1]);
+    // The interrupted output is left truncated
+    let second = scenario.output.join(crashed_on);
+    assert_eq!(fs::read(&second).unwrap(), b"S");
+
+    let resumed = scenario.batch(&["--trust-history"]).success();
+    resumed.says(&format!("{} succeeded, 0 failed", 4 - before.len()));
+    if before.len() > 1 {
+        resumed.says("1 skipped as already transcoded with the same settings");
+    }
+    // Between the two runs every file was encoded to the end exactly once
+    let mut after = scenario.encoded()[before.len()..].to_vec();
+    after.extend_from_slice(&before[..before.len() - 1]);
+    after.sort();
+    assert_eq!(
+        after,
+        [
+            "Show B/Season 01/Show B - S01E01.mkv",
+            "Show B/Season 01/Show B - S01E02.mkv",
+            "Show B/Season 01/Show B - S01E03.mkv",
+        ]
+    );
+    assert_eq!(fs::read(&second).unwrap(), b"Show B - S01E02.mkv");
+    assert_eq!(scenario.history_inputs("done").len(), 3);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_batch_resume_skips_what_the_interrupted_run_finished() {
+    let scenario = Scenario::new();
+    scenario.episodes("Show D", 1, 3);
+    scenario.crash_on("Show D - S01E02.mkv");
+    assert!(!scenario.batch(&[]).output.status.success());
+    let before = scenario.encoded();
+    let finished = &before[..before.len() - 1];
+
+    let manifest: serde_json::Value =
+        serde_json::from_slice(&fs::read(scenario.output.join(".transcoderr-state.json")).unwrap())
+            .unwrap();
+    assert_eq!(manifest["done"], serde_json::json!(finished));
+    assert_eq!(
+        manifest["pending"].as_array().unwrap().len(),
+        3 - finished.len()
+    );
+
+    let resumed = scenario.batch(&["--resume"]).success();
+    resumed.says(&format!("{} succeeded, 0 failed", 3 - finished.len()));
+    if !finished.is_empty() {
+        resumed.says("finished before the interruption (--resume)");
+    }
+    // The interrupted file is encoded again, the finished ones are not
+    let again = &scenario.encoded()[before.len()..];
+    assert!(again.contains(&"Show D/Season 01/Show D - S01E02.mkv".to_string()));
+    assert!(finished.iter().all(|name| !again.contains(name)));
+    let manifest: serde_json::Value =
+        serde_json::from_slice(&fs::read(scenario.output.join(".transcoderr-state.json")).unwrap())
+            .unwrap();
+    assert_eq!(manifest["done"].as_array().unwrap().len(), 3);
+    assert_eq!(manifest["pending"], serde_json::json!([]));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_batch_skip_existing_keeps_valid_outputs() {
+    let scenario = Scenario::new();
+    scenario.probe_as_media();
+    scenario.episodes("Show E", 1, 3);
+    let out = scenario.output.join("Show E/Season 01");
+    fs::create_dir_all(&out).unwrap();
+    // A finished output, and the empty one a failed run leaves behind
+    fs::write(out.join("Show E - S01E01.mkv"), b"kept").unwrap();
+    fs::write(out.join("Show E - S01E02.mkv"), b"").unwrap();
+
+    let run = scenario.batch(&["--skip-existing"]).success();
+    run.says("2 succeeded, 0 failed")
+        .says("1 skipped as their outputs exist (--skip-existing)")
+        .says("Output exists but has no duration; transcoding again");
+    let mut encoded = scenario.encoded();
+    encoded.sort();
+    assert_eq!(
+        encoded,
+        [
+            "Show E/Season 01/Show E - S01E02.mkv",
+            "Show E/Season 01/Show E - S01E03.mkv",
+        ]
+    );
+    assert_eq!(fs::read(out.join("Show E - S01E01.mkv")).unwrap(), b"kept");
+
+    // Running it again has nothing left to do
+    scenario
+        .batch(&["--skip-existing"])
+        .success()
+        .says("3 skipped as their outputs exist (--skip-existing)");
+    assert_eq!(scenario.encoded().len(), 2);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_batch_jobs_runs_encodes_side_by_side() {
+    let scenario = Scenario::new();
+    let episodes = scenario.episodes("Show C", 1, 4);
+
+    let run = scenario.batch(&["--jobs", "2"]).success();
+    run.says("Running up to 2 encodes at once")
+        .says("4 succeeded, 0 failed");
+    // Each file gets its own line as it finishes
+    for episode in &episodes {
+        run.says(&format!("{} done", episode.display()));
+    }
+    let mut encoded = scenario.encoded();
+    encoded.sort();
+    assert_eq!(encoded.len(), 4);
+    assert_eq!(scenario.history_inputs("done").len(), 4);
+    for name in &encoded {
+        assert_eq!(
+            fs::read(scenario.output.join(name)).unwrap(),
+            fs::read(scenario.library.join(name)).unwrap()
+        );
+    }
+}
+
+#[test]
+#[ignore] // Slow test - run with: cargo test -- --ignored
+fn test_scenario_generated_media_in_a_nested_library() {
+    if !common::ffmpeg_available() {
+        eprintln!("SKIP: ffmpeg not available");
+        return;
+    }
+    let scenario = Scenario::with_real_ffmpeg();
+    let fixtures = common::generate_fixtures(&scenario.root().join("fixtures"));
+    assert!(!fixtures.is_empty(), "no fixtures generated");
+    for (index, fixture) in fixtures.iter().enumerate() {
+        let name = fixture.file_name().unwrap().to_string_lossy();
+        scenario.media(
+            &format!("Show C/Season 01/{:02} {}", index + 1, name),
+            fixture,
+        );
+    }
+
+    scenario
+        .batch(&["--vcodec", "libx264", "--extra=-preset ultrafast"])
+        .success()
+        .says(&format!("{} succeeded, 0 failed", fixtures.len()));
+    let outputs = scenario.tree(&scenario.output);
+    assert_eq!(outputs.len(), fixtures.len(), "{:?}", outputs);
+    for output in &outputs {
+        assert!(common::file_exists_and_valid(&scenario.output.join(output)));
+    }
+    assert_eq!(scenario.history_inputs("done").len(), fixtures.len());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_config_file_defaults_yield_to_flags() {
+    let scenario = Scenario::new();
+    scenario.episodes("Show F", 1, 2);
+    scenario.file("Show F/extras.mp4", b"extras");
+    let config = scenario.root().join("config").join("transcoderr");
+    fs::create_dir_all(&config).unwrap();
+    fs::write(
+        config.join("config.toml"),
+        "vcodec = \"libsvtav1\"\ninput-exts = \"mkv\"\nextra = [\"-tune\", \"film\"]\njobs = 2\n",
+    )
+    .unwrap();
+
+    let run = scenario.batch(&["--dry-run"]).success();
+    run.says("vcodec=libsvtav1")
+        .says("extra=[\"-tune\", \"film\"");
+    assert!(!run.stdout.contains("extras.mp4"), "{}", run.stdout);
+    let run = scenario
+        .batch(&["--dry-run", "--vcodec", "libx264", "--input-exts", "mp4"])
+        .success();
+    run.says("vcodec=libx264").says("extras.mp4");
+    assert!(!run.stdout.contains("S01E01"), "{}", run.stdout);
+    scenario
+        .batch(&[])
+        .success()
+        .says("Running up to 2 encodes at once");
+
+    let run = scenario.transcoderr(&["config", "show"]).success();
+    run.says("config.toml");
+    let vcodec = run.stdout.lines().find(|l| l.contains("vcodec")).unwrap();
+    assert!(vcodec.contains("libsvtav1") && vcodec.ends_with("config"));
+    let jobs = run.stdout.lines().find(|l| l.contains("jobs")).unwrap();
+    assert!(jobs.contains('2') && jobs.ends_with("config"));
+
+    // The configured binary runs instead of the one on PATH
+    fs::write(
+        config.join("config.toml"),
+        "ffmpeg = \"/nonexistent/ffmpeg\"\n",
+    )
+    .unwrap();
+    let input = scenario.library.join("Show F/extras.mp4");
+    let run = scenario.transcoderr(&["transcode", input.to_str().unwrap(), "extras.mkv"]);
+    assert!(!run.output.status.success());
+    run.says("/nonexistent/ffmpeg");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_presets_file_adds_and_replaces_presets() {
+    let scenario = Scenario::new();
+    scenario.episodes("Show G", 1, 1);
+    let config = scenario.root().join("config").join("transcoderr");
+    fs::create_dir_all(&config).unwrap();
+    fs::write(
+        config.join("presets.toml"),
+        r#"
+[[preset]]
+name = "site-av1"
+vcodec = "libsvtav1"
+acodec = "libopus"
+extra = ["-crf", "30"]
+container = "mp4"
+
+[[preset]]
+name = "tv-fast"
+vcodec = "libx264"
+extra = ["-crf", "20"]
+"#,
+    )
+    .unwrap();
+
+    let run = scenario
+        .batch(&["--dry-run", "--preset", "site-av1"])
+        .success();
+    run.says("vcodec=libsvtav1 acodec=libopus")
+        .says("extra=[\"-crf\", \"30\"")
+        .says("S01E01.mp4");
+    scenario
+        .batch(&["--dry-run", "--preset", "tv-fast"])
+        .success()
+        .says("vcodec=libx264");
+
+    fs::write(
+        config.join("presets.toml"),
+        "[[preset]]\nname = \"twice\"\n\n[[preset]]\nname = \"twice\"\n",
+    )
+    .unwrap();
+    let run = scenario.batch(&["--dry-run"]);
+    assert!(!run.output.status.success());
+    run.says("presets.toml").says("clashes with 'twice'");
+}
+
+#[test]
+fn test_presets_lists_and_shows_what_a_preset_expands_to() {
+    let scenario = Scenario::with_real_ffmpeg();
+    let config = scenario.root().join("config").join("transcoderr");
+    fs::create_dir_all(&config).unwrap();
+    fs::write(
+        config.join("presets.toml"),
+        "[[preset]]\nname = \"site-av1\"\nvcodec = \"libsvtav1\"\nextra = [\"-crf\", \"30\"]\n",
+    )
+    .unwrap();
+
+    let run = scenario.transcoderr(&["presets"]).success();
+    let line = |name: &str| {
+        run.stdout
+            .lines()
+            .find(|l| l.trim_start().starts_with(name))
+            .unwrap_or_else(|| panic!("no {} in {}", name, run.stdout))
+            .to_string()
+    };
+    assert!(line("site-av1 ").contains("presets.toml"));
+    assert!(line("movie-quality").contains("built-in"));
+
+    scenario
+        .transcoderr(&["presets", "show", "movie"])
+        .success()
+        .says("Preset: movie-quality (built-in)")
+        .says("Video codec: libx265")
+        .says("Command: ffmpeg -hide_banner -y -i INPUT")
+        .says("-c:v libx265 -c:a aac")
+        .says("-crf 16 -preset slow -b:a 320k OUTPUT.mkv");
+    scenario
+        .transcoderr(&["presets", "show", "site-av1"])
+        .success()
+        .says("Audio codec: aac (not set by the preset)")
+        .says("-c:v libsvtav1");
+    let run = scenario.transcoderr(&["presets", "show", "nope"]);
+    assert!(!run.output.status.success());
+    run.says("unknown preset 'nope'");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_hwaccel_swaps_in_the_gpu_encoder() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp = TempDir::new().expect("temp dir");
+    // Lists NVENC encoders, fails VAAPI's test frame and logs every encode
+    let script = temp.path().join("ffmpeg");
+    fs::write(
+        &script,
+        format!(
+            "#!/bin/sh\ncase \"$*\" in\n  *-encoders*) echo ' V....D hevc_nvenc  NVENC'\n    \
+             echo ' V....D h264_nvenc  NVENC'\n    echo ' V....D h264_vaapi  VAAPI'; exit 0;;\n  \
+             *h264_vaapi*) exit 1;;\nesac\necho \"$*\" >> '{}'\n",
+            temp.path().join("ffmpeg.log").display()
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    let mut path = temp.path().as_os_str().to_owned();
+    path.push(":");
+    path.push(std::env::var_os("PATH").unwrap_or_default());
+    let input = temp.path().join("movie.mkv");
+    fs::write(&input, b"video").unwrap();
+    let transcode = |args: &[&str]| {
+        std::process::Command::new(common::binary_path())
+            .arg("transcode")
+            .arg(&input)
+            .arg(temp.path().join("out.mkv"))
+            .args(args)
+            .env("PATH", &path)
+            .env("XDG_CONFIG_HOME", temp.path().join("config"))
+            .env("XDG_CACHE_HOME", temp.path().join("cache"))
+            .env("XDG_DATA_HOME", temp.path().join("data"))
+            .output()
+            .expect("run transcode")
+    };
+
+    let run = transcode(&["--preset", "movie", "--hwaccel", "auto"]);
+    let stdout = String::from_utf8_lossy(&run.stdout);
+    assert!(
+        run.status.success(),
+        "{}",
+        String::from_utf8_lossy(&run.stderr)
+    );
+    assert!(stdout.contains("--hwaccel auto: using nvenc"), "{}", stdout);
+    let log = fs::read_to_string(temp.path().join("ffmpeg.log")).unwrap();
+    let encode = log.lines().find(|l| l.contains("out.mkv")).unwrap();
+    assert!(
+        encode.starts_with("-hide_banner -y -hwaccel cuda -i"),
+        "{}",
+        log
+    );
+    assert!(
+        encode.contains("-c:v hevc_nvenc") && encode.contains("-rc vbr -cq 16 -b:v 0 -preset p5"),
+        "{}",
+        log
+    );
+
+    let run = transcode(&["--hwaccel", "qsv"]);
+    assert!(!run.status.success());
+    assert!(String::from_utf8_lossy(&run.stderr).contains("this ffmpeg has no qsv encoders"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_doctor_reports_usable_presets_and_fails_on_missing_requirements() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp = TempDir::new().expect("temp dir");
+    // An ffmpeg without libopus or hqdn3d, and an ffprobe
+    for (tool, script) in [
+        (
+            "ffmpeg",
+            "#!/bin/sh\ncase \"$*\" in\n  *-version*) echo 'ffmpeg version 7.0-test';;\n  \
+             *-encoders*) printf 'Encoders:\\n ------\\n V....D libx265  x265\\n \
+             V....D libx264  x264\\n A....D aac  AAC\\n';;\n  \
+             *-filters*) printf 'Filters:\\n ------\\n ... scale  V->V  Scale\\n';;\nesac\n",
+        ),
+        ("ffprobe", "#!/bin/sh\necho 'ffprobe version 7.0-test'\n"),
+    ] {
+        let path = temp.path().join(tool);
+        fs::write(&path, script).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    let mut path = temp.path().as_os_str().to_owned();
+    path.push(":");
+    path.push(std::env::var_os("PATH").unwrap_or_default());
+    let doctor = || {
+        let output = std::process::Command::new(common::binary_path())
+            .arg("doctor")
+            .env("PATH", &path)
+            .env("XDG_CONFIG_HOME", temp.path().join("config"))
+            .env("XDG_CACHE_HOME", temp.path().join("cache"))
+            .env("XDG_DATA_HOME", temp.path().join("data"))
+            .output()
+            .expect("run doctor");
+        let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+        (output.status.success(), stdout)
+    };
+    let preset = |stdout: &str, name: &str| {
+        stdout
+            .lines()
+            .find(|line| line.split_whitespace().nth(1) == Some(name))
+            .unwrap_or_else(|| panic!("no {} in {}", name, stdout))
+            .to_string()
+    };
+
+    let (ok, stdout) = doctor();
+    assert!(ok, "{}", stdout);
+    assert!(stdout.contains("ffmpeg version 7.0-test"), "{}", stdout);
+    assert!(preset(&stdout, "movie-quality").starts_with("  OK"));
+    assert!(preset(&stdout, "anime-denoise").contains("libopus encoder, hqdn3d filter"));
+    assert!(stdout.contains("No problems found"), "{}", stdout);
+
+    // Configuring a preset this ffmpeg cannot run is a problem
+    let config = temp.path().join("config").join("transcoderr");
+    fs::create_dir_all(&config).unwrap();
+    fs::write(config.join("config.toml"), "preset = \"anime\"\n").unwrap();
+    let (ok, stdout) = doctor();
+    assert!(!ok);
+    assert!(
+        stdout.contains("the configured preset anime needs libopus encoder"),
+        "{}",
+        stdout
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_batch_smart_copies_streams_already_in_the_target_codec() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let scenario = Scenario::new();
+    scenario.file("hevc.mkv", b"hevc");
+    scenario.file("h264.mkv", b"h264");
+    // Reads the codec from the file name: HEVC video with AAC audio, or H.264 with AC-3
+    let ffprobe = scenario.root().join("bin").join("ffprobe");
+    fs::write(
+        &ffprobe,
+        "#!/bin/sh\nfor arg in \"$@\"; do file=\"$arg\"; done\ncase \"$file\" in\n  \
+         *hevc*) v=hevc; a=aac;;\n  *) v=h264; a=ac3;;\nesac\n\
+         echo \"{\\\"streams\\\": [{\\\"codec_type\\\": \\\"video\\\", \\\"codec_name\\\": \\\"$v\\\"}, \
+         {\\\"codec_type\\\": \\\"audio\\\", \\\"codec_name\\\": \\\"$a\\\"}], \
+         \\\"format\\\": {\\\"duration\\\": \\\"60.0\\\"}}\"\n",
+    )
+    .unwrap();
+    fs::set_permissions(&ffprobe, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let run = scenario
+        .batch(&["--dry-run", "--smart", "--preset", "original"])
+        .success();
+    run.says("Copying video (hevc) and audio (aac): already in the target codec (--smart)")
+        .says("vcodec=copy acodec=copy")
+        .says("vcodec=libx265 acodec=aac")
+        .says("1 copied streams already in the target codec (--smart)");
+    // The copy drops the preset's encoder args
+    let copy = run
+        .stdout
+        .lines()
+        .find(|line| line.contains("vcodec=copy"))
+        .unwrap();
+    assert!(!copy.contains("-crf") && !copy.contains("-b:a"), "{}", copy);
+}
+
+#[test]
+fn test_batch_only_if_skips_or_remuxes_files_the_condition_does_not_hold_for() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let scenario = Scenario::new();
+    scenario.file("uhd.mkv", b"uhd");
+    scenario.file("hd.mkv", b"hd");
+    // 4K H.264 for uhd files, 1080p H.264 otherwise
+    let ffprobe = scenario.root().join("bin").join("ffprobe");
+    fs::write(
+        &ffprobe,
+        "#!/bin/sh\nfor arg in \"$@\"; do file=\"$arg\"; done\ncase \"$file\" in\n  \
+         *uhd*) h=2160;;\n  *) h=1080;;\nesac\n\
+         echo \"{\\\"streams\\\": [{\\\"codec_type\\\": \\\"video\\\", \\\"codec_name\\\": \\\"h264\\\", \\\"height\\\": $h}], \
+         \\\"format\\\": {\\\"duration\\\": \\\"60.0\\\", \\\"bit_rate\\\": \\\"8000000\\\"}}\"\n",
+    )
+    .unwrap();
+    fs::set_permissions(&ffprobe, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let condition = "vcodec!=hevc && height>1080 || bitrate>15M";
+    scenario
+        .batch(&["--dry-run", "--only-if", condition])
+        .success()
+        .says("Skipping: does not match --only-if")
+        .says("vcodec=libx265")
+        .says("1 skipped as they do not match --only-if");
+
+    let run = scenario
+        .batch(&["--dry-run", "--only-if", condition, "--otherwise", "copy"])
+        .success();
+    run.says("Remuxing: does not match --only-if")
+        .says("vcodec=copy acodec=copy")
+        .says("vcodec=libx265")
+        .says("1 remuxed as they do not match --only-if");
+
+    let run = scenario.batch(&["--dry-run", "--only-if", "height>>1080"]);
+    assert!(!run.output.status.success());
+    run.says("invalid condition 'height>>1080'");
+}
+
+#[test]
+fn test_json_reports_each_file_of_transcode_and_batch() {
+    let scenario = Scenario::new();
+    scenario.file("a.mkv", b"first");
+    scenario.file("b.mkv", b"second file");
+
+    let results = scenario.root().join("results.json");
+    let json = format!("--json={}", results.display());
+    scenario.batch(&[&json]).success();
+    let document: serde_json::Value =
+        serde_json::from_slice(&fs::read(&results).expect("results written")).unwrap();
+    assert_eq!(document["schema_version"], 1);
+    assert_eq!(document["command"], "batch");
+    assert_eq!(document["ok"], true);
+    assert_eq!(document["succeeded"], 2);
+    let files = document["files"].as_array().unwrap();
+    assert_eq!(files.len(), 2);
+    assert!(files.iter().all(|file| file["status"] == "done"));
+    let b = files
+        .iter()
+        .find(|file| file["input"].as_str().unwrap().ends_with("b.mkv"))
+        .unwrap();
+    assert_eq!(b["output_bytes"], 11);
+
+    // Skips carry their reason
+    scenario.batch(&["--trust-history", &json]).success();
+    let document: serde_json::Value = serde_json::from_slice(&fs::read(&results).unwrap()).unwrap();
+    assert_eq!(document["skipped"], 2);
+    assert_eq!(document["files"][0]["reason"], "already transcoded");
+
+    // Bare --json ends stdout with the document
+    let input = scenario.file("c.mkv", b"third");
+    let output = scenario.root().join("c.mp4");
+    let run = scenario
+        .transcoderr(&[
+            "transcode",
+            "--json",
+            input.to_str().unwrap(),
+            output.to_str().unwrap(),
+        ])
+        .success();
+    let document: serde_json::Value =
+        serde_json::from_str(run.stdout.lines().last().unwrap()).expect("JSON last line");
+    assert_eq!(document["command"], "transcode");
+    assert_eq!(document["files"][0]["status"], "done");
+    assert_eq!(document["files"][0]["output"], output.to_str().unwrap());
+}
+
+#[test]
+#[cfg(unix)]
+fn test_watch_once_transcodes_settled_files_into_the_mirrored_tree() {
+    let scenario = Scenario::new();
+    scenario.file("Show/a.mkv", b"first");
+    scenario.file("b.mp4", b"second");
+    scenario.file("notes.txt", b"not media");
+    let library = scenario.root().join("library");
+    let out = scenario.root().join("out");
+    let watch = |extra: &[&str]| {
+        let mut args = vec![
+            "watch",
+            library.to_str().unwrap(),
+            out.to_str().unwrap(),
+            "--once",
+            "--interval",
+            "0.05",
+            "--settle",
+            "0",
+        ];
+        args.extend_from_slice(extra);
+        scenario.transcoderr(&args)
+    };
+
+    watch(&["--dry-run"]).success().says("Would transcode");
+    assert!(scenario.encoded().is_empty());
+
+    watch(&[])
+        .success()
+        .says("Watch finished: 2 transcoded, 0 failed");
+    assert_eq!(scenario.tree(&out), ["Show/a.mkv", "b.mkv"]);
+
+    // Outputs that exist are left alone
+    watch(&["--metrics", "127.0.0.1:0"])
+        .success()
+        .says("Metrics on http://127.0.0.1:")
+        .says("0 transcoded");
+    assert_eq!(scenario.encoded().len(), 2);
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_watch_picks_up_files_as_notifications_arrive() {
+    use std::io::{BufRead, BufReader};
+    use std::time::{Duration, Instant};
+
+    let scenario = Scenario::new();
+    let library = scenario.root().join("library");
+    let out = scenario.root().join("out");
+    fs::create_dir_all(&library).unwrap();
+    // Far longer than the test waits: only a notification can bring the file in
+    let mut watch = scenario.spawn(&[
+        "watch",
+        library.to_str().unwrap(),
+        out.to_str().unwrap(),
+        "--interval",
+        "600",
+        "--settle",
+        "0",
+    ]);
+    // Kept open: the watch prints each file it picks up
+    let mut stdout = BufReader::new(watch.stdout.take().unwrap());
+    let mut banner = String::new();
+    stdout.read_line(&mut banner).unwrap();
+    assert!(banner.starts_with("Watching"), "{}", banner);
+
+    scenario.file("Show/a.mkv", b"first");
+    let output = out.join("Show/a.mkv");
+    let started = Instant::now();
+    while !output.exists() && started.elapsed() < Duration::from_secs(10) {
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    watch.kill().unwrap();
+    watch.wait().unwrap();
+    assert_eq!(fs::read(&output).unwrap(), b"first");
+}
+
+#[test]
+fn test_watch_once_forgets_a_file_removed_while_settling() {
+    use std::io::{BufRead, BufReader};
+    use std::time::{Duration, Instant};
+
+    let scenario = Scenario::new();
+    scenario.file("a.mkv", b"partial");
+    let mut watch = scenario.spawn(&[
+        "watch",
+        scenario.root().join("library").to_str().unwrap(),
+        scenario.root().join("out").to_str().unwrap(),
+        "--once",
+        "--interval",
+        "0.1",
+        "--settle",
+        "600",
+        "--metrics",
+        "127.0.0.1:0",
+    ]);
+    let mut stdout = BufReader::new(watch.stdout.take().unwrap());
+    let mut listening = String::new();
+    stdout.read_line(&mut listening).unwrap();
+    let address = listening
+        .trim()
+        .strip_prefix("Metrics on http://")
+        .and_then(|rest| rest.strip_suffix("/metrics"))
+        .unwrap_or_else(|| panic!("{}", listening))
+        .to_string();
+    let queued = || http_raw(&address, "GET /metrics HTTP/1.1\r\n\r\n").1;
+    let started = Instant::now();
+    while !queued().contains("transcoderr_queue_depth 1\n")
+        && started.elapsed() < Duration::from_secs(10)
+    {
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    assert!(
+        queued().contains("transcoderr_queue_depth 1\n"),
+        "never seen"
+    );
+
+    // Abandoned before it settled: nothing is left to wait for
+    fs::remove_file(scenario.root().join("library/a.mkv")).unwrap();
+    let started = Instant::now();
+    while watch.try_wait().unwrap().is_none() && started.elapsed() < Duration::from_secs(10) {
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    let status = watch.try_wait().unwrap();
+    if status.is_none() {
+        watch.kill().unwrap();
+    }
+    assert!(
+        status.is_some_and(|s| s.success()),
+        "watch --once kept waiting"
+    );
+    let mut rest = String::new();
+    std::io::Read::read_to_string(&mut stdout, &mut rest).unwrap();
+    assert!(
+        rest.contains("Watch finished: 0 transcoded, 0 failed"),
+        "{}",
+        rest
+    );
+}
+
+// A running `serve`, from the line it starts with
+struct Served {
+    address: String,
+    token: String,
+}
+
+impl Served {
+    fn from_banner(banner: &str) -> Self {
+        let address = banner
+            .split("http://")
+            .nth(1)
+            .and_then(|rest| rest.split_whitespace().next())
+            .expect("address in the banner")
+            .to_string();
+        let token = banner
+            .split("token ")
+            .nth(1)
+            .expect("token in the banner")
+            .trim()
+            .to_string();
+        Self { address, token }
+    }
+}
+
+// One raw request to `address`: the status and the body
+fn http_raw(address: &str, request: &str) -> (u16, String) {
+    use std::io::{Read, Write};
+
+    let mut stream = std::net::TcpStream::connect(address).expect("connect to serve");
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").expect("headers end");
+    let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+    (status, body.to_string())
+}
+
+// One request to `serve` as a client given its token
+fn http_text(serve: &Served, method: &str, path: &str, body: &str) -> (u16, String) {
+    http_raw(
+        &serve.address,
+        &format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+             X-Transcoderr-Token: {}\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            serve.address,
+            serve.token,
+            body.len(),
+            body
+        ),
+    )
+}
+
+// `http_text` for the JSON API
+fn http(serve: &Served, method: &str, path: &str, body: &str) -> (u16, serde_json::Value) {
+    let (status, body) = http_text(serve, method, path, body);
+    (status, serde_json::from_str(&body).expect("JSON body"))
+}
+
+// Poll job `id` until it reaches `state`
+fn wait_for_job(serve: &Served, id: u64, state: &str) -> serde_json::Value {
+    for _ in 0..200 {
+        let (_, job) = http(serve, "GET", &format!("/jobs/{}", id), "");
+        if job["state"] == state {
+            return job;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+    panic!("job {} never became {}", id, state);
+}
+
+#[test]
+#[cfg(unix)]
+fn test_serve_fills_jobs_from_config_templates() {
+    use std::io::{BufRead, BufReader};
+
+    let scenario = Scenario::new();
+    let input = scenario.file("Show/a.mkv", b"first");
+    let (library, tv) = (scenario.root().join("library"), scenario.root().join("tv"));
+    let config = scenario.root().join("config.toml");
+    let template = |name: &str| {
+        format!(
+            "[templates.{}]\noutput-root = '{}'\ninput-root = '{}'\nlanguages = ['eng']\n",
+            name,
+            tv.display(),
+            library.display()
+        )
+    };
+    fs::write(&config, template("tv")).unwrap();
+    let mut server = scenario.spawn(&[
+        "--config",
+        config.to_str().unwrap(),
+        "serve",
+        "--listen",
+        "127.0.0.1:0",
+    ]);
+    // Kept open: the server reports each reload
+    let mut stdout = BufReader::new(server.stdout.take().unwrap());
+    let mut banner = String::new();
+    stdout.read_line(&mut banner).unwrap();
+    let serve = Served::from_banner(&banner);
+
+    let job = serde_json::json!({ "template": "tv", "path": input }).to_string();
+    let (status, submitted) = http(&serve, "POST", "/jobs", &job);
+    assert_eq!(status, 201, "{}", submitted);
+    assert_eq!(submitted["template"], "tv");
+    assert_eq!(submitted["languages"], serde_json::json!(["eng"]));
+    let output = tv.join("Show").join("a.mkv");
+    assert_eq!(submitted["output"], output.to_str().unwrap());
+    wait_for_job(&serve, 1, "done");
+    assert_eq!(fs::read(&output).unwrap(), b"first");
+
+    // Edits are picked up without a restart; a broken one leaves the templates as they were
+    fs::write(&config, template("movies")).unwrap();
+    let (status, templates) = http(&serve, "GET", "/templates", "");
+    assert_eq!(status, 200);
+    let mut reloaded = String::new();
+    stdout.read_line(&mut reloaded).unwrap();
+    assert!(
+        reloaded.ends_with("config.toml: 1 templates\n"),
+        "{}",
+        reloaded
+    );
+    assert_eq!(templates["movies"]["languages"], serde_json::json!(["eng"]));
+    assert!(templates.get("tv").is_none());
+    fs::write(&config, "[templates.movies\n").unwrap();
+    let (_, templates) = http(&serve, "GET", "/templates", "");
+    assert!(templates.get("movies").is_some());
+    let (status, error) = http(&serve, "POST", "/jobs", &job);
+    assert_eq!(status, 400);
+    assert_eq!(error["error"], "unknown template 'tv'");
+
+    server.kill().unwrap();
+    server.wait().unwrap();
+}
+
+#[test]
+#[cfg(unix)]
+fn test_serve_runs_lists_cancels_and_retries_jobs_with_metrics() {
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::fs::PermissionsExt;
+
+    let scenario = Scenario::new();
+    let input = scenario.file("a.mkv", b"first");
+    let mut server = scenario.spawn(&["serve", "--listen", "127.0.0.1:0"]);
+    let mut banner = String::new();
+    BufReader::new(server.stdout.take().unwrap())
+        .read_line(&mut banner)
+        .unwrap();
+    let serve = Served::from_banner(&banner);
+
+    let (status, presets) = http(&serve, "GET", "/presets", "");
+    assert_eq!(status, 200);
+    assert!(
+        presets
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|preset| preset["name"] == "original-h265" && preset["source"] == "built-in")
+    );
+
+    let output = scenario.root().join("a.mp4");
+    let job = serde_json::json!({ "input": input, "output": output }).to_string();
+    let (status, submitted) = http(&serve, "POST", "/jobs", &job);
+    assert_eq!(status, 201);
+    assert_eq!(submitted["state"], "queued");
+    let done = wait_for_job(&serve, submitted["id"].as_u64().unwrap(), "done");
+    assert_eq!(done["percent"], 100.0);
+    assert_eq!(fs::read(&output).unwrap(), b"first");
+    let (status, _) = http(&serve, "DELETE", "/jobs/1", "");
+    assert_eq!(status, 409);
+    assert_eq!(http(&serve, "POST", "/jobs/1/retry", "").0, 409);
+
+    // An empty output is no output: the job writes next to its input
+    let job = serde_json::json!({
+        "input": input,
+        "output": "",
+        "extra": ["-metadata", "title=Two Words"],
+    })
+    .to_string();
+    let (status, submitted) = http(&serve, "POST", "/jobs", &job);
+    assert_eq!(status, 201);
+    let beside = std::path::PathBuf::from(submitted["output"].as_str().unwrap());
+    assert_eq!(beside.parent(), input.parent());
+    assert!(!beside.is_dir());
+    wait_for_job(&serve, 2, "done");
+    assert_eq!(fs::read(&beside).unwrap(), b"first");
+
+    // Refused up front
+    let bad = serde_json::json!({ "input": input, "preset": "nope" }).to_string();
+    let (status, error) = http(&serve, "POST", "/jobs", &bad);
+    assert_eq!(status, 400);
+    assert_eq!(error["error"], "unknown preset 'nope'");
+    assert_eq!(http(&serve, "GET", "/jobs/99", "").0, 404);
+
+    // An encode that never ends is stopped, and the one queued behind it dropped
+    let ffmpeg = scenario.root().join("bin").join("ffmpeg");
+    fs::write(&ffmpeg, "#!/bin/sh\nexec sleep 60\n").unwrap();
+    fs::set_permissions(&ffmpeg, fs::Permissions::from_mode(0o755)).unwrap();
+    let job = serde_json::json!({ "input": input }).to_string();
+    let running = http(&serve, "POST", "/jobs", &job).1["id"]
+        .as_u64()
+        .unwrap();
+    let queued = http(&serve, "POST", "/jobs", &job).1["id"]
+        .as_u64()
+        .unwrap();
+    wait_for_job(&serve, running, "running");
+    for id in [queued, running] {
+        let (status, job) = http(&serve, "DELETE", &format!("/jobs/{}", id), "");
+        assert_eq!(status, 200);
+        assert_eq!(job["state"], "cancelled");
+    }
+    let (_, jobs) = http(&serve, "GET", "/jobs", "");
+    let states: Vec<_> = jobs
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|j| j["state"].clone())
+        .collect();
+    assert_eq!(states, ["done", "done", "cancelled", "cancelled"]);
+
+    // A cancelled job comes back as a new one
+    let (status, retried) = http(&serve, "POST", &format!("/jobs/{}/retry", running), "");
+    assert_eq!(status, 201);
+    assert_eq!(retried["id"], 5);
+    assert_eq!(retried["input"], input.to_str().unwrap());
+    wait_for_job(&serve, 5, "running");
+    http(&serve, "DELETE", "/jobs/5", "");
+
+    let (status, metrics) = http_text(&serve, "GET", "/metrics", "");
+    assert_eq!(status, 200);
+    assert!(metrics.contains("\ntranscoderr_jobs_completed_total 2\n"));
+    assert!(metrics.contains("\ntranscoderr_jobs_cancelled_total 3\n"));
+    assert!(metrics.contains("\ntranscoderr_output_bytes_total 10\n"));
+    assert!(metrics.contains("\ntranscoderr_queue_depth 0\n"));
+
+    // The dashboard is built in
+    let (status, page) = http_text(&serve, "GET", "/", "");
+    assert_eq!(status, 200);
+    assert!(page.contains("<script src=\"app.js\"></script>"));
+    assert!(page.contains(&format!("content=\"{}\"", serve.token)));
+    assert!(
+        http_text(&serve, "GET", "/app.js", "")
+            .1
+            .contains("jobs/${job.id}/retry")
+    );
+
+    // Changes need the token, JSON, and no page from elsewhere
+    let request = |headers: &str| {
+        let body = serde_json::json!({ "input": input }).to_string();
+        let raw = format!(
+            "POST /jobs HTTP/1.1\r\nHost: {}\r\n{}Content-Length: {}\r\n\r\n{}",
+            serve.address,
+            headers,
+            body.len(),
+            body
+        );
+        http_raw(&serve.address, &raw)
+    };
+    let (json, token) = (
+        "Content-Type: application/json\r\n",
+        format!("X-Transcoderr-Token: {}\r\n", serve.token),
+    );
+    let (status, error) = request(json);
+    assert_eq!(status, 403, "{}", error);
+    assert!(error.contains("X-Transcoderr-Token"), "{}", error);
+    let (status, _) = request(&format!("{}X-Transcoderr-Token: wrong\r\n", json));
+    assert_eq!(status, 403);
+    let (status, error) = request(&format!("{}{}Origin: http://evil.example\r\n", json, token));
+    assert_eq!(status, 403);
+    assert!(error.contains("cross-origin"), "{}", error);
+    let (status, _) = request(&format!("{}Content-Type: text/plain\r\n", token));
+    assert_eq!(status, 415);
+    let (status, _) = request(&token);
+    assert_eq!(status, 415);
+    let retry = format!(
+        "POST /jobs/1/retry HTTP/1.1\r\nHost: {}\r\n{}Content-Type: text/plain\r\n\r\n",
+        serve.address, token
+    );
+    assert_eq!(http_raw(&serve.address, &retry).0, 415);
+    let cancel = format!("DELETE /jobs/5 HTTP/1.1\r\nHost: {}\r\n\r\n", serve.address);
+    assert_eq!(http_raw(&serve.address, &cancel).0, 403);
+    // The dashboard's own origin is its host, whatever the scheme in front of it
+    let origin = format!("Origin: https://{}\r\n", serve.address);
+    let (status, _) = request(&format!("{}{}{}", json, token, origin));
+    assert_eq!(status, 201);
+    let (_, jobs) = http(&serve, "GET", "/jobs", "");
+    http(
+        &serve,
+        "DELETE",
+        &format!("/jobs/{}", jobs.as_array().unwrap().len()),
+        "",
+    );
+
+    server.kill().unwrap();
+    server.wait().unwrap();
+}
+
+#[test]
+#[cfg(unix)]
+fn test_serve_answers_while_another_client_stalls() {
+    use std::io::{BufRead, BufReader};
+    use std::time::{Duration, Instant};
+
+    let scenario = Scenario::new();
+    let mut server = scenario.spawn(&["serve", "--listen", "127.0.0.1:0"]);
+    let mut banner = String::new();
+    BufReader::new(server.stdout.take().unwrap())
+        .read_line(&mut banner)
+        .unwrap();
+    let serve = Served::from_banner(&banner);
+
+    // Connected, but the request never comes
+    let stalled = std::net::TcpStream::connect(&serve.address).unwrap();
+    let started = Instant::now();
+    let (status, jobs) = http(&serve, "GET", "/jobs", "");
+    assert_eq!(status, 200);
+    assert_eq!(jobs, serde_json::json!([]));
+    assert!(
+        started.elapsed() < Duration::from_secs(5),
+        "waited {:?} behind the stalled client",
+        started.elapsed()
+    );
+    drop(stalled);
+
+    server.kill().unwrap();
+    server.wait().unwrap();
+}